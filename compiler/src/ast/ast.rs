@@ -16,6 +16,20 @@ pub struct Prog {
     pub declarations: HashMap<String, Decl>,
     /// Order of declarations
     pub order: Vec<String>,
+    /// Type aliases in order of definition
+    pub aliases: Vec<TypeAlias>,
+    /// Algebraic data types in order of definition
+    pub datatypes: Vec<DataDecl>,
+    /// Exceptions in order of definition
+    pub exceptions: Vec<ExnDecl>,
+    /// Type classes in order of definition
+    pub classes: Vec<ClassDecl>,
+    /// Instances of type classes in order of definition
+    pub instances: Vec<InstanceDecl>,
+    /// Module signatures in order of definition
+    pub signatures: Vec<SigDecl>,
+    /// Structures in order of definition. Their names are in `order` among the declarations
+    pub structures: Vec<StructDecl>,
 }
 
 /// Top level declarations
@@ -24,23 +38,183 @@ pub struct Decl {
     pub id: String,
     pub sig: Type,
     pub body: Expr,
+    /// Contents of the doc comments preceding the declaration
+    pub doc: Option<String>,
+    /// Attributes like `@[inline]`, in order of appearance
+    pub attrs: Vec<Ident>,
+    pub span: Option<Span>,
+}
+
+/// Top level type aliases like `type Predicate = Int -> Bool`
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeAlias {
+    pub id: String,
+    pub typ: Type,
+    /// Contents of the doc comments preceding the alias
+    pub doc: Option<String>,
+    pub span: Option<Span>,
+}
+
+/// Algebraic data types like `data Option A = None | Some A`
+#[derive(Debug, PartialEq, Clone)]
+pub struct DataDecl {
+    pub id: String,
+    /// Type parameters
+    pub params: Vec<Ident>,
+    /// Constructors with the types of their fields
+    pub ctors: Vec<(Ident, Vec<Type>)>,
+    /// Classes whose functions are derived, like `Eq` in `deriving (Eq)`
+    pub deriving: Vec<Ident>,
+    /// Declared with `newtype`, like `newtype UserId = UserId Int`. The only constructor,
+    /// with a single field, is erased before evaluation
+    pub newtype: bool,
+    /// Contents of the doc comments preceding the data type
+    pub doc: Option<String>,
+    pub span: Option<Span>,
+}
+
+/// Name of the built-in data type of exceptions
+pub const EXN: &str = "Exn";
+
+/// Name of the data type of lists in the prelude, and of its constructors.
+/// List literals and patterns like `[1, 2]` and `x :: xs` stand for these constructors
+pub const LIST: &str = "List";
+pub const NIL: &str = "Nil";
+pub const CONS: &str = "Cons";
+
+/// Exception of the prelude raised by array primitives on indices out of bounds
+/// and on negative lengths
+pub const SUBSCRIPT: &str = "Subscript";
+
+/// Exception of the prelude raised by division and modulo by zero
+pub const DIV: &str = "Div";
+
+/// Exceptions like `exception Fail Int`, extra constructors of the type `Exn`
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExnDecl {
+    pub ctor: Ident,
+    /// Types of the fields carried by the exception
+    pub fields: Vec<Type>,
+    /// Contents of the doc comments preceding the exception
+    pub doc: Option<String>,
+    pub span: Option<Span>,
+}
+
+/// Type classes like `class Eq A { eq: A -> A -> Bool }`, giving the types of their methods
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClassDecl {
+    pub id: Ident,
+    /// The type the class is over
+    pub param: Ident,
+    pub methods: Vec<(Ident, Type)>,
+    /// Contents of the doc comments preceding the class
+    pub doc: Option<String>,
+    pub span: Option<Span>,
+}
+
+/// Instances like `instance Eq Int { eq = λ x y. x == y }`, defining every method of the class
+#[derive(Debug, PartialEq, Clone)]
+pub struct InstanceDecl {
+    pub class: Ident,
+    pub typ: Type,
+    pub methods: Vec<(Ident, Expr)>,
+    /// Contents of the doc comments preceding the instance
+    pub doc: Option<String>,
+    pub span: Option<Span>,
+}
+
+/// Module signatures like `signature COUNTER = sig type T val zero: T end`,
+/// giving the abstract types and the types of the members of structures
+#[derive(Debug, PartialEq, Clone)]
+pub struct SigDecl {
+    pub id: Ident,
+    /// Abstract types
+    pub types: Vec<Ident>,
+    /// Members with their types
+    pub vals: Vec<(Ident, Type)>,
+    /// Contents of the doc comments preceding the signature
+    pub doc: Option<String>,
+    pub span: Option<Span>,
+}
+
+/// Structures like `structure Counter : COUNTER = struct type T = Int let zero = 0 end`
+#[derive(Debug, PartialEq, Clone)]
+pub struct StructDecl {
+    pub id: Ident,
+    /// Signature sealing the structure, hiding its types and the members left out of it
+    pub sig: Option<Ident>,
+    /// Types defined by the structure, in scope for its members
+    pub types: Vec<TypeAlias>,
+    /// Members in order of definition
+    pub decls: Vec<Decl>,
+    /// Contents of the doc comments preceding the structure
+    pub doc: Option<String>,
+    pub span: Option<Span>,
 }
 
 /// System F types without metadata
 #[derive(Debug, PartialEq, Clone)]
 pub enum RawType {
+    /// 31 bit integers, the immediates compiled code represents them by
     Int,
+    /// Integers of other widths, ex. `Int8`. Never of the width of `Int`, that's `Int`
+    Sized(Width),
     Bool,
     /// Unit has one value,
     Unit,
+    /// Immutable strings, written `String`
+    Str,
     /// Type variable, introduced by Forall types
     TVar(String),
     /// Product of more than 2 types
     Prod(Vec<Type>),
     /// Function types
     Arrow(Box<Type>, Box<Type>),
-    /// Universal types
-    Forall(Ident, Box<Type>),
+    /// Universal types, quantifying over types of the given kind
+    Forall(Ident, Kind, Box<Type>),
+    /// Type holes `_`, filled in by the type checker
+    Hole,
+    /// Algebraic data types applied to type arguments, ex. `Option Int`
+    Data(String, Vec<Type>),
+    /// Record types like `{x: Int, y: Bool}`, fields in order of appearance
+    Record(Vec<(Ident, Type)>),
+    /// Iso-recursive types like `μ L. Option (Int * L)`
+    Rec(Ident, Box<Type>),
+    /// Existential types like `∃ S. S * (S -> Int)`
+    Exists(Ident, Box<Type>),
+    /// Unknown types solved during inference. Never produced by the parser
+    Meta(usize),
+    /// Type operators like `λ A. A * A`
+    Lam(Ident, Kind, Box<Type>),
+    /// Applications of type operators whose head isn't a name, ex. `(λ A. A * A) Int`.
+    /// Applied names are kept as `Data`, even when they are type variables
+    App(Box<Type>, Box<Type>),
+    /// Mutable references, ex. `Ref Int`
+    Ref(Box<Type>),
+    /// Mutable arrays, ex. `Array Int`
+    Array(Box<Type>),
+    /// Results of tasks started by `spawn`, ex. `Future Int`
+    Future(Box<Type>),
+}
+
+/// Widths of integer types
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Width {
+    W8,
+    W16,
+    /// The 31 bits of `Int`
+    Int,
+    W32,
+    W64,
+}
+
+/// Kinds, the types of types
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Kind {
+    /// Kind of proper types, `*`
+    Star,
+    /// Kind of type operators, ex. `* -> *`
+    Arrow(Box<Kind>, Box<Kind>),
 }
 
 /// Expressions without metadata
@@ -68,22 +242,102 @@ pub enum RawExpr {
     TApp { exp: Box<Expr>, arg: Type },
     /// Tuples, n >= 2
     Tuple { entries: Vec<Expr> },
-    /// Binary operations
+    /// Binary operations. `width` is the type of integer operands, set by type checking
     Binop {
         lhs: Box<Expr>,
         op: Binary,
         rhs: Box<Expr>,
+        width: Width,
     },
     /// Functions, ex. `lambda (x: Int). x + 1
     Lambda { arg: (Ident, Type), body: Box<Expr> },
     /// Type abstractions, ex. `any X. (lambda (x: X). x)`
-    Any { arg: Ident, body: Box<Expr> },
+    Any {
+        arg: Ident,
+        kind: Kind,
+        body: Box<Expr>,
+    },
     /// if [cond] then [t] else [f]
     If {
         cond: Box<Expr>,
         branch_t: Box<Expr>,
         branch_f: Box<Expr>,
     },
+    /// `case [exp] of [pat] => [body]; ... end`
+    Case {
+        exp: Box<Expr>,
+        arms: Vec<(Pattern, Expr)>,
+    },
+    /// Records like `{x = 1, y = true}`
+    Record { fields: Vec<(Ident, Expr)> },
+    /// Field projection like `r.x`
+    Proj { exp: Box<Expr>, field: Ident },
+    /// Tuple projection like `t.0`, counting from 0
+    Nth { exp: Box<Expr>, index: usize },
+    /// Folding into a recursive type, ex. `fold [μ L. Option (Int * L)] e`
+    Fold { typ: Type, exp: Box<Expr> },
+    /// Unfolding a value of recursive type one level
+    Unfold { exp: Box<Expr> },
+    /// Type ascription, ex. `(xs : List Int)`. Erased once type checked
+    Ascribe { exp: Box<Expr>, typ: Type },
+    /// Conversion between integer types, ex. `int8(x)`. Narrowing keeps the lowest bits. `from` is
+    /// the type of `exp`, set by type checking
+    Convert {
+        exp: Box<Expr>,
+        from: Width,
+        to: Width,
+    },
+    /// Existential introduction, ex. `pack [Int] (1, f) as ∃ S. S * (S -> Int)`
+    Pack {
+        witness: Type,
+        exp: Box<Expr>,
+        typ: Type,
+    },
+    /// Existential elimination, ex. `unpack [S] p = e in body`
+    Unpack {
+        tvar: Ident,
+        var: Ident,
+        exp: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// Opening a structure, ex. `open Counter in get zero`. Binds its members in `body`,
+    /// where its abstract types stay abstract
+    Open { exp: Box<Expr>, body: Box<Expr> },
+    /// Allocating a reference, ex. `ref 0`
+    Ref { exp: Box<Expr> },
+    /// Reading a reference, ex. `!r`
+    Deref { exp: Box<Expr> },
+    /// Writing a reference, ex. `r := !r + 1`
+    Assign { lhs: Box<Expr>, rhs: Box<Expr> },
+    /// Allocating an array of `len` elements all initially `init`, ex. `array(3, 0)`
+    Array { len: Box<Expr>, init: Box<Expr> },
+    /// Reading an element of an array, ex. `sub(a, 0)`
+    Sub { arr: Box<Expr>, idx: Box<Expr> },
+    /// Writing an element of an array, ex. `update(a, 0, 1)`
+    Update {
+        arr: Box<Expr>,
+        idx: Box<Expr>,
+        val: Box<Expr>,
+    },
+    /// Asserting that two values of an equality type are equal, ex. `assertEq(f 1, 2)`
+    AssertEq { lhs: Box<Expr>, rhs: Box<Expr> },
+    /// Starting a task evaluating `exp`, ex. `spawn (fib 30)`
+    Spawn { exp: Box<Expr> },
+    /// Waiting for the result of a task, ex. `join t`
+    Join { exp: Box<Expr> },
+    /// Raising an exception, ex. `raise [Int] (Fail 1)`. The type of the
+    /// whole expression is a hole `_` unless given
+    Raise { exp: Box<Expr>, typ: Type },
+    /// Handling the exceptions raised by `exp` that match `pat`, ex. `f x handle Fail n => n`
+    Handle {
+        exp: Box<Expr>,
+        pat: Pattern,
+        handler: Box<Expr>,
+    },
+    /// The dictionary of the type class `class` for `typ`, found among the instances and
+    /// dictionaries in scope by inference. Stands in for the dictionary argument of methods,
+    /// never produced by the parser
+    Instance { class: String, typ: Type },
 }
 
 /// Patterns
@@ -95,6 +349,12 @@ pub enum RawPattern {
     Binding(Ident),
     /// Tuple patterns like `(x: Int, (y: Bool, _: A))`
     Tuple(Vec<Pattern>),
+    /// Literal patterns like `0` or `true`
+    Literal(Constant),
+    /// Constructor patterns like `Some x` or `None`
+    Ctor(Ident, Vec<Pattern>),
+    /// Record patterns like `{x = a, y = _}`. Fields left out aren't matched on
+    Record(Vec<(Ident, Pattern)>),
 }
 
 /// The type of types : )
@@ -105,7 +365,7 @@ pub struct Type {
 }
 
 /// Expression with extra metadata
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq)]
 pub struct Expr {
     pub expr: RawExpr,
     pub span: Option<Span>,
@@ -123,15 +383,31 @@ pub struct Pattern {
 pub enum Constant {
     Null,
     Integer(i64),
+    /// Integers of other widths than that of `Int`, sign extended. Literals checked against the
+    /// types of these widths become them during inference
+    Sized(i64, Width),
     Boolean(bool),
+    /// String literals, ex. `"hi"`
+    Str(String),
 }
 
-/// Binary operands. No division
+/// Binary operands
 #[derive(Debug, PartialEq, Clone)]
 pub enum Binary {
     Add,
     Sub,
     Mul,
+    /// Integer division, rounding towards zero
+    Div,
+    /// Remainder of `Div`, with the sign of the dividend
+    Mod,
+    /// Bitwise and, or, and exclusive or
+    Land,
+    Lor,
+    Lxor,
+    /// Shifts left, and right keeping the sign. Only the lowest 6 bits of the amount count
+    Shl,
+    Shr,
     Eq,
     Lt,
     Gt,
@@ -193,11 +469,25 @@ impl DerefMut for Pattern {
     }
 }
 
+impl Decl {
+    /// Whether the declaration carries the attribute `name`
+    pub fn has_attr(&self, name: &str) -> bool {
+        self.attrs.iter().any(|a| a.name == name)
+    }
+}
+
 impl Prog {
     pub fn new() -> Prog {
         Prog {
             declarations: HashMap::new(),
             order: vec![],
+            aliases: vec![],
+            datatypes: vec![],
+            exceptions: vec![],
+            classes: vec![],
+            instances: vec![],
+            signatures: vec![],
+            structures: vec![],
         }
     }
 }
@@ -212,7 +502,149 @@ impl RawType {
     /// Whether the typ expression is atomic(doesn't contain smaller types)
     pub fn is_atomic(&self) -> bool {
         use RawType::*;
-        matches!(self, Int | Bool | Unit | TVar(_))
+        match self {
+            Int | Sized(_) | Bool | Unit | Str | TVar(_) | Hole | Record(_) | Meta(_) => true,
+            Data(_, args) => args.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Whether the type contains a hole `_` anywhere
+    pub fn has_hole(&self) -> bool {
+        use RawType::*;
+        match self {
+            Hole => true,
+            Int | Sized(_) | Bool | Unit | Str | TVar(_) | Meta(_) => false,
+            Prod(typs) => typs.iter().any(|t| t.has_hole()),
+            Arrow(t1, t2) => t1.has_hole() || t2.has_hole(),
+            Forall(_, _, t) | Lam(_, _, t) | Rec(_, t) | Exists(_, t) => t.has_hole(),
+            Ref(t) | Array(t) | Future(t) => t.has_hole(),
+            Data(_, args) => args.iter().any(|t| t.has_hole()),
+            App(t1, t2) => t1.has_hole() || t2.has_hole(),
+            Record(fields) => fields.iter().any(|(_, t)| t.has_hole()),
+        }
+    }
+
+    /// The width of integer types, that of `Int` for the other types
+    pub fn width(&self) -> Width {
+        match self {
+            RawType::Sized(w) => *w,
+            _ => Width::Int,
+        }
+    }
+}
+
+impl RawPattern {
+    /// Names of the variables bound by `self`
+    pub fn bindings(&self) -> Vec<&str> {
+        match self {
+            RawPattern::Wildcard | RawPattern::Literal(_) => vec![],
+            RawPattern::Binding(v) => vec![v.name.as_str()],
+            RawPattern::Tuple(pats) | RawPattern::Ctor(_, pats) => pats
+                .iter()
+                .fold(vec![], |acc, p| [acc, p.bindings()].concat()),
+            RawPattern::Record(fields) => fields
+                .iter()
+                .fold(vec![], |acc, (_, p)| [acc, p.bindings()].concat()),
+        }
+    }
+}
+
+impl RawExpr {
+    /// Immediate subexpressions of `self`, in order of appearance
+    pub fn subexprs(&self) -> Vec<&Expr> {
+        use RawExpr::*;
+        match self {
+            Con { .. } | Var { .. } | Instance { .. } => vec![],
+            Let { exp, body, .. } | Unpack { exp, body, .. } | Open { exp, body } => {
+                vec![exp, body]
+            }
+            Fix { funcs, body } => funcs
+                .iter()
+                .map(|(.., e)| e)
+                .chain(std::iter::once(&**body))
+                .collect(),
+            EApp { exp, arg } => vec![exp, arg],
+            Binop { lhs, rhs, .. } | Assign { lhs, rhs } | AssertEq { lhs, rhs } => {
+                vec![lhs, rhs]
+            }
+            Array { len, init } => vec![len, init],
+            Sub { arr, idx } => vec![arr, idx],
+            Update { arr, idx, val } => vec![arr, idx, val],
+            Tuple { entries } => entries.iter().collect(),
+            Lambda { body, .. } | Any { body, .. } => vec![body],
+            If {
+                cond,
+                branch_t,
+                branch_f,
+            } => vec![cond, branch_t, branch_f],
+            Case { exp, arms } => std::iter::once(&**exp)
+                .chain(arms.iter().map(|(_, e)| e))
+                .collect(),
+            Record { fields } => fields.iter().map(|(_, e)| e).collect(),
+            TApp { exp, .. }
+            | Proj { exp, .. }
+            | Nth { exp, .. }
+            | Fold { exp, .. }
+            | Unfold { exp }
+            | Ascribe { exp, .. }
+            | Convert { exp, .. }
+            | Pack { exp, .. }
+            | Ref { exp }
+            | Deref { exp }
+            | Spawn { exp }
+            | Join { exp }
+            | Raise { exp, .. } => vec![exp],
+            Handle { exp, handler, .. } => vec![exp, handler],
+        }
+    }
+
+    /// Immediate subexpressions of `self`, in order of appearance
+    pub fn subexprs_mut(&mut self) -> Vec<&mut Expr> {
+        use RawExpr::*;
+        match self {
+            Con { .. } | Var { .. } | Instance { .. } => vec![],
+            Let { exp, body, .. } | Unpack { exp, body, .. } | Open { exp, body } => {
+                vec![exp, body]
+            }
+            Fix { funcs, body } => funcs
+                .iter_mut()
+                .map(|(.., e)| e)
+                .chain(std::iter::once(&mut **body))
+                .collect(),
+            EApp { exp, arg } => vec![exp, arg],
+            Binop { lhs, rhs, .. } | Assign { lhs, rhs } | AssertEq { lhs, rhs } => {
+                vec![lhs, rhs]
+            }
+            Array { len, init } => vec![len, init],
+            Sub { arr, idx } => vec![arr, idx],
+            Update { arr, idx, val } => vec![arr, idx, val],
+            Tuple { entries } => entries.iter_mut().collect(),
+            Lambda { body, .. } | Any { body, .. } => vec![body],
+            If {
+                cond,
+                branch_t,
+                branch_f,
+            } => vec![cond, branch_t, branch_f],
+            Case { exp, arms } => std::iter::once(&mut **exp)
+                .chain(arms.iter_mut().map(|(_, e)| e))
+                .collect(),
+            Record { fields } => fields.iter_mut().map(|(_, e)| e).collect(),
+            TApp { exp, .. }
+            | Proj { exp, .. }
+            | Nth { exp, .. }
+            | Fold { exp, .. }
+            | Unfold { exp }
+            | Ascribe { exp, .. }
+            | Convert { exp, .. }
+            | Pack { exp, .. }
+            | Ref { exp }
+            | Deref { exp }
+            | Spawn { exp }
+            | Join { exp }
+            | Raise { exp, .. } => vec![exp],
+            Handle { exp, handler, .. } => vec![exp, handler],
+        }
     }
 }
 
@@ -228,6 +660,23 @@ impl Expr {
     }
 }
 
+impl Clone for Expr {
+    /** Clones `self` in [`grow`], since deeply nested expressions are cloned recursively */
+    fn clone(&self) -> Self {
+        grow(|| Expr {
+            expr: self.expr.clone(),
+            span: self.span,
+        })
+    }
+}
+
+/** Runs `f` on a new segment of stack when little of the current one is left. Functions recursing
+on subexpressions run in it, so that deeply nested expressions, like long list literals, or the
+chains of `let`s lowering long tuples, don't overflow the stack */
+pub fn grow<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(64 * 1024, 1 << 20, f)
+}
+
 impl Binary {
     /// Maps string rep of binops to their enum counterparts
     pub fn of_str(s: &str) -> Binary {
@@ -236,6 +685,13 @@ impl Binary {
             "+" => Add,
             "-" => Sub,
             "*" => Mul,
+            "/" => Div,
+            "%" => Mod,
+            "land" => Land,
+            "lor" => Lor,
+            "lxor" => Lxor,
+            "<<" => Shl,
+            ">>" => Shr,
             "<" => Lt,
             ">" => Gt,
             "==" => Eq,
@@ -245,6 +701,166 @@ impl Binary {
             _ => panic!(" At the Disco"),
         }
     }
+
+    /// Whether the operator maps integers to integers, unlike comparisons and connectives
+    pub fn is_arithmetic(&self) -> bool {
+        use Binary::*;
+        matches!(
+            self,
+            Add | Sub | Mul | Div | Mod | Land | Lor | Lxor | Shl | Shr
+        )
+    }
+
+    /// The name of the operator in words, ex. `add` for `+`
+    pub fn name(&self) -> &'static str {
+        use Binary::*;
+        match self {
+            Add => "add",
+            Sub => "sub",
+            Mul => "mul",
+            Div => "div",
+            Mod => "mod",
+            Land => "land",
+            Lor => "lor",
+            Lxor => "lxor",
+            Shl => "shl",
+            Shr => "shr",
+            Eq => "eq",
+            Ne => "ne",
+            Lt => "lt",
+            Gt => "gt",
+            And => "and",
+            Or => "or",
+        }
+    }
+
+    /// Result of the arithmetic or comparison `self` on the integers `l` and `r` of the given
+    /// width, wrapping around like the RISC-V instructions of that width. Shift amounts are masked
+    /// to the width rounded up to a power of two, as those instructions do.
+    /// Returns `None` for division and modulo by zero, which must not be folded away
+    pub fn apply_sized(&self, l: i64, r: i64, width: Width) -> Option<Constant> {
+        use Binary::*;
+        use Constant::*;
+        let int = |n: i64| Some(width.constant(width.wrap(n)));
+        let amount = (r & (width.bits().next_power_of_two() as i64 - 1)) as u32;
+        match self {
+            Add => int(l.wrapping_add(r)),
+            Sub => int(l.wrapping_sub(r)),
+            Mul => int(l.wrapping_mul(r)),
+            Div | Mod if r == 0 => None,
+            // Like `div` and `rem` in RISC-V, overflow wraps
+            Div => int(l.wrapping_div(r)),
+            Mod => int(l.wrapping_rem(r)),
+            Land => int(l & r),
+            Lor => int(l | r),
+            Lxor => int(l ^ r),
+            Shl => int(l.wrapping_shl(amount)),
+            Shr => int(l.wrapping_shr(amount)),
+            Lt => Some(Boolean(l < r)),
+            Gt => Some(Boolean(l > r)),
+            Eq => Some(Boolean(l == r)),
+            Ne => Some(Boolean(l != r)),
+            And | Or => None,
+        }
+    }
+
+    /// [`apply_sized`](Binary::apply_sized) on the integers `l` and `r` of type `Int`
+    pub fn apply_int(&self, l: i64, r: i64) -> Option<Constant> {
+        self.apply_sized(l, r, Width::Int)
+    }
+}
+
+impl Width {
+    /// Maps the number of bits, ex. `"8"`, to the width, that of `Int` if there are none
+    pub fn of_str(s: &str) -> Width {
+        match s {
+            "" => Width::Int,
+            "8" => Width::W8,
+            "16" => Width::W16,
+            "32" => Width::W32,
+            "64" => Width::W64,
+            _ => panic!("No integers of {s} bits"),
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            Width::W8 => 8,
+            Width::W16 => 16,
+            Width::Int => 31,
+            Width::W32 => 32,
+            Width::W64 => 64,
+        }
+    }
+
+    /// The integer type of this width
+    pub fn typ(self) -> RawType {
+        match self {
+            Width::Int => RawType::Int,
+            w => RawType::Sized(w),
+        }
+    }
+
+    /// The integer constant `n` of this width
+    pub fn constant(self, n: i64) -> Constant {
+        match self {
+            Width::Int => Constant::Integer(n),
+            w => Constant::Sized(n, w),
+        }
+    }
+
+    /// `n` truncated to the lowest bits of this width, then sign extended
+    pub fn wrap(self, n: i64) -> i64 {
+        let unused = 64 - self.bits();
+        n.wrapping_shl(unused).wrapping_shr(unused)
+    }
+
+    /// Whether `n` is an integer of this width
+    pub fn fits(self, n: i64) -> bool {
+        self.wrap(n) == n
+    }
+}
+
+impl Constant {
+    /// The value of integer constants of any width
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Constant::Integer(n) | Constant::Sized(n, _) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+/// The number of bits, none for `Int`, so that `int{w}` is the conversion to the width
+impl Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Width::Int => Ok(()),
+            w => write!(f, "{}", w.bits()),
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Star => write!(f, "*"),
+            Kind::Arrow(k1, k2) if matches!(**k1, Kind::Arrow(..)) => write!(f, "({k1}) -> {k2}"),
+            Kind::Arrow(k1, k2) => write!(f, "{k1} -> {k2}"),
+        }
+    }
+}
+
+/// Type variable binders, annotated with their kind unless it is `*`
+struct Binder<'a, V: Display>(&'a V, &'a Kind);
+
+impl<V: Display> Display for Binder<'_, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.1 {
+            Kind::Star => write!(f, "{}", self.0),
+            k => write!(f, "({}: {k})", self.0),
+        }
+    }
 }
 
 impl Display for Type {
@@ -267,8 +883,10 @@ impl Display for RawType {
 
         match self {
             RawType::Int => write!(f, "{}", "Int".blue()),
+            RawType::Sized(w) => write!(f, "{}", format!("Int{w}").blue()),
             RawType::Bool => write!(f, "{}", "Bool".blue()),
             RawType::Unit => write!(f, "{}", "Unit".blue()),
+            RawType::Str => write!(f, "{}", "String".blue()),
             RawType::Prod(typs) => {
                 write!(f, "(")?;
                 for (i, t) in typs.iter().enumerate() {
@@ -284,10 +902,56 @@ impl Display for RawType {
                 write!(f, " -> ")?;
                 fmt_composite(y, f)
             }
-            RawType::Forall(v, t) => {
-                write!(f, "∀ {v}. {t}")
+            RawType::Forall(v, k, t) => {
+                write!(f, "∀ {}. {t}", Binder(v, k))
+            }
+            RawType::Lam(v, k, t) => {
+                write!(f, "λ {}. {t}", Binder(v, k))
+            }
+            RawType::App(t1, t2) => {
+                fmt_composite(t1, f)?;
+                write!(f, " ")?;
+                fmt_composite(t2, f)
+            }
+            RawType::Rec(v, t) => {
+                write!(f, "μ {v}. {t}")
+            }
+            RawType::Exists(v, t) => {
+                write!(f, "∃ {v}. {t}")
+            }
+            RawType::Ref(t) => {
+                write!(f, "{} ", "Ref".blue())?;
+                fmt_composite(t, f)
+            }
+            RawType::Array(t) => {
+                write!(f, "{} ", "Array".blue())?;
+                fmt_composite(t, f)
+            }
+            RawType::Future(t) => {
+                write!(f, "{} ", "Future".blue())?;
+                fmt_composite(t, f)
             }
             RawType::TVar(v) => write!(f, "{}", v.blue()),
+            RawType::Hole => write!(f, "_"),
+            RawType::Meta(m) => write!(f, "?{m}"),
+            RawType::Data(id, args) => {
+                write!(f, "{}", id.blue())?;
+                for t in args {
+                    write!(f, " ")?;
+                    fmt_composite(t, f)?;
+                }
+                Ok(())
+            }
+            RawType::Record(fields) => {
+                write!(f, "{{")?;
+                for (i, (l, t)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{l}: {t}")?;
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
@@ -295,7 +959,7 @@ impl Display for RawType {
 impl Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let r = &self.expr;
-        write!(f, "{r}")
+        grow(|| write!(f, "{r}"))
     }
 }
 
@@ -304,7 +968,21 @@ impl Display for RawExpr {
         use RawExpr::*;
         /// Parenths over composite expressions to disambiguate
         fn atomize(ff: &mut fmt::Formatter, exp: &Expr) -> fmt::Result {
-            if matches!(exp.expr, Con { .. } | Var { .. } | Tuple { .. }) {
+            if matches!(
+                exp.expr,
+                Con { .. }
+                    | Var { .. }
+                    | Tuple { .. }
+                    | Case { .. }
+                    | Record { .. }
+                    | Proj { .. }
+                    | Nth { .. }
+                    | Array { .. }
+                    | Sub { .. }
+                    | Update { .. }
+                    | Ascribe { .. }
+                    | Convert { .. }
+            ) {
                 write!(ff, "{}", exp)
             } else {
                 write!(ff, "({})", exp)
@@ -332,16 +1010,22 @@ impl Display for RawExpr {
                 write!(f, " in {}", body)
             }
             RawExpr::EApp { exp, arg } => {
-                if matches!(exp.expr, EApp { .. }) { write!(f, "{exp}") }
-                else { atomize(f, exp) }?;
+                if matches!(exp.expr, EApp { .. }) {
+                    write!(f, "{exp}")
+                } else {
+                    atomize(f, exp)
+                }?;
                 write!(f, " ")?;
                 atomize(f, arg)
             }
             RawExpr::TApp { exp, arg } => {
-                if matches!(exp.expr, TApp { .. }) { write!(f, "{exp}") }
-                else { atomize(f, exp) }?;
+                if matches!(exp.expr, TApp { .. }) {
+                    write!(f, "{exp}")
+                } else {
+                    atomize(f, exp)
+                }?;
                 write!(f, "[{arg}]")
-            },
+            }
             RawExpr::Tuple { entries } => {
                 write!(f, "(")?;
                 for (i, e) in entries.iter().enumerate() {
@@ -352,7 +1036,7 @@ impl Display for RawExpr {
                 }
                 write!(f, ")")
             }
-            RawExpr::Binop { lhs, op, rhs } => {
+            RawExpr::Binop { lhs, op, rhs, .. } => {
                 atomize(f, lhs)?;
                 write!(f, " {op} ")?;
                 atomize(f, rhs)
@@ -360,8 +1044,13 @@ impl Display for RawExpr {
             RawExpr::Lambda { arg: (v, t), body } => {
                 write!(f, "λ {}: {}. {}", v.name.red(), t, body)
             }
-            RawExpr::Any { arg, body } => {
-                write!(f, "Λ {}. {}", arg.name.blue(), body)
+            RawExpr::Any { arg, kind, body } => {
+                write!(
+                    f,
+                    "Λ {}. {}",
+                    Binder(&arg.name.blue().to_string(), kind),
+                    body
+                )
             }
             RawExpr::If {
                 cond,
@@ -370,6 +1059,90 @@ impl Display for RawExpr {
             } => {
                 write!(f, "(if {cond} then {branch_t} else {branch_f})")
             }
+            RawExpr::Case { exp, arms } => {
+                write!(f, "case {exp} of ")?;
+                for (i, (pat, body)) in arms.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{pat} => {body}")?;
+                }
+                write!(f, " end")
+            }
+            RawExpr::Record { fields } => {
+                write!(f, "{{")?;
+                for (i, (l, e)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{l} = {e}")?;
+                }
+                write!(f, "}}")
+            }
+            RawExpr::Proj { exp, field } => {
+                atomize(f, exp)?;
+                write!(f, ".{field}")
+            }
+            RawExpr::Nth { exp, index } => {
+                atomize(f, exp)?;
+                write!(f, ".{index}")
+            }
+            RawExpr::Fold { typ, exp } => {
+                write!(f, "fold [{typ}] ")?;
+                atomize(f, exp)
+            }
+            RawExpr::Unfold { exp } => {
+                write!(f, "unfold ")?;
+                atomize(f, exp)
+            }
+            RawExpr::Pack { witness, exp, typ } => {
+                write!(f, "pack [{witness}] ")?;
+                atomize(f, exp)?;
+                write!(f, " as {typ}")
+            }
+            RawExpr::Unpack {
+                tvar,
+                var,
+                exp,
+                body,
+            } => write!(f, "unpack [{tvar}] {var} = {exp} in {body}"),
+            RawExpr::Open { exp, body } => {
+                write!(f, "open ")?;
+                atomize(f, exp)?;
+                write!(f, " in {body}")
+            }
+            RawExpr::Ref { exp } => {
+                write!(f, "ref ")?;
+                atomize(f, exp)
+            }
+            RawExpr::Deref { exp } => {
+                write!(f, "!")?;
+                atomize(f, exp)
+            }
+            RawExpr::AssertEq { lhs, rhs } => write!(f, "assertEq({lhs}, {rhs})"),
+            RawExpr::Spawn { exp } => {
+                write!(f, "spawn ")?;
+                atomize(f, exp)
+            }
+            RawExpr::Join { exp } => {
+                write!(f, "join ")?;
+                atomize(f, exp)
+            }
+            RawExpr::Assign { lhs, rhs } => write!(f, "{lhs} := {rhs}"),
+            RawExpr::Array { len, init } => write!(f, "array({len}, {init})"),
+            RawExpr::Sub { arr, idx } => write!(f, "sub({arr}, {idx})"),
+            RawExpr::Update { arr, idx, val } => write!(f, "update({arr}, {idx}, {val})"),
+            RawExpr::Raise { exp, typ } => {
+                write!(f, "raise [{typ}] ")?;
+                atomize(f, exp)
+            }
+            RawExpr::Handle { exp, pat, handler } => {
+                atomize(f, exp)?;
+                write!(f, " handle {pat} => {handler}")
+            }
+            RawExpr::Instance { class, typ } => write!(f, "instance {} [{typ}]", class.blue()),
+            RawExpr::Ascribe { exp, typ } => write!(f, "({exp} : {typ})"),
+            RawExpr::Convert { exp, to, .. } => write!(f, "int{to}({exp})"),
         }
     }
 }
@@ -396,6 +1169,28 @@ impl Display for RawPattern {
             }
             RawPattern::Binding(v) => write!(f, "{v}"),
             RawPattern::Wildcard => write!(f, "_"),
+            RawPattern::Literal(c) => write!(f, "{c}"),
+            RawPattern::Ctor(c, pats) => {
+                write!(f, "{c}")?;
+                for p in pats {
+                    if matches!(&p.pat, RawPattern::Ctor(_, ps) if !ps.is_empty()) {
+                        write!(f, " ({p})")?
+                    } else {
+                        write!(f, " {p}")?
+                    }
+                }
+                Ok(())
+            }
+            RawPattern::Record(fields) => {
+                write!(f, "{{")?;
+                for (i, (l, p)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?
+                    }
+                    write!(f, "{l} = {p}")?
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
@@ -403,9 +1198,10 @@ impl Display for RawPattern {
 impl Display for Constant {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Constant::Integer(i) => write!(f, "{i}"),
+            Constant::Integer(i) | Constant::Sized(i, _) => write!(f, "{i}"),
             Constant::Boolean(b) => write!(f, "{b}"),
             Constant::Null => write!(f, "null"),
+            Constant::Str(s) => write!(f, "{s:?}"),
         }
     }
 }
@@ -417,6 +1213,13 @@ impl Display for Binary {
             Add => "+",
             Sub => "-",
             Mul => "*",
+            Div => "/",
+            Mod => "%",
+            Land => "land",
+            Lor => "lor",
+            Lxor => "lxor",
+            Shl => "<<",
+            Shr => ">>",
             Eq => "==",
             Ne => "!=",
             Lt => "<",
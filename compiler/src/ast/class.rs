@@ -0,0 +1,332 @@
+/*! Type classes, elaborated away into dictionary passing before type checking.
+A class `class Eq A { eq: A -> A -> Bool }` becomes the data type `data Eq A = Eq {eq: A -> A -> Bool}`
+of its dictionaries, and each method a function taking a dictionary, `eq: ∀ A. Eq A -> A -> A -> Bool`.
+An instance becomes a declaration of its dictionary. A type abstraction constrained by classes,
+`Λ (A: Eq). e` of type `∀ (A: Eq). T`, takes their dictionaries as parameters after the type, as
+`Λ A. λ #Eq#A: Eq A. e` of type `∀ A. Eq A -> T`. Every use of a method or of a declaration
+constrained this way is applied to an [`Instance`](RawExpr::Instance) for each dictionary, which
+inference replaces by the dictionary of the right type, a parameter if it's in scope. */
+
+use crate::ast::ast::{
+    grow, ClassDecl, DataDecl, Decl, Expr, Ident, InstanceDecl, Kind, Pattern, Prog, RawExpr,
+    RawPattern, RawType, Span, Type,
+};
+use crate::ast::error::TypeError;
+use crate::ast::semant::{equivalent, expand, prog_aliases};
+use annotate_snippets::snippet::{AnnotationType, SourceAnnotation};
+use im::HashMap;
+
+/// Separates the class from the number of the instance in the names of dictionaries
+const SEPARATOR: char = '$';
+
+/// Starts the names of dictionary parameters and separates their class from their type variable
+const PARAMETER: char = '#';
+
+/** Whether the variable `id` holds the dictionary of an instance declared by the program */
+pub fn is_instance(id: &str) -> bool {
+    id.contains(SEPARATOR)
+}
+
+/** Name of the parameter taking the dictionary of `class` for `tvar` in `Λ (tvar: class). e`.
+Programs can't name it */
+pub fn dictionary_param(class: &str, tvar: &str) -> String {
+    format!("{PARAMETER}{class}{PARAMETER}{tvar}")
+}
+
+/// Arguments passed implicitly to methods and constrained declarations
+#[derive(Clone)]
+enum Param {
+    /// A type argument, inferred unless given explicitly
+    Type,
+    /// The dictionary of the class, always inferred
+    Dictionary(String),
+}
+
+/** Elaborates the classes and instances of `prog` into data types and declarations.
+Declarations of methods and instances come before the rest, in order of definition.
+Returns: `prog` without classes and instances, or `TypeError` if a method is defined twice,
+an instance or a constraint is for an unknown class, or two instances of a class are for the
+same type */
+pub fn elaborate_classes(prog: &Prog) -> Result<Prog, TypeError> {
+    let mut out = Prog {
+        classes: vec![],
+        instances: vec![],
+        order: vec![],
+        ..prog.clone()
+    };
+    // The arguments passed implicitly to each method and constrained declaration
+    let mut implicit = HashMap::new();
+    let mut decls = vec![];
+    for class in &prog.classes {
+        out.datatypes.push(dictionary_type(class));
+        for (method, typ) in &class.methods {
+            if prog.declarations.contains_key(&method.name)
+                || implicit
+                    .insert(
+                        method.name.clone(),
+                        vec![Param::Type, Param::Dictionary(class.id.name.clone())],
+                    )
+                    .is_some()
+            {
+                return Err(error(
+                    "Redefinition of method",
+                    method.span.unwrap(),
+                    "a method or declaration with this name already exists",
+                ));
+            }
+            decls.push(method_decl(class, method, typ));
+        }
+    }
+    for id in &prog.order {
+        let params = constraints(&prog.declarations[id].body, &prog.classes)?;
+        if !params.is_empty() {
+            implicit.insert(id.clone(), params);
+        }
+    }
+    let aliases = prog_aliases(&out)?;
+    let mut defined: Vec<(&str, RawType)> = vec![];
+    for (i, inst) in prog.instances.iter().enumerate() {
+        let Some(class) = prog.classes.iter().find(|c| c.id.name == inst.class.name) else {
+            return Err(error(
+                "Unknown class",
+                inst.class.span.unwrap(),
+                "no class with this name has been declared",
+            ));
+        };
+        let mut typ = inst.typ.typ.clone();
+        expand(&mut typ, &aliases);
+        if defined
+            .iter()
+            .any(|(c, t)| *c == class.id.name && equivalent(t, &typ))
+        {
+            return Err(error(
+                "Overlapping instances",
+                inst.typ.span.unwrap(),
+                "an instance of this class for this type already exists",
+            ));
+        }
+        defined.push((&class.id.name, typ));
+        let mut decl = instance_decl(inst, i);
+        pass_dictionaries(&mut decl.body, &implicit);
+        decls.push(decl);
+    }
+    for id in &prog.order {
+        let mut decl = prog.declarations[id].clone();
+        pass_dictionaries(&mut decl.body, &implicit);
+        out.declarations.insert(id.clone(), decl);
+    }
+    for decl in decls {
+        out.order.push(decl.id.clone());
+        out.declarations.insert(decl.id.clone(), decl);
+    }
+    out.order.extend(prog.order.iter().cloned());
+    Ok(out)
+}
+
+/** The data type of the dictionaries of `class`, with one constructor named after the class */
+fn dictionary_type(class: &ClassDecl) -> DataDecl {
+    let record = Type {
+        typ: RawType::Record(class.methods.clone()),
+        span: class.span,
+    };
+    DataDecl {
+        id: class.id.name.clone(),
+        params: vec![class.param.clone()],
+        ctors: vec![(class.id.clone(), vec![record])],
+        deriving: vec![],
+        newtype: false,
+        doc: class.doc.clone(),
+        span: class.span,
+    }
+}
+
+/** `let method: ∀ A. C A -> T = Λ A. λ d: C A. case d of C r => r.method end` for the
+`method` of type `T` in the class `C` over `A` */
+fn method_decl(class: &ClassDecl, method: &Ident, typ: &Type) -> Decl {
+    let span = method.span;
+    let expr = |expr| Expr { expr, span };
+    let var = |id: &str| expr(RawExpr::Var { id: id.to_string() });
+    let ident = |name: &str| Ident {
+        name: name.to_string(),
+        span,
+    };
+    let dict_typ = Type::new(RawType::Data(
+        class.id.name.clone(),
+        vec![Type::new(RawType::TVar(class.param.name.clone()))],
+    ));
+    let arm = (
+        Pattern {
+            pat: RawPattern::Ctor(
+                class.id.clone(),
+                vec![Pattern {
+                    pat: RawPattern::Binding(ident("r")),
+                    span,
+                }],
+            ),
+            span,
+        },
+        expr(RawExpr::Proj {
+            exp: Box::new(var("r")),
+            field: method.clone(),
+        }),
+    );
+    let body = expr(RawExpr::Any {
+        arg: class.param.clone(),
+        kind: Kind::Star,
+        body: Box::new(expr(RawExpr::Lambda {
+            arg: (ident("d"), dict_typ.clone()),
+            body: Box::new(expr(RawExpr::Case {
+                exp: Box::new(var("d")),
+                arms: vec![arm],
+            })),
+        })),
+    });
+    let sig = RawType::Forall(
+        class.param.clone(),
+        Kind::Star,
+        Box::new(Type::new(RawType::Arrow(
+            Box::new(dict_typ),
+            Box::new(typ.clone()),
+        ))),
+    );
+    Decl {
+        id: method.name.clone(),
+        sig: Type::new(sig),
+        body,
+        doc: None,
+        attrs: vec![],
+        span,
+    }
+}
+
+/** `let C$i: C T = C [T] {method = ...}` for the `i`th instance `inst`, of the class `C` for `T` */
+fn instance_decl(inst: &InstanceDecl, i: usize) -> Decl {
+    let span = inst.span;
+    let expr = |expr| Expr { expr, span };
+    let ctor = expr(RawExpr::TApp {
+        exp: Box::new(expr(RawExpr::Var {
+            id: inst.class.name.clone(),
+        })),
+        arg: inst.typ.clone(),
+    });
+    let body = expr(RawExpr::EApp {
+        exp: Box::new(ctor),
+        arg: Box::new(expr(RawExpr::Record {
+            fields: inst.methods.clone(),
+        })),
+    });
+    Decl {
+        id: format!("{}{SEPARATOR}{i}", inst.class.name),
+        sig: Type::new(RawType::Data(
+            inst.class.name.clone(),
+            vec![inst.typ.clone()],
+        )),
+        body,
+        doc: inst.doc.clone(),
+        attrs: vec![],
+        span,
+    }
+}
+
+/** The arguments a declaration with body `body` takes implicitly, up to the last dictionary of
+the classes constraining its type abstractions, ex. a type and an `Eq` dictionary for
+`Λ (A: Eq). Λ B. e`. Returns: `TypeError` if a constraint is for a class not in `classes` */
+fn constraints(mut body: &Expr, classes: &[ClassDecl]) -> Result<Vec<Param>, TypeError> {
+    let mut params = vec![];
+    let mut len = 0;
+    loop {
+        body = match &body.expr {
+            RawExpr::Any { body, .. } => {
+                params.push(Param::Type);
+                body
+            }
+            RawExpr::Lambda {
+                arg: (param, typ),
+                body,
+            } if param.name.starts_with(PARAMETER) => {
+                let RawType::Data(class, _) = &typ.typ else {
+                    unreachable!("the parser makes dictionary parameters")
+                };
+                if !classes.iter().any(|c| &c.id.name == class) {
+                    return Err(error(
+                        "Unknown class",
+                        typ.span.unwrap(),
+                        "no class with this name has been declared",
+                    ));
+                }
+                params.push(Param::Dictionary(class.clone()));
+                len = params.len();
+                body
+            }
+            _ => break,
+        }
+    }
+    params.truncate(len);
+    Ok(params)
+}
+
+/** Applies the methods and constrained declarations occurring in `expr` to the dictionaries
+they take, after the type arguments they're applied to explicitly if any. `implicit` maps them
+to their implicit arguments. Variables can't shadow declarations, so every occurrence of their
+names is them */
+fn pass_dictionaries(expr: &mut Expr, implicit: &HashMap<String, Vec<Param>>) {
+    grow(|| {
+        // The type arguments of a spine of type applications, last first
+        let mut targs = vec![];
+        let mut head = &*expr;
+        while let RawExpr::TApp { exp, arg } = &head.expr {
+            targs.push(arg.clone());
+            head = exp
+        }
+        match &head.expr {
+            RawExpr::Var { id } if implicit.contains_key(id) => {
+                let span = expr.span;
+                let at = |expr| Expr { expr, span };
+                let mut exp = head.clone();
+                for param in &implicit[id] {
+                    exp = at(match param {
+                        Param::Type => match targs.pop() {
+                            Some(arg) => RawExpr::TApp {
+                                exp: Box::new(exp),
+                                arg,
+                            },
+                            None => continue,
+                        },
+                        Param::Dictionary(class) => RawExpr::EApp {
+                            exp: Box::new(exp),
+                            arg: Box::new(at(RawExpr::Instance {
+                                class: class.clone(),
+                                typ: Type::new(RawType::Hole),
+                            })),
+                        },
+                    })
+                }
+                while let Some(arg) = targs.pop() {
+                    exp = at(RawExpr::TApp {
+                        exp: Box::new(exp),
+                        arg,
+                    })
+                }
+                *expr = exp
+            }
+            _ => expr
+                .expr
+                .subexprs_mut()
+                .into_iter()
+                .for_each(|e| pass_dictionaries(e, implicit)),
+        }
+    })
+}
+
+/** Error titled `title` pointing at `range` */
+pub(crate) fn error(title: &'static str, range: Span, label: &'static str) -> TypeError {
+    TypeError {
+        title,
+        annot_type: AnnotationType::Error,
+        annotations: vec![SourceAnnotation {
+            range,
+            label,
+            annotation_type: AnnotationType::Error,
+        }],
+    }
+}
@@ -0,0 +1,154 @@
+/*! Coverage of programs by the interpreter. Evaluation counts the times it starts on each
+expression, by span, and the expressions of the declarations of the program are reported covered
+or not, line by line like gcov, or as an lcov tracefile. The branches are the arms of `if` and
+`case`, taken when their bodies are evaluated */
+
+use crate::ast::ast::{Expr, Prog, RawExpr, Span};
+use crate::ast::interp::{eval_prog_in, EvalError, EvalOutcome, Store};
+
+use std::fmt::Write;
+
+use im::hashmap::HashMap;
+
+pub struct Coverage {
+    /// Offsets of the starts of the lines of the source
+    lines: Vec<usize>,
+    /// Spans of the expressions of the declarations, in order of their starts
+    exprs: Vec<Span>,
+    /// Spans of the `if` and `case` expressions and of their arms, in order of their starts
+    branches: Vec<(Span, Vec<Span>)>,
+    /// Times evaluation started on the expressions at each span
+    hits: HashMap<Span, usize>,
+}
+
+/** Evaluates `prog`, parsed from `source`, through `store` while recording coverage.
+Returns: The coverage as far as evaluation got, and the outcome of evaluation */
+pub fn cover(
+    prog: &Prog,
+    source: &str,
+    store: &mut Store,
+) -> (Coverage, Result<EvalOutcome, EvalError>) {
+    store.hits = Some(HashMap::new());
+    let outcome = eval_prog_in(prog, store);
+    let hits = store.hits.take().unwrap_or_default();
+    (Coverage::new(prog, source, hits), outcome)
+}
+
+impl Coverage {
+    /** The coverage of the declarations of `prog`, parsed from `source`, by the `hits` of
+    evaluation */
+    pub fn new(prog: &Prog, source: &str, hits: HashMap<Span, usize>) -> Coverage {
+        let mut coverage = Coverage {
+            lines: std::iter::once(0)
+                .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+                .collect(),
+            exprs: vec![],
+            branches: vec![],
+            hits,
+        };
+        for decl in prog.declarations.values() {
+            coverage.collect(&decl.body)
+        }
+        coverage.exprs.sort();
+        coverage.exprs.dedup();
+        coverage.branches.sort();
+        coverage
+    }
+
+    fn collect(&mut self, expr: &Expr) {
+        match &expr.expr {
+            // Erased before evaluation
+            RawExpr::Ascribe { exp, .. } => return self.collect(exp),
+            RawExpr::If {
+                branch_t, branch_f, ..
+            } => self.branch(expr.span, [&**branch_t, &**branch_f]),
+            RawExpr::Case { arms, .. } => self.branch(expr.span, arms.iter().map(|(_, e)| e)),
+            _ => (),
+        }
+        if let Some(span) = expr.span {
+            self.exprs.push(span)
+        }
+        for sub in expr.subexprs() {
+            self.collect(sub)
+        }
+    }
+
+    fn branch<'a>(&mut self, span: Option<Span>, arms: impl IntoIterator<Item = &'a Expr>) {
+        let arms: Option<Vec<Span>> = arms.into_iter().map(|e| e.span).collect();
+        if let (Some(span), Some(arms)) = (span, arms) {
+            self.branches.push((span, arms))
+        }
+    }
+
+    /** The times evaluation started on the expression at `span` */
+    pub fn hits(&self, span: Span) -> usize {
+        self.hits.get(&span).copied().unwrap_or(0)
+    }
+
+    /** The number of expressions evaluated at least once, and of all expressions */
+    pub fn covered(&self) -> (usize, usize) {
+        let covered = self.exprs.iter().filter(|&&e| self.hits(e) > 0).count();
+        (covered, self.exprs.len())
+    }
+
+    /** For each line of the source, counting from 1, the fewest times an expression starting on
+    it was evaluated, or `None` if none starts on it. Lines partly evaluated, like those of
+    functions never called, count 0 */
+    pub fn line_hits(&self) -> Vec<Option<usize>> {
+        let mut lines = vec![None; self.lines.len()];
+        for &span in &self.exprs {
+            let line = &mut lines[self.line_of(span) - 1];
+            *line = Some(line.unwrap_or(usize::MAX).min(self.hits(span)))
+        }
+        lines
+    }
+
+    fn line_of(&self, (start, _): Span) -> usize {
+        self.lines.partition_point(|&i| i <= start)
+    }
+
+    /** `source` with each line preceded by its hits, `-` if no expression starts on it, and
+    `#####` if one of those wasn't evaluated */
+    pub fn annotated(&self, source: &str) -> String {
+        let mut out = String::new();
+        for (i, (hits, line)) in self.line_hits().into_iter().zip(source.lines()).enumerate() {
+            let hits = match hits {
+                Some(0) => "#####".to_string(),
+                Some(n) => n.to_string(),
+                None => "-".to_string(),
+            };
+            writeln!(out, "{:>9}:{:>5}:{}", hits, i + 1, line).unwrap();
+        }
+        out
+    }
+
+    /** The lcov tracefile of the coverage of the source file at `path` */
+    pub fn lcov(&self, path: &str) -> String {
+        let mut out = format!("TN:\nSF:{}\n", path);
+        let (mut found, mut hit) = (0, 0);
+        for (block, (span, arms)) in self.branches.iter().enumerate() {
+            let line = self.line_of(*span);
+            for (branch, &arm) in arms.iter().enumerate() {
+                // Branches of conditionals never evaluated are marked `-`
+                let taken = match self.hits(*span) {
+                    0 => "-".to_string(),
+                    _ => self.hits(arm).to_string(),
+                };
+                writeln!(out, "BRDA:{},{},{},{}", line, block, branch, taken).unwrap();
+                found += 1;
+                hit += (self.hits(arm) > 0) as usize
+            }
+        }
+        writeln!(out, "BRF:{}\nBRH:{}", found, hit).unwrap();
+        let (mut found, mut hit) = (0, 0);
+        for (i, hits) in self.line_hits().into_iter().enumerate() {
+            if let Some(hits) = hits {
+                writeln!(out, "DA:{},{}", i + 1, hits).unwrap();
+                found += 1;
+                hit += (hits > 0) as usize
+            }
+        }
+        writeln!(out, "LF:{}\nLH:{}\nend_of_record", found, hit).unwrap();
+        out
+    }
+}
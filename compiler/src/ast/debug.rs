@@ -0,0 +1,262 @@
+/*! Debugger for programs, built on the small-step evaluation of [`step`]. A declaration of the
+program reduces one step at a time, and the declarations it uses are replaced by their bodies
+when evaluation reaches them, and the builtins, references and arrays read and write a heap of its
+own. Evaluation pauses at breakpoints on the names of declarations, before
+their bodies replace them, and on lines of the source, when the expression reduced next starts on
+the line coming from another one.
+
+[`step`]: crate::ast::step */
+
+use crate::ast::ast::{Expr, Prog, RawExpr, RawType};
+use crate::ast::class::elaborate_classes;
+use crate::ast::derive::derive;
+use crate::ast::error::TypeError;
+use crate::ast::infer::elaborate_decl;
+use crate::ast::interp::Store;
+use crate::ast::lower::{lower, Newtypes};
+use crate::ast::module::elaborate_modules;
+use crate::ast::semant::{
+    builtin_context, check_decl, define_datatype, define_exception, expand_decl, prog_aliases,
+    well_formed_decl, with_prelude,
+};
+use crate::ast::step::{evaluated_mut, is_value, raised, redex_mut, step, Heap};
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use im::hashmap::HashMap;
+
+/// Where evaluation pauses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Before the body of the declaration replaces its name
+    Decl(String),
+    /// Before reducing an expression starting on the line, counting from 1
+    Line(usize),
+}
+
+impl FromStr for Breakpoint {
+    type Err = ();
+
+    /// Line numbers, or names of declarations
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(line) => Ok(Breakpoint::Line(line)),
+            Err(_) if s.starts_with(char::is_lowercase) => Ok(Breakpoint::Decl(s.to_string())),
+            Err(_) => Err(()),
+        }
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Decl(id) => write!(f, "{}", id),
+            Breakpoint::Line(line) => write!(f, "line {}", line),
+        }
+    }
+}
+
+/// Why the debugger stopped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pause {
+    /// Before the step a breakpoint is on
+    Breakpoint(Breakpoint),
+    /// After a single step
+    Step,
+    /// The expression is a value, raises an exception, or is stuck
+    Done,
+}
+
+/// The next step of evaluation: reducing the expression on the line, or unfolding the declaration,
+/// the expression after it
+enum Action {
+    Reduce(Option<usize>),
+    Unfold(Box<Expr>, String),
+}
+
+pub struct Debugger<'a> {
+    /// Offsets of the starts of the lines of the source
+    lines: Vec<usize>,
+    /// Lowered bodies of the declarations
+    decls: HashMap<String, Expr>,
+    /// Declarations and their types, in order
+    env: Vec<(String, RawType)>,
+    pub breakpoints: Vec<Breakpoint>,
+    expr: Expr,
+    heap: Heap<'a>,
+    /// Line of the last expression reduced
+    line: Option<usize>,
+    /// Whether the next step is on the breakpoint evaluation paused at
+    paused: bool,
+    steps: usize,
+}
+
+impl<'a> Debugger<'a> {
+    /** Type checks `prog`, parsed from `source`, to debug its declaration `entry`, the builtins
+    reading and writing through `store`.
+    Returns: The debugger paused before the first step, or `TypeError` if `prog` is ill-typed */
+    pub fn new(
+        prog: &Prog,
+        source: &str,
+        entry: &str,
+        store: Store<'a>,
+    ) -> Result<Debugger<'a>, TypeError> {
+        let prog = &elaborate_classes(&derive(&elaborate_modules(&with_prelude(prog))?)?)?;
+        let mut ctxt = builtin_context();
+        let mut newtypes = Newtypes::default();
+        let mut aliases = prog_aliases(prog)?;
+        for data in &prog.datatypes {
+            define_datatype(data, &mut ctxt, &mut aliases)?;
+            if data.newtype {
+                newtypes.insert(data.ctors[0].0.name.clone());
+            }
+        }
+        for exn in &prog.exceptions {
+            define_exception(exn, &mut ctxt, &aliases)?;
+        }
+        let mut decls = HashMap::new();
+        let mut env = vec![];
+        for id in &prog.order {
+            well_formed_decl(&prog.declarations[id], &aliases)?;
+            let decl = expand_decl(&prog.declarations[id], &aliases);
+            let (mut decl, _) = elaborate_decl(&decl, &ctxt)?;
+            check_decl(&decl, &mut ctxt)?;
+            lower(&mut decl.body, &newtypes);
+            env.push((id.clone(), ctxt[id].clone()));
+            decls.insert(id.clone(), decl.body);
+        }
+        let lines = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Ok(Debugger {
+            lines,
+            expr: decls[entry].clone(),
+            decls,
+            env,
+            heap: Heap::new(store),
+            breakpoints: vec![],
+            line: None,
+            paused: false,
+            steps: 0,
+        })
+    }
+
+    /** The expression under evaluation */
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    /** The number of steps taken */
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /** The declarations in scope and their types, in order */
+    pub fn env(&self) -> &[(String, RawType)] {
+        &self.env
+    }
+
+    /** The line of the expression reduced next, if it comes from the source */
+    pub fn line(&self) -> Option<usize> {
+        let mut expr = self.expr.clone();
+        self.line_of(redex_mut(&mut expr))
+    }
+
+    /** Takes one step. Returns: [`Pause::Step`], or [`Pause::Done`] if there's none to take */
+    pub fn step(&mut self) -> Pause {
+        match self.action() {
+            Some(action) => self.take(action),
+            None => Pause::Done,
+        }
+    }
+
+    /** Takes steps until the next one is on a breakpoint, or there's none to take */
+    pub fn resume(&mut self) -> Pause {
+        loop {
+            let Some(action) = self.action() else {
+                return Pause::Done;
+            };
+            // The step paused on is taken
+            if !self.paused {
+                if let Some(breakpoint) = self.hit(&action) {
+                    self.paused = true;
+                    return Pause::Breakpoint(breakpoint);
+                }
+            }
+            if self.take(action) == Pause::Done {
+                return Pause::Done;
+            }
+        }
+    }
+
+    /** The next step, unless the expression is a value or raises an exception. It may still be
+    stuck, which only taking it tells, since it may read and write the heap */
+    fn action(&self) -> Option<Action> {
+        let mut next = self.expr.clone();
+        match self.unfold(&mut next) {
+            Some(id) => Some(Action::Unfold(Box::new(next), id)),
+            None if is_value(&self.expr) || raised(&self.expr).is_some() => None,
+            None => Some(Action::Reduce(self.line())),
+        }
+    }
+
+    /** Replaces the first declaration evaluation reaches in `expr` by its body.
+    Returns: The name of the declaration, if there's one */
+    fn unfold(&self, expr: &mut Expr) -> Option<String> {
+        let subs = match &mut expr.expr {
+            // Applications of declarations are stuck, like those of constructors
+            RawExpr::Var { id } => {
+                let body = self.decls.get(id)?;
+                let id = id.clone();
+                *expr = body.clone();
+                return Some(id);
+            }
+            RawExpr::Handle { exp, .. } => vec![exp.as_mut()],
+            e => evaluated_mut(e),
+        };
+        for sub in subs {
+            let value = is_value(sub);
+            if let Some(id) = self.unfold(sub) {
+                return Some(id);
+            }
+            // Later subexpressions aren't evaluated yet
+            if !value {
+                break;
+            }
+        }
+        None
+    }
+
+    /** The breakpoint `action` is on */
+    fn hit(&self, action: &Action) -> Option<Breakpoint> {
+        let breakpoint = match action {
+            Action::Reduce(Some(line)) if Some(*line) != self.line => Breakpoint::Line(*line),
+            Action::Unfold(_, id) => Breakpoint::Decl(id.clone()),
+            _ => return None,
+        };
+        self.breakpoints.contains(&breakpoint).then_some(breakpoint)
+    }
+
+    /** Takes the step `action`. Returns: [`Pause::Step`], or [`Pause::Done`] if it's stuck */
+    fn take(&mut self, action: Action) -> Pause {
+        match action {
+            Action::Reduce(line) => {
+                let Some(next) = step(&self.expr, &mut self.heap) else {
+                    return Pause::Done;
+                };
+                self.expr = next;
+                self.line = line
+            }
+            Action::Unfold(next, _) => self.expr = *next,
+        }
+        self.paused = false;
+        self.steps += 1;
+        Pause::Step
+    }
+
+    fn line_of(&self, expr: &Expr) -> Option<usize> {
+        let (start, _) = expr.span?;
+        Some(self.lines.partition_point(|&i| i <= start))
+    }
+}
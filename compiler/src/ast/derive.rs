@@ -0,0 +1,580 @@
+/*! Derived functions of data types, synthesized before type checking.
+A data type `data Option A = None | Some A deriving (Eq)` comes with the declaration
+`eq_Option: ∀ A. (A -> A -> Bool) -> Option A -> Option A -> Bool`, comparing values by
+constructor and then field by field. Fields whose types mention no parameter are compared with
+`==`, fields of a parameter with the equality passed for it, and fields of other data types with
+their derived equality.
+Deriving `Show` declares `show_Option: ∀ A. (A -> String) -> Option A -> String` in the same way,
+showing values like the interpreter prints them, ex. `Some (Some 1)`: the constructor, then its
+fields separated by spaces. Fields of data types are in parentheses when their constructor has
+fields. Strings are quoted without escaping, and fields of a parameter are shown by the function
+passed for it, never in parentheses. */
+
+use crate::ast::ast::{
+    Binary, Constant, DataDecl, Decl, Expr, Ident, Kind, Pattern, Prog, RawExpr, RawPattern,
+    RawType, Span, Type, Width,
+};
+use crate::ast::class::error;
+use crate::ast::error::TypeError;
+use crate::ast::semant::free_in;
+use std::iter::zip;
+
+/// The classes that can be derived
+const EQ: &str = "Eq";
+const SHOW: &str = "Show";
+
+/** Name of the derived equality of the data type `id`, ex. `eq_Option` */
+pub fn eq_name(id: &str) -> String {
+    format!("eq_{id}")
+}
+
+/** Name of the derived `Show` of the data type `id`, ex. `show_Option` */
+pub fn show_name(id: &str) -> String {
+    format!("show_{id}")
+}
+
+/** Adds the functions derived by the data types of `prog` to its declarations, before the rest.
+Returns: The extended `prog`, or `TypeError` if a class can't be derived, a field has no
+derivable equality or `Show`, or a derived function is already declared */
+pub fn derive(prog: &Prog) -> Result<Prog, TypeError> {
+    let mut out = Prog {
+        order: vec![],
+        ..prog.clone()
+    };
+    let mut derived: Vec<&DataDecl> = vec![];
+    for data in &prog.datatypes {
+        let deriving = |class: &str, id: &str| {
+            derived
+                .iter()
+                .copied()
+                .find(|d| d.id == id && d.deriving.iter().any(|c| c.name == class))
+        };
+        for decl in derived_decls(data, deriving)? {
+            if prog.declarations.contains_key(&decl.id) {
+                return Err(error(
+                    "Redefinition of derived function",
+                    data.span.unwrap(),
+                    "a declaration with the name of a function derived here already exists",
+                ));
+            }
+            out.order.push(decl.id.clone());
+            out.declarations.insert(decl.id.clone(), decl);
+        }
+        derived.push(data);
+    }
+    out.order.extend(prog.order.iter().cloned());
+    Ok(out)
+}
+
+/** The functions derived by `data`, where `deriving(class, id)` gives the other data type `id` in
+scope if it derives `class` */
+pub fn derived_decls<'d>(
+    data: &DataDecl,
+    deriving: impl Fn(&str, &str) -> Option<&'d DataDecl>,
+) -> Result<Vec<Decl>, TypeError> {
+    let mut decls = vec![];
+    for class in &data.deriving {
+        let mut deriver = Deriver {
+            data,
+            deriving: &deriving,
+            fresh: 0,
+        };
+        decls.push(match class.name.as_str() {
+            EQ => deriver.eq_decl()?,
+            SHOW => deriver.show_decl()?,
+            _ => {
+                return Err(error(
+                    "Underivable class",
+                    class.span.unwrap(),
+                    "only Eq and Show can be derived",
+                ))
+            }
+        })
+    }
+    Ok(decls)
+}
+
+struct Deriver<'a, F> {
+    data: &'a DataDecl,
+    deriving: &'a F,
+    /// Number of variables bound so far, to keep their names apart
+    fresh: usize,
+}
+
+impl<'d, F: Fn(&str, &str) -> Option<&'d DataDecl>> Deriver<'_, F> {
+    /** `let eq_T: ∀ A. (A -> A -> Bool) -> T A -> T A -> Bool = Λ A. λ eq'A. fix eq' x = λ y.
+    case (x, y) of (C x'1, C y'1) => ...; _ => false end in eq'` for the data type `T` over `A` */
+    fn eq_decl(&mut self) -> Result<Decl, TypeError> {
+        let data = self.data;
+        let span = data.span;
+        let typ = Type::new(RawType::Data(
+            data.id.clone(),
+            data.params.iter().map(|p| tvar(&p.name)).collect(),
+        ));
+        let mut arms = vec![];
+        for (ctor, fields) in &data.ctors {
+            let xs: Vec<String> = fields.iter().map(|_| self.fresh("x")).collect();
+            let ys: Vec<String> = fields.iter().map(|_| self.fresh("y")).collect();
+            let mut eqs = vec![];
+            for ((field, x), y) in zip(zip(fields, &xs), &ys) {
+                eqs.push(self.field_eq(field, var(x, span), var(y, span))?)
+            }
+            let pat = |vars: &[String]| Pattern {
+                pat: RawPattern::Ctor(
+                    ctor.clone(),
+                    vars.iter().map(|v| binding(v, span)).collect(),
+                ),
+                span,
+            };
+            arms.push((tuple_pat(vec![pat(&xs), pat(&ys)], span), conj(eqs, span)));
+        }
+        if data.ctors.len() != 1 {
+            let wildcard = Pattern {
+                pat: RawPattern::Wildcard,
+                span,
+            };
+            arms.push((wildcard, boolean(false, span)));
+        }
+        let case = expr(
+            RawExpr::Case {
+                exp: Box::new(tuple(vec![var("x'", span), var("y'", span)], span)),
+                arms,
+            },
+            span,
+        );
+        let pred = arrow(typ.clone(), Type::new(RawType::Bool));
+        let func = expr(
+            RawExpr::Fix {
+                funcs: vec![(
+                    ident("eq'", span),
+                    ident("x'", span),
+                    typ.clone(),
+                    pred.clone(),
+                    expr(
+                        RawExpr::Lambda {
+                            arg: (ident("y'", span), typ.clone()),
+                            body: Box::new(case),
+                        },
+                        span,
+                    ),
+                )],
+                body: Box::new(var("eq'", span)),
+            },
+            span,
+        );
+        let eq = |a: Type| arrow(a.clone(), arrow(a, Type::new(RawType::Bool)));
+        Ok(self.generic(func, arrow(typ, pred), "eq'", eq, eq_name(&data.id)))
+    }
+
+    /** `let show_T: ∀ A. (A -> String) -> T A -> String = Λ A. λ show'A. fix show' x = case x of
+    C x'1 => concat ("C ", ...); ... end in show'` for the data type `T` over `A` */
+    fn show_decl(&mut self) -> Result<Decl, TypeError> {
+        let data = self.data;
+        let span = data.span;
+        let typ = Type::new(RawType::Data(
+            data.id.clone(),
+            data.params.iter().map(|p| tvar(&p.name)).collect(),
+        ));
+        let mut arms = vec![];
+        for (ctor, fields) in &data.ctors {
+            let xs: Vec<String> = fields.iter().map(|_| self.fresh("x")).collect();
+            let mut parts = vec![string(&ctor.name, span)];
+            for (field, x) in zip(fields, &xs) {
+                parts.push(string(" ", span));
+                parts.push(self.field_show(field, var(x, span), true)?)
+            }
+            let pat = Pattern {
+                pat: RawPattern::Ctor(ctor.clone(), xs.iter().map(|v| binding(v, span)).collect()),
+                span,
+            };
+            arms.push((pat, concat(parts, span)));
+        }
+        let case = expr(
+            RawExpr::Case {
+                exp: Box::new(var("x'", span)),
+                arms,
+            },
+            span,
+        );
+        let func = expr(
+            RawExpr::Fix {
+                funcs: vec![(
+                    ident("show'", span),
+                    ident("x'", span),
+                    typ.clone(),
+                    Type::new(RawType::Str),
+                    case,
+                )],
+                body: Box::new(var("show'", span)),
+            },
+            span,
+        );
+        let show = |a: Type| arrow(a, Type::new(RawType::Str));
+        Ok(self.generic(func, show(typ), "show'", show, show_name(&data.id)))
+    }
+
+    /** The declaration `id` of `func: sig` abstracted over the parameters of the data type, and over
+    the functions of type `method(A)` for each parameter `A`, named `prefix` then `A` */
+    fn generic(
+        &self,
+        func: Expr,
+        sig: Type,
+        prefix: &str,
+        method: impl Fn(Type) -> Type,
+        id: String,
+    ) -> Decl {
+        let data = self.data;
+        let span = data.span;
+        // Functions of the parameters, then the parameters themselves, outermost
+        let (body, sig) = data
+            .params
+            .iter()
+            .rev()
+            .fold((func, sig), |(body, sig), p| {
+                let typ = method(tvar(&p.name));
+                let lambda = RawExpr::Lambda {
+                    arg: (ident(&format!("{prefix}{}", p.name), span), typ.clone()),
+                    body: Box::new(body),
+                };
+                (expr(lambda, span), arrow(typ, sig))
+            });
+        let (body, sig) = data
+            .params
+            .iter()
+            .rev()
+            .fold((body, sig), |(body, sig), p| {
+                let any = RawExpr::Any {
+                    arg: p.clone(),
+                    kind: Kind::Star,
+                    body: Box::new(body),
+                };
+                let forall = RawType::Forall(p.clone(), Kind::Star, Box::new(sig));
+                (expr(any, span), Type::new(forall))
+            });
+        Decl {
+            id,
+            sig,
+            body,
+            doc: None,
+            attrs: vec![],
+            span,
+        }
+    }
+
+    /** Whether the values `a` and `b` of the field type `typ` are equal */
+    fn field_eq(&mut self, typ: &Type, a: Expr, b: Expr) -> Result<Expr, TypeError> {
+        let data = self.data;
+        let span = typ.span.or(data.span);
+        let params = &data.params;
+        match &typ.typ {
+            t if !params.iter().any(|p| free_in(&p.name, t)) => {
+                let eq = RawExpr::Binop {
+                    lhs: Box::new(a),
+                    op: Binary::Eq,
+                    rhs: Box::new(b),
+                    width: Width::Int,
+                };
+                Ok(expr(eq, span))
+            }
+            RawType::TVar(v) => Ok(apply(var(&format!("eq'{v}"), span), vec![a, b], span)),
+            RawType::Data(id, args)
+                if *id == data.id
+                    && args.len() == params.len()
+                    && zip(args, params).all(|(t, p)| t.typ == RawType::TVar(p.name.clone())) =>
+            {
+                Ok(apply(var("eq'", span), vec![a, b], span))
+            }
+            RawType::Data(id, args) if (self.deriving)(EQ, id).is_some() => {
+                let f = type_apply(var(&eq_name(id), span), args, span);
+                let mut eqs = vec![];
+                for arg in args {
+                    let (x, y) = (self.fresh("x"), self.fresh("y"));
+                    let body = self.field_eq(arg, var(&x, span), var(&y, span))?;
+                    let lambda = |v: &str, body| {
+                        let lambda = RawExpr::Lambda {
+                            arg: (ident(v, span), arg.clone()),
+                            body: Box::new(body),
+                        };
+                        expr(lambda, span)
+                    };
+                    eqs.push(lambda(&x, lambda(&y, body)))
+                }
+                Ok(apply(apply(f, eqs, span), vec![a, b], span))
+            }
+            RawType::Prod(ts) => {
+                let xs: Vec<String> = ts.iter().map(|_| self.fresh("x")).collect();
+                let ys: Vec<String> = ts.iter().map(|_| self.fresh("y")).collect();
+                let mut eqs = vec![];
+                for ((t, x), y) in zip(zip(ts, &xs), &ys) {
+                    eqs.push(self.field_eq(t, var(x, span), var(y, span))?)
+                }
+                let pat = |vars: &[String]| {
+                    tuple_pat(vars.iter().map(|v| binding(v, span)).collect(), span)
+                };
+                let case = RawExpr::Case {
+                    exp: Box::new(tuple(vec![a, b], span)),
+                    arms: vec![(tuple_pat(vec![pat(&xs), pat(&ys)], span), conj(eqs, span))],
+                };
+                Ok(expr(case, span))
+            }
+            RawType::Record(fields) => {
+                let mut eqs = vec![];
+                for (label, t) in fields {
+                    let proj = |e: &Expr| {
+                        let proj = RawExpr::Proj {
+                            exp: Box::new(e.clone()),
+                            field: label.clone(),
+                        };
+                        expr(proj, span)
+                    };
+                    eqs.push(self.field_eq(t, proj(&a), proj(&b))?)
+                }
+                Ok(conj(eqs, span))
+            }
+            _ => Err(error(
+                "Underivable equality",
+                span.unwrap(),
+                "equality can't be derived for fields of this type",
+            )),
+        }
+    }
+
+    /** `a` of the field type `typ` as a string, in parentheses if `nested` and it's data whose
+    constructor has fields */
+    fn field_show(&mut self, typ: &Type, a: Expr, nested: bool) -> Result<Expr, TypeError> {
+        let data = self.data;
+        let span = typ.span.or(data.span);
+        let params = &data.params;
+        match &typ.typ {
+            RawType::Int => Ok(apply(var("showInt", span), vec![a], span)),
+            RawType::Bool => {
+                let cond = RawExpr::If {
+                    cond: Box::new(a),
+                    branch_t: Box::new(string("true", span)),
+                    branch_f: Box::new(string("false", span)),
+                };
+                Ok(expr(cond, span))
+            }
+            RawType::Str => Ok(concat(
+                vec![string("\"", span), a, string("\"", span)],
+                span,
+            )),
+            RawType::Unit => Ok(string("()", span)),
+            RawType::TVar(v) if params.iter().any(|p| p.name == *v) => {
+                Ok(apply(var(&format!("show'{v}"), span), vec![a], span))
+            }
+            RawType::Data(id, args)
+                if *id == data.id
+                    && args.len() == params.len()
+                    && zip(args, params).all(|(t, p)| t.typ == RawType::TVar(p.name.clone())) =>
+            {
+                let shown = apply(var("show'", span), vec![a.clone()], span);
+                Ok(self.parenthesized(data, shown, a, nested, span))
+            }
+            RawType::Data(id, args) if (self.deriving)(SHOW, id).is_some() => {
+                let decl = (self.deriving)(SHOW, id).unwrap();
+                let f = type_apply(var(&show_name(id), span), args, span);
+                let mut shows = vec![];
+                for arg in args {
+                    let x = self.fresh("x");
+                    let body = self.field_show(arg, var(&x, span), true)?;
+                    let lambda = RawExpr::Lambda {
+                        arg: (ident(&x, span), arg.clone()),
+                        body: Box::new(body),
+                    };
+                    shows.push(expr(lambda, span))
+                }
+                let shown = apply(apply(f, shows, span), vec![a.clone()], span);
+                Ok(self.parenthesized(decl, shown, a, nested, span))
+            }
+            RawType::Prod(ts) => {
+                let xs: Vec<String> = ts.iter().map(|_| self.fresh("x")).collect();
+                let mut parts = vec![string("(", span)];
+                for (i, (t, x)) in zip(ts, &xs).enumerate() {
+                    if i > 0 {
+                        parts.push(string(", ", span))
+                    }
+                    parts.push(self.field_show(t, var(x, span), false)?)
+                }
+                parts.push(string(")", span));
+                let pat = tuple_pat(xs.iter().map(|v| binding(v, span)).collect(), span);
+                let case = RawExpr::Case {
+                    exp: Box::new(a),
+                    arms: vec![(pat, concat(parts, span))],
+                };
+                Ok(expr(case, span))
+            }
+            RawType::Record(fields) => {
+                let mut parts = vec![string("{", span)];
+                for (i, (label, t)) in fields.iter().enumerate() {
+                    let sep = if i > 0 { ", " } else { "" };
+                    parts.push(string(&format!("{sep}{} = ", label.name), span));
+                    let proj = RawExpr::Proj {
+                        exp: Box::new(a.clone()),
+                        field: label.clone(),
+                    };
+                    parts.push(self.field_show(t, expr(proj, span), false)?)
+                }
+                parts.push(string("}", span));
+                Ok(concat(parts, span))
+            }
+            _ => Err(error(
+                "Underivable Show",
+                span.unwrap(),
+                "Show can't be derived for fields of this type",
+            )),
+        }
+    }
+
+    /** `shown`, the string of the value `a` of the data type `decl`, in parentheses if `nested`
+    and the constructor of `a` has fields */
+    fn parenthesized(
+        &self,
+        decl: &DataDecl,
+        shown: Expr,
+        a: Expr,
+        nested: bool,
+        span: Option<Span>,
+    ) -> Expr {
+        if !nested || decl.ctors.iter().all(|(_, fields)| fields.is_empty()) {
+            return shown;
+        }
+        let wildcard = Pattern {
+            pat: RawPattern::Wildcard,
+            span,
+        };
+        let paren = concat(
+            vec![string("(", span), shown.clone(), string(")", span)],
+            span,
+        );
+        let mut arms: Vec<(Pattern, Expr)> = decl
+            .ctors
+            .iter()
+            .filter(|(_, fields)| !fields.is_empty())
+            .map(|(ctor, fields)| {
+                let pat = RawPattern::Ctor(
+                    ctor.clone(),
+                    fields.iter().map(|_| wildcard.clone()).collect(),
+                );
+                (Pattern { pat, span }, paren.clone())
+            })
+            .collect();
+        if arms.len() < decl.ctors.len() {
+            arms.push((wildcard, shown))
+        }
+        expr(
+            RawExpr::Case {
+                exp: Box::new(a),
+                arms,
+            },
+            span,
+        )
+    }
+
+    /** A variable name starting with `prefix` that isn't bound yet */
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.fresh += 1;
+        format!("{prefix}'{}", self.fresh)
+    }
+}
+
+fn expr(expr: RawExpr, span: Option<Span>) -> Expr {
+    Expr { expr, span }
+}
+
+fn var(id: &str, span: Option<Span>) -> Expr {
+    expr(RawExpr::Var { id: id.to_string() }, span)
+}
+
+fn string(s: &str, span: Option<Span>) -> Expr {
+    let val = Constant::Str(s.to_string());
+    expr(RawExpr::Con { val }, span)
+}
+
+/** `f arg1 arg2 ...` */
+fn apply(f: Expr, args: Vec<Expr>, span: Option<Span>) -> Expr {
+    args.into_iter().fold(f, |f, arg| {
+        let app = RawExpr::EApp {
+            exp: Box::new(f),
+            arg: Box::new(arg),
+        };
+        expr(app, span)
+    })
+}
+
+/** `f [T1] [T2] ...` */
+fn type_apply(f: Expr, args: &[Type], span: Option<Span>) -> Expr {
+    args.iter().fold(f, |f, arg| {
+        let tapp = RawExpr::TApp {
+            exp: Box::new(f),
+            arg: arg.clone(),
+        };
+        expr(tapp, span)
+    })
+}
+
+fn boolean(b: bool, span: Option<Span>) -> Expr {
+    let val = Constant::Boolean(b);
+    expr(RawExpr::Con { val }, span)
+}
+
+fn tuple(entries: Vec<Expr>, span: Option<Span>) -> Expr {
+    expr(RawExpr::Tuple { entries }, span)
+}
+
+fn ident(name: &str, span: Option<Span>) -> Ident {
+    Ident {
+        name: name.to_string(),
+        span,
+    }
+}
+
+fn binding(v: &str, span: Option<Span>) -> Pattern {
+    Pattern {
+        pat: RawPattern::Binding(ident(v, span)),
+        span,
+    }
+}
+
+fn tuple_pat(pats: Vec<Pattern>, span: Option<Span>) -> Pattern {
+    Pattern {
+        pat: RawPattern::Tuple(pats),
+        span,
+    }
+}
+
+/** `e1 & e2 & ...`, or `true` without any */
+fn conj(exprs: Vec<Expr>, span: Option<Span>) -> Expr {
+    exprs
+        .into_iter()
+        .reduce(|lhs, rhs| {
+            let and = RawExpr::Binop {
+                lhs: Box::new(lhs),
+                op: Binary::And,
+                rhs: Box::new(rhs),
+                width: Width::Int,
+            };
+            expr(and, span)
+        })
+        .unwrap_or_else(|| boolean(true, span))
+}
+
+/** The strings `exprs` concatenated by the builtin `concat`, from the right */
+fn concat(exprs: Vec<Expr>, span: Option<Span>) -> Expr {
+    exprs
+        .into_iter()
+        .rev()
+        .reduce(|rhs, lhs| {
+            let pair = tuple(vec![lhs, rhs], span);
+            apply(var("concat", span), vec![pair], span)
+        })
+        .unwrap_or_else(|| string("", span))
+}
+
+fn tvar(v: &str) -> Type {
+    Type::new(RawType::TVar(v.to_string()))
+}
+
+fn arrow(t1: Type, t2: Type) -> Type {
+    Type::new(RawType::Arrow(Box::new(t1), Box::new(t2)))
+}
@@ -0,0 +1,75 @@
+/*! Type erasure of type checked programs, an alternative to [monomorphization](crate::ast::mono)
+for compiling them with a uniform representation of values. Type abstractions `Λ A. e` become
+`e`, and type applications `e [T]` become `e`, so unlike in [A-normal form](crate::ir::anf)
+the body of an abstraction is evaluated where the abstraction is, not where it's applied. Folding,
+unfolding and packing become their operand, and `unpack [S] x = e in body` becomes
+`let x = e in body`. Every type left, in annotations, signatures and data types, becomes a hole
+`_`, and data types lose their parameters, so the result is an untyped core language. */
+
+use crate::ast::ast::{Expr, Pattern, Prog, RawExpr, RawPattern, RawType, Type};
+
+/** Erases the types of `prog`, type checked and lowered by
+[`lower_prog`](crate::ast::lower::lower_prog) */
+pub fn erase_prog(prog: &Prog) -> Prog {
+    let mut prog = prog.clone();
+    for decl in prog.declarations.values_mut() {
+        erase_type(&mut decl.sig);
+        erase(&mut decl.body)
+    }
+    for data in &mut prog.datatypes {
+        data.params.clear();
+        let fields = data.ctors.iter_mut().flat_map(|(_, fields)| fields);
+        fields.for_each(erase_type)
+    }
+    for exn in &mut prog.exceptions {
+        exn.fields.iter_mut().for_each(erase_type)
+    }
+    prog.aliases.clear();
+    prog
+}
+
+/** Erases the types of `expr` */
+pub fn erase(expr: &mut Expr) {
+    use RawExpr::*;
+    match &mut expr.expr {
+        Any { body: exp, .. }
+        | TApp { exp, .. }
+        | Fold { exp, .. }
+        | Unfold { exp }
+        | Pack { exp, .. }
+        | Ascribe { exp, .. } => {
+            erase(exp);
+            *expr = (**exp).clone();
+            return;
+        }
+        Unpack {
+            var,
+            exp,
+            body,
+            tvar: _,
+        } => {
+            let pat = Pattern {
+                span: var.span,
+                pat: RawPattern::Binding(var.clone()),
+            };
+            expr.expr = Let {
+                pat,
+                exp: exp.clone(),
+                body: body.clone(),
+            }
+        }
+        Lambda { arg: (_, typ), .. } | Raise { typ, .. } | Instance { typ, .. } => erase_type(typ),
+        Fix { funcs, .. } => {
+            for (_, _, typ, ret, _) in funcs {
+                erase_type(typ);
+                erase_type(ret)
+            }
+        }
+        _ => (),
+    }
+    expr.expr.subexprs_mut().into_iter().for_each(erase)
+}
+
+fn erase_type(typ: &mut Type) {
+    typ.typ = RawType::Hole
+}
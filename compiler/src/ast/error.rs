@@ -1,3 +1,4 @@
+use crate::ast::ast::Span;
 use crate::ast::lex::Token;
 use annotate_snippets::snippet::{AnnotationType, SourceAnnotation};
 
@@ -13,6 +14,16 @@ pub struct TypeError {
     pub annotations: Vec<SourceAnnotation<'static>>,
 }
 
+/// Non-fatal diagnostic reported by a successful check
+#[derive(Debug)]
+pub struct Note {
+    pub title: &'static str,
+    pub annot_type: AnnotationType,
+    /// Byte offsets from source code
+    pub range: Span,
+    pub label: String,
+}
+
 // impl<'a> Into<Snippet<'a>> for TypeError {
 //     fn into(self) -> Snippet<'a> {
 //         Snippet {
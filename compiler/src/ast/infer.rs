@@ -0,0 +1,1506 @@
+/*! Hindley-Milner style type inference. Fills in the annotations left out of
+declarations and expressions by unification, producing the fully annotated System F AST
+that [`check_expr`](super::semant::check_expr) expects. */
+
+use crate::ast::ast::{
+    grow, Binary, Decl, Expr, Ident, Kind, Pattern, RawExpr, RawPattern, RawType, Span, Type,
+};
+use crate::ast::class::is_instance;
+use crate::ast::error::{Note, TypeError};
+use crate::ast::semant::{
+    apply, check_kind, check_kinds, constant_type, equivalent, exn, free_in, illegal_conversion,
+    normalize, not_a_future, not_a_ref, not_a_structure, not_an_array, nth_type, out_of_int_range,
+    out_of_range, rename_shadowed, scoped_type_vars, substitute, unreachable_arms, Context, Kinds,
+};
+use annotate_snippets::snippet::{AnnotationType, SourceAnnotation};
+use im::hashset::HashSet;
+
+/** Infers the annotations left out of `decl` under `ctxt`.
+Whatever remains unknown in the type of `decl` is generalized, so `let id = λ x. x`
+elaborates to `let id: ∀ A. A -> A = Λ A. λ x: A. x`. Bodies that aren't values, like
+`ref (λ x. x)`, must have their type fully known instead. The body is abstracted over the
+[`scoped_type_vars`] of the signature, so `let id: ∀ A. A -> A = λ x: A. x` elaborates the same.
+Returns: The elaborated declaration and notes stating the type of each hole `_`, or `TypeError` */
+pub fn elaborate_decl(decl: &Decl, ctxt: &Context) -> Result<(Decl, Vec<Note>), TypeError> {
+    let at = decl.span.unwrap_or_default();
+    check_kind(&decl.sig, &Kind::Star, &Kinds::default(), at)?;
+    let mut decl = decl.clone();
+    for (arg, kind) in scoped_type_vars(&decl).into_iter().rev() {
+        let span = decl.body.span;
+        let body = Box::new(decl.body);
+        decl.body = Expr {
+            expr: RawExpr::Any { arg, kind, body },
+            span,
+        }
+    }
+    check_kinds(&decl.body, &Kinds::default())?;
+    let mut infer = Infer::default();
+    let mut notes = vec![];
+    infer.open(&mut decl.sig, decl.span);
+    let sig = decl.sig.typ.clone();
+    infer.check(&mut decl.body, &sig, ctxt)?;
+    // Metavariables left in the signature become type parameters, of values only
+    let sig = if is_value(&decl.body) {
+        infer.generalize(&mut decl.body, &sig, ctxt)
+    } else {
+        infer.restrict(&decl.body, &sig, ctxt)?
+    };
+    infer.finish(&mut decl.body)?;
+    infer.resolve(&mut decl.body)?;
+    // A hole standing for the whole signature shows the generalized type
+    let whole_sig = matches!(decl.sig.typ, RawType::Meta(_)) && decl.sig.span.is_some();
+    for (i, (range, meta)) in infer.holes.iter().enumerate() {
+        let typ = if whole_sig && i == 0 {
+            sig.clone()
+        } else {
+            infer.zonk(meta)
+        };
+        notes.push(hole_note(*range, &typ))
+    }
+    notes.extend(infer.unreachable());
+    notes.sort_by_key(|n| n.range);
+    decl.sig.typ = sig;
+    Ok((decl, notes))
+}
+
+/** Infers the annotations left out of `expr` under `ctxt`. Only `let`-bound values are generalized.
+Returns: The elaborated expression and warnings about unreachable `case` arms, or `TypeError` */
+pub fn elaborate_expr(expr: &Expr, ctxt: &Context) -> Result<(Expr, Vec<Note>), TypeError> {
+    check_kinds(expr, &Kinds::default())?;
+    let mut infer = Infer::default();
+    let mut expr = expr.clone();
+    infer.infer(&mut expr, ctxt)?;
+    infer.default_equalities(&[]);
+    infer.finish(&mut expr)?;
+    infer.resolve(&mut expr)?;
+    Ok((expr, infer.unreachable()))
+}
+
+/** State of inference over one declaration or expression */
+#[derive(Default)]
+struct Infer {
+    /// Solutions of the metavariables, indexed by their number
+    solutions: Vec<Option<RawType>>,
+    /// Where each metavariable was introduced, to report it if it's never solved
+    origins: Vec<Option<Span>>,
+    /// Holes `_` written in the source and the metavariables standing for them
+    holes: Vec<(Span, RawType)>,
+    /// Patterns of each `case`, the type they destruct, and the context of the `case`
+    cases: Vec<(Vec<Pattern>, RawType, Context)>,
+    /// Where each dictionary of a method is needed, and the context to find it in
+    dictionaries: Vec<(Span, Context)>,
+    /// Types of the operands compared by `==` and `!=`
+    equalities: Vec<RawType>,
+}
+
+impl Infer {
+    fn fresh(&mut self, origin: Option<Span>) -> RawType {
+        self.solutions.push(None);
+        self.origins.push(origin);
+        RawType::Meta(self.solutions.len() - 1)
+    }
+
+    /// Replaces the holes in `typ` by fresh metavariables.
+    /// Left out annotations have no span of their own, so they originate `at` their binder
+    fn open(&mut self, typ: &mut Type, at: Option<Span>) {
+        use RawType::*;
+        match &mut typ.typ {
+            Hole => {
+                let meta = self.fresh(typ.span.or(at));
+                if let Some(span) = typ.span {
+                    self.holes.push((span, meta.clone()))
+                }
+                typ.typ = meta
+            }
+            Prod(ts) | Data(_, ts) => ts.iter_mut().for_each(|t| self.open(t, at)),
+            Arrow(t1, t2) => {
+                self.open(t1, at);
+                self.open(t2, at)
+            }
+            Forall(_, _, t)
+            | Lam(_, _, t)
+            | Rec(_, t)
+            | Exists(_, t)
+            | Ref(t)
+            | Array(t)
+            | Future(t) => self.open(t, at),
+            App(t1, t2) => {
+                self.open(t1, at);
+                self.open(t2, at)
+            }
+            Record(fields) => fields.iter_mut().for_each(|(_, t)| self.open(t, at)),
+            Int | Sized(_) | Bool | Unit | Str | TVar(_) | Meta(_) => (),
+        }
+    }
+
+    /// Follows the solved metavariables at the head of `typ`
+    fn shallow(&self, typ: &RawType) -> RawType {
+        let mut typ = typ.clone();
+        while let RawType::Meta(m) = typ {
+            match &self.solutions[m] {
+                Some(t) => typ = t.clone(),
+                None => break,
+            }
+        }
+        typ
+    }
+
+    /// `typ` with every solved metavariable replaced by its solution, in normal form
+    fn zonk(&self, typ: &RawType) -> RawType {
+        use RawType::*;
+        let z = |t: &Type| Type::new(self.zonk(t));
+        match self.shallow(typ) {
+            Prod(ts) => Prod(ts.iter().map(z).collect()),
+            Data(id, ts) => Data(id, ts.iter().map(z).collect()),
+            Arrow(t1, t2) => Arrow(Box::new(z(&t1)), Box::new(z(&t2))),
+            Forall(v, k, t) => Forall(v, k, Box::new(z(&t))),
+            Lam(v, k, t) => Lam(v, k, Box::new(z(&t))),
+            App(t1, t2) => apply(self.zonk(&t1), z(&t2)),
+            Rec(v, t) => Rec(v, Box::new(z(&t))),
+            Exists(v, t) => Exists(v, Box::new(z(&t))),
+            Ref(t) => Ref(Box::new(z(&t))),
+            Array(t) => Array(Box::new(z(&t))),
+            Future(t) => Future(Box::new(z(&t))),
+            Record(fields) => Record(fields.iter().map(|(l, t)| (l.clone(), z(t))).collect()),
+            t => t,
+        }
+    }
+
+    /// Renames the type variable `tvar` in the solved types of `ctxt` and in `typs`, before `body`
+    /// binds it again. The new name isn't mentioned by the annotations of `body`
+    fn rename_shadowed(
+        &self,
+        tvar: &str,
+        body: &mut Expr,
+        ctxt: &Context,
+        typs: &mut [&mut RawType],
+    ) -> Option<(Context, String)> {
+        grow(|| {
+            let ctxt = ctxt
+                .iter()
+                .map(|(x, t)| (x.clone(), self.zonk(t)))
+                .collect();
+            for t in typs.iter_mut() {
+                **t = self.zonk(t)
+            }
+            let mut taken = HashSet::new();
+            visit_annotations(body, &mut |t| type_names(t, &mut taken));
+            rename_shadowed(tvar, &ctxt, typs, |v| taken.contains(v))
+        })
+    }
+
+    /// Whether the unsolved metavariable `m` occurs in `typ`
+    fn occurs(&self, m: usize, typ: &RawType) -> bool {
+        metas(&self.zonk(typ)).contains(&m)
+    }
+
+    /// Solves metavariables so that `typ1` and `typ2` become equal, blaming `range` otherwise
+    fn unify(&mut self, typ1: &RawType, typ2: &RawType, range: Span) -> Result<(), TypeError> {
+        use RawType::*;
+        // Solutions may have turned type operators applications into redexes
+        let resolve = |t: &RawType| match self.shallow(t) {
+            App(..) => self.zonk(t),
+            t => t,
+        };
+        let (typ1, typ2) = (resolve(typ1), resolve(typ2));
+        match (&typ1, &typ2) {
+            (Meta(m1), Meta(m2)) if m1 == m2 => Ok(()),
+            (Meta(m), t) | (t, Meta(m)) => {
+                if self.occurs(*m, t) {
+                    return Err(TypeError {
+                        title: "Infinite type",
+                        annot_type: AnnotationType::Error,
+                        annotations: vec![SourceAnnotation {
+                            range,
+                            label: "this expression would need a type containing itself",
+                            annotation_type: AnnotationType::Error,
+                        }],
+                    });
+                }
+                self.solutions[*m] = Some(t.clone());
+                Ok(())
+            }
+            (Int, Int) | (Bool, Bool) | (Unit, Unit) | (Str, Str) => Ok(()),
+            (Sized(w1), Sized(w2)) if w1 == w2 => Ok(()),
+            (TVar(v1), TVar(v2)) if v1 == v2 => Ok(()),
+            (Prod(ts1), Prod(ts2)) if ts1.len() == ts2.len() => {
+                for (t1, t2) in ts1.iter().zip(ts2) {
+                    self.unify(t1, t2, range)?
+                }
+                Ok(())
+            }
+            (Data(id1, ts1), Data(id2, ts2)) if id1 == id2 && ts1.len() == ts2.len() => {
+                for (t1, t2) in ts1.iter().zip(ts2) {
+                    self.unify(t1, t2, range)?
+                }
+                Ok(())
+            }
+            (Arrow(a1, b1), Arrow(a2, b2)) => {
+                self.unify(a1, a2, range)?;
+                self.unify(b1, b2, range)
+            }
+            (Ref(t1), Ref(t2)) | (Array(t1), Array(t2)) | (Future(t1), Future(t2)) => {
+                self.unify(t1, t2, range)
+            }
+            (Record(fs1), Record(fs2))
+                if fs1.len() == fs2.len()
+                    && fs1
+                        .iter()
+                        .all(|(l1, _)| fs2.iter().any(|(l2, _)| l1.name == l2.name)) =>
+            {
+                for (l1, t1) in fs1 {
+                    let (_, t2) = fs2.iter().find(|(l2, _)| l1.name == l2.name).unwrap();
+                    self.unify(t1, t2, range)?
+                }
+                Ok(())
+            }
+            (Forall(v1, k1, t1), Forall(v2, k2, t2)) | (Lam(v1, k1, t1), Lam(v2, k2, t2))
+                if k1 == k2 =>
+            {
+                let (t1, t2) = rename_bound(v1, t1, v2, t2);
+                self.unify(&t1, &t2, range)
+            }
+            (Rec(v1, t1), Rec(v2, t2)) | (Exists(v1, t1), Exists(v2, t2)) => {
+                let (t1, t2) = rename_bound(v1, t1, v2, t2);
+                self.unify(&t1, &t2, range)
+            }
+            (App(f1, a1), App(f2, a2)) => {
+                self.unify(f1, f2, range)?;
+                self.unify(a1, a2, range)
+            }
+            // An unknown operator applied to the last argument of a data type stands for
+            // the data type applied to all the other arguments
+            (App(f, a), Data(id, args)) | (Data(id, args), App(f, a)) if !args.is_empty() => {
+                let (last, init) = args.split_last().unwrap();
+                let mut taken = HashSet::new();
+                type_names(&typ1, &mut taken);
+                type_names(&typ2, &mut taken);
+                let v = fresh_name(&taken);
+                let applied = [init, &[Type::new(TVar(v.clone()))]].concat();
+                let op = Lam(
+                    Ident {
+                        name: v,
+                        span: None,
+                    },
+                    Kind::Star,
+                    Box::new(Type::new(Data(id.clone(), applied))),
+                );
+                self.unify(f, &op, range)?;
+                self.unify(a, last, range)
+            }
+            _ => Err(TypeError {
+                title: "Mismatched Types",
+                annot_type: AnnotationType::Error,
+                annotations: vec![SourceAnnotation {
+                    range,
+                    label: "this expression doesn't have the expected type",
+                    annotation_type: AnnotationType::Error,
+                }],
+            }),
+        }
+    }
+
+    /// Applies `expr` to fresh type arguments for as long as its type `typ` is universal.
+    /// Returns: The type of the instantiated `expr`
+    fn instantiate(&mut self, expr: &mut Expr, typ: RawType) -> RawType {
+        let mut typ = self.shallow(&typ);
+        while let RawType::Forall(v, _, body) = typ {
+            let meta = self.fresh(expr.span);
+            let mut inst = body.typ;
+            substitute(&v.name, &meta, &mut inst);
+            let span = expr.span;
+            let exp = std::mem::replace(expr, Expr::new(RawExpr::Tuple { entries: vec![] }));
+            *expr = Expr {
+                expr: RawExpr::TApp {
+                    exp: Box::new(exp),
+                    arg: Type::new(meta),
+                },
+                span,
+            };
+            typ = self.shallow(&inst);
+        }
+        typ
+    }
+
+    /// Unifies the type `typ` of `expr` with `expected`.
+    /// `expr` is instantiated first, unless a universal type is expected
+    fn coerce(
+        &mut self,
+        expr: &mut Expr,
+        typ: RawType,
+        expected: &RawType,
+    ) -> Result<(), TypeError> {
+        let typ = if matches!(self.shallow(expected), RawType::Forall(..)) {
+            typ
+        } else {
+            self.instantiate(expr, typ)
+        };
+        self.unify(&typ, expected, expr.span.unwrap())
+    }
+
+    /// The type shared by `exprs` with types `typs`, universal only if all of them are
+    fn join(&mut self, exprs: Vec<(&mut Expr, RawType)>) -> Result<RawType, TypeError> {
+        let poly = exprs
+            .iter()
+            .all(|(_, t)| matches!(self.shallow(t), RawType::Forall(..)));
+        let mut typ: Option<RawType> = None;
+        for (expr, t) in exprs {
+            match &typ {
+                Some(expected) => {
+                    let expected = expected.clone();
+                    self.coerce(expr, t, &expected)?
+                }
+                None if poly => typ = Some(t),
+                None => typ = Some(self.instantiate(expr, t)),
+            }
+        }
+        Ok(typ.unwrap())
+    }
+
+    /// Infers the type of `expr` under `ctxt`, filling in its annotations with metavariables
+    fn infer(&mut self, expr: &mut Expr, ctxt: &Context) -> Result<RawType, TypeError> {
+        grow(|| {
+            use RawExpr::*;
+            use RawType::*;
+            let span = expr.span;
+            match &mut expr.expr {
+                Con { val } if out_of_int_range(val) => Err(out_of_range(span.unwrap())),
+                Con { val } => Ok(constant_type(val)),
+                Var { id } => match ctxt.get(id.as_str()) {
+                    Some(typ) => Ok(typ.clone()),
+                    None => Err(TypeError {
+                        title: "Unbound variable",
+                        annot_type: AnnotationType::Error,
+                        annotations: vec![SourceAnnotation {
+                            range: span.unwrap(),
+                            label: "this variable hasn't been defined",
+                            annotation_type: AnnotationType::Error,
+                        }],
+                    }),
+                },
+                Let { pat, exp, body } => {
+                    let ctxt1 = self.infer_let(pat, exp, ctxt)?;
+                    self.infer(body, &ctxt1)
+                }
+                Fix { funcs, body } => {
+                    let ctxt1 = self.infer_fix(funcs, ctxt)?;
+                    self.infer(body, &ctxt1)
+                }
+                EApp { exp, arg } => {
+                    let exp_typ = self.infer(exp, ctxt)?;
+                    let exp_typ = self.instantiate(exp, exp_typ);
+                    let (param, ret) = match exp_typ {
+                        Arrow(t1, t2) => (t1.typ, t2.typ),
+                        Meta(_) => {
+                            let (t1, t2) = (self.fresh(arg.span), self.fresh(span));
+                            let fun_typ = Arrow(
+                                Box::new(Type::new(t1.clone())),
+                                Box::new(Type::new(t2.clone())),
+                            );
+                            self.unify(&exp_typ, &fun_typ, exp.span.unwrap())?;
+                            (t1, t2)
+                        }
+                        _ => {
+                            return Err(TypeError {
+                                title: "Illegal application",
+                                annot_type: AnnotationType::Error,
+                                annotations: vec![SourceAnnotation {
+                                    range: exp.span.unwrap(),
+                                    label: "cannot apply arguments to non-functions",
+                                    annotation_type: AnnotationType::Error,
+                                }],
+                            })
+                        }
+                    };
+                    self.check(arg, &param, ctxt)?;
+                    Ok(ret)
+                }
+                TApp { exp, arg } => {
+                    self.open(arg, arg.span);
+                    let typ = self.infer(exp, ctxt)?;
+                    match self.shallow(&typ) {
+                        Forall(tvar, _, typ) => {
+                            let mut t = typ.typ;
+                            substitute(&tvar.name, arg, &mut t);
+                            Ok(normalize(&t))
+                        }
+                        _ => Err(TypeError {
+                            title: "Illegal type specialization",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: exp.span.unwrap(),
+                                label: "this expression doesn't have `∀` type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        }),
+                    }
+                }
+                Tuple { entries } => {
+                    let mut typs = vec![];
+                    for e in entries {
+                        typs.push(Type::new(self.infer(e, ctxt)?))
+                    }
+                    Ok(Prod(typs))
+                }
+                Binop {
+                    lhs,
+                    op: Binary::Eq | Binary::Ne,
+                    rhs,
+                    ..
+                } => {
+                    self.infer_equality(lhs, rhs, ctxt)?;
+                    Ok(Bool)
+                }
+                AssertEq { lhs, rhs } => {
+                    self.infer_equality(lhs, rhs, ctxt)?;
+                    Ok(Unit)
+                }
+                Binop {
+                    lhs,
+                    op: Binary::And | Binary::Or,
+                    rhs,
+                    ..
+                } => {
+                    self.check(lhs, &Bool, ctxt)?;
+                    self.check(rhs, &Bool, ctxt)?;
+                    Ok(Bool)
+                }
+                Binop {
+                    lhs,
+                    op,
+                    rhs,
+                    width,
+                } => {
+                    let typ = self.infer_operands(lhs, rhs, ctxt)?;
+                    *width = typ.width();
+                    match op {
+                        Binary::Lt | Binary::Gt => Ok(Bool),
+                        _ => Ok(typ),
+                    }
+                }
+                Lambda {
+                    arg: (id, typ),
+                    body,
+                } => {
+                    self.open(typ, id.span);
+                    let mut ctxt1 = ctxt.clone();
+                    ctxt1.insert(id.name.clone(), typ.typ.clone());
+                    let body_typ = self.infer(body, &ctxt1)?;
+                    Ok(Arrow(Box::new(typ.clone()), Box::new(Type::new(body_typ))))
+                }
+                Any { arg, kind, body } => {
+                    let renamed = self.rename_shadowed(&arg.name, body, ctxt, &mut []);
+                    let typ = match &renamed {
+                        Some((ctxt1, _)) => self.infer(body, ctxt1)?,
+                        None => self.infer(body, ctxt)?,
+                    };
+                    let tvar = Ident {
+                        name: arg.name.clone(),
+                        span: None,
+                    };
+                    let typ = Forall(tvar, kind.clone(), Box::new(Type::new(typ)));
+                    match renamed {
+                        Some((_, outer)) => {
+                            let mut typ = self.zonk(&typ);
+                            substitute(&outer, &TVar(arg.name.clone()), &mut typ);
+                            Ok(typ)
+                        }
+                        None => Ok(typ),
+                    }
+                }
+                If {
+                    cond,
+                    branch_t,
+                    branch_f,
+                } => {
+                    self.check(cond, &Bool, ctxt)?;
+                    let t_typ = self.infer(branch_t, ctxt)?;
+                    let f_typ = self.infer(branch_f, ctxt)?;
+                    self.join(vec![(branch_t, t_typ), (branch_f, f_typ)])
+                }
+                Case { exp, arms } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    let typ = self.instantiate(exp, typ);
+                    let pats = arms.iter().map(|(p, _)| p.clone()).collect();
+                    self.cases.push((pats, typ.clone(), ctxt.clone()));
+                    let mut bodies = vec![];
+                    for (pat, body) in arms.iter_mut() {
+                        let mut ctxt1 = ctxt.clone();
+                        self.bind_pat(pat, &typ, &mut ctxt1)?;
+                        let body_typ = self.infer(body, &ctxt1)?;
+                        bodies.push((body, body_typ))
+                    }
+                    self.join(bodies)
+                }
+                RawExpr::Record { fields } => {
+                    let mut typs = vec![];
+                    for (l, e) in fields {
+                        typs.push((l.clone(), Type::new(self.infer(e, ctxt)?)))
+                    }
+                    Ok(RawType::Record(typs))
+                }
+                Proj { exp, field } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    match self.instantiate(exp, typ) {
+                        RawType::Record(fields) => {
+                            match fields.into_iter().find(|(l, _)| l.name == field.name) {
+                                Some((_, typ)) => Ok(typ.typ),
+                                None => Err(TypeError {
+                                    title: "Missing record field",
+                                    annot_type: AnnotationType::Error,
+                                    annotations: vec![SourceAnnotation {
+                                        range: field.span.unwrap(),
+                                        label: "the record has no field with this name",
+                                        annotation_type: AnnotationType::Error,
+                                    }],
+                                }),
+                            }
+                        }
+                        Meta(_) => Err(unknown_shape(exp.span.unwrap())),
+                        _ => Err(TypeError {
+                            title: "Illegal projection",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: exp.span.unwrap(),
+                                label: "this expression isn't a record",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        }),
+                    }
+                }
+                Nth { exp, index } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    match self.instantiate(exp, typ) {
+                        Meta(_) => Err(unknown_shape(exp.span.unwrap())),
+                        typ => nth_type(exp, *index, typ),
+                    }
+                }
+                Ascribe { exp, typ } => {
+                    self.open(typ, typ.span);
+                    self.check(exp, typ, ctxt)?;
+                    Ok(typ.typ.clone())
+                }
+                Open { exp, body } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    let mut typ = self.instantiate(exp, typ);
+                    // The abstract types keep the names given by the signature
+                    while let Exists(_, t) = typ {
+                        typ = self.shallow(&t.typ)
+                    }
+                    match typ {
+                        RawType::Record(fields) => {
+                            let mut ctxt1 = ctxt.clone();
+                            for (l, t) in fields {
+                                ctxt1.insert(l.name, t.typ);
+                            }
+                            self.infer(body, &ctxt1)
+                        }
+                        Meta(_) => Err(unknown_shape(exp.span.unwrap())),
+                        _ => Err(not_a_structure(exp)),
+                    }
+                }
+                Convert { exp, from, to } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    match self.instantiate(exp, typ) {
+                        Int => Ok(to.typ()),
+                        Sized(w) => {
+                            *from = w;
+                            Ok(to.typ())
+                        }
+                        Meta(m) => {
+                            self.unify(&Meta(m), &Int, exp.span.unwrap())?;
+                            Ok(to.typ())
+                        }
+                        _ => Err(illegal_conversion(exp.span.unwrap())),
+                    }
+                }
+                Fold { typ, exp } => {
+                    self.open(typ, typ.span);
+                    let Rec(v, body) = self.shallow(typ) else {
+                        return Err(TypeError {
+                            title: "Illegal fold",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: typ.span.unwrap(),
+                                label: "expected a recursive `μ` type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        });
+                    };
+                    let mut unrolled = body.typ;
+                    substitute(&v.name, typ, &mut unrolled);
+                    self.check(exp, &unrolled, ctxt)?;
+                    Ok(typ.typ.clone())
+                }
+                Unfold { exp } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    match self.instantiate(exp, typ) {
+                        Rec(v, body) => {
+                            let rec_typ = Rec(v.clone(), body.clone());
+                            let mut unrolled = body.typ;
+                            substitute(&v.name, &rec_typ, &mut unrolled);
+                            Ok(unrolled)
+                        }
+                        Meta(_) => Err(unknown_shape(exp.span.unwrap())),
+                        _ => Err(TypeError {
+                            title: "Illegal unfold",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: exp.span.unwrap(),
+                                label: "this expression doesn't have a recursive `μ` type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        }),
+                    }
+                }
+                Pack { witness, exp, typ } => {
+                    self.open(witness, witness.span);
+                    self.open(typ, typ.span);
+                    let Exists(v, body) = self.shallow(typ) else {
+                        return Err(TypeError {
+                            title: "Illegal pack",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: typ.span.unwrap(),
+                                label: "expected an existential `∃` type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        });
+                    };
+                    let mut concrete = body.typ;
+                    substitute(&v.name, witness, &mut concrete);
+                    self.check(exp, &concrete, ctxt)?;
+                    Ok(typ.typ.clone())
+                }
+                Unpack {
+                    tvar,
+                    var,
+                    exp,
+                    body,
+                } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    match self.instantiate(exp, typ) {
+                        Exists(v, typ) => {
+                            let mut var_typ = typ.typ;
+                            substitute(&v.name, &TVar(tvar.name.clone()), &mut var_typ);
+                            let mut ctxt1 = ctxt.clone();
+                            ctxt1.insert(var.name.clone(), var_typ);
+                            self.infer(body, &ctxt1)
+                        }
+                        Meta(_) => Err(unknown_shape(exp.span.unwrap())),
+                        _ => Err(TypeError {
+                            title: "Illegal unpack",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: exp.span.unwrap(),
+                                label: "this expression doesn't have an existential `∃` type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        }),
+                    }
+                }
+                RawExpr::Ref { exp } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    Ok(RawType::Ref(Box::new(Type::new(typ))))
+                }
+                Deref { exp } => self.infer_ref(exp, "Illegal dereference", ctxt),
+                Spawn { exp } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    Ok(Future(Box::new(Type::new(typ))))
+                }
+                Join { exp } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    match self.shallow(&typ) {
+                        Future(t) => Ok(t.typ),
+                        Meta(_) => {
+                            let result = self.fresh(exp.span);
+                            let future = Future(Box::new(Type::new(result.clone())));
+                            self.unify(&typ, &future, exp.span.unwrap())?;
+                            Ok(result)
+                        }
+                        _ => Err(not_a_future(exp)),
+                    }
+                }
+                Assign { lhs, rhs } => {
+                    let typ = self.infer_ref(lhs, "Illegal assignment", ctxt)?;
+                    self.check(rhs, &typ, ctxt)?;
+                    Ok(Unit)
+                }
+                RawExpr::Array { len, init } => {
+                    self.check(len, &Int, ctxt)?;
+                    let typ = self.infer(init, ctxt)?;
+                    Ok(RawType::Array(Box::new(Type::new(typ))))
+                }
+                Sub { arr, idx } => {
+                    let typ = self.infer_array(arr, "Illegal subscript", ctxt)?;
+                    self.check(idx, &Int, ctxt)?;
+                    Ok(typ)
+                }
+                Update { arr, idx, val } => {
+                    let typ = self.infer_array(arr, "Illegal update", ctxt)?;
+                    self.check(idx, &Int, ctxt)?;
+                    self.check(val, &typ, ctxt)?;
+                    Ok(Unit)
+                }
+                Raise { exp, typ } => {
+                    self.open(typ, span);
+                    self.check(exp, &exn(), ctxt)?;
+                    Ok(typ.typ.clone())
+                }
+                Handle { exp, pat, handler } => {
+                    let typ = self.infer(exp, ctxt)?;
+                    let mut ctxt1 = ctxt.clone();
+                    self.bind_pat(pat, &exn(), &mut ctxt1)?;
+                    self.check(handler, &typ, &ctxt1)?;
+                    Ok(typ)
+                }
+                // The type of the dictionary is found by unification, the dictionary itself
+                // once inference is over
+                Instance { class, typ } => {
+                    self.open(typ, span);
+                    self.dictionaries.push((span.unwrap(), ctxt.clone()));
+                    Ok(Data(class.clone(), vec![typ.clone()]))
+                }
+            }
+        })
+    }
+
+    /// Infers the same type for `lhs` and `rhs`, to be checked for equality once it's known
+    fn infer_equality(
+        &mut self,
+        lhs: &mut Expr,
+        rhs: &mut Expr,
+        ctxt: &Context,
+    ) -> Result<(), TypeError> {
+        // An integer literal on the left takes the type of the right operand
+        let (first, second) = match &lhs.expr {
+            RawExpr::Con { val } if val.as_int().is_some() => (rhs, lhs),
+            _ => (lhs, rhs),
+        };
+        let typ = self.infer(first, ctxt)?;
+        let typ = self.instantiate(first, typ);
+        self.check(second, &typ, ctxt)?;
+        self.equalities.push(typ);
+        Ok(())
+    }
+
+    /// Infers `exp`, which must be a reference, reporting `title` otherwise.
+    /// Returns: The type of the contents of the reference
+    fn infer_ref(
+        &mut self,
+        exp: &mut Expr,
+        title: &'static str,
+        ctxt: &Context,
+    ) -> Result<RawType, TypeError> {
+        let typ = self.infer(exp, ctxt)?;
+        match self.shallow(&typ) {
+            RawType::Ref(t) => Ok(t.typ),
+            RawType::Meta(_) => {
+                let contents = self.fresh(exp.span);
+                let ref_typ = RawType::Ref(Box::new(Type::new(contents.clone())));
+                self.unify(&typ, &ref_typ, exp.span.unwrap())?;
+                Ok(contents)
+            }
+            _ => Err(not_a_ref(title, exp)),
+        }
+    }
+
+    /// Infers `exp`, which must be an array, reporting `title` otherwise.
+    /// Returns: The type of the elements of the array
+    fn infer_array(
+        &mut self,
+        exp: &mut Expr,
+        title: &'static str,
+        ctxt: &Context,
+    ) -> Result<RawType, TypeError> {
+        let typ = self.infer(exp, ctxt)?;
+        match self.shallow(&typ) {
+            RawType::Array(t) => Ok(t.typ),
+            RawType::Meta(_) => {
+                let elem = self.fresh(exp.span);
+                let arr_typ = RawType::Array(Box::new(Type::new(elem.clone())));
+                self.unify(&typ, &arr_typ, exp.span.unwrap())?;
+                Ok(elem)
+            }
+            _ => Err(not_an_array(title, exp)),
+        }
+    }
+
+    /// Infers the integer type of both operands of an arithmetic or comparison operator.
+    /// An integer literal on the left takes the type of the right operand instead
+    fn infer_operands(
+        &mut self,
+        lhs: &mut Expr,
+        rhs: &mut Expr,
+        ctxt: &Context,
+    ) -> Result<RawType, TypeError> {
+        let (first, second) = match &lhs.expr {
+            RawExpr::Con { val } if val.as_int().is_some() => (rhs, lhs),
+            _ => (lhs, rhs),
+        };
+        let typ = self.infer(first, ctxt)?;
+        let typ = match self.instantiate(first, typ) {
+            t @ (RawType::Int | RawType::Sized(_)) => t,
+            t => {
+                self.unify(&t, &RawType::Int, first.span.unwrap())?;
+                RawType::Int
+            }
+        };
+        self.check(second, &typ, ctxt)?;
+        Ok(typ)
+    }
+
+    /// Checks `expr` against `expected` under `ctxt`, pushing it into lambdas, type abstractions,
+    /// branches, and tuples so that parameters take their types from `expected` up front
+    fn check(
+        &mut self,
+        expr: &mut Expr,
+        expected: &RawType,
+        ctxt: &Context,
+    ) -> Result<(), TypeError> {
+        grow(|| {
+            use RawExpr::*;
+            use RawType::*;
+            let expected = self.shallow(expected);
+            match (&mut expr.expr, &expected) {
+                (
+                    Lambda {
+                        arg: (id, typ),
+                        body,
+                    },
+                    Arrow(t1, t2),
+                ) => {
+                    self.open(typ, id.span);
+                    self.unify(typ, t1, typ.span.or(id.span).unwrap())?;
+                    let mut ctxt1 = ctxt.clone();
+                    ctxt1.insert(id.name.clone(), typ.typ.clone());
+                    return self.check(body, t2, &ctxt1);
+                }
+                (Any { arg, kind, body }, Forall(tvar, k, t)) if kind == k => {
+                    let mut t = t.typ.clone();
+                    if tvar.name != arg.name {
+                        // A type variable of the same name bound outside is renamed in the body
+                        let renamed = self.rename_shadowed(&arg.name, body, ctxt, &mut [&mut t]);
+                        substitute(&tvar.name, &TVar(arg.name.clone()), &mut t);
+                        if let Some((ctxt1, _)) = renamed {
+                            return self.check(body, &t, &ctxt1);
+                        }
+                    }
+                    return self.check(body, &t, ctxt);
+                }
+                (Let { pat, exp, body }, _) => {
+                    let ctxt1 = self.infer_let(pat, exp, ctxt)?;
+                    return self.check(body, &expected, &ctxt1);
+                }
+                (Fix { funcs, body }, _) => {
+                    let ctxt1 = self.infer_fix(funcs, ctxt)?;
+                    return self.check(body, &expected, &ctxt1);
+                }
+                (
+                    If {
+                        cond,
+                        branch_t,
+                        branch_f,
+                    },
+                    _,
+                ) => {
+                    self.check(cond, &Bool, ctxt)?;
+                    self.check(branch_t, &expected, ctxt)?;
+                    return self.check(branch_f, &expected, ctxt);
+                }
+                (Case { exp, arms }, _) => {
+                    let typ = self.infer(exp, ctxt)?;
+                    let typ = self.instantiate(exp, typ);
+                    for (pat, body) in arms.iter_mut() {
+                        let mut ctxt1 = ctxt.clone();
+                        self.bind_pat(pat, &typ, &mut ctxt1)?;
+                        self.check(body, &expected, &ctxt1)?
+                    }
+                    let pats = arms.iter().map(|(p, _)| p.clone()).collect();
+                    self.cases.push((pats, typ, ctxt.clone()));
+                    return Ok(());
+                }
+                (Con { val }, Sized(w)) if val.as_int().is_some() => {
+                    let n = val.as_int().unwrap();
+                    if !w.fits(n) {
+                        return Err(out_of_range(expr.span.unwrap()));
+                    }
+                    *val = w.constant(n);
+                    return Ok(());
+                }
+                (
+                    Binop {
+                        lhs,
+                        op,
+                        rhs,
+                        width,
+                    },
+                    Sized(w),
+                ) if op.is_arithmetic() => {
+                    *width = *w;
+                    self.check(lhs, &expected, ctxt)?;
+                    return self.check(rhs, &expected, ctxt);
+                }
+                (Tuple { entries }, Prod(typs)) if entries.len() == typs.len() => {
+                    for (e, t) in entries.iter_mut().zip(typs) {
+                        self.check(e, t, ctxt)?
+                    }
+                    return Ok(());
+                }
+                (Handle { exp, pat, handler }, _) => {
+                    self.check(exp, &expected, ctxt)?;
+                    let mut ctxt1 = ctxt.clone();
+                    self.bind_pat(pat, &exn(), &mut ctxt1)?;
+                    return self.check(handler, &expected, &ctxt1);
+                }
+                _ => (),
+            }
+            let typ = self.infer(expr, ctxt)?;
+            self.coerce(expr, typ, &expected)
+        })
+    }
+
+    /// Binds `pat` to the type of `exp`, keeping it polymorphic if `pat` is a variable.
+    /// A variable bound to a syntactic value is also generalized over the metavariables
+    /// that don't occur in `ctxt`, so `let id = λ x. x in (id 1, id true)` checks.
+    /// Returns: The context for the body of the `let`
+    fn infer_let(
+        &mut self,
+        pat: &Pattern,
+        exp: &mut Expr,
+        ctxt: &Context,
+    ) -> Result<Context, TypeError> {
+        let mut typ = self.infer(exp, ctxt)?;
+        match pat.pat {
+            RawPattern::Binding(_) if is_value(exp) => typ = self.generalize(exp, &typ, ctxt),
+            RawPattern::Binding(_) | RawPattern::Wildcard => (),
+            _ => typ = self.instantiate(exp, typ),
+        }
+        let mut ctxt1 = ctxt.clone();
+        self.bind_pat(pat, &typ, &mut ctxt1)?;
+        Ok(ctxt1)
+    }
+
+    /// Abstracts `exp` of type `typ` over the metavariables in `typ` not free in `ctxt`.
+    /// Returns: The generalized type
+    fn generalize(&mut self, exp: &mut Expr, typ: &RawType, ctxt: &Context) -> RawType {
+        let env: Vec<RawType> = ctxt.values().map(|t| self.zonk(t)).collect();
+        let fixed: Vec<usize> = env.iter().flat_map(metas).collect();
+        self.default_equalities(&fixed);
+        let mut typ = self.zonk(typ);
+        let mut taken = HashSet::new();
+        type_names(&typ, &mut taken);
+        visit_annotations(exp, &mut |t| type_names(t, &mut taken));
+        let params: Vec<String> = metas(&typ)
+            .into_iter()
+            .filter(|m| !fixed.contains(m))
+            .map(|m| {
+                let mut name = fresh_name(&taken);
+                while env.iter().any(|t| free_in(&name, t)) {
+                    taken.insert(name);
+                    name = fresh_name(&taken)
+                }
+                taken.insert(name.clone());
+                self.solutions[m] = Some(RawType::TVar(name.clone()));
+                name
+            })
+            .collect();
+        typ = self.zonk(&typ);
+        for name in params.into_iter().rev() {
+            let ident = Ident { name, span: None };
+            typ = RawType::Forall(ident.clone(), Kind::Star, Box::new(Type::new(typ)));
+            let body = std::mem::replace(exp, Expr::new(RawExpr::Tuple { entries: vec![] }));
+            *exp = Expr {
+                span: body.span,
+                expr: RawExpr::Any {
+                    arg: ident,
+                    kind: Kind::Star,
+                    body: Box::new(body),
+                },
+            };
+        }
+        typ
+    }
+
+    /// Defaults the unknown types compared by `==` and `!=` to `Int`, since type variables
+    /// don't admit equality. The metavariables in `fixed` are left for the enclosing context
+    fn default_equalities(&mut self, fixed: &[usize]) {
+        for typ in self.equalities.clone() {
+            for m in metas(&self.zonk(&typ)) {
+                if !fixed.contains(&m) {
+                    self.solutions[m] = Some(RawType::Int)
+                }
+            }
+        }
+    }
+
+    /// The type `typ` of `exp`, which isn't a value and so can't be generalized. Abstracting it
+    /// over types would evaluate it again at each specialization, giving `let r = ref Nil`
+    /// a different reference at each type it's used at.
+    /// Returns: `typ`, or `TypeError` if metavariables not free in `ctxt` are left in it
+    fn restrict(
+        &mut self,
+        exp: &Expr,
+        typ: &RawType,
+        ctxt: &Context,
+    ) -> Result<RawType, TypeError> {
+        let fixed: Vec<usize> = ctxt.values().flat_map(|t| metas(&self.zonk(t))).collect();
+        self.default_equalities(&fixed);
+        let typ = self.zonk(typ);
+        if metas(&typ).iter().all(|m| fixed.contains(m)) {
+            return Ok(typ);
+        }
+        Err(TypeError {
+            title: "Cannot generalize type",
+            annot_type: AnnotationType::Error,
+            annotations: vec![SourceAnnotation {
+                range: exp.span.unwrap(),
+                label:
+                    "this isn't a value, so its type can't be polymorphic, consider annotating it",
+                annotation_type: AnnotationType::Error,
+            }],
+        })
+    }
+
+    /// Infers the mutually recursive functions of a `fix`.
+    /// Returns: The context for the body of the `fix`
+    fn infer_fix(
+        &mut self,
+        funcs: &mut [(Ident, Ident, Type, Type, Expr)],
+        ctxt: &Context,
+    ) -> Result<Context, TypeError> {
+        let mut ctxt1 = ctxt.clone();
+        for (fun, var, typ, ret, _) in funcs.iter_mut() {
+            self.open(typ, var.span);
+            self.open(ret, fun.span);
+            let fun_typ = RawType::Arrow(Box::new(typ.clone()), Box::new(ret.clone()));
+            ctxt1.insert(fun.name.clone(), fun_typ);
+        }
+        for (_, var, typ, ret, def) in funcs.iter_mut() {
+            let mut ctxt2 = ctxt1.clone();
+            ctxt2.insert(var.name.clone(), typ.typ.clone());
+            self.check(def, ret, &ctxt2)?
+        }
+        Ok(ctxt1)
+    }
+
+    /// Binds the variables of `pat` destructing a value of type `typ` in `ctxt`
+    fn bind_pat(
+        &mut self,
+        pat: &Pattern,
+        typ: &RawType,
+        ctxt: &mut Context,
+    ) -> Result<(), TypeError> {
+        let range = pat.span.unwrap();
+        match &pat.pat {
+            RawPattern::Wildcard => Ok(()),
+            RawPattern::Binding(id) => {
+                ctxt.insert(id.name.clone(), typ.clone());
+                Ok(())
+            }
+            RawPattern::Literal(c) => match (c.as_int(), self.shallow(typ)) {
+                (Some(n), RawType::Sized(w)) if w.fits(n) => Ok(()),
+                (Some(_), RawType::Sized(_)) => Err(out_of_range(range)),
+                _ if out_of_int_range(c) => Err(out_of_range(range)),
+                _ => self.unify(typ, &constant_type(c), range),
+            },
+            RawPattern::Tuple(pats) => {
+                let typs: Vec<RawType> = pats.iter().map(|p| self.fresh(p.span)).collect();
+                let prod = RawType::Prod(typs.iter().cloned().map(Type::new).collect());
+                self.unify(typ, &prod, range)?;
+                for (p, t) in pats.iter().zip(&typs) {
+                    self.bind_pat(p, t, ctxt)?
+                }
+                Ok(())
+            }
+            RawPattern::Ctor(c, pats) => {
+                let Some(mut ctor_typ) = ctxt.get(&c.name).cloned() else {
+                    return Err(TypeError {
+                        title: "Mismatched constructor pattern",
+                        annot_type: AnnotationType::Error,
+                        annotations: vec![SourceAnnotation {
+                            range: c.span.unwrap(),
+                            label: "this isn't a constructor of the type being destructed",
+                            annotation_type: AnnotationType::Error,
+                        }],
+                    });
+                };
+                while let RawType::Forall(v, _, body) = ctor_typ {
+                    let meta = self.fresh(c.span);
+                    ctor_typ = body.typ;
+                    substitute(&v.name, &meta, &mut ctor_typ)
+                }
+                let mut fields = vec![];
+                while let RawType::Arrow(t1, t2) = ctor_typ {
+                    fields.push(t1.typ);
+                    ctor_typ = t2.typ
+                }
+                if fields.len() != pats.len() {
+                    return Err(TypeError {
+                        title: "Wrong number of constructor arguments",
+                        annot_type: AnnotationType::Error,
+                        annotations: vec![SourceAnnotation {
+                            range,
+                            label: "pattern expected with one entry per constructor field",
+                            annotation_type: AnnotationType::Error,
+                        }],
+                    });
+                }
+                self.unify(typ, &ctor_typ, range)?;
+                for (p, t) in pats.iter().zip(&fields) {
+                    self.bind_pat(p, t, ctxt)?
+                }
+                Ok(())
+            }
+            RawPattern::Record(pats) => match self.shallow(typ) {
+                RawType::Record(fields) => {
+                    for (l, p) in pats {
+                        match fields.iter().find(|(f, _)| f.name == l.name) {
+                            Some((_, t)) => self.bind_pat(p, t, ctxt)?,
+                            None => {
+                                return Err(TypeError {
+                                    title: "Missing record field",
+                                    annot_type: AnnotationType::Error,
+                                    annotations: vec![SourceAnnotation {
+                                        range: l.span.unwrap(),
+                                        label: "the record has no field with this name",
+                                        annotation_type: AnnotationType::Error,
+                                    }],
+                                })
+                            }
+                        }
+                    }
+                    Ok(())
+                }
+                RawType::Meta(_) => Err(unknown_shape(range)),
+                _ => Err(TypeError {
+                    title: "Malformed pattern assignment",
+                    annot_type: AnnotationType::Error,
+                    annotations: vec![SourceAnnotation {
+                        range,
+                        label: "record pattern used on a value that isn't a record",
+                        annotation_type: AnnotationType::Error,
+                    }],
+                }),
+            },
+        }
+    }
+
+    /// Warnings for the arms of every `case` that can't match any value the arms above don't
+    fn unreachable(&self) -> Vec<Note> {
+        let mut warnings = vec![];
+        for (pats, typ, ctxt) in &self.cases {
+            for range in unreachable_arms(pats, &self.zonk(typ), ctxt) {
+                warnings.push(Note {
+                    title: "Unreachable pattern",
+                    annot_type: AnnotationType::Warning,
+                    range,
+                    label: "the arms above already match every value this pattern does".to_string(),
+                })
+            }
+        }
+        warnings
+    }
+
+    /// Replaces the dictionaries left for methods in `expr` by the variables holding them.
+    /// A dictionary is any variable in scope of the right type, but the instances declared
+    /// by the program take precedence over the dictionaries passed around by hand
+    fn resolve(&self, expr: &mut Expr) -> Result<(), TypeError> {
+        grow(|| {
+            if let RawExpr::Instance { class, typ } = &expr.expr {
+                let range = expr.span.unwrap();
+                let (_, ctxt) = self.dictionaries.iter().find(|(s, _)| *s == range).unwrap();
+                let wanted = RawType::Data(class.clone(), vec![typ.clone()]);
+                let mut found: Vec<&String> = ctxt
+                    .iter()
+                    .filter(|(_, t)| equivalent(&self.zonk(t), &wanted))
+                    .map(|(id, _)| id)
+                    .collect();
+                if found.iter().any(|id| is_instance(id)) {
+                    found.retain(|id| is_instance(id))
+                }
+                let label = match found[..] {
+                    [id] => {
+                        expr.expr = RawExpr::Var { id: id.clone() };
+                        return Ok(());
+                    }
+                    [] => "no instance of the class is in scope for the type this is used at",
+                    _ => "several dictionaries in scope could be passed to this method",
+                };
+                return Err(TypeError {
+                    title: "Cannot resolve instance",
+                    annot_type: AnnotationType::Error,
+                    annotations: vec![SourceAnnotation {
+                        range,
+                        label,
+                        annotation_type: AnnotationType::Error,
+                    }],
+                });
+            }
+            expr.subexprs_mut()
+                .into_iter()
+                .try_for_each(|e| self.resolve(e))
+        })
+    }
+
+    /// Replaces the metavariables in the annotations of `expr` by their solutions,
+    /// rejecting the ones that remain unsolved
+    fn finish(&self, expr: &mut Expr) -> Result<(), TypeError> {
+        let mut unsolved = None;
+        visit_annotations(expr, &mut |t| {
+            t.typ = self.zonk(t);
+            unsolved = unsolved.or(metas(t).first().copied());
+        });
+        match unsolved {
+            Some(m) => Err(TypeError {
+                title: "Cannot infer type",
+                annot_type: AnnotationType::Error,
+                annotations: vec![SourceAnnotation {
+                    range: self.origins[m].unwrap(),
+                    label: "the type here is ambiguous, consider annotating it",
+                    annotation_type: AnnotationType::Error,
+                }],
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/** Error for values destructed before anything is known about their type */
+fn unknown_shape(range: Span) -> TypeError {
+    TypeError {
+        title: "Cannot infer type",
+        annot_type: AnnotationType::Error,
+        annotations: vec![SourceAnnotation {
+            range,
+            label: "the type of this must be known here, consider annotating it",
+            annotation_type: AnnotationType::Error,
+        }],
+    }
+}
+
+/** Note stating the type a hole `_` was filled with */
+fn hole_note(range: Span, typ: &RawType) -> Note {
+    Note {
+        title: "Filled type hole",
+        annot_type: AnnotationType::Info,
+        range,
+        label: format!("`_` has type `{typ}`"),
+    }
+}
+
+/** Whether `expr` is a syntactic value, whose type is safe to generalize */
+fn is_value(expr: &Expr) -> bool {
+    grow(|| {
+        use RawExpr::*;
+        match &expr.expr {
+            Con { .. } | Var { .. } | Lambda { .. } | Any { .. } => true,
+            TApp { exp, .. } | Fold { exp, .. } | Pack { exp, .. } | Ascribe { exp, .. } => {
+                is_value(exp)
+            }
+            // The functions of a `fix` are closures, allocating nothing
+            Fix { body, .. } => is_value(body),
+            Tuple { entries } => entries.iter().all(is_value),
+            Record { fields } => fields.iter().all(|(_, e)| is_value(e)),
+            _ => false,
+        }
+    })
+}
+
+/** The bodies `t1` and `t2` of binders of `v1` and `v2`, with both variables renamed to the
+same name, taken from neither body so that nothing is captured */
+fn rename_bound(v1: &Ident, t1: &RawType, v2: &Ident, t2: &RawType) -> (RawType, RawType) {
+    if v1.name == v2.name {
+        return (t1.clone(), t2.clone());
+    }
+    let mut taken = HashSet::new();
+    type_names(t1, &mut taken);
+    type_names(t2, &mut taken);
+    let fresh = RawType::TVar(fresh_name(&taken));
+    let (mut t1, mut t2) = (t1.clone(), t2.clone());
+    substitute(&v1.name, &fresh, &mut t1);
+    substitute(&v2.name, &fresh, &mut t2);
+    (t1, t2)
+}
+
+/** Unsolved metavariables in `typ`, in order of first appearance */
+fn metas(typ: &RawType) -> Vec<usize> {
+    fn go(typ: &RawType, acc: &mut Vec<usize>) {
+        use RawType::*;
+        match typ {
+            Meta(m) if !acc.contains(m) => acc.push(*m),
+            Prod(ts) | Data(_, ts) => ts.iter().for_each(|t| go(t, acc)),
+            Arrow(t1, t2) => {
+                go(t1, acc);
+                go(t2, acc)
+            }
+            Forall(_, _, t)
+            | Lam(_, _, t)
+            | Rec(_, t)
+            | Exists(_, t)
+            | Ref(t)
+            | Array(t)
+            | Future(t) => go(t, acc),
+            App(t1, t2) => {
+                go(t1, acc);
+                go(t2, acc)
+            }
+            Record(fields) => fields.iter().for_each(|(_, t)| go(t, acc)),
+            _ => (),
+        }
+    }
+    let mut acc = vec![];
+    go(typ, &mut acc);
+    acc
+}
+
+/** Adds the names of type variables, binders, and data types mentioned in `typ` to `names` */
+fn type_names(typ: &RawType, names: &mut HashSet<String>) {
+    use RawType::*;
+    match typ {
+        TVar(v) => {
+            names.insert(v.clone());
+        }
+        Prod(ts) => ts.iter().for_each(|t| type_names(t, names)),
+        Data(id, ts) => {
+            names.insert(id.clone());
+            ts.iter().for_each(|t| type_names(t, names))
+        }
+        Arrow(t1, t2) => {
+            type_names(t1, names);
+            type_names(t2, names)
+        }
+        Ref(t) | Array(t) | Future(t) => type_names(t, names),
+        Forall(v, _, t) | Lam(v, _, t) | Rec(v, t) | Exists(v, t) => {
+            names.insert(v.name.clone());
+            type_names(t, names)
+        }
+        App(t1, t2) => {
+            type_names(t1, names);
+            type_names(t2, names)
+        }
+        Record(fields) => fields.iter().for_each(|(_, t)| type_names(t, names)),
+        Int | Sized(_) | Bool | Unit | Str | Hole | Meta(_) => (),
+    }
+}
+
+/** The first of `A`, `B`, ..., `Z`, `A1`, ... not in `taken` */
+fn fresh_name(taken: &HashSet<String>) -> String {
+    (0..)
+        .flat_map(|i| {
+            ('A'..='Z').map(move |c| match i {
+                0 => c.to_string(),
+                _ => format!("{c}{i}"),
+            })
+        })
+        .find(|name| !taken.contains(name))
+        .unwrap()
+}
+
+/** Calls `f` on every type annotation in `expr`.
+Type variables bound by `Λ` and `unpack` are passed as annotations too, but changes to them are dropped */
+fn visit_annotations(expr: &mut Expr, f: &mut impl FnMut(&mut Type)) {
+    grow(|| {
+        use RawExpr::*;
+        match &mut expr.expr {
+            Con { .. } | Var { .. } => (),
+            Let { exp, body, .. } => {
+                visit_annotations(exp, f);
+                visit_annotations(body, f)
+            }
+            Fix { funcs, body } => {
+                for (_, _, typ, ret, def) in funcs {
+                    f(typ);
+                    f(ret);
+                    visit_annotations(def, f)
+                }
+                visit_annotations(body, f)
+            }
+            EApp { exp, arg } => {
+                visit_annotations(exp, f);
+                visit_annotations(arg, f)
+            }
+            TApp { exp, arg } => {
+                visit_annotations(exp, f);
+                f(arg)
+            }
+            Tuple { entries } => entries.iter_mut().for_each(|e| visit_annotations(e, f)),
+            Binop { lhs, rhs, .. } => {
+                visit_annotations(lhs, f);
+                visit_annotations(rhs, f)
+            }
+            Lambda {
+                arg: (_, typ),
+                body,
+            } => {
+                f(typ);
+                visit_annotations(body, f)
+            }
+            Any { arg, body, .. } => {
+                f(&mut Type::new(RawType::TVar(arg.name.clone())));
+                visit_annotations(body, f)
+            }
+            If {
+                cond,
+                branch_t,
+                branch_f,
+            } => {
+                visit_annotations(cond, f);
+                visit_annotations(branch_t, f);
+                visit_annotations(branch_f, f)
+            }
+            Case { exp, arms } => {
+                visit_annotations(exp, f);
+                arms.iter_mut().for_each(|(_, e)| visit_annotations(e, f))
+            }
+            RawExpr::Record { fields } => {
+                fields.iter_mut().for_each(|(_, e)| visit_annotations(e, f))
+            }
+            Proj { exp, .. }
+            | Nth { exp, .. }
+            | Unfold { exp }
+            | Ref { exp }
+            | Deref { exp }
+            | Spawn { exp }
+            | Join { exp }
+            | Convert { exp, .. } => visit_annotations(exp, f),
+            Assign { lhs, rhs } | AssertEq { lhs, rhs } => {
+                visit_annotations(lhs, f);
+                visit_annotations(rhs, f)
+            }
+            RawExpr::Array { .. } | Sub { .. } | Update { .. } => expr
+                .subexprs_mut()
+                .into_iter()
+                .for_each(|e| visit_annotations(e, f)),
+            Raise { exp, typ } => {
+                f(typ);
+                visit_annotations(exp, f)
+            }
+            Handle { exp, handler, .. } => {
+                visit_annotations(exp, f);
+                visit_annotations(handler, f)
+            }
+            Instance { typ, .. } => f(typ),
+            Fold { typ, exp } | Ascribe { exp, typ } => {
+                f(typ);
+                visit_annotations(exp, f)
+            }
+            Pack { witness, exp, typ } => {
+                f(witness);
+                visit_annotations(exp, f);
+                f(typ)
+            }
+            Unpack {
+                tvar, exp, body, ..
+            } => {
+                f(&mut Type::new(RawType::TVar(tvar.name.clone())));
+                visit_annotations(exp, f);
+                visit_annotations(body, f)
+            }
+            Open { exp, body } => {
+                visit_annotations(exp, f);
+                visit_annotations(body, f)
+            }
+        }
+    })
+}
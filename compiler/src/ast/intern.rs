@@ -0,0 +1,483 @@
+/*! Hash-consing of types. Types are interned in an arena where each distinct type is stored once,
+its subterms shared, and named by a [`TypeId`], so that comparing interned types compares their ids.
+Bound type variables are stored as de Bruijn indices, counting binders outwards from 0, free ones by
+their names, and the fields of records sorted. Types are interned in beta-normal form, so types
+[`equivalent`](crate::ast::semant::equivalent) up to the names of their bound variables and the
+order of their fields are interned alike. Holes, equal to no type, are each interned apart.
+The type checker interns the types of one declaration or expression in an arena of its own, dropped
+with it once checked. */
+
+use crate::ast::ast::{Ident, Kind, RawType, Type, Width};
+
+use im::hashset::HashSet;
+use std::collections::HashMap;
+
+/// Names of interned types, equal if and only if the types are
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct TypeId(u32);
+
+/// Type variables, either bound by the type they occur in or free
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Name {
+    /// The number of binders between the variable and the one binding it
+    Bound(usize),
+    Free(String),
+}
+
+/// Types interned, their subterms by their ids. Those of [`RawType`] not documented here are alike
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Node {
+    Int,
+    Sized(Width),
+    Bool,
+    Unit,
+    Str,
+    TVar(Name),
+    Prod(Vec<TypeId>),
+    Arrow(TypeId, TypeId),
+    /// Binders name their variable `Bound(0)` in their body
+    Forall(Kind, TypeId),
+    /// Holes, numbered apart
+    Hole(usize),
+    Data(Name, Vec<TypeId>),
+    /// Records, their fields sorted by label
+    Record(Vec<(String, TypeId)>),
+    Rec(TypeId),
+    Exists(TypeId),
+    Meta(usize),
+    Lam(Kind, TypeId),
+    /// Applications of type operators that can't be applied yet, like type variables of `Meta`
+    App(TypeId, TypeId),
+    Ref(TypeId),
+    Array(TypeId),
+    Future(TypeId),
+}
+
+/// Arenas of types interned
+#[derive(Debug, Default)]
+pub struct Interner {
+    nodes: Vec<Node>,
+    ids: HashMap<Node, TypeId>,
+    /// For each node, one more than the largest index of the bound variables it leaves unbound
+    loose: Vec<usize>,
+    /// For each node, its free type variables
+    free: Vec<HashSet<String>>,
+    /// Names of the variables of binders, those they were interned with in order
+    hints: HashMap<TypeId, Vec<String>>,
+    holes: usize,
+}
+
+/** Types already rewritten by a traversal, by their ids and the number of binders around them */
+type Memo = HashMap<(TypeId, usize), TypeId>;
+
+impl Interner {
+    /** The id of `node`, added to the arena unless it's there already. Applications are applied */
+    pub fn node(&mut self, node: Node) -> TypeId {
+        match node {
+            Node::App(op, arg) => self.apply(op, arg),
+            node => self.insert(node),
+        }
+    }
+
+    /** The id of `node` as it is, added to the arena unless it's there already */
+    fn insert(&mut self, node: Node) -> TypeId {
+        if let Some(id) = self.ids.get(&node) {
+            return *id;
+        }
+        let (mut loose, mut free) = (0, HashSet::new());
+        self.children(&node, |types, t, binders| {
+            loose = loose.max(types.loose[t.0 as usize].saturating_sub(binders));
+            free = free.clone().union(types.free[t.0 as usize].clone());
+        });
+        match &node {
+            Node::TVar(Name::Bound(k)) | Node::Data(Name::Bound(k), _) => loose = loose.max(k + 1),
+            Node::TVar(Name::Free(v)) | Node::Data(Name::Free(v), _) => {
+                free.insert(v.clone());
+            }
+            _ => {}
+        }
+        let id = TypeId(self.nodes.len() as u32);
+        self.nodes.push(node.clone());
+        self.ids.insert(node, id);
+        self.loose.push(loose);
+        self.free.push(free);
+        id
+    }
+
+    /** The id of the binder `node`, whose variable is named `hint` */
+    pub fn binder(&mut self, node: Node, hint: &str) -> TypeId {
+        let id = self.node(node);
+        self.name(id, [hint.to_string()]);
+        id
+    }
+
+    /** Adds the names `hints` to those of the variable of the binder `id` */
+    fn name(&mut self, id: TypeId, hints: impl IntoIterator<Item = String>) {
+        let names = self.hints.entry(id).or_default();
+        for hint in hints {
+            if !names.contains(&hint) {
+                names.push(hint)
+            }
+        }
+    }
+
+    /** Calls `f` on the subterms of `node`, with the number of binders between `node` and them */
+    fn children(&self, node: &Node, mut f: impl FnMut(&Self, TypeId, usize)) {
+        use Node::*;
+        match node {
+            Prod(ts) | Data(_, ts) => ts.iter().for_each(|t| f(self, *t, 0)),
+            Record(fields) => fields.iter().for_each(|(_, t)| f(self, *t, 0)),
+            Arrow(t1, t2) | App(t1, t2) => {
+                f(self, *t1, 0);
+                f(self, *t2, 0)
+            }
+            Forall(_, t) | Lam(_, t) | Rec(t) | Exists(t) => f(self, *t, 1),
+            Ref(t) | Array(t) | Future(t) => f(self, *t, 0),
+            Int | Sized(_) | Bool | Unit | Str | TVar(_) | Hole(_) | Meta(_) => {}
+        }
+    }
+
+    /** Interns the type `id` with `f` applied to its subterms, along with the number of binders
+    between `id` and them. The variables of `id` are left as they are */
+    fn map(&mut self, id: TypeId, mut f: impl FnMut(&mut Self, TypeId, usize) -> TypeId) -> TypeId {
+        use Node::*;
+        let node = match self.get(id).clone() {
+            Prod(ts) => Prod(ts.into_iter().map(|t| f(self, t, 0)).collect()),
+            Data(v, ts) => Data(v, ts.into_iter().map(|t| f(self, t, 0)).collect()),
+            Record(fs) => Record(fs.into_iter().map(|(l, t)| (l, f(self, t, 0))).collect()),
+            Arrow(t1, t2) => Arrow(f(self, t1, 0), f(self, t2, 0)),
+            App(t1, t2) => App(f(self, t1, 0), f(self, t2, 0)),
+            Forall(k, t) => Forall(k, f(self, t, 1)),
+            Lam(k, t) => Lam(k, f(self, t, 1)),
+            Rec(t) => Rec(f(self, t, 1)),
+            Exists(t) => Exists(f(self, t, 1)),
+            Ref(t) => Ref(f(self, t, 0)),
+            Array(t) => Array(f(self, t, 0)),
+            Future(t) => Future(f(self, t, 0)),
+            Int | Sized(_) | Bool | Unit | Str | TVar(_) | Hole(_) | Meta(_) => return id,
+        };
+        let new = self.node(node);
+        if let Some(hints) = self.hints.get(&id).cloned() {
+            self.name(new, hints)
+        }
+        new
+    }
+
+    /** Interns `typ`, under the binders of the type variables `bound`, innermost last. Its free
+    type variables are interned by the names `rename` gives them */
+    fn intern_under(
+        &mut self,
+        typ: &RawType,
+        bound: &mut Vec<String>,
+        rename: &dyn Fn(&str) -> String,
+    ) -> TypeId {
+        use RawType::*;
+        let name = |var: &str, bound: &[String]| match bound.iter().rev().position(|b| b == var) {
+            Some(index) => Name::Bound(index),
+            None => Name::Free(rename(var)),
+        };
+        let mut under = |types: &mut Self, var: &Ident, body: &RawType| {
+            bound.push(var.name.clone());
+            let body = types.intern_under(body, bound, rename);
+            bound.pop();
+            body
+        };
+        let (node, hint) = match typ {
+            Forall(tv, k, body) => (Node::Forall(k.clone(), under(self, tv, body)), tv),
+            Lam(tv, k, body) => (Node::Lam(k.clone(), under(self, tv, body)), tv),
+            Rec(tv, body) => (Node::Rec(under(self, tv, body)), tv),
+            Exists(tv, body) => (Node::Exists(under(self, tv, body)), tv),
+            _ => {
+                let mut go = |t: &Type| self.intern_under(t, bound, rename);
+                let node = match typ {
+                    Int => Node::Int,
+                    Sized(w) => Node::Sized(*w),
+                    Bool => Node::Bool,
+                    Unit => Node::Unit,
+                    Str => Node::Str,
+                    TVar(var) => Node::TVar(name(var, bound)),
+                    Prod(ts) => Node::Prod(ts.iter().map(go).collect()),
+                    Arrow(a, b) => Node::Arrow(go(a), go(b)),
+                    Data(id, ts) => {
+                        let ts = ts.iter().map(go).collect();
+                        Node::Data(name(id, bound), ts)
+                    }
+                    Record(fields) => {
+                        let mut fields: Vec<(String, TypeId)> = fields
+                            .iter()
+                            .map(|(l, t)| (l.name.clone(), go(t)))
+                            .collect();
+                        fields.sort_by(|(l1, _), (l2, _)| l1.cmp(l2));
+                        Node::Record(fields)
+                    }
+                    Meta(m) => Node::Meta(*m),
+                    App(f, a) => Node::App(go(f), go(a)),
+                    Ref(t) => Node::Ref(go(t)),
+                    Array(t) => Node::Array(go(t)),
+                    Future(t) => Node::Future(go(t)),
+                    Hole => {
+                        self.holes += 1;
+                        Node::Hole(self.holes)
+                    }
+                    Forall(..) | Lam(..) | Rec(..) | Exists(..) => unreachable!(),
+                };
+                return self.node(node);
+            }
+        };
+        self.binder(node, &hint.name)
+    }
+
+    /** Interns `typ`. Returns: Its id, the same as that of any type equivalent to it */
+    pub fn intern(&mut self, typ: &RawType) -> TypeId {
+        self.intern_under(typ, &mut vec![], &|v| v.to_string())
+    }
+
+    /** Interns `typ`, its free type variables named as `rename` names them */
+    pub fn intern_renamed(&mut self, typ: &RawType, rename: impl Fn(&str) -> String) -> TypeId {
+        self.intern_under(typ, &mut vec![], &rename)
+    }
+
+    /** The type `id` names */
+    pub fn get(&self, id: TypeId) -> &Node {
+        &self.nodes[id.0 as usize]
+    }
+
+    /** Whether the type variable `tvar` occurs free in the type `id` */
+    pub fn free_in(&self, tvar: &str, id: TypeId) -> bool {
+        self.free[id.0 as usize].contains(tvar)
+    }
+
+    /** Normal form of the type operator `op` applied to `arg` */
+    pub fn apply(&mut self, op: TypeId, arg: TypeId) -> TypeId {
+        match self.get(op).clone() {
+            Node::Lam(_, body) => self.open(body, arg),
+            Node::TVar(v) => self.node(Node::Data(v, vec![arg])),
+            Node::Data(v, mut args) => {
+                args.push(arg);
+                self.node(Node::Data(v, args))
+            }
+            _ => self.insert(Node::App(op, arg)),
+        }
+    }
+
+    /** The body `body` of a binder, its variable replaced by `arg` */
+    pub fn open(&mut self, body: TypeId, arg: TypeId) -> TypeId {
+        self.subst(body, 0, arg, &mut Memo::new())
+    }
+
+    /** Replaces the variable bound `depth` binders out of `id` by `arg`, which is under none of
+    them, and lowers the indices of the variables bound further out */
+    fn subst(&mut self, id: TypeId, depth: usize, arg: TypeId, memo: &mut Memo) -> TypeId {
+        if self.loose[id.0 as usize] <= depth {
+            return id;
+        }
+        if let Some(done) = memo.get(&(id, depth)) {
+            return *done;
+        }
+        let var = |k: usize| Name::Bound(if k > depth { k - 1 } else { k });
+        let done = match self.get(id).clone() {
+            Node::TVar(Name::Bound(k)) if k == depth => self.shift(arg, depth),
+            Node::TVar(Name::Bound(k)) => self.node(Node::TVar(var(k))),
+            Node::Data(Name::Bound(k), ts) => {
+                let ts: Vec<TypeId> = ts
+                    .into_iter()
+                    .map(|t| self.subst(t, depth, arg, memo))
+                    .collect();
+                if k == depth {
+                    let op = self.shift(arg, depth);
+                    ts.into_iter().fold(op, |op, t| self.apply(op, t))
+                } else {
+                    self.node(Node::Data(var(k), ts))
+                }
+            }
+            _ => self.map(id, |types, t, binders| {
+                types.subst(t, depth + binders, arg, memo)
+            }),
+        };
+        memo.insert((id, depth), done);
+        done
+    }
+
+    /** Raises by `by` the indices of the variables `id` leaves unbound */
+    fn shift(&mut self, id: TypeId, by: usize) -> TypeId {
+        fn go(
+            types: &mut Interner,
+            id: TypeId,
+            by: usize,
+            cutoff: usize,
+            memo: &mut Memo,
+        ) -> TypeId {
+            if types.loose[id.0 as usize] <= cutoff {
+                return id;
+            }
+            if let Some(done) = memo.get(&(id, cutoff)) {
+                return *done;
+            }
+            let done = match types.get(id).clone() {
+                Node::TVar(Name::Bound(k)) => types.node(Node::TVar(Name::Bound(k + by))),
+                Node::Data(Name::Bound(k), ts) => {
+                    let ts = ts
+                        .into_iter()
+                        .map(|t| go(types, t, by, cutoff, memo))
+                        .collect();
+                    let k = if k >= cutoff { k + by } else { k };
+                    types.node(Node::Data(Name::Bound(k), ts))
+                }
+                _ => types.map(id, |types, t, binders| {
+                    go(types, t, by, cutoff + binders, memo)
+                }),
+            };
+            memo.insert((id, cutoff), done);
+            done
+        }
+        if by == 0 {
+            return id;
+        }
+        go(self, id, by, 0, &mut Memo::new())
+    }
+
+    /** The body of a binder of the free type variable `tvar` in the type `id` */
+    pub fn close(&mut self, tvar: &str, id: TypeId) -> TypeId {
+        fn go(
+            types: &mut Interner,
+            tvar: &str,
+            id: TypeId,
+            depth: usize,
+            memo: &mut Memo,
+        ) -> TypeId {
+            if !types.free_in(tvar, id) {
+                return id;
+            }
+            if let Some(done) = memo.get(&(id, depth)) {
+                return *done;
+            }
+            let done = match types.get(id).clone() {
+                Node::TVar(_) => types.node(Node::TVar(Name::Bound(depth))),
+                Node::Data(v, ts) => {
+                    let ts = ts
+                        .into_iter()
+                        .map(|t| go(types, tvar, t, depth, memo))
+                        .collect();
+                    let v = match v {
+                        Name::Free(v) if v == tvar => Name::Bound(depth),
+                        v => v,
+                    };
+                    types.node(Node::Data(v, ts))
+                }
+                _ => types.map(id, |types, t, binders| {
+                    go(types, tvar, t, depth + binders, memo)
+                }),
+            };
+            memo.insert((id, depth), done);
+            done
+        }
+        go(self, tvar, id, 0, &mut Memo::new())
+    }
+
+    /** The type `id` names. Bound type variables are named as in the first type interned with their
+    binder, or renamed so as not to capture others */
+    pub fn raw(&self, id: TypeId) -> RawType {
+        self.raw_under(id, &mut vec![])
+    }
+
+    /** The type `id` names under the binders of the type variables `bound`, innermost last */
+    fn raw_under(&self, id: TypeId, bound: &mut Vec<String>) -> RawType {
+        use Node::*;
+        let name = |v: &Name, bound: &[String]| match v {
+            Name::Bound(k) => bound[bound.len() - 1 - k].clone(),
+            Name::Free(v) => v.clone(),
+        };
+        let go = |t: &TypeId, bound: &mut Vec<String>| Type::new(self.raw_under(*t, bound));
+        let under = |t: &TypeId, bound: &mut Vec<String>| {
+            let hint = self.hint(id).unwrap_or("T");
+            let taken = |v: &str| bound.iter().any(|b| b == v) || self.free_in(v, *t);
+            let var = match taken(hint) {
+                false => hint.to_string(),
+                true => (1..)
+                    .map(|i| format!("{hint}{i}"))
+                    .find(|v| !taken(v))
+                    .unwrap(),
+            };
+            bound.push(var.clone());
+            let body = Box::new(go(t, bound));
+            bound.pop();
+            (
+                Ident {
+                    name: var,
+                    span: None,
+                },
+                body,
+            )
+        };
+        match self.get(id) {
+            Int => RawType::Int,
+            Sized(w) => RawType::Sized(*w),
+            Bool => RawType::Bool,
+            Unit => RawType::Unit,
+            Str => RawType::Str,
+            TVar(v) => RawType::TVar(name(v, bound)),
+            Prod(ts) => RawType::Prod(ts.iter().map(|t| go(t, bound)).collect()),
+            Arrow(a, b) => RawType::Arrow(Box::new(go(a, bound)), Box::new(go(b, bound))),
+            Hole(_) => RawType::Hole,
+            Data(v, ts) => {
+                let ts = ts.iter().map(|t| go(t, bound)).collect();
+                RawType::Data(name(v, bound), ts)
+            }
+            Record(fields) => RawType::Record(
+                fields
+                    .iter()
+                    .map(|(l, t)| {
+                        (
+                            Ident {
+                                name: l.clone(),
+                                span: None,
+                            },
+                            go(t, bound),
+                        )
+                    })
+                    .collect(),
+            ),
+            Meta(m) => RawType::Meta(*m),
+            App(f, a) => RawType::App(Box::new(go(f, bound)), Box::new(go(a, bound))),
+            Ref(t) => RawType::Ref(Box::new(go(t, bound))),
+            Array(t) => RawType::Array(Box::new(go(t, bound))),
+            Future(t) => RawType::Future(Box::new(go(t, bound))),
+            Forall(k, t) => {
+                let (v, t) = under(t, bound);
+                RawType::Forall(v, k.clone(), t)
+            }
+            Lam(k, t) => {
+                let (v, t) = under(t, bound);
+                RawType::Lam(v, k.clone(), t)
+            }
+            Rec(t) => {
+                let (v, t) = under(t, bound);
+                RawType::Rec(v, t)
+            }
+            Exists(t) => {
+                let (v, t) = under(t, bound);
+                RawType::Exists(v, t)
+            }
+        }
+    }
+
+    /** The name of the variable of the binder `id`, that of the first type interned with it */
+    pub fn hint(&self, id: TypeId) -> Option<&str> {
+        self.hints(id).first().map(|h| h.as_str())
+    }
+
+    /** The names of the variable of the binder `id` in the types interned with it, first first */
+    pub fn hints(&self, id: TypeId) -> &[String] {
+        self.hints.get(&id).map_or(&[], |h| h.as_slice())
+    }
+
+    /** The number of distinct types interned */
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /** Whether no type is interned yet */
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
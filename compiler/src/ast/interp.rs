@@ -1,193 +1,1180 @@
-use crate::ast::ast::{Binary, Constant, Expr, RawExpr, RawPattern, Decl, Prog, RawType};
-use crate::ast::error::TypeError;
-use crate::ast::semant::{check_decl, check_expr, Context};
+use crate::ast::ast::{
+    grow, Binary, Constant, DataDecl, Decl, ExnDecl, Expr, Pattern, Prog, RawExpr, RawPattern,
+    RawType, Span, Width, DIV, SUBSCRIPT,
+};
+use crate::ast::class::elaborate_classes;
+use crate::ast::derive::derive;
+use crate::ast::error::{Note, TypeError};
+use crate::ast::infer::{elaborate_decl, elaborate_expr};
+use crate::ast::lower::{lower, Newtypes};
+use crate::ast::module::elaborate_modules;
+use crate::ast::pretty::{Limits, Pretty};
+use crate::ast::semant::{
+    builtin_context, check_decl, check_expr, define_datatype, define_exception, expand_decl,
+    prog_aliases, well_formed_decl, with_prelude, Aliases, Context, Kinds, BUILTINS,
+};
+use crate::ast::step::{trace, Heap};
 
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fmt::Display;
+use std::io::{stdin, stdout, BufRead, Write};
+use std::iter::zip;
 use std::rc::Rc;
+use std::str::FromStr;
 
-use im::{hashmap::HashMap, hashset::HashSet};
+use im::hashmap::HashMap;
 
 pub type Environment = HashMap<String, Value>;
 
+/// Heap of mutable cells allocated by `ref` and `array`, indexed by the locations in `VRef`
+/// and `VArray`, along with the input and output of the builtins
+pub struct Store<'a> {
+    cells: Vec<Value>,
+    /// Where `readLine` reads from
+    pub input: Box<dyn BufRead + 'a>,
+    /// Where `print` and `printInt` write to
+    pub output: Box<dyn Write + 'a>,
+    /// Times evaluation started on the expressions at each span, when recording coverage
+    pub hits: Option<HashMap<Span, usize>>,
+}
+
+impl<'a> Store<'a> {
+    /** An empty store with the builtins reading from `input` and writing to `output` */
+    pub fn new(input: impl BufRead + 'a, output: impl Write + 'a) -> Self {
+        Store {
+            cells: vec![],
+            input: Box::new(input),
+            output: Box::new(output),
+            hits: None,
+        }
+    }
+}
+
+/// An empty store with the builtins reading from standard input and writing to standard output
+impl Default for Store<'_> {
+    fn default() -> Self {
+        Store::new(stdin().lock(), stdout())
+    }
+}
+
+/// Values share their entries and the bodies of their functions, so copying them, like looking up
+/// variables does, takes constant time
 #[derive(Clone, Debug)]
 pub enum Value {
     VConst(Constant),
-    VTuple(Vec<Value>),
-    VClosure(RawExpr, Rc<RefCell<Environment>>),
-    VAny(RawExpr, Rc<RefCell<Environment>>),
+    VTuple(Rc<[Value]>),
+    /// A function with its parameter and body, and the environment it was defined in.
+    /// The environment is shared with the other functions of the same `fix`
+    VClosure {
+        param: String,
+        body: Rc<Code>,
+        env: Rc<RefCell<Environment>>,
+    },
+    /// A type abstraction with its body, and the environment it was defined in
+    VAny {
+        body: Rc<Code>,
+        env: Rc<RefCell<Environment>>,
+    },
+    /// A constructor with its arity, applied to fewer arguments than that
+    VCtor(String, usize, Vec<Value>),
+    VData(String, Rc<[Value]>),
+    VRecord(Rc<[(String, Value)]>),
+    /// Location of a cell in the `Store`
+    VRef(usize),
+    /// Location of the first of the consecutive cells of an array in the `Store`, and their number.
+    /// Updates write to the cells in place
+    VArray(usize, usize),
+    /// A function argument or `let` bound expression not evaluated yet, when evaluating by name or
+    /// lazily. Only ever bound in environments, looking up its variable evaluates it
+    VThunk(Rc<RefCell<Thunk>>),
+    /// One of the [`BUILTINS`], by name
+    VBuiltin(&'static str),
+    /// A task started by `spawn`. Tasks take turns on a pool of the interpreter, and joins wait for
+    /// the task to finish, then return or raise its result
+    VFuture(Future),
+}
+
+/// The value of a task or the exception it raised, once it's finished
+pub type Future = Rc<RefCell<Option<Result<Value, Value>>>>;
+
+#[derive(Clone, Debug)]
+pub enum Thunk {
+    /// The expression and the environment it's evaluated in
+    Delayed(Rc<Code>, Environment),
+    /// The value of the expression, remembered when evaluating lazily
+    Forced(Value),
+}
+
+/// Evaluation strategies, deciding when function arguments and `let` bound variables are evaluated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Before the body, once
+    #[default]
+    CallByValue,
+    /// Every time the variable is used
+    CallByName,
+    /// The first time the variable is used, remembering the value for the next ones
+    Lazy,
+}
+
+impl FromStr for Strategy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cbv" => Ok(Strategy::CallByValue),
+            "cbn" => Ok(Strategy::CallByName),
+            "lazy" => Ok(Strategy::Lazy),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Reasons for a program to stop without a value
+#[derive(Debug)]
+pub enum EvalError {
+    /// The program is rejected by the type checker
+    Type(TypeError),
+    /// An exception was raised and never handled
+    Uncaught(Box<Value>),
+    /// The program went wrong in a way type checking rules out
+    Runtime(RuntimeError),
+}
+
+/// Ways for evaluation to go wrong, at the span of the expression being evaluated. Besides failed
+/// assertions and input/output errors, only ill-typed programs go wrong
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// A variable without a value in the environment
+    Unbound(String, Option<Span>),
+    /// A value of the wrong shape for the expression, ex. applying an integer, with the shape
+    /// expected
+    Mismatch(&'static str, Box<Value>, Option<Span>),
+    /// A value no pattern of a `let` or `case` matches
+    NoMatch(Box<Value>, Option<Span>),
+    /// A type ascription or class instance that should've been erased before evaluation
+    Unlowered(Option<Span>),
+    /// Reading or writing for a builtin failed, with the reason
+    Io(String, Option<Span>),
+    /// An `assert` of `false`, or an `assertEq` of the two different values
+    Assertion(Option<Box<(Value, Value)>>, Option<Span>),
+    /// Every task left waits to join another, at the join of the last one to wait
+    Deadlock(Option<Span>),
+}
+
+impl RuntimeError {
+    fn mismatch(expected: &'static str, val: Value) -> RuntimeError {
+        RuntimeError::Mismatch(expected, Box::new(val), None)
+    }
+
+    /** `self` at `span` if its span isn't known yet */
+    fn at(mut self, span: Option<Span>) -> RuntimeError {
+        use RuntimeError::*;
+        match &mut self {
+            Unbound(_, s)
+            | Mismatch(_, _, s)
+            | NoMatch(_, s)
+            | Unlowered(s)
+            | Io(_, s)
+            | Assertion(_, s)
+            | Deadlock(s) => *s = s.or(span),
+        }
+        self
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            RuntimeError::Unbound(..) => "Unbound variable at runtime",
+            RuntimeError::Mismatch(..) => "Type mismatch at runtime",
+            RuntimeError::NoMatch(..) => "Match failure",
+            RuntimeError::Unlowered(..) => "Unlowered expression",
+            RuntimeError::Io(..) => "Input/output error",
+            RuntimeError::Assertion(..) => "Assertion failed",
+            RuntimeError::Deadlock(..) => "Deadlock",
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        use RuntimeError::*;
+        match self {
+            Unbound(_, s)
+            | Mismatch(_, _, s)
+            | NoMatch(_, s)
+            | Unlowered(s)
+            | Io(_, s)
+            | Assertion(_, s)
+            | Deadlock(s) => *s,
+        }
+    }
+}
+
+impl From<RuntimeError> for EvalError {
+    fn from(err: RuntimeError) -> Self {
+        EvalError::Runtime(err)
+    }
 }
 
-/** Evaluates `expr` under `env` */
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::Unbound(id, _) => write!(f, "{} has no value", id),
+            RuntimeError::Mismatch(expected, val, _) => {
+                write!(f, "expected {}, found {}", expected, val)
+            }
+            RuntimeError::NoMatch(val, _) => write!(f, "no pattern matches {}", val),
+            RuntimeError::Unlowered(_) => write!(f, "this should've been erased by type checking"),
+            RuntimeError::Io(reason, _) => write!(f, "{}", reason),
+            RuntimeError::Assertion(None, _) => write!(f, "this assertion doesn't hold"),
+            RuntimeError::Assertion(Some(vals), _) => {
+                write!(f, "{} isn't equal to {}", vals.0, vals.1)
+            }
+            RuntimeError::Deadlock(_) => write!(f, "every task left waits for another to finish"),
+        }
+    }
+}
+
+impl From<TypeError> for EvalError {
+    fn from(err: TypeError) -> Self {
+        EvalError::Type(err)
+    }
+}
+
+/** Evaluates `expr` under `env` and `store` by `strategy`, returning the warnings from type checking along with the value.
+The constructors of `newtypes` are erased after type checking */
 pub fn eval_expr(
     expr: &Expr,
     context: &Context,
     environment: &Environment,
-) -> Result<Value, TypeError> {
-    let mut ctxt = context.clone();
-    check_expr(&expr, &ctxt, &HashSet::default())?;
-    Ok(eval(environment, expr))
+    newtypes: &Newtypes,
+    store: &mut Store,
+    strategy: Strategy,
+) -> Result<(Value, Vec<Note>), EvalError> {
+    let (mut expr, notes) = elaborate_expr(expr, context)?;
+    check_expr(&expr, context, &Kinds::default())?;
+    lower(&mut expr, newtypes);
+    let val = eval(environment, store, &expr, strategy)?;
+    Ok((val, notes))
+}
+
+/** Type checks `expr` under `context` like [`eval_expr`], then reduces it one step at a time,
+the builtins reading and writing through `store`.
+Returns: The `limit` first expressions it reduces to */
+pub fn trace_expr(
+    expr: &Expr,
+    context: &Context,
+    newtypes: &Newtypes,
+    limit: usize,
+    store: Store,
+) -> Result<Vec<Expr>, TypeError> {
+    let (mut expr, _) = elaborate_expr(expr, context)?;
+    check_expr(&expr, context, &Kinds::default())?;
+    lower(&mut expr, newtypes);
+    Ok(trace(&expr, limit, &mut Heap::new(store)))
 }
 
-/** Evaluates `decl` under current `environment` */
+/** Evaluates `decl` under current `environment` and `store` by `strategy`, returning the notes from type checking.
+The constructors of `newtypes` are erased after type checking */
 pub fn eval_decl(
     decl: &Decl,
     context: &mut Context,
     environment: &mut Environment,
-) -> Result<(), TypeError> {
+    newtypes: &Newtypes,
+    store: &mut Store,
+    strategy: Strategy,
+) -> Result<Vec<Note>, EvalError> {
+    let (mut decl, notes) = elaborate_decl(decl, context)?;
     check_decl(&decl, context)?;
-    environment.insert(decl.id.clone(), eval(environment, &decl.body));
+    lower(&mut decl.body, newtypes);
+    let val = eval(environment, store, &decl.body, strategy)?;
+    environment.insert(decl.id.clone(), val);
+    Ok(notes)
+}
+
+/** Type checks `data` and binds its constructors in `environment`,
+or adds its constructor to `newtypes` if it's a newtype */
+pub fn eval_datatype(
+    data: &DataDecl,
+    context: &mut Context,
+    aliases: &mut Aliases,
+    environment: &mut Environment,
+    newtypes: &mut Newtypes,
+) -> Result<(), TypeError> {
+    define_datatype(data, context, aliases)?;
+    if data.newtype {
+        newtypes.insert(data.ctors[0].0.name.clone());
+        return Ok(());
+    }
+    for (ctor, fields) in &data.ctors {
+        environment.insert(ctor.name.clone(), ctor_value(&ctor.name, fields.len()));
+    }
     Ok(())
 }
 
-/** Evaluates program */
-pub fn eval_prog(prog: &Prog) -> Result<(), TypeError> {
-    let mut env = Environment::default();
-    let mut ctxt = Context::default();
+/** Type checks `exn` and binds its constructor in `environment` */
+pub fn eval_exception(
+    exn: &ExnDecl,
+    context: &mut Context,
+    aliases: &Aliases,
+    environment: &mut Environment,
+) -> Result<(), TypeError> {
+    define_exception(exn, context, aliases)?;
+    let val = ctor_value(&exn.ctor.name, exn.fields.len());
+    environment.insert(exn.ctor.name.clone(), val);
+    Ok(())
+}
+
+/** The environment binding the [`BUILTINS`], which type check under [`builtin_context`] */
+pub fn builtin_environment() -> Environment {
+    BUILTINS
+        .iter()
+        .map(|(id, _)| (id.to_string(), Value::VBuiltin(id)))
+        .collect()
+}
+
+/** The value of the constructor `ctor` taking `arity` fields, before it's applied */
+fn ctor_value(ctor: &str, arity: usize) -> Value {
+    if arity == 0 {
+        Value::VData(ctor.to_string(), Rc::new([]))
+    } else {
+        Value::VCtor(ctor.to_string(), arity, vec![])
+    }
+}
+
+/// The declarations of an evaluated program
+#[derive(Clone, Debug, Default)]
+pub struct EvalOutcome {
+    /// Names of the declarations, in order
+    pub order: Vec<String>,
+    /// Values of the declarations and their types
+    pub decls: HashMap<String, (Value, RawType)>,
+}
+
+impl EvalOutcome {
+    /** The value of the declaration `id` */
+    pub fn value(&self, id: &str) -> Option<&Value> {
+        self.decls.get(id).map(|(val, _)| val)
+    }
+
+    /** The type of the declaration `id` */
+    pub fn typ(&self, id: &str) -> Option<&RawType> {
+        self.decls.get(id).map(|(_, typ)| typ)
+    }
+}
+
+/** Evaluates program.
+Returns: The values and types of its declarations, without those of the prelude or derived ones,
+or the `EvalError` evaluation stopped at */
+pub fn eval_prog(prog: &Prog) -> Result<EvalOutcome, EvalError> {
+    eval_prog_in(prog, &mut Store::default())
+}
+
+/** Evaluates program like [`eval_prog`], allocating and updating cells in `store` */
+pub fn eval_prog_in(prog: &Prog, store: &mut Store) -> Result<EvalOutcome, EvalError> {
+    let declared = &prog.declarations;
+    let prog = &elaborate_classes(&derive(&elaborate_modules(&with_prelude(prog))?)?)?;
+    let mut env = builtin_environment();
+    let mut ctxt = builtin_context();
+    let mut newtypes = Newtypes::default();
+    let mut aliases = prog_aliases(prog)?;
+    for data in &prog.datatypes {
+        eval_datatype(data, &mut ctxt, &mut aliases, &mut env, &mut newtypes)?;
+    }
+    for exn in &prog.exceptions {
+        eval_exception(exn, &mut ctxt, &aliases, &mut env)?;
+    }
+    let mut outcome = EvalOutcome::default();
     for id in &prog.order {
-        let decl = &prog.declarations[id];
-        eval_decl(decl, &mut ctxt, &mut env)?;
+        well_formed_decl(&prog.declarations[id], &aliases)?;
+        let decl = expand_decl(&prog.declarations[id], &aliases);
+        eval_decl(
+            &decl,
+            &mut ctxt,
+            &mut env,
+            &newtypes,
+            store,
+            Strategy::CallByValue,
+        )?;
+        if declared.contains_key(id) {
+            outcome.order.push(id.clone());
+            let decl = (env[id].clone(), ctxt[id].clone());
+            outcome.decls.insert(id.clone(), decl);
+        }
     }
-    Ok(())
+    Ok(outcome)
 }
 
-pub fn eval_closed_expr(expr: &Expr) -> Value {
-    eval(&Environment::default(), expr)
+/** Evaluates the closed `expr` without type checking it.
+Returns: The value, or the exception it raised and didn't handle, or the `RuntimeError` if it's
+ill-typed */
+pub fn eval_closed_expr(expr: &Expr) -> Result<Value, EvalError> {
+    let mut expr = expr.clone();
+    lower(&mut expr, &Newtypes::default());
+    let (env, mut store) = (builtin_environment(), Store::default());
+    eval(&env, &mut store, &expr, Strategy::CallByValue)
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Value::VConst(c) => write!(f, "{}", c),
-            Value::VTuple(vs) => {
-                write!(f, "(")?;
-                write!(f, "{}", vs[0])?;
-                for val in vs.iter().skip(1) {
-                    write!(f, ", {}", val)?
+        write!(f, "{}", Pretty::new(self, Limits::NONE))
+    }
+}
+
+/// Expressions as they're evaluated, once lowered. Their subexpressions are shared, so that
+/// closures, thunks and the frames of evaluation point into them rather than copy them, and calling
+/// a function takes constant time whatever the size of its body
+#[derive(Debug)]
+pub struct Code {
+    code: RawCode,
+    span: Option<Span>,
+}
+
+/// The [`RawExpr`]s evaluated, their types erased
+#[derive(Debug)]
+enum RawCode {
+    Con(Constant),
+    Var(String),
+    Let(Rc<Pattern>, Rc<Code>, Rc<Code>),
+    /// The functions by name, parameter and body
+    Fix(Vec<(String, String, Rc<Code>)>, Rc<Code>),
+    EApp(Rc<Code>, Rc<Code>),
+    TApp(Rc<Code>),
+    Tuple(Rc<[Rc<Code>]>),
+    Record(Rc<[(String, Rc<Code>)]>),
+    Convert(Rc<Code>, Width),
+    /// Folding, unfolding and packing, which do nothing at runtime
+    Erased(Rc<Code>),
+    Unpack(String, Rc<Code>, Rc<Code>),
+    Open(Rc<Code>, Rc<Code>),
+    Proj(Rc<Code>, String),
+    Nth(Rc<Code>, usize),
+    Binop(Rc<Code>, Binary, Rc<Code>),
+    Lambda(String, Rc<Code>),
+    Any(Rc<Code>),
+    If(Rc<Code>, Rc<Code>, Rc<Code>),
+    Case(Rc<Code>, Rc<[(Pattern, Rc<Code>)]>),
+    Ref(Rc<Code>),
+    Deref(Rc<Code>),
+    Spawn(Rc<Code>),
+    Join(Rc<Code>),
+    Assign(Rc<Code>, Rc<Code>),
+    AssertEq(Rc<Code>, Rc<Code>),
+    Array(Rc<Code>, Rc<Code>),
+    Sub(Rc<Code>, Rc<Code>),
+    Update(Rc<Code>, Rc<Code>, Rc<Code>),
+    Raise(Rc<Code>),
+    Handle(Rc<Code>, Rc<Pattern>, Rc<Code>),
+    /// Instances and ascriptions, erased by type checking and lowering
+    Unlowered,
+}
+
+/** The code of the lowered `expr` */
+fn code(expr: &Expr) -> Rc<Code> {
+    use RawCode::*;
+    grow(|| {
+        let code = match &expr.expr {
+            RawExpr::Con { val } => Con(val.clone()),
+            RawExpr::Var { id } => Var(id.clone()),
+            RawExpr::Let { pat, exp, body } => Let(Rc::new(pat.clone()), code(exp), code(body)),
+            RawExpr::Fix { funcs, body } => {
+                let funcs = funcs.iter();
+                let funcs =
+                    funcs.map(|(f, v, _, _, bod)| (f.name.clone(), v.name.clone(), code(bod)));
+                Fix(funcs.collect(), code(body))
+            }
+            RawExpr::EApp { exp, arg } => EApp(code(exp), code(arg)),
+            RawExpr::TApp { exp, .. } => TApp(code(exp)),
+            RawExpr::Tuple { entries } => Tuple(entries.iter().map(code).collect()),
+            RawExpr::Record { fields } => Record(
+                fields
+                    .iter()
+                    .map(|(l, e)| (l.name.clone(), code(e)))
+                    .collect(),
+            ),
+            RawExpr::Convert { exp, to, .. } => Convert(code(exp), *to),
+            RawExpr::Fold { exp, .. } | RawExpr::Unfold { exp } | RawExpr::Pack { exp, .. } => {
+                Erased(code(exp))
+            }
+            RawExpr::Unpack { var, exp, body, .. } => {
+                Unpack(var.name.clone(), code(exp), code(body))
+            }
+            RawExpr::Open { exp, body } => Open(code(exp), code(body)),
+            RawExpr::Proj { exp, field } => Proj(code(exp), field.name.clone()),
+            RawExpr::Nth { exp, index } => Nth(code(exp), *index),
+            RawExpr::Binop { lhs, op, rhs, .. } => Binop(code(lhs), op.clone(), code(rhs)),
+            RawExpr::Lambda { arg: (v, _), body } => Lambda(v.name.clone(), code(body)),
+            RawExpr::Any { body, .. } => Any(code(body)),
+            RawExpr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => If(code(cond), code(branch_t), code(branch_f)),
+            RawExpr::Case { exp, arms } => Case(
+                code(exp),
+                arms.iter().map(|(p, e)| (p.clone(), code(e))).collect(),
+            ),
+            RawExpr::Ref { exp } => Ref(code(exp)),
+            RawExpr::Deref { exp } => Deref(code(exp)),
+            RawExpr::Spawn { exp } => Spawn(code(exp)),
+            RawExpr::Join { exp } => Join(code(exp)),
+            RawExpr::Assign { lhs, rhs } => Assign(code(lhs), code(rhs)),
+            RawExpr::AssertEq { lhs, rhs } => AssertEq(code(lhs), code(rhs)),
+            RawExpr::Array { len, init } => Array(code(len), code(init)),
+            RawExpr::Sub { arr, idx } => Sub(code(arr), code(idx)),
+            RawExpr::Update { arr, idx, val } => Update(code(arr), code(idx), code(val)),
+            RawExpr::Raise { exp, .. } => Raise(code(exp)),
+            RawExpr::Handle { exp, pat, handler } => {
+                Handle(code(exp), Rc::new(pat.clone()), code(handler))
+            }
+            RawExpr::Instance { .. } | RawExpr::Ascribe { .. } => Unlowered,
+        };
+        Rc::new(Code {
+            code,
+            span: expr.span,
+        })
+    })
+}
+
+/// What remains to be done with the value of the expression under evaluation, with the
+/// environment of the expressions left to evaluate
+enum Frame {
+    /// Binds the value to the pattern, then evaluates the body
+    Let(Rc<Pattern>, Rc<Code>, Environment),
+    /// Evaluates the argument of the function
+    Arg(Rc<Code>, Environment),
+    /// Applies the function to the value
+    Call(Value),
+    TApp,
+    /// The entries evaluated so far, of all those of the tuple
+    Tuple(Vec<Value>, Rc<[Rc<Code>]>, Environment),
+    /// The fields evaluated so far, of all those of the record
+    Record(Vec<(String, Value)>, Rc<[(String, Rc<Code>)]>, Environment),
+    Convert(Width),
+    Unpack(String, Rc<Code>, Environment),
+    Open(Rc<Code>, Environment),
+    Proj(String),
+    Nth(usize),
+    /// Evaluates the right operand
+    Rhs(Binary, Rc<Code>, Environment),
+    /// Applies the operator to the left operand and the value
+    Binop(Value, Binary),
+    If(Rc<Code>, Rc<Code>, Environment),
+    Case(Rc<[(Pattern, Rc<Code>)]>, Environment),
+    Ref,
+    Deref,
+    /// Returns the value of the task, or raises its exception, once it's finished
+    Join,
+    /// Evaluates the value assigned to the reference
+    AssignRhs(Rc<Code>, Environment),
+    Assign(Value),
+    /// Evaluates the value asserted equal to the value of the left side
+    AssertRhs(Rc<Code>, Environment),
+    AssertEq(Value),
+    /// Evaluates the initial elements of an array of the length
+    Init(Rc<Code>, Environment),
+    Array(usize),
+    /// Evaluates the index into the array
+    SubIdx(Rc<Code>, Environment),
+    Sub(Value),
+    /// Evaluates the index into the array, then the value written
+    UpdateIdx(Rc<Code>, Rc<Code>, Environment),
+    UpdateVal(Value, Rc<Code>, Environment),
+    /// Writes the value to the location
+    Update(usize),
+    Raise,
+    /// Handles the exceptions raised before the frame is reached
+    Handle(Rc<Pattern>, Rc<Code>, Environment),
+    /// Remembers the value of the thunk
+    Force(Rc<RefCell<Thunk>>),
+}
+
+/// States of evaluation between steps
+enum State {
+    /// Evaluating the code under the environment
+    Eval(Rc<Code>, Environment),
+    /// Returning the value to the frame on top of the stack
+    Return(Value),
+    /// Unwinding the stack to the nearest handler of the exception
+    Raise(Value),
+    /// Waiting for the task to finish, then returning it to the join on top of the stack
+    Wait(Future),
+}
+
+/// A task of the pool, with the state and stack of its evaluation, and where its result goes
+struct Task {
+    state: State,
+    stack: Vec<(Frame, Option<Span>)>,
+    future: Future,
+}
+
+/// Steps a task takes in a turn, before the next task of the pool takes its own
+const QUANTUM: usize = 64;
+
+/** The evaluation function that returns the value of `expr` under the `env`, allocating and updating cells in `store`.
+Frames of evaluation live on a stack of continuations instead of the Rust stack, so deep recursion
+in programs doesn't overflow it. Each frame is kept with the span of the expression it belongs to.
+Tasks spawned run on a pool along with `expr`, taking turns in the order they're spawned, and are
+all finished before it returns, joined or not.
+Returns: The value, or the exception raised and not handled inside `expr`, or the `RuntimeError`
+if `expr` is ill-typed */
+fn eval(
+    env: &Environment,
+    store: &mut Store,
+    expr: &Expr,
+    strategy: Strategy,
+) -> Result<Value, EvalError> {
+    let main = Future::default();
+    let mut pool = VecDeque::from([Task {
+        state: State::Eval(code(expr), env.clone()),
+        stack: vec![],
+        future: main.clone(),
+    }]);
+    // Turns in a row the tasks spent waiting
+    let mut waited = 0;
+    while let Some(task) = pool.pop_front() {
+        waited = if run(task, &mut pool, store, strategy)? {
+            0
+        } else {
+            waited + 1
+        };
+        if waited > 0 && waited == pool.len() {
+            let span = pool.back().and_then(|task| task.stack.last()?.1);
+            return Err(RuntimeError::Deadlock(span).into());
+        }
+    }
+    let result = main.borrow_mut().take();
+    match result {
+        Some(Ok(val)) => Ok(val),
+        Some(Err(exn)) => Err(EvalError::Uncaught(Box::new(exn))),
+        None => unreachable!("tasks are finished when the pool is empty"),
+    }
+}
+
+/** Runs `task` for its turn, spawning tasks to the back of `pool`, and putting it back there unless
+it's finished.
+Returns: Whether it took a step, instead of waiting for another task */
+fn run(
+    task: Task,
+    pool: &mut VecDeque<Task>,
+    store: &mut Store,
+    strategy: Strategy,
+) -> Result<bool, EvalError> {
+    let Task {
+        mut state,
+        mut stack,
+        future,
+    } = task;
+    for steps in 0..QUANTUM {
+        state = match state {
+            State::Eval(code, env) => {
+                if let (Some(hits), Some(span)) = (&mut store.hits, code.span) {
+                    *hits.entry(span).or_default() += 1
                 }
-                write!(f, ")")
+                eval_step(&code, env, &mut stack, pool, strategy)?
             }
-            Value::VClosure(..) | Value::VAny(..) => {
-                write!(f, "<closure>")
+            State::Return(val) => match stack.pop() {
+                Some((frame, span)) => resume(frame, span, val, store, &mut stack, strategy)?,
+                None => {
+                    *future.borrow_mut() = Some(Ok(val));
+                    return Ok(true);
+                }
+            },
+            State::Raise(exn) => match stack.pop() {
+                Some((Frame::Handle(pat, handler, mut env), span)) => {
+                    if bind_pat(&exn, &pat, &mut env).map_err(|err| err.at(span))? {
+                        State::Eval(handler, env)
+                    } else {
+                        State::Raise(exn)
+                    }
+                }
+                Some(_) => State::Raise(exn),
+                // Joining the task raises it again
+                None => {
+                    *future.borrow_mut() = Some(Err(exn));
+                    return Ok(true);
+                }
+            },
+            State::Wait(joined) if joined.borrow().is_some() => {
+                State::Return(Value::VFuture(joined))
+            }
+            State::Wait(joined) => {
+                let state = State::Wait(joined);
+                pool.push_back(Task {
+                    state,
+                    stack,
+                    future,
+                });
+                return Ok(steps > 0);
             }
         }
     }
+    pool.push_back(Task {
+        state,
+        stack,
+        future,
+    });
+    Ok(true)
 }
 
-/** The evaluation function that returns the value of `expr` under the `env`, while potentially updating `env` with new bindings. */
-fn eval(env: &Environment, expr: &RawExpr) -> Value {
-    use RawExpr::*;
+/** Starts evaluating `code` under `env`, pushing to `stack` what to do with the values of its
+subexpressions */
+fn eval_step(
+    code: &Code,
+    env: Environment,
+    stack: &mut Vec<(Frame, Option<Span>)>,
+    pool: &mut VecDeque<Task>,
+    strategy: Strategy,
+) -> Result<State, RuntimeError> {
+    use RawCode::*;
+    use State::{Eval, Return};
     use Value::*;
-    // println!("Evaluating {} in {:?}", expr, env.keys());
-    match expr {
+    let span = code.span;
+    let mut eval_then = |exp: &Rc<Code>, frame: Frame, env: Environment| {
+        stack.push((frame, span));
+        Ok(Eval(exp.clone(), env))
+    };
+    match &code.code {
         // Constants being constants
-        Con { val } => Value::VConst(val.clone()),
+        Con(val) => Ok(Return(VConst(val.clone()))),
         // Yeah
-        Var { id } => env[id].clone(),
-        Let { pat, exp, body } => {
-            let mut new_env = env.clone();
-            let tup = eval(env, exp);
-            bind_pat(&tup, pat, &mut new_env);
-            eval(&new_env, body)
-        }
-        Fix { funcs, body } => {
-            let new_env = Rc::new(RefCell::new(env.clone()));
-            for (f, v, t, _, bod) in funcs {
-                let lam = RawExpr::Lambda {
-                    arg: (v.clone(), t.clone()),
-                    body: Box::new(bod.clone()),
-                };
-                let closure = VClosure(lam, new_env.clone());
-                new_env.borrow_mut().insert(f.name.clone(), closure);
+        Var(id) => match env.get(id) {
+            Some(VThunk(thunk)) => match &*thunk.borrow() {
+                Thunk::Forced(val) => Ok(Return(val.clone())),
+                Thunk::Delayed(exp, env) => {
+                    if strategy == Strategy::Lazy {
+                        stack.push((Frame::Force(thunk.clone()), span))
+                    }
+                    Ok(Eval(exp.clone(), env.clone()))
+                }
+            },
+            Some(val) => Ok(Return(val.clone())),
+            None => Err(RuntimeError::Unbound(id.clone(), span)),
+        },
+        Let(pat, exp, body) => match &pat.pat {
+            RawPattern::Binding(x) if strategy != Strategy::CallByValue => {
+                let mut new_env = env.clone();
+                new_env.insert(x.name.clone(), delay(exp.clone(), env));
+                Ok(Eval(body.clone(), new_env))
             }
-            let res = eval(&(*new_env).borrow().clone(), body);
-            res
-        }
-        EApp { exp, arg } => match eval(env, exp) {
-            Value::VClosure(Lambda { arg: (id, _), body }, e) => {
-                let b = eval(env, arg);
-                let mut map = (*e).borrow().clone();
-                map.insert(id.name, b);
-                eval(&map, &body.expr)
+            _ => eval_then(exp, Frame::Let(pat.clone(), body.clone(), env.clone()), env),
+        },
+        Fix(funcs, body) => {
+            let new_env = Rc::new(RefCell::new(env));
+            for (f, v, bod) in funcs {
+                let closure = VClosure {
+                    param: v.clone(),
+                    body: bod.clone(),
+                    env: new_env.clone(),
+                };
+                new_env.borrow_mut().insert(f.clone(), closure);
             }
-            _ => panic!("\n{}\n{:?}\n", expr, env),
+            let env = new_env.borrow().clone();
+            Ok(Eval(body.clone(), env))
+        }
+        EApp(exp, arg) => eval_then(exp, Frame::Arg(arg.clone(), env.clone()), env),
+        TApp(exp) => eval_then(exp, Frame::TApp, env),
+        Tuple(entries) => match entries.first() {
+            Some(first) => eval_then(
+                first,
+                Frame::Tuple(vec![], entries.clone(), env.clone()),
+                env,
+            ),
+            None => Ok(Return(VTuple(Rc::new([])))),
+        },
+        Record(fields) => match fields.first() {
+            Some((_, e)) => eval_then(e, Frame::Record(vec![], fields.clone(), env.clone()), env),
+            None => Ok(Return(VRecord(Rc::new([])))),
         },
-        // TODO properly apply
-        TApp { exp, arg } => {
-            if let VAny(
-                Any {
-                    arg: tvar,
-                    mut body,
-                },
-                env2,
-            ) = eval(env, exp)
-            {
-                // subst(&mut body, tvar.name.as_str(), arg);
-                eval(&(*env2).borrow(), &body)
+        Convert(exp, to) => eval_then(exp, Frame::Convert(*to), env),
+        // Recursive and existential types are erased at runtime
+        Erased(exp) => Ok(Eval(exp.clone(), env)),
+        Unpack(var, exp, body) => eval_then(
+            exp,
+            Frame::Unpack(var.clone(), body.clone(), env.clone()),
+            env,
+        ),
+        // Structures are records of their members, their types erased like existential ones
+        Open(exp, body) => eval_then(exp, Frame::Open(body.clone(), env.clone()), env),
+        Proj(exp, field) => eval_then(exp, Frame::Proj(field.clone()), env),
+        Nth(exp, index) => eval_then(exp, Frame::Nth(*index), env),
+        Binop(lhs, op, rhs) => {
+            eval_then(lhs, Frame::Rhs(op.clone(), rhs.clone(), env.clone()), env)
+        }
+        Lambda(param, body) => Ok(Return(VClosure {
+            param: param.clone(),
+            body: body.clone(),
+            env: Rc::new(RefCell::new(env)),
+        })),
+        Any(body) => Ok(Return(VAny {
+            body: body.clone(),
+            env: Rc::new(RefCell::new(env)),
+        })),
+        If(cond, branch_t, branch_f) => {
+            let frame = Frame::If(branch_t.clone(), branch_f.clone(), env.clone());
+            eval_then(cond, frame, env)
+        }
+        Case(exp, arms) => eval_then(exp, Frame::Case(arms.clone(), env.clone()), env),
+        Ref(exp) => eval_then(exp, Frame::Ref, env),
+        Deref(exp) => eval_then(exp, Frame::Deref, env),
+        Spawn(exp) => {
+            let future = Future::default();
+            pool.push_back(Task {
+                state: Eval(exp.clone(), env),
+                stack: vec![],
+                future: future.clone(),
+            });
+            Ok(Return(VFuture(future)))
+        }
+        Join(exp) => eval_then(exp, Frame::Join, env),
+        Assign(lhs, rhs) => eval_then(lhs, Frame::AssignRhs(rhs.clone(), env.clone()), env),
+        AssertEq(lhs, rhs) => eval_then(lhs, Frame::AssertRhs(rhs.clone(), env.clone()), env),
+        Array(len, init) => eval_then(len, Frame::Init(init.clone(), env.clone()), env),
+        Sub(arr, idx) => eval_then(arr, Frame::SubIdx(idx.clone(), env.clone()), env),
+        Update(arr, idx, val) => {
+            let frame = Frame::UpdateIdx(idx.clone(), val.clone(), env.clone());
+            eval_then(arr, frame, env)
+        }
+        Raise(exp) => eval_then(exp, Frame::Raise, env),
+        Handle(exp, pat, handler) => eval_then(
+            exp,
+            Frame::Handle(pat.clone(), handler.clone(), env.clone()),
+            env,
+        ),
+        // Resolved by inference and erased by lowering, respectively
+        Unlowered => Err(RuntimeError::Unlowered(span)),
+    }
+}
+
+/** Continues evaluating with `frame` of the expression at `span` and the value `val` of the
+expression before it */
+fn resume(
+    frame: Frame,
+    span: Option<Span>,
+    val: Value,
+    store: &mut Store,
+    stack: &mut Vec<(Frame, Option<Span>)>,
+    strategy: Strategy,
+) -> Result<State, RuntimeError> {
+    use State::*;
+    use Value::*;
+    let mut eval_then = |exp: Rc<Code>, frame: Frame, env: Environment| {
+        stack.push((frame, span));
+        Ok(Eval(exp, env))
+    };
+    let mismatch = |expected, val| Err(RuntimeError::Mismatch(expected, Box::new(val), span));
+    match frame {
+        Frame::Let(pat, body, mut env) => {
+            if bind_pat(&val, &pat, &mut env).map_err(|err| err.at(span))? {
+                Ok(Eval(body, env))
             } else {
-                panic!("{}", TYPE_ERR_MSG)
+                Err(RuntimeError::NoMatch(Box::new(val), span))
             }
         }
-        Tuple { entries } => {
-            let neu = entries.iter().map(|e| (eval(env, e))).collect();
-            Value::VTuple(neu)
-        }
-        Binop { lhs, op, rhs } => {
-            use Binary::*;
-            use Constant::*;
-            match op {
-                // Integer arguments
-                Add | Sub | Mul | Eq | Lt | Gt | Ne => {
-                    let lhs_nf = eval(env, lhs);
-                    let rhs_nf = eval(env, rhs);
-                    if let (VConst(Integer(l)), VConst(Integer(r))) = (&lhs_nf, &rhs_nf) {
-                        VConst(match op {
-                            Add => Integer(l + r),
-                            Sub => Integer(l - r),
-                            Mul => Integer(l * r),
-                            Eq => Boolean(l == r),
-                            Lt => Boolean(l < r),
-                            Gt => Boolean(l > r),
-                            Ne => Boolean(l != r),
-                            // Unreachable
-                            _ => panic!(),
-                        })
-                    } else {
-                        panic!()
-                    }
+        // Constructors take their arguments evaluated, so thunks stay in environments
+        Frame::Arg(arg, env) => match val {
+            VClosure { .. } if strategy != Strategy::CallByValue => {
+                let arg = delay(arg, env);
+                resume(Frame::Call(val), span, arg, store, stack, strategy)
+            }
+            _ => eval_then(arg, Frame::Call(val), env),
+        },
+        Frame::Call(fun) => match fun {
+            // The body sees the environment of the closure, not the one of the call
+            VClosure { param, body, env } => {
+                let mut map = env.borrow().clone();
+                map.insert(param, val);
+                Ok(Eval(body, map))
+            }
+            VCtor(c, arity, mut args) => {
+                args.push(val);
+                if args.len() == arity {
+                    Ok(Return(VData(c, args.into())))
+                } else {
+                    Ok(Return(VCtor(c, arity, args)))
                 }
-                _ => {
-                    let lhs_nf = eval(env, lhs);
-                    let rhs_nf = eval(env, rhs);
-                    if let (VConst(Boolean(l)), VConst(Boolean(r))) = (&lhs_nf, &rhs_nf) {
-                        match op {
-                            And => VConst(Boolean(l & r)),
-                            Or => VConst(Boolean(l | r)),
-                            _ => panic!(),
-                        }
-                    } else {
-                        panic!()
+            }
+            VBuiltin(id) => match builtin(id, val, store) {
+                Ok(val) => Ok(Return(VConst(val))),
+                Err(err) => Err(err.at(span)),
+            },
+            fun => mismatch("a function", fun),
+        },
+        Frame::TApp => match val {
+            VAny { body, env } => {
+                let env = env.borrow().clone();
+                Ok(Eval(body, env))
+            }
+            // Constructors take their type arguments only for type checking
+            VCtor(..) | VData(..) => Ok(Return(val)),
+            _ => mismatch("a type abstraction", val),
+        },
+        Frame::Tuple(mut done, entries, env) => {
+            done.push(val);
+            match entries.get(done.len()) {
+                Some(e) => eval_then(e.clone(), Frame::Tuple(done, entries, env.clone()), env),
+                None => Ok(Return(VTuple(done.into()))),
+            }
+        }
+        Frame::Record(mut done, fields, env) => {
+            done.push((fields[done.len()].0.clone(), val));
+            match fields.get(done.len()) {
+                Some((_, e)) => eval_then(e.clone(), Frame::Record(done, fields, env.clone()), env),
+                None => Ok(Return(VRecord(done.into()))),
+            }
+        }
+        // Narrowing keeps the lowest bits, sign extended like RISC-V's `*w` instructions
+        Frame::Convert(to) => match &val {
+            VConst(c) if c.as_int().is_some() => {
+                Ok(Return(VConst(to.constant(to.wrap(c.as_int().unwrap())))))
+            }
+            _ => mismatch("an integer", val),
+        },
+        Frame::Unpack(var, body, mut env) => {
+            env.insert(var, val);
+            Ok(Eval(body, env))
+        }
+        Frame::Open(body, mut env) => match val {
+            VRecord(fields) => {
+                env.extend(fields.iter().cloned());
+                Ok(Eval(body, env))
+            }
+            _ => mismatch("a structure", val),
+        },
+        Frame::Proj(field) => match &val {
+            VRecord(fields) => match fields.iter().find(|(l, _)| l == &field) {
+                Some((_, val)) => Ok(Return(val.clone())),
+                None => mismatch("a record with this field", val),
+            },
+            _ => mismatch("a record", val),
+        },
+        Frame::Nth(index) => match &val {
+            VTuple(vals) if index < vals.len() => Ok(Return(vals[index].clone())),
+            _ => mismatch("a tuple with this entry", val),
+        },
+        Frame::Rhs(op, rhs, env) => eval_then(rhs, Frame::Binop(val, op), env),
+        Frame::Binop(lhs, op) => binop(lhs, op, val).map_err(|err| err.at(span)),
+        Frame::If(branch_t, branch_f, env) => match val {
+            VConst(Constant::Boolean(b)) => Ok(Eval(if b { branch_t } else { branch_f }, env)),
+            _ => mismatch("a boolean", val),
+        },
+        Frame::Case(arms, env) => {
+            for (pat, body) in arms.iter() {
+                let mut new_env = env.clone();
+                if bind_pat(&val, pat, &mut new_env).map_err(|err| err.at(span))? {
+                    return Ok(Eval(body.clone(), new_env));
+                }
+            }
+            // Exhaustiveness is checked beforehand
+            Err(RuntimeError::NoMatch(Box::new(val), span))
+        }
+        Frame::Ref => {
+            store.cells.push(val);
+            Ok(Return(VRef(store.cells.len() - 1)))
+        }
+        Frame::Deref => match val {
+            VRef(loc) => Ok(Return(store.cells[loc].clone())),
+            _ => mismatch("a reference", val),
+        },
+        Frame::Join => match val {
+            VFuture(task) => {
+                let result = task.borrow().clone();
+                match result {
+                    Some(Ok(val)) => Ok(Return(val)),
+                    Some(Err(exn)) => Ok(Raise(exn)),
+                    None => {
+                        stack.push((Frame::Join, span));
+                        Ok(Wait(task))
                     }
                 }
             }
+            _ => mismatch("a future", val),
+        },
+        Frame::AssignRhs(rhs, env) => eval_then(rhs, Frame::Assign(val), env),
+        Frame::AssertRhs(rhs, env) => eval_then(rhs, Frame::AssertEq(val), env),
+        Frame::AssertEq(lhs) => match equal(&lhs, &val).map_err(|err| err.at(span))? {
+            true => Ok(Return(VConst(Constant::Null))),
+            false => Err(RuntimeError::Assertion(Some(Box::new((lhs, val))), span)),
+        },
+        Frame::Assign(r) => match r {
+            VRef(loc) => {
+                store.cells[loc] = val;
+                Ok(Return(VConst(Constant::Null)))
+            }
+            _ => mismatch("a reference", r),
+        },
+        Frame::Init(init, env) => match val {
+            VConst(Constant::Integer(n)) if n < 0 => {
+                Ok(Raise(VData(SUBSCRIPT.to_string(), Rc::new([]))))
+            }
+            VConst(Constant::Integer(n)) => eval_then(init, Frame::Array(n as usize), env),
+            _ => mismatch("an integer", val),
+        },
+        Frame::Array(len) => {
+            let start = store.cells.len();
+            store.cells.resize(start + len, val);
+            Ok(Return(VArray(start, len)))
         }
-        Lambda { .. } => VClosure(expr.clone(), Rc::new(RefCell::new(env.clone()))),
-        Any { .. } => VAny(expr.clone(), Rc::new(RefCell::new(env.clone()))),
-        If {
-            cond,
-            branch_t,
-            branch_f,
-        } => {
-            if let VConst(Constant::Boolean(b)) = eval(&env, cond) {
-                if b {
-                    eval(env, branch_t)
-                } else {
-                    eval(env, branch_f)
+        Frame::SubIdx(idx, env) => eval_then(idx, Frame::Sub(val), env),
+        Frame::Sub(arr) => match cell(arr, val).map_err(|err| err.at(span))? {
+            Some(loc) => Ok(Return(store.cells[loc].clone())),
+            None => Ok(Raise(VData(SUBSCRIPT.to_string(), Rc::new([])))),
+        },
+        Frame::UpdateIdx(idx, new, env) => {
+            eval_then(idx, Frame::UpdateVal(val, new, env.clone()), env)
+        }
+        Frame::UpdateVal(arr, new, env) => match cell(arr, val).map_err(|err| err.at(span))? {
+            Some(loc) => eval_then(new, Frame::Update(loc), env),
+            None => Ok(Raise(VData(SUBSCRIPT.to_string(), Rc::new([])))),
+        },
+        Frame::Update(loc) => {
+            store.cells[loc] = val;
+            Ok(Return(VConst(Constant::Null)))
+        }
+        Frame::Raise => Ok(Raise(val)),
+        // Nothing was raised
+        Frame::Handle(..) => Ok(Return(val)),
+        Frame::Force(thunk) => {
+            *thunk.borrow_mut() = Thunk::Forced(val.clone());
+            Ok(Return(val))
+        }
+    }
+}
+
+/** Applies the builtin `id` to `arg`, reading and writing through `store`.
+`readLine` reads a line without its line break, or the empty string at the end of the input.
+`assert` fails at the span of its application, filled in by the caller */
+pub(crate) fn builtin(id: &str, arg: Value, store: &mut Store) -> Result<Constant, RuntimeError> {
+    let io = |err: std::io::Error| RuntimeError::Io(err.to_string(), None);
+    let val = match (id, arg) {
+        ("print", Value::VConst(Constant::Str(s))) => {
+            write!(store.output, "{}", s).map_err(io)?;
+            Constant::Null
+        }
+        ("printInt", Value::VConst(Constant::Integer(n))) => {
+            write!(store.output, "{}", n).map_err(io)?;
+            Constant::Null
+        }
+        ("readLine", Value::VConst(Constant::Null)) => {
+            let mut line = String::new();
+            store.input.read_line(&mut line).map_err(io)?;
+            if line.ends_with('\n') {
+                line.pop();
+            }
+            Constant::Str(line)
+        }
+        ("showInt", Value::VConst(Constant::Integer(n))) => Constant::Str(n.to_string()),
+        ("concat", Value::VTuple(strs)) => match &strs[..] {
+            [Value::VConst(Constant::Str(s1)), Value::VConst(Constant::Str(s2))] => {
+                Constant::Str(format!("{s1}{s2}"))
+            }
+            _ => {
+                return Err(RuntimeError::mismatch(
+                    "a pair of strings",
+                    Value::VTuple(strs),
+                ))
+            }
+        },
+        ("assert", Value::VConst(Constant::Boolean(true))) => Constant::Null,
+        ("assert", Value::VConst(Constant::Boolean(false))) => {
+            return Err(RuntimeError::Assertion(None, None))
+        }
+        ("print", arg) => return Err(RuntimeError::mismatch("a string", arg)),
+        ("assert", arg) => return Err(RuntimeError::mismatch("a boolean", arg)),
+        ("printInt" | "showInt", arg) => return Err(RuntimeError::mismatch("an integer", arg)),
+        ("concat", arg) => return Err(RuntimeError::mismatch("a pair of strings", arg)),
+        (_, arg) => return Err(RuntimeError::mismatch("null", arg)),
+    };
+    store.output.flush().map_err(io)?;
+    Ok(val)
+}
+
+/** The thunk of `exp` under `env` */
+fn delay(exp: Rc<Code>, env: Environment) -> Value {
+    Value::VThunk(Rc::new(RefCell::new(Thunk::Delayed(exp, env))))
+}
+
+/** Applies `op` to the values `lhs` and `rhs`.
+Returns: The result, or the exception `Div` raised for division by zero */
+fn binop(lhs: Value, op: Binary, rhs: Value) -> Result<State, RuntimeError> {
+    use Binary::*;
+    use Constant::*;
+    use Value::*;
+    let result = match op {
+        Eq | Ne => Boolean(equal(&lhs, &rhs)? == (op == Eq)),
+        // Integer arguments
+        Add | Sub | Mul | Div | Mod | Land | Lor | Lxor | Shl | Shr | Lt | Gt => {
+            let result = match (&lhs, &rhs) {
+                (VConst(Integer(l)), VConst(Integer(r))) => op.apply_int(*l, *r),
+                (VConst(Sized(l, w)), VConst(Sized(r, _))) => op.apply_sized(*l, *r, *w),
+                (VConst(Integer(_) | Sized(..)), _) => {
+                    return Err(RuntimeError::mismatch("an integer", rhs))
                 }
-            } else {
-                panic!("{}", TYPE_ERR_MSG)
+                _ => return Err(RuntimeError::mismatch("an integer", lhs)),
+            };
+            match result {
+                Some(c) => c,
+                // Division by zero
+                None => return Ok(State::Raise(VData(DIV.to_string(), Rc::new([])))),
+            }
+        }
+        And | Or => match (&lhs, &rhs) {
+            (VConst(Boolean(l)), VConst(Boolean(r))) => {
+                Boolean(if op == And { l & r } else { l | r })
             }
+            (VConst(Boolean(_)), _) => return Err(RuntimeError::mismatch("a boolean", rhs)),
+            _ => return Err(RuntimeError::mismatch("a boolean", lhs)),
+        },
+    };
+    Ok(State::Return(VConst(result)))
+}
+
+/** Location in the store of the element of the array `arr` at `idx`.
+Returns: The location, or `None` if `idx` is out of bounds */
+fn cell(arr: Value, idx: Value) -> Result<Option<usize>, RuntimeError> {
+    match (&arr, &idx) {
+        (Value::VArray(start, len), Value::VConst(Constant::Integer(i))) => {
+            Ok((0 <= *i && (*i as usize) < *len).then(|| start + *i as usize))
         }
+        (Value::VArray(..), _) => Err(RuntimeError::mismatch("an integer", idx)),
+        _ => Err(RuntimeError::mismatch("an array", arr)),
+    }
+}
+
+/** Equality of constants. Integer literals in patterns are of any width */
+pub(crate) fn same_constant(c1: &Constant, c2: &Constant) -> bool {
+    match (c1.as_int(), c2.as_int()) {
+        (Some(n1), Some(n2)) => n1 == n2,
+        _ => c1 == c2,
+    }
+}
+
+/** Structural equality of values whose type admits equality. References and arrays are
+equal when they start at the same cell.
+Returns: Whether they are equal, or `RuntimeError` if they don't admit equality */
+fn equal(v1: &Value, v2: &Value) -> Result<bool, RuntimeError> {
+    use Value::*;
+    let all_equal = |vs1: &[Value], vs2: &[Value]| {
+        zip(vs1, vs2).try_fold(true, |all, (v1, v2)| Ok(all && equal(v1, v2)?))
+    };
+    match (v1, v2) {
+        (VConst(c1), VConst(c2)) => Ok(same_constant(c1, c2)),
+        (VTuple(vs1), VTuple(vs2)) => all_equal(vs1, vs2),
+        (VData(c1, vs1), VData(c2, vs2)) => Ok(c1 == c2 && all_equal(vs1, vs2)?),
+        // Fields may come in any order
+        (VRecord(fs1), VRecord(fs2)) => fs1.iter().try_fold(true, |all, (l, v1)| {
+            match fs2.iter().find(|(l2, _)| l2 == l) {
+                Some((_, v2)) => Ok(all && equal(v1, v2)?),
+                None => Err(RuntimeError::mismatch(
+                    "a record with the same fields",
+                    v2.clone(),
+                )),
+            }
+        }),
+        (VRef(loc1), VRef(loc2)) | (VArray(loc1, _), VArray(loc2, _)) => Ok(loc1 == loc2),
+        _ => Err(RuntimeError::mismatch(
+            "a value of equality type",
+            v1.clone(),
+        )),
     }
 }
 
@@ -199,33 +1186,40 @@ fn eval(env: &Environment, expr: &RawExpr) -> Value {
 //         .collect()
 // }
 
-/// Pattern matches `pat` recursively and binds to `exp`
-fn bind_pat(clo: &Value, pat: &RawPattern, env: &mut Environment) {
+/// Pattern matches `pat` recursively against `clo`, binding into `env`.
+/// Returns whether the match succeeded; `env` may be partially updated otherwise.
+/// Fails with `RuntimeError` if `pat` can't be a pattern of the type of `clo`
+fn bind_pat(clo: &Value, pat: &RawPattern, env: &mut Environment) -> Result<bool, RuntimeError> {
+    let all = |vals: &[Value], pats: &[Pattern], env: &mut Environment| {
+        zip(vals, pats).try_fold(true, |all, (v, p)| Ok(all && bind_pat(v, p, env)?))
+    };
     match (clo, pat) {
-        (Value::VTuple(entries), RawPattern::Tuple(patterns)) => {
-            // Since we type check beforehand, these two vectors must have the same length
-            for (e, p) in entries.iter().zip(patterns) {
-                bind_pat(e, p, env)
-            }
+        // Since we type check beforehand, these two vectors must have the same length
+        (Value::VTuple(entries), RawPattern::Tuple(patterns)) => all(entries, patterns, env),
+        (Value::VConst(c), RawPattern::Literal(lit)) => Ok(same_constant(c, lit)),
+        (Value::VRecord(fields), RawPattern::Record(patterns)) => {
+            patterns.iter().try_fold(true, |all, (l, p)| {
+                match fields.iter().find(|(f, _)| f == &l.name) {
+                    Some((_, val)) => Ok(all && bind_pat(val, p, env)?),
+                    None => Err(RuntimeError::mismatch(
+                        "a record with this field",
+                        clo.clone(),
+                    )),
+                }
+            })
+        }
+        (Value::VData(c, args), RawPattern::Ctor(id, patterns)) => {
+            Ok(c == &id.name && all(args, patterns, env)?)
         }
-        (_, RawPattern::Wildcard) => (),
+        (_, RawPattern::Wildcard) => Ok(true),
         (_, RawPattern::Binding(id)) => {
             env.insert(id.name.clone(), clo.clone());
+            Ok(true)
         }
-        _ => panic!("{}", TYPE_ERR_MSG),
-    }
-}
-
-impl RawPattern {
-    /// Whether `self` contains the variable `var`
-    fn bindings<'ast>(&'ast self) -> Vec<&'ast str> {
-        match self {
-            RawPattern::Wildcard => vec![],
-            RawPattern::Binding(v) => vec![v.name.as_str()],
-            RawPattern::Tuple(pats) => pats
-                .iter()
-                .fold(vec![], |acc, p| [p.bindings(), acc].concat()),
-        }
+        _ => Err(RuntimeError::mismatch(
+            "a value of the type of the pattern",
+            clo.clone(),
+        )),
     }
 }
 
@@ -341,6 +1335,3 @@ impl RawPattern {
 //         }
 //     }
 // }
-
-const TYPE_ERR_MSG: &str =
-    "Type mismatch during interpretation. This shouldn't happen. Did you typecheck?";
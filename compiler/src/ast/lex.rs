@@ -12,6 +12,33 @@ fn token_bool_lit<'a>(lex: &mut Lexer<'a, Token<'a>>) -> Result<bool, LexError>
     }
 }
 
+/// Callback for doc comment tokens, strips the delimiters and surrounding whitespace
+fn token_doc_comment<'a>(lex: &mut Lexer<'a, Token<'a>>) -> &'a str {
+    let slice = lex.slice();
+    slice[3..slice.len() - 1].trim_end_matches('*').trim()
+}
+
+/// Callback for string literal tokens, strips the quotes and replaces the escapes
+/// `\n`, `\t`, `\\` and `\"`
+fn token_str_lit<'a>(lex: &mut Lexer<'a, Token<'a>>) -> Result<String, LexError> {
+    let slice = lex.slice();
+    let mut s = String::new();
+    let mut chars = slice[1..slice.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => s.push('\n'),
+            Some('t') => s.push('\t'),
+            Some(c @ ('\\' | '"')) => s.push(c),
+            _ => return Err(lex.span().start),
+        }
+    }
+    Ok(s)
+}
+
 /// Callback for int literal tokens
 fn token_int_lit<'a>(lex: &mut Lexer<'a, Token<'a>>) -> Result<i64, LexError> {
     match lex.slice().parse::<i64>() {
@@ -43,15 +70,45 @@ pub enum Token<'source> {
     LBrack,
     #[token("]")]
     RBrack,
+    #[token("{")]
+    LBrace,
+    #[token("}")]
+    RBrace,
     #[token("_")]
     Underscore,
     #[token("->")]
     Arrow,
+    #[token("@")]
+    At,
+    #[token("=>")]
+    FatArrow,
+    #[token(";")]
+    Semicolon,
+    /// Assignment to a reference `:=`
+    #[token(":=")]
+    Assign,
+    /// Dereference `!`
+    #[token("!")]
+    Bang,
+    /// List constructor `::`
+    #[token("::")]
+    ColonColon,
+
+    /// Doc comments like `(** Adds one *)`, attached to the following declaration
+    #[regex(r"\(\*\*[^*]*\*+(?:[^)*][^*]*\*+)*\)", token_doc_comment)]
+    DocComment(&'source str),
 
     /// Precedence 7 as multiplication
     #[token("*")]
     Mul,
 
+    /// Precedence 7, division, modulo, and bitwise operators
+    #[regex(r"/|%|<<|>>", |lex| lex.slice())]
+    #[token("land", |lex| lex.slice())]
+    #[token("lor", |lex| lex.slice())]
+    #[token("lxor", |lex| lex.slice())]
+    Infix7(&'source str),
+
     // Different precedences in the token enum level is
     // necessary for the parser to disambiguate
     /// Precedence 6
@@ -62,6 +119,10 @@ pub enum Token<'source> {
     #[regex(r"<|>|==|!=", |lex| lex.slice())]
     Infix4(&'source str),
 
+    /// Pipeline `|>`, binds looser than comparisons
+    #[token("|>")]
+    Pipe,
+
     /// Precedence 3
     #[regex(r"[&|]", |lex| lex.slice())]
     Infix3(&'source str),
@@ -82,6 +143,10 @@ pub enum Token<'source> {
     #[regex(r"true|false", token_bool_lit)]
     BoolLit(bool),
 
+    /// String literals, ex. `"hello\n"`
+    #[regex(r#""([^"\\]|\\.)*""#, token_str_lit)]
+    StrLit(String),
+
     /// Unit literal aka null
     #[token("null")]
     UnitLit,
@@ -101,20 +166,100 @@ pub enum Token<'source> {
     And,
     #[token("in")]
     In,
+    #[token("data")]
+    Data,
+    #[token("newtype")]
+    Newtype,
+    #[token("type")]
+    Type,
+    #[token("case")]
+    Case,
+    #[token("of")]
+    Of,
+    #[token("end")]
+    End,
     #[regex("Λ|any")]
     Any,
     #[regex("\\\\|λ|lambda")]
     Lambda,
     #[regex("∀|forall")]
     Forall,
+    #[regex("μ|rec")]
+    Rec,
+    #[regex("∃|exists")]
+    Exists,
+    #[token("pack")]
+    Pack,
+    #[token("as")]
+    As,
+    #[token("unpack")]
+    Unpack,
+    #[token("fold")]
+    Fold,
+    #[token("unfold")]
+    Unfold,
+    #[token("ref")]
+    Ref,
+    #[token("array")]
+    Array,
+    #[token("sub")]
+    Sub,
+    #[token("update")]
+    Update,
+    #[token("assertEq")]
+    AssertEq,
+    #[token("spawn")]
+    Spawn,
+    #[token("join")]
+    Join,
+    #[token("exception")]
+    Exception,
+    #[token("raise")]
+    Raise,
+    #[token("handle")]
+    Handle,
+    #[token("class")]
+    Class,
+    #[token("instance")]
+    Instance,
+    #[token("deriving")]
+    Deriving,
+    #[token("signature")]
+    Signature,
+    #[token("sig")]
+    Sig,
+    #[token("val")]
+    Val,
+    #[token("structure")]
+    Structure,
+    #[token("struct")]
+    Struct,
+    #[token("open")]
+    Open,
+    /// Integer conversions, ex. `int8`. Holds the number of bits, none for `int`
+    #[regex("int(8|16|32|64)?", |lex| &lex.slice()[3..])]
+    Convert(&'source str),
 
     // Built-in types
     #[token("Int")]
     TInt,
+    /// Sized integer types, ex. `Int8`. Holds the number of bits
+    #[regex("Int(8|16|32|64)", |lex| &lex.slice()[3..])]
+    TSized(&'source str),
     #[token("Bool")]
     TBool,
     #[token("Unit")]
     TUnit,
+    #[token("String")]
+    TString,
+    #[token("Ref")]
+    TRef,
+    #[token("Array")]
+    TArray,
+    #[token("Future")]
+    TFuture,
+    #[token("Exn")]
+    TExn,
 }
 
 impl<'source> fmt::Display for Token<'source> {
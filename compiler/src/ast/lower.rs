@@ -0,0 +1,137 @@
+/*! Lowering passes, run on type checked expressions before they are evaluated.
+Type ascriptions `(e : T)` become `e`.
+Newtypes like `newtype UserId = UserId Int` are distinct from their field for the type checker,
+but represented by it: applying the constructor becomes its argument, the constructor alone the
+identity, and the pattern `UserId p` the pattern `p`. */
+
+use crate::ast::ast::{grow, Expr, Ident, Pattern, Prog, RawExpr, RawPattern, RawType, Type};
+use crate::ast::class::elaborate_classes;
+use crate::ast::derive::derive;
+use crate::ast::error::TypeError;
+use crate::ast::infer::elaborate_decl;
+use crate::ast::module::elaborate_modules;
+use crate::ast::semant::{
+    builtin_context, check_decl, define_datatype, define_exception, expand_decl, prog_aliases,
+    well_formed_decl, with_prelude,
+};
+use im::HashSet;
+
+/// Constructors of the newtypes in scope
+pub type Newtypes = HashSet<String>;
+
+/** Type checks `prog` and runs every lowering pass on its declarations.
+Returns: `prog` with its classes, modules and derived functions elaborated away, preceded by the
+prelude, and its declarations elaborated with their signatures in full, or `TypeError` */
+pub fn lower_prog(prog: &Prog) -> Result<Prog, TypeError> {
+    let mut prog = elaborate_classes(&derive(&elaborate_modules(&with_prelude(prog))?)?)?;
+    let mut ctxt = builtin_context();
+    let mut newtypes = Newtypes::default();
+    let mut aliases = prog_aliases(&prog)?;
+    for data in &prog.datatypes {
+        define_datatype(data, &mut ctxt, &mut aliases)?;
+        if data.newtype {
+            newtypes.insert(data.ctors[0].0.name.clone());
+        }
+    }
+    for exn in &prog.exceptions {
+        define_exception(exn, &mut ctxt, &aliases)?;
+    }
+    for id in &prog.order {
+        well_formed_decl(&prog.declarations[id], &aliases)?;
+        let decl = expand_decl(&prog.declarations[id], &aliases);
+        let (mut decl, _) = elaborate_decl(&decl, &ctxt)?;
+        check_decl(&decl, &mut ctxt)?;
+        lower(&mut decl.body, &newtypes);
+        prog.declarations.insert(id.clone(), decl);
+    }
+    Ok(prog)
+}
+
+/** Runs every lowering pass on `expr`, where `newtypes` are the constructors of newtypes in scope */
+pub fn lower(expr: &mut Expr, newtypes: &Newtypes) {
+    erase_ascriptions(expr);
+    erase_newtypes(expr, newtypes)
+}
+
+/** Replaces the ascriptions `(e : T)` in `expr` by `e` */
+pub fn erase_ascriptions(expr: &mut Expr) {
+    grow(|| {
+        if let RawExpr::Ascribe { exp, .. } = &mut expr.expr {
+            erase_ascriptions(exp);
+            *expr = (**exp).clone();
+            return;
+        }
+        expr.expr
+            .subexprs_mut()
+            .into_iter()
+            .for_each(erase_ascriptions)
+    })
+}
+
+/** Replaces the constructors of `newtypes` in `expr` and its patterns by their fields */
+pub fn erase_newtypes(expr: &mut Expr, newtypes: &Newtypes) {
+    grow(|| {
+        if is_newtype(expr, newtypes) {
+            let x = Ident {
+                name: "x".to_string(),
+                span: expr.span,
+            };
+            let body = Expr {
+                expr: RawExpr::Var { id: x.name.clone() },
+                span: expr.span,
+            };
+            expr.expr = RawExpr::Lambda {
+                arg: (x, Type::new(RawType::Hole)),
+                body: Box::new(body),
+            };
+            return;
+        }
+        match &mut expr.expr {
+            RawExpr::EApp { exp, arg } if is_newtype(exp, newtypes) => {
+                erase_newtypes(arg, newtypes);
+                *expr = (**arg).clone()
+            }
+            e => {
+                match e {
+                    RawExpr::Let { pat, .. } | RawExpr::Handle { pat, .. } => {
+                        erase_pattern(pat, newtypes)
+                    }
+                    RawExpr::Case { arms, .. } => arms
+                        .iter_mut()
+                        .for_each(|(pat, _)| erase_pattern(pat, newtypes)),
+                    _ => (),
+                }
+                e.subexprs_mut()
+                    .into_iter()
+                    .for_each(|e| erase_newtypes(e, newtypes))
+            }
+        }
+    })
+}
+
+/** Whether `expr` is a constructor of `newtypes`, possibly applied to types */
+fn is_newtype(expr: &Expr, newtypes: &Newtypes) -> bool {
+    match &expr.expr {
+        RawExpr::Var { id } => newtypes.contains(id),
+        RawExpr::TApp { exp, .. } => is_newtype(exp, newtypes),
+        _ => false,
+    }
+}
+
+/** Replaces the patterns of `newtypes` constructors in `pat` by the patterns of their fields */
+fn erase_pattern(pat: &mut Pattern, newtypes: &Newtypes) {
+    match &mut pat.pat {
+        RawPattern::Ctor(c, pats) if newtypes.contains(&c.name) => {
+            let mut field = pats.remove(0);
+            erase_pattern(&mut field, newtypes);
+            *pat = field
+        }
+        RawPattern::Ctor(_, pats) | RawPattern::Tuple(pats) => {
+            pats.iter_mut().for_each(|p| erase_pattern(p, newtypes))
+        }
+        RawPattern::Record(fields) => fields
+            .iter_mut()
+            .for_each(|(_, p)| erase_pattern(p, newtypes)),
+        RawPattern::Wildcard | RawPattern::Binding(_) | RawPattern::Literal(_) => (),
+    }
+}
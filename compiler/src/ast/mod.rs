@@ -1,7 +1,22 @@
+#[allow(clippy::module_inception)]
 pub mod ast;
+pub mod class;
+pub mod coverage;
+pub mod debug;
+pub mod derive;
+pub mod erase;
 pub mod error;
+pub mod infer;
+pub mod intern;
 pub mod interp;
 pub mod lex;
+pub mod lower;
+pub mod module;
+pub mod mono;
+pub mod nbe;
 pub mod parse;
+pub mod peval;
+pub mod pretty;
 pub mod repl;
 pub mod semant;
+pub mod step;
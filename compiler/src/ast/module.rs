@@ -0,0 +1,219 @@
+/*! Structures and signatures, elaborated into records and existential types before type checking.
+A signature `signature COUNTER = sig type T val zero: T val get: T -> Int end` becomes the alias
+`type COUNTER = ∃ T. {zero: T, get: T -> Int}`. A structure becomes a declaration of the record of
+its members, bound one after the other by `let`. Sealed with a signature, like
+`structure Counter : COUNTER = struct type T = Int let zero = 0 let get = λ n: T. n end`, the record
+is packed with the types the structure defines for the abstract ones, and the members left out of
+the signature are hidden. Within the structure its types are aliases. [`Open`](RawExpr::Open)
+binds the members of a structure in an expression. */
+
+use crate::ast::ast::{
+    Decl, Expr, Ident, Pattern, Prog, RawExpr, RawPattern, RawType, SigDecl, Span, StructDecl,
+    Type, TypeAlias,
+};
+use crate::ast::class::error;
+use crate::ast::error::TypeError;
+use crate::ast::semant::{expand, expand_expr, scoped_type_vars, substitute, Aliases};
+use std::iter::zip;
+
+/** Elaborates the signatures and structures of `prog` into type aliases and declarations.
+Signatures come after the other aliases, and structures take their place among the declarations.
+Returns: `prog` without signatures and structures, or `TypeError` if a structure is named like a
+declaration, or is sealed with an unknown signature or without one of its types or members */
+pub fn elaborate_modules(prog: &Prog) -> Result<Prog, TypeError> {
+    let mut out = Prog {
+        signatures: vec![],
+        structures: vec![],
+        ..prog.clone()
+    };
+    out.aliases
+        .extend(prog.signatures.iter().map(signature_alias));
+    for module in &prog.structures {
+        if prog.declarations.contains_key(&module.id.name) {
+            return Err(error(
+                "Redefinition of structure",
+                module.id.span.unwrap(),
+                "a declaration with the name of this structure already exists",
+            ));
+        }
+        let sig = match &module.sig {
+            Some(id) => match prog.signatures.iter().find(|s| s.id.name == id.name) {
+                Some(sig) => Some((id, sig)),
+                None => {
+                    return Err(error(
+                        "Unknown signature",
+                        id.span.unwrap(),
+                        "no signature with this name has been defined",
+                    ))
+                }
+            },
+            None => None,
+        };
+        let decl = structure_decl(module, sig)?;
+        out.declarations.insert(decl.id.clone(), decl);
+    }
+    Ok(out)
+}
+
+fn signature_alias(sig: &SigDecl) -> TypeAlias {
+    TypeAlias {
+        id: sig.id.name.clone(),
+        typ: Type {
+            typ: packed_type(&sig.types, &sig.vals),
+            span: sig.span,
+        },
+        doc: sig.doc.clone(),
+        span: sig.span,
+    }
+}
+
+/** `∃ T1. ... ∃ Tn. {x1: t1, ...}` for the abstract `types` and the members `vals` */
+fn packed_type(types: &[Ident], vals: &[(Ident, Type)]) -> RawType {
+    let record = RawType::Record(vals.to_vec());
+    types.iter().rev().fold(record, |typ, v| {
+        RawType::Exists(v.clone(), Box::new(Type::new(typ)))
+    })
+}
+
+/** `let x1 = e1 in ... let xn = en in {x1 = x1, ...}` for the members of `module`. If sealed
+with the signature `sig`, named as given by the structure, the record only has the members of
+`sig`, packed as its type */
+fn structure_decl(module: &StructDecl, sig: Option<(&Ident, &SigDecl)>) -> Result<Decl, TypeError> {
+    let span = module.span;
+    // Types of the structure, each in terms of the previous ones
+    let mut types = Aliases::new();
+    for alias in &module.types {
+        let mut typ = alias.typ.typ.clone();
+        expand(&mut typ, &types);
+        types.insert(alias.id.clone(), typ);
+    }
+    let mut lets = vec![];
+    for decl in &module.decls {
+        let at = decl.body.span;
+        let mut body = decl.body.clone();
+        // As for declarations, type variables of the signature scope over the body
+        for (arg, kind) in scoped_type_vars(decl).into_iter().rev() {
+            let body1 = Box::new(body);
+            body = expr(
+                RawExpr::Any {
+                    arg,
+                    kind,
+                    body: body1,
+                },
+                at,
+            )
+        }
+        if decl.sig.typ != RawType::Hole {
+            let ascribe = RawExpr::Ascribe {
+                exp: Box::new(body),
+                typ: decl.sig.clone(),
+            };
+            body = expr(ascribe, at)
+        }
+        expand_expr(&mut body, &types);
+        lets.push((ident(&decl.id, decl.span), body));
+    }
+    let (record, typ) = match sig {
+        None => {
+            let fields = module
+                .decls
+                .iter()
+                .map(|d| (ident(&d.id, span), var(&d.id, span)))
+                .collect();
+            (
+                expr(RawExpr::Record { fields }, span),
+                Type::new(RawType::Hole),
+            )
+        }
+        Some((id, sig)) => {
+            let mut witnesses = vec![];
+            for v in &sig.types {
+                match types.get(&v.name) {
+                    Some(typ) => witnesses.push(typ.clone()),
+                    None => {
+                        return Err(error(
+                            "Missing structure type",
+                            id.span.unwrap(),
+                            "the structure doesn't define a type of this signature",
+                        ))
+                    }
+                }
+            }
+            let mut fields = vec![];
+            for (member, typ) in &sig.vals {
+                if module.decls.iter().all(|d| d.id != member.name) {
+                    return Err(error(
+                        "Missing structure member",
+                        id.span.unwrap(),
+                        "the structure doesn't define a member of this signature",
+                    ));
+                }
+                // Members are checked against their types in the signature, with the types
+                // of the structure for the abstract ones
+                let mut typ = typ.clone();
+                for (v, w) in zip(&sig.types, &witnesses) {
+                    substitute(&v.name, w, &mut typ.typ)
+                }
+                let ascribe = RawExpr::Ascribe {
+                    exp: Box::new(var(&member.name, span)),
+                    typ,
+                };
+                fields.push((ident(&member.name, span), expr(ascribe, span)))
+            }
+            // Innermost first, packing as the abstract types left after the earlier ones
+            let mut packed = expr(RawExpr::Record { fields }, span);
+            for (i, witness) in witnesses.iter().enumerate().rev() {
+                let mut typ = packed_type(&sig.types[i..], &sig.vals);
+                for (v, w) in zip(&sig.types[..i], &witnesses) {
+                    substitute(&v.name, w, &mut typ)
+                }
+                let pack = RawExpr::Pack {
+                    witness: Type::new(witness.clone()),
+                    exp: Box::new(packed),
+                    typ: Type::new(typ),
+                };
+                packed = expr(pack, span)
+            }
+            let sig_typ = Type {
+                typ: RawType::TVar(id.name.clone()),
+                span: id.span,
+            };
+            (packed, sig_typ)
+        }
+    };
+    let body = lets.into_iter().rev().fold(record, |body, (id, exp)| {
+        let pat = Pattern {
+            pat: RawPattern::Binding(id),
+            span,
+        };
+        let binding = RawExpr::Let {
+            pat,
+            exp: Box::new(exp),
+            body: Box::new(body),
+        };
+        expr(binding, span)
+    });
+    Ok(Decl {
+        id: module.id.name.clone(),
+        sig: typ,
+        body,
+        doc: module.doc.clone(),
+        attrs: vec![],
+        span,
+    })
+}
+
+fn expr(expr: RawExpr, span: Option<Span>) -> Expr {
+    Expr { expr, span }
+}
+
+fn var(id: &str, span: Option<Span>) -> Expr {
+    expr(RawExpr::Var { id: id.to_string() }, span)
+}
+
+fn ident(name: &str, span: Option<Span>) -> Ident {
+    Ident {
+        name: name.to_string(),
+        span,
+    }
+}
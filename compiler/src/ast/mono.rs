@@ -0,0 +1,438 @@
+/*! Monomorphization of type checked programs, so that no type abstraction is left to represent
+when compiling them. A polymorphic declaration is replaced by its instances, copies of its body for
+each of the lists of types it's applied to, named after them like `id<Int>`. Type abstractions bound
+by `let` have their instances bound there the same way, and those applied to types right away are
+substituted. Constructors keep their type arguments, which need no representation.
+The types must be known before running the program: polymorphic values passed around, or applied
+to types abstracted by `unpack`, are rejected. Declarations can't refer to themselves, so each
+instance needs finitely many others, but polymorphic recursion is rejected rather than looping when
+they do. */
+
+use crate::ast::ast::{Decl, Expr, Ident, Pattern, Prog, RawExpr, RawPattern, RawType, Span, Type};
+use crate::ast::class::error;
+use crate::ast::error::TypeError;
+use crate::ast::semant::{free_in, normalize, substitute};
+use im::{HashMap, HashSet};
+
+/** Monomorphizes `prog`, type checked and lowered by [`lower_prog`](crate::ast::lower::lower_prog).
+Returns: `prog` with the instances of each polymorphic declaration in its place, or `TypeError` if
+the types some polymorphic value is applied to aren't known before running the program */
+pub fn monomorphize(prog: &Prog) -> Result<Prog, TypeError> {
+    let mut mono = Mono::default();
+    for id in &prog.order {
+        let decl = &prog.declarations[id];
+        if let RawType::Forall(..) = decl.sig.typ {
+            mono.generic.insert(id.clone(), decl.clone());
+            mono.origins.insert(id.clone(), id.clone());
+        } else {
+            mono.queue.push((decl.clone(), vec![]))
+        }
+        if let Some(members) = members(&decl.sig.typ) {
+            mono.structures.insert(id.clone(), members);
+        }
+    }
+    let data = prog.datatypes.iter().flat_map(|data| &data.ctors);
+    let ctors = data
+        .map(|(c, _)| c)
+        .chain(prog.exceptions.iter().map(|e| &e.ctor));
+    mono.ctors = ctors.map(|c| c.name.clone()).collect();
+    // Queued last are specialized first, so the monomorphic declarations in order
+    mono.queue.reverse();
+    while let Some((mut decl, chain)) = mono.queue.pop() {
+        mono.at = decl.span.unwrap_or_default();
+        mono.chain = chain;
+        mono.expr(&mut decl.body, &Scope::default())?;
+        mono.done.insert(decl.id.clone(), decl);
+    }
+    let mut out = Prog {
+        declarations: Default::default(),
+        order: vec![],
+        ..prog.clone()
+    };
+    for id in &prog.order {
+        let names = match mono.generic.contains_key(id) {
+            true => mono.instances.get(id).cloned().unwrap_or_default(),
+            false => vec![id.clone()],
+        };
+        for name in names {
+            out.order.push(name.clone());
+            out.declarations
+                .insert(name.clone(), mono.done.remove(&name).unwrap());
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Default)]
+struct Mono {
+    /// Polymorphic declarations, and instances of them still polymorphic
+    generic: HashMap<String, Decl>,
+    /// The declarations polymorphic declarations and their instances are instances of
+    origins: HashMap<String, String>,
+    /// Monomorphic instances of the polymorphic declarations, in order of use
+    instances: HashMap<String, Vec<String>>,
+    /// Declarations left to specialize, with the declarations of the instances they're needed by
+    queue: Vec<(Decl, Vec<String>)>,
+    /// Declarations specialized
+    done: HashMap<String, Decl>,
+    /// Members of the structures among the declarations
+    structures: HashMap<String, Vec<String>>,
+    /// Constructors of data types and exceptions
+    ctors: HashSet<String>,
+    /// Type abstractions bound by `let` around the expression specialized, innermost last
+    lets: Vec<Let>,
+    /// Declarations of the instances the declaration specialized is needed by
+    chain: Vec<String>,
+    /// Location of the declaration specialized, for errors in expressions elaborated
+    at: Span,
+}
+
+/// A type abstraction bound by `let`, and the instances of it used so far
+struct Let {
+    var: Ident,
+    generic: Expr,
+    instances: Vec<(String, Vec<RawType>)>,
+}
+
+/// Variables bound in an expression
+#[derive(Clone, Default)]
+struct Scope {
+    /// Variables shadowing the declarations, with the type abstractions among them
+    vars: HashMap<String, Option<usize>>,
+    /// Type variables abstracted by `unpack`
+    tvars: HashSet<String>,
+}
+
+impl Scope {
+    fn bind<'a>(&self, vars: impl IntoIterator<Item = &'a str>) -> Scope {
+        let mut scope = self.clone();
+        scope
+            .vars
+            .extend(vars.into_iter().map(|v| (v.to_string(), None)));
+        scope
+    }
+}
+
+/** The fields of the record type `typ`, possibly packed like the types of structures */
+fn members(typ: &RawType) -> Option<Vec<String>> {
+    match typ {
+        RawType::Record(fields) => Some(fields.iter().map(|(l, _)| l.name.clone()).collect()),
+        RawType::Exists(_, typ) => members(&typ.typ),
+        _ => None,
+    }
+}
+
+/** The name of the instance of `id` at `args` */
+fn instance_name(id: &str, args: &[RawType]) -> String {
+    let args: Vec<String> = args.iter().map(|t| t.to_string()).collect();
+    format!("{id}<{}>", args.join(", "))
+}
+
+/** `expr` applied to the type `arg`, substituting it if `expr` is a type abstraction */
+fn instantiate(expr: Expr, arg: &RawType) -> Expr {
+    match expr.expr {
+        RawExpr::Any {
+            arg: tvar, body, ..
+        } => {
+            let mut body = *body;
+            substitute_expr(&mut body, &tvar.name, arg);
+            body
+        }
+        _ => Expr {
+            span: expr.span,
+            expr: RawExpr::TApp {
+                exp: Box::new(expr),
+                arg: Type::new(arg.clone()),
+            },
+        },
+    }
+}
+
+/** The number of leading type abstractions of `expr` */
+fn abstractions(expr: &Expr) -> usize {
+    match &expr.expr {
+        RawExpr::Any { body, .. } => 1 + abstractions(body),
+        _ => 0,
+    }
+}
+
+/** Substitutes the closed `target` for the type variable `tvar` in the types in `expr` */
+fn substitute_expr(expr: &mut Expr, tvar: &str, target: &RawType) {
+    use RawExpr::*;
+    let subst = |t: &mut Type| substitute(tvar, target, &mut t.typ);
+    match &mut expr.expr {
+        Any { arg, .. } if arg.name == tvar => return,
+        Unpack { tvar: v, exp, .. } if v.name == tvar => return substitute_expr(exp, tvar, target),
+        Fix { funcs, .. } => {
+            for (_, _, typ, ret, _) in funcs {
+                subst(typ);
+                subst(ret)
+            }
+        }
+        Lambda { arg: (_, t), .. }
+        | TApp { arg: t, .. }
+        | Fold { typ: t, .. }
+        | Ascribe { typ: t, .. }
+        | Raise { typ: t, .. }
+        | Instance { typ: t, .. } => subst(t),
+        Pack { witness, typ, .. } => {
+            subst(witness);
+            subst(typ)
+        }
+        _ => (),
+    }
+    expr.expr
+        .subexprs_mut()
+        .into_iter()
+        .for_each(|e| substitute_expr(e, tvar, target))
+}
+
+impl Mono {
+    /** Replaces the polymorphic values applied to types in `expr` by their instances */
+    fn expr(&mut self, expr: &mut Expr, scope: &Scope) -> Result<(), TypeError> {
+        use RawExpr::*;
+        let at = expr.span.unwrap_or(self.at);
+        match &mut expr.expr {
+            TApp { .. } => self.tapp(expr, scope),
+            Var { id } if self.is_generic(id, scope) => Err(error(
+                "Cannot monomorphize",
+                at,
+                "this polymorphic value isn't applied to types",
+            )),
+            Any { .. } => Err(error(
+                "Cannot monomorphize",
+                at,
+                "this type abstraction isn't applied to types",
+            )),
+            Let { pat, exp, body } => {
+                if let (RawPattern::Binding(var), Any { .. }) = (&pat.pat, &exp.expr) {
+                    let (var, generic) = (var.clone(), (**exp).clone());
+                    return self.generic_let(expr, var, generic, scope);
+                }
+                self.expr(exp, scope)?;
+                self.expr(body, &scope.bind(pat.bindings()))
+            }
+            Lambda { arg, body } => self.expr(body, &scope.bind([arg.0.name.as_str()])),
+            Fix { funcs, body } => {
+                let scope = scope.bind(funcs.iter().map(|(f, ..)| f.name.as_str()));
+                for (_, x, _, _, body) in funcs {
+                    self.expr(body, &scope.bind([x.name.as_str()]))?
+                }
+                self.expr(body, &scope)
+            }
+            Case { exp, arms } => {
+                self.expr(exp, scope)?;
+                for (pat, body) in arms {
+                    self.expr(body, &scope.bind(pat.bindings()))?
+                }
+                Ok(())
+            }
+            Handle { exp, pat, handler } => {
+                self.expr(exp, scope)?;
+                self.expr(handler, &scope.bind(pat.bindings()))
+            }
+            Unpack {
+                tvar,
+                var,
+                exp,
+                body,
+            } => {
+                self.expr(exp, scope)?;
+                let mut scope = scope.bind([var.name.as_str()]);
+                scope.tvars.insert(tvar.name.clone());
+                self.expr(body, &scope)
+            }
+            Open { exp, body } => {
+                self.expr(exp, scope)?;
+                let members = match &exp.expr {
+                    Var { id } if !scope.vars.contains_key(id) => self.structures.get(id),
+                    _ => None,
+                };
+                let members = members.into_iter().flatten().map(String::as_str);
+                self.expr(body, &scope.bind(members))
+            }
+            e => e
+                .subexprs_mut()
+                .into_iter()
+                .try_for_each(|e| self.expr(e, scope)),
+        }
+    }
+
+    /** Whether `var` is a polymorphic declaration or a type abstraction bound by `let` */
+    fn is_generic(&self, var: &str, scope: &Scope) -> bool {
+        match scope.vars.get(var) {
+            Some(local) => local.is_some(),
+            None => self.generic.contains_key(var),
+        }
+    }
+
+    /** Replaces the type application `expr` by the instance it stands for */
+    fn tapp(&mut self, expr: &mut Expr, scope: &Scope) -> Result<(), TypeError> {
+        let mut head = expr.clone();
+        let mut args = vec![];
+        while let RawExpr::TApp { exp, arg } = head.expr {
+            let at = arg.span.or(exp.span).unwrap_or(self.at);
+            if scope.tvars.iter().any(|v| free_in(v, &arg.typ)) {
+                return Err(error(
+                    "Cannot monomorphize",
+                    at,
+                    "this type is abstracted by `unpack`, only known when running the program",
+                ));
+            }
+            args.push(normalize(&arg.typ));
+            head = *exp
+        }
+        args.reverse();
+        let at = head.span.unwrap_or(self.at);
+        let partial = || {
+            error(
+                "Cannot monomorphize",
+                at,
+                "this is applied to fewer types than it abstracts over",
+            )
+        };
+        match &head.expr {
+            RawExpr::Any { .. } => {
+                *expr = args.iter().fold(head, instantiate);
+                self.expr(expr, scope)
+            }
+            RawExpr::Var { id } if self.ctors.contains(id) => Ok(()),
+            RawExpr::Var { id } => match scope.vars.get(id) {
+                Some(Some(i)) => {
+                    let local = &mut self.lets[*i];
+                    if args.len() != abstractions(&local.generic) {
+                        return Err(partial());
+                    }
+                    let name = instance_name(&local.var.name, &args);
+                    if local.instances.iter().all(|(n, _)| n != &name) {
+                        local.instances.push((name.clone(), args))
+                    }
+                    expr.expr = RawExpr::Var { id: name };
+                    Ok(())
+                }
+                None if self.generic.contains_key(id) => {
+                    let mut typ = &self.generic[id].sig.typ;
+                    let mut params = 0;
+                    while let RawType::Forall(_, _, t) = typ {
+                        params += 1;
+                        typ = &t.typ
+                    }
+                    if args.len() < params {
+                        return Err(partial());
+                    }
+                    let name = self.instance(id, &args[..params], at)?;
+                    let var = Expr {
+                        expr: RawExpr::Var { id: name },
+                        span: head.span,
+                    };
+                    *expr = args[params..].iter().fold(var, |exp, arg| Expr {
+                        span: expr.span,
+                        expr: RawExpr::TApp {
+                            exp: Box::new(exp),
+                            arg: Type::new(arg.clone()),
+                        },
+                    });
+                    match params < args.len() {
+                        true => self.tapp(expr, scope),
+                        false => Ok(()),
+                    }
+                }
+                _ => Err(error(
+                    "Cannot monomorphize",
+                    at,
+                    "the polymorphic value applied to types here isn't known statically",
+                )),
+            },
+            _ => Err(error(
+                "Cannot monomorphize",
+                at,
+                "the polymorphic value applied to types here isn't known statically",
+            )),
+        }
+    }
+
+    /** The name of the instance of the polymorphic declaration `id` at `args`, queuing it to be
+    specialized if it's new and monomorphic */
+    fn instance(&mut self, id: &str, args: &[RawType], at: Span) -> Result<String, TypeError> {
+        let name = instance_name(id, args);
+        if self.origins.contains_key(&name) {
+            return Ok(name);
+        }
+        let origin = self.origins[id].clone();
+        if self.chain.contains(&origin) {
+            return Err(error(
+                "Polymorphic recursion",
+                at,
+                "this needs infinitely many instances of its declaration",
+            ));
+        }
+        let mut decl = self.generic[id].clone();
+        decl.id = name.clone();
+        for arg in args {
+            let RawType::Forall(tvar, _, typ) = decl.sig.typ else {
+                unreachable!("instances have as many type arguments as type parameters")
+            };
+            decl.sig.typ = typ.typ;
+            substitute(&tvar.name, arg, &mut decl.sig.typ);
+            decl.body = instantiate(decl.body, arg)
+        }
+        decl.sig.typ = normalize(&decl.sig.typ);
+        self.origins.insert(name.clone(), origin.clone());
+        if let RawType::Forall(..) = decl.sig.typ {
+            // Instantiated at a polymorphic type, to be applied to more types
+            self.generic.insert(name.clone(), decl);
+        } else {
+            let instances = self.instances.entry(origin.clone()).or_default();
+            instances.push(name.clone());
+            let mut chain = self.chain.clone();
+            chain.push(origin);
+            self.queue.push((decl, chain))
+        }
+        Ok(name)
+    }
+
+    /** Replaces `expr`, binding the type abstraction `generic` to `var`, by bindings of the
+    instances of it its body uses */
+    fn generic_let(
+        &mut self,
+        expr: &mut Expr,
+        var: Ident,
+        generic: Expr,
+        scope: &Scope,
+    ) -> Result<(), TypeError> {
+        let RawExpr::Let { pat, body, .. } = &mut expr.expr else {
+            unreachable!("type abstractions are bound by `let`")
+        };
+        let mut inner = scope.clone();
+        inner.vars.insert(var.name.clone(), Some(self.lets.len()));
+        self.lets.push(Let {
+            var,
+            generic,
+            instances: vec![],
+        });
+        self.expr(body, &inner)?;
+        let local = self.lets.pop().unwrap();
+        let (pat, mut rest) = (pat.clone(), (**body).clone());
+        for (name, args) in local.instances.into_iter().rev() {
+            let mut exp = args.iter().fold(local.generic.clone(), instantiate);
+            self.expr(&mut exp, scope)?;
+            let var = Ident {
+                name,
+                span: local.var.span,
+            };
+            rest = Expr {
+                expr: RawExpr::Let {
+                    pat: Pattern {
+                        pat: RawPattern::Binding(var),
+                        span: pat.span,
+                    },
+                    exp: Box::new(exp),
+                    body: Box::new(rest),
+                },
+                span: expr.span,
+            };
+        }
+        *expr = rest;
+        Ok(())
+    }
+}
@@ -0,0 +1,685 @@
+/*! Normalization by evaluation of open expressions, for partial evaluation and for comparing
+programs up to β and η. Expressions evaluate to semantic values, where the parts that depend on
+free variables are neutral: normal forms stuck on an unknown. Values are read back as expressions
+by applying functions to fresh neutral variables, so normalization goes under binders, and
+`λ x. f x` reads back as `f`.
+
+Effects are kept in order: applications of unknown functions, like the builtins, and anything
+that may raise, like `1 / x`, or touch the store are bound by `let` where they're evaluated
+instead of being copied or dropped. Conditionals and matches on unknowns are left in place with
+their branches normalized. Recursive functions unfold when applied to arguments that aren't
+neutral, so normalizing diverges when evaluating would. Like [`trace`], types are erased from
+`fold`, `unfold` and `pack`, and constructors don't keep the types they're applied to.
+
+[`trace`]: crate::ast::step::trace */
+
+use crate::ast::ast::{
+    Binary, Constant, Expr, Ident, Kind, Pattern, RawExpr, RawPattern, RawType, Type, Width,
+};
+use crate::ast::interp::same_constant;
+use crate::ast::semant::{expand_expr, Aliases};
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use im::hashmap::HashMap;
+
+type Env = HashMap<String, Sem>;
+
+type Func = (Ident, Ident, Type, Type, Expr);
+
+/// Semantic values of expressions
+#[derive(Clone)]
+enum Sem {
+    Con(Constant),
+    /// A function with its parameter, and the environment it was defined in
+    Lam(Ident, Type, Rc<Expr>, Env),
+    /// A type abstraction, and the environment it was defined in
+    Any(Ident, Kind, Rc<Expr>, Env),
+    /// The function at the index among the functions of a `fix`, and the environment of the `fix`
+    Fix(Rc<Vec<Func>>, usize, Env),
+    Tuple(Vec<Sem>),
+    Record(Vec<(Ident, Sem)>),
+    /// A constructor applied to arguments
+    Data(String, Vec<Sem>),
+    /// A normal form stuck on a free variable, without effects
+    Neutral(Expr),
+}
+
+/// Result of matching a value against a pattern
+enum Match {
+    Bound(Vec<(String, Sem)>),
+    Failed,
+    /// The value is neutral where the pattern needs to know it
+    Stuck,
+}
+
+/** The normal form of `expr`. Its variables are kept, and the ones it binds are renamed `v0`,
+`v1`... in order, skipping names it uses */
+pub fn normalize(expr: &Expr) -> Expr {
+    Normalizer::new(&[expr]).normalize(expr)
+}
+
+/** Whether `e1` and `e2` have the same normal form, up to the names of bound variables */
+pub fn beta_eta_equal(e1: &Expr, e2: &Expr) -> bool {
+    Normalizer::new(&[e1, e2]).normalize(e1) == Normalizer::new(&[e1, e2]).normalize(e2)
+}
+
+struct Normalizer {
+    /// Names of the variables of the expressions normalized, never given to new ones
+    taken: HashSet<String>,
+    next: usize,
+    /// Effectful expressions evaluated in the current scope in order, with the variables they're
+    /// bound to
+    lets: Vec<(String, Expr)>,
+}
+
+impl Normalizer {
+    fn new(exprs: &[&Expr]) -> Self {
+        let mut taken = HashSet::new();
+        for expr in exprs {
+            collect_names(expr, &mut taken)
+        }
+        Normalizer {
+            taken,
+            next: 0,
+            lets: vec![],
+        }
+    }
+
+    fn normalize(&mut self, expr: &Expr) -> Expr {
+        self.scope(|n| n.eval(expr, &Env::new())).0
+    }
+
+    fn fresh(&mut self) -> String {
+        loop {
+            let name = format!("v{}", self.next);
+            self.next += 1;
+            if !self.taken.contains(&name) {
+                return name;
+            }
+        }
+    }
+
+    /** Binds the effectful `expr` to a new variable in the current scope.
+    Returns: The variable */
+    fn emit(&mut self, expr: RawExpr) -> Sem {
+        let x = self.fresh();
+        self.lets.push((x.clone(), Expr::new(expr)));
+        Sem::Neutral(var(&x))
+    }
+
+    /** Reads back the value of `body` in a scope of its own, under the `let`s of the effects
+    it evaluates.
+    Returns: The expression, and whether it has effects */
+    fn scope(&mut self, body: impl FnOnce(&mut Self) -> Sem) -> (Expr, bool) {
+        let outer = std::mem::take(&mut self.lets);
+        let val = body(self);
+        let lets = std::mem::replace(&mut self.lets, outer);
+        let effects = !lets.is_empty();
+        (self.wrap(&val, lets), effects)
+    }
+
+    /** `val` read back under `lets`. `let x = e in x` is `e` */
+    fn wrap(&mut self, val: &Sem, mut lets: Vec<(String, Expr)>) -> Expr {
+        let mut expr = self.reify(val);
+        if let Some((x, exp)) = lets.last() {
+            if expr.expr == (RawExpr::Var { id: x.clone() }) {
+                expr = exp.clone();
+                lets.pop();
+            }
+        }
+        for (x, exp) in lets.into_iter().rev() {
+            expr = Expr::new(RawExpr::Let {
+                pat: pattern(RawPattern::Binding(ident(&x))),
+                exp: Box::new(exp),
+                body: Box::new(expr),
+            })
+        }
+        expr
+    }
+
+    /** `expr`, bound by `let` if `effects` */
+    fn residual(&mut self, expr: RawExpr, effects: bool) -> Sem {
+        if effects {
+            self.emit(expr)
+        } else {
+            Sem::Neutral(Expr::new(expr))
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, env: &Env) -> Sem {
+        use RawExpr::*;
+        match &expr.expr {
+            Con { val } => Sem::Con(val.clone()),
+            Var { id } => match env.get(id) {
+                Some(val) => val.clone(),
+                None if id.starts_with(char::is_uppercase) => Sem::Data(id.clone(), vec![]),
+                None => Sem::Neutral(var(id)),
+            },
+            Let { pat, exp, body } => {
+                let val = self.eval(exp, env);
+                self.eval_arms(val, &[(pat.clone(), (**body).clone())], env, true)
+            }
+            Fix { funcs, body } => {
+                let funcs = Rc::new(funcs.clone());
+                let mut new_env = env.clone();
+                for (i, (f, ..)) in funcs.iter().enumerate() {
+                    new_env.insert(f.name.clone(), Sem::Fix(funcs.clone(), i, env.clone()));
+                }
+                self.eval(body, &new_env)
+            }
+            EApp { exp, arg } => {
+                let fun = self.eval(exp, env);
+                let arg = self.eval(arg, env);
+                self.apply(fun, arg)
+            }
+            TApp { exp, arg } => match self.eval(exp, env) {
+                Sem::Any(v, _, body, env) => {
+                    let mut body = (*body).clone();
+                    expand_expr(&mut body, &Aliases::from([(v.name, arg.typ.clone())]));
+                    self.eval(&body, &env)
+                }
+                Sem::Neutral(e) => Sem::Neutral(Expr::new(TApp {
+                    exp: Box::new(e),
+                    arg: Type::new(arg.typ.clone()),
+                })),
+                val => val,
+            },
+            Tuple { entries } => Sem::Tuple(entries.iter().map(|e| self.eval(e, env)).collect()),
+            Record { fields } => Sem::Record(
+                fields
+                    .iter()
+                    .map(|(l, e)| (ident(&l.name), self.eval(e, env)))
+                    .collect(),
+            ),
+            Binop {
+                lhs,
+                op,
+                rhs,
+                width,
+            } => {
+                let lhs = self.eval(lhs, env);
+                let rhs = self.eval(rhs, env);
+                self.binop(lhs, op, rhs, *width)
+            }
+            Lambda { arg: (x, t), body } => {
+                Sem::Lam(x.clone(), t.clone(), Rc::new((**body).clone()), env.clone())
+            }
+            Any { arg, kind, body } => Sem::Any(
+                arg.clone(),
+                kind.clone(),
+                Rc::new((**body).clone()),
+                env.clone(),
+            ),
+            If {
+                cond,
+                branch_t,
+                branch_f,
+            } => match self.eval(cond, env) {
+                Sem::Con(Constant::Boolean(true)) => self.eval(branch_t, env),
+                Sem::Con(Constant::Boolean(false)) => self.eval(branch_f, env),
+                cond => {
+                    let cond = self.reify(&cond);
+                    let (t, t_effects) = self.scope(|n| n.eval(branch_t, env));
+                    let (f, f_effects) = self.scope(|n| n.eval(branch_f, env));
+                    let expr = If {
+                        cond: Box::new(cond),
+                        branch_t: Box::new(t),
+                        branch_f: Box::new(f),
+                    };
+                    self.residual(expr, t_effects || f_effects)
+                }
+            },
+            Case { exp, arms } => {
+                let val = self.eval(exp, env);
+                self.eval_arms(val, arms, env, false)
+            }
+            Proj { exp, field } => match self.eval(exp, env) {
+                Sem::Record(fields) => match fields.into_iter().find(|(l, _)| l.name == field.name)
+                {
+                    Some((_, val)) => val,
+                    None => Sem::Neutral(expr.clone()),
+                },
+                val => Sem::Neutral(Expr::new(Proj {
+                    exp: Box::new(self.reify(&val)),
+                    field: ident(&field.name),
+                })),
+            },
+            Nth { exp, index } => match self.eval(exp, env) {
+                Sem::Tuple(mut vals) if *index < vals.len() => vals.swap_remove(*index),
+                val => Sem::Neutral(Expr::new(Nth {
+                    exp: Box::new(self.reify(&val)),
+                    index: *index,
+                })),
+            },
+            Fold { exp, .. } | Unfold { exp } | Pack { exp, .. } | Ascribe { exp, .. } => {
+                self.eval(exp, env)
+            }
+            Unpack { var, exp, body, .. } => {
+                let val = self.eval(exp, env);
+                self.eval(body, &env.update(var.name.clone(), val))
+            }
+            Convert { exp, from, to } => match self.eval(exp, env) {
+                Sem::Con(c) if c.as_int().is_some() => {
+                    Sem::Con(to.constant(to.wrap(c.as_int().unwrap())))
+                }
+                val => Sem::Neutral(Expr::new(Convert {
+                    exp: Box::new(self.reify(&val)),
+                    from: *from,
+                    to: *to,
+                })),
+            },
+            Open { exp, body } => match self.eval(exp, env) {
+                Sem::Record(fields) => {
+                    let mut new_env = env.clone();
+                    for (l, val) in fields {
+                        new_env.insert(l.name, val);
+                    }
+                    self.eval(body, &new_env)
+                }
+                // The members of an unknown structure aren't known, so the body stays as it is,
+                // under the values of the variables it may refer to
+                val => {
+                    let mut expr = Expr::new(Open {
+                        exp: Box::new(self.reify(&val)),
+                        body: body.clone(),
+                    });
+                    let mut used = HashSet::new();
+                    collect_names(body, &mut used);
+                    // By name, so normal forms don't depend on the order of the environment
+                    let mut free: Vec<_> = env.iter().filter(|(x, _)| used.contains(*x)).collect();
+                    free.sort_by(|(x, _), (y, _)| y.cmp(x));
+                    for (x, val) in free {
+                        expr = Expr::new(Let {
+                            pat: pattern(RawPattern::Binding(ident(x))),
+                            exp: Box::new(self.reify(val)),
+                            body: Box::new(expr),
+                        })
+                    }
+                    self.emit(expr.expr)
+                }
+            },
+            Handle { exp, pat, handler } => {
+                let outer = std::mem::take(&mut self.lets);
+                let val = self.eval(exp, env);
+                let lets = std::mem::replace(&mut self.lets, outer);
+                // Only effects may raise
+                if lets.is_empty() {
+                    return val;
+                }
+                let exp = self.wrap(&val, lets);
+                let mut new_env = env.clone();
+                let pat = self.bind_fresh(pat, &mut new_env);
+                let (handler, _) = self.scope(|n| n.eval(handler, &new_env));
+                self.emit(Handle {
+                    exp: Box::new(exp),
+                    pat,
+                    handler: Box::new(handler),
+                })
+            }
+            // The task catches the exceptions it raises, so its effects stay inside
+            Spawn { exp } => {
+                let (exp, _) = self.scope(|n| n.eval(exp, env));
+                self.emit(Spawn { exp: Box::new(exp) })
+            }
+            // Effects, with their subexpressions evaluated in order
+            RawExpr::Ref { .. }
+            | Deref { .. }
+            | AssertEq { .. }
+            | Join { .. }
+            | Assign { .. }
+            | RawExpr::Array { .. }
+            | Sub { .. }
+            | Update { .. }
+            | Raise { .. } => {
+                let mut residual = expr.expr.clone();
+                for sub in residual.subexprs_mut() {
+                    let val = self.eval(sub, env);
+                    *sub = self.reify(&val)
+                }
+                if let Raise { typ, .. } = &mut residual {
+                    *typ = Type::new(RawType::Hole)
+                }
+                self.emit(residual)
+            }
+            Instance { .. } => Sem::Neutral(expr.clone()),
+        }
+    }
+
+    fn apply(&mut self, fun: Sem, arg: Sem) -> Sem {
+        match fun {
+            Sem::Lam(x, _, body, env) => self.eval(&body, &env.update(x.name, arg)),
+            Sem::Fix(funcs, i, env) if !arg.is_neutral() => {
+                let mut new_env = env.clone();
+                for (j, (f, ..)) in funcs.iter().enumerate() {
+                    new_env.insert(f.name.clone(), Sem::Fix(funcs.clone(), j, env.clone()));
+                }
+                let (_, v, _, _, body) = &funcs[i];
+                new_env.insert(v.name.clone(), arg);
+                self.eval(body, &new_env)
+            }
+            Sem::Data(c, mut args) => {
+                args.push(arg);
+                Sem::Data(c, args)
+            }
+            // Unknown functions may have effects
+            fun => {
+                let expr = RawExpr::EApp {
+                    exp: Box::new(self.reify(&fun)),
+                    arg: Box::new(self.reify(&arg)),
+                };
+                self.emit(expr)
+            }
+        }
+    }
+
+    fn binop(&mut self, lhs: Sem, op: &Binary, rhs: Sem, width: Width) -> Sem {
+        use Binary::*;
+        use Constant::*;
+        let folded = match (op, &lhs, &rhs) {
+            (Eq | Ne, _, _) => equal(&lhs, &rhs).map(|eq| Boolean(eq == (*op == Eq))),
+            (And, Sem::Con(Boolean(l)), Sem::Con(Boolean(r))) => Some(Boolean(*l & *r)),
+            (Or, Sem::Con(Boolean(l)), Sem::Con(Boolean(r))) => Some(Boolean(*l | *r)),
+            (_, Sem::Con(Integer(l)), Sem::Con(Integer(r))) => op.apply_int(*l, *r),
+            (_, Sem::Con(Sized(l, w)), Sem::Con(Sized(r, _))) => op.apply_sized(*l, *r, *w),
+            _ => None,
+        };
+        if let Some(c) = folded {
+            return Sem::Con(c);
+        }
+        let expr = RawExpr::Binop {
+            lhs: Box::new(self.reify(&lhs)),
+            op: op.clone(),
+            rhs: Box::new(self.reify(&rhs)),
+            width,
+        };
+        // Division and modulo may raise `Div`
+        self.residual(expr, matches!(op, Div | Mod))
+    }
+
+    /** Evaluates the body of the first of `arms` whose pattern `val` matches. The arms from
+    the first one stuck on `val` are left as a `case`, or a `let` if `is_let` */
+    fn eval_arms(&mut self, val: Sem, arms: &[(Pattern, Expr)], env: &Env, is_let: bool) -> Sem {
+        for (i, (pat, body)) in arms.iter().enumerate() {
+            match matches(&val, &pat.pat) {
+                Match::Bound(binds) => {
+                    let mut new_env = env.clone();
+                    new_env.extend(binds);
+                    return self.eval(body, &new_env);
+                }
+                Match::Failed => continue,
+                Match::Stuck => {
+                    let exp = Box::new(self.reify(&val));
+                    let mut effects = false;
+                    let mut residual_arms = vec![];
+                    for (pat, body) in &arms[i..] {
+                        let mut new_env = env.clone();
+                        let pat = self.bind_fresh(pat, &mut new_env);
+                        let (body, arm_effects) = self.scope(|n| n.eval(body, &new_env));
+                        effects |= arm_effects;
+                        residual_arms.push((pat, body))
+                    }
+                    let expr = if is_let {
+                        let (pat, body) = residual_arms.remove(0);
+                        RawExpr::Let {
+                            pat,
+                            exp,
+                            body: Box::new(body),
+                        }
+                    } else {
+                        RawExpr::Case {
+                            exp,
+                            arms: residual_arms,
+                        }
+                    };
+                    return self.residual(expr, effects);
+                }
+            }
+        }
+        // Exhaustiveness is checked beforehand
+        let expr = RawExpr::Case {
+            exp: Box::new(self.reify(&val)),
+            arms: vec![],
+        };
+        self.emit(expr)
+    }
+
+    /** `pat` with its variables renamed to new ones, bound to themselves in `env` */
+    fn bind_fresh(&mut self, pat: &Pattern, env: &mut Env) -> Pattern {
+        let pat = match &pat.pat {
+            RawPattern::Binding(x) => {
+                let y = self.fresh();
+                env.insert(x.name.clone(), Sem::Neutral(var(&y)));
+                RawPattern::Binding(ident(&y))
+            }
+            RawPattern::Tuple(pats) => {
+                RawPattern::Tuple(pats.iter().map(|p| self.bind_fresh(p, env)).collect())
+            }
+            RawPattern::Ctor(c, pats) => RawPattern::Ctor(
+                ident(&c.name),
+                pats.iter().map(|p| self.bind_fresh(p, env)).collect(),
+            ),
+            RawPattern::Record(fields) => RawPattern::Record(
+                fields
+                    .iter()
+                    .map(|(l, p)| (ident(&l.name), self.bind_fresh(p, env)))
+                    .collect(),
+            ),
+            p @ (RawPattern::Wildcard | RawPattern::Literal(_)) => p.clone(),
+        };
+        pattern(pat)
+    }
+
+    /** The normal form of `val` as an expression */
+    fn reify(&mut self, val: &Sem) -> Expr {
+        use RawExpr::*;
+        let expr = match val {
+            Sem::Con(c) => Con { val: c.clone() },
+            Sem::Lam(x, t, body, env) => {
+                let y = self.fresh();
+                let env = env.update(x.name.clone(), Sem::Neutral(var(&y)));
+                let (body, _) = self.scope(|n| n.eval(body, &env));
+                // η: `λ y. f y` is `f`, if `y` isn't free in `f`
+                if let EApp { exp, arg } = &body.expr {
+                    let mut used = HashSet::new();
+                    collect_names(exp, &mut used);
+                    if arg.expr == (Var { id: y.clone() }) && !used.contains(&y) {
+                        return (**exp).clone();
+                    }
+                }
+                Lambda {
+                    arg: (ident(&y), Type::new(t.typ.clone())),
+                    body: Box::new(body),
+                }
+            }
+            Sem::Any(v, kind, body, env) => {
+                let (body, _) = self.scope(|n| n.eval(body, env));
+                Any {
+                    arg: ident(&v.name),
+                    kind: kind.clone(),
+                    body: Box::new(body),
+                }
+            }
+            // Within the functions, the others are unknown so they don't unfold again
+            Sem::Fix(funcs, i, env) => {
+                let mut new_env = env.clone();
+                let renamed: Vec<_> = funcs
+                    .iter()
+                    .map(|(f, ..)| {
+                        let g = self.fresh();
+                        new_env.insert(f.name.clone(), Sem::Neutral(var(&g)));
+                        g
+                    })
+                    .collect();
+                let mut new_funcs = vec![];
+                for (g, (_, v, t, ret, body)) in renamed.iter().zip(funcs.iter()) {
+                    let w = self.fresh();
+                    let env = new_env.update(v.name.clone(), Sem::Neutral(var(&w)));
+                    let (body, _) = self.scope(|n| n.eval(body, &env));
+                    new_funcs.push((
+                        ident(g),
+                        ident(&w),
+                        Type::new(t.typ.clone()),
+                        Type::new(ret.typ.clone()),
+                        body,
+                    ))
+                }
+                Fix {
+                    funcs: new_funcs,
+                    body: Box::new(var(&renamed[*i])),
+                }
+            }
+            Sem::Tuple(vals) => Tuple {
+                entries: vals.iter().map(|v| self.reify(v)).collect(),
+            },
+            Sem::Record(fields) => Record {
+                fields: fields
+                    .iter()
+                    .map(|(l, v)| (l.clone(), self.reify(v)))
+                    .collect(),
+            },
+            Sem::Data(c, args) => {
+                return args.iter().fold(var(c), |exp, arg| {
+                    Expr::new(EApp {
+                        exp: Box::new(exp),
+                        arg: Box::new(self.reify(arg)),
+                    })
+                })
+            }
+            Sem::Neutral(e) => return e.clone(),
+        };
+        Expr::new(expr)
+    }
+}
+
+impl Sem {
+    /** Whether the value is unknown, or made of unknowns */
+    fn is_neutral(&self) -> bool {
+        match self {
+            Sem::Neutral(_) => true,
+            Sem::Tuple(vals) | Sem::Data(_, vals) => vals.iter().any(Sem::is_neutral),
+            Sem::Record(fields) => fields.iter().any(|(_, v)| v.is_neutral()),
+            _ => false,
+        }
+    }
+}
+
+/** Matches `val` against `pat`, like [`eval`](crate::ast::interp) */
+fn matches(val: &Sem, pat: &RawPattern) -> Match {
+    let all = |pairs: Vec<(&Sem, &RawPattern)>| {
+        let mut binds = vec![];
+        for (v, p) in pairs {
+            match matches(v, p) {
+                Match::Bound(bs) => binds.extend(bs),
+                m => return m,
+            }
+        }
+        Match::Bound(binds)
+    };
+    match (val, pat) {
+        (_, RawPattern::Wildcard) => Match::Bound(vec![]),
+        (_, RawPattern::Binding(x)) => Match::Bound(vec![(x.name.clone(), val.clone())]),
+        (Sem::Con(c), RawPattern::Literal(lit)) if same_constant(c, lit) => Match::Bound(vec![]),
+        (Sem::Con(_), RawPattern::Literal(_)) => Match::Failed,
+        (Sem::Tuple(vals), RawPattern::Tuple(pats)) => {
+            all(vals.iter().zip(pats.iter().map(|p| &p.pat)).collect())
+        }
+        (Sem::Record(fields), RawPattern::Record(pats)) => {
+            let mut pairs = vec![];
+            for (l, p) in pats {
+                match fields.iter().find(|(f, _)| f.name == l.name) {
+                    Some((_, v)) => pairs.push((v, &p.pat)),
+                    None => return Match::Failed,
+                }
+            }
+            all(pairs)
+        }
+        (Sem::Data(c, vals), RawPattern::Ctor(ctor, pats)) => {
+            if *c != ctor.name || vals.len() != pats.len() {
+                return Match::Failed;
+            }
+            all(vals.iter().zip(pats.iter().map(|p| &p.pat)).collect())
+        }
+        (Sem::Neutral(_), _) => Match::Stuck,
+        _ => Match::Failed,
+    }
+}
+
+/** Structural equality of `v1` and `v2`, or `None` if it can't be decided from them, like for
+functions and unknowns */
+fn equal(v1: &Sem, v2: &Sem) -> Option<bool> {
+    let all = |vs1: &[Sem], vs2: &[Sem]| {
+        vs1.iter()
+            .zip(vs2)
+            .try_fold(true, |all, (v1, v2)| Some(all && equal(v1, v2)?))
+    };
+    match (v1, v2) {
+        (Sem::Con(c1), Sem::Con(c2)) => Some(same_constant(c1, c2)),
+        (Sem::Tuple(vs1), Sem::Tuple(vs2)) => all(vs1, vs2),
+        // Fields may come in any order
+        (Sem::Record(fs1), Sem::Record(fs2)) => {
+            let mut vs1 = vec![];
+            let mut vs2 = vec![];
+            for (l, v1) in fs1 {
+                let (_, v2) = fs2.iter().find(|(l2, _)| l2.name == l.name)?;
+                vs1.push(v1.clone());
+                vs2.push(v2.clone());
+            }
+            all(&vs1, &vs2)
+        }
+        // Different constructors differ whatever their arguments
+        (Sem::Data(c1, _), Sem::Data(c2, _)) if c1 != c2 => Some(false),
+        (Sem::Data(_, vs1), Sem::Data(_, vs2)) => all(vs1, vs2),
+        _ => None,
+    }
+}
+
+/** Adds the names of the variables of `expr`, free or bound, to `names` */
+fn collect_names(expr: &Expr, names: &mut HashSet<String>) {
+    use RawExpr::*;
+    match &expr.expr {
+        Var { id } => {
+            names.insert(id.clone());
+        }
+        Lambda { arg: (x, _), .. } => {
+            names.insert(x.name.clone());
+        }
+        Fix { funcs, .. } => {
+            for (f, v, ..) in funcs {
+                names.insert(f.name.clone());
+                names.insert(v.name.clone());
+            }
+        }
+        Let { pat, .. } | Handle { pat, .. } => {
+            names.extend(pat.pat.bindings().into_iter().map(String::from))
+        }
+        Case { arms, .. } => {
+            for (pat, _) in arms {
+                names.extend(pat.pat.bindings().into_iter().map(String::from))
+            }
+        }
+        Unpack { var, .. } => {
+            names.insert(var.name.clone());
+        }
+        _ => (),
+    }
+    for sub in expr.expr.subexprs() {
+        collect_names(sub, names)
+    }
+}
+
+fn var(id: &str) -> Expr {
+    Expr::new(RawExpr::Var { id: id.to_string() })
+}
+
+fn pattern(pat: RawPattern) -> Pattern {
+    Pattern { pat, span: None }
+}
+
+fn ident(name: &str) -> Ident {
+    Ident {
+        name: name.to_string(),
+        span: None,
+    }
+}
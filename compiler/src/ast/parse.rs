@@ -1,38 +1,218 @@
-lalrpop_mod!(pub parser, "/ast/parser.rs"); // synthesized by LALRPOP
+lalrpop_mod!(#[allow(clippy::all)] pub parser, "/ast/parser.rs"); // synthesized by LALRPOP
 use super::{ast, error::ParseError, lex::LexerWrap};
 
 pub mod utils {
     use crate::ast::ast::*;
+    use crate::ast::class::dictionary_param;
+
+    /// Items that can appear at the top level of a program
+    pub enum TopLevel {
+        Decl(Box<Decl>),
+        Alias(TypeAlias),
+        Data(DataDecl),
+        Exn(ExnDecl),
+        Class(ClassDecl),
+        Instance(InstanceDecl),
+        Signature(SigDecl),
+        Structure(StructDecl),
+    }
 
     pub fn make_binop(l: Expr, op: &str, r: Expr) -> RawExpr {
         RawExpr::Binop {
             op: Binary::of_str(op),
             lhs: Box::new(l),
             rhs: Box::new(r),
+            width: Width::Int,
+        }
+    }
+
+    /// `Cons head tail`, every part of the application spanning `span`
+    pub fn make_cons(head: Expr, tail: Expr, span: Span) -> Expr {
+        let at = |expr| Expr {
+            expr,
+            span: Some(span),
+        };
+        let ctor = at(RawExpr::Var {
+            id: CONS.to_owned(),
+        });
+        let partial = at(RawExpr::EApp {
+            exp: Box::new(ctor),
+            arg: Box::new(head),
+        });
+        at(RawExpr::EApp {
+            exp: Box::new(partial),
+            arg: Box::new(tail),
+        })
+    }
+
+    /// The list literal `[entries]` spanning `(l, r)`, ex. `[1, 2]` is `Cons 1 (Cons 2 Nil)`.
+    /// Each tail spans from its first entry, and `Nil` spans the closing bracket
+    pub fn make_list(entries: Vec<Expr>, (l, r): Span) -> Expr {
+        let nil = Expr {
+            expr: RawExpr::Var { id: NIL.to_owned() },
+            span: Some((r - 1, r)),
+        };
+        entries
+            .into_iter()
+            .enumerate()
+            .rev()
+            .fold(nil, |tail, (i, head)| {
+                let start = if i == 0 { l } else { head.span.unwrap().0 };
+                make_cons(head, tail, (start, r))
+            })
+    }
+
+    /// The pattern `Cons head tail` spanning `span`
+    pub fn make_cons_pat(head: Pattern, tail: Pattern, span: Span) -> Pattern {
+        let ctor = Ident {
+            name: CONS.to_owned(),
+            span: Some(span),
+        };
+        Pattern {
+            pat: RawPattern::Ctor(ctor, vec![head, tail]),
+            span: Some(span),
+        }
+    }
+
+    /// The list pattern `[pats]` spanning `(l, r)`, spanned like [`make_list`]
+    pub fn make_list_pat(pats: Vec<Pattern>, (l, r): Span) -> Pattern {
+        let nil = Pattern {
+            pat: RawPattern::Ctor(
+                Ident {
+                    name: NIL.to_owned(),
+                    span: Some((r - 1, r)),
+                },
+                vec![],
+            ),
+            span: Some((r - 1, r)),
+        };
+        pats.into_iter()
+            .enumerate()
+            .rev()
+            .fold(nil, |tail, (i, head)| {
+                let start = if i == 0 { l } else { head.span.unwrap().0 };
+                make_cons_pat(head, tail, (start, r))
+            })
+    }
+
+    /// The type of the dictionaries of `class` for the type variable `tvar`, ex. `Eq A`
+    fn dictionary(tvar: &Ident, class: &Ident) -> Type {
+        let arg = Type {
+            typ: RawType::TVar(tvar.name.clone()),
+            span: tvar.span,
+        };
+        Type {
+            typ: RawType::Data(class.name.clone(), vec![arg]),
+            span: class.span,
+        }
+    }
+
+    /// The type abstraction `Λ (arg: classes). body` spanning `span`, taking the dictionaries
+    /// of `classes` for `arg` after the type, ex. `Λ (A: Eq). e` is `Λ A. λ #Eq#A: Eq A. e`
+    pub fn make_any(arg: Ident, kind: Kind, classes: Vec<Ident>, body: Expr, span: Span) -> Expr {
+        let at = |expr| Expr {
+            expr,
+            span: Some(span),
+        };
+        let body = classes.iter().rev().fold(body, |body, class| {
+            let param = Ident {
+                name: dictionary_param(&class.name, &arg.name),
+                span: class.span,
+            };
+            at(RawExpr::Lambda {
+                arg: (param, dictionary(&arg, class)),
+                body: Box::new(body),
+            })
+        });
+        at(RawExpr::Any {
+            arg,
+            kind,
+            body: Box::new(body),
+        })
+    }
+
+    /// The universal type `∀ (arg: classes). body` spanning `span`, of type abstractions made
+    /// by [`make_any`], ex. `∀ (A: Eq). T` is `∀ A. Eq A -> T`
+    pub fn make_forall(
+        arg: Ident,
+        kind: Kind,
+        classes: Vec<Ident>,
+        body: Type,
+        span: Span,
+    ) -> Type {
+        let body = classes.iter().rev().fold(body, |body, class| Type {
+            typ: RawType::Arrow(Box::new(dictionary(&arg, class)), Box::new(body)),
+            span: Some(span),
+        });
+        Type {
+            typ: RawType::Forall(arg, kind, Box::new(body)),
+            span: Some(span),
         }
     }
 }
 
 /// Parses a value expression
-pub fn parse_expr(input: &str) -> Result<ast::Expr, ParseError> {
+pub fn parse_expr(input: &str) -> Result<ast::Expr, ParseError<'_>> {
     let lexer = LexerWrap::new(input);
     parser::ValExprParser::new().parse(lexer)
 }
 
 /// Parses a type expression
-pub fn parse_type(input: &str) -> Result<ast::Type, ParseError> {
+pub fn parse_type(input: &str) -> Result<ast::Type, ParseError<'_>> {
     let lexer = LexerWrap::new(input);
     parser::TypExprParser::new().parse(lexer)
 }
 
 /// Parses a function declaration
-pub fn parse_decl(input: &str) -> Result<ast::Decl, ParseError> {
+pub fn parse_decl(input: &str) -> Result<ast::Decl, ParseError<'_>> {
     let lexer = LexerWrap::new(input);
     parser::DeclParser::new().parse(lexer)
 }
 
+/// Parses a type alias declaration
+pub fn parse_alias(input: &str) -> Result<ast::TypeAlias, ParseError<'_>> {
+    let lexer = LexerWrap::new(input);
+    parser::AliasParser::new().parse(lexer)
+}
+
+/// Parses an algebraic data type declaration
+pub fn parse_data(input: &str) -> Result<ast::DataDecl, ParseError<'_>> {
+    let lexer = LexerWrap::new(input);
+    parser::DataParser::new().parse(lexer)
+}
+
+/// Parses an exception declaration
+pub fn parse_exn(input: &str) -> Result<ast::ExnDecl, ParseError<'_>> {
+    let lexer = LexerWrap::new(input);
+    parser::ExnParser::new().parse(lexer)
+}
+
+/// Parses a type class declaration
+pub fn parse_class(input: &str) -> Result<ast::ClassDecl, ParseError<'_>> {
+    let lexer = LexerWrap::new(input);
+    parser::ClassParser::new().parse(lexer)
+}
+
+/// Parses an instance of a type class
+pub fn parse_instance(input: &str) -> Result<ast::InstanceDecl, ParseError<'_>> {
+    let lexer = LexerWrap::new(input);
+    parser::InstanceParser::new().parse(lexer)
+}
+
+/// Parses a module signature
+pub fn parse_signature(input: &str) -> Result<ast::SigDecl, ParseError<'_>> {
+    let lexer = LexerWrap::new(input);
+    parser::SignatureParser::new().parse(lexer)
+}
+
+/// Parses a structure
+pub fn parse_structure(input: &str) -> Result<ast::StructDecl, ParseError<'_>> {
+    let lexer = LexerWrap::new(input);
+    parser::StructureParser::new().parse(lexer)
+}
+
 /// Parses a source file
-pub fn parse_prog(input: &str) -> Result<ast::Prog, ParseError> {
+pub fn parse_prog(input: &str) -> Result<ast::Prog, ParseError<'_>> {
     let lexer = LexerWrap::new(input);
     parser::ProgParser::new().parse(lexer)
 }
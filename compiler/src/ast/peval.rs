@@ -0,0 +1,58 @@
+/*! Partial evaluation of top-level declarations at compile time, on programs type checked and
+lowered. The body of each declaration, with the values of those before it substituted, is reduced
+by [`trace`] with a limited fuel. Declarations reducing to closed values of data, constants,
+tuples, records and constructors applied to them, are replaced by these values. Evaluation is stuck
+on references, arrays and builtins, on a [pure](Heap::pure) heap, so only pure declarations reduce,
+and those raising an exception, or running out of fuel, are kept as they are. Those the [effect
+analysis](crate::ir::effects) finds reading, writing or allocating aren't even tried. Functions
+aren't substituted, nor evaluated to, so that code isn't duplicated. */
+
+use crate::ast::ast::{Expr, Prog, RawExpr};
+use crate::ast::step::{is_value, substituted, trace, Heap};
+
+use std::collections::HashSet;
+
+/// The number of reductions each declaration is evaluated for at most
+pub const FUEL: usize = 10_000;
+
+/** Partially evaluates the declarations of `prog`, each reduced `fuel` times at most, those
+`evaluable` is true of */
+pub fn peval(prog: &Prog, fuel: usize, evaluable: impl Fn(&str) -> bool) -> Prog {
+    let datatypes = prog.datatypes.iter().flat_map(|data| &data.ctors);
+    let mut ctors: HashSet<&str> = datatypes.map(|(ctor, _)| ctor.name.as_str()).collect();
+    ctors.extend(prog.exceptions.iter().map(|exn| exn.ctor.name.as_str()));
+    let mut evaluated = prog.clone();
+    // The declarations evaluated, by their values
+    let mut values: Vec<(String, Expr)> = vec![];
+    for id in prog.order.iter().filter(|id| evaluable(id)) {
+        let decl = evaluated.declarations.get_mut(id).unwrap();
+        let body = substituted(&decl.body, &values);
+        let steps = trace(&body, fuel, &mut Heap::pure());
+        let val = steps.last().unwrap_or(&body);
+        if !is_value(val) || !closed(val, &ctors) {
+            continue;
+        }
+        if !steps.is_empty() {
+            decl.body = Expr {
+                expr: val.expr.clone(),
+                span: decl.body.span,
+            }
+        }
+        values.push((id.clone(), val.clone()))
+    }
+    evaluated
+}
+
+/** Whether the value `val` is data without variables but the constructors `ctors` */
+fn closed(val: &Expr, ctors: &HashSet<&str>) -> bool {
+    use RawExpr::*;
+    match &val.expr {
+        Con { .. } => true,
+        Var { id } => ctors.contains(id.as_str()),
+        Tuple { entries } => entries.iter().all(|e| closed(e, ctors)),
+        Record { fields } => fields.iter().all(|(_, e)| closed(e, ctors)),
+        EApp { exp, arg } => closed(exp, ctors) && closed(arg, ctors),
+        TApp { exp, .. } => closed(exp, ctors),
+        _ => false,
+    }
+}
@@ -0,0 +1,191 @@
+/*! Printing of values and expressions within limits, for those too large to print whole. Parts
+nested deeper than the depth limit print as `…`, and so do the entries of tuples, records and lists
+past the width limit */
+
+use crate::ast::ast::{Expr, RawExpr, CONS, NIL};
+use crate::ast::interp::Value;
+
+use std::fmt::{self, Display, Formatter};
+
+const ELLIPSIS: &str = "…";
+
+/// How much of a value or expression is printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Nesting depth from which composite values and expressions are elided
+    pub depth: usize,
+    /// Number of entries of a tuple, record or list from which the others are elided
+    pub width: usize,
+}
+
+impl Limits {
+    /// Limits printing everything
+    pub const NONE: Limits = Limits {
+        depth: usize::MAX,
+        width: usize::MAX,
+    };
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            depth: 10,
+            width: 20,
+        }
+    }
+}
+
+/// A value printed within limits
+pub struct Pretty<'a> {
+    val: &'a Value,
+    limits: Limits,
+    /// How deep `val` is nested in the value printed
+    depth: usize,
+}
+
+impl<'a> Pretty<'a> {
+    pub fn new(val: &'a Value, limits: Limits) -> Self {
+        Pretty {
+            val,
+            limits,
+            depth: 0,
+        }
+    }
+
+    fn nested(&self, val: &'a Value) -> Self {
+        Pretty {
+            val,
+            limits: self.limits,
+            depth: self.depth + 1,
+        }
+    }
+
+    /** Prints the comma separated `entries` by `print`, up to the width limit */
+    fn entries<T>(
+        &self,
+        f: &mut Formatter<'_>,
+        entries: &[T],
+        print: impl Fn(&mut Formatter<'_>, &T) -> fmt::Result,
+    ) -> fmt::Result {
+        for (i, entry) in entries.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?
+            }
+            if i == self.limits.width {
+                return write!(f, "{ELLIPSIS}");
+            }
+            print(f, entry)?
+        }
+        Ok(())
+    }
+}
+
+impl Display for Pretty<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let composite = match self.val {
+            Value::VTuple(_) | Value::VRecord(_) => true,
+            Value::VData(_, vals) => !vals.is_empty(),
+            _ => false,
+        };
+        if composite && self.depth >= self.limits.depth {
+            return write!(f, "{ELLIPSIS}");
+        }
+        // Lists print like their literals
+        if let Some(items) = list_items(self.val) {
+            write!(f, "[")?;
+            self.entries(f, &items, |f, val| write!(f, "{}", self.nested(val)))?;
+            return write!(f, "]");
+        }
+        match self.val {
+            Value::VConst(c) => write!(f, "{}", c),
+            Value::VTuple(vals) => {
+                write!(f, "(")?;
+                self.entries(f, vals, |f, val| write!(f, "{}", self.nested(val)))?;
+                write!(f, ")")
+            }
+            Value::VData(c, vals) => {
+                write!(f, "{}", c)?;
+                for val in vals.iter() {
+                    match val {
+                        Value::VData(_, args) if !args.is_empty() && list_items(val).is_none() => {
+                            write!(f, " ({})", self.nested(val))?
+                        }
+                        _ => write!(f, " {}", self.nested(val))?,
+                    }
+                }
+                Ok(())
+            }
+            Value::VRecord(fields) => {
+                write!(f, "{{")?;
+                self.entries(f, fields, |f, (l, val)| {
+                    write!(f, "{} = {}", l, self.nested(val))
+                })?;
+                write!(f, "}}")
+            }
+            Value::VClosure { .. } | Value::VAny { .. } | Value::VCtor(..) | Value::VBuiltin(_) => {
+                write!(f, "<closure>")
+            }
+            Value::VRef(_) => write!(f, "<ref>"),
+            Value::VArray(..) => write!(f, "<array>"),
+            Value::VThunk(..) => write!(f, "<thunk>"),
+            Value::VFuture(..) => write!(f, "<future>"),
+        }
+    }
+}
+
+/** The elements of `val` if it's a list built by `Cons` and `Nil` */
+fn list_items(val: &Value) -> Option<Vec<&Value>> {
+    let mut items = vec![];
+    let mut tail = val;
+    loop {
+        match tail {
+            Value::VData(c, vs) if c == NIL && vs.is_empty() => return Some(items),
+            Value::VData(c, vs) if c == CONS && vs.len() == 2 => {
+                items.push(&vs[0]);
+                tail = &vs[1]
+            }
+            _ => return None,
+        }
+    }
+}
+
+/** `expr` with its subexpressions nested as deep as the depth limit replaced by `…`, and the
+entries of its tuples past the width limit by a single `…` */
+pub fn elide(expr: &Expr, limits: Limits) -> Expr {
+    let mut expr = expr.clone();
+    elide_from(&mut expr, limits, 0);
+    expr
+}
+
+fn elide_from(expr: &mut Expr, limits: Limits, depth: usize) {
+    let ellipsis = || {
+        Expr::new(RawExpr::Var {
+            id: ELLIPSIS.to_string(),
+        })
+    };
+    match &mut expr.expr {
+        RawExpr::Con { .. } | RawExpr::Var { .. } => return,
+        _ if depth >= limits.depth => {
+            *expr = ellipsis();
+            return;
+        }
+        RawExpr::Tuple { entries } if entries.len() > limits.width => {
+            entries.truncate(limits.width);
+            entries.push(ellipsis())
+        }
+        // Functions are as deep as their applications, ex. constructors with their arguments
+        RawExpr::EApp { exp, arg } => {
+            elide_from(exp, limits, depth);
+            elide_from(arg, limits, depth + 1);
+            return;
+        }
+        RawExpr::TApp { exp, .. } => {
+            elide_from(exp, limits, depth);
+            return;
+        }
+        _ => (),
+    }
+    for sub in expr.subexprs_mut() {
+        elide_from(sub, limits, depth + 1)
+    }
+}
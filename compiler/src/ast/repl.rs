@@ -1,20 +1,70 @@
 use rustyline::error::ReadlineError;
 use rustyline::{DefaultEditor, Result};
 
-use super::error::TypeError;
-use super::interp::{eval_decl, eval_expr, Environment};
-use super::parse::{parse_decl, parse_expr};
-use super::semant::Context;
+use super::ast::{DataDecl, Expr};
+use super::coverage::cover;
+use super::debug::{Breakpoint, Debugger, Pause};
+use super::derive::derived_decls;
+use super::error::{Note, TypeError};
+use super::interp::{
+    builtin_environment, eval_datatype, eval_decl, eval_exception, eval_expr, trace_expr,
+    Environment, EvalError, RuntimeError, Store, Strategy,
+};
+use super::lower::Newtypes;
+use super::parse::{parse_alias, parse_data, parse_decl, parse_exn, parse_expr, parse_prog};
+use super::pretty::{elide, Limits, Pretty};
+use super::semant::{
+    builtin_context, define_alias, expand_decl, expand_expr, fold, prelude, well_formed_decl,
+    well_formed_expr, Aliases, Context,
+};
+use crate::codegen::riscv::{self, Options};
+use crate::ir::dot::{self, Graph};
+use crate::ir::pass::{Ir, PassManager};
 
-pub fn repl() -> Result<()> {
+use std::str::FromStr;
+
+/// What compiling prints instead of the program in SSA
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Emit {
+    Dot(Graph),
+    /// RV32I assembly, once the representation is decided, compiled with those options
+    Asm(Options),
+}
+
+impl FromStr for Emit {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "asm" => Ok(Emit::Asm(Options::default())),
+            s => s.parse().map(Emit::Dot),
+        }
+    }
+}
+
+/** Runs the REPL, evaluating by `strategy` and printing values and expressions within `limits`.
+With `trace`, the value of each expression is preceded by the `trace` first steps it reduces by */
+pub fn repl(trace: Option<usize>, strategy: Strategy, limits: Limits) -> Result<()> {
     // `()` can be used when no completer is required
     let mut rl = DefaultEditor::new()?;
     #[cfg(feature = "with-file-history")]
     if rl.load_history("history.txt").is_err() {
         println!("No previous history.");
     }
-    let mut env = Environment::default();
-    let mut ctxt = Context::default();
+    let mut env = builtin_environment();
+    let mut store = Store::default();
+    let mut ctxt = builtin_context();
+    let mut aliases = Aliases::default();
+    let mut newtypes = Newtypes::default();
+    // Data types defined so far, latest last, for the functions derived by later ones
+    let mut datatypes: Vec<DataDecl> = vec![];
+    let prelude = prelude();
+    for data in &prelude.datatypes {
+        eval_datatype(data, &mut ctxt, &mut aliases, &mut env, &mut newtypes).unwrap()
+    }
+    for exn in &prelude.exceptions {
+        eval_exception(exn, &mut ctxt, &aliases, &mut env).unwrap()
+    }
     println!("Welcome to the polylamb interpreter!");
     println!("Type \"#help\" to display the help message\n");
     loop {
@@ -29,20 +79,85 @@ pub fn repl() -> Result<()> {
                             println!("See ya!");
                             break;
                         }
-                        "#env" => print_env(&env, &ctxt),
+                        "#env" => print_env(&env, &ctxt, &aliases),
                         _ => println!("Unknown command"),
                     }
+                } else if let Ok(alias) = parse_alias(input) {
+                    if let Err(err) = define_alias(&alias, &mut aliases) {
+                        display_type_error(input, err)
+                    }
+                } else if let Ok(data) = parse_data(input) {
+                    let result =
+                        eval_datatype(&data, &mut ctxt, &mut aliases, &mut env, &mut newtypes)
+                            .and_then(|()| {
+                                derived_decls(&data, |class, id| {
+                                    let data = datatypes.iter().rev().find(|d| d.id == id)?;
+                                    let derives = data.deriving.iter().any(|c| c.name == class);
+                                    derives.then_some(data)
+                                })
+                            })
+                            .map_err(EvalError::from)
+                            .and_then(|decls| {
+                                for decl in decls {
+                                    let decl = expand_decl(&decl, &aliases);
+                                    eval_decl(
+                                        &decl, &mut ctxt, &mut env, &newtypes, &mut store, strategy,
+                                    )?;
+                                }
+                                Ok(())
+                            });
+                    match result {
+                        Ok(()) => datatypes.push(data),
+                        Err(err) => display_eval_error(input, err),
+                    }
+                } else if let Ok(exn) = parse_exn(input) {
+                    if let Err(err) = eval_exception(&exn, &mut ctxt, &aliases, &mut env) {
+                        display_type_error(input, err)
+                    }
                 } else {
                     match parse_decl(input) {
-                        Ok(decl) => match eval_decl(&decl, &mut ctxt, &mut env) {
-                            Ok(_) => (),
-                            Err(err) => display_type_error(input, err),
-                        },
+                        Ok(decl) => {
+                            let result = well_formed_decl(&decl, &aliases)
+                                .map_err(EvalError::from)
+                                .and_then(|()| {
+                                    let decl = expand_decl(&decl, &aliases);
+                                    eval_decl(
+                                        &decl, &mut ctxt, &mut env, &newtypes, &mut store, strategy,
+                                    )
+                                });
+                            match result {
+                                Ok(notes) => {
+                                    for note in notes {
+                                        display_note(input, note)
+                                    }
+                                }
+                                Err(err) => display_eval_error(input, err),
+                            }
+                        }
                         Err(_) => match parse_expr(input) {
-                            Ok(expr) => match eval_expr(&expr, &mut ctxt, &mut env) {
-                                Ok(closure) => println!("{}", closure),
-                                Err(err) => display_type_error(input, err),
-                            },
+                            Ok(mut expr) => {
+                                let names = aliases.keys().cloned().collect();
+                                let result = well_formed_expr(&expr, &names)
+                                    .map_err(EvalError::from)
+                                    .and_then(|()| {
+                                        expand_expr(&mut expr, &aliases);
+                                        eval_expr(
+                                            &expr, &ctxt, &env, &newtypes, &mut store, strategy,
+                                        )
+                                    });
+                                match result {
+                                    Ok((closure, notes)) => {
+                                        for note in notes {
+                                            display_note(input, note)
+                                        }
+                                        if let Some(limit) = trace {
+                                            print_trace(&expr, &ctxt, &newtypes, limit, limits)
+                                        }
+                                        println!("{}", Pretty::new(&closure, limits))
+                                    }
+                                    Err(err) => display_eval_error(input, err),
+                                }
+                            }
                             Err(parse_err) => println!("{}", parse_err),
                         },
                     }
@@ -67,9 +182,212 @@ pub fn repl() -> Result<()> {
     Ok(())
 }
 
-fn print_env(env: &Environment, ctxt: &Context) {
-    for (k, v) in ctxt {
-        println!("{} : {} := {}", k, v, env[k])
+/** Runs the debugger on the program `source`, from its last declaration, printing expressions
+within `limits` */
+pub fn debug(source: &str, limits: Limits) -> Result<()> {
+    let prog = match parse_prog(source) {
+        Ok(prog) => prog,
+        Err(parse_err) => {
+            println!("{}", parse_err);
+            return Ok(());
+        }
+    };
+    let Some(entry) = prog.order.last() else {
+        println!("No declaration to debug");
+        return Ok(());
+    };
+    let mut debugger = match Debugger::new(&prog, source, entry, Store::default()) {
+        Ok(debugger) => debugger,
+        Err(err) => {
+            display_type_error(source, err);
+            return Ok(());
+        }
+    };
+    let mut rl = DefaultEditor::new()?;
+    println!("Debugging {}", entry);
+    println!("Type \"help\" to display the help message\n");
+    print_position(&debugger, limits);
+    loop {
+        let input = match rl.readline("(debug) ") {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                break;
+            }
+        };
+        rl.add_history_entry(input.as_str())?;
+        let mut words = input.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("s" | "step"), None) => match debugger.step() {
+                Pause::Done => print_done(&debugger, limits),
+                _ => print_position(&debugger, limits),
+            },
+            (Some("c" | "continue"), None) => match debugger.resume() {
+                Pause::Breakpoint(breakpoint) => {
+                    println!("Breakpoint at {}", breakpoint);
+                    print_position(&debugger, limits)
+                }
+                _ => print_done(&debugger, limits),
+            },
+            (Some("b" | "break"), Some(arg)) => match arg.parse::<Breakpoint>() {
+                Ok(breakpoint) => {
+                    println!("Breakpoint set at {}", breakpoint);
+                    debugger.breakpoints.push(breakpoint)
+                }
+                Err(()) => println!("Expected a declaration or a line number"),
+            },
+            (Some("e" | "env"), None) => {
+                for (id, typ) in debugger.env() {
+                    println!("{} : {}", id, typ)
+                }
+            }
+            (Some("p" | "print"), None) => print_position(&debugger, limits),
+            (Some("h" | "help"), None) => println!("{}", DEBUG_HELP_MESSAGE),
+            (Some("q" | "quit"), None) => break,
+            (None, _) => (),
+            _ => println!("Unknown command"),
+        }
+    }
+    Ok(())
+}
+
+/** Evaluates the program `source` read from `path`, then prints its coverage, as an lcov
+tracefile if `lcov`. What the program prints goes to standard error, leaving the report alone on
+standard output */
+pub fn coverage(source: &str, path: &str, lcov: bool) {
+    let prog = match parse_prog(source) {
+        Ok(prog) => prog,
+        Err(parse_err) => return println!("{}", parse_err),
+    };
+    let mut store = Store::new(std::io::stdin().lock(), std::io::stderr());
+    let (coverage, outcome) = cover(&prog, source, &mut store);
+    if let Err(err) = outcome {
+        display_eval_error(source, err)
+    }
+    if lcov {
+        print!("{}", coverage.lcov(path))
+    } else {
+        print!("{}", coverage.annotated(source));
+        let (covered, total) = coverage.covered();
+        println!("Covered {} of {} expressions", covered, total)
+    }
+}
+
+/** Compiles the program `source` by the passes of `manager` and prints it, or the first pass to
+break an invariant of the IR if it verifies them. With `graph`, prints that graph of the program
+for Graphviz instead, its syntax tree as parsed or its graphs once compiled */
+pub fn compile(source: &str, manager: &mut PassManager, emit: Option<Emit>) {
+    let prog = match parse_prog(source) {
+        Ok(prog) => prog,
+        Err(parse_err) => return println!("{}", parse_err),
+    };
+    if emit == Some(Emit::Dot(Graph::Ast)) {
+        return print!("{}", dot::ast(&prog));
+    }
+    let prog = match manager.lower(&prog) {
+        Ok(prog) => prog,
+        Err(err) => return display_type_error(source, err),
+    };
+    if let Some(entry) = manager
+        .entry
+        .as_ref()
+        .filter(|entry| prog.decl(entry).is_none())
+    {
+        return println!("No declaration is named {}", entry);
+    }
+    let represented = manager.names().contains(&"repr");
+    match (manager.run(prog), emit) {
+        (Ok(ir), None) => print!("{}", ir),
+        (Ok(Ir::Ssa(prog)), Some(Emit::Dot(Graph::Cfg))) => print!("{}", dot::cfg(&prog)),
+        (Ok(Ir::Ssa(prog)), Some(Emit::Dot(_))) => print!("{}", dot::callgraph(&prog)),
+        (Ok(Ir::Ssa(prog)), Some(Emit::Asm(options))) if represented => {
+            print!("{}", riscv::emit(&prog, options))
+        }
+        (Ok(_), Some(Emit::Asm(_))) => println!("Assembly is of programs after the repr pass"),
+        (Ok(Ir::Anf(_)), Some(_)) => println!("Graphs are of programs in SSA, after the ssa pass"),
+        (Err(err), _) => println!("{}", err),
+    }
+}
+
+fn print_position(debugger: &Debugger, limits: Limits) {
+    let expr = elide(debugger.expr(), limits);
+    match debugger.line() {
+        Some(line) => println!("[line {}] {}", line, expr),
+        None => println!("{}", expr),
+    }
+}
+
+fn print_done(debugger: &Debugger, limits: Limits) {
+    let expr = elide(debugger.expr(), limits);
+    println!("Done after {} steps: {}", debugger.steps(), expr)
+}
+
+fn print_env(env: &Environment, ctxt: &Context, aliases: &Aliases) {
+    let mut ids: Vec<_> = ctxt.keys().collect();
+    ids.sort();
+    for k in ids {
+        let v = &ctxt[k];
+        println!("{} : {} := {}", k, fold(v, aliases), env[k])
+    }
+}
+
+fn print_trace(expr: &Expr, ctxt: &Context, newtypes: &Newtypes, limit: usize, limits: Limits) {
+    // Already type checked and evaluated, its output printed and its input read
+    let store = Store::new(std::io::empty(), std::io::sink());
+    if let Ok(steps) = trace_expr(expr, ctxt, newtypes, limit, store) {
+        for step in steps {
+            println!("  ⟶ {}", elide(&step, limits))
+        }
+    }
+}
+
+fn display_eval_error(source: &str, err: EvalError) {
+    match err {
+        EvalError::Type(err) => display_type_error(source, err),
+        EvalError::Uncaught(exn) => println!("Uncaught exception: {}", exn),
+        EvalError::Runtime(err) => display_runtime_error(source, err),
+    }
+}
+
+fn display_runtime_error(source: &str, err: RuntimeError) {
+    use annotate_snippets::display_list::DisplayList;
+    use annotate_snippets::display_list::FormatOptions;
+    use annotate_snippets::snippet::*;
+    let label = err.to_string();
+    let snippet = Snippet {
+        title: Some(Annotation {
+            id: None,
+            label: Some(err.title()),
+            annotation_type: AnnotationType::Error,
+        }),
+        footer: vec![],
+        slices: vec![Slice {
+            source,
+            line_start: 1,
+            origin: None,
+            // Expressions made up by elaboration have no span to point at
+            annotations: err
+                .span()
+                .map(|range| SourceAnnotation {
+                    range,
+                    label: &label,
+                    annotation_type: AnnotationType::Error,
+                })
+                .into_iter()
+                .collect(),
+            fold: false,
+        }],
+        opt: FormatOptions {
+            color: true,
+            anonymized_line_numbers: false,
+            margin: None,
+        },
+    };
+    let dlist: DisplayList = snippet.into();
+    println!("{}", dlist);
+    if err.span().is_none() {
+        println!("{}", label)
     }
 }
 
@@ -85,7 +403,7 @@ fn display_type_error(source: &str, err: TypeError) {
         }),
         footer: vec![],
         slices: vec![Slice {
-            source: &source,
+            source,
             line_start: 1, // TODO
             origin: None,
             annotations: err.annotations,
@@ -101,8 +419,49 @@ fn display_type_error(source: &str, err: TypeError) {
     println!("{}", dlist)
 }
 
+fn display_note(source: &str, note: Note) {
+    use annotate_snippets::display_list::DisplayList;
+    use annotate_snippets::display_list::FormatOptions;
+    use annotate_snippets::snippet::*;
+    let snippet = Snippet {
+        title: Some(Annotation {
+            id: None,
+            label: Some(note.title),
+            annotation_type: note.annot_type,
+        }),
+        footer: vec![],
+        slices: vec![Slice {
+            source,
+            line_start: 1,
+            origin: None,
+            annotations: vec![SourceAnnotation {
+                range: note.range,
+                label: &note.label,
+                annotation_type: note.annot_type,
+            }],
+            fold: false,
+        }],
+        opt: FormatOptions {
+            color: true,
+            anonymized_line_numbers: false,
+            margin: None,
+        },
+    };
+    let dlist: DisplayList = snippet.into();
+    println!("{}", dlist)
+}
+
 const HELP_MESSAGE: &str = r#"
 #help - Displays this help message
 #exit - Terminates the repl
 #remove [id] - Removes [id] and its associated value from the context
 #env - Print the current context"#;
+
+const DEBUG_HELP_MESSAGE: &str = r#"
+step, s - Takes one step
+continue, c - Takes steps until a breakpoint or the end
+break, b [id|line] - Sets a breakpoint on the declaration [id] or the line [line]
+env, e - Prints the declarations and their types
+print, p - Prints the expression under evaluation
+help, h - Displays this help message
+quit, q - Terminates the debugger"#;
@@ -3,290 +3,1692 @@
 use std::iter::zip;
 
 use crate::ast::ast::{
-    Binary, Constant, Decl, Expr, Ident, Pattern, Prog, RawExpr, RawPattern, RawType, Type,
+    grow, Binary, Constant, DataDecl, Decl, ExnDecl, Expr, Ident, Kind, Pattern, Prog, RawExpr,
+    RawPattern, RawType, Span, Type, TypeAlias, Width, EXN,
 };
-use crate::ast::error::TypeError;
+use crate::ast::class::elaborate_classes;
+use crate::ast::derive::derive;
+use crate::ast::error::{Note, TypeError};
+use crate::ast::infer::elaborate_decl;
+use crate::ast::intern::{Interner, Name, Node, TypeId};
+use crate::ast::module::elaborate_modules;
+use crate::ast::parse::{parse_prog, parse_type};
 use annotate_snippets::snippet::{AnnotationType, SourceAnnotation};
 use im::hashmap::HashMap;
 use im::hashset::HashSet;
+use indexmap::IndexMap;
 
 /** Mapping of variable names to types. Latest element is most recent */
 pub type Context = HashMap<String, RawType>;
 
-/** Type-checks the expression `expr`.
+/** Mapping of type alias names to their fully expanded definitions, in the order they're defined */
+pub type Aliases = IndexMap<String, RawType>;
+
+/** Mapping of the type variables in scope to their kinds */
+pub type Kinds = HashMap<String, Kind>;
+
+/** Type-checks the expression `expr` in synthesis mode, working its type out bottom-up.
+Subterms with a known expected type are checked by [`check_against`] instead. The types met along
+the way are interned in an arena of the check, so that comparing them compares their ids.
 Returns: The raw type of the checked `expr`, or `TypeError`
 # Arguments
  * `expr`: The expression to check
  * `val_ctxt`: Snapshots of mapping from variable names to raw type.
- * `typ_vars`: Set of declared type variables */
-pub fn check_expr(
+ * `typ_vars`: Declared type variables and their kinds */
+pub fn check_expr(expr: &Expr, val_ctxt: &Context, typ_vars: &Kinds) -> Result<RawType, TypeError> {
+    let mut checker = Checker::new(val_ctxt);
+    let typ = checker.synth(expr, &Scope::new(typ_vars))?;
+    Ok(checker.types.raw(typ))
+}
+
+/** Type-checks the expression `expr` in checking mode, pushing the `expected` type inwards
+through lambdas, type abstractions, branches, and tuples. Lambda parameters may then leave out
+their annotations, and a mismatch is reported at the innermost subterm that doesn't check.
+Returns: `Ok` if `expr` has type `expected`, or `TypeError`
+# Arguments
+ * `expr`: The expression to check
+ * `expected`: The type `expr` must have
+ * `val_ctxt`: Snapshots of mapping from variable names to raw type.
+ * `typ_vars`: Declared type variables and their kinds */
+pub fn check_against(
     expr: &Expr,
+    expected: &RawType,
     val_ctxt: &Context,
-    typ_vars: &HashSet<String>,
-) -> Result<RawType, TypeError> {
-    use RawExpr::*;
-    use RawType::*;
-    match &expr.expr {
-        Con { val } => match val {
-            Constant::Integer(_) => Ok(Int),
-            Constant::Boolean(_) => Ok(Bool),
-            Constant::Null => Ok(Unit),
-        },
-        Var { id } => match val_ctxt.get(id.as_str()) {
-            Some(typ) => Ok(typ.clone()),
-            None => Err(TypeError {
-                title: "Unbound variable",
-                annot_type: AnnotationType::Error,
-                annotations: vec![SourceAnnotation {
-                    range: expr.span.unwrap(),
-                    label: "this variable hasn't been defined",
-                    annotation_type: AnnotationType::Error,
-                }],
-            }),
-        },
-        Let { pat, exp, body } => {
-            let exp_typ = check_expr(exp, val_ctxt, typ_vars)?;
-            let mut ctxt1 = val_ctxt.clone();
-            traverse_pat(&pat, &mut HashSet::new(), &mut ctxt1, &exp_typ)?;
-            check_expr(body, &ctxt1, typ_vars)
-        }
-        Fix { funcs, body } => {
-            let mut ctxt1 = val_ctxt.clone();
-            // Add the function signatures to context first
-            for (fun, _, typ, ret, _) in funcs {
-                let fun_typ = RawType::Arrow(Box::new(typ.clone()), Box::new(ret.clone()));
-                ctxt1.insert(fun.name.clone(), fun_typ);
-            }
-            // Now type check each function definition
-            for (_, var, typ, ret, def) in funcs {
-                let mut ctxt2 = ctxt1.clone();
-                ctxt2.insert(var.name.clone(), typ.typ.clone());
-                let checked_typ = check_expr(def, &ctxt2, typ_vars)?;
-                if !equivalent(&checked_typ, &ret) {
-                    return Err(TypeError {
-                        title: "Mismatched Types",
+    typ_vars: &Kinds,
+) -> Result<(), TypeError> {
+    let mut checker = Checker::new(val_ctxt);
+    let expected = checker.types.intern(expected);
+    checker.check(expr, expected, &Scope::new(typ_vars))
+}
+
+/** Type variables in scope, and variables bound within the expression being checked */
+#[derive(Clone)]
+struct Scope {
+    /// Types of the variables bound within the expression, shadowing those of the context
+    vals: HashMap<String, TypeId>,
+    /// Kinds of the type variables in scope, by the names they are written with
+    kinds: Kinds,
+    /// Names the type variables bound within the expression are interned by, each bound apart
+    names: HashMap<String, String>,
+}
+
+impl Scope {
+    fn new(kinds: &Kinds) -> Scope {
+        Scope {
+            vals: HashMap::new(),
+            kinds: kinds.clone(),
+            names: HashMap::new(),
+        }
+    }
+}
+
+/** State of checking one declaration or expression */
+struct Checker<'a> {
+    /// The arena of the types of the check, dropped with it
+    types: Interner,
+    /// The context of the check, its types interned the first time they are looked up
+    ctxt: &'a Context,
+    interned: HashMap<String, TypeId>,
+    /// The number of type variables bound so far
+    bound: usize,
+    int: TypeId,
+    bool: TypeId,
+    unit: TypeId,
+    exn: TypeId,
+}
+
+impl<'a> Checker<'a> {
+    fn new(ctxt: &'a Context) -> Checker<'a> {
+        let mut types = Interner::default();
+        Checker {
+            int: types.node(Node::Int),
+            bool: types.node(Node::Bool),
+            unit: types.node(Node::Unit),
+            exn: types.intern(&exn()),
+            types,
+            ctxt,
+            interned: HashMap::new(),
+            bound: 0,
+        }
+    }
+
+    /** The type of the variable `id`, or `None` if it's unbound */
+    fn lookup(&mut self, id: &str, scope: &Scope) -> Option<TypeId> {
+        if let Some(typ) = scope.vals.get(id).or_else(|| self.interned.get(id)) {
+            return Some(*typ);
+        }
+        let ctxt = self.ctxt;
+        let typ = self.types.intern(ctxt.get(id)?);
+        self.interned.insert(id.to_string(), typ);
+        Some(typ)
+    }
+
+    /** Interns the type `typ` written in `scope` */
+    fn annot(&mut self, typ: &RawType, scope: &Scope) -> TypeId {
+        let rename = |v: &str| scope.names.get(v).cloned().unwrap_or_else(|| v.to_string());
+        self.types.intern_renamed(typ, rename)
+    }
+
+    /** Binds the type variable `tvar` of kind `kind`. It's interned by a name of its own, so that
+    binding it again doesn't capture its occurrences in the types of the variables in scope.
+    Returns: The scope it is bound in, and the type variable interned */
+    fn bind_tvar(&mut self, tvar: &str, kind: &Kind, scope: &Scope) -> (Scope, String) {
+        let name = self.fresh(tvar);
+        let mut scope1 = scope.clone();
+        scope1.kinds.insert(tvar.to_string(), kind.clone());
+        scope1.names.insert(tvar.to_string(), name.clone());
+        (scope1, name)
+    }
+
+    /** A name for a type variable bound within the expression, named `tvar` in the source */
+    fn fresh(&mut self, tvar: &str) -> String {
+        self.bound += 1;
+        format!("{tvar}'{}", self.bound)
+    }
+
+    /** The type variable `tvar`, as interned */
+    fn tvar(&mut self, tvar: &str) -> TypeId {
+        self.types.node(Node::TVar(Name::Free(tvar.to_string())))
+    }
+
+    /** The interned type of the literal `c` */
+    fn constant(&mut self, c: &Constant) -> TypeId {
+        self.types.intern(&constant_type(c))
+    }
+
+    /** Type-checks `expr` in synthesis mode, see [`check_expr`] */
+    fn synth(&mut self, expr: &Expr, scope: &Scope) -> Result<TypeId, TypeError> {
+        grow(|| {
+            use RawExpr::*;
+            let (int, bool, unit) = (self.int, self.bool, self.unit);
+            match &expr.expr {
+                Con { val } if out_of_int_range(val) => Err(out_of_range(expr.span.unwrap())),
+                Con { val } => Ok(self.constant(val)),
+                Var { id } => match self.lookup(id, scope) {
+                    Some(typ) => Ok(typ),
+                    None => Err(TypeError {
+                        title: "Unbound variable",
+                        annot_type: AnnotationType::Error,
+                        annotations: vec![SourceAnnotation {
+                            range: expr.span.unwrap(),
+                            label: "this variable hasn't been defined",
+                            annotation_type: AnnotationType::Error,
+                        }],
+                    }),
+                },
+                Let { pat, exp, body } => {
+                    let scope1 = self.check_let(pat, exp, scope)?;
+                    self.synth(body, &scope1)
+                }
+                Fix { funcs, body } => {
+                    let scope1 = self.check_fix(funcs, scope)?;
+                    self.synth(body, &scope1)
+                }
+                EApp { exp, arg } => {
+                    let exp_t = self.synth(exp, scope)?;
+                    match *self.types.get(exp_t) {
+                        Node::Arrow(t1, t2) => {
+                            self.check(arg, t1, scope)?;
+                            Ok(t2)
+                        }
+                        _ => Err(TypeError {
+                            title: "Illegal application",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: exp.span.unwrap(),
+                                label: "cannot apply arguments to non-functions",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        }),
+                    }
+                }
+                TApp { exp, arg } => {
+                    unfilled_hole(arg, arg.span)?;
+                    let exp_t = self.synth(exp, scope)?;
+                    match self.types.get(exp_t).clone() {
+                        Node::Forall(kind, body) => {
+                            let at = arg.span.or(exp.span).unwrap();
+                            check_kind(arg, &kind, &scope.kinds, at)?;
+                            let arg = self.annot(arg, scope);
+                            Ok(self.types.open(body, arg))
+                        }
+                        _ => Err(TypeError {
+                            title: "Illegal type specialization",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: exp.span.unwrap(),
+                                label: "this expression doesn't have `∀` type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        }),
+                    }
+                }
+                Tuple { entries } => {
+                    let typs = entries
+                        .iter()
+                        .map(|e| self.synth(e, scope))
+                        .collect::<Result<Vec<TypeId>, TypeError>>()?;
+                    Ok(self.types.node(Node::Prod(typs)))
+                }
+                Binop {
+                    lhs,
+                    op: Binary::Eq | Binary::Ne,
+                    rhs,
+                    ..
+                } => {
+                    self.check_equality(lhs, rhs, scope)?;
+                    Ok(bool)
+                }
+                AssertEq { lhs, rhs } => {
+                    self.check_equality(lhs, rhs, scope)?;
+                    Ok(unit)
+                }
+                Binop {
+                    lhs,
+                    op: Binary::And | Binary::Or,
+                    rhs,
+                    ..
+                } => {
+                    self.check(lhs, bool, scope)?;
+                    self.check(rhs, bool, scope)?;
+                    Ok(bool)
+                }
+                Binop { lhs, op, rhs, .. } => {
+                    let (first, second) = match &lhs.expr {
+                        Con { val } if val.as_int().is_some() => (rhs, lhs),
+                        _ => (lhs, rhs),
+                    };
+                    let typ = self.synth(first, scope)?;
+                    if !matches!(self.types.get(typ), Node::Int | Node::Sized(_)) {
+                        return Err(TypeError {
+                            title: "Mismatched Types",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: first.span.unwrap(),
+                                label: "this expression doesn't have an integer type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        });
+                    }
+                    self.check(second, typ, scope)?;
+                    match op {
+                        Binary::Lt | Binary::Gt => Ok(bool),
+                        _ => Ok(typ),
+                    }
+                }
+                Lambda { arg, body } => {
+                    let (id, typ) = arg;
+                    unfilled_hole(typ, id.span)?;
+                    let typ = self.annot(typ, scope);
+                    let scope1 = self.bind_param(id, typ, scope)?;
+                    let body_typ = self.synth(body, &scope1)?;
+                    Ok(self.types.node(Node::Arrow(typ, body_typ)))
+                }
+                Any { arg, kind, body } => {
+                    let (scope1, tvar) = self.bind_tvar(&arg.name, kind, scope);
+                    let typ = self.synth(body, &scope1)?;
+                    let typ = self.types.close(&tvar, typ);
+                    Ok(self
+                        .types
+                        .binder(Node::Forall(kind.clone(), typ), &arg.name))
+                }
+                If {
+                    cond,
+                    branch_t,
+                    branch_f,
+                } => {
+                    // The false branch is checked against the type of the true branch
+                    self.check(cond, bool, scope)?;
+                    let t_typ = self.synth(branch_t, scope)?;
+                    self.check(branch_f, t_typ, scope)?;
+                    Ok(t_typ)
+                }
+                Case { exp, arms } => self.check_case(exp, arms, None, scope),
+                RawExpr::Record { fields } => {
+                    distinct_fields(fields.iter().map(|(l, _)| l))?;
+                    let mut typs = fields
+                        .iter()
+                        .map(|(l, e)| Ok((l.name.clone(), self.synth(e, scope)?)))
+                        .collect::<Result<Vec<(String, TypeId)>, TypeError>>()?;
+                    typs.sort_by(|(l1, _), (l2, _)| l1.cmp(l2));
+                    Ok(self.types.node(Node::Record(typs)))
+                }
+                Ascribe { exp, typ } => {
+                    unfilled_hole(typ, typ.span)?;
+                    let typ = self.annot(typ, scope);
+                    self.check(exp, typ, scope)?;
+                    Ok(typ)
+                }
+                Fold { typ, exp } => {
+                    unfilled_hole(typ, typ.span)?;
+                    let rec_typ = self.annot(typ, scope);
+                    let Node::Rec(body) = *self.types.get(rec_typ) else {
+                        return Err(TypeError {
+                            title: "Illegal fold",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: typ.span.unwrap(),
+                                label: "expected a recursive `μ` type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        });
+                    };
+                    let unrolled = self.types.open(body, rec_typ);
+                    self.check(exp, unrolled, scope)?;
+                    Ok(rec_typ)
+                }
+                Convert { exp, to, .. } => {
+                    let typ = self.synth(exp, scope)?;
+                    match self.types.get(typ) {
+                        Node::Int | Node::Sized(_) => Ok(self.types.intern(&to.typ())),
+                        _ => Err(illegal_conversion(exp.span.unwrap())),
+                    }
+                }
+                Unfold { exp } => {
+                    let typ = self.synth(exp, scope)?;
+                    match *self.types.get(typ) {
+                        Node::Rec(body) => Ok(self.types.open(body, typ)),
+                        _ => Err(TypeError {
+                            title: "Illegal unfold",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: exp.span.unwrap(),
+                                label: "this expression doesn't have a recursive `μ` type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        }),
+                    }
+                }
+                Pack { witness, exp, typ } => {
+                    unfilled_hole(witness, witness.span)?;
+                    unfilled_hole(typ, typ.span)?;
+                    let exists_typ = self.annot(typ, scope);
+                    let Node::Exists(body) = *self.types.get(exists_typ) else {
+                        return Err(TypeError {
+                            title: "Illegal pack",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: typ.span.unwrap(),
+                                label: "expected an existential `∃` type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        });
+                    };
+                    let witness = self.annot(witness, scope);
+                    let concrete = self.types.open(body, witness);
+                    self.check(exp, concrete, scope)?;
+                    Ok(exists_typ)
+                }
+                Unpack {
+                    tvar,
+                    var,
+                    exp,
+                    body,
+                } => {
+                    let typ = self.synth(exp, scope)?;
+                    let Node::Exists(hidden_in) = *self.types.get(typ) else {
+                        return Err(TypeError {
+                            title: "Illegal unpack",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: exp.span.unwrap(),
+                                label: "this expression doesn't have an existential `∃` type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        });
+                    };
+                    let (mut scope1, hidden) = self.bind_tvar(&tvar.name, &Kind::Star, scope);
+                    let witness = self.tvar(&hidden);
+                    let var_typ = self.types.open(hidden_in, witness);
+                    scope1.vals.insert(var.name.clone(), var_typ);
+                    let body_typ = self.synth(body, &scope1)?;
+                    if self.types.free_in(&hidden, body_typ) {
+                        return Err(TypeError {
+                            title: "Escaping type variable",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: tvar.span.unwrap(),
+                                label:
+                                    "the hidden type can't appear in the type of the unpack body",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        });
+                    }
+                    Ok(body_typ)
+                }
+                Open { exp, body } => {
+                    let mut typ = self.synth(exp, scope)?;
+                    let (mut scope1, mut hidden) = (scope.clone(), vec![]);
+                    while let Node::Exists(t) = *self.types.get(typ) {
+                        // The abstract types keep the names given by the signature, of any of the
+                        // types interned alike, except those of type variables in scope
+                        let hint = self.types.hint(typ).unwrap_or("T").to_string();
+                        let tvar = self.fresh(&hint);
+                        for name in self.types.hints(typ) {
+                            if !scope.kinds.contains_key(name) {
+                                scope1.kinds.insert(name.clone(), Kind::Star);
+                                scope1.names.insert(name.clone(), tvar.clone());
+                            }
+                        }
+                        let witness = self.tvar(&tvar);
+                        typ = self.types.open(t, witness);
+                        hidden.push(tvar)
+                    }
+                    let Node::Record(fields) = self.types.get(typ).clone() else {
+                        return Err(not_a_structure(exp));
+                    };
+                    for (l, t) in fields {
+                        if self.is_bound(&l, &scope1) {
+                            return Err(TypeError {
+                                title: "Redefinition of variables",
+                                annot_type: AnnotationType::Error,
+                                annotations: vec![SourceAnnotation {
+                                    range: exp.span.unwrap(),
+                                    label:
+                                        "this structure has a member named like a bound variable",
+                                    annotation_type: AnnotationType::Error,
+                                }],
+                            });
+                        }
+                        scope1.vals.insert(l, t);
+                    }
+                    let body_typ = self.synth(body, &scope1)?;
+                    if hidden.iter().any(|v| self.types.free_in(v, body_typ)) {
+                        return Err(TypeError {
+                        title: "Escaping type variable",
                         annot_type: AnnotationType::Error,
                         annotations: vec![SourceAnnotation {
-                            range: body.span.unwrap(),
-                            label: "fixpoint body's type doesn't match annotation",
+                            range: exp.span.unwrap(),
+                            label: "an abstract type of this structure can't appear in the type of the open body",
                             annotation_type: AnnotationType::Error,
                         }],
                     });
-                };
+                    }
+                    Ok(body_typ)
+                }
+                RawExpr::Proj { exp, field } => {
+                    let typ = self.synth(exp, scope)?;
+                    match self.types.get(typ) {
+                        Node::Record(fields) => match fields.iter().find(|(l, _)| *l == field.name)
+                        {
+                            Some((_, typ)) => Ok(*typ),
+                            None => Err(TypeError {
+                                title: "Missing record field",
+                                annot_type: AnnotationType::Error,
+                                annotations: vec![SourceAnnotation {
+                                    range: field.span.unwrap(),
+                                    label: "the record has no field with this name",
+                                    annotation_type: AnnotationType::Error,
+                                }],
+                            }),
+                        },
+                        _ => Err(TypeError {
+                            title: "Illegal projection",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: exp.span.unwrap(),
+                                label: "this expression isn't a record",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        }),
+                    }
+                }
+                RawExpr::Nth { exp, index } => {
+                    let typ = self.synth(exp, scope)?;
+                    match self.types.get(typ) {
+                        Node::Prod(typs) if *index < typs.len() => Ok(typs[*index]),
+                        Node::Prod(_) => Err(not_an_entry(exp, true)),
+                        _ => Err(not_an_entry(exp, false)),
+                    }
+                }
+                RawExpr::Ref { exp } => {
+                    let typ = self.synth(exp, scope)?;
+                    Ok(self.types.node(Node::Ref(typ)))
+                }
+                Deref { exp } => {
+                    let typ = self.synth(exp, scope)?;
+                    match *self.types.get(typ) {
+                        Node::Ref(typ) => Ok(typ),
+                        _ => Err(not_a_ref("Illegal dereference", exp)),
+                    }
+                }
+                Spawn { exp } => {
+                    let typ = self.synth(exp, scope)?;
+                    Ok(self.types.node(Node::Future(typ)))
+                }
+                Join { exp } => {
+                    let typ = self.synth(exp, scope)?;
+                    match *self.types.get(typ) {
+                        Node::Future(typ) => Ok(typ),
+                        _ => Err(not_a_future(exp)),
+                    }
+                }
+                Assign { lhs, rhs } => {
+                    let typ = self.synth(lhs, scope)?;
+                    match *self.types.get(typ) {
+                        Node::Ref(typ) => {
+                            self.check(rhs, typ, scope)?;
+                            Ok(unit)
+                        }
+                        _ => Err(not_a_ref("Illegal assignment", lhs)),
+                    }
+                }
+                RawExpr::Array { len, init } => {
+                    self.check(len, int, scope)?;
+                    let typ = self.synth(init, scope)?;
+                    Ok(self.types.node(Node::Array(typ)))
+                }
+                Sub { arr, idx } => {
+                    let typ = self.synth(arr, scope)?;
+                    match *self.types.get(typ) {
+                        Node::Array(typ) => {
+                            self.check(idx, int, scope)?;
+                            Ok(typ)
+                        }
+                        _ => Err(not_an_array("Illegal subscript", arr)),
+                    }
+                }
+                Update { arr, idx, val } => {
+                    let typ = self.synth(arr, scope)?;
+                    match *self.types.get(typ) {
+                        Node::Array(typ) => {
+                            self.check(idx, int, scope)?;
+                            self.check(val, typ, scope)?;
+                            Ok(unit)
+                        }
+                        _ => Err(not_an_array("Illegal update", arr)),
+                    }
+                }
+                Raise { exp, typ } => {
+                    unfilled_hole(typ, expr.span)?;
+                    self.check(exp, self.exn, scope)?;
+                    Ok(self.annot(typ, scope))
+                }
+                Handle { exp, pat, handler } => {
+                    let typ = self.synth(exp, scope)?;
+                    let scope1 = self.bind_handler(pat, scope)?;
+                    self.check(handler, typ, &scope1)?;
+                    Ok(typ)
+                }
+                // Inference replaces these by the dictionaries they stand for
+                Instance { .. } => Err(TypeError {
+                    title: "Unresolved instance",
+                    annot_type: AnnotationType::Error,
+                    annotations: vec![SourceAnnotation {
+                        range: expr.span.unwrap(),
+                        label: "the dictionary for this method hasn't been found",
+                        annotation_type: AnnotationType::Error,
+                    }],
+                }),
             }
-            check_expr(body, &ctxt1, typ_vars)
-        }
-        EApp { exp, arg } => {
-            let exp_t = check_expr(exp, val_ctxt, typ_vars)?;
-            let arg_t = check_expr(arg, val_ctxt, typ_vars)?;
-            match exp_t {
-                RawType::Arrow(t1, t2) => {
-                    if equivalent(&t1.typ, &arg_t) {
-                        Ok(t2.typ)
+        })
+    }
+
+    /** Type-checks `expr` against the type `expected` in checking mode, see [`check_against`] */
+    fn check(&mut self, expr: &Expr, expected: TypeId, scope: &Scope) -> Result<(), TypeError> {
+        grow(|| {
+            use RawExpr::*;
+            match (&expr.expr, self.types.get(expected).clone()) {
+                (
+                    Lambda {
+                        arg: (id, typ),
+                        body,
+                    },
+                    Node::Arrow(t1, t2),
+                ) => {
+                    if !typ.has_hole() && self.annot(typ, scope) != t1 {
+                        return Err(TypeError {
+                            title: "Mismatched Types",
+                            annot_type: AnnotationType::Error,
+                            annotations: vec![SourceAnnotation {
+                                range: typ.span.or(id.span).unwrap(),
+                                label:
+                                    "parameter annotation differs from the expected parameter type",
+                                annotation_type: AnnotationType::Error,
+                            }],
+                        });
+                    }
+                    let scope1 = self.bind_param(id, t1, scope)?;
+                    self.check(body, t2, &scope1)
+                }
+                (Any { arg, kind, body }, Node::Forall(k, t)) if *kind == k => {
+                    let (scope1, tvar) = self.bind_tvar(&arg.name, kind, scope);
+                    let witness = self.tvar(&tvar);
+                    let t = self.types.open(t, witness);
+                    self.check(body, t, &scope1)
+                }
+                (Let { pat, exp, body }, _) => {
+                    let scope1 = self.check_let(pat, exp, scope)?;
+                    self.check(body, expected, &scope1)
+                }
+                (Fix { funcs, body }, _) => {
+                    let scope1 = self.check_fix(funcs, scope)?;
+                    self.check(body, expected, &scope1)
+                }
+                (
+                    If {
+                        cond,
+                        branch_t,
+                        branch_f,
+                    },
+                    _,
+                ) => {
+                    self.check(cond, self.bool, scope)?;
+                    self.check(branch_t, expected, scope)?;
+                    self.check(branch_f, expected, scope)
+                }
+                (Case { exp, arms }, _) => {
+                    self.check_case(exp, arms, Some(expected), scope)?;
+                    Ok(())
+                }
+                // A raise without its type written has every type
+                (Raise { exp, typ }, _) if typ.has_hole() => self.check(exp, self.exn, scope),
+                (Handle { exp, pat, handler }, _) => {
+                    self.check(exp, expected, scope)?;
+                    let scope1 = self.bind_handler(pat, scope)?;
+                    self.check(handler, expected, &scope1)
+                }
+                (Tuple { entries }, Node::Prod(typs)) if entries.len() == typs.len() => {
+                    for (e, t) in zip(entries, typs) {
+                        self.check(e, t, scope)?
+                    }
+                    Ok(())
+                }
+                (Con { val }, Node::Sized(w)) if val.as_int().is_some() => match val.as_int() {
+                    Some(n) if w.fits(n) => Ok(()),
+                    _ => Err(out_of_range(expr.span.unwrap())),
+                },
+                (Binop { lhs, op, rhs, .. }, Node::Sized(_)) if op.is_arithmetic() => {
+                    self.check(lhs, expected, scope)?;
+                    self.check(rhs, expected, scope)
+                }
+                _ => {
+                    if self.synth(expr, scope)? == expected {
+                        Ok(())
                     } else {
                         Err(TypeError {
                             title: "Mismatched Types",
                             annot_type: AnnotationType::Error,
                             annotations: vec![SourceAnnotation {
-                                range: arg.span.unwrap(),
-                                label: "function argument has wrong type",
+                                range: expr.span.unwrap(),
+                                label: "this expression doesn't have the expected type",
                                 annotation_type: AnnotationType::Error,
                             }],
                         })
                     }
                 }
-                _ => Err(TypeError {
-                    title: "Illegal application",
+            }
+        })
+    }
+
+    /** Whether the variable `id` is bound in `scope` or in the context */
+    fn is_bound(&self, id: &str, scope: &Scope) -> bool {
+        scope.vals.contains_key(id) || self.ctxt.contains_key(id)
+    }
+
+    /** Binds the lambda parameter `id` of type `typ`, rejecting names already bound */
+    fn bind_param(&self, id: &Ident, typ: TypeId, scope: &Scope) -> Result<Scope, TypeError> {
+        if self.is_bound(&id.name, scope) {
+            return Err(TypeError {
+                title: "Redefinition of variables",
+                annot_type: AnnotationType::Error,
+                annotations: vec![SourceAnnotation {
+                    range: id.span.unwrap(),
+                    label: "attempting to declare a bound variable",
+                    annotation_type: AnnotationType::Error,
+                }],
+            });
+        }
+        let mut scope1 = scope.clone();
+        scope1.vals.insert(id.name.clone(), typ);
+        Ok(scope1)
+    }
+
+    /** Binds the variables of the handler pattern `pat`, which destructs exceptions.
+    Handlers needn't be exhaustive, the exceptions they don't match keep unwinding.
+    Returns: The scope of the handler */
+    fn bind_handler(&mut self, pat: &Pattern, scope: &Scope) -> Result<Scope, TypeError> {
+        let mut scope1 = scope.clone();
+        self.bind_pat(pat, &mut HashSet::new(), &mut scope1, self.exn)?;
+        Ok(scope1)
+    }
+
+    /** Checks that `lhs` and `rhs` have the same type, admitting equality */
+    fn check_equality(&mut self, lhs: &Expr, rhs: &Expr, scope: &Scope) -> Result<(), TypeError> {
+        // An integer literal on the left takes the type of the right operand
+        let (first, second) = match &lhs.expr {
+            RawExpr::Con { val } if val.as_int().is_some() => (rhs, lhs),
+            _ => (lhs, rhs),
+        };
+        let typ = self.synth(first, scope)?;
+        self.check(second, typ, scope)?;
+        if !admits_equality(&self.types.raw(typ), self.ctxt) {
+            return Err(TypeError {
+                title: "Illegal comparison",
+                annot_type: AnnotationType::Error,
+                annotations: vec![SourceAnnotation {
+                    range: lhs.span.unwrap(),
+                    label: "values of this type can't be compared for equality",
+                    annotation_type: AnnotationType::Error,
+                }],
+            });
+        }
+        Ok(())
+    }
+
+    /** Binds `pat` to the type of `exp`, rejecting patterns that don't match every value.
+    Returns: The scope of the body of the `let` */
+    fn check_let(&mut self, pat: &Pattern, exp: &Expr, scope: &Scope) -> Result<Scope, TypeError> {
+        let exp_typ = self.synth(exp, scope)?;
+        let mut scope1 = scope.clone();
+        self.bind_pat(pat, &mut HashSet::new(), &mut scope1, exp_typ)?;
+        if !exhaustive(&[&pat.pat], &self.types.raw(exp_typ), self.ctxt) {
+            return Err(TypeError {
+                title: "Refutable pattern in let binding",
+                annot_type: AnnotationType::Error,
+                annotations: vec![SourceAnnotation {
+                    range: pat.span.unwrap(),
+                    label: "this pattern doesn't match every value, use `case` instead",
+                    annotation_type: AnnotationType::Error,
+                }],
+            });
+        }
+        Ok(scope1)
+    }
+
+    /** Checks the mutually recursive functions of a `fix`.
+    Returns: The scope of the body of the `fix` */
+    fn check_fix(
+        &mut self,
+        funcs: &[(Ident, Ident, Type, Type, Expr)],
+        scope: &Scope,
+    ) -> Result<Scope, TypeError> {
+        let mut scope1 = scope.clone();
+        let mut sigs = vec![];
+        // Add the function signatures to the scope first
+        for (fun, var, typ, ret, _) in funcs {
+            unfilled_hole(typ, var.span)?;
+            unfilled_hole(ret, fun.span)?;
+            let (typ, ret) = (self.annot(typ, scope), self.annot(ret, scope));
+            let fun_typ = self.types.node(Node::Arrow(typ, ret));
+            scope1.vals.insert(fun.name.clone(), fun_typ);
+            sigs.push((typ, ret))
+        }
+        // Now check each function definition against its return type
+        for ((_, var, _, _, def), (typ, ret)) in zip(funcs, sigs) {
+            let mut scope2 = scope1.clone();
+            scope2.vals.insert(var.name.clone(), typ);
+            self.check(def, ret, &scope2)?
+        }
+        Ok(scope1)
+    }
+
+    /** Checks the arms of a `case` on `exp`, against `expected` if it is known.
+    Otherwise the later arms are checked against the type of the first.
+    Returns: The type of the arms */
+    fn check_case(
+        &mut self,
+        exp: &Expr,
+        arms: &[(Pattern, Expr)],
+        expected: Option<TypeId>,
+        scope: &Scope,
+    ) -> Result<TypeId, TypeError> {
+        let exp_typ = self.synth(exp, scope)?;
+        let mut case_typ = expected;
+        for (pat, body) in arms {
+            let mut scope1 = scope.clone();
+            self.bind_pat(pat, &mut HashSet::new(), &mut scope1, exp_typ)?;
+            match case_typ {
+                Some(typ) => self.check(body, typ, &scope1)?,
+                None => case_typ = Some(self.synth(body, &scope1)?),
+            }
+        }
+        let pats: Vec<&RawPattern> = arms.iter().map(|(p, _)| &p.pat).collect();
+        if !exhaustive(&pats, &self.types.raw(exp_typ), self.ctxt) {
+            return Err(TypeError {
+                title: "Non-exhaustive patterns",
+                annot_type: AnnotationType::Error,
+                annotations: vec![SourceAnnotation {
+                    range: exp.span.unwrap(),
+                    label: "some values of this expression aren't matched by any arm",
+                    annotation_type: AnnotationType::Error,
+                }],
+            });
+        }
+        // The grammar guarantees at least one arm
+        Ok(case_typ.unwrap())
+    }
+
+    /** Binds the variables of `pat`, which destructs values of type `typ`, in `scope`. Rejects
+    patterns that don't fit `typ`, or bind a variable twice, counting the `vars` seen already */
+    fn bind_pat(
+        &mut self,
+        pat: &Pattern,
+        vars: &mut HashSet<String>,
+        scope: &mut Scope,
+        typ: TypeId,
+    ) -> Result<(), TypeError> {
+        match &pat.pat {
+            RawPattern::Binding(ident) => {
+                let seen = vars.insert(ident.name.clone());
+                if seen.is_some() {
+                    Err(TypeError {
+                        title: "Conflicting argument names",
+                        annot_type: AnnotationType::Error,
+                        annotations: vec![SourceAnnotation {
+                            range: pat.span.unwrap(),
+                            label: "variable bound multiple times in pattern",
+                            annotation_type: AnnotationType::Error,
+                        }],
+                    })
+                } else {
+                    scope.vals.insert(ident.name.clone(), typ);
+                    Ok(())
+                }
+            }
+            RawPattern::Wildcard => Ok(()),
+            RawPattern::Literal(c) => {
+                let width = match self.types.get(typ) {
+                    Node::Sized(w) => Some(*w),
+                    _ => None,
+                };
+                match (c.as_int(), width) {
+                    // Integer literals match integers of any width they fit in
+                    (Some(n), Some(w)) if w.fits(n) => Ok(()),
+                    (Some(_), Some(_)) => Err(out_of_range(pat.span.unwrap())),
+                    _ if out_of_int_range(c) => Err(out_of_range(pat.span.unwrap())),
+                    _ if self.constant(c) == typ => Ok(()),
+                    _ => Err(TypeError {
+                        title: "Mismatched pattern literal",
+                        annot_type: AnnotationType::Error,
+                        annotations: vec![SourceAnnotation {
+                            range: pat.span.unwrap(),
+                            label: "literal doesn't have the type being destructed",
+                            annotation_type: AnnotationType::Error,
+                        }],
+                    }),
+                }
+            }
+            RawPattern::Ctor(c, pats) => match self.ctor_fields(&c.name, typ, scope) {
+                Some(fields) if fields.len() == pats.len() => {
+                    for (p, t) in zip(pats, fields) {
+                        self.bind_pat(p, vars, scope, t)?
+                    }
+                    Ok(())
+                }
+                Some(_) => Err(TypeError {
+                    title: "Wrong number of constructor arguments",
                     annot_type: AnnotationType::Error,
                     annotations: vec![SourceAnnotation {
-                        range: exp.span.unwrap(),
-                        label: "cannot apply arguments to non-functions",
+                        range: pat.span.unwrap(),
+                        label: "pattern expected with one entry per constructor field",
                         annotation_type: AnnotationType::Error,
                     }],
                 }),
+                None => Err(TypeError {
+                    title: "Mismatched constructor pattern",
+                    annot_type: AnnotationType::Error,
+                    annotations: vec![SourceAnnotation {
+                        range: c.span.unwrap(),
+                        label: "this isn't a constructor of the type being destructed",
+                        annotation_type: AnnotationType::Error,
+                    }],
+                }),
+            },
+            RawPattern::Record(pats) => {
+                distinct_fields(pats.iter().map(|(l, _)| l))?;
+                let Node::Record(fields) = self.types.get(typ).clone() else {
+                    return Err(TypeError {
+                        title: "Malformed pattern assignment",
+                        annot_type: AnnotationType::Error,
+                        annotations: vec![SourceAnnotation {
+                            range: pat.span.unwrap(),
+                            label: "record pattern used on a value that isn't a record",
+                            annotation_type: AnnotationType::Error,
+                        }],
+                    });
+                };
+                for (l, p) in pats {
+                    match fields.iter().find(|(f, _)| *f == l.name) {
+                        Some((_, t)) => self.bind_pat(p, vars, scope, *t)?,
+                        None => {
+                            return Err(TypeError {
+                                title: "Missing record field",
+                                annot_type: AnnotationType::Error,
+                                annotations: vec![SourceAnnotation {
+                                    range: l.span.unwrap(),
+                                    label: "the record has no field with this name",
+                                    annotation_type: AnnotationType::Error,
+                                }],
+                            })
+                        }
+                    }
+                }
+                Ok(())
             }
-        }
-        TApp { exp, arg } => {
-            let exp_t = check_expr(exp, val_ctxt, typ_vars)?;
-            match exp_t {
-                RawType::Forall(tvar, typ) => {
-                    let mut t = typ.typ.clone();
-                    substitute(&tvar.name, &arg, &mut t);
-                    Ok(t)
+            RawPattern::Tuple(pats) => match self.types.get(typ).clone() {
+                Node::Prod(ts) if pats.len() == ts.len() => {
+                    for (p, t) in zip(pats, ts) {
+                        self.bind_pat(p, vars, scope, t)?
+                    }
+                    Ok(())
                 }
                 _ => Err(TypeError {
-                    title: "Illegal type specialization",
+                    title: "Malformed pattern assignment",
                     annot_type: AnnotationType::Error,
                     annotations: vec![SourceAnnotation {
-                        range: exp.span.unwrap(),
-                        label: "this expression doesn't have `∀` type",
+                        range: pat.span.unwrap(),
+                        label: "pattern expected with same number of entries as product type",
                         annotation_type: AnnotationType::Error,
                     }],
                 }),
+            },
+        }
+    }
+
+    /** Field types of the constructor `ctor` when building a value of type `typ`,
+    or `None` if `ctor` doesn't build values of `typ` */
+    fn ctor_fields(&mut self, ctor: &str, typ: TypeId, scope: &Scope) -> Option<Vec<TypeId>> {
+        let Node::Data(Name::Free(id), args) = self.types.get(typ).clone() else {
+            return None;
+        };
+        let mut ctor_typ = self.lookup(ctor, scope)?;
+        for arg in args {
+            let Node::Forall(_, body) = *self.types.get(ctor_typ) else {
+                return None;
+            };
+            ctor_typ = self.types.open(body, arg)
+        }
+        let mut fields = vec![];
+        loop {
+            match self.types.get(ctor_typ) {
+                Node::Arrow(t1, t2) => {
+                    fields.push(*t1);
+                    ctor_typ = *t2
+                }
+                Node::Data(Name::Free(d), _) if *d == id => return Some(fields),
+                _ => return None,
+            }
+        }
+    }
+}
+
+/** The type of exceptions */
+pub fn exn() -> RawType {
+    RawType::Data(EXN.to_string(), vec![])
+}
+
+/** Whether values of `typ` can be compared with `==`, like the eqtypes of SML.
+Functions, abstract types, and exceptions can't, and references and arrays are compared by identity */
+pub fn admits_equality(typ: &RawType, ctxt: &Context) -> bool {
+    /// `assumed` are the data types being checked and the variables of enclosing recursive
+    /// types, whose occurrences admit equality as long as their arguments do
+    fn go(typ: &RawType, ctxt: &Context, assumed: &mut Vec<String>) -> bool {
+        use RawType::*;
+        match typ {
+            Int | Sized(_) | Bool | Unit | Str | Ref(_) | Array(_) => true,
+            Prod(ts) => ts.iter().all(|t| go(t, ctxt, assumed)),
+            Record(fields) => fields.iter().all(|(_, t)| go(t, ctxt, assumed)),
+            TVar(v) => assumed.contains(v),
+            Data(id, _) if id == EXN => false,
+            Data(id, args) if assumed.contains(id) => args.iter().all(|t| go(t, ctxt, assumed)),
+            Data(id, _) => {
+                assumed.push(id.clone());
+                datatype_ctors(ctxt, id).iter().all(|c| {
+                    let fields = ctor_fields(ctxt, c, typ).unwrap_or_default();
+                    fields.iter().all(|t| go(t, ctxt, assumed))
+                })
+            }
+            Rec(v, t) => {
+                assumed.push(v.name.clone());
+                go(t, ctxt, assumed)
+            }
+            _ => false,
+        }
+    }
+    go(&normalize(typ), ctxt, &mut vec![])
+}
+
+/** Error for reading or writing `exp`, which isn't a reference */
+pub fn not_a_ref(title: &'static str, exp: &Expr) -> TypeError {
+    TypeError {
+        title,
+        annot_type: AnnotationType::Error,
+        annotations: vec![SourceAnnotation {
+            range: exp.span.unwrap(),
+            label: "this expression isn't a reference",
+            annotation_type: AnnotationType::Error,
+        }],
+    }
+}
+
+/** Error for joining `exp`, which isn't a future */
+pub fn not_a_future(exp: &Expr) -> TypeError {
+    TypeError {
+        title: "Illegal join",
+        annot_type: AnnotationType::Error,
+        annotations: vec![SourceAnnotation {
+            range: exp.span.unwrap(),
+            label: "this expression isn't a future",
+            annotation_type: AnnotationType::Error,
+        }],
+    }
+}
+
+/** Error for indexing `exp`, which isn't an array */
+pub fn not_an_array(title: &'static str, exp: &Expr) -> TypeError {
+    TypeError {
+        title,
+        annot_type: AnnotationType::Error,
+        annotations: vec![SourceAnnotation {
+            range: exp.span.unwrap(),
+            label: "this expression isn't an array",
+            annotation_type: AnnotationType::Error,
+        }],
+    }
+}
+
+/** Error for opening `exp`, which isn't a structure */
+pub fn not_a_structure(exp: &Expr) -> TypeError {
+    TypeError {
+        title: "Illegal open",
+        annot_type: AnnotationType::Error,
+        annotations: vec![SourceAnnotation {
+            range: exp.span.unwrap(),
+            label: "this expression isn't a structure",
+            annotation_type: AnnotationType::Error,
+        }],
+    }
+}
+
+/** The type of the entry `index` of `exp`, which has type `typ`.
+Returns: The type of the entry, or `TypeError` if `typ` isn't a tuple type with such an entry */
+pub fn nth_type(exp: &Expr, index: usize, typ: RawType) -> Result<RawType, TypeError> {
+    match typ {
+        RawType::Prod(mut typs) if index < typs.len() => Ok(typs.swap_remove(index).typ),
+        typ => Err(not_an_entry(exp, matches!(typ, RawType::Prod(_)))),
+    }
+}
+
+/** Error for projecting an entry out of `exp`, a `tuple` without it or not a tuple */
+fn not_an_entry(exp: &Expr, tuple: bool) -> TypeError {
+    let (title, label) = match tuple {
+        true => (
+            "Tuple index out of range",
+            "this tuple has no entry at the index projected",
+        ),
+        false => ("Illegal projection", "this expression isn't a tuple"),
+    };
+    TypeError {
+        title,
+        annot_type: AnnotationType::Error,
+        annotations: vec![SourceAnnotation {
+            range: exp.span.unwrap(),
+            label,
+            annotation_type: AnnotationType::Error,
+        }],
+    }
+}
+
+/** Error for the integer literal at `range`, which doesn't fit its sized integer type */
+pub fn out_of_range(range: Span) -> TypeError {
+    TypeError {
+        title: "Integer literal out of range",
+        annot_type: AnnotationType::Error,
+        annotations: vec![SourceAnnotation {
+            range,
+            label: "this literal doesn't fit in the integer type expected here",
+            annotation_type: AnnotationType::Error,
+        }],
+    }
+}
+
+/** Whether `c` is an integer literal out of the range of `Int`, the type of those not expected to
+be of a sized integer type */
+pub fn out_of_int_range(c: &Constant) -> bool {
+    matches!(c, Constant::Integer(n) if !Width::Int.fits(*n))
+}
+
+/** Error for converting the expression at `range`, which isn't an integer, to an integer type */
+pub fn illegal_conversion(range: Span) -> TypeError {
+    TypeError {
+        title: "Illegal conversion",
+        annot_type: AnnotationType::Error,
+        annotations: vec![SourceAnnotation {
+            range,
+            label: "only integers can be converted between widths",
+            annotation_type: AnnotationType::Error,
+        }],
+    }
+}
+
+/** Type-checks the declaration `decl`. `val_ctxt` is a the context up to all the previous declarations.
+If the `decl` body checks against the `decl` signature, then this adds the pair of (`decl` id, signature) to `val_ctxt`.
+Holes and left out annotations are first inferred by [`elaborate_decl`].
+Returns: Notes stating the type of each filled hole if everything is fine, or `TypeError` otherwise.
+# Arguments
+ * `decl`: The declaration to check
+ * `val_ctxt`: Persistent mapping from variable names to raw type */
+pub fn check_decl(decl: &Decl, ctxt: &mut Context) -> Result<Vec<Note>, TypeError> {
+    let val_ctxt = ctxt.clone();
+    let typ_vars = Kinds::default();
+    let (decl, notes) = elaborate_decl(decl, &val_ctxt)?;
+    check_against(&decl.body, &decl.sig.typ, &val_ctxt, &typ_vars)?;
+    ctxt.insert(decl.id.clone(), decl.sig.typ);
+    Ok(notes)
+}
+
+/** Type-checks the program `prog` starting from the typing context of the [`BUILTINS`].
+Returns: `Ok` if everything is fine, or `TypeError` otherwise.
+# Arguments
+ * `prog`: The prog to check */
+pub fn check_prog(prog: &Prog) -> Result<(), TypeError> {
+    let prog = &elaborate_classes(&derive(&elaborate_modules(&with_prelude(prog))?)?)?;
+    let mut aliases = prog_aliases(prog)?;
+    let mut ctxt = builtin_context();
+    for data in &prog.datatypes {
+        define_datatype(data, &mut ctxt, &mut aliases)?;
+    }
+    for exn in &prog.exceptions {
+        define_exception(exn, &mut ctxt, &aliases)?;
+    }
+    for id in &prog.order {
+        well_formed_decl(&prog.declarations[id], &aliases)?;
+        let decl = expand_decl(&prog.declarations[id], &aliases);
+        check_decl(&decl, &mut ctxt)?;
+    }
+    Ok(())
+}
+
+/** Source of the data types and exceptions every program starts with */
+const PRELUDE: &str = "
+data List A = Nil | Cons A (List A)
+(** Raised by indexing an array out of its bounds, or allocating one of negative length *)
+exception Subscript
+(** Raised by division and modulo by zero *)
+exception Div
+";
+
+/** Names and types of the functions built into the interpreter, in scope in every program */
+pub const BUILTINS: &[(&str, &str)] = &[
+    ("print", "String -> Unit"),
+    ("printInt", "Int -> Unit"),
+    ("readLine", "Unit -> String"),
+    ("showInt", "Int -> String"),
+    ("concat", "String * String -> String"),
+    ("assert", "Bool -> Unit"),
+];
+
+/** The typing context of the [`BUILTINS`] */
+pub fn builtin_context() -> Context {
+    BUILTINS
+        .iter()
+        .map(|(id, typ)| (id.to_string(), parse_type(typ).unwrap().typ))
+        .collect()
+}
+
+/** The data types and exceptions of the prelude */
+pub fn prelude() -> Prog {
+    parse_prog(PRELUDE).unwrap()
+}
+
+/** `prog` preceded by the data types of the prelude it doesn't define itself,
+and by the exceptions of the prelude, which can't be redefined */
+pub fn with_prelude(prog: &Prog) -> Prog {
+    let prelude = prelude();
+    let mut datatypes: Vec<DataDecl> = prelude
+        .datatypes
+        .into_iter()
+        .filter(|d| prog.datatypes.iter().all(|data| data.id != d.id))
+        .collect();
+    datatypes.extend(prog.datatypes.iter().cloned());
+    let mut exceptions = prelude.exceptions;
+    exceptions.extend(prog.exceptions.iter().cloned());
+    Prog {
+        datatypes,
+        exceptions,
+        ..prog.clone()
+    }
+}
+
+/** Collects the type aliases of `prog` in order, each expanded in terms of the previous ones.
+Data type names are included first, standing for themselves. */
+pub fn prog_aliases(prog: &Prog) -> Result<Aliases, TypeError> {
+    let mut aliases = Aliases::default();
+    for data in &prog.datatypes {
+        aliases.insert(data.id.clone(), type_operator(data));
+    }
+    for alias in &prog.aliases {
+        define_alias(alias, &mut aliases)?;
+    }
+    Ok(aliases)
+}
+
+/** Expands `alias` under the existing `aliases` and adds it to them.
+Returns `TypeError` if an alias of the same name already exists. */
+pub fn define_alias(alias: &TypeAlias, aliases: &mut Aliases) -> Result<(), TypeError> {
+    let names = aliases.keys().cloned().collect();
+    well_formed(&alias.typ, &names, alias.span.unwrap())?;
+    let mut typ = alias.typ.typ.clone();
+    expand(&mut typ, aliases);
+    kind_of(&alias.typ, &Kinds::default(), alias.span.unwrap())?;
+    if aliases.contains_key(&alias.id) {
+        return Err(TypeError {
+            title: "Redefinition of type alias",
+            annot_type: AnnotationType::Error,
+            annotations: vec![SourceAnnotation {
+                range: alias.typ.span.unwrap(),
+                label: "a type alias with this name already exists",
+                annotation_type: AnnotationType::Error,
+            }],
+        });
+    }
+    aliases.insert(alias.id.clone(), typ);
+    Ok(())
+}
+
+/** Adds the constructors of `data` to `ctxt` as polymorphic curried functions,
+ex. `Some : ∀ A. A -> Option A`, and makes the name of `data` usable in `aliases`.
+Returns `TypeError` if a constructor of the same name already exists. */
+pub fn define_datatype(
+    data: &DataDecl,
+    ctxt: &mut Context,
+    aliases: &mut Aliases,
+) -> Result<(), TypeError> {
+    let placeholder = type_operator(data);
+    if aliases
+        .get(&data.id)
+        .is_some_and(|t| !equivalent(t, &placeholder))
+    {
+        return Err(TypeError {
+            title: "Redefinition of type",
+            annot_type: AnnotationType::Error,
+            annotations: vec![SourceAnnotation {
+                range: data.span.unwrap(),
+                label: "a type with this name already exists",
+                annotation_type: AnnotationType::Error,
+            }],
+        });
+    }
+    aliases.insert(data.id.clone(), placeholder);
+    let params = data.params.iter().fold(aliases.clone(), |mut acc, p| {
+        acc.shift_remove(&p.name);
+        acc
+    });
+    let result = RawType::Data(
+        data.id.clone(),
+        data.params
+            .iter()
+            .map(|p| Type::new(RawType::TVar(p.name.clone())))
+            .collect(),
+    );
+    let names = data
+        .params
+        .iter()
+        .map(|p| p.name.clone())
+        .chain(aliases.keys().cloned())
+        .collect();
+    for (ctor, fields) in &data.ctors {
+        for field in fields {
+            well_formed(field, &names, data.span.unwrap())?;
+        }
+        let curried = fields.iter().rev().fold(result.clone(), |acc, field| {
+            let mut field = field.typ.clone();
+            expand(&mut field, &params);
+            RawType::Arrow(Box::new(Type::new(field)), Box::new(Type::new(acc)))
+        });
+        let typ = data.params.iter().rev().fold(curried, |acc, p| {
+            RawType::Forall(p.clone(), Kind::Star, Box::new(Type::new(acc)))
+        });
+        if ctxt.insert(ctor.name.clone(), typ).is_some() {
+            return Err(TypeError {
+                title: "Redefinition of constructor",
+                annot_type: AnnotationType::Error,
+                annotations: vec![SourceAnnotation {
+                    range: ctor.span.unwrap(),
+                    label: "a constructor with this name already exists",
+                    annotation_type: AnnotationType::Error,
+                }],
+            });
+        }
+    }
+    Ok(())
+}
+
+/** Adds the constructor of `exn` to `ctxt` as a curried function returning `Exn`,
+ex. `Fail : Int -> Exn`, with its fields expanded under `aliases`.
+Returns `TypeError` if a constructor of the same name already exists. */
+pub fn define_exception(
+    exn_decl: &ExnDecl,
+    ctxt: &mut Context,
+    aliases: &Aliases,
+) -> Result<(), TypeError> {
+    let at = exn_decl.span.unwrap();
+    let names = aliases.keys().cloned().collect();
+    let mut typ = exn();
+    for field in exn_decl.fields.iter().rev() {
+        well_formed(field, &names, at)?;
+        check_kind(field, &Kind::Star, &Kinds::default(), at)?;
+        let mut field = field.typ.clone();
+        expand(&mut field, aliases);
+        typ = RawType::Arrow(Box::new(Type::new(field)), Box::new(Type::new(typ)))
+    }
+    if ctxt.insert(exn_decl.ctor.name.clone(), typ).is_some() {
+        return Err(TypeError {
+            title: "Redefinition of constructor",
+            annot_type: AnnotationType::Error,
+            annotations: vec![SourceAnnotation {
+                range: exn_decl.ctor.span.unwrap(),
+                label: "a constructor with this name already exists",
+                annotation_type: AnnotationType::Error,
+            }],
+        });
+    }
+    Ok(())
+}
+
+/** The data type `data` as a type operator taking its parameters, ex. `λ A. Option A` */
+fn type_operator(data: &DataDecl) -> RawType {
+    let params = data
+        .params
+        .iter()
+        .map(|p| Type::new(RawType::TVar(p.name.clone())));
+    let applied = RawType::Data(data.id.clone(), params.collect());
+    data.params.iter().rev().fold(applied, |acc, p| {
+        RawType::Lam(p.clone(), Kind::Star, Box::new(Type::new(acc)))
+    })
+}
+
+/** Replaces the aliases in `typ` with their definitions.
+Type variables bound inside `typ` shadow aliases of the same name. */
+pub fn expand(typ: &mut RawType, aliases: &Aliases) {
+    use RawType::*;
+    match typ {
+        TVar(v) => {
+            if let Some(t) = aliases.get(v) {
+                *typ = t.clone()
+            }
+        }
+        Prod(typs) => {
+            for t in typs {
+                expand(t, aliases)
+            }
+        }
+        Arrow(t1, t2) => {
+            expand(t1, aliases);
+            expand(t2, aliases)
+        }
+        Ref(t) | Array(t) | Future(t) => expand(t, aliases),
+        Forall(v, _, t) | Lam(v, _, t) | Rec(v, t) | Exists(v, t) => {
+            expand(t, &without(aliases, &v.name))
+        }
+        Data(id, typs) => {
+            for t in typs.iter_mut() {
+                expand(t, aliases)
+            }
+            // Aliases and data types applied to arguments are type operators
+            if let Some(op) = aliases.get(id) {
+                *typ = typs.drain(..).fold(op.clone(), apply)
+            }
+        }
+        App(t1, t2) => {
+            expand(t1, aliases);
+            expand(t2, aliases)
+        }
+        Record(fields) => {
+            for (_, t) in fields {
+                expand(t, aliases)
+            }
+        }
+        Int | Sized(_) | Bool | Unit | Str | Hole | Meta(_) => (),
+    }
+}
+
+/** Replaces the aliases in every annotation of `expr` with their definitions */
+pub fn expand_expr(expr: &mut Expr, aliases: &Aliases) {
+    grow(|| {
+        use RawExpr::*;
+        match &mut expr.expr {
+            Con { .. } | Var { .. } => (),
+            Let { exp, body, .. } | Open { exp, body } => {
+                expand_expr(exp, aliases);
+                expand_expr(body, aliases)
             }
-        }
-        Tuple { entries } => {
-            let typs = entries
-                .iter()
-                .map(|e| Result::map(check_expr(e, val_ctxt, typ_vars), Type::new))
-                .collect::<Result<Vec<Type>, TypeError>>()?;
-            Ok(RawType::Prod(typs))
-        }
-        Binop { lhs, op, rhs } => {
-            use Binary::*;
-            use RawType::*;
-            fn err(msg: &'static str, span: (usize, usize)) -> TypeError {
-                TypeError {
-                    title: "Mismatched Types",
-                    annot_type: AnnotationType::Error,
-                    annotations: vec![SourceAnnotation {
-                        range: span,
-                        label: msg,
-                        annotation_type: AnnotationType::Error,
-                    }],
+            Fix { funcs, body } => {
+                for (_, _, typ, ret, def) in funcs {
+                    expand(typ, aliases);
+                    expand(ret, aliases);
+                    expand_expr(def, aliases)
                 }
+                expand_expr(body, aliases)
             }
-            let typ_l = check_expr(lhs, val_ctxt, typ_vars)?;
-            let typ_r = check_expr(rhs, val_ctxt, typ_vars)?;
-            match op {
-                Add | Sub | Mul | Eq | Ne | Gt | Lt => {
-                    let err_msg = "expected to have type `Int`";
-                    match (typ_l, typ_r) {
-                        (Int, Int) => Ok(match op {
-                            Add | Sub | Mul => Int,
-                            _ => Bool,
-                        }),
-                        (Int, _) => Err(err(err_msg, rhs.span.unwrap())),
-                        _ => Err(err(err_msg, lhs.span.unwrap())),
-                    }
+            EApp { exp, arg } => {
+                expand_expr(exp, aliases);
+                expand_expr(arg, aliases)
+            }
+            TApp { exp, arg } => {
+                expand_expr(exp, aliases);
+                expand(arg, aliases)
+            }
+            Tuple { entries } => {
+                for e in entries {
+                    expand_expr(e, aliases)
                 }
-                And | Or => {
-                    let err_msg = "expected to have type `Bool`";
-                    match (typ_l, typ_r) {
-                        (Bool, Bool) => Ok(Bool),
-                        (Bool, _) => Err(err(err_msg, rhs.span.unwrap())),
-                        _ => Err(err(err_msg, lhs.span.unwrap())),
-                    }
+            }
+            Binop { lhs, rhs, .. } => {
+                expand_expr(lhs, aliases);
+                expand_expr(rhs, aliases)
+            }
+            Lambda {
+                arg: (_, typ),
+                body,
+            } => {
+                expand(typ, aliases);
+                expand_expr(body, aliases)
+            }
+            Any { arg, body, .. } => expand_expr(body, &without(aliases, &arg.name)),
+            If {
+                cond,
+                branch_t,
+                branch_f,
+            } => {
+                expand_expr(cond, aliases);
+                expand_expr(branch_t, aliases);
+                expand_expr(branch_f, aliases)
+            }
+            Case { exp, arms } => {
+                expand_expr(exp, aliases);
+                for (_, body) in arms {
+                    expand_expr(body, aliases)
                 }
             }
-        }
-        Lambda { arg, body } => {
-            let mut ctxt1 = val_ctxt.clone();
-            let (id, typ) = arg;
-            let bound = ctxt1.insert(id.name.clone(), typ.typ.clone());
-            if bound.is_some() {
-                return Err(TypeError {
-                    title: "Redefinition of variables",
-                    annot_type: AnnotationType::Error,
-                    annotations: vec![SourceAnnotation {
-                        range: id.span.unwrap(),
-                        label: "attempting to declare a bound variable",
-                        annotation_type: AnnotationType::Error,
-                    }],
-                });
-            }
-            let body_typ = check_expr(body, &ctxt1, typ_vars)?;
-            Ok(Arrow(Box::new(typ.clone()), Box::new(Type::new(body_typ))))
-        }
-        Any { arg, body } => {
-            let mut tvars1 = typ_vars.clone();
-            tvars1.insert(arg.name.clone());
-            let typ = check_expr(body, val_ctxt, &tvars1)?;
-            let poly_copy = Ident {
-                name: arg.name.clone(),
-                span: None,
-            };
-            Ok(Forall(poly_copy, Box::new(Type::new(typ))))
-        }
-        If {
-            cond,
-            branch_t,
-            branch_f,
-        } => {
-            // Check the three branches independently
-            let c_typ = check_expr(cond, val_ctxt, typ_vars)?;
-            let t_typ = check_expr(branch_t, val_ctxt, typ_vars)?;
-            let f_typ = check_expr(branch_f, val_ctxt, typ_vars)?;
-            match c_typ {
-                Bool => {
-                    if equivalent(&t_typ, &f_typ) {
-                        Ok(t_typ)
-                    } else {
-                        Err(TypeError {
-                            title: "Non uniform types in conditional branches",
-                            annot_type: AnnotationType::Error,
-                            annotations: vec![SourceAnnotation {
-                                range: branch_f.span.unwrap(),
-                                label: "true and false branches must have same types",
-                                annotation_type: AnnotationType::Error,
-                            }],
-                        })
-                    }
+            Record { fields } => {
+                for (_, e) in fields {
+                    expand_expr(e, aliases)
                 }
-                _ => Err(TypeError {
-                    title: "Mismatched type for if condition",
-                    annot_type: AnnotationType::Error,
-                    annotations: vec![SourceAnnotation {
-                        range: cond.span.unwrap(),
-                        label: "if condition expected to have type `Bool`",
-                        annotation_type: AnnotationType::Error,
-                    }],
-                }),
             }
+            Proj { exp, .. }
+            | Nth { exp, .. }
+            | Unfold { exp }
+            | RawExpr::Ref { exp }
+            | Deref { exp }
+            | Spawn { exp }
+            | Join { exp }
+            | Convert { exp, .. } => expand_expr(exp, aliases),
+            Raise { exp, typ } => {
+                expand(typ, aliases);
+                expand_expr(exp, aliases)
+            }
+            Handle { exp, handler, .. } => {
+                expand_expr(exp, aliases);
+                expand_expr(handler, aliases)
+            }
+            Instance { typ, .. } => expand(typ, aliases),
+            Assign { lhs, rhs } | AssertEq { lhs, rhs } => {
+                expand_expr(lhs, aliases);
+                expand_expr(rhs, aliases)
+            }
+            RawExpr::Array { .. } | Sub { .. } | Update { .. } => expr
+                .subexprs_mut()
+                .into_iter()
+                .for_each(|e| expand_expr(e, aliases)),
+            Fold { typ, exp } | Ascribe { exp, typ } => {
+                expand(typ, aliases);
+                expand_expr(exp, aliases)
+            }
+            Pack { witness, exp, typ } => {
+                expand(witness, aliases);
+                expand_expr(exp, aliases);
+                expand(typ, aliases)
+            }
+            Unpack {
+                tvar, exp, body, ..
+            } => {
+                expand_expr(exp, aliases);
+                expand_expr(body, &without(aliases, &tvar.name))
+            }
+        }
+    })
+}
+
+/** Copy of `decl` with the aliases in its signature and body expanded */
+pub fn expand_decl(decl: &Decl, aliases: &Aliases) -> Decl {
+    let mut decl = decl.clone();
+    expand(&mut decl.sig, aliases);
+    expand_expr(&mut decl.body, aliases);
+    decl
+}
+
+/** Rejects the names in `typ` that are neither type variables nor data types and aliases in
+`names`. Expansion leaves unknown names in place, so this runs before it, to report them at
+the annotation instead of failing later during instantiation.
+Errors point at the innermost subterm with a span, or `at` if there is none */
+pub fn well_formed(typ: &Type, names: &HashSet<String>, at: Span) -> Result<(), TypeError> {
+    use RawType::*;
+    let at = typ.span.unwrap_or(at);
+    let wf = |t: &Type| well_formed(t, names, at);
+    match &typ.typ {
+        Int | Sized(_) | Bool | Unit | Str | Hole | Meta(_) => Ok(()),
+        // Exceptions are built in
+        TVar(id) | Data(id, _) if !names.contains(id) && id != EXN => Err(TypeError {
+            title: "Unknown type",
+            annot_type: AnnotationType::Error,
+            annotations: vec![SourceAnnotation {
+                range: at,
+                label: "no type or type variable with this name is in scope",
+                annotation_type: AnnotationType::Error,
+            }],
+        }),
+        TVar(_) => Ok(()),
+        Data(_, ts) | Prod(ts) => ts.iter().try_for_each(wf),
+        Arrow(t1, t2) | App(t1, t2) => {
+            wf(t1)?;
+            wf(t2)
+        }
+        Ref(t) | Array(t) | Future(t) => wf(t),
+        Forall(v, _, t) | Lam(v, _, t) | Rec(v, t) | Exists(v, t) => {
+            well_formed(t, &names.update(v.name.clone()), at)
         }
+        Record(fields) => fields.iter().try_for_each(|(_, t)| wf(t)),
     }
 }
 
-/** Type-checks the declaration `decl`. `val_ctxt` is a the context up to all the previous declarations.
-If the checked type of the `decl` body matches the `decl` signature, then this adds the pair of (`decl` id, signature) to `val_ctxt`.
-Returns: `Ok` if everything is fine, or `TypeError` otherwise.
-# Arguments
- * `decl`: The declaration to check
- * `val_ctxt`: Persistent mapping from variable names to raw type */
-pub fn check_decl<'ast>(decl: &'ast Decl, ctxt: &mut Context) -> Result<(), TypeError> {
-    let val_ctxt = ctxt.clone();
-    let typ_vars = HashSet::default();
-    let check_result = check_expr(&decl.body, &val_ctxt, &typ_vars);
-    match check_result {
-        Ok(typ) => {
-            if equivalent(&typ, &decl.sig.typ) {
-                ctxt.insert(decl.id.clone(), typ);
-                Ok(())
-            } else {
-                Err(TypeError {
-                    title: "Mismatched type in declaration",
-                    annot_type: AnnotationType::Error,
-                    annotations: vec![SourceAnnotation {
-                        range: decl.body.span.unwrap(),
-                        label: "expression type differs from declaration signature",
-                        annotation_type: AnnotationType::Error,
-                    }],
-                })
+/** Checks that every annotation in `expr` is [`well_formed`] under `names`,
+along with the type variables bound around it */
+pub fn well_formed_expr(expr: &Expr, names: &HashSet<String>) -> Result<(), TypeError> {
+    grow(|| {
+        use RawExpr::*;
+        let at = expr.span.unwrap_or_default();
+        let wf = |t: &Type| well_formed(t, names, at);
+        match &expr.expr {
+            Fix { funcs, .. } => funcs.iter().try_for_each(|(_, _, typ, ret, _)| {
+                wf(typ)?;
+                wf(ret)
+            })?,
+            TApp { arg: typ, .. }
+            | Lambda { arg: (_, typ), .. }
+            | Raise { typ, .. }
+            | Instance { typ, .. }
+            | Fold { typ, .. }
+            | Ascribe { typ, .. } => wf(typ)?,
+            Pack { witness, typ, .. } => {
+                wf(witness)?;
+                wf(typ)?
             }
+            Any { arg, body, .. } => {
+                return well_formed_expr(body, &names.update(arg.name.clone()))
+            }
+            Unpack {
+                tvar, exp, body, ..
+            } => {
+                well_formed_expr(exp, names)?;
+                return well_formed_expr(body, &names.update(tvar.name.clone()));
+            }
+            _ => (),
         }
-        Err(te) => Err(te),
+        expr.subexprs()
+            .into_iter()
+            .try_for_each(|e| well_formed_expr(e, names))
+    })
+}
+
+/** Checks that the signature and body of `decl` only mention the data types and aliases
+in `aliases`, and type variables bound around their uses, including the [`scoped_type_vars`] */
+pub fn well_formed_decl(decl: &Decl, aliases: &Aliases) -> Result<(), TypeError> {
+    let names: HashSet<String> = aliases.keys().cloned().collect();
+    well_formed(&decl.sig, &names, decl.span.unwrap_or_default())?;
+    let scoped = scoped_type_vars(decl).into_iter().map(|(v, _)| v.name);
+    well_formed_expr(&decl.body, &names.into_iter().chain(scoped).collect())
+}
+
+/** The type variables bound by the leading `∀`s of the signature of `decl`, which scope over its
+body unless the body abstracts over types itself, ex. `A` in `let id: ∀ A. A -> A = λ x: A. x`.
+Inference abstracts the body over them */
+pub fn scoped_type_vars(decl: &Decl) -> Vec<(Ident, Kind)> {
+    if matches!(decl.body.expr, RawExpr::Any { .. }) {
+        return vec![];
     }
+    let mut vars = vec![];
+    let mut typ = &decl.sig.typ;
+    while let RawType::Forall(v, k, t) = typ {
+        vars.push((v.clone(), k.clone()));
+        typ = &t.typ
+    }
+    vars
 }
 
-/** Type-checks the program `prog` starting from an empty typing context.
-Returns: `Ok` if everything is fine, or `TypeError` otherwise.
-# Arguments
- * `prog`: The prog to check */
-pub fn check_prog<'ast>(prog: &'ast Prog) -> Result<(), TypeError> {
-    for id in &prog.order {
-        check_decl(&prog.declarations[id], &mut Default::default())?
+/** `aliases` without the alias `v`, shadowed by a type variable, the rest in order */
+fn without(aliases: &Aliases, v: &str) -> Aliases {
+    let mut aliases = aliases.clone();
+    aliases.shift_remove(v);
+    aliases
+}
+
+/** Replaces parts of `typ` that are equivalent to the definition of an alias by the alias name,
+the first defined among equivalent ones. Used for printing types the way the user wrote them. */
+pub fn fold(typ: &RawType, aliases: &Aliases) -> RawType {
+    use RawType::*;
+    let alias = aliases
+        .iter()
+        .find(|(_, def)| !def.is_atomic() && equivalent(def, typ));
+    if let Some((name, _)) = alias {
+        return TVar(name.clone());
+    }
+    match typ {
+        Prod(typs) => Prod(typs.iter().map(|t| Type::new(fold(t, aliases))).collect()),
+        Arrow(t1, t2) => Arrow(
+            Box::new(Type::new(fold(t1, aliases))),
+            Box::new(Type::new(fold(t2, aliases))),
+        ),
+        Ref(t) => Ref(Box::new(Type::new(fold(t, aliases)))),
+        Array(t) => Array(Box::new(Type::new(fold(t, aliases)))),
+        Future(t) => Future(Box::new(Type::new(fold(t, aliases)))),
+        Forall(v, k, t) => Forall(
+            v.clone(),
+            k.clone(),
+            Box::new(Type::new(fold(t, &without(aliases, &v.name)))),
+        ),
+        Lam(v, k, t) => Lam(
+            v.clone(),
+            k.clone(),
+            Box::new(Type::new(fold(t, &without(aliases, &v.name)))),
+        ),
+        App(t1, t2) => App(
+            Box::new(Type::new(fold(t1, aliases))),
+            Box::new(Type::new(fold(t2, aliases))),
+        ),
+        Rec(v, t) => Rec(
+            v.clone(),
+            Box::new(Type::new(fold(t, &without(aliases, &v.name)))),
+        ),
+        Exists(v, t) => Exists(
+            v.clone(),
+            Box::new(Type::new(fold(t, &without(aliases, &v.name)))),
+        ),
+        Data(id, typs) => Data(
+            id.clone(),
+            typs.iter().map(|t| Type::new(fold(t, aliases))).collect(),
+        ),
+        Record(fields) => Record(
+            fields
+                .iter()
+                .map(|(l, t)| (l.clone(), Type::new(fold(t, aliases))))
+                .collect(),
+        ),
+        _ => typ.clone(),
     }
-    Ok(())
 }
 
 // Check closed expression
 pub fn check_closed_expr(expr: &Expr) -> Result<RawType, TypeError> {
-    let ctxt = HashMap::default();
-    let tvars = HashSet::default();
+    let ctxt = builtin_context();
+    let tvars = Kinds::default();
+    well_formed_expr(expr, &HashSet::default())?;
+    check_kinds(expr, &tvars)?;
     check_expr(expr, &ctxt, &tvars)
 }
 
-/** Substitution
+/** Capture-avoiding substitution. Binders in `typ` of type variables free in `target` are renamed
 `tvar`: The type variable to replace
 `target`: The type to replace with
 `typ`: The type in which to perform the replacement */
@@ -303,77 +1705,719 @@ pub fn substitute(tvar: &str, target: &RawType, typ: &mut RawType) {
             substitute(tvar, target, v);
             substitute(tvar, target, t);
         }
-        Forall(v, t) if &v.name != tvar => substitute(tvar, target, t),
+        Ref(t) | Array(t) | Future(t) => substitute(tvar, target, t),
+        Forall(v, _, t) | Lam(v, _, t) | Rec(v, t) | Exists(v, t) if v.name != tvar => {
+            if free_in(&v.name, target) && free_in(tvar, t) {
+                let fresh = fresh_var(&v.name, &[target, t]);
+                substitute(&v.name, &TVar(fresh.clone()), t);
+                v.name = fresh
+            }
+            substitute(tvar, target, t)
+        }
+        Data(id, typs) => {
+            for t in typs.iter_mut() {
+                substitute(tvar, target, t)
+            }
+            // The type variable is applied to arguments, so it stands for a type operator
+            if id == tvar {
+                *typ = typs.drain(..).fold(target.clone(), apply)
+            }
+        }
+        App(t1, t2) => {
+            substitute(tvar, target, t1);
+            substitute(tvar, target, t2)
+        }
+        Record(fields) => {
+            for (_, t) in fields {
+                substitute(tvar, target, t)
+            }
+        }
         _ => {}
     }
 }
 
-/** Equivalence of types. No alpha equivalence to make life easier. */
-pub fn equivalent<'src>(typ1: &'src RawType, typ2: &'src RawType) -> bool {
+/** Beta-normal form of `typ`, with every type operator applied to its arguments.
+Names applied to arguments stay `Data`, even when they are type variables */
+pub fn normalize(typ: &RawType) -> RawType {
+    use RawType::*;
+    let norm = |t: &Type| Type::new(normalize(t));
+    match typ {
+        Prod(ts) => Prod(ts.iter().map(norm).collect()),
+        Arrow(t1, t2) => Arrow(Box::new(norm(t1)), Box::new(norm(t2))),
+        Ref(t) => Ref(Box::new(norm(t))),
+        Array(t) => Array(Box::new(norm(t))),
+        Future(t) => Future(Box::new(norm(t))),
+        Forall(v, k, t) => Forall(v.clone(), k.clone(), Box::new(norm(t))),
+        Lam(v, k, t) => Lam(v.clone(), k.clone(), Box::new(norm(t))),
+        Rec(v, t) => Rec(v.clone(), Box::new(norm(t))),
+        Exists(v, t) => Exists(v.clone(), Box::new(norm(t))),
+        Data(id, ts) => Data(id.clone(), ts.iter().map(norm).collect()),
+        Record(fields) => Record(fields.iter().map(|(l, t)| (l.clone(), norm(t))).collect()),
+        App(t1, t2) => apply(normalize(t1), norm(t2)),
+        Int | Sized(_) | Bool | Unit | Str | TVar(_) | Hole | Meta(_) => typ.clone(),
+    }
+}
+
+/** Normal form of the type operator `op` applied to `arg` */
+pub fn apply(op: RawType, arg: Type) -> RawType {
+    use RawType::*;
+    match op {
+        Lam(v, _, body) => {
+            let mut typ = body.typ;
+            substitute(&v.name, &arg, &mut typ);
+            normalize(&typ)
+        }
+        TVar(v) => Data(v, vec![arg]),
+        Data(id, mut args) => {
+            args.push(arg);
+            Data(id, args)
+        }
+        op => App(Box::new(Type::new(op)), Box::new(arg)),
+    }
+}
+
+/** Kind of `typ`, where `typ_vars` are the kinds of the type variables in scope.
+Other names are data types and aliases, applied to proper types like data types are.
+Errors point at the innermost subterm with a span, or `at` if there is none */
+pub fn kind_of(typ: &Type, typ_vars: &Kinds, at: Span) -> Result<Kind, TypeError> {
+    use RawType::*;
+    let at = typ.span.unwrap_or(at);
+    let star = |t: &Type| check_kind(t, &Kind::Star, typ_vars, at);
+    match &typ.typ {
+        Int | Sized(_) | Bool | Unit | Str | Hole | Meta(_) => Ok(Kind::Star),
+        TVar(v) => Ok(typ_vars.get(v).cloned().unwrap_or(Kind::Star)),
+        Prod(ts) => {
+            ts.iter().try_for_each(star)?;
+            Ok(Kind::Star)
+        }
+        Arrow(t1, t2) => {
+            star(t1)?;
+            star(t2)?;
+            Ok(Kind::Star)
+        }
+        Ref(t) | Array(t) | Future(t) => {
+            star(t)?;
+            Ok(Kind::Star)
+        }
+        Record(fields) => {
+            fields.iter().try_for_each(|(_, t)| star(t))?;
+            Ok(Kind::Star)
+        }
+        Forall(v, k, t) => {
+            check_kind(
+                t,
+                &Kind::Star,
+                &typ_vars.update(v.name.clone(), k.clone()),
+                at,
+            )?;
+            Ok(Kind::Star)
+        }
+        Rec(v, t) | Exists(v, t) => {
+            check_kind(
+                t,
+                &Kind::Star,
+                &typ_vars.update(v.name.clone(), Kind::Star),
+                at,
+            )?;
+            Ok(Kind::Star)
+        }
+        Lam(v, k, t) => {
+            let body = kind_of(t, &typ_vars.update(v.name.clone(), k.clone()), at)?;
+            Ok(Kind::Arrow(Box::new(k.clone()), Box::new(body)))
+        }
+        App(t1, t2) => {
+            let op = kind_of(t1, typ_vars, at)?;
+            apply_kind(op, t2, typ_vars, at)
+        }
+        Data(id, args) => {
+            let op = match typ_vars.get(id) {
+                Some(k) => k.clone(),
+                None => args.iter().fold(Kind::Star, |acc, _| {
+                    Kind::Arrow(Box::new(Kind::Star), Box::new(acc))
+                }),
+            };
+            args.iter()
+                .try_fold(op, |op, arg| apply_kind(op, arg, typ_vars, at))
+        }
+    }
+}
+
+/** Kind of a type operator of kind `op` applied to `arg` */
+fn apply_kind(op: Kind, arg: &Type, typ_vars: &Kinds, at: Span) -> Result<Kind, TypeError> {
+    match op {
+        Kind::Arrow(k1, k2) => {
+            check_kind(arg, &k1, typ_vars, at)?;
+            Ok(*k2)
+        }
+        Kind::Star => Err(TypeError {
+            title: "Illegal type application",
+            annot_type: AnnotationType::Error,
+            annotations: vec![SourceAnnotation {
+                range: at,
+                label: "this type isn't a type operator, so it can't be applied",
+                annotation_type: AnnotationType::Error,
+            }],
+        }),
+    }
+}
+
+/** Rejects `typ` unless it has kind `expected` */
+pub fn check_kind(
+    typ: &Type,
+    expected: &Kind,
+    typ_vars: &Kinds,
+    at: Span,
+) -> Result<(), TypeError> {
+    if kind_of(typ, typ_vars, at)? == *expected {
+        Ok(())
+    } else {
+        Err(TypeError {
+            title: "Kind mismatch",
+            annot_type: AnnotationType::Error,
+            annotations: vec![SourceAnnotation {
+                range: typ.span.unwrap_or(at),
+                label: "this type doesn't have the expected kind",
+                annotation_type: AnnotationType::Error,
+            }],
+        })
+    }
+}
+
+/** Kind checks every annotation in `expr`. Annotations of values must be proper types of kind `*`.
+Type arguments are checked against their binders later, once the type of the function is known */
+pub fn check_kinds(expr: &Expr, typ_vars: &Kinds) -> Result<(), TypeError> {
+    grow(|| {
+        use RawExpr::*;
+        let at = expr.span.unwrap_or_default();
+        let star = |t: &Type| check_kind(t, &Kind::Star, typ_vars, at);
+        match &expr.expr {
+            Con { .. } | Var { .. } => Ok(()),
+            Let { exp, body, .. } | EApp { exp, arg: body } | Open { exp, body } => {
+                check_kinds(exp, typ_vars)?;
+                check_kinds(body, typ_vars)
+            }
+            Binop { lhs, rhs, .. } => {
+                check_kinds(lhs, typ_vars)?;
+                check_kinds(rhs, typ_vars)
+            }
+            Fix { funcs, body } => {
+                for (_, _, typ, ret, def) in funcs {
+                    star(typ)?;
+                    star(ret)?;
+                    check_kinds(def, typ_vars)?
+                }
+                check_kinds(body, typ_vars)
+            }
+            TApp { exp, arg } => {
+                kind_of(arg, typ_vars, at)?;
+                check_kinds(exp, typ_vars)
+            }
+            Tuple { entries } => entries.iter().try_for_each(|e| check_kinds(e, typ_vars)),
+            Lambda {
+                arg: (_, typ),
+                body,
+            } => {
+                star(typ)?;
+                check_kinds(body, typ_vars)
+            }
+            Any { arg, kind, body } => {
+                check_kinds(body, &typ_vars.update(arg.name.clone(), kind.clone()))
+            }
+            If {
+                cond,
+                branch_t,
+                branch_f,
+            } => {
+                check_kinds(cond, typ_vars)?;
+                check_kinds(branch_t, typ_vars)?;
+                check_kinds(branch_f, typ_vars)
+            }
+            Case { exp, arms } => {
+                check_kinds(exp, typ_vars)?;
+                arms.iter().try_for_each(|(_, e)| check_kinds(e, typ_vars))
+            }
+            RawExpr::Record { fields } => fields
+                .iter()
+                .try_for_each(|(_, e)| check_kinds(e, typ_vars)),
+            RawExpr::Proj { exp, .. }
+            | RawExpr::Nth { exp, .. }
+            | Unfold { exp }
+            | RawExpr::Ref { exp }
+            | Deref { exp }
+            | Spawn { exp }
+            | Join { exp }
+            | Convert { exp, .. } => check_kinds(exp, typ_vars),
+            Assign { lhs, rhs } | AssertEq { lhs, rhs } => {
+                check_kinds(lhs, typ_vars)?;
+                check_kinds(rhs, typ_vars)
+            }
+            RawExpr::Sub { arr, idx } => {
+                check_kinds(arr, typ_vars)?;
+                check_kinds(idx, typ_vars)
+            }
+            RawExpr::Array { len, init } => {
+                check_kinds(len, typ_vars)?;
+                check_kinds(init, typ_vars)
+            }
+            Update { arr, idx, val } => {
+                check_kinds(arr, typ_vars)?;
+                check_kinds(idx, typ_vars)?;
+                check_kinds(val, typ_vars)
+            }
+            Raise { exp, typ } => {
+                star(typ)?;
+                check_kinds(exp, typ_vars)
+            }
+            Handle { exp, handler, .. } => {
+                check_kinds(exp, typ_vars)?;
+                check_kinds(handler, typ_vars)
+            }
+            Instance { typ, .. } => star(typ),
+            Fold { typ, exp } | Ascribe { exp, typ } => {
+                star(typ)?;
+                check_kinds(exp, typ_vars)
+            }
+            Pack { witness, exp, typ } => {
+                star(witness)?;
+                star(typ)?;
+                check_kinds(exp, typ_vars)
+            }
+            Unpack {
+                tvar, exp, body, ..
+            } => {
+                check_kinds(exp, typ_vars)?;
+                check_kinds(body, &typ_vars.update(tvar.name.clone(), Kind::Star))
+            }
+        }
+    })
+}
+
+/** Whether the type variable `tvar` occurs free in `typ` */
+pub fn free_in(tvar: &str, typ: &RawType) -> bool {
+    use RawType::*;
+    match typ {
+        TVar(v) => v == tvar,
+        Prod(ts) => ts.iter().any(|t| free_in(tvar, t)),
+        Data(id, ts) => id == tvar || ts.iter().any(|t| free_in(tvar, t)),
+        Arrow(t1, t2) | App(t1, t2) => free_in(tvar, t1) || free_in(tvar, t2),
+        Ref(t) | Array(t) | Future(t) => free_in(tvar, t),
+        Forall(v, _, t) | Lam(v, _, t) | Rec(v, t) | Exists(v, t) => {
+            v.name != tvar && free_in(tvar, t)
+        }
+        Record(fields) => fields.iter().any(|(_, t)| free_in(tvar, t)),
+        Int | Sized(_) | Bool | Unit | Str | Hole | Meta(_) => false,
+    }
+}
+
+/** A name for the type variable `v` free in none of `typs` */
+fn fresh_var(v: &str, typs: &[&RawType]) -> String {
+    (1..)
+        .map(|i| format!("{v}{i}"))
+        .find(|name| typs.iter().all(|t| !free_in(name, t)))
+        .unwrap()
+}
+
+/** Renames the type variable `tvar` in the types of `val_ctxt` and in `typs`, before binding
+`tvar` again so that the new binder doesn't capture it. The new name is free in none of them and
+not `taken`.
+Returns: The renamed context and the new name, or `None` if `tvar` is free in none of the types */
+pub fn rename_shadowed(
+    tvar: &str,
+    val_ctxt: &Context,
+    typs: &mut [&mut RawType],
+    taken: impl Fn(&str) -> bool,
+) -> Option<(Context, String)> {
+    let free = |name: &str| {
+        val_ctxt.values().any(|t| free_in(name, t)) || typs.iter().any(|t| free_in(name, t))
+    };
+    if !free(tvar) {
+        return None;
+    }
+    let fresh = (1..)
+        .map(|i| format!("{tvar}{i}"))
+        .find(|name| !taken(name) && !free(name))
+        .unwrap();
+    let renamed = RawType::TVar(fresh.clone());
+    for t in typs.iter_mut() {
+        substitute(tvar, &renamed, t)
+    }
+    let ctxt = val_ctxt
+        .iter()
+        .map(|(x, t)| {
+            let mut t = t.clone();
+            substitute(tvar, &renamed, &mut t);
+            (x.clone(), t)
+        })
+        .collect();
+    Some((ctxt, fresh))
+}
+
+/** Rejects annotations whose holes couldn't be filled from the surrounding types.
+Left out annotations have no span of their own, so they are reported `at` the binder instead */
+fn unfilled_hole(typ: &Type, at: Option<Span>) -> Result<(), TypeError> {
+    if typ.has_hole() {
+        Err(TypeError {
+            title: "Cannot infer type hole",
+            annot_type: AnnotationType::Error,
+            annotations: vec![SourceAnnotation {
+                range: typ.span.or(at).unwrap(),
+                label: "no expected type is known here, write the type explicitly",
+                annotation_type: AnnotationType::Error,
+            }],
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/** Rejects records and record patterns that give the same field twice */
+fn distinct_fields<'a>(labels: impl Iterator<Item = &'a Ident>) -> Result<(), TypeError> {
+    let mut seen = HashSet::new();
+    for l in labels {
+        if seen.insert(l.name.clone()).is_some() {
+            return Err(TypeError {
+                title: "Duplicate record field",
+                annot_type: AnnotationType::Error,
+                annotations: vec![SourceAnnotation {
+                    range: l.span.unwrap(),
+                    label: "this field is already given",
+                    annotation_type: AnnotationType::Error,
+                }],
+            });
+        }
+    }
+    Ok(())
+}
+
+/** Equivalence of types, compared in beta-normal form up to the names of bound type variables.
+Records are structural, so the order of their fields doesn't matter. */
+pub fn equivalent(typ1: &RawType, typ2: &RawType) -> bool {
+    alpha_equivalent(&normalize(typ1), &normalize(typ2))
+}
+
+/** Whether `typ1` and `typ2` are the same up to the names of their bound type variables,
+ex. `∀ A. A -> A` and `∀ B. B -> B`. Type operators aren't applied, see [`equivalent`] */
+pub fn alpha_equivalent(typ1: &RawType, typ2: &RawType) -> bool {
+    equal(typ1, typ2, &[])
+}
+
+/** Structural equality of types, where `bound` pairs up the type variables bound around
+`typ1` with those bound around `typ2`, innermost last */
+fn equal(typ1: &RawType, typ2: &RawType, bound: &[(&str, &str)]) -> bool {
     use RawType::*;
+    let eq = |t1: &RawType, t2: &RawType| equal(t1, t2, bound);
+    // Bound variables correspond when bound together, free ones when they have the same name
+    let same = |v1: &str, v2: &str| match bound.iter().rev().find(|(b1, b2)| *b1 == v1 || *b2 == v2)
+    {
+        Some(pair) => *pair == (v1, v2),
+        None => v1 == v2,
+    };
+    let under = |v1: &Ident, v2: &Ident, b1: &RawType, b2: &RawType| {
+        equal(
+            b1,
+            b2,
+            &[bound, &[(v1.name.as_str(), v2.name.as_str())]].concat(),
+        )
+    };
     match (typ1, typ2) {
-        (Int, Int) | (Bool, Bool) | (Unit, Unit) => true,
-        (TVar(v1), TVar(v2)) => v1 == v2,
-        (Prod(ts1), Prod(ts2)) => ts1
-            .iter()
-            .zip(ts2.iter())
-            .all(|(t1, t2)| equivalent(t1, t2)),
-        (Arrow(a1, b1), Arrow(a2, b2)) => equivalent(a1, a2) && equivalent(b1, b2),
-        (Forall(tv1, b1), Forall(tv2, b2)) => tv1.name == tv2.name && equivalent(b1, b2),
+        (Int, Int) | (Bool, Bool) | (Unit, Unit) | (Str, Str) => true,
+        (Sized(w1), Sized(w2)) => w1 == w2,
+        (TVar(v1), TVar(v2)) => same(v1, v2),
+        (Meta(m1), Meta(m2)) => m1 == m2,
+        (Prod(ts1), Prod(ts2)) => {
+            ts1.len() == ts2.len() && zip(ts1, ts2).all(|(t1, t2)| eq(t1, t2))
+        }
+        (Arrow(a1, b1), Arrow(a2, b2)) => eq(a1, a2) && eq(b1, b2),
+        (Ref(t1), Ref(t2)) | (Array(t1), Array(t2)) | (Future(t1), Future(t2)) => eq(t1, t2),
+        (Forall(tv1, k1, b1), Forall(tv2, k2, b2)) | (Lam(tv1, k1, b1), Lam(tv2, k2, b2)) => {
+            k1 == k2 && under(tv1, tv2, b1, b2)
+        }
+        (Rec(tv1, b1), Rec(tv2, b2)) | (Exists(tv1, b1), Exists(tv2, b2)) => {
+            under(tv1, tv2, b1, b2)
+        }
+        (App(f1, a1), App(f2, a2)) => eq(f1, f2) && eq(a1, a2),
+        // Heads may be type variables of higher kinds
+        (Data(id1, ts1), Data(id2, ts2)) => {
+            same(id1, id2) && ts1.len() == ts2.len() && zip(ts1, ts2).all(|(t1, t2)| eq(t1, t2))
+        }
+        (Record(fs1), Record(fs2)) => {
+            fs1.len() == fs2.len()
+                && fs1
+                    .iter()
+                    .all(|(l1, t1)| fs2.iter().any(|(l2, t2)| l1.name == l2.name && eq(t1, t2)))
+        }
         _ => false,
     }
 }
 
-/** Returns `Ok(())` and inserts relevant bindings to `ctxt` when no duplicates,
-or `Err(te)` where te is the type error, when yes duplicates.
-Adds binding to `ctxt` along the way
+/** The type of the literal `c` */
+pub fn constant_type(c: &Constant) -> RawType {
+    match c {
+        Constant::Integer(_) => RawType::Int,
+        Constant::Sized(_, w) => RawType::Sized(*w),
+        Constant::Boolean(_) => RawType::Bool,
+        Constant::Null => RawType::Unit,
+        Constant::Str(_) => RawType::Str,
+    }
+}
+
+/** Whether the patterns `pats` together match every value of type `typ`.
+Assumes every pattern has already been checked against `typ` under `ctxt`. */
+fn exhaustive(pats: &[&RawPattern], typ: &RawType, ctxt: &Context) -> bool {
+    let matrix: Vec<Vec<&RawPattern>> = pats.iter().map(|p| vec![*p]).collect();
+    !useful(ctxt, &matrix, &[&WILDCARD], std::slice::from_ref(typ))
+}
+
+/** Spans of the patterns in `pats` that match no value of type `typ` left unmatched by the patterns before them */
+pub fn unreachable_arms(pats: &[Pattern], typ: &RawType, ctxt: &Context) -> Vec<Span> {
+    let mut matrix: Vec<Vec<&RawPattern>> = vec![];
+    let mut spans = vec![];
+    for p in pats {
+        if !useful(ctxt, &matrix, &[&p.pat], std::slice::from_ref(typ)) {
+            spans.push(p.span.unwrap())
+        }
+        matrix.push(vec![&p.pat])
+    }
+    spans
+}
+
+static WILDCARD: RawPattern = RawPattern::Wildcard;
+
+/** Shapes of values that patterns can distinguish */
+enum Head {
+    Lit(Constant),
+    Tuple,
+    Ctor(String),
+    /// Records with the given fields, in the order of the record type
+    Record(Vec<String>),
+}
+
+/** Whether some value matched by the pattern vector `row` is matched by no row in `matrix`.
 # Arguments
- * `pat`: The pattern to check
- * `vars`: Set of vars already seen in `pat`
- * `ctxt`: The typing Context
- * `typ` : The type to be destructed */
-fn traverse_pat(
-    pat: &Pattern,
-    vars: &mut HashSet<String>,
-    ctxt: &mut Context,
-    typ: &RawType,
-) -> Result<(), TypeError> {
-    match &pat.pat {
-        RawPattern::Binding(ident) => {
-            let seen = vars.insert(ident.name.clone());
-            if seen.is_some() {
-                Err(TypeError {
-                    title: "Conflicting argument names",
-                    annot_type: AnnotationType::Error,
-                    annotations: vec![SourceAnnotation {
-                        range: pat.span.unwrap(),
-                        label: "variable bound multiple times in pattern",
-                        annotation_type: AnnotationType::Error,
-                    }],
-                })
-            } else {
-                ctxt.insert(ident.name.clone(), typ.clone());
-                Ok(())
-            }
+ * `ctxt`: The typing context, used to look up constructors
+ * `matrix`: Rows of patterns, each as long as `row`
+ * `row`: The pattern vector to test
+ * `typs`: The types of each column */
+fn useful(
+    ctxt: &Context,
+    matrix: &[Vec<&RawPattern>],
+    row: &[&RawPattern],
+    typs: &[RawType],
+) -> bool {
+    use RawPattern::*;
+    if row.is_empty() {
+        return matrix.is_empty();
+    }
+    let (head, rest) = (row[0], &row[1..]);
+    let specialize_row = |h: &Head, pats: Vec<&RawPattern>| {
+        let fields = head_fields(ctxt, h, &typs[0]);
+        let spec = specialize(matrix, h, fields.len());
+        let row1: Vec<&RawPattern> = pats.into_iter().chain(rest.iter().copied()).collect();
+        let typs1: Vec<RawType> = fields
+            .into_iter()
+            .chain(typs[1..].iter().cloned())
+            .collect();
+        useful(ctxt, &spec, &row1, &typs1)
+    };
+    match head {
+        Literal(c) => specialize_row(&Head::Lit(c.clone()), vec![]),
+        Tuple(pats) => specialize_row(&Head::Tuple, pats.iter().map(|p| &p.pat).collect()),
+        Ctor(c, pats) => specialize_row(
+            &Head::Ctor(c.name.clone()),
+            pats.iter().map(|p| &p.pat).collect(),
+        ),
+        Record(pats) => {
+            let names = field_names(&typs[0]);
+            let fields = record_row(pats, &names);
+            specialize_row(&Head::Record(names), fields)
         }
-        RawPattern::Wildcard => Ok(()),
-        RawPattern::Tuple(pats) =>
-            match typ {
-                RawType::Prod(ts) if pats.len() == ts.len() => {
-                    zip(pats.iter(), ts.iter())
-                        .map(|(p, t)| traverse_pat(p, vars, ctxt, t))
-                        .collect::<Result<Vec<()>, TypeError>>()?;
-                    Ok(())
+        Wildcard | Binding(_) => match signature(ctxt, &typs[0]) {
+            Some(heads) if heads.iter().all(|h| column_has(matrix, h)) => heads.iter().any(|h| {
+                let arity = head_fields(ctxt, h, &typs[0]).len();
+                specialize_row(h, vec![&WILDCARD; arity])
+            }),
+            _ => useful(ctxt, &default_matrix(matrix), rest, &typs[1..]),
+        },
+    }
+}
+
+/** All the heads of values of type `typ`, or `None` if there are infinitely many */
+fn signature(ctxt: &Context, typ: &RawType) -> Option<Vec<Head>> {
+    match typ {
+        RawType::Bool => Some(vec![
+            Head::Lit(Constant::Boolean(true)),
+            Head::Lit(Constant::Boolean(false)),
+        ]),
+        RawType::Unit => Some(vec![Head::Lit(Constant::Null)]),
+        RawType::Prod(_) => Some(vec![Head::Tuple]),
+        RawType::Record(_) => Some(vec![Head::Record(field_names(typ))]),
+        // More exceptions may be declared later on
+        RawType::Data(id, _) if id == EXN => None,
+        RawType::Data(id, _) => Some(
+            datatype_ctors(ctxt, id)
+                .into_iter()
+                .map(Head::Ctor)
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/** Whether some row of `matrix` has head `h` in its first column */
+fn column_has(matrix: &[Vec<&RawPattern>], h: &Head) -> bool {
+    matrix.iter().any(|r| match (r[0], h) {
+        (RawPattern::Literal(c1), Head::Lit(c)) => c1 == c,
+        (RawPattern::Tuple(_), Head::Tuple) => true,
+        (RawPattern::Ctor(c1, _), Head::Ctor(c)) => &c1.name == c,
+        (RawPattern::Record(_), Head::Record(_)) => true,
+        _ => false,
+    })
+}
+
+/** Types of the fields of values of type `typ` with head `h` */
+fn head_fields(ctxt: &Context, h: &Head, typ: &RawType) -> Vec<RawType> {
+    match (h, typ) {
+        (Head::Tuple, RawType::Prod(ts)) => ts.iter().map(|t| t.typ.clone()).collect(),
+        (Head::Ctor(c), _) => ctor_fields(ctxt, c, typ).unwrap_or_default(),
+        (Head::Record(_), RawType::Record(fs)) => fs.iter().map(|(_, t)| t.typ.clone()).collect(),
+        _ => vec![],
+    }
+}
+
+/** Rows of `matrix` matching head `h` with `arity` fields, the fields replacing the first column */
+fn specialize<'a>(
+    matrix: &[Vec<&'a RawPattern>],
+    h: &Head,
+    arity: usize,
+) -> Vec<Vec<&'a RawPattern>> {
+    matrix
+        .iter()
+        .filter_map(|r| {
+            let fields: Vec<&RawPattern> = match (r[0], h) {
+                (RawPattern::Wildcard | RawPattern::Binding(_), _) => vec![&WILDCARD; arity],
+                (RawPattern::Literal(c1), Head::Lit(c)) if c1 == c => vec![],
+                (RawPattern::Tuple(pats), Head::Tuple) => pats.iter().map(|p| &p.pat).collect(),
+                (RawPattern::Ctor(c1, pats), Head::Ctor(c)) if &c1.name == c => {
+                    pats.iter().map(|p| &p.pat).collect()
                 }
-                _ => Err(TypeError {
-                    title: "Malformed pattern assignment",
-                    annot_type: AnnotationType::Error,
-                    annotations: vec![SourceAnnotation {
-                        range: pat.span.unwrap(),
-                        label: "pattern expected with same number of entries as product type",
-                        annotation_type: AnnotationType::Error,
-                    }],
-                })
+                (RawPattern::Record(pats), Head::Record(names)) => record_row(pats, names),
+                _ => return None,
+            };
+            Some(fields.into_iter().chain(r[1..].iter().copied()).collect())
+        })
+        .collect()
+}
+
+/** Names of the fields of the record type `typ`, in order */
+fn field_names(typ: &RawType) -> Vec<String> {
+    match typ {
+        RawType::Record(fields) => fields.iter().map(|(l, _)| l.name.clone()).collect(),
+        _ => vec![],
+    }
+}
+
+/** The sub-patterns of the record pattern `pats` for the fields `names`, wildcards for the ones left out */
+fn record_row<'a>(pats: &'a [(Ident, Pattern)], names: &[String]) -> Vec<&'a RawPattern> {
+    names
+        .iter()
+        .map(|n| match pats.iter().find(|(l, _)| &l.name == n) {
+            Some((_, p)) => &p.pat,
+            None => &WILDCARD,
+        })
+        .collect()
+}
+
+/** Rows of `matrix` whose first column matches anything, without that column */
+fn default_matrix<'a>(matrix: &[Vec<&'a RawPattern>]) -> Vec<Vec<&'a RawPattern>> {
+    matrix
+        .iter()
+        .filter(|r| matches!(r[0], RawPattern::Wildcard | RawPattern::Binding(_)))
+        .map(|r| r[1..].to_vec())
+        .collect()
+}
+
+/** Names of the constructors of the data type `id` in `ctxt`, sorted.
+Constructors are the capitalized variables whose type ultimately returns the data type. */
+fn datatype_ctors(ctxt: &Context, id: &str) -> Vec<String> {
+    let mut ctors: Vec<String> = ctxt
+        .iter()
+        .filter(|(name, typ)| {
+            name.starts_with(|c: char| c.is_ascii_uppercase())
+                && matches!(result_type(typ), RawType::Data(d, _) if d == id)
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    ctors.sort();
+    ctors
+}
+
+/** The type eventually returned by `typ` after all type and value arguments */
+fn result_type(mut typ: &RawType) -> &RawType {
+    loop {
+        match typ {
+            RawType::Forall(_, _, t) | RawType::Arrow(_, t) => typ = t,
+            _ => return typ,
+        }
+    }
+}
+
+/** Field types of the constructor `ctor` when building a value of type `typ`,
+or `None` if `ctor` doesn't build values of `typ` */
+fn ctor_fields(ctxt: &Context, ctor: &str, typ: &RawType) -> Option<Vec<RawType>> {
+    let RawType::Data(id, args) = typ else {
+        return None;
+    };
+    let mut ctor_typ = ctxt.get(ctor)?;
+    let mut params = vec![];
+    while let RawType::Forall(v, _, t) = ctor_typ {
+        params.push(v.name.clone());
+        ctor_typ = t;
+    }
+    if params.len() != args.len()
+        || !matches!(result_type(ctor_typ), RawType::Data(d, _) if d == id)
+    {
+        return None;
+    }
+    let mut fields = vec![];
+    while let RawType::Arrow(t1, t2) = ctor_typ {
+        fields.push(instantiate(t1, &params, args));
+        ctor_typ = t2;
+    }
+    Some(fields)
+}
+
+/** Simultaneously substitutes `args` for the type variables `params` in `typ` */
+fn instantiate(typ: &RawType, params: &[String], args: &[Type]) -> RawType {
+    use RawType::*;
+    let inst = |t: &Type| Type::new(instantiate(t, params, args));
+    match typ {
+        TVar(v) => match params.iter().position(|p| p == v) {
+            Some(i) => args[i].typ.clone(),
+            None => typ.clone(),
+        },
+        Prod(ts) => Prod(ts.iter().map(inst).collect()),
+        Arrow(t1, t2) => Arrow(Box::new(inst(t1)), Box::new(inst(t2))),
+        Ref(t) => Ref(Box::new(inst(t))),
+        Array(t) => Array(Box::new(inst(t))),
+        Future(t) => Future(Box::new(inst(t))),
+        Forall(v, _, t) | Lam(v, _, t) | Rec(v, t) | Exists(v, t) => {
+            let (ps, ars): (Vec<String>, Vec<Type>) = params
+                .iter()
+                .cloned()
+                .zip(args.iter().cloned())
+                .filter(|(p, _)| p != &v.name)
+                .unzip();
+            let body = Box::new(Type::new(instantiate(t, &ps, &ars)));
+            match typ {
+                Forall(_, k, _) => Forall(v.clone(), k.clone(), body),
+                Lam(_, k, _) => Lam(v.clone(), k.clone(), body),
+                Rec(..) => Rec(v.clone(), body),
+                _ => Exists(v.clone(), body),
             }
+        }
+        Data(id, ts) => Data(id.clone(), ts.iter().map(inst).collect()),
+        App(t1, t2) => App(Box::new(inst(t1)), Box::new(inst(t2))),
+        Record(fs) => Record(fs.iter().map(|(l, t)| (l.clone(), inst(t))).collect()),
+        Int | Sized(_) | Bool | Unit | Str | Hole | Meta(_) => typ.clone(),
     }
 }
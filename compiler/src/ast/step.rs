@@ -0,0 +1,524 @@
+/*! Small-step evaluation by substitution, one reduction at a time, for tracing how a type checked
+and lowered expression evaluates. Reduction is call by value from left to right like [`eval`],
+but values are expressions: constants, functions, type abstractions, and tuples, records and
+constructors applied to values. Names not bound in the expression, like constructors and earlier
+declarations, are values too. Types are erased: `fold`, `unfold` and `pack` reduce to their
+argument, and type applications of type abstractions to their body.
+Applications of the builtins, references and arrays read and write a [`Heap`]: the builtins the
+input and output of a store of [`eval`], and references and arrays the cells of the heap, whose
+locations like `ℓ0` are values like names. On a [pure](Heap::pure) heap they don't reduce.
+
+[`eval`]: crate::ast::interp */
+
+use crate::ast::ast::{Binary, Constant, Expr, RawExpr, RawPattern, RawType, Type, DIV, SUBSCRIPT};
+use crate::ast::interp::{builtin, same_constant, Store, Value};
+use crate::ast::semant::BUILTINS;
+
+/// What evaluation reads and writes: the input and output of the builtins, and the cells of
+/// references and arrays
+pub struct Heap<'a> {
+    /// The store the builtins read and write through, `None` while evaluation is pure
+    io: Option<Store<'a>>,
+    /// The values in the cells by location, one for references and one per element for arrays
+    cells: Vec<Vec<Expr>>,
+}
+
+impl<'a> Heap<'a> {
+    /** An empty heap, the builtins reading and writing through `io` */
+    pub fn new(io: Store<'a>) -> Self {
+        Heap {
+            io: Some(io),
+            cells: vec![],
+        }
+    }
+
+    /** An empty heap on which the builtins, references and arrays are stuck */
+    pub fn pure() -> Self {
+        Heap {
+            io: None,
+            cells: vec![],
+        }
+    }
+
+    /** Allocates a cell holding `vals`. Returns: Its location, unless the heap is pure */
+    fn alloc(&mut self, vals: Vec<Expr>) -> Option<Expr> {
+        self.io.as_ref()?;
+        self.cells.push(vals);
+        let id = format!("ℓ{}", self.cells.len() - 1);
+        Some(Expr::new(RawExpr::Var { id }))
+    }
+
+    /** The cell at the location `loc`, unless the heap is pure */
+    fn cell(&mut self, loc: &Expr) -> Option<&mut Vec<Expr>> {
+        self.io.as_ref()?;
+        match &loc.expr {
+            RawExpr::Var { id } => self
+                .cells
+                .get_mut(id.strip_prefix('ℓ')?.parse::<usize>().ok()?),
+            _ => None,
+        }
+    }
+}
+
+/** Reduces `expr` `limit` times at most, reading and writing `heap`.
+Returns: The expressions `expr` reduces to, in order. The last one is a value, a raised exception,
+or stuck if there are fewer than `limit` */
+pub fn trace(expr: &Expr, limit: usize, heap: &mut Heap) -> Vec<Expr> {
+    let mut steps: Vec<Expr> = vec![];
+    while steps.len() < limit {
+        match step(steps.last().unwrap_or(expr), heap) {
+            Some(next) => steps.push(next),
+            None => break,
+        }
+    }
+    steps
+}
+
+/** Reduces the leftmost innermost redex of `expr`, reading and writing `heap`.
+Returns: The reduced expression, or `None` if `expr` is a value, a raised exception, or stuck */
+pub fn step(expr: &Expr, heap: &mut Heap) -> Option<Expr> {
+    if let RawExpr::Handle { exp, pat, handler } = &expr.expr {
+        if let Some(exn) = raised(exp) {
+            let mut binds = vec![];
+            if !matches(exn, &pat.pat, &mut binds) {
+                return Some((**exp).clone());
+            }
+            return Some(substituted(handler, &binds));
+        }
+        if is_value(exp) {
+            return Some((**exp).clone());
+        }
+        let mut next = expr.clone();
+        if let RawExpr::Handle { exp, .. } = &mut next.expr {
+            **exp = step(exp, heap)?
+        }
+        return Some(next);
+    }
+    // Congruence, an exception raised in an evaluated position is raised by the whole expression
+    let mut next = expr.clone();
+    for sub in evaluated_mut(&mut next.expr) {
+        if raised(sub).is_some() {
+            return Some(sub.clone());
+        }
+        if !is_value(sub) {
+            *sub = step(sub, heap)?;
+            return Some(next);
+        }
+    }
+    reduce(expr, heap)
+}
+
+/** The subexpression of `expr` that [`step`] reduces next, or is stuck on */
+pub fn redex_mut(expr: &mut Expr) -> &mut Expr {
+    // The index of the evaluated subexpression to go into. An exception raised in an evaluated
+    // position is raised by `expr` itself
+    let next = match &expr.expr {
+        RawExpr::Handle { exp, .. } => (!is_value(exp) && raised(exp).is_none()).then_some(0),
+        _ => {
+            let subs = evaluated_mut(&mut expr.expr);
+            subs.iter()
+                .position(|sub| !is_value(sub))
+                .filter(|&i| raised(subs[i]).is_none())
+        }
+    };
+    let Some(i) = next else {
+        return expr;
+    };
+    match &mut expr.expr {
+        RawExpr::Handle { exp, .. } => redex_mut(exp),
+        e => redex_mut(evaluated_mut(e).swap_remove(i)),
+    }
+}
+
+/** Whether `expr` is a value */
+pub fn is_value(expr: &Expr) -> bool {
+    use RawExpr::*;
+    match &expr.expr {
+        Con { .. } | Var { .. } | Lambda { .. } | Any { .. } => true,
+        Tuple { entries } => entries.iter().all(is_value),
+        Record { fields } => fields.iter().all(|(_, e)| is_value(e)),
+        EApp { exp, arg } => is_data(exp) && is_value(arg),
+        TApp { exp, .. } => is_data(exp),
+        _ => false,
+    }
+}
+
+/** Whether `expr` is a constructor applied to values and types */
+fn is_data(expr: &Expr) -> bool {
+    match &expr.expr {
+        RawExpr::Var { id } => !is_builtin(id),
+        RawExpr::TApp { exp, .. } => is_data(exp),
+        RawExpr::EApp { exp, arg } => is_data(exp) && is_value(arg),
+        _ => false,
+    }
+}
+
+/** Whether `id` names a builtin, unless it's bound */
+fn is_builtin(id: &str) -> bool {
+    BUILTINS.iter().any(|(builtin, _)| *builtin == id)
+}
+
+/** The value of `expr` if it's a constant or a tuple of those, like the arguments of builtins */
+fn constant_value(expr: &Expr) -> Option<Value> {
+    match &expr.expr {
+        RawExpr::Con { val } => Some(Value::VConst(val.clone())),
+        RawExpr::Tuple { entries } => {
+            let vals: Option<Vec<Value>> = entries.iter().map(constant_value).collect();
+            Some(Value::VTuple(vals?.into()))
+        }
+        _ => None,
+    }
+}
+
+/** The exception `expr` raises, if it's `raise v` for a value `v` */
+pub(crate) fn raised(expr: &Expr) -> Option<&Expr> {
+    match &expr.expr {
+        RawExpr::Raise { exp, .. } if is_value(exp) => Some(exp),
+        _ => None,
+    }
+}
+
+/** The constructor of the value `expr` and its arguments, without the types it's applied to */
+fn spine(expr: &Expr) -> Option<(&str, Vec<&Expr>)> {
+    match &expr.expr {
+        RawExpr::Var { id } => Some((id, vec![])),
+        RawExpr::TApp { exp, .. } => spine(exp),
+        RawExpr::EApp { exp, arg } => {
+            let (ctor, mut args) = spine(exp)?;
+            args.push(arg);
+            Some((ctor, args))
+        }
+        _ => None,
+    }
+}
+
+/** The subexpressions of `expr` evaluated before it reduces, in order */
+pub(crate) fn evaluated_mut(expr: &mut RawExpr) -> Vec<&mut Expr> {
+    use RawExpr::*;
+    match expr {
+        Let { exp, .. }
+        | TApp { exp, .. }
+        | Proj { exp, .. }
+        | Nth { exp, .. }
+        | Case { exp, .. }
+        | Fold { exp, .. }
+        | Unfold { exp }
+        | Convert { exp, .. }
+        | Pack { exp, .. }
+        | Unpack { exp, .. }
+        | Open { exp, .. }
+        | Ref { exp }
+        | Deref { exp }
+        | Raise { exp, .. } => vec![exp],
+        If { cond, .. } => vec![cond],
+        EApp { exp, arg } => vec![exp, arg],
+        Binop { lhs, rhs, .. } | Assign { lhs, rhs } | AssertEq { lhs, rhs } => vec![lhs, rhs],
+        Array { len, init } => vec![len, init],
+        Sub { arr, idx } => vec![arr, idx],
+        Update { arr, idx, val } => vec![arr, idx, val],
+        Tuple { entries } => entries.iter_mut().collect(),
+        Record { fields } => fields.iter_mut().map(|(_, e)| e).collect(),
+        _ => vec![],
+    }
+}
+
+/** Reduces `expr` whose evaluated subexpressions are values, reading and writing `heap` */
+fn reduce(expr: &Expr, heap: &mut Heap) -> Option<Expr> {
+    use RawExpr::*;
+    let null = || {
+        Expr::new(Con {
+            val: Constant::Null,
+        })
+    };
+    let raise = |exn: &str| Expr {
+        expr: Raise {
+            exp: Box::new(Expr::new(Var {
+                id: exn.to_string(),
+            })),
+            typ: Type::new(RawType::Hole),
+        },
+        span: expr.span,
+    };
+    match &expr.expr {
+        Let { pat, exp, body } => {
+            let mut binds = vec![];
+            matches(exp, &pat.pat, &mut binds).then(|| substituted(body, &binds))
+        }
+        // Each function calls the others through a copy of the whole `fix`
+        Fix { funcs, body } => {
+            let binds: Vec<_> = funcs
+                .iter()
+                .map(|(f, v, t, _, e)| {
+                    let fix = Fix {
+                        funcs: funcs.clone(),
+                        body: Box::new(e.clone()),
+                    };
+                    let lambda = Lambda {
+                        arg: (v.clone(), t.clone()),
+                        body: Box::new(Expr::new(fix)),
+                    };
+                    (f.name.clone(), Expr::new(lambda))
+                })
+                .collect();
+            Some(substituted(body, &binds))
+        }
+        EApp { exp, arg } => match &exp.expr {
+            Lambda { arg: (x, _), body } => {
+                Some(substituted(body, &[(x.name.clone(), *arg.clone())]))
+            }
+            // Failed assertions and errors of input and output are stuck
+            Var { id } if is_builtin(id) => {
+                let val = builtin(id, constant_value(arg)?, heap.io.as_mut()?).ok()?;
+                Some(Expr::new(Con { val }))
+            }
+            _ => None,
+        },
+        TApp { exp, .. } => match &exp.expr {
+            Any { body, .. } => Some((**body).clone()),
+            _ => None,
+        },
+        Proj { exp, field } => match &exp.expr {
+            Record { fields } => fields
+                .iter()
+                .find(|(l, _)| l.name == field.name)
+                .map(|(_, e)| e.clone()),
+            _ => None,
+        },
+        Nth { exp, index } => match &exp.expr {
+            Tuple { entries } => entries.get(*index).cloned(),
+            _ => None,
+        },
+        Binop { lhs, op, rhs, .. } => {
+            use Binary::*;
+            use Constant::*;
+            let val = match (op, &lhs.expr, &rhs.expr) {
+                (Eq | Ne, _, _) => Boolean(equal(lhs, rhs)? == (*op == Eq)),
+                (And, Con { val: Boolean(l) }, Con { val: Boolean(r) }) => Boolean(*l & *r),
+                (Or, Con { val: Boolean(l) }, Con { val: Boolean(r) }) => Boolean(*l | *r),
+                (_, Con { val: Integer(l) }, Con { val: Integer(r) }) => match op.apply_int(*l, *r)
+                {
+                    Some(c) => c,
+                    None => return Some(raise(DIV)),
+                },
+                (_, Con { val: Sized(l, w) }, Con { val: Sized(r, _) }) => {
+                    match op.apply_sized(*l, *r, *w) {
+                        Some(c) => c,
+                        None => return Some(raise(DIV)),
+                    }
+                }
+                _ => return None,
+            };
+            Some(Expr::new(Con { val }))
+        }
+        If {
+            cond,
+            branch_t,
+            branch_f,
+        } => match &cond.expr {
+            Con {
+                val: Constant::Boolean(b),
+            } => Some(if *b {
+                *branch_t.clone()
+            } else {
+                *branch_f.clone()
+            }),
+            _ => None,
+        },
+        Case { exp, arms } => arms.iter().find_map(|(pat, body)| {
+            let mut binds = vec![];
+            matches(exp, &pat.pat, &mut binds).then(|| substituted(body, &binds))
+        }),
+        Convert { exp, to, .. } => match &exp.expr {
+            Con { val } => Some(Expr::new(Con {
+                val: to.constant(to.wrap(val.as_int()?)),
+            })),
+            _ => None,
+        },
+        Fold { exp, .. } | Unfold { exp } | Pack { exp, .. } => Some((**exp).clone()),
+        Unpack { var, exp, body, .. } => {
+            Some(substituted(body, &[(var.name.clone(), *exp.clone())]))
+        }
+        Open { exp, body } => match &exp.expr {
+            Record { fields } => {
+                let binds: Vec<_> = fields
+                    .iter()
+                    .map(|(l, e)| (l.name.clone(), e.clone()))
+                    .collect();
+                Some(substituted(body, &binds))
+            }
+            _ => None,
+        },
+        Ref { exp } => heap.alloc(vec![(**exp).clone()]),
+        Deref { exp } => heap.cell(exp)?.first().cloned(),
+        Assign { lhs, rhs } => {
+            heap.cell(lhs)?[0] = (**rhs).clone();
+            Some(null())
+        }
+        Array { len, init } => match &len.expr {
+            Con {
+                val: Constant::Integer(n),
+            } => match usize::try_from(*n) {
+                Ok(n) => heap.alloc(vec![(**init).clone(); n]),
+                Err(_) => Some(raise(SUBSCRIPT)),
+            },
+            _ => None,
+        },
+        Sub { arr, idx } | Update { arr, idx, .. } => {
+            let Con {
+                val: Constant::Integer(i),
+            } = &idx.expr
+            else {
+                return None;
+            };
+            let cell = heap.cell(arr)?;
+            let Some(elem) = usize::try_from(*i).ok().and_then(|i| cell.get_mut(i)) else {
+                return Some(raise(SUBSCRIPT));
+            };
+            match &expr.expr {
+                Update { val, .. } => {
+                    *elem = (**val).clone();
+                    Some(null())
+                }
+                _ => Some(elem.clone()),
+            }
+        }
+        // Failed assertions are stuck
+        AssertEq { lhs, rhs } => equal(lhs, rhs)?.then(null),
+        _ => None,
+    }
+}
+
+/** Structural equality of the values `v1` and `v2`, or `None` if it can't be decided from them,
+like for functions */
+fn equal(v1: &Expr, v2: &Expr) -> Option<bool> {
+    use RawExpr::*;
+    match (&v1.expr, &v2.expr) {
+        (Con { val: c1 }, Con { val: c2 }) => Some(same_constant(c1, c2)),
+        (Tuple { entries: es1 }, Tuple { entries: es2 }) => all_equal(es1.iter().zip(es2)),
+        // Fields may come in any order
+        (Record { fields: fs1 }, Record { fields: fs2 }) => {
+            let pairs = fs1.iter().map(|(l, e1)| {
+                let (_, e2) = fs2.iter().find(|(l2, _)| l2.name == l.name)?;
+                Some((e1, e2))
+            });
+            all_equal(pairs.collect::<Option<Vec<_>>>()?.into_iter())
+        }
+        _ => {
+            let ((c1, args1), (c2, args2)) = (spine(v1)?, spine(v2)?);
+            Some(c1 == c2 && all_equal(args1.into_iter().zip(args2))?)
+        }
+    }
+}
+
+fn all_equal<'a>(mut pairs: impl Iterator<Item = (&'a Expr, &'a Expr)>) -> Option<bool> {
+    pairs.try_fold(true, |all, (v1, v2)| Some(all && equal(v1, v2)?))
+}
+
+/** Matches the value `val` against `pat`, pushing the bindings to `binds`.
+Returns: Whether the match succeeded */
+fn matches(val: &Expr, pat: &RawPattern, binds: &mut Vec<(String, Expr)>) -> bool {
+    match (&val.expr, pat) {
+        (_, RawPattern::Wildcard) => true,
+        (_, RawPattern::Binding(id)) => {
+            binds.push((id.name.clone(), val.clone()));
+            true
+        }
+        (RawExpr::Con { val }, RawPattern::Literal(lit)) => same_constant(val, lit),
+        (RawExpr::Tuple { entries }, RawPattern::Tuple(pats)) => entries
+            .iter()
+            .zip(pats)
+            .all(|(e, p)| matches(e, &p.pat, binds)),
+        (RawExpr::Record { fields }, RawPattern::Record(pats)) => {
+            pats.iter().all(
+                |(l, p)| match fields.iter().find(|(f, _)| f.name == l.name) {
+                    Some((_, e)) => matches(e, &p.pat, binds),
+                    None => false,
+                },
+            )
+        }
+        (_, RawPattern::Ctor(c, pats)) => match spine(val) {
+            Some((ctor, args)) => {
+                ctor == c.name
+                    && args.len() == pats.len()
+                    && args
+                        .into_iter()
+                        .zip(pats)
+                        .all(|(a, p)| matches(a, &p.pat, binds))
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/** `expr` with the variables of `binds` replaced by their closed values */
+pub(crate) fn substituted(expr: &Expr, binds: &[(String, Expr)]) -> Expr {
+    let mut expr = expr.clone();
+    for (x, val) in binds {
+        subst(&mut expr, x, val)
+    }
+    expr
+}
+
+/** Replaces the free occurrences of `x` in `expr` by the closed value `val` */
+fn subst(expr: &mut Expr, x: &str, val: &Expr) {
+    use RawExpr::*;
+    let binds = |pat: &RawPattern| pat.bindings().contains(&x);
+    match &mut expr.expr {
+        Var { id } if id == x => *expr = val.clone(),
+        Let { pat, exp, body } => {
+            subst(exp, x, val);
+            if !binds(&pat.pat) {
+                subst(body, x, val)
+            }
+        }
+        Lambda { arg: (y, _), body } => {
+            if y.name != x {
+                subst(body, x, val)
+            }
+        }
+        Fix { funcs, body } => {
+            if funcs.iter().any(|(f, ..)| f.name == x) {
+                return;
+            }
+            for (_, v, _, _, e) in funcs {
+                if v.name != x {
+                    subst(e, x, val)
+                }
+            }
+            subst(body, x, val)
+        }
+        Case { exp, arms } => {
+            subst(exp, x, val);
+            for (pat, body) in arms {
+                if !binds(&pat.pat) {
+                    subst(body, x, val)
+                }
+            }
+        }
+        Handle { exp, pat, handler } => {
+            subst(exp, x, val);
+            if !binds(&pat.pat) {
+                subst(handler, x, val)
+            }
+        }
+        Unpack { var, exp, body, .. } => {
+            subst(exp, x, val);
+            if var.name != x {
+                subst(body, x, val)
+            }
+        }
+        // The members of a structure are only known once it's a record
+        Open { exp, body } => {
+            subst(exp, x, val);
+            let bound = match &exp.expr {
+                Record { fields } => fields.iter().any(|(l, _)| l.name == x),
+                _ => false,
+            };
+            if !bound {
+                subst(body, x, val)
+            }
+        }
+        e => e.subexprs_mut().into_iter().for_each(|e| subst(e, x, val)),
+    }
+}
@@ -0,0 +1,145 @@
+/*! Frame lowering, laying out the frames of functions whose registers are allocated, and adding
+their prologue and epilogues. From the frame pointer `s0` down, which is the stack pointer of the
+caller and where the arguments passed on the stack start, frames hold the return address, the
+frame pointer of the caller, the slots of the function, the registers saved for the caller that the
+function changes, and the arguments of its calls passed on the stack, up from the stack pointer,
+kept aligned to 16 bytes. The registers saved are restored by the epilogues, before returns and
+calls in tail position, those calling a register saved through `t1` instead.
+
+Slots are addressed from the frame pointer and the arguments of calls from the stack pointer. Frames
+too large for the immediates of 12 bits are allocated in two steps, saving the return address and
+frame pointer first, and slots out of reach of the immediates are [legalized](crate::codegen::legalize)
+later. */
+
+use crate::codegen::isel::imm12;
+use crate::codegen::riscv::{Alu, Func, Inst, Reg, Target, CALLEE_SAVED, FP, RA, SP, T0, T1};
+
+/// The words of the return address and the frame pointer of the caller
+const LINKAGE: usize = 2;
+
+/// The layout of a frame
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    /// Its size in bytes, a multiple of 16
+    pub size: i32,
+    pub slots: usize,
+    /// The registers the callee saves that the function changes, in order
+    pub saved: Vec<Reg>,
+    /// The words of the arguments of calls passed on the stack
+    pub outgoing: usize,
+}
+
+impl Frame {
+    /** The layout of the frame of `func` */
+    pub fn new(func: &Func) -> Frame {
+        let saved = clobbered(func);
+        let words = LINKAGE + func.slots + saved.len() + func.outgoing;
+        Frame {
+            size: (4 * words as i32 + 15) & !15,
+            slots: func.slots,
+            saved,
+            outgoing: func.outgoing,
+        }
+    }
+
+    /** The offset of the word the register saved `index`th is saved in from the frame pointer */
+    fn save(&self, index: usize) -> i32 {
+        slot(self.slots + index)
+    }
+
+    /** Whether the frame is addressed with immediates of 12 bits only */
+    pub fn small(&self) -> bool {
+        imm12(self.size as i64)
+    }
+
+    fn prologue(&self) -> Vec<Inst> {
+        let size = self.size;
+        let mut insts = match self.small() {
+            true => vec![
+                Inst::OpImm(Alu::Add, SP, SP, -size),
+                Inst::Sw(RA, size - 4, SP),
+                Inst::Sw(FP, size - 8, SP),
+                Inst::OpImm(Alu::Add, FP, SP, size),
+            ],
+            false => vec![
+                Inst::OpImm(Alu::Add, SP, SP, -16),
+                Inst::Sw(RA, 12, SP),
+                Inst::Sw(FP, 8, SP),
+                Inst::OpImm(Alu::Add, FP, SP, 16),
+                Inst::Li(T0, 16 - size),
+                Inst::Op(Alu::Add, SP, SP, T0),
+            ],
+        };
+        for (index, reg) in self.saved.iter().enumerate() {
+            insts.push(Inst::Sw(*reg, self.save(index), FP))
+        }
+        insts
+    }
+
+    fn epilogue(&self) -> Vec<Inst> {
+        let size = self.size;
+        let mut insts = vec![];
+        for (index, reg) in self.saved.iter().enumerate() {
+            insts.push(Inst::Lw(*reg, self.save(index), FP))
+        }
+        insts.extend(match self.small() {
+            true => vec![
+                Inst::Lw(RA, size - 4, SP),
+                Inst::Lw(FP, size - 8, SP),
+                Inst::OpImm(Alu::Add, SP, SP, size),
+            ],
+            false => vec![
+                Inst::OpImm(Alu::Add, SP, FP, -16),
+                Inst::Lw(RA, 12, SP),
+                Inst::Lw(FP, 8, SP),
+                Inst::OpImm(Alu::Add, SP, SP, 16),
+            ],
+        });
+        insts
+    }
+}
+
+/** The registers the callee saves that `func` changes, but the frame pointer, saved apart */
+pub fn clobbered(func: &Func) -> Vec<Reg> {
+    let defs: Vec<Reg> = func
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .flat_map(Inst::defs)
+        .collect();
+    CALLEE_SAVED[1..]
+        .iter()
+        .filter(|reg| defs.contains(reg))
+        .copied()
+        .collect()
+}
+
+/** The offset of the slot `slot` from the frame pointer */
+pub fn slot(slot: usize) -> i32 {
+    -4 * (LINKAGE + 1 + slot) as i32
+}
+
+/** Lays out the frame of `func` and adds its prologue and its epilogues, before each return and
+call in tail position */
+pub fn lower(func: &mut Func) {
+    let frame = Frame::new(func);
+    let epilogue = frame.epilogue();
+    for block in &mut func.blocks {
+        let mut insts = vec![];
+        for mut inst in block.insts.drain(..) {
+            if let Inst::Tail(Target::Reg(reg), _) = &mut inst {
+                if frame.saved.contains(reg) {
+                    insts.push(Inst::OpImm(Alu::Add, T1, *reg, 0));
+                    *reg = T1
+                }
+            }
+            if matches!(inst, Inst::Ret | Inst::Tail(..)) {
+                insts.extend(epilogue.iter().cloned())
+            }
+            insts.push(inst)
+        }
+        block.insts = insts
+    }
+    let entry = &mut func.blocks[0].insts;
+    entry.splice(0..0, frame.prologue());
+}
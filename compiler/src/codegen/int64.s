@@ -0,0 +1,254 @@
+# The arithmetic of Int64 for programs compiled to RV32I, part of the runtime emitted with the
+# programs doing any. Integers of Int64 are boxed in blocks of tag 250, their low word then their
+# high word. Like the rest of the runtime, everything here follows the standard calling convention.
+
+    .text
+
+# The Int64 integer of the low word a0 and the high word a1, boxed
+polylamb_int64_box:
+    la t0, polylamb_hp
+    lw t1, 0(t0)
+    addi t2, t1, 12
+    sw t2, 0(t0)
+    li t2, 2298                 # 8 bytes, of tag 250
+    sw t2, 0(t1)
+    sw a0, 4(t1)
+    sw a1, 8(t1)
+    mv a0, t1
+    ret
+
+# The boxed Int64 integers a0 and a1 added, modulo 2^64, boxed like every result below. Each loads
+# the words of a0 in t0 and t1, and those of a1 in t2 and t3, the low words first
+    .globl polylamb_int64_add
+polylamb_int64_add:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    add a0, t0, t2
+    sltu t4, a0, t0             # the carry
+    add a1, t1, t3
+    add a1, a1, t4
+    j polylamb_int64_box
+
+    .globl polylamb_int64_sub
+polylamb_int64_sub:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    sub a0, t0, t2
+    sltu t4, t0, t2             # the borrow
+    sub a1, t1, t3
+    sub a1, a1, t4
+    j polylamb_int64_box
+
+    .globl polylamb_int64_mul
+polylamb_int64_mul:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    li a0, 0
+    li a1, 0
+.Lmul64_bit:
+    or t4, t2, t3
+    beqz t4, polylamb_int64_box
+    andi t4, t2, 1
+    beqz t4, .Lmul64_shift
+    add a0, a0, t0
+    sltu t5, a0, t0
+    add a1, a1, t1
+    add a1, a1, t5
+.Lmul64_shift:
+    srli t5, t0, 31
+    slli t1, t1, 1
+    or t1, t1, t5
+    slli t0, t0, 1
+    slli t5, t3, 31
+    srli t2, t2, 1
+    or t2, t2, t5
+    srli t3, t3, 1
+    j .Lmul64_bit
+
+# a0 / a1 rounding towards zero, a1 nonzero
+    .globl polylamb_int64_div
+polylamb_int64_div:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    xor a6, t1, t3              # negative if the quotient is
+    jal t6, .Ldivmod64
+    mv a0, a2
+    mv a1, a3
+    bgez a6, polylamb_int64_box
+    j .Lneg64
+
+# The remainder of a0 / a1, of the sign of a0, a1 nonzero
+    .globl polylamb_int64_mod
+polylamb_int64_mod:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    mv a6, t1                   # the sign of the remainder
+    jal t6, .Ldivmod64
+    mv a0, t2
+    mv a1, t3
+    bgez a6, polylamb_int64_box
+.Lneg64:
+    snez t4, a0
+    neg a0, a0
+    neg a1, a1
+    sub a1, a1, t4
+    j polylamb_int64_box
+
+# The quotient of the magnitudes of t0 and t1 by those of t2 and t3 in a2 and a3, the remainder in
+# t2 and t3, returning to t6
+.Ldivmod64:
+    mv a2, t0
+    mv a3, t1
+    mv a4, t2
+    mv a5, t3
+    bgez a3, .Ldivmod64_dividend
+    snez t4, a2
+    neg a2, a2
+    neg a3, a3
+    sub a3, a3, t4
+.Ldivmod64_dividend:
+    bgez a5, .Ldivmod64_divisor
+    snez t4, a4
+    neg a4, a4
+    neg a5, a5
+    sub a5, a5, t4
+.Ldivmod64_divisor:
+    li t2, 0                    # the remainder
+    li t3, 0
+    li t4, 64
+.Ldivmod64_bit:
+    slli t3, t3, 1
+    srli t5, t2, 31
+    or t3, t3, t5
+    slli t2, t2, 1
+    srli t5, a3, 31
+    or t2, t2, t5
+    slli a3, a3, 1
+    srli t5, a2, 31
+    or a3, a3, t5
+    slli a2, a2, 1              # the quotient, shifted in as the dividend shifts out
+    bltu t3, a5, .Ldivmod64_next
+    bne t3, a5, .Ldivmod64_sub
+    bltu t2, a4, .Ldivmod64_next
+.Ldivmod64_sub:
+    sltu t5, t2, a4
+    sub t2, t2, a4
+    sub t3, t3, a5
+    sub t3, t3, t5
+    ori a2, a2, 1
+.Ldivmod64_next:
+    addi t4, t4, -1
+    bnez t4, .Ldivmod64_bit
+    jr t6
+
+    .globl polylamb_int64_land
+polylamb_int64_land:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    and a0, t0, t2
+    and a1, t1, t3
+    j polylamb_int64_box
+
+    .globl polylamb_int64_lor
+polylamb_int64_lor:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    or a0, t0, t2
+    or a1, t1, t3
+    j polylamb_int64_box
+
+    .globl polylamb_int64_lxor
+polylamb_int64_lxor:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    xor a0, t0, t2
+    xor a1, t1, t3
+    j polylamb_int64_box
+
+# a0 shifted left by the lowest 6 bits of a1
+    .globl polylamb_int64_shl
+polylamb_int64_shl:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    andi t2, t2, 63
+    beqz t2, .Lshift64_none
+    li t4, 32
+    bgeu t2, t4, .Lshl64_words
+    sll a1, t1, t2
+    sub t5, t4, t2
+    srl t5, t0, t5
+    or a1, a1, t5
+    sll a0, t0, t2
+    j polylamb_int64_box
+.Lshl64_words:
+    addi t2, t2, -32
+    sll a1, t0, t2
+    li a0, 0
+    j polylamb_int64_box
+.Lshift64_none:
+    mv a0, t0
+    mv a1, t1
+    j polylamb_int64_box
+
+# a0 shifted right arithmetically by the lowest 6 bits of a1
+    .globl polylamb_int64_shr
+polylamb_int64_shr:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    andi t2, t2, 63
+    beqz t2, .Lshift64_none
+    li t4, 32
+    bgeu t2, t4, .Lshr64_words
+    srl a0, t0, t2
+    sub t5, t4, t2
+    sll t5, t1, t5
+    or a0, a0, t5
+    sra a1, t1, t2
+    j polylamb_int64_box
+.Lshr64_words:
+    addi t2, t2, -32
+    sra a0, t1, t2
+    srai a1, t1, 31
+    j polylamb_int64_box
+
+# Whether the boxed Int64 integer a0 is less than a1, 1 if it is and 0 if not
+    .globl polylamb_int64_lt
+polylamb_int64_lt:
+    lw t0, 4(a0)
+    lw t1, 8(a0)
+    lw t2, 4(a1)
+    lw t3, 8(a1)
+    bne t1, t3, .Llt64_high
+    sltu a0, t0, t2
+    ret
+.Llt64_high:
+    slt a0, t1, t3
+    ret
+
+# Whether the boxed Int64 integer a0 is greater than a1, 1 if it is and 0 if not
+    .globl polylamb_int64_gt
+polylamb_int64_gt:
+    mv t0, a0
+    mv a0, a1
+    mv a1, t0
+    j polylamb_int64_lt
@@ -0,0 +1,491 @@
+/*! Instruction selection by matching trees against patterns. The pure arithmetic, comparisons and
+tagging of a block, and its branch, are made into [trees](Tree): a register defined by such an
+instruction and used once, by another in the same block, is its subtree there rather than a leaf.
+Every tree is then covered by [rules](Rule), each a [pattern](Pat) and the instructions it emits
+for what matches it, at the least cost in instructions, the subtrees left to their own rules. The
+rules are data, in [`RULES`], so that fusing a comparison with the branch on it, or an untagged
+addition of a constant with its tagging, is one more entry. */
+
+use crate::ast::ast::Binary;
+use crate::codegen::riscv::{constant, immediate, Alu, Cond, Inst, Reg, ZERO};
+use crate::ir::anf::Atom;
+use crate::ir::ssa::Op;
+
+/// Trees of pure operations, over leaves in registers or constants
+#[derive(Debug, PartialEq, Clone)]
+pub enum Tree<'a> {
+    /// An atom, raw if it's a constant and it's an operand of arithmetic
+    Leaf(&'a Atom, bool),
+    Binop(Box<Tree<'a>>, Binary, Box<Tree<'a>>),
+    Tag(Box<Tree<'a>>),
+    Untag(Box<Tree<'a>>),
+    /// Branching on a tagged boolean
+    Branch(Box<Tree<'a>>),
+}
+
+/// Patterns of trees
+#[derive(Debug, Clone)]
+pub enum Pat {
+    /// Any tree, its value in a register
+    Any,
+    /// Constants whose word the function accepts
+    Imm(fn(i64) -> bool),
+    Binop(&'static Pat, Binary, &'static Pat),
+    Tag(&'static Pat),
+    Untag(&'static Pat),
+    Branch(&'static Pat),
+}
+
+/// What patterns bind, in order: the trees matching `Any`, and the words matching `Imm`
+#[derive(Debug, PartialEq, Clone)]
+pub enum Binding<'t, 'a> {
+    Tree(&'t Tree<'a>),
+    Imm(i64),
+}
+
+/// The operands of the instructions of rules, once the trees bound are in registers
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(i64),
+}
+
+/// What rules emit their instructions for
+#[derive(Debug, PartialEq, Clone)]
+pub struct Matched {
+    /// The register of the result, or a scratch register for branches
+    pub dst: Reg,
+    pub ops: Vec<Operand>,
+    /// The labels branches go to if the condition holds and if it doesn't
+    pub targets: Option<(String, String)>,
+}
+
+/// Rules covering trees matching their pattern with instructions
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: &'static str,
+    pub pat: Pat,
+    /// The number of instructions emitted
+    pub cost: u32,
+    pub emit: fn(&Matched) -> Vec<Inst>,
+}
+
+impl Matched {
+    /** The register of the operand `index` */
+    pub fn reg(&self, index: usize) -> Reg {
+        match self.ops[index] {
+            Operand::Reg(reg) => reg,
+            Operand::Imm(_) => panic!("operand {index} is an immediate"),
+        }
+    }
+
+    /** The immediate of the operand `index` */
+    pub fn imm(&self, index: usize) -> i32 {
+        match self.ops[index] {
+            Operand::Imm(n) => n as i32,
+            Operand::Reg(_) => panic!("operand {index} is a register"),
+        }
+    }
+
+    /** The labels branched to if the condition holds and if it doesn't */
+    pub fn targets(&self) -> (String, String) {
+        self.targets.clone().expect("branches have targets")
+    }
+}
+
+/** Whether the binary operation `op` is pure, and works on machine words */
+pub fn pure(op: &Binary) -> bool {
+    use Binary::*;
+    matches!(
+        op,
+        Add | Sub | Land | Lor | Lxor | Shl | Shr | Lt | Gt | And | Or
+    )
+}
+
+/** Whether the operands of `op` are raw integers */
+pub fn raw(op: &Binary) -> bool {
+    use Binary::*;
+    matches!(
+        op,
+        Add | Sub | Mul | Div | Mod | Land | Lor | Lxor | Shl | Shr
+    )
+}
+
+/** Whether `op` is an operation of trees. Equality is when either operand is an [`immediate`]
+constant, so that the words of the operands are equal exactly when their values are */
+pub fn treeable(op: &Op) -> bool {
+    let immediate = |atom: &Atom| matches!(atom, Atom::Con(con) if immediate(con, false));
+    match op {
+        Op::Binop(_, Binary::Eq | Binary::Ne, _) => op.atoms().into_iter().any(immediate),
+        Op::Binop(_, op, _) => pure(op),
+        Op::Tag(_) | Op::Untag(_) => true,
+        _ => false,
+    }
+}
+
+/** Whether `pat` matches `tree`, adding what it binds to `bindings` */
+fn matches<'t, 'a>(pat: &Pat, tree: &'t Tree<'a>, bindings: &mut Vec<Binding<'t, 'a>>) -> bool {
+    match (pat, tree) {
+        (Pat::Any, tree) => {
+            bindings.push(Binding::Tree(tree));
+            true
+        }
+        (Pat::Imm(accepts), Tree::Leaf(Atom::Con(con), raw)) => {
+            match con.as_int() {
+                _ if !immediate(con, *raw) => false,
+                // Integers are wider than words, and not truncated
+                Some(n) if i32::try_from(n).is_err() => false,
+                _ => {
+                    let word = constant(con, *raw) as i64;
+                    bindings.push(Binding::Imm(word));
+                    accepts(word)
+                }
+            }
+        }
+        (Pat::Binop(l, op, r), Tree::Binop(lhs, other, rhs)) if op == other => {
+            matches(l, lhs, bindings) && matches(r, rhs, bindings)
+        }
+        (Pat::Tag(p), Tree::Tag(t))
+        | (Pat::Untag(p), Tree::Untag(t))
+        | (Pat::Branch(p), Tree::Branch(t)) => matches(p, t, bindings),
+        _ => false,
+    }
+}
+
+/** The cost of the instructions computing `tree` into a register */
+fn cost(rules: &'static [Rule], tree: &Tree) -> u32 {
+    match tree {
+        Tree::Leaf(Atom::Var(_), _) => 0,
+        Tree::Leaf(Atom::Con(_), _) => 1,
+        tree => best(rules, tree).map_or(u32::MAX, |(cost, ..)| cost),
+    }
+}
+
+/** The rule of `rules` covering `tree` at the least cost, with the cost and what the pattern of
+the rule binds */
+pub fn best<'t, 'a>(
+    rules: &'static [Rule],
+    tree: &'t Tree<'a>,
+) -> Option<(u32, &'static Rule, Vec<Binding<'t, 'a>>)> {
+    let mut best: Option<(u32, &'static Rule, Vec<Binding>)> = None;
+    for rule in rules {
+        let mut bindings = vec![];
+        if !matches(&rule.pat, tree, &mut bindings) {
+            continue;
+        }
+        let mut total = rule.cost;
+        for binding in &bindings {
+            if let Binding::Tree(tree) = binding {
+                total = total.saturating_add(cost(rules, tree))
+            }
+        }
+        if best.as_ref().is_none_or(|(cost, ..)| total < *cost) {
+            best = Some((total, rule, bindings))
+        }
+    }
+    best
+}
+
+/** Whether `n` fits the immediates of 12 bits of I-type and S-type instructions */
+pub(crate) fn imm12(n: i64) -> bool {
+    (-2048..2048).contains(&n)
+}
+
+fn shamt(n: i64) -> bool {
+    (0..32).contains(&n)
+}
+
+/** Whether `n` doubled, and negated, fits in 12 bits, to be added to tagged integers */
+fn tagged12(n: i64) -> bool {
+    imm12(2 * n) && imm12(-2 * n)
+}
+
+fn negated12(n: i64) -> bool {
+    imm12(-n)
+}
+
+fn any(_: i64) -> bool {
+    true
+}
+
+const ANY: Pat = Pat::Any;
+const IMM12: Pat = Pat::Imm(imm12);
+
+/** The rule `name` emitting one instruction for `op` on two registers */
+const fn op(name: &'static str, op: Binary, emit: fn(&Matched) -> Vec<Inst>) -> Rule {
+    Rule {
+        name,
+        pat: Pat::Binop(&ANY, op, &ANY),
+        cost: 1,
+        emit,
+    }
+}
+
+/// The rules instructions are selected by
+pub static RULES: &[Rule] = &[
+    op("add", Binary::Add, |m| {
+        vec![Inst::Op(Alu::Add, m.dst, m.reg(0), m.reg(1))]
+    }),
+    op("sub", Binary::Sub, |m| {
+        vec![Inst::Op(Alu::Sub, m.dst, m.reg(0), m.reg(1))]
+    }),
+    op("land", Binary::Land, |m| {
+        vec![Inst::Op(Alu::And, m.dst, m.reg(0), m.reg(1))]
+    }),
+    op("lor", Binary::Lor, |m| {
+        vec![Inst::Op(Alu::Or, m.dst, m.reg(0), m.reg(1))]
+    }),
+    op("lxor", Binary::Lxor, |m| {
+        vec![Inst::Op(Alu::Xor, m.dst, m.reg(0), m.reg(1))]
+    }),
+    op("shl", Binary::Shl, |m| {
+        vec![Inst::Op(Alu::Sll, m.dst, m.reg(0), m.reg(1))]
+    }),
+    op("shr", Binary::Shr, |m| {
+        vec![Inst::Op(Alu::Sra, m.dst, m.reg(0), m.reg(1))]
+    }),
+    op("lt", Binary::Lt, |m| {
+        vec![Inst::Op(Alu::Slt, m.dst, m.reg(0), m.reg(1))]
+    }),
+    op("gt", Binary::Gt, |m| {
+        vec![Inst::Op(Alu::Slt, m.dst, m.reg(1), m.reg(0))]
+    }),
+    // Booleans are tagged, `true` 3 and `false` 1
+    op("and", Binary::And, |m| {
+        vec![Inst::Op(Alu::And, m.dst, m.reg(0), m.reg(1))]
+    }),
+    op("or", Binary::Or, |m| {
+        vec![Inst::Op(Alu::Or, m.dst, m.reg(0), m.reg(1))]
+    }),
+    Rule {
+        name: "eq",
+        pat: Pat::Binop(&ANY, Binary::Eq, &ANY),
+        cost: 2,
+        emit: |m| {
+            vec![
+                Inst::Op(Alu::Xor, m.dst, m.reg(0), m.reg(1)),
+                Inst::OpImm(Alu::Sltu, m.dst, m.dst, 1),
+            ]
+        },
+    },
+    Rule {
+        name: "ne",
+        pat: Pat::Binop(&ANY, Binary::Ne, &ANY),
+        cost: 2,
+        emit: |m| {
+            vec![
+                Inst::Op(Alu::Xor, m.dst, m.reg(0), m.reg(1)),
+                Inst::Op(Alu::Sltu, m.dst, ZERO, m.dst),
+            ]
+        },
+    },
+    Rule {
+        name: "addi",
+        pat: Pat::Binop(&ANY, Binary::Add, &IMM12),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::Add, m.dst, m.reg(0), m.imm(1))],
+    },
+    Rule {
+        name: "subi",
+        pat: Pat::Binop(&ANY, Binary::Sub, &Pat::Imm(negated12)),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::Add, m.dst, m.reg(0), -m.imm(1))],
+    },
+    Rule {
+        name: "landi",
+        pat: Pat::Binop(&ANY, Binary::Land, &IMM12),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::And, m.dst, m.reg(0), m.imm(1))],
+    },
+    Rule {
+        name: "lori",
+        pat: Pat::Binop(&ANY, Binary::Lor, &IMM12),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::Or, m.dst, m.reg(0), m.imm(1))],
+    },
+    Rule {
+        name: "lxori",
+        pat: Pat::Binop(&ANY, Binary::Lxor, &IMM12),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::Xor, m.dst, m.reg(0), m.imm(1))],
+    },
+    Rule {
+        name: "shli",
+        pat: Pat::Binop(&ANY, Binary::Shl, &Pat::Imm(shamt)),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::Sll, m.dst, m.reg(0), m.imm(1))],
+    },
+    Rule {
+        name: "shri",
+        pat: Pat::Binop(&ANY, Binary::Shr, &Pat::Imm(shamt)),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::Sra, m.dst, m.reg(0), m.imm(1))],
+    },
+    Rule {
+        name: "lti",
+        pat: Pat::Binop(&ANY, Binary::Lt, &IMM12),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::Slt, m.dst, m.reg(0), m.imm(1))],
+    },
+    Rule {
+        name: "eqi",
+        pat: Pat::Binop(&ANY, Binary::Eq, &IMM12),
+        cost: 2,
+        emit: |m| {
+            vec![
+                Inst::OpImm(Alu::Xor, m.dst, m.reg(0), m.imm(1)),
+                Inst::OpImm(Alu::Sltu, m.dst, m.dst, 1),
+            ]
+        },
+    },
+    Rule {
+        name: "nei",
+        pat: Pat::Binop(&ANY, Binary::Ne, &IMM12),
+        cost: 2,
+        emit: |m| {
+            vec![
+                Inst::OpImm(Alu::Xor, m.dst, m.reg(0), m.imm(1)),
+                Inst::Op(Alu::Sltu, m.dst, ZERO, m.dst),
+            ]
+        },
+    },
+    Rule {
+        name: "tag",
+        pat: Pat::Tag(&ANY),
+        cost: 2,
+        emit: |m| {
+            vec![
+                Inst::Op(Alu::Add, m.dst, m.reg(0), m.reg(0)),
+                Inst::OpImm(Alu::Add, m.dst, m.dst, 1),
+            ]
+        },
+    },
+    Rule {
+        name: "tag-constant",
+        pat: Pat::Tag(&Pat::Imm(any)),
+        cost: 1,
+        emit: |m| vec![Inst::Li(m.dst, m.imm(0).wrapping_mul(2).wrapping_add(1))],
+    },
+    Rule {
+        name: "untag-constant",
+        pat: Pat::Untag(&Pat::Imm(any)),
+        cost: 1,
+        emit: |m| vec![Inst::Li(m.dst, m.imm(0) >> 1)],
+    },
+    Rule {
+        name: "untag",
+        pat: Pat::Untag(&ANY),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::Sra, m.dst, m.reg(0), 1)],
+    },
+    // Adding to tagged integers: 2a + 1 + 2b = 2(a + b) + 1
+    Rule {
+        name: "tagged-addi",
+        pat: Pat::Tag(&Pat::Binop(
+            &Pat::Untag(&ANY),
+            Binary::Add,
+            &Pat::Imm(tagged12),
+        )),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::Add, m.dst, m.reg(0), 2 * m.imm(1))],
+    },
+    Rule {
+        name: "tagged-subi",
+        pat: Pat::Tag(&Pat::Binop(
+            &Pat::Untag(&ANY),
+            Binary::Sub,
+            &Pat::Imm(tagged12),
+        )),
+        cost: 1,
+        emit: |m| vec![Inst::OpImm(Alu::Add, m.dst, m.reg(0), -2 * m.imm(1))],
+    },
+    Rule {
+        name: "tagged-add",
+        pat: Pat::Tag(&Pat::Binop(
+            &Pat::Untag(&ANY),
+            Binary::Add,
+            &Pat::Untag(&ANY),
+        )),
+        cost: 2,
+        emit: |m| {
+            vec![
+                Inst::Op(Alu::Add, m.dst, m.reg(0), m.reg(1)),
+                Inst::OpImm(Alu::Add, m.dst, m.dst, -1),
+            ]
+        },
+    },
+    Rule {
+        name: "tagged-sub",
+        pat: Pat::Tag(&Pat::Binop(
+            &Pat::Untag(&ANY),
+            Binary::Sub,
+            &Pat::Untag(&ANY),
+        )),
+        cost: 2,
+        emit: |m| {
+            vec![
+                Inst::Op(Alu::Sub, m.dst, m.reg(0), m.reg(1)),
+                Inst::OpImm(Alu::Add, m.dst, m.dst, 1),
+            ]
+        },
+    },
+    Rule {
+        name: "branch",
+        pat: Pat::Branch(&ANY),
+        cost: 3,
+        emit: |m| {
+            let (t, f) = m.targets();
+            vec![
+                Inst::OpImm(Alu::And, m.dst, m.reg(0), 2),
+                Inst::Branch(Cond::Eq, m.dst, ZERO, f),
+                Inst::J(t),
+            ]
+        },
+    },
+    Rule {
+        name: "branch-constant",
+        pat: Pat::Branch(&Pat::Imm(any)),
+        cost: 1,
+        emit: |m| {
+            let (t, f) = m.targets();
+            vec![Inst::J(if m.imm(0) & 2 != 0 { t } else { f })]
+        },
+    },
+    // Comparing and branching at once
+    Rule {
+        name: "branch-lt",
+        pat: Pat::Branch(&Pat::Tag(&Pat::Binop(&ANY, Binary::Lt, &ANY))),
+        cost: 2,
+        emit: |m| {
+            let (t, f) = m.targets();
+            vec![Inst::Branch(Cond::Lt, m.reg(0), m.reg(1), t), Inst::J(f)]
+        },
+    },
+    Rule {
+        name: "branch-gt",
+        pat: Pat::Branch(&Pat::Tag(&Pat::Binop(&ANY, Binary::Gt, &ANY))),
+        cost: 2,
+        emit: |m| {
+            let (t, f) = m.targets();
+            vec![Inst::Branch(Cond::Lt, m.reg(1), m.reg(0), t), Inst::J(f)]
+        },
+    },
+    Rule {
+        name: "branch-eq",
+        pat: Pat::Branch(&Pat::Tag(&Pat::Binop(&ANY, Binary::Eq, &ANY))),
+        cost: 2,
+        emit: |m| {
+            let (t, f) = m.targets();
+            vec![Inst::Branch(Cond::Eq, m.reg(0), m.reg(1), t), Inst::J(f)]
+        },
+    },
+    Rule {
+        name: "branch-ne",
+        pat: Pat::Branch(&Pat::Tag(&Pat::Binop(&ANY, Binary::Ne, &ANY))),
+        cost: 2,
+        emit: |m| {
+            let (t, f) = m.targets();
+            vec![Inst::Branch(Cond::Ne, m.reg(0), m.reg(1), t), Inst::J(f)]
+        },
+    },
+];
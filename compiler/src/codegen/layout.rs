@@ -0,0 +1,74 @@
+/*! Block layout, ordering the blocks of functions so that their likely successors follow them,
+falling through to them rather than jumping. Blocks are placed in chains from the entry, each
+followed by its likely successor not placed yet: the target of the jump ending it, taken when its
+branch isn't, like the code after a check of an exception raised, or else the target of the
+branch. Chains ending in a return, or at a block placed already, go on from the first block not
+placed yet in the original order, keeping blocks no chain reaches where they were. Blocks ending in
+a branch to the next block and a jump elsewhere branch to the target of the jump instead, on the
+inverse condition, falling through to the next. */
+
+use crate::codegen::riscv::{Func, Inst};
+
+use std::collections::HashMap;
+
+/** The successors of the block ending in `insts`, most likely first */
+fn likely(insts: &[Inst]) -> impl Iterator<Item = &String> {
+    insts.iter().rev().filter_map(|inst| match inst {
+        Inst::Branch(.., label) | Inst::J(label) => Some(label),
+        _ => None,
+    })
+}
+
+/** Orders the blocks of `func`, its entry first, so that they fall through to their likely
+successors */
+pub fn layout(func: &mut Func) {
+    // Blocks falling through jump to the next block instead, so that they can be moved
+    let labels: Vec<String> = func
+        .blocks
+        .iter()
+        .map(|block| block.label.clone())
+        .collect();
+    for (block, next) in func.blocks.iter_mut().zip(labels.iter().skip(1)) {
+        if !matches!(
+            block.insts.last(),
+            Some(Inst::J(_) | Inst::Ret | Inst::Tail(..))
+        ) {
+            block.insts.push(Inst::J(next.clone()))
+        }
+    }
+    let index: HashMap<&str, usize> = labels
+        .iter()
+        .enumerate()
+        .map(|(index, label)| (&label[..], index))
+        .collect();
+    let mut placed = vec![false; labels.len()];
+    let mut order = vec![];
+    while let Some(start) = placed.iter().position(|placed| !placed) {
+        let mut current = Some(start);
+        while let Some(block) = current {
+            placed[block] = true;
+            order.push(block);
+            current = likely(&func.blocks[block].insts)
+                .filter_map(|label| index.get(&label[..]).copied())
+                .find(|succ| !placed[*succ]);
+        }
+    }
+    let mut blocks: Vec<_> = std::mem::take(&mut func.blocks)
+        .into_iter()
+        .map(Some)
+        .collect();
+    func.blocks = order
+        .into_iter()
+        .map(|i| blocks[i].take().unwrap())
+        .collect();
+    for i in 0..func.blocks.len().saturating_sub(1) {
+        let next = func.blocks[i + 1].label.clone();
+        let insts = &mut func.blocks[i].insts;
+        if let [.., Inst::Branch(cond, _, _, target), Inst::J(other)] = &mut insts[..] {
+            if *target == next && *other != next {
+                *cond = cond.inverse();
+                std::mem::swap(target, other)
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+/*! Legalization, expanding the instructions of functions whose frames are laid out into those of
+RV32I, rather than leaving the pseudo-instructions loading constants and addresses to the assembler,
+or assuming immediates and offsets fit in 12 bits. Constants are loaded by `lui` then `addi`, or by
+`addi` alone when they fit, and addresses by `lui` and `addi` of their absolute parts, or for
+position independent code by `auipc` and `addi` of their parts relative to the `auipc`, labelled for
+it. Operations on immediates out of range take them in a register, their destination when it's not
+also their operand, and loads and stores at offsets out of range add the upper part of the offset to
+their base, in their destination for loads but of their base, and otherwise in a scratch register
+not in use: no value is allocated `t0` to `t2`, which hold spilled values within instructions only.
+*/
+
+use crate::codegen::isel::imm12;
+use crate::codegen::riscv::{Alu, Func, Inst, Reg, Reloc, T0, T1, T2, ZERO};
+
+/** The upper 20 bits and lower 12 bits adding up to `n`, modulo 2^32 */
+pub(crate) fn split(n: i32) -> (i32, i32) {
+    let lo = (n << 20) >> 20;
+    let hi = (n.wrapping_sub(lo) as u32 >> 12) as i32;
+    (hi, lo)
+}
+
+/** The instructions loading the constant `n` into `rd` */
+fn constant(rd: Reg, n: i32) -> Vec<Inst> {
+    if imm12(n as i64) {
+        return vec![Inst::OpImm(Alu::Add, rd, ZERO, n)];
+    }
+    match split(n) {
+        (hi, 0) => vec![Inst::Lui(rd, hi)],
+        (hi, lo) => vec![Inst::Lui(rd, hi), Inst::OpImm(Alu::Add, rd, rd, lo)],
+    }
+}
+
+/** A scratch register, none of `regs` */
+fn scratch(regs: &[Reg]) -> Reg {
+    *[T2, T0, T1].iter().find(|reg| !regs.contains(reg)).unwrap()
+}
+
+/// Legalizing the instructions of a function
+struct Legalizer<'a> {
+    name: &'a str,
+    pic: bool,
+    /// The number of labels of `auipc`
+    labels: usize,
+}
+
+impl Legalizer<'_> {
+    fn inst(&mut self, inst: Inst) -> Vec<Inst> {
+        let far = |offset: i32| !imm12(offset as i64);
+        match inst {
+            Inst::Li(rd, n) => constant(rd, n),
+            Inst::La(rd, symbol) if self.pic => {
+                let label = format!(".L{}.pcrel{}", self.name, self.labels);
+                self.labels += 1;
+                vec![
+                    Inst::Label(label.clone()),
+                    Inst::Upper(rd, Reloc::PcrelHi(symbol)),
+                    Inst::Lower(rd, rd, Reloc::PcrelLo(label)),
+                ]
+            }
+            Inst::La(rd, symbol) => vec![
+                Inst::Upper(rd, Reloc::Hi(symbol.clone())),
+                Inst::Lower(rd, rd, Reloc::Lo(symbol)),
+            ],
+            Inst::OpImm(alu, rd, rs, n) if far(n) => {
+                let temp = if rd != rs { rd } else { scratch(&[rs]) };
+                let mut insts = constant(temp, n);
+                insts.push(Inst::Op(alu, rd, rs, temp));
+                insts
+            }
+            Inst::Lw(rd, offset, base) | Inst::Lbu(rd, offset, base) if far(offset) => {
+                let (hi, lo) = split(offset);
+                let temp = if rd != base { rd } else { scratch(&[base]) };
+                let load = match inst {
+                    Inst::Lw(..) => Inst::Lw(rd, lo, temp),
+                    _ => Inst::Lbu(rd, lo, temp),
+                };
+                vec![
+                    Inst::Lui(temp, hi),
+                    Inst::Op(Alu::Add, temp, temp, base),
+                    load,
+                ]
+            }
+            Inst::Sw(src, offset, base) if far(offset) => {
+                let (hi, lo) = split(offset);
+                let temp = scratch(&[src, base]);
+                vec![
+                    Inst::Lui(temp, hi),
+                    Inst::Op(Alu::Add, temp, temp, base),
+                    Inst::Sw(src, lo, temp),
+                ]
+            }
+            inst => vec![inst],
+        }
+    }
+}
+
+/** Expands the instructions of `func` into those of RV32I, its addresses position independent if
+`pic` */
+pub fn legalize(func: &mut Func, pic: bool) {
+    let mut legalizer = Legalizer {
+        name: &func.name,
+        pic,
+        labels: 0,
+    };
+    for block in &mut func.blocks {
+        let insts = std::mem::take(&mut block.insts);
+        block.insts = insts
+            .into_iter()
+            .flat_map(|inst| legalizer.inst(inst))
+            .collect()
+    }
+}
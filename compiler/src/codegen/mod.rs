@@ -0,0 +1,8 @@
+pub mod frame;
+pub mod isel;
+pub mod layout;
+pub mod legalize;
+pub mod regalloc;
+pub mod relax;
+pub mod riscv;
+pub mod sim;
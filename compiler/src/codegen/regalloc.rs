@@ -0,0 +1,338 @@
+/*! Register allocation, giving the virtual registers of functions whose instructions are selected a
+register of the machine or a slot in their frame. Linear scan computes the liveness of registers
+over the blocks of a function, virtual and of the machine alike, and the interval of each virtual
+register: from the first instruction it's live at to the last, in the order of the blocks. The
+registers of the machine are live in ranges of their own, where arguments are passed and results
+returned, and calls write all those of the [caller](crate::codegen::riscv::CALLER_SAVED), so that
+values live across calls are given those of the callee, which [frames](frame) save. Intervals are
+then scanned by their start, each given a register free all through it, or if there's none, the
+interval ending last, it or an active one, is spilled to a slot, shared by intervals spilled that
+don't overlap. Spilled registers are loaded into scratch registers, `t0` and `t1`, before each
+instruction using them, and stored from `t2` after each defining them. */
+
+use crate::codegen::frame;
+use crate::codegen::riscv::{Alu, Func, Inst, Reg, ARGS, CALLEE_SAVED, FP, T0, T1, T2, ZERO};
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// The register allocators
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Allocator {
+    /// Every virtual register in a slot
+    Spill,
+    #[default]
+    LinearScan,
+}
+
+impl FromStr for Allocator {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "spill" => Ok(Allocator::Spill),
+            "linear" => Ok(Allocator::LinearScan),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Where virtual registers are allocated
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Location {
+    Reg(Reg),
+    /// The slot of the frame, counted from 0
+    Slot(usize),
+}
+
+/// The registers allocated, in order of preference: those of the caller, `a2` to `a5` first, which
+/// compressed instructions can name and few calls take arguments in, then those of the callee but
+/// the frame pointer, saved by its [frame](frame) when they are, for values live across calls
+const POOL: [Reg; 23] = [
+    ARGS[5],
+    ARGS[4],
+    ARGS[3],
+    ARGS[2],
+    Reg::X(28),
+    Reg::X(29),
+    Reg::X(30),
+    Reg::X(31),
+    ARGS[7],
+    ARGS[6],
+    ARGS[1],
+    ARGS[0],
+    CALLEE_SAVED[1],
+    CALLEE_SAVED[2],
+    CALLEE_SAVED[3],
+    CALLEE_SAVED[4],
+    CALLEE_SAVED[5],
+    CALLEE_SAVED[6],
+    CALLEE_SAVED[7],
+    CALLEE_SAVED[8],
+    CALLEE_SAVED[9],
+    CALLEE_SAVED[10],
+    CALLEE_SAVED[11],
+];
+
+/// Live intervals, between positions: the uses of the instruction `k` are at `2k`, its
+/// definitions at `2k + 1`
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Interval {
+    vreg: usize,
+    start: usize,
+    end: usize,
+}
+
+/// The ranges of positions registers of the machine are live in, from and to inclusive
+type Ranges = HashMap<Reg, Vec<(usize, usize)>>;
+
+/** The blocks each block of `func` may go to next, by index */
+fn successors(func: &Func) -> Vec<Vec<usize>> {
+    let index: HashMap<&str, usize> = func
+        .blocks
+        .iter()
+        .enumerate()
+        .map(|(index, block)| (block.label.as_str(), index))
+        .collect();
+    let mut succs = vec![];
+    for (i, block) in func.blocks.iter().enumerate() {
+        let mut next = vec![];
+        for inst in &block.insts {
+            if let Inst::Branch(.., label) | Inst::J(label) = inst {
+                next.extend(index.get(label.as_str()))
+            }
+        }
+        let falls = !matches!(
+            block.insts.last(),
+            Some(Inst::J(_) | Inst::Ret | Inst::Tail(..))
+        );
+        if falls && i + 1 < func.blocks.len() {
+            next.push(i + 1)
+        }
+        succs.push(next)
+    }
+    succs
+}
+
+/** Whether `reg` is allocated or live in ranges: not `zero`, `sp`, `gp`, `tp` or the frame
+pointer */
+fn tracked(reg: Reg) -> bool {
+    !matches!(reg, Reg::X(0 | 2 | 3 | 4 | 8))
+}
+
+/** The registers of `func` live at the entry and at the exit of each block */
+fn liveness(func: &Func) -> (Vec<HashSet<Reg>>, Vec<HashSet<Reg>>) {
+    let succs = successors(func);
+    let n = func.blocks.len();
+    let mut live_in: Vec<HashSet<Reg>> = vec![HashSet::new(); n];
+    let mut live_out: Vec<HashSet<Reg>> = vec![HashSet::new(); n];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..n).rev() {
+            let out: HashSet<Reg> = succs[b]
+                .iter()
+                .flat_map(|succ| live_in[*succ].iter().copied())
+                .collect();
+            let mut live = out.clone();
+            for inst in func.blocks[b].insts.iter().rev() {
+                for def in inst.defs() {
+                    live.remove(&def);
+                }
+                live.extend(inst.uses().into_iter().filter(|reg| tracked(*reg)))
+            }
+            changed |= live != live_in[b] || out != live_out[b];
+            live_in[b] = live;
+            live_out[b] = out
+        }
+    }
+    (live_in, live_out)
+}
+
+/** The live intervals of the virtual registers of `func`, by start, and the ranges the registers
+of the pool are live in */
+fn intervals(func: &Func) -> (Vec<Interval>, Ranges) {
+    let (live_in, live_out) = liveness(func);
+    let mut bounds: HashMap<usize, (usize, usize)> = HashMap::new();
+    let mut extend = |reg: Reg, at: usize| {
+        if let Reg::V(vreg) = reg {
+            let bound = bounds.entry(vreg).or_insert((at, at));
+            *bound = (bound.0.min(at), bound.1.max(at))
+        }
+    };
+    let mut fixed: Ranges = HashMap::new();
+    let mut k = func
+        .blocks
+        .iter()
+        .map(|block| block.insts.len())
+        .sum::<usize>();
+    for (b, block) in func.blocks.iter().enumerate().rev() {
+        let end = 2 * k + 1;
+        k -= block.insts.len();
+        let start = 2 * k;
+        // The ends of the ranges of the registers of the pool live from there on
+        let mut open: HashMap<Reg, usize> = HashMap::new();
+        for reg in &live_out[b] {
+            extend(*reg, end);
+            if POOL.contains(reg) {
+                open.insert(*reg, end);
+            }
+        }
+        for (i, inst) in block.insts.iter().enumerate().rev() {
+            let at = k + i;
+            for def in inst.defs() {
+                extend(def, 2 * at + 1);
+                if POOL.contains(&def) {
+                    let until = open.remove(&def).unwrap_or(2 * at + 1);
+                    fixed.entry(def).or_default().push((2 * at + 1, until))
+                }
+            }
+            for reg in inst.uses().into_iter().filter(|reg| tracked(*reg)) {
+                extend(reg, 2 * at);
+                if POOL.contains(&reg) {
+                    open.entry(reg).or_insert(2 * at);
+                }
+            }
+        }
+        for (reg, until) in open {
+            fixed.entry(reg).or_default().push((start, until))
+        }
+        for reg in &live_in[b] {
+            extend(*reg, start)
+        }
+    }
+    let mut intervals: Vec<Interval> = bounds
+        .into_iter()
+        .map(|(vreg, (start, end))| Interval { vreg, start, end })
+        .collect();
+    intervals.sort_by_key(|interval| (interval.start, interval.vreg));
+    (intervals, fixed)
+}
+
+/** Whether the ranges `ranges` overlap `interval` */
+fn overlaps(ranges: Option<&Vec<(usize, usize)>>, interval: &Interval) -> bool {
+    let ranges = ranges.map(Vec::as_slice).unwrap_or_default();
+    ranges
+        .iter()
+        .any(|(start, end)| *start <= interval.end && interval.start <= *end)
+}
+
+/** The locations of the virtual registers of `func` by linear scan */
+fn linear_scan(func: &Func) -> Vec<Location> {
+    let (intervals, fixed) = intervals(func);
+    let mut locations = vec![None; func.vregs];
+    // The intervals spilled to each slot, sharing it when they don't overlap
+    let mut slots: Vec<Vec<(usize, usize)>> = vec![];
+    let mut spill = |locations: &mut Vec<Option<Location>>, interval: Interval| {
+        let slot = match slots
+            .iter()
+            .position(|spilled| !overlaps(Some(spilled), &interval))
+        {
+            Some(slot) => slot,
+            None => {
+                slots.push(vec![]);
+                slots.len() - 1
+            }
+        };
+        slots[slot].push((interval.start, interval.end));
+        locations[interval.vreg] = Some(Location::Slot(slot))
+    };
+    // The intervals given a register, and the register
+    let mut active: Vec<(Interval, Reg)> = vec![];
+    for interval in intervals {
+        active.retain(|(other, _)| other.end >= interval.start);
+        let free = POOL.iter().find(|reg| {
+            active.iter().all(|(_, other)| other != *reg) && !overlaps(fixed.get(reg), &interval)
+        });
+        if let Some(reg) = free {
+            locations[interval.vreg] = Some(Location::Reg(*reg));
+            active.push((interval, *reg));
+            continue;
+        }
+        let victim = active
+            .iter()
+            .enumerate()
+            .filter(|(_, (other, reg))| {
+                other.end > interval.end && !overlaps(fixed.get(reg), &interval)
+            })
+            .max_by_key(|(_, (other, _))| other.end)
+            .map(|(index, _)| index);
+        match victim {
+            Some(index) => {
+                let (other, reg) = active.remove(index);
+                spill(&mut locations, other);
+                locations[interval.vreg] = Some(Location::Reg(reg));
+                active.push((interval, reg))
+            }
+            None => spill(&mut locations, interval),
+        }
+    }
+    // Registers neither used nor defined are never rewritten
+    let unused = Location::Reg(ZERO);
+    locations.into_iter().map(|l| l.unwrap_or(unused)).collect()
+}
+
+/** Rewrites the instructions of `func` with the registers of its virtual registers at
+`locations`, loading those in slots before each instruction using them and storing them after
+each defining them */
+fn rewrite(func: &mut Func, locations: &[Location]) {
+    let location = |reg: Reg| match reg {
+        Reg::V(vreg) => locations[vreg],
+        reg => Location::Reg(reg),
+    };
+    for block in &mut func.blocks {
+        let mut insts = vec![];
+        for inst in &block.insts {
+            let mut loaded: Vec<(Reg, Reg)> = vec![];
+            for reg in inst.uses() {
+                if let Location::Slot(slot) = location(reg) {
+                    if loaded.iter().all(|(other, _)| *other != reg) {
+                        let scratch = [T0, T1][loaded.len()];
+                        insts.push(Inst::Lw(scratch, frame::slot(slot), FP));
+                        loaded.push((reg, scratch))
+                    }
+                }
+            }
+            let uses = |reg: Reg| match (
+                loaded.iter().find(|(other, _)| *other == reg),
+                location(reg),
+            ) {
+                (Some((_, scratch)), _) => *scratch,
+                (None, Location::Reg(reg)) => reg,
+                (None, Location::Slot(_)) => unreachable!("slots are loaded"),
+            };
+            let defs = |reg: Reg| match location(reg) {
+                Location::Reg(reg) => reg,
+                Location::Slot(_) => T2,
+            };
+            match inst.map_regs(uses, defs) {
+                // Moves between registers allocated alike
+                Inst::OpImm(Alu::Add, rd, rs, 0) if rd == rs => (),
+                inst => insts.push(inst),
+            }
+            for reg in inst.defs() {
+                if let Location::Slot(slot) = location(reg) {
+                    insts.push(Inst::Sw(T2, frame::slot(slot), FP))
+                }
+            }
+        }
+        block.insts = insts
+    }
+}
+
+/** Allocates the registers of `func` by `allocator`, setting the number of its slots */
+pub fn allocate(func: &mut Func, allocator: Allocator) {
+    let locations = match allocator {
+        Allocator::Spill => (0..func.vregs).map(Location::Slot).collect(),
+        Allocator::LinearScan => linear_scan(func),
+    };
+    func.slots = locations
+        .iter()
+        .filter_map(|location| match location {
+            Location::Slot(slot) => Some(slot + 1),
+            Location::Reg(_) => None,
+        })
+        .max()
+        .unwrap_or(0);
+    rewrite(func, &locations)
+}
@@ -0,0 +1,93 @@
+/*! Branch relaxation, rewriting the branches and jumps of functions whose blocks are laid out in
+their final order whose targets are out of their range: ±4 KiB for conditional branches, whose
+offsets have 13 bits, and ±1 MiB for jumps, whose offsets have 21. Branches out of range become the
+inverted branch over a jump to their target, labelled after it, and jumps out of range become
+`tail`, reaching any address by `auipc` and `jalr` through `t1`, which holds no value between
+instructions. Relaxing only grows the code, pushing other targets out of range in turn, so the
+addresses of the instructions are computed again until none is. They're computed uncompressed,
+compressing only bringing targets closer. */
+
+use crate::codegen::riscv::{Func, Inst, Target};
+
+use std::collections::HashMap;
+
+/** Whether `offset` fits in a signed immediate of `bits` bits */
+fn fits(offset: i64, bits: u32) -> bool {
+    -(1 << (bits - 1)) <= offset && offset < 1 << (bits - 1)
+}
+
+/** The size of `inst` in bytes once assembled, uncompressed */
+fn size(inst: &Inst) -> i64 {
+    match inst {
+        Inst::Label(_) => 0,
+        Inst::Li(..) | Inst::La(..) => 8,
+        Inst::Call(Target::Symbol(_), _) | Inst::Tail(Target::Symbol(_), _) => 8,
+        _ => 4,
+    }
+}
+
+/** The addresses of the instructions of `func` and of its labels, from its start. Jumps to the
+next block take no space, falling through instead */
+fn addresses(func: &Func) -> (Vec<Vec<i64>>, HashMap<&str, i64>) {
+    let mut addr = 0;
+    let mut addrs = vec![];
+    let mut labels = HashMap::new();
+    for (index, block) in func.blocks.iter().enumerate() {
+        labels.insert(&block.label[..], addr);
+        let next = func.blocks.get(index + 1).map(|block| &block.label);
+        let mut block_addrs = vec![];
+        for (i, inst) in block.insts.iter().enumerate() {
+            block_addrs.push(addr);
+            match inst {
+                Inst::J(label) if Some(label) == next && i + 1 == block.insts.len() => (),
+                Inst::Label(label) => {
+                    labels.insert(label, addr);
+                }
+                inst => addr += size(inst),
+            }
+        }
+        addrs.push(block_addrs)
+    }
+    (addrs, labels)
+}
+
+/** Rewrites the branches and jumps of `func` whose targets are out of their range */
+pub fn relax(func: &mut Func) {
+    let mut relaxed = 0;
+    loop {
+        let (addrs, labels) = addresses(func);
+        let offset = |label: &str, addr: i64| labels.get(label).map(|target| target - addr);
+        // The branches and jumps out of range, by block and index, in order
+        let mut far = vec![];
+        for (block, insts) in func.blocks.iter().zip(&addrs) {
+            for (inst, addr) in block.insts.iter().zip(insts) {
+                far.push(match inst {
+                    Inst::Branch(.., label) => offset(label, *addr).is_some_and(|o| !fits(o, 13)),
+                    Inst::J(label) => offset(label, *addr).is_some_and(|o| !fits(o, 21)),
+                    _ => false,
+                })
+            }
+        }
+        if !far.contains(&true) {
+            return;
+        }
+        let mut far = far.into_iter();
+        for block in &mut func.blocks {
+            let mut insts = vec![];
+            for inst in block.insts.drain(..) {
+                match (far.next().unwrap(), inst) {
+                    (true, Inst::Branch(cond, rs1, rs2, label)) => {
+                        let over = format!(".L{}.far{relaxed}", func.name);
+                        relaxed += 1;
+                        insts.push(Inst::Branch(cond.inverse(), rs1, rs2, over.clone()));
+                        insts.push(Inst::J(label));
+                        insts.push(Inst::Label(over))
+                    }
+                    (true, Inst::J(label)) => insts.push(Inst::Tail(Target::Symbol(label), 0)),
+                    (_, inst) => insts.push(inst),
+                }
+            }
+            block.insts = insts
+        }
+    }
+}
@@ -0,0 +1,1435 @@
+/*! Code generation for RV32I, emitting assembly for the GNU assembler from programs in static
+single assignment form whose [representation](crate::ir::repr) is decided. Instructions are
+selected for each instruction and terminator of each block, on virtual registers: one for each
+register of the program, and more for temporaries. Those of arithmetic, comparisons, tagging and
+branches are selected by [matching trees](crate::codegen::isel) of them against rules. Virtual
+registers are then [allocated](crate::codegen::regalloc) registers of the machine, or slots in the
+frame of their function, by linear scan or every one to a slot, [frames](crate::codegen::frame)
+are laid out, addressed from the frame pointer `s0`, and instructions are
+[legalized](crate::codegen::legalize), expanding constants, addresses and immediates out of range.
+Blocks are then [laid out](crate::codegen::layout) to fall through to their likely successors, and
+branches out of range are [relaxed](crate::codegen::relax).
+
+Functions follow the standard calling convention of RISC-V, for ILP32, so that C can call them and
+be called by them. They take their closure then their arguments in `a0` to `a7`, the rest on the
+stack from the stack pointer up, kept aligned to 16 bytes, and return their result in `a0` with
+`a1` zero. They may change the [registers of the caller](CALLER_SAVED), and restore those of the
+[callee](CALLEE_SAVED) they change. Raising an exception returns it instead, with `a1` nonzero,
+and callers check `a1` after every call, going to their handler or returning it in turn, but for
+calls of the builtins, functions of the runtime that never raise and take no closure.
+Calls in tail position leave the frame of the caller before jumping to the callee, so that tail
+recursion runs in constant space, but for those passing arguments on the stack or handling
+exceptions. Closures hold their code after their header, the values of declarations are in static
+data, and functions called by name take no closure.
+
+Blocks on the heap, allocated by bumping a pointer and never freed, have a header word of their
+length shifted left by 8 and their tag: the index of their constructor, 0 for tuples and records,
+[`OPAQUE`] for closures and tasks, [`MUTABLE`] for references and arrays, [`STRING`] for strings,
+and [`INTEGER`] for the integers of `Int32` and `Int64`, the length of those two in bytes. Records
+hold the id of the label of each field before it, their fields sorted by label, and projections
+search them. Tasks, run when spawned, hold whether they raised, then their value or exception. The
+builtins, structural equality, and the multiplication and division RV32I lacks are in the
+[runtime](RUNTIME) emitted with every program, for Linux, those done by the instructions of the M
+extension instead when it's [selected](March), and the arithmetic of [`Int64`](INT64) is emitted
+with the programs doing any. With the C extension, the assembler is left to compress the
+instructions that have a compressed encoding. */
+
+use crate::ast::ast::{Binary, Constant, Width, DIV, SUBSCRIPT};
+use crate::codegen::frame;
+use crate::codegen::isel::{self, Binding, Matched, Operand, Tree};
+use crate::codegen::layout::layout;
+use crate::codegen::legalize::legalize;
+use crate::codegen::regalloc::{self, Allocator};
+use crate::codegen::relax::relax;
+use crate::ir::anf::{Atom, Prim};
+use crate::ir::ssa::{self, Label, Op, Term};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Write};
+use std::iter::zip;
+use std::str::FromStr;
+
+/// The runtime, starting programs and providing what they need but their code
+pub const RUNTIME: &str = include_str!("runtime.s");
+/// The arithmetic of `Int64` in the runtime, emitted only with the programs doing any
+pub const INT64: &str = include_str!("int64.s");
+
+/// Tag of the blocks of closures and tasks, never compared
+pub const OPAQUE: i32 = 247;
+/// Tag of the blocks of references and arrays, equal only when they're the same
+pub const MUTABLE: i32 = 248;
+/// Tag of the blocks of strings
+pub const STRING: i32 = 249;
+/// Tag of the blocks of integers too wide to be immediates, those of `Int32` and `Int64`
+pub const INTEGER: i32 = 250;
+
+/// Registers, those of the machine by number, or virtual ones before allocation
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Reg {
+    X(u8),
+    V(usize),
+}
+
+pub const ZERO: Reg = Reg::X(0);
+pub const RA: Reg = Reg::X(1);
+pub const SP: Reg = Reg::X(2);
+pub const T0: Reg = Reg::X(5);
+pub const T1: Reg = Reg::X(6);
+pub const T2: Reg = Reg::X(7);
+/// The frame pointer, `s0`
+pub const FP: Reg = Reg::X(8);
+pub const A0: Reg = Reg::X(10);
+pub const A1: Reg = Reg::X(11);
+
+/// The registers arguments are passed in, in order
+pub const ARGS: [Reg; 8] = [
+    Reg::X(10),
+    Reg::X(11),
+    Reg::X(12),
+    Reg::X(13),
+    Reg::X(14),
+    Reg::X(15),
+    Reg::X(16),
+    Reg::X(17),
+];
+
+/// The registers calls may change: the return address, temporaries and arguments
+pub const CALLER_SAVED: [Reg; 16] = [
+    Reg::X(1),
+    Reg::X(5),
+    Reg::X(6),
+    Reg::X(7),
+    Reg::X(10),
+    Reg::X(11),
+    Reg::X(12),
+    Reg::X(13),
+    Reg::X(14),
+    Reg::X(15),
+    Reg::X(16),
+    Reg::X(17),
+    Reg::X(28),
+    Reg::X(29),
+    Reg::X(30),
+    Reg::X(31),
+];
+
+/// The registers calls keep, `s0` to `s11`, saved by the callees changing them
+pub const CALLEE_SAVED: [Reg; 12] = [
+    Reg::X(8),
+    Reg::X(9),
+    Reg::X(18),
+    Reg::X(19),
+    Reg::X(20),
+    Reg::X(21),
+    Reg::X(22),
+    Reg::X(23),
+    Reg::X(24),
+    Reg::X(25),
+    Reg::X(26),
+    Reg::X(27),
+];
+
+/// The names of the registers of the machine in the standard calling convention
+const NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// Operations of the arithmetic and logic unit
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Alu {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    /// Multiplying, of the M extension, like the division and remainder
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Conditions of branches, comparing two registers
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+/// What calls call: the function at a symbol, or at the address in a register
+#[derive(Debug, PartialEq, Clone)]
+pub enum Target {
+    Symbol(String),
+    Reg(Reg),
+}
+
+/// Instructions, those of RV32I and pseudo-instructions of the assembler
+#[derive(Debug, PartialEq, Clone)]
+pub enum Inst {
+    /// Operations on registers, like `add rd, rs1, rs2`
+    Op(Alu, Reg, Reg, Reg),
+    /// Operations on a register and an immediate, like `addi rd, rs1, imm`. Not `Sub` nor those of
+    /// the M extension
+    OpImm(Alu, Reg, Reg, i32),
+    /// `li rd, imm`, of any word
+    Li(Reg, i32),
+    /// `la rd, symbol`
+    La(Reg, String),
+    /// `lui rd, imm`, of the upper 20 bits
+    Lui(Reg, i32),
+    /// The upper 20 bits of a relocation, `lui rd, %hi(symbol)` or `auipc rd, %pcrel_hi(symbol)`
+    Upper(Reg, Reloc),
+    /// Adding the lower 12 bits of a relocation, like `addi rd, rs, %lo(symbol)`
+    Lower(Reg, Reg, Reloc),
+    /// A label within a block, of the `auipc` following it
+    Label(String),
+    /// `lw rd, offset(base)`
+    Lw(Reg, i32, Reg),
+    /// `lbu rd, offset(base)`
+    Lbu(Reg, i32, Reg),
+    /// `sw src, offset(base)`
+    Sw(Reg, i32, Reg),
+    /// Branching to the label if the registers compare, like `beq rs1, rs2, label`
+    Branch(Cond, Reg, Reg, String),
+    /// `j label`
+    J(String),
+    /// Calling the target with arguments in the given number of the [`ARGS`]
+    Call(Target, usize),
+    /// Calling the target in tail position, once the frame is left
+    Tail(Target, usize),
+    /// Returning `a0` and `a1`, once the frame is left
+    Ret,
+}
+
+/// Relocations, parts of the address of a symbol the linker fills immediates with
+#[derive(Debug, PartialEq, Clone)]
+pub enum Reloc {
+    /// The upper 20 bits of the address of the symbol, rounded for the lower 12 added to them
+    Hi(String),
+    Lo(String),
+    /// The upper 20 bits of the address of the symbol, relative to the `auipc` they're added to
+    PcrelHi(String),
+    /// The lower 12 bits of the address of the `auipc` at the label, relative to it
+    PcrelLo(String),
+}
+
+/// Blocks of instructions, ending in a branch or a jump, or going on to the next block
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub label: String,
+    pub insts: Vec<Inst>,
+}
+
+/// Functions, their blocks in order, the first labelled by their symbol
+#[derive(Debug, PartialEq, Clone)]
+pub struct Func {
+    pub name: String,
+    pub blocks: Vec<Block>,
+    /// The number of virtual registers
+    pub vregs: usize,
+    /// The number of slots of the frame, once registers are allocated
+    pub slots: usize,
+    /// The number of words of the arguments of calls passed on the stack, at most
+    pub outgoing: usize,
+}
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reg::X(n) => write!(f, "{}", NAMES[*n as usize]),
+            Reg::V(n) => write!(f, "v{n}"),
+        }
+    }
+}
+
+impl Display for Alu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Alu::Add => "add",
+            Alu::Sub => "sub",
+            Alu::Sll => "sll",
+            Alu::Slt => "slt",
+            Alu::Sltu => "sltu",
+            Alu::Xor => "xor",
+            Alu::Srl => "srl",
+            Alu::Sra => "sra",
+            Alu::Or => "or",
+            Alu::And => "and",
+            Alu::Mul => "mul",
+            Alu::Div => "div",
+            Alu::Rem => "rem",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Cond::Eq => "eq",
+            Cond::Ne => "ne",
+            Cond::Lt => "lt",
+            Cond::Ge => "ge",
+            Cond::Ltu => "ltu",
+            Cond::Geu => "geu",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inst::Op(alu, rd, rs1, rs2) => write!(f, "{alu} {rd}, {rs1}, {rs2}"),
+            Inst::OpImm(Alu::Add, rd, rs, 0) => write!(f, "mv {rd}, {rs}"),
+            Inst::OpImm(Alu::Sltu, rd, rs, imm) => write!(f, "sltiu {rd}, {rs}, {imm}"),
+            Inst::OpImm(alu, rd, rs, imm) => write!(f, "{alu}i {rd}, {rs}, {imm}"),
+            Inst::Li(rd, imm) => write!(f, "li {rd}, {imm}"),
+            Inst::La(rd, symbol) => write!(f, "la {rd}, {symbol}"),
+            Inst::Lui(rd, imm) => write!(f, "lui {rd}, {imm:#x}"),
+            Inst::Upper(rd, reloc @ Reloc::PcrelHi(_)) => write!(f, "auipc {rd}, {reloc}"),
+            Inst::Upper(rd, reloc) => write!(f, "lui {rd}, {reloc}"),
+            Inst::Lower(rd, rs, reloc) => write!(f, "addi {rd}, {rs}, {reloc}"),
+            Inst::Label(label) => write!(f, "{label}:"),
+            Inst::Lw(rd, offset, base) => write!(f, "lw {rd}, {offset}({base})"),
+            Inst::Lbu(rd, offset, base) => write!(f, "lbu {rd}, {offset}({base})"),
+            Inst::Sw(src, offset, base) => write!(f, "sw {src}, {offset}({base})"),
+            Inst::Branch(cond, rs1, rs2, label) => write!(f, "b{cond} {rs1}, {rs2}, {label}"),
+            Inst::J(label) => write!(f, "j {label}"),
+            Inst::Call(Target::Symbol(symbol), _) => write!(f, "call {symbol}"),
+            Inst::Call(Target::Reg(reg), _) => write!(f, "jalr {reg}"),
+            Inst::Tail(Target::Symbol(symbol), _) => write!(f, "tail {symbol}"),
+            Inst::Tail(Target::Reg(reg), _) => write!(f, "jr {reg}"),
+            Inst::Ret => write!(f, "ret"),
+        }
+    }
+}
+
+impl Display for Reloc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reloc::Hi(symbol) => write!(f, "%hi({symbol})"),
+            Reloc::Lo(symbol) => write!(f, "%lo({symbol})"),
+            Reloc::PcrelHi(symbol) => write!(f, "%pcrel_hi({symbol})"),
+            Reloc::PcrelLo(label) => write!(f, "%pcrel_lo({label})"),
+        }
+    }
+}
+
+impl Display for Func {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "{}:", block.label)?;
+            let next = self.blocks.get(index + 1).map(|block| &block.label);
+            for (i, inst) in block.insts.iter().enumerate() {
+                // Jumps to the next block fall through instead
+                match inst {
+                    Inst::J(label) if Some(label) == next && i + 1 == block.insts.len() => (),
+                    Inst::Label(_) => writeln!(f, "{inst}")?,
+                    inst => writeln!(f, "    {inst}")?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Cond {
+    /** The condition holding exactly when `self` doesn't */
+    pub fn inverse(self) -> Cond {
+        match self {
+            Cond::Eq => Cond::Ne,
+            Cond::Ne => Cond::Eq,
+            Cond::Lt => Cond::Ge,
+            Cond::Ge => Cond::Lt,
+            Cond::Ltu => Cond::Geu,
+            Cond::Geu => Cond::Ltu,
+        }
+    }
+}
+
+impl Inst {
+    /** The registers read */
+    pub fn uses(&self) -> Vec<Reg> {
+        let target = |target: &Target| match target {
+            Target::Reg(reg) => vec![*reg],
+            Target::Symbol(_) => vec![],
+        };
+        match self {
+            Inst::Op(_, _, rs1, rs2) | Inst::Branch(_, rs1, rs2, _) => vec![*rs1, *rs2],
+            Inst::OpImm(_, _, rs, _)
+            | Inst::Lower(_, rs, _)
+            | Inst::Lw(_, _, rs)
+            | Inst::Lbu(_, _, rs) => vec![*rs],
+            Inst::Sw(src, _, base) => vec![*src, *base],
+            Inst::Li(..)
+            | Inst::La(..)
+            | Inst::Lui(..)
+            | Inst::Upper(..)
+            | Inst::Label(_)
+            | Inst::J(_) => vec![],
+            Inst::Call(t, args) | Inst::Tail(t, args) => {
+                let mut uses = target(t);
+                uses.extend(&ARGS[..*args]);
+                uses
+            }
+            Inst::Ret => vec![A0, A1],
+        }
+    }
+
+    /** The registers written */
+    pub fn defs(&self) -> Vec<Reg> {
+        match self {
+            Inst::Op(_, rd, ..)
+            | Inst::OpImm(_, rd, ..)
+            | Inst::Li(rd, _)
+            | Inst::La(rd, _)
+            | Inst::Lui(rd, _)
+            | Inst::Upper(rd, _)
+            | Inst::Lower(rd, ..)
+            | Inst::Lw(rd, ..)
+            | Inst::Lbu(rd, ..) => vec![*rd],
+            Inst::Call(..) => CALLER_SAVED.to_vec(),
+            Inst::Sw(..)
+            | Inst::Branch(..)
+            | Inst::J(_)
+            | Inst::Label(_)
+            | Inst::Tail(..)
+            | Inst::Ret => vec![],
+        }
+    }
+
+    /** The instruction reading the registers `uses` maps those of `self` to, and writing those
+    `defs` does */
+    pub fn map_regs(&self, uses: impl Fn(Reg) -> Reg, defs: impl Fn(Reg) -> Reg) -> Inst {
+        let target = |target: &Target| match target {
+            Target::Reg(reg) => Target::Reg(uses(*reg)),
+            target => target.clone(),
+        };
+        match self {
+            Inst::Op(alu, rd, rs1, rs2) => Inst::Op(*alu, defs(*rd), uses(*rs1), uses(*rs2)),
+            Inst::OpImm(alu, rd, rs, imm) => Inst::OpImm(*alu, defs(*rd), uses(*rs), *imm),
+            Inst::Li(rd, imm) => Inst::Li(defs(*rd), *imm),
+            Inst::La(rd, symbol) => Inst::La(defs(*rd), symbol.clone()),
+            Inst::Lui(rd, imm) => Inst::Lui(defs(*rd), *imm),
+            Inst::Upper(rd, reloc) => Inst::Upper(defs(*rd), reloc.clone()),
+            Inst::Lower(rd, rs, reloc) => Inst::Lower(defs(*rd), uses(*rs), reloc.clone()),
+            Inst::Lw(rd, offset, base) => Inst::Lw(defs(*rd), *offset, uses(*base)),
+            Inst::Lbu(rd, offset, base) => Inst::Lbu(defs(*rd), *offset, uses(*base)),
+            Inst::Sw(src, offset, base) => Inst::Sw(uses(*src), *offset, uses(*base)),
+            Inst::Branch(cond, rs1, rs2, label) => {
+                Inst::Branch(*cond, uses(*rs1), uses(*rs2), label.clone())
+            }
+            Inst::Call(t, args) => Inst::Call(target(t), *args),
+            Inst::Tail(t, args) => Inst::Tail(target(t), *args),
+            inst => inst.clone(),
+        }
+    }
+}
+
+/** `mv rd, rs` */
+fn mv(rd: Reg, rs: Reg) -> Inst {
+    Inst::OpImm(Alu::Add, rd, rs, 0)
+}
+
+/** The header of blocks of `len` words, or bytes for strings, of the tag `tag` */
+fn header(len: usize, tag: i32) -> i32 {
+    ((len as i32) << 8) | tag
+}
+
+/** Whether the word of the constant `con`, raw or tagged, is the constant itself, rather than the
+address of its block in static data, as it is for strings and for `Int32` and `Int64` integers
+outside of arithmetic */
+pub(crate) fn immediate(con: &Constant, raw: bool) -> bool {
+    match con {
+        Constant::Str(_) => false,
+        Constant::Sized(_, Width::W32 | Width::W64) => raw,
+        _ => true,
+    }
+}
+
+/** The word of the constant `con`, raw or tagged, if it's [`immediate`] */
+pub(crate) fn constant(con: &Constant, raw: bool) -> i32 {
+    let n = match con {
+        Constant::Null => 0,
+        Constant::Boolean(b) => *b as i64,
+        Constant::Integer(n) | Constant::Sized(n, _) => *n,
+        Constant::Str(_) => unreachable!("strings are static data"),
+    };
+    match raw {
+        true => n as i32,
+        false => n.wrapping_mul(2).wrapping_add(1) as i32,
+    }
+}
+
+/** The symbols of the closure and the function of the builtin `id`, in the runtime */
+fn builtin(id: &str) -> Option<(&'static str, &'static str)> {
+    match id {
+        "print" => Some(("polylamb_print_closure", "polylamb_print")),
+        "printInt" => Some(("polylamb_print_int_closure", "polylamb_print_int")),
+        "readLine" => Some(("polylamb_read_line_closure", "polylamb_read_line")),
+        "showInt" => Some(("polylamb_show_int_closure", "polylamb_show_int")),
+        "concat" => Some(("polylamb_concat_closure", "polylamb_concat")),
+        "assert" => Some(("polylamb_assert_closure", "polylamb_assert")),
+        _ => None,
+    }
+}
+
+/** The symbol of the code of the function or declaration `name` */
+fn code(name: &str) -> String {
+    format!("{}.code", symbol(name))
+}
+
+/** The symbol of the value of the declaration `name` */
+fn value(name: &str) -> String {
+    format!("{}.value", symbol(name))
+}
+
+/** `name` as symbols spell it, with the characters they can't have, like the brackets and spaces of
+the names of [instances](crate::ast::mono), written as their code in hexadecimal between `$`s */
+fn symbol(name: &str) -> String {
+    let escape = |c: char| match c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+        true => c.to_string(),
+        false => format!("${:x}$", c as u32),
+    };
+    name.chars().map(escape).collect()
+}
+
+/// What the functions of a program share
+struct Module<'a> {
+    march: March,
+    /// The index of each constructor among those of its data type
+    ctors: HashMap<&'a str, usize>,
+    decls: HashSet<&'a str>,
+    funcs: HashSet<&'a str>,
+    /// The labels of records, sorted, their ids their positions
+    labels: Vec<&'a str>,
+    /// The string literals, by the number of their symbol
+    strings: Vec<&'a str>,
+    /// The constants boxed in static data of `Int32` and `Int64`, by the number of their symbol
+    integers: Vec<(i64, Width)>,
+    /// Whether functions do the arithmetic of `Int64`
+    int64: bool,
+}
+
+impl<'a> Module<'a> {
+    fn new(prog: &'a ssa::Prog, march: March) -> Self {
+        let datatypes = prog.datatypes.iter();
+        let ctors = datatypes.flat_map(|data| {
+            let ctors = data.ctors.iter().enumerate();
+            ctors.map(|(index, (ctor, _))| (ctor.as_str(), index))
+        });
+        let mut labels = vec![];
+        let funcs = prog.funcs.iter().chain(&prog.decls);
+        for inst in funcs
+            .flat_map(|func| &func.blocks)
+            .flat_map(|block| &block.insts)
+        {
+            match &inst.op {
+                Op::Record(fields) => labels.extend(fields.iter().map(|(l, _)| l.as_str())),
+                Op::Proj(_, label) => labels.push(label),
+                _ => (),
+            }
+        }
+        labels.sort();
+        labels.dedup();
+        Module {
+            march,
+            ctors: ctors.collect(),
+            decls: prog.decls.iter().map(|decl| decl.name.as_str()).collect(),
+            funcs: prog.funcs.iter().map(|func| func.name.as_str()).collect(),
+            labels,
+            strings: vec![],
+            integers: vec![],
+            int64: false,
+        }
+    }
+
+    /** The tagged id of the label `label` */
+    fn label(&self, label: &str) -> i32 {
+        let id = self.labels.binary_search(&label).unwrap();
+        2 * id as i32 + 1
+    }
+
+    /** The symbol of the string literal `s` */
+    fn string(&mut self, s: &'a str) -> String {
+        let index = match self.strings.iter().position(|other| *other == s) {
+            Some(index) => index,
+            None => {
+                self.strings.push(s);
+                self.strings.len() - 1
+            }
+        };
+        format!(".Lstr.{index}")
+    }
+
+    /** The symbol of the boxed integer `n` of the width `width` */
+    fn integer(&mut self, n: i64, width: Width) -> String {
+        let index = match self.integers.iter().position(|other| *other == (n, width)) {
+            Some(index) => index,
+            None => {
+                self.integers.push((n, width));
+                self.integers.len() - 1
+            }
+        };
+        format!(".Lint.{index}")
+    }
+}
+
+/// The selection of the instructions of a function
+struct Selection<'m, 'a> {
+    module: &'m mut Module<'a>,
+    func: &'a ssa::Func,
+    /// The virtual register of each register of the function
+    regs: HashMap<&'a str, Reg>,
+    vregs: usize,
+    /// The number of labels made up
+    locals: usize,
+    blocks: Vec<Block>,
+    /// The handler of the block selected
+    handler: Option<Label>,
+    outgoing: usize,
+    /// The operations of trees whose register is a subtree of the tree using it
+    folded: HashMap<&'a str, &'a Op>,
+}
+
+impl<'m, 'a> Selection<'m, 'a> {
+    fn fresh(&mut self) -> Reg {
+        self.vregs += 1;
+        Reg::V(self.vregs - 1)
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.blocks.last_mut().unwrap().insts.push(inst)
+    }
+
+    /** Starts the block `label`, the one selected until then going on to it */
+    fn start(&mut self, label: String) {
+        self.blocks.push(Block {
+            label,
+            insts: vec![],
+        })
+    }
+
+    /** A new label of the function */
+    fn local(&mut self) -> String {
+        self.locals += 1;
+        format!(".L{}.{}", symbol(&self.func.name), self.locals)
+    }
+
+    /** The label of the block `label` of the function */
+    fn label(&self, label: Label) -> String {
+        format!(".L{}.b{label}", symbol(&self.func.name))
+    }
+
+    /** The virtual register of the parameter of the block `label` */
+    fn param(&self, label: Label) -> Option<Reg> {
+        let params = &self.func.blocks[label].params;
+        params.first().map(|param| self.regs[param.as_str()])
+    }
+
+    /** A register holding `atom`, raw if it's a constant and `raw` */
+    fn atom(&mut self, atom: &'a Atom, raw: bool) -> Reg {
+        if let Some(reg) = atom.var().and_then(|var| self.regs.get(var.as_str())) {
+            return *reg;
+        }
+        let reg = self.fresh();
+        match atom {
+            Atom::Var(var) if self.module.decls.contains(var.as_str()) => {
+                let addr = self.fresh();
+                self.emit(Inst::La(addr, value(var)));
+                self.emit(Inst::Lw(reg, 0, addr))
+            }
+            Atom::Var(var) => match builtin(var) {
+                Some((closure, _)) => self.emit(Inst::La(reg, closure.to_string())),
+                None => self.emit(Inst::La(reg, code(var))),
+            },
+            Atom::Con(Constant::Str(s)) => {
+                let symbol = self.module.string(s);
+                self.emit(Inst::La(reg, symbol))
+            }
+            Atom::Con(con @ Constant::Sized(n, width)) if !immediate(con, raw) => {
+                let symbol = self.module.integer(*n, *width);
+                self.emit(Inst::La(reg, symbol))
+            }
+            Atom::Con(con) => self.emit(Inst::Li(reg, constant(con, raw))),
+        }
+        reg
+    }
+
+    /** Sets `dst` to `atom`, raw if it's a constant and `raw` */
+    fn load(&mut self, dst: Reg, atom: &'a Atom, raw: bool) {
+        match atom {
+            Atom::Con(con) if immediate(con, raw) => self.emit(Inst::Li(dst, constant(con, raw))),
+            atom => {
+                let src = self.atom(atom, raw);
+                self.emit(mv(dst, src))
+            }
+        }
+    }
+
+    /** Stores `atom` in the word `index` of the block `base` */
+    fn store(&mut self, base: Reg, index: usize, atom: &'a Atom) {
+        let src = self.atom(atom, false);
+        self.emit(Inst::Sw(src, 4 * index as i32, base))
+    }
+
+    /** Allocates a block of `len` words after its header `header`, its address in `dst` */
+    fn alloc(&mut self, dst: Reg, len: usize, header: i32) {
+        let (hp, next, head) = (self.fresh(), self.fresh(), self.fresh());
+        self.emit(Inst::La(hp, "polylamb_hp".to_string()));
+        self.emit(Inst::Lw(dst, 0, hp));
+        self.emit(Inst::OpImm(Alu::Add, next, dst, 4 * (len as i32 + 1)));
+        self.emit(Inst::Sw(next, 0, hp));
+        self.emit(Inst::Li(head, header));
+        self.emit(Inst::Sw(head, 0, dst))
+    }
+
+    /** Calls the runtime function `symbol` with `args`, its result in `dst` */
+    fn runtime(&mut self, dst: Reg, symbol: &str, args: &[Reg]) {
+        for (arg, src) in zip(ARGS, args) {
+            self.emit(mv(arg, *src))
+        }
+        self.emit(Inst::Call(Target::Symbol(symbol.to_string()), args.len()));
+        self.emit(mv(dst, A0))
+    }
+
+    /** Raises the exception in `exn`, to the handler of the block or the caller */
+    fn raise(&mut self, exn: Reg) {
+        match self.handler {
+            Some(handler) => {
+                if let Some(param) = self.param(handler) {
+                    self.emit(mv(param, exn))
+                }
+                self.emit(Inst::J(self.label(handler)))
+            }
+            None => {
+                self.emit(mv(A0, exn));
+                self.emit(Inst::Li(A1, 1));
+                self.emit(Inst::Ret)
+            }
+        }
+    }
+
+    /** Raises the exception `ctor` of the prelude, unless `cond` holds of `rs1` and `rs2` */
+    fn raise_unless(&mut self, cond: Cond, rs1: Reg, rs2: Reg, ctor: &str) {
+        let ok = self.local();
+        self.emit(Inst::Branch(cond, rs1, rs2, ok.clone()));
+        let raise = self.local();
+        self.start(raise);
+        let exn = self.fresh();
+        self.emit(Inst::Li(exn, 2 * self.module.ctors[ctor] as i32 + 1));
+        self.raise(exn);
+        self.start(ok)
+    }
+
+    /** The symbols of the builtin `fun` is, unless a variable shadows it */
+    fn builtin(&self, fun: &Atom) -> Option<(&'static str, &'static str)> {
+        let local = |var: &str| self.regs.contains_key(var);
+        fun.var()
+            .filter(|var| !local(var))
+            .and_then(|var| builtin(var))
+    }
+
+    /** Calls `fun` with `args`, leaving the frame first if `tail`. Builtins are called as the
+    functions of the runtime they are, with neither a closure nor an exception to check after */
+    fn call(&mut self, fun: &'a Atom, args: &'a [Atom], tail: bool) {
+        let local = |var: &str| self.regs.contains_key(var);
+        let (target, closure) = match fun.var().filter(|var| !local(var)) {
+            _ if self.builtin(fun).is_some() => {
+                let (_, function) = self.builtin(fun).unwrap();
+                (Target::Symbol(function.to_string()), None)
+            }
+            Some(var) if self.module.funcs.contains(var.as_str()) => {
+                (Target::Symbol(code(var)), None)
+            }
+            _ => {
+                let closure = self.atom(fun, false);
+                let code = self.fresh();
+                self.emit(Inst::Lw(code, 4, closure));
+                (Target::Reg(code), Some(closure))
+            }
+        };
+        let mut values: Vec<Reg> = closure.into_iter().collect();
+        for arg in args {
+            values.push(self.atom(arg, false))
+        }
+        for (index, value) in values.iter().enumerate().skip(ARGS.len()) {
+            let offset = 4 * (index - ARGS.len()) as i32;
+            self.emit(Inst::Sw(*value, offset, SP))
+        }
+        let stacked = values.len().saturating_sub(ARGS.len());
+        self.outgoing = self.outgoing.max(stacked);
+        for (arg, value) in zip(ARGS, &values) {
+            self.emit(mv(arg, *value))
+        }
+        let passed = values.len().min(ARGS.len());
+        self.emit(match tail {
+            true => Inst::Tail(target, passed),
+            false => Inst::Call(target, passed),
+        })
+    }
+
+    /** Goes on after a call of `fun`, with its result to the block `ret`, or returning it if None */
+    fn returned(&mut self, fun: &Atom, ret: Option<Label>) {
+        if self.builtin(fun).is_some() {
+            match ret {
+                Some(ret) => {
+                    if let Some(param) = self.param(ret) {
+                        self.emit(mv(param, A0))
+                    }
+                    self.emit(Inst::J(self.label(ret)))
+                }
+                None => {
+                    self.emit(Inst::Li(A1, 0));
+                    self.emit(Inst::Ret)
+                }
+            }
+            return;
+        }
+        if ret.is_none() && self.handler.is_none() {
+            return self.emit(Inst::Ret);
+        }
+        let raised = self.local();
+        self.emit(Inst::Branch(Cond::Ne, A1, ZERO, raised.clone()));
+        let returned = self.local();
+        self.start(returned);
+        match ret {
+            Some(ret) => {
+                if let Some(param) = self.param(ret) {
+                    self.emit(mv(param, A0))
+                }
+                self.emit(Inst::J(self.label(ret)))
+            }
+            None => self.emit(Inst::Ret),
+        }
+        self.start(raised);
+        self.raise(A0)
+    }
+
+    fn jump(&mut self, label: Label, args: &'a [Atom]) {
+        let params: Vec<Reg> = self.func.blocks[label]
+            .params
+            .iter()
+            .map(|param| self.regs[param.as_str()])
+            .collect();
+        let mut values: Vec<Reg> = args.iter().map(|arg| self.atom(arg, false)).collect();
+        // Arguments that are parameters too are moved aside first
+        if values.iter().any(|value| params.contains(value)) {
+            for value in &mut values {
+                let temp = self.fresh();
+                self.emit(mv(temp, *value));
+                *value = temp
+            }
+        }
+        for (param, value) in zip(params, values) {
+            if param != value {
+                self.emit(mv(param, value))
+            }
+        }
+        self.emit(Inst::J(self.label(label)))
+    }
+
+    fn term(&mut self, term: &'a Term) {
+        match term {
+            Term::Jump(label, args) => self.jump(*label, args),
+            Term::Branch {
+                cond,
+                branch_t,
+                branch_f,
+            } => {
+                let tree = Tree::Branch(Box::new(self.tree(cond, false)));
+                let scratch = self.fresh();
+                let targets = (self.label(*branch_t), self.label(*branch_f));
+                self.select(&tree, scratch, Some(targets))
+            }
+            Term::Switch {
+                scrut,
+                arms,
+                default,
+            } => {
+                let scrut = self.atom(scrut, false);
+                let (bit, index) = (self.fresh(), self.fresh());
+                let (boxed, matched) = (self.local(), self.local());
+                self.emit(Inst::OpImm(Alu::And, bit, scrut, 1));
+                self.emit(Inst::Branch(Cond::Eq, bit, ZERO, boxed.clone()));
+                let immediate = self.local();
+                self.start(immediate);
+                self.emit(Inst::OpImm(Alu::Sra, index, scrut, 1));
+                self.emit(Inst::J(matched.clone()));
+                self.start(boxed);
+                self.emit(Inst::Lbu(index, 0, scrut));
+                self.start(matched);
+                for (i, (ctor, label)) in arms.iter().enumerate() {
+                    if i + 1 == arms.len() && default.is_none() {
+                        return self.emit(Inst::J(self.label(*label)));
+                    }
+                    let tag = self.fresh();
+                    self.emit(Inst::Li(tag, self.module.ctors[ctor.as_str()] as i32));
+                    self.emit(Inst::Branch(Cond::Eq, index, tag, self.label(*label)));
+                    let next = self.local();
+                    self.start(next)
+                }
+                if let Some(default) = default {
+                    self.emit(Inst::J(self.label(*default)))
+                }
+            }
+            Term::Call { fun, args, ret } => {
+                self.call(fun, args, false);
+                self.returned(fun, Some(*ret))
+            }
+            Term::TailCall { fun, args } => {
+                let builtin = self.builtin(fun).is_some();
+                match self.handler.is_none() && args.len() < ARGS.len() && !builtin {
+                    true => self.call(fun, args, true),
+                    false => {
+                        self.call(fun, args, false);
+                        self.returned(fun, None)
+                    }
+                }
+            }
+            Term::Ret(val) => {
+                self.load(A0, val, false);
+                self.emit(Inst::Li(A1, 0));
+                self.emit(Inst::Ret)
+            }
+            Term::Raise(exn) => {
+                let exn = self.atom(exn, false);
+                self.raise(exn)
+            }
+        }
+    }
+
+    /** The tree of `atom`, raw if it's a constant and `raw`, its operation if it's folded */
+    fn tree(&self, atom: &'a Atom, raw: bool) -> Tree<'a> {
+        let op = atom.var().and_then(|var| self.folded.get(var.as_str()));
+        match op {
+            Some(op) => self.op_tree(op),
+            None => Tree::Leaf(atom, raw),
+        }
+    }
+
+    /** The tree of the operation `op` of trees */
+    fn op_tree(&self, op: &'a Op) -> Tree<'a> {
+        match op {
+            // Constants compared with are on the right
+            Op::Binop(lhs @ Atom::Con(_), op @ (Binary::Eq | Binary::Ne), rhs) => Tree::Binop(
+                Box::new(self.tree(rhs, false)),
+                op.clone(),
+                Box::new(self.tree(lhs, false)),
+            ),
+            Op::Binop(lhs, op, rhs) => Tree::Binop(
+                Box::new(self.tree(lhs, isel::raw(op))),
+                op.clone(),
+                Box::new(self.tree(rhs, isel::raw(op))),
+            ),
+            Op::Tag(atom) => Tree::Tag(Box::new(self.tree(atom, true))),
+            Op::Untag(atom) => Tree::Untag(Box::new(self.tree(atom, false))),
+            op => unreachable!("{op} isn't an operation of trees"),
+        }
+    }
+
+    /** Selects the instructions of `tree` by the least costly of the [rules](isel::RULES),
+    computing it into `dst`, or branching to `targets` */
+    fn select(&mut self, tree: &Tree<'a>, dst: Reg, targets: Option<(String, String)>) {
+        let (_, rule, bindings) = isel::best(isel::RULES, tree).expect("rules cover every tree");
+        let mut ops = vec![];
+        for binding in bindings {
+            ops.push(match binding {
+                Binding::Tree(Tree::Leaf(atom, raw)) => Operand::Reg(self.atom(atom, *raw)),
+                Binding::Tree(tree) => {
+                    let reg = self.fresh();
+                    self.select(tree, reg, None);
+                    Operand::Reg(reg)
+                }
+                Binding::Imm(n) => Operand::Imm(n),
+            })
+        }
+        for inst in (rule.emit)(&Matched { dst, ops, targets }) {
+            self.emit(inst)
+        }
+    }
+
+    /** The operations of binary operators not of trees */
+    fn binop(&mut self, dst: Reg, lhs: &'a Atom, op: &Binary, rhs: &'a Atom) {
+        let raw = isel::raw(op);
+        let (l, r) = (self.atom(lhs, raw), self.atom(rhs, raw));
+        match op {
+            Binary::Mul if self.module.march.m => self.emit(Inst::Op(Alu::Mul, dst, l, r)),
+            Binary::Mul => self.runtime(dst, "__mulsi3", &[l, r]),
+            Binary::Div | Binary::Mod => {
+                if !matches!(rhs, Atom::Con(c) if c.as_int().is_some_and(|n| n != 0)) {
+                    self.raise_unless(Cond::Ne, r, ZERO, DIV)
+                }
+                let (alu, symbol) = if *op == Binary::Div {
+                    (Alu::Div, "__divsi3")
+                } else {
+                    (Alu::Rem, "__modsi3")
+                };
+                match self.module.march.m {
+                    true => self.emit(Inst::Op(alu, dst, l, r)),
+                    false => self.runtime(dst, symbol, &[l, r]),
+                }
+            }
+            Binary::Eq | Binary::Ne => {
+                // The same words, or blocks structurally equal
+                let done = self.local();
+                self.emit(Inst::Li(dst, 1));
+                self.emit(Inst::Branch(Cond::Eq, l, r, done.clone()));
+                let differ = self.local();
+                self.start(differ);
+                let either = self.fresh();
+                self.emit(Inst::Li(dst, 0));
+                self.emit(Inst::Op(Alu::Or, either, l, r));
+                self.emit(Inst::OpImm(Alu::And, either, either, 1));
+                self.emit(Inst::Branch(Cond::Ne, either, ZERO, done.clone()));
+                let boxed = self.local();
+                self.start(boxed);
+                self.runtime(dst, "polylamb_equal", &[l, r]);
+                self.start(done);
+                if *op == Binary::Ne {
+                    self.emit(Inst::OpImm(Alu::Xor, dst, dst, 1))
+                }
+            }
+            op => unreachable!("{op} is an operation of trees"),
+        }
+    }
+
+    /** The address of the element `idx` of the array `arr`, before the header, raising
+    `Subscript` if it's out of bounds */
+    fn element(&mut self, arr: &'a Atom, idx: &'a Atom) -> Reg {
+        let (arr, idx) = (self.atom(arr, false), self.atom(idx, false));
+        let (raw, len, offset, addr) = (self.fresh(), self.fresh(), self.fresh(), self.fresh());
+        self.emit(Inst::OpImm(Alu::Sra, raw, idx, 1));
+        self.emit(Inst::Lw(len, 0, arr));
+        self.emit(Inst::OpImm(Alu::Srl, len, len, 8));
+        // Negative indices are too large unsigned
+        self.raise_unless(Cond::Ltu, raw, len, SUBSCRIPT);
+        self.emit(Inst::OpImm(Alu::Sll, offset, raw, 2));
+        self.emit(Inst::Op(Alu::Add, addr, arr, offset));
+        addr
+    }
+
+    fn prim(&mut self, dst: Reg, prim: &Prim, args: &'a [Atom]) {
+        match (prim, args) {
+            (Prim::Arith(op, Width::W64), [lhs, rhs]) => {
+                let (l, r) = (self.atom(lhs, false), self.atom(rhs, false));
+                let nonzero = matches!(rhs, Atom::Con(c) if c.as_int().is_some_and(|n| n != 0));
+                if matches!(op, Binary::Div | Binary::Mod) && !nonzero {
+                    let (low, high, either) = (self.fresh(), self.fresh(), self.fresh());
+                    self.emit(Inst::Lw(low, 4, r));
+                    self.emit(Inst::Lw(high, 8, r));
+                    self.emit(Inst::Op(Alu::Or, either, low, high));
+                    self.raise_unless(Cond::Ne, either, ZERO, DIV)
+                }
+                self.module.int64 = true;
+                self.runtime(dst, &format!("polylamb_int64_{}", op.name()), &[l, r])
+            }
+            (Prim::Convert(_, width), [arg]) => {
+                let src = self.atom(arg, true);
+                match width {
+                    Width::W8 | Width::W16 => {
+                        let unused = 32 - width.bits() as i32;
+                        self.emit(Inst::OpImm(Alu::Sll, dst, src, unused));
+                        self.emit(Inst::OpImm(Alu::Sra, dst, dst, unused))
+                    }
+                    Width::Int => self.emit(mv(dst, src)),
+                    Width::W32 | Width::W64 => unreachable!("conversions to Int{width} box"),
+                }
+            }
+            (Prim::Ref, [val]) => {
+                self.alloc(dst, 1, header(1, MUTABLE));
+                self.store(dst, 1, val)
+            }
+            (Prim::Deref, [cell]) => {
+                let cell = self.atom(cell, false);
+                self.emit(Inst::Lw(dst, 4, cell))
+            }
+            (Prim::Assign, [cell, val]) => {
+                let cell = self.atom(cell, false);
+                self.store(cell, 1, val);
+                self.emit(Inst::Li(dst, 1))
+            }
+            (Prim::Array, [len, init]) => {
+                let len = self.atom(len, false);
+                let init = self.atom(init, false);
+                let (raw, head, hp) = (self.fresh(), self.fresh(), self.fresh());
+                self.emit(Inst::OpImm(Alu::Sra, raw, len, 1));
+                self.raise_unless(Cond::Ge, raw, ZERO, SUBSCRIPT);
+                self.emit(Inst::OpImm(Alu::Sll, head, raw, 8));
+                self.emit(Inst::OpImm(Alu::Or, head, head, MUTABLE));
+                self.emit(Inst::La(hp, "polylamb_hp".to_string()));
+                self.emit(Inst::Lw(dst, 0, hp));
+                self.emit(Inst::Sw(head, 0, dst));
+                let (cursor, size, end) = (self.fresh(), self.fresh(), self.fresh());
+                self.emit(Inst::OpImm(Alu::Add, cursor, dst, 4));
+                self.emit(Inst::OpImm(Alu::Sll, size, raw, 2));
+                self.emit(Inst::Op(Alu::Add, end, cursor, size));
+                self.emit(Inst::Sw(end, 0, hp));
+                let (fill, done) = (self.local(), self.local());
+                self.start(fill.clone());
+                self.emit(Inst::Branch(Cond::Eq, cursor, end, done.clone()));
+                let next = self.local();
+                self.start(next);
+                self.emit(Inst::Sw(init, 0, cursor));
+                self.emit(Inst::OpImm(Alu::Add, cursor, cursor, 4));
+                self.emit(Inst::J(fill));
+                self.start(done)
+            }
+            (Prim::Sub, [arr, idx]) => {
+                let addr = self.element(arr, idx);
+                self.emit(Inst::Lw(dst, 4, addr))
+            }
+            (Prim::Update, [arr, idx, val]) => {
+                let addr = self.element(arr, idx);
+                self.store(addr, 1, val);
+                self.emit(Inst::Li(dst, 1))
+            }
+            (Prim::AssertEq, [lhs, rhs]) => {
+                let (l, r) = (self.atom(lhs, false), self.atom(rhs, false));
+                let equal = self.fresh();
+                self.runtime(equal, "polylamb_equal", &[l, r]);
+                let ok = self.local();
+                self.emit(Inst::Branch(Cond::Ne, equal, ZERO, ok.clone()));
+                let failed = self.local();
+                self.start(failed);
+                let symbol = "polylamb_assertion_failed".to_string();
+                self.emit(Inst::Call(Target::Symbol(symbol), 0));
+                self.start(ok);
+                self.emit(Inst::Li(dst, 1))
+            }
+            (Prim::Spawn, [fun]) => {
+                let fun = self.atom(fun, false);
+                let (code, raised, result) = (self.fresh(), self.fresh(), self.fresh());
+                self.emit(Inst::Lw(code, 4, fun));
+                self.emit(mv(A0, fun));
+                self.emit(Inst::Li(A1, 1));
+                self.emit(Inst::Call(Target::Reg(code), 2));
+                self.emit(mv(raised, A1));
+                self.emit(mv(result, A0));
+                self.alloc(dst, 2, header(2, OPAQUE));
+                self.emit(Inst::Sw(raised, 4, dst));
+                self.emit(Inst::Sw(result, 8, dst))
+            }
+            (Prim::Join, [task]) => {
+                let task = self.atom(task, false);
+                let (raised, exn) = (self.fresh(), self.fresh());
+                let ok = self.local();
+                self.emit(Inst::Lw(raised, 4, task));
+                self.emit(Inst::Branch(Cond::Eq, raised, ZERO, ok.clone()));
+                let failed = self.local();
+                self.start(failed);
+                self.emit(Inst::Lw(exn, 8, task));
+                self.raise(exn);
+                self.start(ok);
+                self.emit(Inst::Lw(dst, 8, task))
+            }
+            (prim, args) => unreachable!("{prim} of {} arguments", args.len()),
+        }
+    }
+
+    fn inst(&mut self, inst: &'a ssa::Inst) {
+        if self.folded.contains_key(inst.var.as_str()) {
+            return;
+        }
+        let dst = self.regs[inst.var.as_str()];
+        match &inst.op {
+            Op::Atom(atom) => self.load(dst, atom, false),
+            op if isel::treeable(op) => {
+                let tree = self.op_tree(op);
+                self.select(&tree, dst, None)
+            }
+            Op::Binop(lhs, op, rhs) => self.binop(dst, lhs, op, rhs),
+            Op::Tuple(entries) => {
+                self.alloc(dst, entries.len(), header(entries.len(), 0));
+                for (index, entry) in entries.iter().enumerate() {
+                    self.store(dst, index + 1, entry)
+                }
+            }
+            Op::Nth(block, index) | Op::Field(block, index) => {
+                let block = self.atom(block, false);
+                self.emit(Inst::Lw(dst, 4 * (*index as i32 + 1), block))
+            }
+            Op::Record(fields) => {
+                let mut fields: Vec<&(String, Atom)> = fields.iter().collect();
+                fields.sort_by(|(l1, _), (l2, _)| l1.cmp(l2));
+                self.alloc(dst, 2 * fields.len(), header(2 * fields.len(), 0));
+                for (index, (label, field)) in fields.into_iter().enumerate() {
+                    let id = self.fresh();
+                    self.emit(Inst::Li(id, self.module.label(label)));
+                    self.emit(Inst::Sw(id, 4 * (2 * index as i32 + 1), dst));
+                    self.store(dst, 2 * index + 2, field)
+                }
+            }
+            Op::Proj(record, label) => {
+                let record = self.atom(record, false);
+                let (cursor, id, word) = (self.fresh(), self.fresh(), self.fresh());
+                self.emit(Inst::OpImm(Alu::Add, cursor, record, 4));
+                self.emit(Inst::Li(id, self.module.label(label)));
+                let search = self.local();
+                self.start(search.clone());
+                self.emit(Inst::Lw(word, 0, cursor));
+                self.emit(Inst::OpImm(Alu::Add, cursor, cursor, 8));
+                self.emit(Inst::Branch(Cond::Ne, word, id, search));
+                let found = self.local();
+                self.start(found);
+                self.emit(Inst::Lw(dst, -4, cursor))
+            }
+            Op::Data(ctor, fields) if fields.is_empty() => self.emit(Inst::Li(
+                dst,
+                2 * self.module.ctors[ctor.as_str()] as i32 + 1,
+            )),
+            Op::Data(ctor, fields) => {
+                let tag = self.module.ctors[ctor.as_str()] as i32;
+                self.alloc(dst, fields.len(), header(fields.len(), tag));
+                for (index, field) in fields.iter().enumerate() {
+                    self.store(dst, index + 1, field)
+                }
+            }
+            Op::Closure(func, env) => {
+                self.alloc(dst, env.len() + 1, header(env.len() + 1, OPAQUE));
+                let addr = self.fresh();
+                self.emit(Inst::La(addr, code(func)));
+                self.emit(Inst::Sw(addr, 4, dst));
+                for (index, val) in env.iter().enumerate() {
+                    self.store(dst, index + 2, val)
+                }
+            }
+            Op::Env(closure, index) => {
+                let closure = self.atom(closure, false);
+                self.emit(Inst::Lw(dst, 4 * (*index as i32 + 2), closure))
+            }
+            Op::Prim(prim, args) => self.prim(dst, prim, args),
+            Op::Box(width, raw) => {
+                let src = self.atom(raw, true);
+                let words = width.bits() as usize / 32;
+                self.alloc(dst, words, header(4 * words, INTEGER));
+                self.emit(Inst::Sw(src, 4, dst));
+                if *width == Width::W64 {
+                    let high = self.fresh();
+                    self.emit(Inst::OpImm(Alu::Sra, high, src, 31));
+                    self.emit(Inst::Sw(high, 8, dst))
+                }
+            }
+            Op::Unbox(atom @ Atom::Con(_)) => self.load(dst, atom, true),
+            Op::Unbox(boxed) => {
+                let boxed = self.atom(boxed, false);
+                self.emit(Inst::Lw(dst, 4, boxed))
+            }
+            Op::Tag(_) | Op::Untag(_) => unreachable!("tagging is an operation of trees"),
+        }
+    }
+}
+
+/** The operations of trees of `func` whose register is used once, by another operation of trees
+or the branch of the same block, by register */
+fn folded(func: &ssa::Func) -> HashMap<&str, &Op> {
+    let mut uses: HashMap<&str, usize> = HashMap::new();
+    for block in &func.blocks {
+        let insts = block.insts.iter().flat_map(|inst| inst.op.atoms());
+        for var in insts.chain(block.term.atoms()).filter_map(Atom::var) {
+            *uses.entry(var).or_default() += 1
+        }
+    }
+    let mut folded = HashMap::new();
+    for block in &func.blocks {
+        let mut users = HashSet::new();
+        for inst in block.insts.iter().filter(|inst| isel::treeable(&inst.op)) {
+            users.extend(inst.op.atoms().into_iter().filter_map(Atom::var))
+        }
+        if let Term::Branch { cond, .. } = &block.term {
+            users.extend(cond.var())
+        }
+        for inst in &block.insts {
+            let var = inst.var.as_str();
+            if isel::treeable(&inst.op) && uses.get(var) == Some(&1) && users.contains(&inst.var) {
+                folded.insert(var, &inst.op);
+            }
+        }
+    }
+    folded
+}
+
+/** Selects the instructions of `func`, of the module `module` */
+fn select<'a>(func: &'a ssa::Func, module: &mut Module<'a>) -> Func {
+    let mut selection = Selection {
+        module,
+        func,
+        regs: HashMap::new(),
+        vregs: 0,
+        locals: 0,
+        blocks: vec![],
+        handler: None,
+        outgoing: 0,
+        folded: folded(func),
+    };
+    for block in &func.blocks {
+        let vars = block.insts.iter().map(|inst| &inst.var);
+        for var in block.params.iter().chain(vars) {
+            let reg = selection.fresh();
+            selection.regs.insert(var, reg);
+        }
+    }
+    selection.start(code(&func.name));
+    for (index, param) in func.params().iter().enumerate() {
+        let reg = selection.regs[param.as_str()];
+        match ARGS.get(index) {
+            Some(arg) => selection.emit(mv(reg, *arg)),
+            None => selection.emit(Inst::Lw(reg, 4 * (index - ARGS.len()) as i32, FP)),
+        }
+    }
+    for (label, block) in func.blocks.iter().enumerate() {
+        selection.start(selection.label(label));
+        selection.handler = block.handler;
+        for inst in &block.insts {
+            selection.inst(inst)
+        }
+        selection.term(&block.term)
+    }
+    Func {
+        name: code(&func.name),
+        blocks: selection.blocks,
+        vregs: selection.vregs,
+        slots: 0,
+        outgoing: selection.outgoing,
+    }
+}
+
+/** `polylamb_main`, evaluating the declarations `decls` in order, ending the program on the
+exceptions they raise. It's `int polylamb_main(void)` to C, returning 0 */
+fn main(decls: &[ssa::Func]) -> Func {
+    let uncaught = ".Lpolylamb_main.uncaught".to_string();
+    let mut blocks = vec![Block {
+        label: "polylamb_main".to_string(),
+        insts: vec![],
+    }];
+    for (index, decl) in decls.iter().enumerate() {
+        let insts = &mut blocks.last_mut().unwrap().insts;
+        insts.push(Inst::Call(Target::Symbol(code(&decl.name)), 0));
+        insts.push(Inst::Branch(Cond::Ne, A1, ZERO, uncaught.clone()));
+        let insts = vec![
+            Inst::La(Reg::V(index), value(&decl.name)),
+            Inst::Sw(A0, 0, Reg::V(index)),
+        ];
+        blocks.push(Block {
+            label: format!(".Lpolylamb_main.{index}"),
+            insts,
+        })
+    }
+    let insts = &mut blocks.last_mut().unwrap().insts;
+    insts.extend([Inst::Li(A0, 0), Inst::Ret]);
+    blocks.push(Block {
+        label: uncaught,
+        insts: vec![Inst::Call(Target::Symbol("polylamb_uncaught".into()), 1)],
+    });
+    Func {
+        name: "polylamb_main".to_string(),
+        blocks,
+        vregs: decls.len(),
+        slots: 0,
+        outgoing: 0,
+    }
+}
+
+/** `s` quoted for `.ascii` */
+fn quoted(s: &str) -> String {
+    let mut quoted = String::new();
+    for byte in s.bytes() {
+        match byte {
+            b'"' | b'\\' => write!(quoted, "\\{}", byte as char),
+            b' '..=b'~' => write!(quoted, "{}", byte as char),
+            byte => write!(quoted, "\\{byte:03o}"),
+        }
+        .unwrap()
+    }
+    format!("\"{quoted}\"")
+}
+
+/// The extensions of RV32I instructions are selected from, like `rv32imc`
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct March {
+    /// Multiplication and division, done by the runtime otherwise
+    pub m: bool,
+    /// Compressed instructions, which the assembler picks where they're legal
+    pub c: bool,
+}
+
+impl FromStr for March {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut march = March::default();
+        // In their canonical order
+        for extension in s.strip_prefix("rv32i").ok_or(())?.chars() {
+            match extension {
+                'm' if !march.m && !march.c => march.m = true,
+                'c' if !march.c => march.c = true,
+                _ => return Err(()),
+            }
+        }
+        Ok(march)
+    }
+}
+
+/// How programs are compiled to assembly
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Options {
+    pub march: March,
+    pub allocator: Allocator,
+    /// Whether code addresses symbols relative to itself, as position independent code does
+    pub pic: bool,
+}
+
+/** The assembly of `prog`, whose representation is decided, compiled with `options`, followed by
+the runtime */
+pub fn emit(prog: &ssa::Prog, options: Options) -> String {
+    let mut module = Module::new(prog, options.march);
+    let mut funcs = vec![main(&prog.decls)];
+    for func in prog.funcs.iter().chain(&prog.decls) {
+        funcs.push(select(func, &mut module))
+    }
+    let mut out = "    .text\n    .globl polylamb_main\n".to_string();
+    if options.march.c {
+        out.push_str("    .option rvc\n")
+    }
+    for func in &mut funcs {
+        regalloc::allocate(func, options.allocator);
+        frame::lower(func);
+        legalize(func, options.pic);
+        layout(func);
+        relax(func);
+        write!(out, "{func}").unwrap()
+    }
+    out.push_str("\n    .data\n    .align 2\n");
+    for decl in &prog.decls {
+        writeln!(out, "{}:\n    .word 0", value(&decl.name)).unwrap()
+    }
+    for (index, s) in module.strings.iter().enumerate() {
+        writeln!(out, ".Lstr.{index}:").unwrap();
+        writeln!(out, "    .word {}", header(s.len(), STRING)).unwrap();
+        writeln!(out, "    .ascii {}\n    .align 2", quoted(s)).unwrap()
+    }
+    for (index, (n, width)) in module.integers.iter().enumerate() {
+        let words = width.bits() as usize / 32;
+        writeln!(out, ".Lint.{index}:").unwrap();
+        writeln!(out, "    .word {}", header(4 * words, INTEGER)).unwrap();
+        for word in 0..words {
+            writeln!(out, "    .word {}", (n >> (32 * word)) as i32).unwrap()
+        }
+    }
+    out.push('\n');
+    match module.int64 {
+        true => out + RUNTIME + INT64,
+        false => out + RUNTIME,
+    }
+}
@@ -0,0 +1,491 @@
+# The runtime of programs compiled to RV32I, for Linux. It starts programs, evaluating their
+# declarations in order by `polylamb_main`, and provides the builtins, structural equality, the
+# failures ending programs, and the multiplication and division RV32I lacks. Blocks on the heap
+# have a header word of their length shifted left by 8 and their tag: 248 for references and
+# arrays, 249 for strings and 250 for the integers of Int32 and Int64, whose length is in bytes.
+# Everything here follows the standard calling convention, so that any of it could be written in
+# C, but for the code of the closures of the builtins: those take their closure in a0 and their
+# argument in a1, and return their result in a0 with a1 zero, like compiled functions do, calling
+# the builtins themselves.
+
+    .text
+    .globl _start
+_start:
+    .option push
+    .option norelax
+    la gp, __global_pointer$
+    .option pop
+    li a0, 0
+    li a7, 214                  # brk
+    ecall
+    la t0, polylamb_hp
+    sw a0, 0(t0)
+    li t1, 0x1000000            # 16 MiB of heap, never collected
+    add a0, a0, t1
+    li a7, 214
+    ecall
+    call polylamb_main
+    li a7, 93                   # exit
+    ecall
+
+# Writes the string a0 to the file descriptor a1
+polylamb_write:
+    lw a2, 0(a0)
+    srli a2, a2, 8
+    addi t0, a0, 4
+    mv a0, a1
+    mv a1, t0
+    li a7, 64                   # write
+    ecall
+    ret
+
+# Prints the string a0
+polylamb_print:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    li a1, 1
+    call polylamb_write
+    li a0, 1
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+# Prints the integer a0 in decimal
+polylamb_print_int:
+    addi sp, sp, -32
+    sw ra, 28(sp)
+    sw s0, 24(sp)
+    sw s1, 20(sp)
+    sw a0, 16(sp)
+    srai s0, a0, 1
+    addi s1, sp, 16             # the digits, from the end of the buffer at sp
+    bgez s0, .Lprint_int_digit
+    neg s0, s0
+.Lprint_int_digit:
+    mv a0, s0
+    li a1, 10
+    call __umodsi3
+    addi a0, a0, 48
+    addi s1, s1, -1
+    sb a0, 0(s1)
+    mv a0, s0
+    li a1, 10
+    call __udivsi3
+    mv s0, a0
+    bnez s0, .Lprint_int_digit
+    lw t0, 16(sp)
+    bgez t0, .Lprint_int_write
+    li t0, 45                   # -
+    addi s1, s1, -1
+    sb t0, 0(s1)
+.Lprint_int_write:
+    li a0, 1
+    mv a1, s1
+    addi a2, sp, 16
+    sub a2, a2, s1
+    li a7, 64                   # write
+    ecall
+    li a0, 1
+    lw ra, 28(sp)
+    lw s0, 24(sp)
+    lw s1, 20(sp)
+    addi sp, sp, 32
+    ret
+
+# Reads a line of the standard input, without its newline
+polylamb_read_line:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    sw s0, 8(sp)
+    sw s1, 4(sp)
+    la t0, polylamb_hp
+    lw s0, 0(t0)                # the string
+    addi s1, s0, 4              # its next byte
+.Lread_line_byte:
+    li a0, 0
+    mv a1, s1
+    li a2, 1
+    li a7, 63                   # read
+    ecall
+    blez a0, .Lread_line_done
+    lbu t0, 0(s1)
+    li t1, 10
+    beq t0, t1, .Lread_line_done
+    addi s1, s1, 1
+    j .Lread_line_byte
+.Lread_line_done:
+    sub t0, s1, s0
+    addi t0, t0, -4
+    slli t0, t0, 8
+    ori t0, t0, 249
+    sw t0, 0(s0)
+    addi s1, s1, 3
+    andi s1, s1, -4
+    la t0, polylamb_hp
+    sw s1, 0(t0)
+    mv a0, s0
+    lw ra, 12(sp)
+    lw s0, 8(sp)
+    lw s1, 4(sp)
+    addi sp, sp, 16
+    ret
+
+# The integer a0 in decimal, as a new string
+polylamb_show_int:
+    addi sp, sp, -32
+    sw ra, 28(sp)
+    sw s0, 24(sp)
+    sw s1, 20(sp)
+    sw a0, 16(sp)
+    srai s0, a0, 1
+    addi s1, sp, 16             # the digits, from the end of the buffer at sp
+    bgez s0, .Lshow_int_digit
+    neg s0, s0
+.Lshow_int_digit:
+    mv a0, s0
+    li a1, 10
+    call __umodsi3
+    addi a0, a0, 48
+    addi s1, s1, -1
+    sb a0, 0(s1)
+    mv a0, s0
+    li a1, 10
+    call __udivsi3
+    mv s0, a0
+    bnez s0, .Lshow_int_digit
+    lw t0, 16(sp)
+    bgez t0, .Lshow_int_string
+    li t0, 45                   # -
+    addi s1, s1, -1
+    sb t0, 0(s1)
+.Lshow_int_string:
+    la t0, polylamb_hp
+    lw a0, 0(t0)                # the string
+    addi t1, sp, 16
+    sub t1, t1, s1
+    slli t2, t1, 8
+    ori t2, t2, 249
+    sw t2, 0(a0)
+    addi a1, a0, 4
+    mv a2, s1
+    call polylamb_copy
+    addi a1, a1, 3
+    andi a1, a1, -4
+    la t0, polylamb_hp
+    sw a1, 0(t0)
+    lw ra, 28(sp)
+    lw s0, 24(sp)
+    lw s1, 20(sp)
+    addi sp, sp, 32
+    ret
+
+# The pair of strings a0 concatenated, as a new string
+polylamb_concat:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    lw t3, 4(a0)
+    lw t4, 8(a0)
+    la t0, polylamb_hp
+    lw a0, 0(t0)                # the string
+    lw t1, 0(t3)
+    srli t1, t1, 8
+    lw t2, 0(t4)
+    srli t2, t2, 8
+    add t5, t1, t2
+    slli t5, t5, 8
+    ori t5, t5, 249
+    sw t5, 0(a0)
+    addi a1, a0, 4
+    addi a2, t3, 4
+    call polylamb_copy
+    mv t1, t2
+    addi a2, t4, 4
+    call polylamb_copy
+    addi a1, a1, 3
+    andi a1, a1, -4
+    la t0, polylamb_hp
+    sw a1, 0(t0)
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+# Copies the t1 bytes at a2 to a1, leaving a1 past them. Only touches a1, a2, t1 and t6
+polylamb_copy:
+    beqz t1, .Lcopy_done
+    lbu t6, 0(a2)
+    sb t6, 0(a1)
+    addi a1, a1, 1
+    addi a2, a2, 1
+    addi t1, t1, -1
+    j polylamb_copy
+.Lcopy_done:
+    ret
+
+# Ends the program unless a0 is true
+polylamb_assert:
+    li t0, 3                    # true
+    bne a0, t0, polylamb_assertion_failed
+    li a0, 1
+    ret
+
+# The code of the closures of the builtins
+polylamb_print_code:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    mv a0, a1
+    call polylamb_print
+    li a1, 0
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+polylamb_print_int_code:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    mv a0, a1
+    call polylamb_print_int
+    li a1, 0
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+polylamb_read_line_code:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    mv a0, a1
+    call polylamb_read_line
+    li a1, 0
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+polylamb_show_int_code:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    mv a0, a1
+    call polylamb_show_int
+    li a1, 0
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+polylamb_concat_code:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    mv a0, a1
+    call polylamb_concat
+    li a1, 0
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+polylamb_assert_code:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    mv a0, a1
+    call polylamb_assert
+    li a1, 0
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+polylamb_assertion_failed:
+    la a0, .Lassertion_failed
+    li a1, 2
+    call polylamb_write
+    li a0, 1
+    li a7, 93                   # exit
+    ecall
+
+# Ends the program on the exception a0 no handler caught
+polylamb_uncaught:
+    la a0, .Luncaught
+    li a1, 2
+    call polylamb_write
+    li a0, 2
+    li a7, 93                   # exit
+    ecall
+
+# Whether the values a0 and a1 are structurally equal, 1 if they are and 0 if not
+polylamb_equal:
+    beq a0, a1, .Lequal_true
+    or t0, a0, a1
+    andi t0, t0, 1
+    bnez t0, .Lequal_false      # an immediate and another value
+    lw t0, 0(a0)
+    lw t1, 0(a1)
+    bne t0, t1, .Lequal_false   # of other tags or lengths
+    andi t1, t0, 255
+    li t2, 248
+    beq t1, t2, .Lequal_false   # mutable, equal only when the same
+    srli t0, t0, 8
+    li t2, 249
+    bgeu t1, t2, .Lequal_bytes  # strings and integers
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    sw s0, 8(sp)
+    sw s1, 4(sp)
+    sw s2, 0(sp)
+    mv s0, a0
+    mv s1, a1
+    slli s2, t0, 2
+    add s2, s0, s2              # the last field of a0
+.Lequal_field:
+    li a0, 1
+    beq s0, s2, .Lequal_fields_done
+    addi s0, s0, 4
+    addi s1, s1, 4
+    lw a0, 0(s0)
+    lw a1, 0(s1)
+    call polylamb_equal
+    bnez a0, .Lequal_field
+.Lequal_fields_done:
+    lw ra, 12(sp)
+    lw s0, 8(sp)
+    lw s1, 4(sp)
+    lw s2, 0(sp)
+    addi sp, sp, 16
+    ret
+.Lequal_bytes:
+    addi a0, a0, 4
+    addi a1, a1, 4
+    add t0, a0, t0
+.Lequal_byte:
+    beq a0, t0, .Lequal_true
+    lbu t1, 0(a0)
+    lbu t2, 0(a1)
+    bne t1, t2, .Lequal_false
+    addi a0, a0, 1
+    addi a1, a1, 1
+    j .Lequal_byte
+.Lequal_true:
+    li a0, 1
+    ret
+.Lequal_false:
+    li a0, 0
+    ret
+
+# a0 * a1, modulo 2^32
+    .globl __mulsi3
+__mulsi3:
+    mv t0, a0
+    li a0, 0
+.Lmul_bit:
+    beqz a1, .Lmul_done
+    andi t1, a1, 1
+    beqz t1, .Lmul_shift
+    add a0, a0, t0
+.Lmul_shift:
+    slli t0, t0, 1
+    srli a1, a1, 1
+    j .Lmul_bit
+.Lmul_done:
+    ret
+
+# a0 / a1 unsigned, the remainder in a1
+    .globl __udivsi3
+__udivsi3:
+    li t0, 0                    # the quotient
+    li t1, 0                    # the remainder
+    li t2, 32
+.Ludiv_bit:
+    slli t1, t1, 1
+    srli t3, a0, 31
+    or t1, t1, t3
+    slli a0, a0, 1
+    slli t0, t0, 1
+    bltu t1, a1, .Ludiv_next
+    sub t1, t1, a1
+    ori t0, t0, 1
+.Ludiv_next:
+    addi t2, t2, -1
+    bnez t2, .Ludiv_bit
+    mv a0, t0
+    mv a1, t1
+    ret
+
+    .globl __umodsi3
+__umodsi3:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    call __udivsi3
+    mv a0, a1
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+# a0 / a1 rounding towards zero
+    .globl __divsi3
+__divsi3:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    xor t0, a0, a1
+    sw t0, 8(sp)                # negative if the quotient is
+    bgez a0, .Ldiv_dividend
+    neg a0, a0
+.Ldiv_dividend:
+    bgez a1, .Ldiv_divisor
+    neg a1, a1
+.Ldiv_divisor:
+    call __udivsi3
+    lw t0, 8(sp)
+    bgez t0, .Ldiv_done
+    neg a0, a0
+.Ldiv_done:
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+# The remainder of a0 / a1, of the sign of a0
+    .globl __modsi3
+__modsi3:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    sw a0, 8(sp)
+    bgez a0, .Lmod_dividend
+    neg a0, a0
+.Lmod_dividend:
+    bgez a1, .Lmod_divisor
+    neg a1, a1
+.Lmod_divisor:
+    call __udivsi3
+    mv a0, a1
+    lw t0, 8(sp)
+    bgez t0, .Lmod_done
+    neg a0, a0
+.Lmod_done:
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+
+    .data
+    .align 2
+polylamb_hp:
+    .word 0
+# The closures of the builtins, of tag 247 and holding their code
+polylamb_print_closure:
+    .word 503
+    .word polylamb_print_code
+polylamb_print_int_closure:
+    .word 503
+    .word polylamb_print_int_code
+polylamb_read_line_closure:
+    .word 503
+    .word polylamb_read_line_code
+polylamb_show_int_closure:
+    .word 503
+    .word polylamb_show_int_code
+polylamb_concat_closure:
+    .word 503
+    .word polylamb_concat_code
+polylamb_assert_closure:
+    .word 503
+    .word polylamb_assert_code
+.Lassertion_failed:
+    .word 4601                  # 17 bytes
+    .ascii "Assertion failed\n"
+    .align 2
+.Luncaught:
+    .word 5113                  # 19 bytes
+    .ascii "Uncaught exception\n"
+    .align 2
@@ -0,0 +1,949 @@
+/*! A simulator of RV32IMC running programs on Linux, to test the assembly generated without a
+toolchain. It assembles the text of a program as the GNU assembler would, the pseudo-instructions
+and directives generated and used by the runtime included, checking the ranges of immediates and of
+the offsets of branches and jumps, and filling the relocations of addresses generated, and links it
+at fixed addresses. Once `.option rvc` is given, instructions with a compressed encoding of the C
+extension take 2 bytes, but branches and jumps to symbols, whose encoding depends on their offsets. It then runs the machine code from `_start` until the program exits, faulting on
+illegal instructions and on accesses out of its memory or misaligned, with the few system calls the
+runtime makes: `read` from the input, `write` to the output and errors, `exit` and `brk`. */
+
+use crate::codegen::legalize;
+use crate::codegen::riscv::{Alu, Cond};
+
+use std::collections::HashMap;
+use std::iter::zip;
+
+/// The address of the text of programs
+pub const TEXT: u32 = 0x10000;
+/// The address above the stack, which starts empty there
+const STACK: u32 = 0x8000_0000;
+const STACK_SIZE: u32 = 1 << 20;
+/// How far the break can grow past the data, in bytes
+const HEAP_SIZE: u32 = 1 << 28;
+
+/// How programs that ran exited
+#[derive(Debug, PartialEq, Clone)]
+pub struct Exit {
+    pub code: i32,
+    pub output: String,
+    pub errors: String,
+    /// The number of instructions run
+    pub steps: usize,
+}
+
+/// Why programs couldn't run to their exit
+#[derive(Debug, PartialEq, Clone)]
+pub enum Fault {
+    /// An error assembling the line, counted from 1
+    Assembly(usize, String),
+    /// An access out of memory or misaligned, at the address
+    Memory(u32),
+    /// An illegal instruction or system call, at the address
+    Illegal(u32),
+    /// Running out of fuel
+    Exhausted,
+}
+
+/// The sizes of loads and stores, their values extended by sign or by zeros
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Size {
+    Byte,
+    ByteU,
+    Half,
+    HalfU,
+    Word,
+}
+
+/// Machine instructions, registers by number and offsets relative to the instruction
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Machine {
+    Op(Alu, u8, u8, u8),
+    OpImm(Alu, u8, u8, i32),
+    Lui(u8, i32),
+    Auipc(u8, i32),
+    Load(Size, u8, i32, u8),
+    Store(Size, u8, i32, u8),
+    Branch(Cond, u8, u8, i32),
+    Jal(u8, i32),
+    Jalr(u8, u8, i32),
+    Ecall,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Section {
+    Text,
+    Data,
+}
+
+/// Statements of the text, assembled once the symbols are known
+struct Statement {
+    line: usize,
+    addr: u32,
+    mnemonic: String,
+    operands: Vec<String>,
+    /// The size of each instruction it assembles to, in bytes
+    sizes: Vec<u32>,
+}
+
+/// Programs assembled and linked
+struct Image {
+    /// The instructions of the text and their sizes, by the halfword they start at
+    text: Vec<Option<(Machine, u32)>>,
+    data: Vec<u8>,
+    /// The address of the data, past the text
+    base: u32,
+    entry: u32,
+}
+
+/** The number of the register `name`, by its number or its name in the calling convention */
+fn reg(name: &str) -> Result<u8, String> {
+    const NAMES: [&str; 32] = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+        "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+        "t5", "t6",
+    ];
+    if name == "fp" {
+        return Ok(8);
+    }
+    if let Some(n) = name.strip_prefix('x').and_then(|n| n.parse::<u8>().ok()) {
+        if n < 32 {
+            return Ok(n);
+        }
+    }
+    match NAMES.iter().position(|other| *other == name) {
+        Some(n) => Ok(n as u8),
+        None => Err(format!("no register is named {name}")),
+    }
+}
+
+/** The integer `s`, in decimal or hexadecimal */
+fn number(s: &str) -> Option<i64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let n = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { -n } else { n })
+}
+
+/** `line` without its comment */
+fn uncommented(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => (),
+        }
+    }
+    line
+}
+
+/** The bytes of the string literal `s`, with its escapes */
+fn string(s: &str) -> Result<Vec<u8>, String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a string, not {s}"))?;
+    let mut bytes = vec![];
+    let mut chars = inner.bytes().peekable();
+    while let Some(byte) = chars.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match chars.next() {
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(b'r') => bytes.push(b'\r'),
+            Some(digit @ b'0'..=b'7') => {
+                // Up to three octal digits
+                let mut n = (digit - b'0') as u32;
+                for _ in 0..2 {
+                    match chars.peek() {
+                        Some(digit @ b'0'..=b'7') => n = 8 * n + (digit - b'0') as u32,
+                        _ => break,
+                    }
+                    chars.next();
+                }
+                bytes.push(n as u8)
+            }
+            Some(b'x') => {
+                let mut n = 0u8;
+                while let Some(d) = chars.peek().and_then(|d| (*d as char).to_digit(16)) {
+                    n = n.wrapping_mul(16).wrapping_add(d as u8);
+                    chars.next();
+                }
+                bytes.push(n)
+            }
+            Some(byte) => bytes.push(byte),
+            None => return Err("unterminated escape".to_string()),
+        }
+    }
+    Ok(bytes)
+}
+
+/** The operands of a statement, split at commas outside of strings */
+fn operands(s: &str) -> Vec<String> {
+    let mut operands = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in s.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => (),
+        }
+        current.push(c)
+    }
+    if !current.trim().is_empty() {
+        operands.push(current.trim().to_string())
+    }
+    operands
+}
+
+/** The offset and base register of the memory operand `s`, like `-4(sp)` */
+fn memory(s: &str) -> Result<(i64, u8), String> {
+    let (offset, base) = s
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .ok_or_else(|| format!("expected an offset and a register, not {s}"))?;
+    let offset = match offset.trim() {
+        "" => 0,
+        offset => number(offset).ok_or_else(|| format!("expected an offset, not {offset}"))?,
+    };
+    Ok((offset, reg(base.trim())?))
+}
+
+/** Whether `n` fits in a signed immediate of `bits` bits */
+fn fits(n: i64, bits: u32) -> bool {
+    -(1 << (bits - 1)) <= n && n < 1 << (bits - 1)
+}
+
+/** `n` as an immediate of 12 bits */
+fn imm12(n: i64) -> Result<i32, String> {
+    match fits(n, 12) {
+        true => Ok(n as i32),
+        false => Err(format!("immediate {n} out of range [-2048, 2047]")),
+    }
+}
+
+/** The upper 20 bits and lower 12 bits adding up to `n`, modulo 2^32 */
+fn split(n: i64) -> (i32, i32) {
+    legalize::split(n as i32)
+}
+
+/** The number of instructions the statement `mnemonic operands` assembles to */
+fn length(mnemonic: &str, operands: &[String]) -> usize {
+    match mnemonic {
+        "la" | "lla" | "call" | "tail" => 2,
+        "li" => match operands.get(1).and_then(|n| number(n)) {
+            Some(n) if !fits(n, 12) && split(n).1 != 0 => 2,
+            _ => 1,
+        },
+        _ => 1,
+    }
+}
+
+fn alu(mnemonic: &str) -> Option<Alu> {
+    Some(match mnemonic {
+        "add" => Alu::Add,
+        "sub" => Alu::Sub,
+        "sll" => Alu::Sll,
+        "slt" => Alu::Slt,
+        "sltu" => Alu::Sltu,
+        "xor" => Alu::Xor,
+        "srl" => Alu::Srl,
+        "sra" => Alu::Sra,
+        "or" => Alu::Or,
+        "and" => Alu::And,
+        "mul" => Alu::Mul,
+        "div" => Alu::Div,
+        "rem" => Alu::Rem,
+        _ => return None,
+    })
+}
+
+fn cond(mnemonic: &str) -> Option<Cond> {
+    Some(match mnemonic {
+        "beq" => Cond::Eq,
+        "bne" => Cond::Ne,
+        "blt" => Cond::Lt,
+        "bge" => Cond::Ge,
+        "bltu" => Cond::Ltu,
+        "bgeu" => Cond::Geu,
+        _ => return None,
+    })
+}
+
+fn size(mnemonic: &str) -> Option<Size> {
+    Some(match mnemonic {
+        "lb" | "sb" => Size::Byte,
+        "lbu" => Size::ByteU,
+        "lh" | "sh" => Size::Half,
+        "lhu" => Size::HalfU,
+        "lw" | "sw" => Size::Word,
+        _ => return None,
+    })
+}
+
+/** Whether `machine` has a compressed encoding, which the assembler picks once `.option rvc` is
+given */
+fn compressible(machine: &Machine) -> bool {
+    use Machine::*;
+    // The registers of the 3 bits of compressed encodings, `s0` to `a5`
+    let small = |reg: &u8| (8..16).contains(reg);
+    let scaled = |offset: i32, limit: i32| offset % 4 == 0 && (0..limit).contains(&offset);
+    match *machine {
+        OpImm(Alu::Add, 0, 0, 0) => true,
+        OpImm(Alu::Add, 2, 2, imm) if imm != 0 && imm % 16 == 0 && fits(imm as i64, 10) => true,
+        OpImm(Alu::Add, rd, 2, imm) if small(&rd) && imm != 0 && scaled(imm, 1024) => true,
+        OpImm(Alu::Add, rd, rs, imm) if rd == rs && imm != 0 => rd != 0 && fits(imm as i64, 6),
+        OpImm(Alu::Add, rd, 0, imm) => rd != 0 && fits(imm as i64, 6),
+        OpImm(Alu::Add, rd, rs, 0) => rd != 0 && rs != 0,
+        OpImm(Alu::Sll, rd, rs, imm) => rd != 0 && rd == rs && imm != 0,
+        OpImm(Alu::Srl | Alu::Sra, rd, rs, imm) => small(&rd) && rd == rs && imm != 0,
+        OpImm(Alu::And, rd, rs, imm) => small(&rd) && rd == rs && fits(imm as i64, 6),
+        Op(Alu::Add, rd, rs1, rs2) => rd != 0 && rd == rs1 && rs2 != 0,
+        Op(Alu::Sub | Alu::Xor | Alu::Or | Alu::And, rd, rs1, rs2) => {
+            small(&rd) && rd == rs1 && small(&rs2)
+        }
+        // Of 6 bits sign extended to 20
+        Lui(rd, imm) => {
+            rd != 0 && rd != 2 && imm != 0 && !(1 << 5..(1 << 20) - (1 << 5)).contains(&imm)
+        }
+        Load(Size::Word, rd, offset, 2) => rd != 0 && scaled(offset, 256),
+        Store(Size::Word, _, offset, 2) => scaled(offset, 256),
+        Load(Size::Word, rd, offset, base) | Store(Size::Word, rd, offset, base) => {
+            small(&rd) && small(&base) && scaled(offset, 128)
+        }
+        Jalr(0 | 1, rs, 0) => rs != 0,
+        _ => false,
+    }
+}
+
+/// The symbols of a program, by address
+#[derive(Default)]
+struct Symbols {
+    addrs: HashMap<String, u32>,
+    /// The symbols of `auipc` of `%pcrel_hi`, by their address
+    pcrel: HashMap<u32, String>,
+}
+
+impl Symbols {
+    fn get(&self, name: &str) -> Result<u32, String> {
+        match self.addrs.get(name) {
+            Some(addr) => Ok(*addr),
+            None => Err(format!("undefined symbol {name}")),
+        }
+    }
+
+    /** The offset of the symbol `name` from `addr`, checked to fit in `bits` bits */
+    fn offset(&self, name: &str, addr: u32, bits: u32) -> Result<i32, String> {
+        let offset = self.get(name)?.wrapping_sub(addr) as i32 as i64;
+        match fits(offset, bits) {
+            true => Ok(offset as i32),
+            false => Err(format!(
+                "{name} is out of range of the jump or branch to it"
+            )),
+        }
+    }
+
+    /** The value of the operand `op` of the statement at `addr` if it's a relocation, like
+    `%hi(symbol)` */
+    fn relocation(&self, op: &str, addr: u32) -> Option<Result<i64, String>> {
+        let (kind, name) = op.strip_prefix('%')?.strip_suffix(')')?.split_once('(')?;
+        let value = |name: &str| -> Result<i64, String> {
+            Ok(match kind {
+                "hi" => split(self.get(name)? as i64).0 as i64,
+                "lo" => split(self.get(name)? as i64).1 as i64,
+                "pcrel_hi" => split(self.get(name)?.wrapping_sub(addr) as i64).0 as i64,
+                "pcrel_lo" => {
+                    let auipc = self.get(name)?;
+                    let symbol = self
+                        .pcrel
+                        .get(&auipc)
+                        .ok_or_else(|| format!("{name} doesn't label an auipc of %pcrel_hi"))?;
+                    split(self.get(symbol)?.wrapping_sub(auipc) as i64).1 as i64
+                }
+                kind => return Err(format!("unknown relocation %{kind}")),
+            })
+        };
+        Some(value(name.trim()))
+    }
+}
+
+/** The machine instructions of the statement at `addr` */
+fn assemble(st: &Statement, symbols: &Symbols) -> Result<Vec<Machine>, String> {
+    use Machine::*;
+    let ops = &st.operands;
+    let arity = |n: usize| match ops.len() == n {
+        true => Ok(()),
+        false => Err(format!("{} takes {n} operands", st.mnemonic)),
+    };
+    let r = |index: usize| reg(&ops[index]);
+    let imm = |index: usize| match symbols.relocation(&ops[index], st.addr) {
+        Some(value) => value,
+        None => {
+            number(&ops[index]).ok_or_else(|| format!("expected an immediate, not {}", ops[index]))
+        }
+    };
+    let addr = st.addr;
+    let mnemonic = st.mnemonic.as_str();
+    if let Some(alu) = alu(mnemonic) {
+        arity(3)?;
+        return Ok(vec![Op(alu, r(0)?, r(1)?, r(2)?)]);
+    }
+    if let Some(alu) = mnemonic.strip_suffix('i').and_then(alu) {
+        arity(3)?;
+        let imm = imm(2)?;
+        let imm = match alu {
+            Alu::Sub | Alu::Mul | Alu::Div | Alu::Rem => return Err(format!("no {mnemonic}")),
+            Alu::Sll | Alu::Srl | Alu::Sra if !(0..32).contains(&imm) => {
+                return Err(format!("shift amount {imm} out of range [0, 31]"))
+            }
+            _ => imm12(imm)?,
+        };
+        return Ok(vec![OpImm(alu, r(0)?, r(1)?, imm)]);
+    }
+    if mnemonic == "sltiu" {
+        arity(3)?;
+        return Ok(vec![OpImm(Alu::Sltu, r(0)?, r(1)?, imm12(imm(2)?)?)]);
+    }
+    if let Some(size) = size(mnemonic) {
+        arity(2)?;
+        let (offset, base) = memory(&ops[1])?;
+        let offset = imm12(offset)?;
+        return Ok(vec![match mnemonic.starts_with('s') {
+            true => Store(size, r(0)?, offset, base),
+            false => Load(size, r(0)?, offset, base),
+        }]);
+    }
+    if let Some(cond) = cond(mnemonic) {
+        arity(3)?;
+        let offset = symbols.offset(&ops[2], addr, 13)?;
+        return Ok(vec![Branch(cond, r(0)?, r(1)?, offset)]);
+    }
+    // Branches comparing with zero, and swapping their operands
+    let zero = |cond: Cond, swap: bool| -> Result<Vec<Machine>, String> {
+        arity(2)?;
+        let offset = symbols.offset(&ops[1], addr, 13)?;
+        Ok(vec![match swap {
+            true => Branch(cond, 0, r(0)?, offset),
+            false => Branch(cond, r(0)?, 0, offset),
+        }])
+    };
+    let swapped = |cond: Cond| -> Result<Vec<Machine>, String> {
+        arity(3)?;
+        let offset = symbols.offset(&ops[2], addr, 13)?;
+        Ok(vec![Branch(cond, r(1)?, r(0)?, offset)])
+    };
+    let far = |rd: u8, scratch: u8, symbol: &str| -> Result<Vec<Machine>, String> {
+        let (hi, lo) = split(symbols.get(symbol)?.wrapping_sub(addr) as i32 as i64);
+        Ok(vec![Auipc(scratch, hi), Jalr(rd, scratch, lo)])
+    };
+    match (mnemonic, ops.len()) {
+        ("beqz", _) => zero(Cond::Eq, false),
+        ("bnez", _) => zero(Cond::Ne, false),
+        ("bltz", _) => zero(Cond::Lt, false),
+        ("bgez", _) => zero(Cond::Ge, false),
+        ("blez", _) => zero(Cond::Ge, true),
+        ("bgtz", _) => zero(Cond::Lt, true),
+        ("bgt", _) => swapped(Cond::Lt),
+        ("ble", _) => swapped(Cond::Ge),
+        ("bgtu", _) => swapped(Cond::Ltu),
+        ("bleu", _) => swapped(Cond::Geu),
+        ("lui" | "auipc", 2) => {
+            let n = imm(1)?;
+            if !(0..1 << 20).contains(&n) {
+                return Err(format!("immediate {n} out of range [0, 1048575]"));
+            }
+            Ok(vec![match mnemonic {
+                "lui" => Lui(r(0)?, n as i32),
+                _ => Auipc(r(0)?, n as i32),
+            }])
+        }
+        ("li", 2) => {
+            let n = imm(1)?;
+            if !fits(n, 32) && !(0..1 << 32).contains(&n) {
+                return Err(format!("immediate {n} out of range of a word"));
+            }
+            if fits(n, 12) {
+                return Ok(vec![OpImm(Alu::Add, r(0)?, 0, n as i32)]);
+            }
+            let (hi, lo) = split(n);
+            match lo {
+                0 => Ok(vec![Lui(r(0)?, hi)]),
+                lo => Ok(vec![Lui(r(0)?, hi), OpImm(Alu::Add, r(0)?, r(0)?, lo)]),
+            }
+        }
+        ("la" | "lla", 2) => {
+            let (hi, lo) = split(symbols.get(&ops[1])?.wrapping_sub(addr) as i32 as i64);
+            Ok(vec![Auipc(r(0)?, hi), OpImm(Alu::Add, r(0)?, r(0)?, lo)])
+        }
+        ("call", 1) => far(1, 1, &ops[0]),
+        ("tail", 1) => far(0, 6, &ops[0]),
+        ("mv", 2) => Ok(vec![OpImm(Alu::Add, r(0)?, r(1)?, 0)]),
+        ("not", 2) => Ok(vec![OpImm(Alu::Xor, r(0)?, r(1)?, -1)]),
+        ("neg", 2) => Ok(vec![Op(Alu::Sub, r(0)?, 0, r(1)?)]),
+        ("seqz", 2) => Ok(vec![OpImm(Alu::Sltu, r(0)?, r(1)?, 1)]),
+        ("snez", 2) => Ok(vec![Op(Alu::Sltu, r(0)?, 0, r(1)?)]),
+        ("nop", 0) => Ok(vec![OpImm(Alu::Add, 0, 0, 0)]),
+        ("j", 1) => Ok(vec![Jal(0, symbols.offset(&ops[0], addr, 21)?)]),
+        ("jal", 1) => Ok(vec![Jal(1, symbols.offset(&ops[0], addr, 21)?)]),
+        ("jal", 2) => Ok(vec![Jal(r(0)?, symbols.offset(&ops[1], addr, 21)?)]),
+        ("jr", 1) => Ok(vec![Jalr(0, r(0)?, 0)]),
+        ("jalr", 1) => Ok(vec![Jalr(1, r(0)?, 0)]),
+        ("jalr", 2) => {
+            let (offset, base) = memory(&ops[1])?;
+            Ok(vec![Jalr(r(0)?, base, imm12(offset)?)])
+        }
+        ("ret", 0) => Ok(vec![Jalr(0, 1, 0)]),
+        ("ecall", 0) => Ok(vec![Ecall]),
+        (mnemonic, n) => Err(format!("unknown instruction {mnemonic} of {n} operands")),
+    }
+}
+
+/** Assembles and links `asm`, its text at [`TEXT`] and its data at the next page after it */
+fn link(asm: &str) -> Result<Image, Fault> {
+    let mut section = Section::Text;
+    let mut statements = vec![];
+    let mut text_len = 0u32;
+    // Whether instructions are compressed, and as it was before each `.option push`
+    let mut rvc = false;
+    let mut pushed = vec![];
+    let mut data = vec![];
+    // The words of data holding the addresses of symbols, by offset
+    let mut fixups = vec![];
+    let mut labels = vec![];
+    for (index, line) in asm.lines().enumerate() {
+        let line_no = index + 1;
+        let error = |message: String| Fault::Assembly(line_no, message);
+        let mut rest = uncommented(line).trim();
+        // Labels, possibly followed by a statement
+        while let Some((label, after)) = rest.split_once(':') {
+            if label.contains(char::is_whitespace) || label.contains('"') || label.is_empty() {
+                break;
+            }
+            let offset = match section {
+                Section::Text => text_len,
+                Section::Data => data.len() as u32,
+            };
+            labels.push((label.to_string(), section, offset, line_no));
+            rest = after.trim()
+        }
+        if rest.is_empty() {
+            continue;
+        }
+        let (mnemonic, args) = match rest.split_once(char::is_whitespace) {
+            Some((mnemonic, args)) => (mnemonic, args.trim()),
+            None => (rest, ""),
+        };
+        let ops = operands(args);
+        match mnemonic {
+            ".text" => section = Section::Text,
+            ".data" | ".bss" | ".rodata" => section = Section::Data,
+            ".section" => {
+                section = match ops.first().map(String::as_str) {
+                    Some(".text") => Section::Text,
+                    _ => Section::Data,
+                }
+            }
+            ".option" => match ops.first().map(String::as_str) {
+                Some("rvc") => rvc = true,
+                Some("norvc") => rvc = false,
+                Some("push") => pushed.push(rvc),
+                Some("pop") => rvc = pushed.pop().unwrap_or(rvc),
+                _ => (),
+            },
+            ".globl" | ".global" | ".type" | ".size" | ".file" | ".local" => (),
+            ".align" | ".p2align" => {
+                let power = ops
+                    .first()
+                    .and_then(|n| number(n))
+                    .filter(|n| (0..16).contains(n));
+                let align = 1u32 << power.ok_or_else(|| error("expected an alignment".into()))?;
+                match section {
+                    Section::Text => {
+                        while !text_len.is_multiple_of(align) {
+                            // Compressed, after compressed instructions
+                            let size = if text_len.is_multiple_of(4) { 4 } else { 2 };
+                            statements.push(Statement {
+                                line: line_no,
+                                addr: TEXT + text_len,
+                                mnemonic: "nop".to_string(),
+                                operands: vec![],
+                                sizes: vec![size],
+                            });
+                            text_len += size
+                        }
+                    }
+                    Section::Data => {
+                        while !(data.len() as u32).is_multiple_of(align) {
+                            data.push(0)
+                        }
+                    }
+                }
+            }
+            _ if mnemonic.starts_with('.') && section == Section::Text => {
+                return Err(error(format!("{mnemonic} in text")))
+            }
+            ".word" | ".half" | ".byte" => {
+                let width = match mnemonic {
+                    ".word" => 4,
+                    ".half" => 2,
+                    _ => 1,
+                };
+                for op in &ops {
+                    match number(op) {
+                        Some(n) => data.extend(&(n as u32).to_le_bytes()[..width]),
+                        None if width == 4 => {
+                            fixups.push((data.len(), op.clone(), line_no));
+                            data.extend([0; 4])
+                        }
+                        None => return Err(error(format!("expected a number, not {op}"))),
+                    }
+                }
+            }
+            ".ascii" | ".asciz" | ".string" => {
+                for op in &ops {
+                    data.extend(string(op).map_err(error)?);
+                    if mnemonic != ".ascii" {
+                        data.push(0)
+                    }
+                }
+            }
+            ".zero" | ".space" => {
+                let n = ops.first().and_then(|n| number(n)).filter(|n| *n >= 0);
+                let n = n.ok_or_else(|| error("expected a size".into()))?;
+                data.extend(std::iter::repeat_n(0, n as usize))
+            }
+            _ if mnemonic.starts_with('.') => {
+                return Err(error(format!("unknown directive {mnemonic}")))
+            }
+            _ if section == Section::Data => {
+                return Err(error(format!("{mnemonic} in data")));
+            }
+            _ => {
+                let len = length(mnemonic, &ops);
+                let mut statement = Statement {
+                    line: line_no,
+                    addr: TEXT + text_len,
+                    mnemonic: mnemonic.to_string(),
+                    operands: ops,
+                    sizes: vec![4; len],
+                };
+                // Those of symbols stay uncompressed, failing to assemble without them
+                if let (true, Ok(machine)) = (rvc, assemble(&statement, &Symbols::default())) {
+                    statement.sizes = machine
+                        .iter()
+                        .map(|machine| if compressible(machine) { 2 } else { 4 })
+                        .collect()
+                }
+                text_len += statement.sizes.iter().sum::<u32>();
+                statements.push(statement)
+            }
+        }
+    }
+    let base = (TEXT + text_len + 0xfff) & !0xfff;
+    let mut symbols = HashMap::new();
+    for (label, section, offset, line) in labels {
+        let addr = match section {
+            Section::Text => TEXT + offset,
+            Section::Data => base + offset,
+        };
+        if symbols.insert(label.clone(), addr).is_some() {
+            return Err(Fault::Assembly(line, format!("{label} is defined twice")));
+        }
+    }
+    symbols.insert("__global_pointer$".to_string(), base + 0x800);
+    let pcrel = statements
+        .iter()
+        .filter(|st| st.mnemonic == "auipc")
+        .filter_map(|st| {
+            let op = st.operands.get(1)?.strip_prefix("%pcrel_hi(")?;
+            Some((st.addr, op.strip_suffix(')')?.trim().to_string()))
+        })
+        .collect();
+    let symbols = Symbols {
+        addrs: symbols,
+        pcrel,
+    };
+    for (offset, symbol, line) in fixups {
+        let addr = symbols.get(&symbol).map_err(|e| Fault::Assembly(line, e))?;
+        data[offset..offset + 4].copy_from_slice(&addr.to_le_bytes())
+    }
+    let mut text = vec![None; text_len as usize / 2];
+    for statement in &statements {
+        let machine = assemble(statement, &symbols);
+        let machine = machine.map_err(|e| Fault::Assembly(statement.line, e))?;
+        assert_eq!(
+            machine.len(),
+            statement.sizes.len(),
+            "{}",
+            statement.mnemonic
+        );
+        let mut addr = statement.addr;
+        for (machine, size) in zip(machine, &statement.sizes) {
+            text[(addr - TEXT) as usize / 2] = Some((machine, *size));
+            addr += size
+        }
+    }
+    let entry = symbols.get("_start").map_err(|e| Fault::Assembly(0, e))?;
+    Ok(Image {
+        text,
+        data,
+        base,
+        entry,
+    })
+}
+
+/// The state of the machine running a program
+struct Machine32<'a> {
+    regs: [u32; 32],
+    pc: u32,
+    image: &'a Image,
+    /// The data then the heap, up to the break
+    heap: Vec<u8>,
+    stack: Vec<u8>,
+    input: &'a [u8],
+    output: Vec<u8>,
+    errors: Vec<u8>,
+}
+
+impl<'a> Machine32<'a> {
+    /** The bytes `len` bytes long at `addr`, unless out of memory or misaligned */
+    fn bytes(&mut self, addr: u32, len: u32) -> Result<&mut [u8], Fault> {
+        if !addr.is_multiple_of(len.min(4)) {
+            return Err(Fault::Memory(addr));
+        }
+        let base = self.image.base;
+        let (region, offset) = if (STACK - STACK_SIZE..STACK).contains(&addr) {
+            (&mut self.stack, addr - (STACK - STACK_SIZE))
+        } else if addr >= base && addr - base < self.heap.len() as u32 {
+            (&mut self.heap, addr - base)
+        } else {
+            return Err(Fault::Memory(addr));
+        };
+        let (start, end) = (offset as usize, offset as usize + len as usize);
+        match region.get_mut(start..end) {
+            Some(bytes) => Ok(bytes),
+            None => Err(Fault::Memory(addr)),
+        }
+    }
+
+    fn load(&mut self, size: Size, addr: u32) -> Result<u32, Fault> {
+        Ok(match size {
+            Size::Byte => self.bytes(addr, 1)?[0] as i8 as i32 as u32,
+            Size::ByteU => self.bytes(addr, 1)?[0] as u32,
+            Size::Half => {
+                let bytes = self.bytes(addr, 2)?;
+                i16::from_le_bytes([bytes[0], bytes[1]]) as i32 as u32
+            }
+            Size::HalfU => {
+                let bytes = self.bytes(addr, 2)?;
+                u16::from_le_bytes([bytes[0], bytes[1]]) as u32
+            }
+            Size::Word => {
+                let bytes = self.bytes(addr, 4)?;
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            }
+        })
+    }
+
+    fn store(&mut self, size: Size, addr: u32, value: u32) -> Result<(), Fault> {
+        let len = match size {
+            Size::Byte | Size::ByteU => 1,
+            Size::Half | Size::HalfU => 2,
+            Size::Word => 4,
+        };
+        let bytes = self.bytes(addr, len)?;
+        bytes.copy_from_slice(&value.to_le_bytes()[..len as usize]);
+        Ok(())
+    }
+
+    /** Makes the system call numbered in `a7`. Returns: The code the program exits with, if it
+    does */
+    fn syscall(&mut self) -> Result<Option<i32>, Fault> {
+        let [a0, a1, a2] = [self.regs[10], self.regs[11], self.regs[12]];
+        let result = match self.regs[17] {
+            63 if a0 == 0 => {
+                let len = (a2 as usize).min(self.input.len());
+                let (read, rest) = self.input.split_at(len);
+                self.input = rest;
+                self.bytes(a1, 1)?;
+                for (offset, byte) in read.iter().enumerate() {
+                    self.store(Size::ByteU, a1 + offset as u32, *byte as u32)?
+                }
+                len as u32
+            }
+            64 if a0 == 1 || a0 == 2 => {
+                let mut written = vec![];
+                for offset in 0..a2 {
+                    written.push(self.load(Size::ByteU, a1 + offset)? as u8)
+                }
+                match a0 {
+                    1 => self.output.extend(written),
+                    _ => self.errors.extend(written),
+                }
+                a2
+            }
+            93 | 94 => return Ok(Some(a0 as i32)),
+            214 => {
+                let base = self.image.base;
+                let start = base + self.image.data.len() as u32;
+                let brk = base + self.heap.len() as u32;
+                if a0 >= start && a0 - start <= HEAP_SIZE {
+                    self.heap.resize((a0 - base) as usize, 0);
+                    a0
+                } else {
+                    brk
+                }
+            }
+            _ => return Err(Fault::Illegal(self.pc)),
+        };
+        self.regs[10] = result;
+        Ok(None)
+    }
+
+    /** Runs one instruction. Returns: The code the program exits with, if it does */
+    fn step(&mut self) -> Result<Option<i32>, Fault> {
+        let pc = self.pc;
+        let index = pc.wrapping_sub(TEXT) / 2;
+        let (inst, size) = match self.image.text.get(index as usize) {
+            Some(Some(inst)) if pc.is_multiple_of(2) && pc >= TEXT => *inst,
+            _ => return Err(Fault::Memory(pc)),
+        };
+        let mut next = pc.wrapping_add(size);
+        let regs = self.regs;
+        let write = |rd: u8, value: u32, regs: &mut [u32; 32]| {
+            if rd != 0 {
+                regs[rd as usize] = value
+            }
+        };
+        match inst {
+            Machine::Op(alu, rd, rs1, rs2) => {
+                let value = compute(alu, regs[rs1 as usize], regs[rs2 as usize]);
+                write(rd, value, &mut self.regs)
+            }
+            Machine::OpImm(alu, rd, rs, imm) => {
+                let value = compute(alu, regs[rs as usize], imm as u32);
+                write(rd, value, &mut self.regs)
+            }
+            Machine::Lui(rd, imm) => write(rd, (imm as u32) << 12, &mut self.regs),
+            Machine::Auipc(rd, imm) => {
+                write(rd, pc.wrapping_add((imm as u32) << 12), &mut self.regs)
+            }
+            Machine::Load(size, rd, offset, base) => {
+                let value = self.load(size, regs[base as usize].wrapping_add(offset as u32))?;
+                write(rd, value, &mut self.regs)
+            }
+            Machine::Store(size, src, offset, base) => {
+                let addr = regs[base as usize].wrapping_add(offset as u32);
+                self.store(size, addr, regs[src as usize])?
+            }
+            Machine::Branch(cond, rs1, rs2, offset) => {
+                let (a, b) = (regs[rs1 as usize], regs[rs2 as usize]);
+                let taken = match cond {
+                    Cond::Eq => a == b,
+                    Cond::Ne => a != b,
+                    Cond::Lt => (a as i32) < b as i32,
+                    Cond::Ge => a as i32 >= b as i32,
+                    Cond::Ltu => a < b,
+                    Cond::Geu => a >= b,
+                };
+                if taken {
+                    next = pc.wrapping_add(offset as u32)
+                }
+            }
+            Machine::Jal(rd, offset) => {
+                write(rd, next, &mut self.regs);
+                next = pc.wrapping_add(offset as u32)
+            }
+            Machine::Jalr(rd, rs, offset) => {
+                write(rd, next, &mut self.regs);
+                next = regs[rs as usize].wrapping_add(offset as u32) & !1
+            }
+            Machine::Ecall => {
+                if let Some(code) = self.syscall()? {
+                    return Ok(Some(code));
+                }
+            }
+        }
+        self.pc = next;
+        Ok(None)
+    }
+}
+
+fn compute(alu: Alu, a: u32, b: u32) -> u32 {
+    match alu {
+        Alu::Add => a.wrapping_add(b),
+        Alu::Sub => a.wrapping_sub(b),
+        Alu::Sll => a << (b & 31),
+        Alu::Slt => ((a as i32) < b as i32) as u32,
+        Alu::Sltu => (a < b) as u32,
+        Alu::Xor => a ^ b,
+        Alu::Srl => a >> (b & 31),
+        Alu::Sra => ((a as i32) >> (b & 31)) as u32,
+        Alu::Or => a | b,
+        Alu::And => a & b,
+        Alu::Mul => a.wrapping_mul(b),
+        // Dividing by zero gives all ones and leaves the remainder the dividend
+        Alu::Div if b == 0 => u32::MAX,
+        Alu::Rem if b == 0 => a,
+        Alu::Div => (a as i32).wrapping_div(b as i32) as u32,
+        Alu::Rem => (a as i32).wrapping_rem(b as i32) as u32,
+    }
+}
+
+/** The size of the text of `asm` once assembled, in bytes */
+pub fn text_size(asm: &str) -> Result<u32, Fault> {
+    Ok(2 * link(asm)?.text.len() as u32)
+}
+
+/** Assembles `asm` and runs it on `input`, for at most `fuel` instructions. Returns: How it
+exited, or why it couldn't */
+pub fn run(asm: &str, input: &str, fuel: usize) -> Result<Exit, Fault> {
+    let image = link(asm)?;
+    let mut machine = Machine32 {
+        regs: [0; 32],
+        pc: image.entry,
+        image: &image,
+        heap: image.data.clone(),
+        stack: vec![0; STACK_SIZE as usize],
+        input: input.as_bytes(),
+        output: vec![],
+        errors: vec![],
+    };
+    // The break starts at the next page after the data
+    let brk = (image.base + image.data.len() as u32 + 0xfff) & !0xfff;
+    machine.heap.resize((brk - image.base) as usize, 0);
+    machine.regs[2] = STACK;
+    for steps in 0..fuel {
+        if let Some(code) = machine.step()? {
+            return Ok(Exit {
+                code,
+                output: String::from_utf8_lossy(&machine.output).into_owned(),
+                errors: String::from_utf8_lossy(&machine.errors).into_owned(),
+                steps: steps + 1,
+            });
+        }
+    }
+    Err(Fault::Exhausted)
+}
@@ -1,6 +1,6 @@
 use crate::ast::ast::{self, Constant};
 
-type Id = String;
+pub type Id = String;
 
 /// types
 #[derive(Debug, PartialEq, Clone)]
@@ -21,6 +21,7 @@ pub enum Type {
 }
 
 /// Bindings
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum Bind {
     ValueBind(Id, Value),
@@ -1 +1,2 @@
-mod cps;
+#[allow(clippy::module_inception)]
+pub mod cps;
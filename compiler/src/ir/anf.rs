@@ -0,0 +1,1334 @@
+/*! A-normal form, the intermediate representation type checked programs are lowered to before
+compilation. The operands of every operation are atoms, variables or constants, and every
+intermediate result is named by `let`. Types are erased: a type abstraction `Λ A. e` becomes a
+function of a unit argument, so that `e` is evaluated when the abstraction is applied to a type,
+like the interpreter does. Constructors become `Data` when applied to all of their fields, and
+functions building it otherwise. Control flow is explicit. The branches of an `if` or `case` not in
+tail position jump to a join point `join k(x) = e in ...` with their values, and patterns become
+tests of constructors and literals on projections, jumping to the next arm when they fail. Binders
+are named apart, keeping the names of the source with a suffix `$n`, so every variable is bound
+once in the program. */
+
+use crate::ast::ast::{
+    self, grow, Binary, Constant, Pattern, RawExpr, RawPattern, RawType, Width, EXN,
+};
+use crate::ast::error::TypeError;
+use crate::ast::lower;
+use crate::ast::semant::BUILTINS;
+
+use std::fmt::{self, Display};
+use std::iter::zip;
+
+use im::{HashMap, HashSet};
+
+/// Variables, named apart
+pub type Var = String;
+
+/// Operands of operations
+#[derive(Debug, PartialEq, Clone)]
+pub enum Atom {
+    Var(Var),
+    Con(Constant),
+}
+
+/// Operations on atoms, their results named by `let`
+#[derive(Debug, PartialEq, Clone)]
+pub enum Comp {
+    /// The atom itself
+    Atom(Atom),
+    Binop(Atom, Binary, Atom),
+    /// Applying a function to its arguments, like `f(x)`
+    App(Atom, Vec<Atom>),
+    /// Tuples, n >= 2
+    Tuple(Vec<Atom>),
+    /// Tuple projection like `t.0`, counting from 0
+    Nth(Atom, usize),
+    /// Records like `{x = a, y = b}`
+    Record(Vec<(String, Atom)>),
+    /// Field projection like `r.x`
+    Proj(Atom, String),
+    /// A constructor applied to all of its fields, like `Cons(x, xs)`
+    Data(String, Vec<Atom>),
+    /// Field of the value of a constructor like `xs#1`, counting from 0
+    Field(Atom, usize),
+    /// The closure of the function, with the values of the variables it captures, like
+    /// `closure f(x, y)`. Only after closure conversion
+    Closure(Var, Vec<Atom>),
+    /// Value captured by a closure like `clo@1`, counting from 0
+    Env(Atom, usize),
+    /// Primitive operations
+    Prim(Prim, Vec<Atom>),
+    /// The value of `body`, or of `handler` with the exception bound to `exn` if `body` raises one
+    Handle {
+        body: Box<Expr>,
+        exn: Var,
+        handler: Box<Expr>,
+    },
+}
+
+/// Primitive operations, taking the operands of their source counterparts in order
+#[derive(Debug, PartialEq, Clone)]
+pub enum Prim {
+    /// Arithmetic and comparisons on integers of a width other than that of `Int`, like
+    /// `add8(x, y)`, wrapping around to it
+    Arith(Binary, Width),
+    /// Conversion from integers of the first width to the second, like `int8(x)`, or `intof8(x)`
+    /// from another width than that of `Int`
+    Convert(Width, Width),
+    Ref,
+    Deref,
+    Assign,
+    Array,
+    Sub,
+    Update,
+    AssertEq,
+    /// Starting a task applying the function to `()`
+    Spawn,
+    Join,
+}
+
+/// Expressions, evaluating to the atom they return
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    /// `let x = c in body`
+    Let {
+        var: Var,
+        comp: Comp,
+        body: Box<Expr>,
+    },
+    /// Mutually recursive functions, like `fix f(x) = e1 and g(y) = e2 in body`
+    Fix {
+        funcs: Vec<Func>,
+        body: Box<Expr>,
+    },
+    /// Join points `join k(x) = join in body`, jumped to from tail positions of `body` outside of
+    /// its functions and handlers. Evaluating `join` after the jump, with the arguments bound to
+    /// `params`
+    Join {
+        name: Var,
+        params: Vec<Var>,
+        join: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// Jumping to a join point like `jump k(x)`
+    Jump {
+        name: Var,
+        args: Vec<Atom>,
+    },
+    If {
+        cond: Atom,
+        branch_t: Box<Expr>,
+        branch_f: Box<Expr>,
+    },
+    /// Branching on the constructor of the value of `scrut`, taking `default` for the constructors
+    /// without an arm
+    Case {
+        scrut: Atom,
+        arms: Vec<(String, Expr)>,
+        default: Option<Box<Expr>>,
+    },
+    Raise {
+        exn: Atom,
+    },
+    /// Returning the atom
+    Ret {
+        val: Atom,
+    },
+}
+
+/// Functions of a `fix`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Func {
+    pub name: Var,
+    pub params: Vec<Var>,
+    pub body: Expr,
+}
+
+/// Top level declarations, evaluating their body to their value
+#[derive(Debug, PartialEq, Clone)]
+pub struct Decl {
+    pub id: String,
+    pub body: Expr,
+    /// The attributes of the declaration in the source, like `inline`
+    pub attrs: Vec<String>,
+}
+
+/// Algebraic data types, with the names and arities of their constructors in order
+#[derive(Debug, PartialEq, Clone)]
+pub struct DataType {
+    pub id: String,
+    pub ctors: Vec<(String, usize)>,
+}
+
+/// The entire program
+#[derive(Debug, PartialEq, Clone)]
+pub struct Prog {
+    /// Data types in order of definition. The constructors of `Exn` are the exceptions
+    pub datatypes: Vec<DataType>,
+    /// Functions lifted to the top level, global like the declarations
+    pub funcs: Vec<Func>,
+    /// Declarations in order, the prelude's and derived ones included
+    pub decls: Vec<Decl>,
+}
+
+impl Prog {
+    /** The declaration `id` */
+    pub fn decl(&self, id: &str) -> Option<&Decl> {
+        self.decls.iter().find(|decl| decl.id == id)
+    }
+
+    /** The tag of the constructor `ctor`, its position among those of its data type, and its
+    arity */
+    pub fn ctor(&self, ctor: &str) -> Option<(usize, usize)> {
+        self.datatypes.iter().find_map(|data| {
+            let tag = data.ctors.iter().position(|(c, _)| c == ctor)?;
+            Some((tag, data.ctors[tag].1))
+        })
+    }
+}
+
+/// Supply of variables named apart from those of a program
+#[derive(Debug, Default, Clone)]
+pub struct Fresh(usize);
+
+impl Fresh {
+    /** The supply of variables not bound in `prog` */
+    pub fn after(prog: &Prog) -> Fresh {
+        let funcs = prog.funcs.iter().flat_map(|func| {
+            let params = func.params.iter().map(|p| p.as_str());
+            std::iter::once(func.name.as_str())
+                .chain(params)
+                .chain(func.body.binders())
+        });
+        let decls = prog
+            .decls
+            .iter()
+            .flat_map(|decl| std::iter::once(decl.id.as_str()).chain(decl.body.binders()));
+        Fresh::above(funcs.chain(decls))
+    }
+
+    /** The supply of variables other than `vars` */
+    pub fn above<'a>(vars: impl Iterator<Item = &'a str>) -> Fresh {
+        let suffix = |var: &str| {
+            var.rsplit_once('$')
+                .and_then(|(_, n)| n.parse::<usize>().ok())
+        };
+        Fresh(vars.filter_map(suffix).map(|n| n + 1).max().unwrap_or(0))
+    }
+
+    /** A variable not bound yet, named after `name` */
+    pub fn var(&mut self, name: &str) -> Var {
+        let base = name.split('$').next().unwrap_or(name);
+        self.0 += 1;
+        format!("{}${}", base, self.0 - 1)
+    }
+}
+
+impl Prim {
+    /** The number of operands */
+    pub fn arity(&self) -> usize {
+        match self {
+            Prim::Convert(..) | Prim::Ref | Prim::Deref | Prim::Spawn | Prim::Join => 1,
+            Prim::Arith(..) | Prim::Assign | Prim::Array | Prim::Sub | Prim::AssertEq => 2,
+            Prim::Update => 3,
+        }
+    }
+}
+
+impl Atom {
+    /** The variable, if the atom is one */
+    pub fn var(&self) -> Option<&Var> {
+        match self {
+            Atom::Var(var) => Some(var),
+            Atom::Con(_) => None,
+        }
+    }
+}
+
+impl Comp {
+    /** The atoms operated on, and the functions of closures */
+    pub fn atoms(&self) -> Vec<&Atom> {
+        match self {
+            Comp::Atom(a) | Comp::Nth(a, _) | Comp::Proj(a, _) | Comp::Field(a, _) => vec![a],
+            Comp::Env(a, _) => vec![a],
+            Comp::Binop(lhs, _, rhs) => vec![lhs, rhs],
+            Comp::App(fun, args) => std::iter::once(fun).chain(args).collect(),
+            Comp::Tuple(args) | Comp::Data(_, args) | Comp::Prim(_, args) => args.iter().collect(),
+            Comp::Closure(_, env) => env.iter().collect(),
+            Comp::Record(fields) => fields.iter().map(|(_, a)| a).collect(),
+            Comp::Handle { .. } => vec![],
+        }
+    }
+
+    /** `self` with its atoms mapped by `f`, but those of the expressions of handlers */
+    pub fn map_atoms(&self, f: impl Fn(&Atom) -> Atom) -> Comp {
+        let map = |atoms: &[Atom]| atoms.iter().map(&f).collect();
+        match self {
+            Comp::Atom(a) => Comp::Atom(f(a)),
+            Comp::Binop(lhs, op, rhs) => Comp::Binop(f(lhs), op.clone(), f(rhs)),
+            Comp::App(fun, args) => Comp::App(f(fun), map(args)),
+            Comp::Tuple(entries) => Comp::Tuple(map(entries)),
+            Comp::Nth(tuple, i) => Comp::Nth(f(tuple), *i),
+            Comp::Record(fields) => {
+                let fields = fields.iter().map(|(l, a)| (l.clone(), f(a)));
+                Comp::Record(fields.collect())
+            }
+            Comp::Proj(record, label) => Comp::Proj(f(record), label.clone()),
+            Comp::Data(ctor, fields) => Comp::Data(ctor.clone(), map(fields)),
+            Comp::Field(data, i) => Comp::Field(f(data), *i),
+            Comp::Closure(func, env) => Comp::Closure(func.clone(), map(env)),
+            Comp::Env(clo, i) => Comp::Env(f(clo), *i),
+            Comp::Prim(prim, args) => Comp::Prim(prim.clone(), map(args)),
+            Comp::Handle { .. } => self.clone(),
+        }
+    }
+
+    /** The variables free in `self` */
+    pub fn free_vars(&self) -> HashSet<Var> {
+        let mut vars: HashSet<Var> = self
+            .atoms()
+            .into_iter()
+            .filter_map(Atom::var)
+            .cloned()
+            .collect();
+        match self {
+            Comp::Closure(func, _) => {
+                vars.insert(func.clone());
+            }
+            Comp::Handle { body, exn, handler } => {
+                vars.extend(body.free_vars());
+                vars.extend(handler.free_vars().without(exn))
+            }
+            _ => (),
+        }
+        vars
+    }
+}
+
+impl Clone for Expr {
+    /** Clones `self` in [`grow`], since long chains of `let`s are cloned recursively */
+    fn clone(&self) -> Self {
+        grow(|| match self {
+            Expr::Let { var, comp, body } => Expr::Let {
+                var: var.clone(),
+                comp: comp.clone(),
+                body: body.clone(),
+            },
+            Expr::Fix { funcs, body } => Expr::Fix {
+                funcs: funcs.clone(),
+                body: body.clone(),
+            },
+            Expr::Join {
+                name,
+                params,
+                join,
+                body,
+            } => Expr::Join {
+                name: name.clone(),
+                params: params.clone(),
+                join: join.clone(),
+                body: body.clone(),
+            },
+            Expr::Jump { name, args } => Expr::Jump {
+                name: name.clone(),
+                args: args.clone(),
+            },
+            Expr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => Expr::If {
+                cond: cond.clone(),
+                branch_t: branch_t.clone(),
+                branch_f: branch_f.clone(),
+            },
+            Expr::Case {
+                scrut,
+                arms,
+                default,
+            } => Expr::Case {
+                scrut: scrut.clone(),
+                arms: arms.clone(),
+                default: default.clone(),
+            },
+            Expr::Raise { exn } => Expr::Raise { exn: exn.clone() },
+            Expr::Ret { val } => Expr::Ret { val: val.clone() },
+        })
+    }
+}
+
+impl Expr {
+    /** The variables free in `self`. Join points aren't variables */
+    pub fn free_vars(&self) -> HashSet<Var> {
+        grow(|| {
+            let atoms = |atoms: &[&Atom]| -> HashSet<Var> {
+                atoms.iter().filter_map(|a| a.var()).cloned().collect()
+            };
+            match self {
+                Expr::Let { var, comp, body } => {
+                    comp.free_vars().union(body.free_vars().without(var))
+                }
+                Expr::Fix { funcs, body } => {
+                    let mut vars = body.free_vars();
+                    for func in funcs {
+                        let params = func.params.iter().cloned().collect();
+                        vars.extend(func.body.free_vars().relative_complement(params))
+                    }
+                    funcs
+                        .iter()
+                        .fold(vars, |vars, func| vars.without(&func.name))
+                }
+                Expr::Join {
+                    params, join, body, ..
+                } => {
+                    let params = params.iter().cloned().collect();
+                    body.free_vars()
+                        .union(join.free_vars().relative_complement(params))
+                }
+                Expr::Jump { args, .. } => atoms(&args.iter().collect::<Vec<_>>()),
+                Expr::If {
+                    cond,
+                    branch_t,
+                    branch_f,
+                } => atoms(&[cond])
+                    .union(branch_t.free_vars())
+                    .union(branch_f.free_vars()),
+                Expr::Case {
+                    scrut,
+                    arms,
+                    default,
+                } => {
+                    let bodies = arms.iter().map(|(_, e)| e).chain(default.as_deref());
+                    bodies.fold(atoms(&[scrut]), |vars, e| vars.union(e.free_vars()))
+                }
+                Expr::Raise { exn: val } | Expr::Ret { val } => atoms(&[val]),
+            }
+        })
+    }
+
+    /** The variables bound in `self`, join points included, in order */
+    pub fn binders(&self) -> Vec<&str> {
+        let mut vars = vec![];
+        self.visit_binders(&mut |var| vars.push(var));
+        vars
+    }
+
+    fn visit_binders<'a>(&'a self, visit: &mut impl FnMut(&'a str)) {
+        grow(|| match self {
+            Expr::Let { var, comp, body } => {
+                if let Comp::Handle { body, exn, handler } = comp {
+                    body.visit_binders(visit);
+                    visit(exn);
+                    handler.visit_binders(visit)
+                }
+                visit(var);
+                body.visit_binders(visit)
+            }
+            Expr::Fix { funcs, body } => {
+                for func in funcs {
+                    visit(&func.name);
+                    func.params.iter().for_each(|p| visit(p));
+                    func.body.visit_binders(visit)
+                }
+                body.visit_binders(visit)
+            }
+            Expr::Join {
+                name,
+                params,
+                join,
+                body,
+            } => {
+                visit(name);
+                params.iter().for_each(|p| visit(p));
+                join.visit_binders(visit);
+                body.visit_binders(visit)
+            }
+            Expr::If {
+                branch_t, branch_f, ..
+            } => {
+                branch_t.visit_binders(visit);
+                branch_f.visit_binders(visit)
+            }
+            Expr::Case { arms, default, .. } => {
+                for body in arms.iter().map(|(_, e)| e).chain(default.as_deref()) {
+                    body.visit_binders(visit)
+                }
+            }
+            Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => (),
+        })
+    }
+}
+
+/** Type checks `prog` and lowers it to A-normal form, after the passes run before evaluation.
+Returns: The program, or `TypeError` if `prog` is ill-typed */
+pub fn lower_prog(prog: &ast::Prog) -> Result<Prog, TypeError> {
+    Ok(lower_checked(&lower::lower_prog(prog)?))
+}
+
+/** Lowers `prog` to A-normal form, type checked and lowered by [`lower::lower_prog`] */
+pub fn lower_checked(prog: &ast::Prog) -> Prog {
+    let mut lowering = Lowering::default();
+    let mut datatypes = vec![];
+    for data in prog.datatypes.iter().filter(|data| !data.newtype) {
+        let ctors = data
+            .ctors
+            .iter()
+            .map(|(c, fields)| (c.name.clone(), fields.len()));
+        let data_type = DataType {
+            id: data.id.clone(),
+            ctors: ctors.collect(),
+        };
+        for (ctor, arity) in &data_type.ctors {
+            lowering
+                .ctors
+                .insert(ctor.clone(), (data.params.len(), *arity));
+        }
+        datatypes.push(data_type)
+    }
+    let mut exns = DataType {
+        id: EXN.to_string(),
+        ctors: vec![],
+    };
+    for exn in &prog.exceptions {
+        let ctor = (exn.ctor.name.clone(), exn.fields.len());
+        lowering.ctors.insert(ctor.0.clone(), (0, ctor.1));
+        exns.ctors.push(ctor)
+    }
+    datatypes.push(exns);
+    let mut decls = vec![];
+    for id in &prog.order {
+        let decl = &prog.declarations[id];
+        // Functions are named after their declaration
+        let mut binds = vec![];
+        let val = lowering.named(&decl.body, id, &Env::default(), &mut binds);
+        let body = wrap(binds, Expr::Ret { val });
+        decls.push(Decl {
+            id: id.clone(),
+            body,
+            attrs: decl.attrs.iter().map(|attr| attr.name.clone()).collect(),
+        });
+        lowering.globals.insert(id.clone());
+        if let Some(members) = members(&decl.sig) {
+            lowering.structures.insert(id.clone(), members);
+        }
+    }
+    Prog {
+        datatypes,
+        funcs: vec![],
+        decls,
+    }
+}
+
+/** Lowers the closed `expr`, type checked and lowered, to A-normal form. Only the builtins are
+free in it */
+pub fn lower_expr(expr: &ast::Expr) -> Expr {
+    Lowering::default().tail(expr, &Env::default(), &Tail::Ret)
+}
+
+/** The fields of the record type `typ`, possibly packed like the types of structures */
+fn members(typ: &RawType) -> Option<Vec<String>> {
+    match typ {
+        RawType::Record(fields) => Some(fields.iter().map(|(l, _)| l.name.clone()).collect()),
+        RawType::Exists(_, typ) => members(&typ.typ),
+        _ => None,
+    }
+}
+
+struct Lowering {
+    /// Numbers of type parameters and of fields of the constructors
+    ctors: HashMap<String, (usize, usize)>,
+    /// Members of the structures among the declarations lowered
+    structures: HashMap<String, Vec<String>>,
+    /// Names of the builtins and of the declarations lowered
+    globals: HashSet<String>,
+    names: Fresh,
+}
+
+impl Default for Lowering {
+    fn default() -> Self {
+        Lowering {
+            ctors: HashMap::new(),
+            structures: HashMap::new(),
+            globals: BUILTINS.iter().map(|(id, _)| id.to_string()).collect(),
+            names: Fresh::default(),
+        }
+    }
+}
+
+/// Variables in scope
+#[derive(Clone, Default)]
+struct Env {
+    /// Atoms standing for the variables of the source
+    vars: HashMap<String, Atom>,
+    /// Structures opened whose members aren't known, innermost last. Variables bound neither in
+    /// `vars` nor globally are members of the innermost
+    opened: Vec<Atom>,
+}
+
+/// What is done with the value of an expression in tail position
+enum Tail {
+    /// Returning it from the function, handler or declaration
+    Ret,
+    /// Jumping with it to the join point
+    Jump(Var),
+}
+
+impl Tail {
+    fn apply(&self, val: Atom) -> Expr {
+        match self {
+            Tail::Ret => Expr::Ret { val },
+            Tail::Jump(name) => Expr::Jump {
+                name: name.clone(),
+                args: vec![val],
+            },
+        }
+    }
+}
+
+/// Bindings evaluated before the atom of an expression, in order
+enum Bind {
+    Let(Var, Comp),
+    Fix(Vec<Func>),
+    /// The join point `k(x)` jumped to by the branches of the expression, whose body is what
+    /// follows
+    Join(Var, Var, Expr),
+    /// Raising the exception, what follows never evaluated
+    Raise(Atom),
+}
+
+/** The expression evaluating `binds` then `body` */
+fn wrap(binds: Vec<Bind>, body: Expr) -> Expr {
+    binds.into_iter().rev().fold(body, |body, bind| match bind {
+        Bind::Let(var, comp) => Expr::Let {
+            var,
+            comp,
+            body: Box::new(body),
+        },
+        Bind::Fix(funcs) => Expr::Fix {
+            funcs,
+            body: Box::new(body),
+        },
+        Bind::Join(name, param, branches) => Expr::Join {
+            name,
+            params: vec![param],
+            join: Box::new(body),
+            body: Box::new(branches),
+        },
+        Bind::Raise(exn) => Expr::Raise { exn },
+    })
+}
+
+/// Steps of matching a pattern
+enum Step {
+    /// Naming a part of the value
+    Let(Var, Comp),
+    /// Testing the constructor of the atom
+    Ctor(Atom, String),
+    /// Testing the atom is equal to the literal, naming the outcome
+    Literal(Atom, Constant, Var),
+}
+
+/** The expression taking the `steps` of matching a pattern, then `success`. Tests failing evaluate
+`fail`, and are left out if there is no `fail`, the pattern then known to match */
+fn matching(steps: Vec<Step>, success: Expr, fail: Option<&Expr>) -> Expr {
+    steps
+        .into_iter()
+        .rev()
+        .fold(success, |body, step| match (step, fail) {
+            (Step::Let(var, comp), _) => Expr::Let {
+                var,
+                comp,
+                body: Box::new(body),
+            },
+            (_, None) => body,
+            (Step::Ctor(scrut, ctor), Some(fail)) => Expr::Case {
+                scrut,
+                arms: vec![(ctor, body)],
+                default: Some(Box::new(fail.clone())),
+            },
+            (Step::Literal(atom, lit, var), Some(fail)) => Expr::Let {
+                var: var.clone(),
+                comp: Comp::Binop(atom, Binary::Eq, Atom::Con(lit)),
+                body: Box::new(Expr::If {
+                    cond: Atom::Var(var),
+                    branch_t: Box::new(body),
+                    branch_f: Box::new(fail.clone()),
+                }),
+            },
+        })
+}
+
+impl Lowering {
+    fn fresh(&mut self, name: &str) -> Var {
+        self.names.var(name)
+    }
+
+    /** The variable naming `comp`, bound in `binds` */
+    fn name(&mut self, comp: Comp, binds: &mut Vec<Bind>) -> Atom {
+        let var = self.fresh("t");
+        binds.push(Bind::Let(var.clone(), comp));
+        Atom::Var(var)
+    }
+
+    /** `expr` with its value passed to `tail` */
+    fn tail(&mut self, expr: &ast::Expr, env: &Env, tail: &Tail) -> Expr {
+        grow(|| {
+            let mut binds = vec![];
+            let (mut expr, mut env) = (expr, env.clone());
+            while let Some((body, body_env)) = self.scope(expr, &env, &mut binds) {
+                (expr, env) = (body, body_env)
+            }
+            let body = match &expr.expr {
+                RawExpr::If {
+                    cond,
+                    branch_t,
+                    branch_f,
+                } => {
+                    let cond = self.atom(cond, &env, &mut binds);
+                    Expr::If {
+                        cond,
+                        branch_t: Box::new(self.tail(branch_t, &env, tail)),
+                        branch_f: Box::new(self.tail(branch_f, &env, tail)),
+                    }
+                }
+                RawExpr::Case { exp, arms } => {
+                    let scrut = self.atom(exp, &env, &mut binds);
+                    self.case(&scrut, arms, &env, tail)
+                }
+                _ => {
+                    let val = self.atom(expr, &env, &mut binds);
+                    tail.apply(val)
+                }
+            };
+            wrap(binds, body)
+        })
+    }
+
+    /** Binds the variables of `expr` in `binds`, if it's a scope like `let`.
+    Returns: Its body, with the variables bound in it */
+    fn scope<'e>(
+        &mut self,
+        expr: &'e ast::Expr,
+        env: &Env,
+        binds: &mut Vec<Bind>,
+    ) -> Option<(&'e ast::Expr, Env)> {
+        let mut env = env.clone();
+        match &expr.expr {
+            RawExpr::Let { pat, exp, body } => {
+                let val = match &pat.pat {
+                    RawPattern::Binding(x) => self.named(exp, &x.name, &env, binds),
+                    _ => self.atom(exp, &env, binds),
+                };
+                let mut steps = vec![];
+                self.pattern(val, pat, &mut env, &mut steps);
+                // Exhaustive, so the pattern matches
+                binds.extend(steps.into_iter().filter_map(|step| match step {
+                    Step::Let(var, comp) => Some(Bind::Let(var, comp)),
+                    _ => None,
+                }));
+                Some((body, env))
+            }
+            RawExpr::Fix { funcs, body } => {
+                let names: Vec<Var> = funcs.iter().map(|(f, ..)| self.fresh(&f.name)).collect();
+                for ((f, ..), name) in zip(funcs, &names) {
+                    env.vars.insert(f.name.clone(), Atom::Var(name.clone()));
+                }
+                let funcs = zip(funcs, names)
+                    .map(|((_, x, _, _, body), name)| self.func(name, &x.name, body, &env))
+                    .collect();
+                binds.push(Bind::Fix(funcs));
+                Some((body, env))
+            }
+            RawExpr::Unpack { var, exp, body, .. } => {
+                let val = self.named(exp, &var.name, &env, binds);
+                env.vars.insert(var.name.clone(), val);
+                Some((body, env))
+            }
+            RawExpr::Open { exp, body } => {
+                let structure = self.atom(exp, &env, binds);
+                let members = match &exp.expr {
+                    RawExpr::Var { id } if !env.vars.contains_key(id) => self.structures.get(id),
+                    _ => None,
+                };
+                match members.cloned() {
+                    Some(members) => {
+                        for member in members {
+                            let var = self.fresh(&member);
+                            let comp = Comp::Proj(structure.clone(), member.clone());
+                            binds.push(Bind::Let(var.clone(), comp));
+                            env.vars.insert(member, Atom::Var(var));
+                        }
+                    }
+                    None => env.opened.push(structure),
+                }
+                Some((body, env))
+            }
+            // Recursive and existential types are erased
+            RawExpr::Fold { exp, .. }
+            | RawExpr::Unfold { exp }
+            | RawExpr::Pack { exp, .. }
+            | RawExpr::Ascribe { exp, .. } => Some((exp, env)),
+            _ => None,
+        }
+    }
+
+    /** The atom of the value of `expr`, its bindings pushed to `binds` */
+    fn atom(&mut self, expr: &ast::Expr, env: &Env, binds: &mut Vec<Bind>) -> Atom {
+        grow(|| {
+            use RawExpr::*;
+            if let Some((body, env)) = self.scope(expr, env, binds) {
+                return self.atom(body, &env, binds);
+            }
+            let atoms = |this: &mut Self, exps: &[&ast::Expr], binds: &mut Vec<Bind>| {
+                exps.iter()
+                    .map(|e| this.atom(e, env, binds))
+                    .collect::<Vec<_>>()
+            };
+            let prim = |this: &mut Self, prim, exps: &[&ast::Expr], binds: &mut Vec<Bind>| {
+                let args = atoms(this, exps, binds);
+                this.name(Comp::Prim(prim, args), binds)
+            };
+            match &expr.expr {
+                Con { val } => Atom::Con(val.clone()),
+                Var { id } => self.var(id, env, binds),
+                EApp { .. } | TApp { .. } if self.ctor_spine(expr).is_some() => {
+                    let (ctor, types, args) = self.ctor_spine(expr).unwrap();
+                    let fields = atoms(self, &args, binds);
+                    self.ctor(ctor, types, fields, binds)
+                }
+                EApp { exp, arg } => {
+                    let fun = self.atom(exp, env, binds);
+                    let arg = self.atom(arg, env, binds);
+                    self.name(Comp::App(fun, vec![arg]), binds)
+                }
+                TApp { exp, .. } => {
+                    let fun = self.atom(exp, env, binds);
+                    self.name(Comp::App(fun, vec![Atom::Con(Constant::Null)]), binds)
+                }
+                Lambda { .. } | Any { .. } => self.named(expr, "f", env, binds),
+                Tuple { entries } => {
+                    let entries = atoms(self, &entries.iter().collect::<Vec<_>>(), binds);
+                    self.name(Comp::Tuple(entries), binds)
+                }
+                Record { fields } => {
+                    let exps: Vec<_> = fields.iter().map(|(_, e)| e).collect();
+                    let labels = fields.iter().map(|(l, _)| l.name.clone());
+                    let fields = zip(labels, atoms(self, &exps, binds)).collect();
+                    self.name(Comp::Record(fields), binds)
+                }
+                Proj { exp, field } => {
+                    let record = self.atom(exp, env, binds);
+                    self.name(Comp::Proj(record, field.name.clone()), binds)
+                }
+                Nth { exp, index } => {
+                    let tuple = self.atom(exp, env, binds);
+                    self.name(Comp::Nth(tuple, *index), binds)
+                }
+                Binop {
+                    lhs,
+                    op,
+                    rhs,
+                    width,
+                } if *width != Width::Int
+                    && (op.is_arithmetic() || matches!(op, Binary::Lt | Binary::Gt)) =>
+                {
+                    prim(self, Prim::Arith(op.clone(), *width), &[lhs, rhs], binds)
+                }
+                Binop { lhs, op, rhs, .. } => {
+                    let lhs = self.atom(lhs, env, binds);
+                    let rhs = self.atom(rhs, env, binds);
+                    self.name(Comp::Binop(lhs, op.clone(), rhs), binds)
+                }
+                If { .. } | Case { .. } => {
+                    let (k, v) = (self.fresh("k"), self.fresh("v"));
+                    let branches = self.tail(expr, env, &Tail::Jump(k.clone()));
+                    binds.push(Bind::Join(k, v.clone(), branches));
+                    Atom::Var(v)
+                }
+                Convert { exp, from, to } => prim(self, Prim::Convert(*from, *to), &[exp], binds),
+                Ref { exp } => prim(self, Prim::Ref, &[exp], binds),
+                Deref { exp } => prim(self, Prim::Deref, &[exp], binds),
+                Assign { lhs, rhs } => prim(self, Prim::Assign, &[lhs, rhs], binds),
+                Array { len, init } => prim(self, Prim::Array, &[len, init], binds),
+                Sub { arr, idx } => prim(self, Prim::Sub, &[arr, idx], binds),
+                Update { arr, idx, val } => prim(self, Prim::Update, &[arr, idx, val], binds),
+                AssertEq { lhs, rhs } => prim(self, Prim::AssertEq, &[lhs, rhs], binds),
+                Join { exp } => prim(self, Prim::Join, &[exp], binds),
+                Spawn { exp } => {
+                    let task = self.thunk("task", exp, env, binds);
+                    self.name(Comp::Prim(Prim::Spawn, vec![task]), binds)
+                }
+                Raise { exp, .. } => {
+                    let exn = self.atom(exp, env, binds);
+                    binds.push(Bind::Raise(exn));
+                    // Never evaluated
+                    Atom::Con(Constant::Null)
+                }
+                Handle { exp, pat, handler } => {
+                    let body = self.tail(exp, env, &Tail::Ret);
+                    let exn = self.fresh("exn");
+                    let reraise = Expr::Raise {
+                        exn: Atom::Var(exn.clone()),
+                    };
+                    let arms = [(pat.clone(), (**handler).clone())];
+                    let handler = self.arms(
+                        &Atom::Var(exn.clone()),
+                        &arms,
+                        env,
+                        &Tail::Ret,
+                        Some(reraise),
+                    );
+                    let comp = Comp::Handle {
+                        body: Box::new(body),
+                        exn,
+                        handler: Box::new(handler),
+                    };
+                    self.name(comp, binds)
+                }
+                // Resolved by inference
+                Instance { .. } => unreachable!("instances are resolved before lowering"),
+                Let { .. } | Fix { .. } | Unpack { .. } | Open { .. } => unreachable!(),
+                Fold { .. } | Unfold { .. } | Pack { .. } | Ascribe { .. } => unreachable!(),
+            }
+        })
+    }
+
+    /** The atom of `expr`, named after `name` if it's a function or the result of an operation */
+    fn named(&mut self, expr: &ast::Expr, name: &str, env: &Env, binds: &mut Vec<Bind>) -> Atom {
+        match &expr.expr {
+            RawExpr::Lambda { arg: (x, _), body } => {
+                let name = self.fresh(name);
+                let func = self.func(name.clone(), &x.name, body, env);
+                binds.push(Bind::Fix(vec![func]));
+                Atom::Var(name)
+            }
+            RawExpr::Any { body, .. } => self.thunk(name, body, env, binds),
+            _ => {
+                let val = self.atom(expr, env, binds);
+                match (&val, binds.last_mut()) {
+                    // The operation just named, only referred to by `val`
+                    (Atom::Var(var), Some(Bind::Let(last, _))) if var == last => {
+                        let renamed = self.fresh(name);
+                        *last = renamed.clone();
+                        Atom::Var(renamed)
+                    }
+                    _ => val,
+                }
+            }
+        }
+    }
+
+    /** The function `name` of the parameter `param` returning `body` */
+    fn func(&mut self, name: Var, param: &str, body: &ast::Expr, env: &Env) -> Func {
+        let mut env = env.clone();
+        let var = self.fresh(param);
+        env.vars.insert(param.to_string(), Atom::Var(var.clone()));
+        Func {
+            name,
+            params: vec![var],
+            body: self.tail(body, &env, &Tail::Ret),
+        }
+    }
+
+    /** The function of a unit argument, named after `name`, evaluating `body` */
+    fn thunk(&mut self, name: &str, body: &ast::Expr, env: &Env, binds: &mut Vec<Bind>) -> Atom {
+        let (name, unit) = (self.fresh(name), self.fresh("u"));
+        let func = Func {
+            name: name.clone(),
+            params: vec![unit],
+            body: self.tail(body, env, &Tail::Ret),
+        };
+        binds.push(Bind::Fix(vec![func]));
+        Atom::Var(name)
+    }
+
+    /** The atom of the variable `id` */
+    fn var(&mut self, id: &str, env: &Env, binds: &mut Vec<Bind>) -> Atom {
+        if let Some(atom) = env.vars.get(id) {
+            return atom.clone();
+        }
+        if self.ctors.contains_key(id) {
+            return self.ctor(id, 0, vec![], binds);
+        }
+        match env.opened.last() {
+            Some(structure) if !self.globals.contains(id) => {
+                self.name(Comp::Proj(structure.clone(), id.to_string()), binds)
+            }
+            _ => Atom::Var(id.to_string()),
+        }
+    }
+
+    /** The constructor applied in `expr` to types then to fields, like `Some [Int] 1`, with the
+    number of types and the fields */
+    fn ctor_spine<'e>(&self, expr: &'e ast::Expr) -> Option<(&'e str, usize, Vec<&'e ast::Expr>)> {
+        let (mut expr, mut args, mut types) = (expr, vec![], 0);
+        while let RawExpr::EApp { exp, arg } = &expr.expr {
+            args.push(&**arg);
+            expr = exp
+        }
+        while let RawExpr::TApp { exp, .. } = &expr.expr {
+            types += 1;
+            expr = exp
+        }
+        args.reverse();
+        match &expr.expr {
+            RawExpr::Var { id } if self.ctors.contains_key(id) => Some((id, types, args)),
+            _ => None,
+        }
+    }
+
+    /** The atom of the constructor `ctor` applied to `types` types and to `fields`. Until it's
+    applied to all of them, functions taking the rest */
+    fn ctor(
+        &mut self,
+        ctor: &str,
+        types: usize,
+        mut fields: Vec<Atom>,
+        binds: &mut Vec<Bind>,
+    ) -> Atom {
+        let (params, arity) = self.ctors[ctor];
+        if types >= params && fields.len() == arity {
+            return self.name(Comp::Data(ctor.to_string(), fields), binds);
+        }
+        let name = self.fresh("f");
+        let param = self.fresh(if types < params { "u" } else { "x" });
+        let types = match types < params {
+            true => types + 1,
+            false => {
+                fields.push(Atom::Var(param.clone()));
+                types
+            }
+        };
+        let mut body = vec![];
+        let val = self.ctor(ctor, types, fields, &mut body);
+        let func = Func {
+            name: name.clone(),
+            params: vec![param],
+            body: wrap(body, Expr::Ret { val }),
+        };
+        binds.push(Bind::Fix(vec![func]));
+        Atom::Var(name)
+    }
+
+    /** Matching the value of `scrut` against the `arms` in order, passing the value of the body
+    of the first matching to `tail` */
+    fn case(
+        &mut self,
+        scrut: &Atom,
+        arms: &[(Pattern, ast::Expr)],
+        env: &Env,
+        tail: &Tail,
+    ) -> Expr {
+        match self.switch(scrut, arms, env, tail) {
+            Some(expr) => expr,
+            None => self.arms(scrut, arms, env, tail, None),
+        }
+    }
+
+    /** Matching like [`case`](Lowering::case) by a single `Case`, if the arms are on distinct
+    constructors binding their fields to variables, maybe but for a last catching all */
+    fn switch(
+        &mut self,
+        scrut: &Atom,
+        arms: &[(Pattern, ast::Expr)],
+        env: &Env,
+        tail: &Tail,
+    ) -> Option<Expr> {
+        let flat = |pat: &Pattern| matches!(pat.pat, RawPattern::Wildcard | RawPattern::Binding(_));
+        let (ctors, default) = match arms.split_last()? {
+            ((pat, body), ctors) if flat(pat) => (ctors, Some((pat, body))),
+            _ => (arms, None),
+        };
+        let mut seen = HashSet::new();
+        for (pat, _) in ctors {
+            match &pat.pat {
+                RawPattern::Ctor(c, pats) if pats.iter().all(flat) => {
+                    if seen.insert(&c.name).is_some() {
+                        return None;
+                    }
+                }
+                _ => return None,
+            }
+        }
+        if ctors.is_empty() {
+            return None;
+        }
+        let mut switch_arms = vec![];
+        for (pat, body) in ctors {
+            let (mut env, mut steps) = (env.clone(), vec![]);
+            self.pattern(scrut.clone(), pat, &mut env, &mut steps);
+            let body = self.tail(body, &env, tail);
+            let ctor = match &pat.pat {
+                RawPattern::Ctor(c, _) => c.name.clone(),
+                _ => unreachable!(),
+            };
+            switch_arms.push((ctor, matching(steps, body, None)))
+        }
+        let default = default.map(|(pat, body)| {
+            let (mut env, mut steps) = (env.clone(), vec![]);
+            self.pattern(scrut.clone(), pat, &mut env, &mut steps);
+            Box::new(self.tail(body, &env, tail))
+        });
+        Some(Expr::Case {
+            scrut: scrut.clone(),
+            arms: switch_arms,
+            default,
+        })
+    }
+
+    /** Matching like [`case`](Lowering::case) one arm after the other, evaluating `fallback` if
+    none matches. Without `fallback` one must match, the last then not tested */
+    fn arms(
+        &mut self,
+        scrut: &Atom,
+        arms: &[(Pattern, ast::Expr)],
+        env: &Env,
+        tail: &Tail,
+        fallback: Option<Expr>,
+    ) -> Expr {
+        let ((pat, body), rest) = match arms.split_first() {
+            Some(arms) => arms,
+            // Values of data types without constructors don't exist
+            None => return fallback.unwrap_or_else(|| tail.apply(Atom::Con(Constant::Null))),
+        };
+        let (mut body_env, mut steps) = (env.clone(), vec![]);
+        self.pattern(scrut.clone(), pat, &mut body_env, &mut steps);
+        let success = self.tail(body, &body_env, tail);
+        let refutable = steps.iter().any(|step| !matches!(step, Step::Let(..)));
+        if !refutable || (rest.is_empty() && fallback.is_none()) {
+            return matching(steps, success, None);
+        }
+        let fail = self.arms(scrut, rest, env, tail, fallback);
+        let name = self.fresh("next");
+        let jump = Expr::Jump {
+            name: name.clone(),
+            args: vec![],
+        };
+        Expr::Join {
+            name,
+            params: vec![],
+            join: Box::new(fail),
+            body: Box::new(matching(steps, success, Some(&jump))),
+        }
+    }
+
+    /** Pushes to `steps` those of matching `val` against `pat`, and binds its variables in `env` */
+    fn pattern(&mut self, val: Atom, pat: &Pattern, env: &mut Env, steps: &mut Vec<Step>) {
+        match &pat.pat {
+            RawPattern::Wildcard => (),
+            RawPattern::Binding(x) => {
+                env.vars.insert(x.name.clone(), val);
+            }
+            RawPattern::Tuple(pats) => {
+                for (i, pat) in pats.iter().enumerate() {
+                    self.subpattern(Comp::Nth(val.clone(), i), pat, env, steps)
+                }
+            }
+            RawPattern::Record(fields) => {
+                for (label, pat) in fields {
+                    let comp = Comp::Proj(val.clone(), label.name.clone());
+                    self.subpattern(comp, pat, env, steps)
+                }
+            }
+            RawPattern::Literal(lit) => {
+                let var = self.fresh("t");
+                steps.push(Step::Literal(val, lit.clone(), var))
+            }
+            RawPattern::Ctor(ctor, pats) => {
+                steps.push(Step::Ctor(val.clone(), ctor.name.clone()));
+                for (i, pat) in pats.iter().enumerate() {
+                    self.subpattern(Comp::Field(val.clone(), i), pat, env, steps)
+                }
+            }
+        }
+    }
+
+    /** Pushes to `steps` those of matching the part `comp` of a value against `pat` */
+    fn subpattern(&mut self, comp: Comp, pat: &Pattern, env: &mut Env, steps: &mut Vec<Step>) {
+        let name = match &pat.pat {
+            RawPattern::Wildcard => return,
+            RawPattern::Binding(x) => x.name.as_str(),
+            _ => "p",
+        };
+        let var = self.fresh(name);
+        steps.push(Step::Let(var.clone(), comp));
+        self.pattern(Atom::Var(var), pat, env, steps)
+    }
+}
+
+impl Display for Atom {
+    /** Integers of a width are suffixed with it, like `3i8` */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Atom::Var(var) => write!(f, "{var}"),
+            Atom::Con(Constant::Sized(i, width)) => write!(f, "{i}i{}", width.bits()),
+            Atom::Con(con) => write!(f, "{con}"),
+        }
+    }
+}
+
+/** The atoms separated by commas */
+fn commas<T: Display>(items: &[T]) -> String {
+    let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+    items.join(", ")
+}
+
+/** Writes `comp`, the lines after the first indented `depth` times */
+fn write_comp(f: &mut fmt::Formatter, comp: &Comp, depth: usize) -> fmt::Result {
+    let pad = "    ".repeat(depth);
+    match comp {
+        Comp::Atom(atom) => write!(f, "{atom}"),
+        Comp::Binop(lhs, op, rhs) => write!(f, "{lhs} {op} {rhs}"),
+        Comp::App(fun, args) => write!(f, "{fun}({})", commas(args)),
+        Comp::Tuple(entries) => write!(f, "({})", commas(entries)),
+        Comp::Nth(tuple, index) => write!(f, "{tuple}.{index}"),
+        Comp::Record(fields) => {
+            let fields: Vec<String> = fields.iter().map(|(l, a)| format!("{l} = {a}")).collect();
+            write!(f, "{{{}}}", fields.join(", "))
+        }
+        Comp::Proj(record, label) => write!(f, "{record}.{label}"),
+        Comp::Data(ctor, fields) if fields.is_empty() => write!(f, "{ctor}"),
+        Comp::Data(ctor, fields) => write!(f, "{ctor}({})", commas(fields)),
+        Comp::Field(data, index) => write!(f, "{data}#{index}"),
+        Comp::Closure(func, env) => write!(f, "closure {func}({})", commas(env)),
+        Comp::Env(clo, index) => write!(f, "{clo}@{index}"),
+        Comp::Prim(prim, args) => match (prim, &args[..]) {
+            (Prim::Ref, [arg]) => write!(f, "ref {arg}"),
+            (Prim::Deref, [arg]) => write!(f, "!{arg}"),
+            (Prim::Assign, [lhs, rhs]) => write!(f, "{lhs} := {rhs}"),
+            (Prim::Spawn, [arg]) => write!(f, "spawn {arg}"),
+            (Prim::Join, [arg]) => write!(f, "join {arg}"),
+            (prim, args) => write!(f, "{prim}({})", commas(args)),
+        },
+        Comp::Handle { body, exn, handler } => {
+            write!(f, "handle\n{pad}    ")?;
+            write_expr(f, body, depth + 1)?;
+            write!(f, "\n{pad}with {exn} =>\n{pad}    ")?;
+            write_expr(f, handler, depth + 1)?;
+            write!(f, "\n{pad}end")
+        }
+    }
+}
+
+/** Writes `expr`, the lines after the first indented `depth` times */
+fn write_expr(f: &mut fmt::Formatter, expr: &Expr, depth: usize) -> fmt::Result {
+    grow(|| {
+        let pad = "    ".repeat(depth);
+        match expr {
+            Expr::Let { var, comp, body } => {
+                write!(f, "let {var} = ")?;
+                write_comp(f, comp, depth)?;
+                write!(f, " in\n{pad}")?;
+                write_expr(f, body, depth)
+            }
+            Expr::Fix { funcs, body } => {
+                for (i, func) in funcs.iter().enumerate() {
+                    let keyword = if i == 0 { "fix" } else { "and" };
+                    let params = func.params.join(", ");
+                    write!(f, "{keyword} {}({params}) =\n{pad}    ", func.name)?;
+                    write_expr(f, &func.body, depth + 1)?;
+                    write!(f, "\n{pad}")?;
+                }
+                write!(f, "in\n{pad}")?;
+                write_expr(f, body, depth)
+            }
+            Expr::Join {
+                name,
+                params,
+                join,
+                body,
+            } => {
+                write!(f, "join {name}({}) =\n{pad}    ", params.join(", "))?;
+                write_expr(f, join, depth + 1)?;
+                write!(f, "\n{pad}in\n{pad}")?;
+                write_expr(f, body, depth)
+            }
+            Expr::Jump { name, args } => write!(f, "jump {name}({})", commas(args)),
+            Expr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => {
+                write!(f, "if {cond} then\n{pad}    ")?;
+                write_expr(f, branch_t, depth + 1)?;
+                write!(f, "\n{pad}else\n{pad}    ")?;
+                write_expr(f, branch_f, depth + 1)
+            }
+            Expr::Case {
+                scrut,
+                arms,
+                default,
+            } => {
+                write!(f, "case {scrut} of")?;
+                let default = default.iter().map(|e| ("_".to_string(), &**e));
+                for (ctor, body) in arms.iter().map(|(c, e)| (c.clone(), e)).chain(default) {
+                    write!(f, "\n{pad}| {ctor} =>\n{pad}    ")?;
+                    write_expr(f, body, depth + 1)?;
+                }
+                write!(f, "\n{pad}end")
+            }
+            Expr::Raise { exn } => write!(f, "raise {exn}"),
+            Expr::Ret { val } => write!(f, "{val}"),
+        }
+    })
+}
+
+impl Display for Prim {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Prim::Arith(op, width) => return write!(f, "{}{width}", op.name()),
+            Prim::Convert(Width::Int, to) => return write!(f, "int{to}"),
+            Prim::Convert(from, to) => return write!(f, "int{to}of{from}"),
+            Prim::Ref => "ref",
+            Prim::Deref => "deref",
+            Prim::Assign => "assign",
+            Prim::Array => "array",
+            Prim::Sub => "sub",
+            Prim::Update => "update",
+            Prim::AssertEq => "assertEq",
+            Prim::Spawn => "spawn",
+            Prim::Join => "join",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for Comp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_comp(f, self, 0)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expr(f, self, 0)
+    }
+}
+
+impl Display for Prog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for data in &self.datatypes {
+            let ctors: Vec<String> = data
+                .ctors
+                .iter()
+                .map(|(ctor, arity)| format!("{ctor}{}", " _".repeat(*arity)))
+                .collect();
+            writeln!(f, "data {} = {}", data.id, ctors.join(" | "))?;
+        }
+        for func in &self.funcs {
+            write!(f, "\nfun {}({}) =\n    ", func.name, func.params.join(", "))?;
+            write_expr(f, &func.body, 1)?;
+            writeln!(f)?;
+        }
+        for decl in &self.decls {
+            writeln!(f)?;
+            if !decl.attrs.is_empty() {
+                write!(f, "@[{}] ", decl.attrs.join(", "))?;
+            }
+            write!(f, "let {} =\n    ", decl.id)?;
+            write_expr(f, &decl.body, 1)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+/*! Closure conversion of programs in A-normal form. The functions of a `fix` become closed, taking
+their closure as an extra first parameter: the function with the values of the variables it
+captures, built by [`Closure`](Comp::Closure) where the `fix` was. The functions of a `fix` capture
+the local variables any of them refers to, in the same order. A function reads what it captures by
+[`Env`](Comp::Env) on its closure, refers to itself by its closure, and to the other functions of
+its `fix` by closures of the same values. Applying a closure is unchanged, calling its function
+with the closure before the arguments. The declarations, builtins and functions at the top level
+are global, never captured. */
+
+use crate::ast::ast::grow;
+use crate::ir::anf::{Atom, Comp, Decl, Expr, Fresh, Func, Prog, Var};
+use crate::ir::pass::{Pass, Run};
+
+use im::{HashMap, HashSet};
+
+/// The pass converting closures
+pub const PASS: Pass = Pass {
+    name: "closure",
+    run: Run::Anf(convert),
+    requires: &[],
+    once: true,
+};
+
+/** Converts the closures of `prog` */
+pub fn convert(prog: &Prog) -> Prog {
+    let mut conversion = Conversion {
+        names: Fresh::after(prog),
+    };
+    // Functions at the top level are closed already
+    let funcs = prog.funcs.iter().map(|func| Func {
+        body: conversion.expr(&func.body, &HashMap::new(), &HashSet::new()),
+        ..func.clone()
+    });
+    let funcs = funcs.collect();
+    let decls = prog.decls.iter().map(|decl| Decl {
+        body: conversion.expr(&decl.body, &HashMap::new(), &HashSet::new()),
+        ..decl.clone()
+    });
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs,
+        decls: decls.collect(),
+    }
+}
+
+struct Conversion {
+    names: Fresh,
+}
+
+/// Variables standing for others in the function being converted, those it captures and those of
+/// its `fix`
+type Renaming = HashMap<Var, Var>;
+
+fn rename(atom: &Atom, renaming: &Renaming) -> Atom {
+    match atom {
+        Atom::Var(var) => Atom::Var(renaming.get(var).unwrap_or(var).clone()),
+        Atom::Con(_) => atom.clone(),
+    }
+}
+
+fn rename_all(atoms: &[Atom], renaming: &Renaming) -> Vec<Atom> {
+    atoms.iter().map(|a| rename(a, renaming)).collect()
+}
+
+impl Conversion {
+    /** Converts `expr`, where `locals` are the variables bound outside of it in the declaration,
+    and `renaming` the variables standing for them in the function being converted */
+    fn expr(&mut self, expr: &Expr, renaming: &Renaming, locals: &HashSet<Var>) -> Expr {
+        grow(|| {
+            let atom = |a| rename(a, renaming);
+            match expr {
+                Expr::Let { var, comp, body } => Expr::Let {
+                    var: var.clone(),
+                    comp: self.comp(comp, renaming, locals),
+                    body: Box::new(self.expr(body, renaming, &locals.update(var.clone()))),
+                },
+                Expr::Fix { funcs, body } => {
+                    let locals = funcs
+                        .iter()
+                        .fold(locals.clone(), |locals, f| locals.update(f.name.clone()));
+                    // Captured by each function, in order
+                    let mut captured: Vec<Var> = funcs
+                        .iter()
+                        .flat_map(|f| {
+                            f.body
+                                .free_vars()
+                                .relative_complement(f.params.iter().cloned().collect())
+                        })
+                        .filter(|var| locals.contains(var) && !funcs.iter().any(|f| &f.name == var))
+                        .collect::<HashSet<_>>()
+                        .into_iter()
+                        .collect();
+                    captured.sort();
+                    let codes: Vec<Var> = funcs.iter().map(|f| self.names.var(&f.name)).collect();
+                    let closed = funcs
+                        .iter()
+                        .enumerate()
+                        .map(|(i, f)| self.func(f, i, funcs, &codes, &captured, &locals))
+                        .collect();
+                    let env: Vec<Atom> = captured
+                        .iter()
+                        .map(|v| Atom::Var(renaming.get(v).unwrap_or(v).clone()))
+                        .collect();
+                    let body = self.expr(body, renaming, &locals);
+                    let closures = funcs
+                        .iter()
+                        .zip(&codes)
+                        .rev()
+                        .fold(body, |body, (f, code)| Expr::Let {
+                            var: f.name.clone(),
+                            comp: Comp::Closure(code.clone(), env.clone()),
+                            body: Box::new(body),
+                        });
+                    Expr::Fix {
+                        funcs: closed,
+                        body: Box::new(closures),
+                    }
+                }
+                Expr::Join {
+                    name,
+                    params,
+                    join,
+                    body,
+                } => {
+                    let join_locals = params
+                        .iter()
+                        .fold(locals.clone(), |locals, p| locals.update(p.clone()));
+                    Expr::Join {
+                        name: name.clone(),
+                        params: params.clone(),
+                        join: Box::new(self.expr(join, renaming, &join_locals)),
+                        body: Box::new(self.expr(body, renaming, locals)),
+                    }
+                }
+                Expr::Jump { name, args } => Expr::Jump {
+                    name: name.clone(),
+                    args: rename_all(args, renaming),
+                },
+                Expr::If {
+                    cond,
+                    branch_t,
+                    branch_f,
+                } => Expr::If {
+                    cond: atom(cond),
+                    branch_t: Box::new(self.expr(branch_t, renaming, locals)),
+                    branch_f: Box::new(self.expr(branch_f, renaming, locals)),
+                },
+                Expr::Case {
+                    scrut,
+                    arms,
+                    default,
+                } => Expr::Case {
+                    scrut: atom(scrut),
+                    arms: arms
+                        .iter()
+                        .map(|(c, e)| (c.clone(), self.expr(e, renaming, locals)))
+                        .collect(),
+                    default: default
+                        .as_ref()
+                        .map(|e| Box::new(self.expr(e, renaming, locals))),
+                },
+                Expr::Raise { exn } => Expr::Raise { exn: atom(exn) },
+                Expr::Ret { val } => Expr::Ret { val: atom(val) },
+            }
+        })
+    }
+
+    fn comp(&mut self, comp: &Comp, renaming: &Renaming, locals: &HashSet<Var>) -> Comp {
+        let atom = |a| rename(a, renaming);
+        match comp {
+            Comp::Atom(a) => Comp::Atom(atom(a)),
+            Comp::Binop(lhs, op, rhs) => Comp::Binop(atom(lhs), op.clone(), atom(rhs)),
+            Comp::App(fun, args) => Comp::App(atom(fun), rename_all(args, renaming)),
+            Comp::Tuple(entries) => Comp::Tuple(rename_all(entries, renaming)),
+            Comp::Nth(tuple, i) => Comp::Nth(atom(tuple), *i),
+            Comp::Record(fields) => {
+                Comp::Record(fields.iter().map(|(l, a)| (l.clone(), atom(a))).collect())
+            }
+            Comp::Proj(record, label) => Comp::Proj(atom(record), label.clone()),
+            Comp::Data(ctor, fields) => Comp::Data(ctor.clone(), rename_all(fields, renaming)),
+            Comp::Field(data, i) => Comp::Field(atom(data), *i),
+            Comp::Prim(prim, args) => Comp::Prim(prim.clone(), rename_all(args, renaming)),
+            Comp::Closure(func, env) => Comp::Closure(func.clone(), rename_all(env, renaming)),
+            Comp::Env(clo, i) => Comp::Env(atom(clo), *i),
+            Comp::Handle { body, exn, handler } => Comp::Handle {
+                body: Box::new(self.expr(body, renaming, locals)),
+                exn: exn.clone(),
+                handler: Box::new(self.expr(handler, renaming, &locals.update(exn.clone()))),
+            },
+        }
+    }
+
+    /** The closed function of the `i`th of `funcs`, named `codes[i]`. The functions capture the
+    variables `captured`, among the `locals` */
+    fn func(
+        &mut self,
+        func: &Func,
+        i: usize,
+        funcs: &[Func],
+        codes: &[Var],
+        captured: &[Var],
+        locals: &HashSet<Var>,
+    ) -> Func {
+        let free = func.body.free_vars();
+        let clo = self.names.var("env");
+        let siblings: Vec<usize> = (0..funcs.len())
+            .filter(|&j| j != i && free.contains(&funcs[j].name))
+            .collect();
+        let mut renaming = Renaming::new();
+        let mut prologue = vec![];
+        // The closures of the other functions need all of the values captured
+        let mut env = vec![];
+        for (index, var) in captured.iter().enumerate() {
+            if !siblings.is_empty() || free.contains(var) {
+                let renamed = self.names.var(var);
+                prologue.push((renamed.clone(), Comp::Env(Atom::Var(clo.clone()), index)));
+                renaming.insert(var.clone(), renamed.clone());
+                env.push(Atom::Var(renamed))
+            }
+        }
+        renaming.insert(func.name.clone(), clo.clone());
+        for j in siblings {
+            let renamed = self.names.var(&funcs[j].name);
+            prologue.push((
+                renamed.clone(),
+                Comp::Closure(codes[j].clone(), env.clone()),
+            ));
+            renaming.insert(funcs[j].name.clone(), renamed);
+        }
+        let params = func.params.iter().cloned();
+        let body_locals = locals
+            .iter()
+            .filter(|var| renaming.contains_key(*var))
+            .cloned()
+            .chain(params.clone())
+            .collect();
+        let body = self.expr(&func.body, &renaming, &body_locals);
+        let body = prologue
+            .into_iter()
+            .rev()
+            .fold(body, |body, (var, comp)| Expr::Let {
+                var,
+                comp,
+                body: Box::new(body),
+            });
+        Func {
+            name: codes[i].clone(),
+            params: std::iter::once(clo).chain(params).collect(),
+            body,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+/*! Copy propagation of programs in static single assignment form. Lowering to A-normal form,
+closure conversion and the passes on it leave copies `x = y` behind, and joins pass on values as
+block parameters, moves once registers are allocated. Copies are removed, their registers replaced
+by what they copy. Parameters every jump to their block passes the same atom, or the parameter
+itself back, are removed along with their arguments, so are coalesced with that atom. Registers
+being assigned once, what a copy copies is defined wherever the copy is used. */
+
+use crate::ir::anf::{Atom, Var};
+use crate::ir::pass::{Pass, Run};
+use crate::ir::ssa::{Block, Func, Inst, Label, Op, Prog, Term};
+
+use std::collections::HashMap;
+
+/// The pass propagating copies
+pub const PASS: Pass = Pass {
+    name: "copy",
+    run: Run::Ssa(copy),
+    requires: &[],
+    once: false,
+};
+
+/** Propagates the copies of the functions and declarations of `prog` */
+pub fn copy(prog: &Prog) -> Prog {
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs: prog.funcs.iter().map(copy_func).collect(),
+        decls: prog.decls.iter().map(copy_func).collect(),
+    }
+}
+
+/** `atom` with the registers of `copies` replaced by what they copy */
+pub fn resolve(copies: &HashMap<Var, Atom>, atom: &Atom) -> Atom {
+    let mut atom = atom;
+    while let Some(copied) = atom.var().and_then(|var| copies.get(var)) {
+        atom = copied
+    }
+    atom.clone()
+}
+
+fn copy_func(func: &Func) -> Func {
+    let mut copies = HashMap::new();
+    for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+        if let Op::Atom(atom) = &inst.op {
+            copies.insert(inst.var.clone(), atom.clone());
+        }
+    }
+    let jumps = func.jumps();
+    // Removing parameters may make others passed the same atom
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (block, jumps) in func.blocks.iter().zip(&jumps) {
+            let Some(jumps) = jumps else { continue };
+            for (index, param) in block.params.iter().enumerate() {
+                if copies.contains_key(param) {
+                    continue;
+                }
+                let passed = jumps.iter().map(|args| resolve(&copies, &args[index]));
+                let mut others = passed.filter(|atom| atom.var() != Some(param));
+                let Some(atom) = others.next() else { continue };
+                if others.all(|other| other == atom) {
+                    copies.insert(param.clone(), atom);
+                    changed = true
+                }
+            }
+        }
+    }
+    let replace = |atom: &Atom| resolve(&copies, atom);
+    let kept = |label: Label| {
+        let params = func.blocks[label].params.iter();
+        params
+            .map(|param| !copies.contains_key(param))
+            .collect::<Vec<_>>()
+    };
+    let blocks = func.blocks.iter().enumerate().map(|(label, block)| {
+        let insts = block
+            .insts
+            .iter()
+            .filter(|inst| !matches!(inst.op, Op::Atom(_)));
+        let insts = insts.map(|inst| Inst {
+            var: inst.var.clone(),
+            op: inst.op.map_atoms(replace),
+        });
+        let term = match block.term.map_atoms(replace) {
+            Term::Jump(next, args) => {
+                let args = args.into_iter().zip(kept(next)).filter(|(_, kept)| *kept);
+                Term::Jump(next, args.map(|(arg, _)| arg).collect())
+            }
+            term => term,
+        };
+        let params = block.params.iter().zip(kept(label));
+        Block {
+            params: params
+                .filter(|(_, kept)| *kept)
+                .map(|(p, _)| p.clone())
+                .collect(),
+            insts: insts.collect(),
+            term,
+            handler: block.handler,
+        }
+    });
+    Func {
+        name: func.name.clone(),
+        blocks: blocks.collect(),
+    }
+}
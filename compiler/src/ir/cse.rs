@@ -0,0 +1,167 @@
+/*! Common subexpression elimination of programs in static single assignment form, by global value
+numbering over the dominator tree. An instruction computing what an instruction dominating it
+computed already is removed, its register replaced by the other's. Registers being assigned once,
+operations are the same when their operands are, up to the order of those of commutative operators.
+What's merged depends on the [effects](crate::ir::effects) of the operation: allocations of
+references and arrays are never merged, and reads of them only within a block, up to the first
+write. Calls, including those of builtins doing I/O, are terminators, so they're never merged
+either. */
+
+use crate::ast::ast::Binary;
+use crate::ir::anf::{Atom, Var};
+use crate::ir::dominators::immediate;
+use crate::ir::effects::{self, Effects};
+use crate::ir::pass::{Pass, Run};
+use crate::ir::ssa::{Block, Func, Inst, Label, Op, Prog};
+
+use std::collections::HashMap;
+
+/// The pass eliminating common subexpressions
+pub const PASS: Pass = Pass {
+    name: "cse",
+    run: Run::Ssa(cse),
+    requires: &[],
+    once: false,
+};
+
+/** Eliminates the common subexpressions of the functions and declarations of `prog` */
+pub fn cse(prog: &Prog) -> Prog {
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs: prog.funcs.iter().map(cse_func).collect(),
+        decls: prog.decls.iter().map(cse_func).collect(),
+    }
+}
+
+/// The registers of the operations computed already, in order
+type Avail = im::Vector<(Op, Var)>;
+
+struct Numbering<'a> {
+    func: &'a Func,
+    /// The nodes each node of the graph of entries and exits of blocks immediately dominates
+    children: Vec<Vec<usize>>,
+    /// The registers replaced, by the atoms replacing them
+    replaced: HashMap<Var, Atom>,
+    /// The instructions left in each block
+    insts: Vec<Vec<Inst>>,
+}
+
+fn cse_func(func: &Func) -> Func {
+    // The entry of block `l` is node `2 * l`, and its exit `2 * l + 1`, after its instructions.
+    // Exceptions may be raised before any of them, so handlers are reached from entries
+    let mut succs = vec![];
+    for block in &func.blocks {
+        let entry = std::iter::once(succs.len() + 1).chain(block.handler.map(|h| 2 * h));
+        succs.push(entry.collect());
+        succs.push(block.term.successors().iter().map(|l| 2 * l).collect())
+    }
+    let mut children = vec![vec![]; succs.len()];
+    for (node, idom) in immediate(&succs).into_iter().enumerate() {
+        if let Some(idom) = idom {
+            children[idom].push(node)
+        }
+    }
+    let mut numbering = Numbering {
+        func,
+        children,
+        replaced: HashMap::new(),
+        insts: func
+            .blocks
+            .iter()
+            .map(|block| block.insts.clone())
+            .collect(),
+    };
+    numbering.node(0, Avail::new());
+    let replace = |atom: &Atom| numbering.replace(atom);
+    let blocks = func
+        .blocks
+        .iter()
+        .zip(&numbering.insts)
+        .map(|(block, insts)| {
+            let insts = insts.iter().map(|inst| Inst {
+                var: inst.var.clone(),
+                op: inst.op.map_atoms(replace),
+            });
+            Block {
+                params: block.params.clone(),
+                insts: insts.collect(),
+                term: block.term.map_atoms(replace),
+                handler: block.handler,
+            }
+        });
+    Func {
+        name: func.name.clone(),
+        blocks: blocks.collect(),
+    }
+}
+
+impl<'a> Numbering<'a> {
+    fn replace(&self, atom: &Atom) -> Atom {
+        match atom.var().and_then(|var| self.replaced.get(var)) {
+            Some(replacing) => replacing.clone(),
+            None => atom.clone(),
+        }
+    }
+
+    /** Numbers the values computed at `node` and those it dominates, with the operations of
+    `avail` computed before it */
+    fn node(&mut self, node: usize, mut avail: Avail) {
+        if node % 2 == 1 {
+            avail = self.block(node / 2, avail)
+        }
+        for child in self.children[node].clone() {
+            self.node(child, avail.clone())
+        }
+    }
+
+    /** Numbers the values of the instructions of block `label`. Returns: The operations computed
+    after them */
+    fn block(&mut self, label: Label, mut avail: Avail) -> Avail {
+        // Reads of references and arrays since the last write
+        let mut reads: Vec<(Op, Var)> = vec![];
+        let mut insts = vec![];
+        for inst in &self.func.blocks[label].insts {
+            let op = normalize(inst.op.map_atoms(|atom| self.replace(atom)));
+            // Raising, the operation dominating this one would have raised first
+            let effects = effects::op(&op);
+            let computed = match effects {
+                Effects { writes: true, .. }
+                | Effects {
+                    allocates: true, ..
+                } => None,
+                Effects { reads: true, .. } => reads.iter().find(|(o, _)| *o == op),
+                _ => avail.iter().find(|(o, _)| *o == op),
+            };
+            if let Some((_, var)) = computed {
+                let var = Atom::Var(var.clone());
+                self.replaced.insert(inst.var.clone(), var);
+                continue;
+            }
+            match effects {
+                Effects { writes: true, .. } => reads.clear(),
+                Effects {
+                    allocates: true, ..
+                } => (),
+                Effects { reads: true, .. } => reads.push((op, inst.var.clone())),
+                _ => avail.push_back((op, inst.var.clone())),
+            }
+            insts.push(inst.clone())
+        }
+        self.insts[label] = insts;
+        avail
+    }
+}
+
+/** `op` with the operands of commutative operators in order, and `>` flipped to `<` */
+fn normalize(op: Op) -> Op {
+    use Binary::*;
+    match op {
+        Op::Binop(lhs, Gt, rhs) => Op::Binop(rhs, Lt, lhs),
+        Op::Binop(lhs, op @ (Add | Mul | Land | Lor | Lxor | Eq | Ne | And | Or), rhs)
+            if lhs.to_string() > rhs.to_string() =>
+        {
+            Op::Binop(rhs, op, lhs)
+        }
+        op => op,
+    }
+}
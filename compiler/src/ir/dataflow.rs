@@ -0,0 +1,211 @@
+/*! Dataflow analyses of functions in static single assignment form. An [`Analysis`] gives the
+facts at the boundary of a function, how facts of paths meeting at a block combine, and how each
+block transforms them, in its direction: forward from the entry, or backward from the returns.
+[`solve`] iterates to the fixed point the facts at the entry and exit of every block. Exceptions
+may be raised before any instruction of a block, so a handler is reached with the facts at the
+entry of the blocks it handles going forward, and going backward, what it needs holds all through
+them. [`liveness`] is the analysis of the registers live at each block, as needed by register
+allocation. */
+
+use crate::ir::anf::{Atom, Var};
+use crate::ir::ssa::{Func, Label};
+
+use im::HashSet;
+
+/// Which way facts flow
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Direction {
+    /// From the entry to the successors of blocks
+    Forward,
+    /// From the returns to the predecessors of blocks
+    Backward,
+}
+
+/// Dataflow analyses, by their facts about the program at points of a function
+pub trait Analysis {
+    type Fact: Clone + PartialEq;
+
+    fn direction(&self) -> Direction;
+
+    /** The facts at the entry of the function going forward, or at its returns going backward */
+    fn boundary(&self, func: &Func) -> Self::Fact;
+
+    /** The facts at blocks before any path to them is found, the identity of `meet` */
+    fn init(&self, func: &Func) -> Self::Fact;
+
+    /** The facts holding where paths with facts `a` and `b` meet */
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact;
+
+    /** The facts at the other end of block `label`, with `fact` at the end control comes from */
+    fn transfer(&self, func: &Func, label: Label, fact: &Self::Fact) -> Self::Fact;
+}
+
+/// The facts at the entry and exit of each block, by label
+#[derive(Debug, PartialEq, Clone)]
+pub struct Solution<F> {
+    pub entry: Vec<F>,
+    pub exit: Vec<F>,
+}
+
+/** Solves `analysis` on `func`. Returns: The facts at the fixed point */
+pub fn solve<A: Analysis>(analysis: &A, func: &Func) -> Solution<A::Fact> {
+    let n = func.blocks.len();
+    let init = analysis.init(func);
+    let mut solution = Solution {
+        entry: vec![init.clone(); n],
+        exit: vec![init; n],
+    };
+    let mut preds = vec![vec![]; n];
+    for (label, block) in func.blocks.iter().enumerate() {
+        for succ in block.successors() {
+            preds[succ].push(label)
+        }
+    }
+    let order = reverse_postorder(func);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        match analysis.direction() {
+            Direction::Forward => {
+                for &label in &order {
+                    let mut fact = match label {
+                        0 => analysis.boundary(func),
+                        _ => analysis.init(func),
+                    };
+                    for &pred in &preds[label] {
+                        let block = &func.blocks[pred];
+                        if block.term.successors().contains(&label) {
+                            fact = analysis.meet(&fact, &solution.exit[pred])
+                        }
+                        if block.handler == Some(label) {
+                            fact = analysis.meet(&fact, &solution.entry[pred])
+                        }
+                    }
+                    let exit = analysis.transfer(func, label, &fact);
+                    changed |= solution.entry[label] != fact || solution.exit[label] != exit;
+                    solution.entry[label] = fact;
+                    solution.exit[label] = exit
+                }
+            }
+            Direction::Backward => {
+                for &label in order.iter().rev() {
+                    let block = &func.blocks[label];
+                    let mut fact = match block.term.successors().is_empty() {
+                        true => analysis.boundary(func),
+                        false => analysis.init(func),
+                    };
+                    for succ in block.successors() {
+                        fact = analysis.meet(&fact, &solution.entry[succ])
+                    }
+                    let entry = analysis.transfer(func, label, &fact);
+                    changed |= solution.exit[label] != fact || solution.entry[label] != entry;
+                    solution.exit[label] = fact;
+                    solution.entry[label] = entry
+                }
+            }
+        }
+    }
+    solution
+}
+
+/** The blocks of `func` reachable from its entry, in reverse postorder */
+pub fn reverse_postorder(func: &Func) -> Vec<Label> {
+    let mut order = vec![];
+    let mut visited = vec![false; func.blocks.len()];
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+    while let Some((label, next)) = stack.pop() {
+        match func.blocks[label].successors().get(next) {
+            Some(&succ) => {
+                stack.push((label, next + 1));
+                if !visited[succ] {
+                    visited[succ] = true;
+                    stack.push((succ, 0))
+                }
+            }
+            None => order.push(label),
+        }
+    }
+    order.reverse();
+    order
+}
+
+/// Liveness of registers, those the rest of the function may use before they're assigned again
+pub struct Liveness;
+
+impl Analysis for Liveness {
+    type Fact = HashSet<Var>;
+
+    fn direction(&self) -> Direction {
+        Direction::Backward
+    }
+
+    fn boundary(&self, _: &Func) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn init(&self, _: &Func) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact {
+        a.clone().union(b.clone())
+    }
+
+    fn transfer(&self, func: &Func, label: Label, fact: &Self::Fact) -> Self::Fact {
+        let block = &func.blocks[label];
+        let mut live = fact.clone().union(vars(block.term.atoms()));
+        for inst in block.insts.iter().rev() {
+            live = live.without(&inst.var).union(vars(inst.op.atoms()))
+        }
+        live.relative_complement(block.params.iter().cloned().collect())
+    }
+}
+
+/** The registers of `func` live at the entry and exit of each block. Variables it doesn't assign
+are global */
+pub fn liveness(func: &Func) -> Solution<HashSet<Var>> {
+    let registers = registers(func);
+    let local = |live: HashSet<Var>| live.intersection(registers.clone());
+    let solution = solve(&Liveness, func);
+    Solution {
+        entry: solution.entry.into_iter().map(local).collect(),
+        exit: solution.exit.into_iter().map(local).collect(),
+    }
+}
+
+/** The registers live after each instruction of block `label`, the last before the terminator,
+from the solution of [`liveness`] */
+pub fn live_after(
+    func: &Func,
+    solution: &Solution<HashSet<Var>>,
+    label: Label,
+) -> Vec<HashSet<Var>> {
+    let registers = registers(func);
+    let block = &func.blocks[label];
+    let uses = |atoms: Vec<&Atom>| vars(atoms).intersection(registers.clone());
+    let mut live = solution.exit[label].clone().union(uses(block.term.atoms()));
+    let mut after = vec![];
+    for inst in block.insts.iter().rev() {
+        after.push(live.clone());
+        live = live.without(&inst.var).union(uses(inst.op.atoms()))
+    }
+    after.reverse();
+    after
+}
+
+/** The variables of `atoms` */
+fn vars(atoms: Vec<&Atom>) -> HashSet<Var> {
+    atoms.into_iter().filter_map(Atom::var).cloned().collect()
+}
+
+/** The registers `func` assigns */
+fn registers(func: &Func) -> HashSet<Var> {
+    let blocks = func.blocks.iter();
+    blocks
+        .flat_map(|block| {
+            let insts = block.insts.iter().map(|inst| inst.var.clone());
+            block.params.iter().cloned().chain(insts)
+        })
+        .collect()
+}
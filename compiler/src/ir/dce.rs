@@ -0,0 +1,247 @@
+/*! Dead code elimination of programs in A-normal form. Bindings of computations not used after
+them are removed when their [effects](crate::ir::effects) are unobservable, applications of
+functions without any included, and so are the functions of a `fix` not called from its body and
+the join points never jumped to. Branches are unreachable when they test a constant, or the constructor of
+a variable bound to a value of a known one. Compiling with an entry point, the declarations and
+top level functions it doesn't refer to are pruned too, the declarations left in the order of the
+source, [`Prog::order`](crate::ast::ast::Prog::order). */
+
+use crate::ast::ast::{grow, Constant};
+use crate::ir::anf::{Atom, Comp, Decl, Expr, Func, Prog, Var};
+use crate::ir::effects::{self, summaries, Summaries};
+use crate::ir::pass::{Analyses, Pass, Run};
+
+use im::{HashMap, HashSet};
+
+/// The pass eliminating dead code
+pub const PASS: Pass = Pass {
+    name: "dce",
+    run: Run::AnfAnalyzed(dce_analyzed),
+    requires: &[],
+    once: false,
+};
+
+/// The constructors of the values variables are bound to
+type Known = HashMap<Var, String>;
+
+/** Eliminates the dead code of the functions and declarations of `prog` */
+pub fn dce(prog: &Prog) -> Prog {
+    eliminate(prog, &summaries(prog))
+}
+
+fn dce_analyzed(prog: &Prog, analyses: &mut Analyses) -> Prog {
+    eliminate(prog, &analyses.effects(prog))
+}
+
+/** Eliminates the dead code of `prog`, where `summaries` are the effects of its functions */
+pub fn eliminate(prog: &Prog, summaries: &Summaries) -> Prog {
+    let funcs = prog.funcs.iter().map(|func| Func {
+        body: dce_expr(&func.body, &Known::new(), summaries).0,
+        ..func.clone()
+    });
+    let decls = prog.decls.iter().map(|decl| Decl {
+        body: dce_expr(&decl.body, &Known::new(), summaries).0,
+        ..decl.clone()
+    });
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs: funcs.collect(),
+        decls: decls.collect(),
+    }
+}
+
+/** `prog` with only the declaration `entry` and the declarations and functions it refers to,
+directly or not */
+pub fn prune(prog: &Prog, entry: &str) -> Prog {
+    let mut live = HashSet::new();
+    let mut todo = vec![entry.to_string()];
+    while let Some(global) = todo.pop() {
+        if live.contains(&global) {
+            continue;
+        }
+        let func = prog.funcs.iter().find(|func| func.name == global);
+        let uses = match (func, prog.decl(&global)) {
+            (Some(func), _) => func.body.free_vars(),
+            (None, Some(decl)) => decl.body.free_vars(),
+            // Builtins
+            (None, None) => continue,
+        };
+        todo.extend(uses);
+        live.insert(global);
+    }
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs: prog
+            .funcs
+            .iter()
+            .filter(|func| live.contains(&func.name))
+            .cloned()
+            .collect(),
+        decls: prog
+            .decls
+            .iter()
+            .filter(|decl| live.contains(&decl.id))
+            .cloned()
+            .collect(),
+    }
+}
+
+/** `expr` without its dead code, and the variables and join points used in it, where `known`
+are the constructors of the values of variables, and `summaries` the effects of functions */
+fn dce_expr(expr: &Expr, known: &Known, summaries: &Summaries) -> (Expr, HashSet<Var>) {
+    grow(|| {
+        let atoms = |atoms: &[&Atom]| -> HashSet<Var> {
+            atoms.iter().filter_map(|a| a.var()).cloned().collect()
+        };
+        match expr {
+            Expr::Let { var, comp, body } => {
+                let known = match comp {
+                    Comp::Data(ctor, _) => known.update(var.clone(), ctor.clone()),
+                    _ => known.clone(),
+                };
+                let (body, used) = dce_expr(body, &known, summaries);
+                if !used.contains(var) && effects::comp(comp, summaries).removable() {
+                    return (body, used);
+                }
+                let (comp, comp_used) = match comp {
+                    Comp::Handle { body, exn, handler } => {
+                        let (body, body_used) = dce_expr(body, &Known::new(), summaries);
+                        let (handler, handler_used) = dce_expr(handler, &Known::new(), summaries);
+                        let comp = Comp::Handle {
+                            body: Box::new(body),
+                            exn: exn.clone(),
+                            handler: Box::new(handler),
+                        };
+                        (comp, body_used.union(handler_used.without(exn)))
+                    }
+                    comp => (comp.clone(), comp.free_vars()),
+                };
+                let expr = Expr::Let {
+                    var: var.clone(),
+                    comp,
+                    body: Box::new(body),
+                };
+                (expr, comp_used.union(used.without(var)))
+            }
+            Expr::Fix { funcs, body } => {
+                let (body, mut used) = dce_expr(body, known, summaries);
+                let funcs: Vec<(Func, HashSet<Var>)> = funcs
+                    .iter()
+                    .map(|func| {
+                        let (body, used) = dce_expr(&func.body, &Known::new(), summaries);
+                        let used = func.params.iter().fold(used, |used, p| used.without(p));
+                        let func = Func {
+                            body,
+                            ..func.clone()
+                        };
+                        (func, used)
+                    })
+                    .collect();
+                // The functions called from the body, directly or not
+                let mut live = HashSet::new();
+                let mut todo: Vec<&Var> = funcs.iter().map(|(f, _)| &f.name).collect();
+                todo.retain(|name| used.contains(*name));
+                while let Some(name) = todo.pop() {
+                    if live.insert(name.clone()).is_none() {
+                        let (_, uses) = funcs.iter().find(|(f, _)| f.name == *name).unwrap();
+                        todo.extend(
+                            funcs
+                                .iter()
+                                .map(|(f, _)| &f.name)
+                                .filter(|n| uses.contains(*n)),
+                        )
+                    }
+                }
+                if live.is_empty() {
+                    return (body, used);
+                }
+                let funcs: Vec<(Func, HashSet<Var>)> = funcs
+                    .into_iter()
+                    .filter(|(f, _)| live.contains(&f.name))
+                    .collect();
+                for (_, uses) in &funcs {
+                    used.extend(uses.clone())
+                }
+                let funcs: Vec<Func> = funcs.into_iter().map(|(f, _)| f).collect();
+                let used = funcs.iter().fold(used, |used, f| used.without(&f.name));
+                let expr = Expr::Fix {
+                    funcs,
+                    body: Box::new(body),
+                };
+                (expr, used)
+            }
+            Expr::Join {
+                name,
+                params,
+                join,
+                body,
+            } => {
+                let (body, used) = dce_expr(body, known, summaries);
+                if !used.contains(name) {
+                    return (body, used);
+                }
+                let (join, join_used) = dce_expr(join, known, summaries);
+                let join_used = params.iter().fold(join_used, |used, p| used.without(p));
+                let expr = Expr::Join {
+                    name: name.clone(),
+                    params: params.clone(),
+                    join: Box::new(join),
+                    body: Box::new(body),
+                };
+                (expr, used.union(join_used))
+            }
+            Expr::Jump { name, args } => {
+                let used = atoms(&args.iter().collect::<Vec<_>>()).update(name.clone());
+                (expr.clone(), used)
+            }
+            Expr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => match cond {
+                Atom::Con(Constant::Boolean(b)) => {
+                    dce_expr(if *b { branch_t } else { branch_f }, known, summaries)
+                }
+                _ => {
+                    let (branch_t, used_t) = dce_expr(branch_t, known, summaries);
+                    let (branch_f, used_f) = dce_expr(branch_f, known, summaries);
+                    let expr = Expr::If {
+                        cond: cond.clone(),
+                        branch_t: Box::new(branch_t),
+                        branch_f: Box::new(branch_f),
+                    };
+                    (expr, atoms(&[cond]).union(used_t).union(used_f))
+                }
+            },
+            Expr::Case {
+                scrut,
+                arms,
+                default,
+            } => {
+                let ctor = scrut.var().and_then(|var| known.get(var));
+                let taken = ctor.and_then(|ctor| match arms.iter().find(|(c, _)| c == ctor) {
+                    Some((_, body)) => Some(body),
+                    None => default.as_deref(),
+                });
+                if let Some(body) = taken {
+                    return dce_expr(body, known, summaries);
+                }
+                let mut used = atoms(&[scrut]);
+                let mut dce = |body: &Expr| {
+                    let (body, body_used) = dce_expr(body, known, summaries);
+                    used.extend(body_used);
+                    body
+                };
+                let arms = arms.iter().map(|(c, e)| (c.clone(), dce(e))).collect();
+                let default = default.as_deref().map(|e| Box::new(dce(e)));
+                let expr = Expr::Case {
+                    scrut: scrut.clone(),
+                    arms,
+                    default,
+                };
+                (expr, used)
+            }
+            Expr::Raise { exn: val } | Expr::Ret { val } => (expr.clone(), atoms(&[val])),
+        }
+    })
+}
@@ -0,0 +1,118 @@
+/*! Dominators of the blocks of functions in static single assignment form. A block dominates
+another when every path from the entry to the other goes through it, and its immediate dominator is
+the closest of those dominating it but itself, its parent in the dominator tree. Handlers are
+successors of the blocks they handle. Computed by the iterative algorithm of Cooper, Harvey and
+Kennedy, on any graph by [`immediate`]. */
+
+use crate::ir::ssa::{Func, Label};
+
+/// The dominator tree of a function
+#[derive(Debug, PartialEq, Clone)]
+pub struct Dominators {
+    /// The immediate dominator of each block, `None` for the entry and the unreachable blocks
+    idoms: Vec<Option<Label>>,
+}
+
+/** The dominators of the blocks of `func` */
+pub fn dominators(func: &Func) -> Dominators {
+    let succs: Vec<Vec<Label>> = func.blocks.iter().map(|block| block.successors()).collect();
+    Dominators {
+        idoms: immediate(&succs),
+    }
+}
+
+impl Dominators {
+    /** The immediate dominator of block `label`, `None` for the entry and the unreachable blocks */
+    pub fn idom(&self, label: Label) -> Option<Label> {
+        self.idoms[label]
+    }
+
+    /** Whether block `label` is reachable from the entry */
+    pub fn reachable(&self, label: Label) -> bool {
+        label == 0 || self.idoms[label].is_some()
+    }
+
+    /** Whether block `a` dominates block `b`, both reachable */
+    pub fn dominates(&self, a: Label, b: Label) -> bool {
+        let mut label = Some(b);
+        while let Some(l) = label {
+            if l == a {
+                return true;
+            }
+            label = self.idoms[l]
+        }
+        false
+    }
+
+    /** The blocks `label` immediately dominates, its children in the dominator tree, in order */
+    pub fn children(&self, label: Label) -> Vec<Label> {
+        let labels = self.idoms.iter().enumerate();
+        labels
+            .filter(|(_, idom)| **idom == Some(label))
+            .map(|(l, _)| l)
+            .collect()
+    }
+}
+
+/** The immediate dominator of each node of the graph of successors `succs`, `None` for the first,
+its entry, and the nodes unreachable from it */
+pub fn immediate(succs: &[Vec<usize>]) -> Vec<Option<usize>> {
+    // Reverse postorder, by depth first search from the entry
+    let mut order = vec![];
+    let mut visited = vec![false; succs.len()];
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+    while let Some((node, next)) = stack.pop() {
+        match succs[node].get(next) {
+            Some(&succ) => {
+                stack.push((node, next + 1));
+                if !visited[succ] {
+                    visited[succ] = true;
+                    stack.push((succ, 0))
+                }
+            }
+            None => order.push(node),
+        }
+    }
+    order.reverse();
+    let mut position = vec![usize::MAX; succs.len()];
+    for (i, node) in order.iter().enumerate() {
+        position[*node] = i
+    }
+    let mut preds = vec![vec![]; succs.len()];
+    for (node, succs) in succs.iter().enumerate() {
+        for succ in succs {
+            preds[*succ].push(node)
+        }
+    }
+    let mut idoms: Vec<Option<usize>> = vec![None; succs.len()];
+    idoms[0] = Some(0);
+    let intersect = |idoms: &[Option<usize>], mut a: usize, mut b: usize| {
+        while a != b {
+            while position[a] > position[b] {
+                a = idoms[a].unwrap()
+            }
+            while position[b] > position[a] {
+                b = idoms[b].unwrap()
+            }
+        }
+        a
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in order.iter().skip(1) {
+            let mut processed = preds[node].iter().filter(|pred| idoms[**pred].is_some());
+            let first = *processed
+                .next()
+                .expect("nodes are reached from a predecessor");
+            let idom = processed.fold(first, |idom, pred| intersect(&idoms, *pred, idom));
+            if idoms[node] != Some(idom) {
+                idoms[node] = Some(idom);
+                changed = true
+            }
+        }
+    }
+    idoms[0] = None;
+    idoms
+}
@@ -0,0 +1,275 @@
+/*! Graphviz dumps of programs, to be rendered by `dot`: the syntax tree of every declaration, the
+control flow graph of every function in static single assignment form, and the call graph between
+them. Calls go through closures, so the callee is known when it's the closure of a function built
+in the caller, the caller itself through its environment, or a declaration of a function; the
+others go to an unknown node. */
+
+use crate::ast::ast::{self, grow, RawExpr};
+use crate::ir::ssa::{Func, Op, Prog, Term};
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// The graphs that can be dumped
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Graph {
+    /// The syntax trees of the declarations, as parsed
+    Ast,
+    /// The control flow graphs of the functions, once compiled
+    Cfg,
+    /// Which functions call which, once compiled
+    CallGraph,
+}
+
+impl FromStr for Graph {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot-ast" => Ok(Graph::Ast),
+            "dot-cfg" => Ok(Graph::Cfg),
+            "dot-callgraph" => Ok(Graph::CallGraph),
+            _ => Err(()),
+        }
+    }
+}
+
+/** `text` quoted as a Graphviz string, its lines left-justified */
+fn quoted(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped.replace('\n', "\\l"))
+}
+
+/** The label of the node of `expr`, without its subexpressions */
+fn label(expr: &RawExpr) -> String {
+    use RawExpr::*;
+    match expr {
+        Con { val } => val.to_string(),
+        Var { id } => id.clone(),
+        Let { pat, .. } => format!("let {pat}"),
+        Fix { funcs, .. } => {
+            let names: Vec<&str> = funcs.iter().map(|(f, ..)| f.name.as_str()).collect();
+            format!("fix {}", names.join(", "))
+        }
+        EApp { .. } => "app".to_string(),
+        TApp { arg, .. } => format!("app [{arg}]"),
+        Tuple { .. } => "tuple".to_string(),
+        Binop { op, .. } => op.to_string(),
+        Lambda { arg: (x, t), .. } => format!("λ {x}: {t}"),
+        Any { arg, kind, .. } => format!("Λ {arg}: {kind}"),
+        If { .. } => "if".to_string(),
+        Case { .. } => "case".to_string(),
+        Record { .. } => "record".to_string(),
+        Proj { field, .. } => format!(".{field}"),
+        Nth { index, .. } => format!(".{index}"),
+        Fold { typ, .. } => format!("fold [{typ}]"),
+        Unfold { .. } => "unfold".to_string(),
+        Ascribe { typ, .. } => format!(": {typ}"),
+        Convert { to, .. } => format!("convert {to}"),
+        Pack { witness, typ, .. } => format!("pack [{witness}] as {typ}"),
+        Unpack { tvar, var, .. } => format!("unpack [{tvar}] {var}"),
+        Open { .. } => "open".to_string(),
+        Ref { .. } => "ref".to_string(),
+        Deref { .. } => "!".to_string(),
+        Assign { .. } => ":=".to_string(),
+        Array { .. } => "array".to_string(),
+        Sub { .. } => "sub".to_string(),
+        Update { .. } => "update".to_string(),
+        AssertEq { .. } => "assertEq".to_string(),
+        Spawn { .. } => "spawn".to_string(),
+        Join { .. } => "join".to_string(),
+        Raise { typ, .. } => format!("raise [{typ}]"),
+        Handle { pat, .. } => format!("handle {pat}"),
+        Instance { class, typ } => format!("instance {class} {typ}"),
+    }
+}
+
+/** The labels of the edges to the subexpressions of `expr`, if they need any */
+fn roles(expr: &RawExpr) -> Vec<String> {
+    use RawExpr::*;
+    match expr {
+        Let { .. } | Unpack { .. } | Open { .. } => vec!["=".to_string(), "in".to_string()],
+        Fix { funcs, .. } => {
+            let funcs = funcs.iter().map(|(f, ..)| f.name.clone());
+            funcs.chain(["in".to_string()]).collect()
+        }
+        If { .. } => ["cond", "then", "else"].map(str::to_string).to_vec(),
+        Case { arms, .. } => {
+            let arms = arms.iter().map(|(pat, _)| pat.to_string());
+            std::iter::once(String::new()).chain(arms).collect()
+        }
+        Record { fields } => fields.iter().map(|(l, _)| l.to_string()).collect(),
+        Handle { pat, .. } => vec![String::new(), pat.to_string()],
+        _ => vec![],
+    }
+}
+
+/** Adds the nodes of the tree of `expr` to `out`, numbered from `next`, returning its own */
+fn tree(expr: &ast::Expr, next: &mut usize, out: &mut String) -> usize {
+    grow(|| {
+        let node = *next;
+        *next += 1;
+        writeln!(out, "    n{node} [label={}];", quoted(&label(&expr.expr))).unwrap();
+        let roles = roles(&expr.expr);
+        for (index, sub) in expr.expr.subexprs().into_iter().enumerate() {
+            let child = tree(sub, next, out);
+            match roles.get(index).filter(|role| !role.is_empty()) {
+                Some(role) => writeln!(out, "    n{node} -> n{child} [label={}];", quoted(role)),
+                None => writeln!(out, "    n{node} -> n{child};"),
+            }
+            .unwrap()
+        }
+        node
+    })
+}
+
+/** The syntax trees of the declarations of `prog`, in order */
+pub fn ast(prog: &ast::Prog) -> String {
+    let mut out = "digraph ast {\n    node [shape=box];\n".to_string();
+    let mut next = 0;
+    for id in &prog.order {
+        let Some(decl) = prog.declarations.get(id) else {
+            continue;
+        };
+        let root = next;
+        next += 1;
+        let header = format!("let {} : {}", decl.id, decl.sig);
+        writeln!(out, "    n{root} [label={}, style=bold];", quoted(&header)).unwrap();
+        let body = tree(&decl.body, &mut next, &mut out);
+        writeln!(out, "    n{root} -> n{body};").unwrap()
+    }
+    out.push_str("}\n");
+    out
+}
+
+/** The control flow graphs of the functions and declarations of `prog`, one cluster each */
+pub fn cfg(prog: &Prog) -> String {
+    let mut out = "digraph cfg {\n    node [shape=box, fontname=monospace];\n".to_string();
+    for (index, func) in prog.funcs.iter().chain(&prog.decls).enumerate() {
+        writeln!(out, "    subgraph cluster{index} {{").unwrap();
+        writeln!(out, "        label={};", quoted(&func.name)).unwrap();
+        for (label, block) in func.blocks.iter().enumerate() {
+            let mut text = format!("b{label}");
+            if !block.params.is_empty() {
+                write!(text, "({})", block.params.join(", ")).unwrap();
+            }
+            text.push_str(":\n");
+            for inst in &block.insts {
+                writeln!(text, "    {} = {}", inst.var, inst.op).unwrap();
+            }
+            writeln!(text, "    {}", block.term).unwrap();
+            writeln!(out, "        f{index}b{label} [label={}];", quoted(&text)).unwrap();
+        }
+        for (label, block) in func.blocks.iter().enumerate() {
+            let edges: Vec<(usize, String)> = match &block.term {
+                Term::Branch {
+                    branch_t, branch_f, ..
+                } => vec![(*branch_t, "true".into()), (*branch_f, "false".into())],
+                Term::Switch { arms, default, .. } => {
+                    let arms = arms.iter().map(|(ctor, l)| (*l, ctor.clone()));
+                    arms.chain(default.map(|l| (l, "_".into()))).collect()
+                }
+                Term::Call { ret, .. } => vec![(*ret, "ret".into())],
+                term => term
+                    .successors()
+                    .into_iter()
+                    .map(|l| (l, "".into()))
+                    .collect(),
+            };
+            for (next, role) in edges {
+                let edge = format!("        f{index}b{label} -> f{index}b{next}");
+                match &role[..] {
+                    "" => writeln!(out, "{edge};"),
+                    role => writeln!(out, "{edge} [label={}];", quoted(role)),
+                }
+                .unwrap()
+            }
+            if let Some(handler) = block.handler {
+                let edge = format!("        f{index}b{label} -> f{index}b{handler}");
+                writeln!(out, "{edge} [label=\"unwind\", style=dashed];").unwrap()
+            }
+        }
+        writeln!(out, "    }}").unwrap()
+    }
+    out.push_str("}\n");
+    out
+}
+
+/** The function whose closure `func` returns, if it's a declaration of a function */
+fn declared(func: &Func) -> Option<&str> {
+    let [block] = &func.blocks[..] else {
+        return None;
+    };
+    match (&block.insts[..], &block.term) {
+        ([inst], Term::Ret(val)) if val.var() == Some(&inst.var) => match &inst.op {
+            Op::Closure(name, _) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/** The functions `func` calls, None for those unknown. `declared` are the functions of
+declarations, `own` whether the environment of `func` is its own closure */
+fn callees<'a>(
+    func: &'a Func,
+    declared: &HashMap<&str, &'a str>,
+    own: bool,
+) -> BTreeSet<Option<&'a str>> {
+    let mut closures: HashMap<&str, &str> = declared.clone();
+    if let (true, Some(env)) = (own, func.params().first()) {
+        closures.insert(env, &func.name);
+    }
+    for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+        if let Op::Closure(name, _) = &inst.op {
+            closures.insert(&inst.var, name);
+        }
+    }
+    let calls = func.blocks.iter().filter_map(|block| match &block.term {
+        Term::Call { fun, .. } | Term::TailCall { fun, .. } => Some(fun),
+        _ => None,
+    });
+    calls
+        .map(|fun| {
+            fun.var()
+                .and_then(|var| closures.get(var.as_str()).copied())
+        })
+        .collect()
+}
+
+/** The call graph of the functions and declarations of `prog` */
+pub fn callgraph(prog: &Prog) -> String {
+    let mut out = "digraph callgraph {\n".to_string();
+    let declared: HashMap<&str, &str> = prog
+        .decls
+        .iter()
+        .filter_map(|decl| Some((decl.name.as_str(), declared(decl)?)))
+        .collect();
+    for func in &prog.funcs {
+        writeln!(out, "    {};", quoted(&func.name)).unwrap()
+    }
+    for decl in &prog.decls {
+        writeln!(out, "    {} [shape=box];", quoted(&decl.name)).unwrap()
+    }
+    let funcs = prog.funcs.iter().map(|func| (func, true));
+    let mut unknown = false;
+    for (func, own) in funcs.chain(prog.decls.iter().map(|decl| (decl, false))) {
+        for callee in callees(func, &declared, own) {
+            let caller = quoted(&func.name);
+            match callee {
+                Some(callee) => writeln!(out, "    {caller} -> {};", quoted(callee)),
+                None => {
+                    unknown = true;
+                    writeln!(out, "    {caller} -> unknown [style=dashed];")
+                }
+            }
+            .unwrap()
+        }
+    }
+    if unknown {
+        out.push_str("    unknown [label=\"?\", shape=plaintext];\n")
+    }
+    out.push_str("}\n");
+    out
+}
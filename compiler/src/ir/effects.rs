@@ -0,0 +1,307 @@
+/*! Effects of computations, what evaluating them may do but compute their value, conservatively:
+operations reading, writing or allocating mutable references and arrays, raising exceptions, or
+looping forever. In A-normal form, applications of known functions have the effects of their body,
+summarized for every function of a program, and recursive functions may loop. Applications of
+anything else, builtins doing I/O included, may do anything. Dead code elimination removes the
+computations whose value is unused and [removable](Effects::removable), common subexpression
+elimination and loop-invariant code motion merge and hoist [pure](Effects::pure) operations, and
+only declarations neither reading, writing nor allocating are partially evaluated. The summaries
+are an analysis of the [pass manager](crate::ir::pass::Analyses). */
+
+use crate::ast::ast::{grow, Binary};
+use crate::ir::anf::{Atom, Comp, Decl, Expr, Func, Prim, Prog, Var};
+use crate::ir::ssa::Op;
+
+use std::collections::{HashMap, HashSet};
+
+/// What evaluating a computation may do but compute its value
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Effects {
+    /// Reading mutable references or arrays
+    pub reads: bool,
+    /// Writing them, or anything else observable, like printing or starting tasks
+    pub writes: bool,
+    /// Allocating a mutable reference or array, a new one every time
+    pub allocates: bool,
+    pub raises: bool,
+    /// Looping forever
+    pub diverges: bool,
+}
+
+/// The effects of applying known functions, by their names
+pub type Summaries = HashMap<Var, Effects>;
+
+impl Effects {
+    /// No effect at all
+    pub const PURE: Effects = Effects {
+        reads: false,
+        writes: false,
+        allocates: false,
+        raises: false,
+        diverges: false,
+    };
+
+    /// Every effect, those of unknown computations
+    pub const ANY: Effects = Effects {
+        reads: true,
+        writes: true,
+        allocates: true,
+        raises: true,
+        diverges: true,
+    };
+
+    /** The effects of either `self` or `other` */
+    pub fn union(self, other: Effects) -> Effects {
+        Effects {
+            reads: self.reads || other.reads,
+            writes: self.writes || other.writes,
+            allocates: self.allocates || other.allocates,
+            raises: self.raises || other.raises,
+            diverges: self.diverges || other.diverges,
+        }
+    }
+
+    /** Whether the value is always the same, so that computing it once is enough */
+    pub fn pure(self) -> bool {
+        self == Effects::PURE
+    }
+
+    /** Whether not computing the value is unobservable, when it's unused */
+    pub fn removable(self) -> bool {
+        !self.writes && !self.raises && !self.diverges
+    }
+}
+
+/** The effects of `lhs op rhs` */
+fn binop(op: &Binary, rhs: &Atom) -> Effects {
+    match op {
+        Binary::Div | Binary::Mod => Effects {
+            raises: !matches!(rhs, Atom::Con(c) if c.as_int().is_some_and(|n| n != 0)),
+            ..Effects::PURE
+        },
+        _ => Effects::PURE,
+    }
+}
+
+/** The effects of the primitive operation `prim` on `args` */
+fn prim(prim: &Prim, args: &[Atom]) -> Effects {
+    let effects = |reads, writes, allocates, raises| Effects {
+        reads,
+        writes,
+        allocates,
+        raises,
+        diverges: false,
+    };
+    match prim {
+        Prim::Arith(op, _) => binop(op, &args[1]),
+        Prim::Convert(..) => Effects::PURE,
+        Prim::Ref => effects(false, false, true, false),
+        // Of a negative length
+        Prim::Array => effects(false, false, true, true),
+        Prim::Deref => effects(true, false, false, false),
+        Prim::Sub => effects(true, false, false, true),
+        Prim::Assign => effects(false, true, false, false),
+        Prim::Update | Prim::AssertEq => effects(false, true, false, true),
+        Prim::Spawn | Prim::Join => Effects::ANY,
+    }
+}
+
+/** The effects of the operation `op` in static single assignment form. Calls are terminators */
+pub fn op(op: &Op) -> Effects {
+    match op {
+        Op::Binop(_, op, rhs) => binop(op, rhs),
+        Op::Prim(p, args) => prim(p, args),
+        Op::Box(..) => Effects {
+            allocates: true,
+            ..Effects::PURE
+        },
+        _ => Effects::PURE,
+    }
+}
+
+/** The effects of `comp`, applying the functions of `summaries` */
+pub fn comp(comp: &Comp, summaries: &Summaries) -> Effects {
+    match comp {
+        Comp::Binop(_, op, rhs) => binop(op, rhs),
+        Comp::Prim(p, args) => prim(p, args),
+        Comp::App(fun, _) => match fun.var().and_then(|fun| summaries.get(fun)) {
+            Some(effects) => *effects,
+            None => Effects::ANY,
+        },
+        Comp::Handle { body, handler, .. } => {
+            let body = expr(body, summaries);
+            let caught = Effects {
+                raises: false,
+                ..body
+            };
+            caught.union(expr(handler, summaries))
+        }
+        _ => Effects::PURE,
+    }
+}
+
+/** The effects of evaluating `e`, applying the functions of `summaries`. Defining functions has
+none */
+pub fn expr(e: &Expr, summaries: &Summaries) -> Effects {
+    grow(|| match e {
+        Expr::Let { comp: c, body, .. } => comp(c, summaries).union(expr(body, summaries)),
+        Expr::Fix { body, .. } => expr(body, summaries),
+        Expr::Join { join, body, .. } => expr(join, summaries).union(expr(body, summaries)),
+        Expr::If {
+            branch_t, branch_f, ..
+        } => expr(branch_t, summaries).union(expr(branch_f, summaries)),
+        Expr::Case { arms, default, .. } => {
+            let arms = arms.iter().map(|(_, arm)| arm).chain(default.as_deref());
+            arms.fold(Effects::PURE, |effects, arm| {
+                effects.union(expr(arm, summaries))
+            })
+        }
+        Expr::Raise { .. } => Effects {
+            raises: true,
+            ..Effects::PURE
+        },
+        Expr::Jump { .. } | Expr::Ret { .. } => Effects::PURE,
+    })
+}
+
+/** Adds the functions `e` defines to `funcs` */
+fn defined<'a>(e: &'a Expr, funcs: &mut Vec<&'a Func>) {
+    grow(|| match e {
+        Expr::Let { comp, body, .. } => {
+            if let Comp::Handle { body, handler, .. } = comp {
+                defined(body, funcs);
+                defined(handler, funcs)
+            }
+            defined(body, funcs)
+        }
+        Expr::Fix { funcs: fix, body } => {
+            for func in fix {
+                funcs.push(func);
+                defined(&func.body, funcs)
+            }
+            defined(body, funcs)
+        }
+        Expr::Join { join, body, .. } => {
+            defined(join, funcs);
+            defined(body, funcs)
+        }
+        Expr::If {
+            branch_t, branch_f, ..
+        } => {
+            defined(branch_t, funcs);
+            defined(branch_f, funcs)
+        }
+        Expr::Case { arms, default, .. } => {
+            for arm in arms.iter().map(|(_, arm)| arm).chain(default.as_deref()) {
+                defined(arm, funcs)
+            }
+        }
+        Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => (),
+    })
+}
+
+/** The functions `e` applies */
+fn applied(e: &Expr) -> HashSet<&Var> {
+    let mut applied = HashSet::new();
+    let mut todo = vec![e];
+    while let Some(e) = todo.pop() {
+        match e {
+            Expr::Let { comp, body, .. } => {
+                match comp {
+                    Comp::App(Atom::Var(fun), _) => {
+                        applied.insert(fun);
+                    }
+                    Comp::Handle { body, handler, .. } => todo.extend([&**body, &**handler]),
+                    _ => (),
+                }
+                todo.push(body)
+            }
+            Expr::Fix { body, .. } => todo.push(body),
+            Expr::Join { join, body, .. } => todo.extend([&**join, &**body]),
+            Expr::If {
+                branch_t, branch_f, ..
+            } => todo.extend([&**branch_t, &**branch_f]),
+            Expr::Case { arms, default, .. } => {
+                todo.extend(arms.iter().map(|(_, arm)| arm).chain(default.as_deref()))
+            }
+            Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => (),
+        }
+    }
+    applied
+}
+
+/** The function declaration `decl` is, if it's like `fix f(x) = e in f` */
+fn declared(decl: &Decl) -> Option<&Var> {
+    let Expr::Fix { funcs, body } = &decl.body else {
+        return None;
+    };
+    match (&funcs[..], &**body) {
+        ([func], Expr::Ret { val }) if val.var() == Some(&func.name) => Some(&func.name),
+        _ => None,
+    }
+}
+
+/** The effects of applying each function of `prog`, lifted or defined by `fix`, and each
+declaration of a function, like `fix f(x) = e in f` */
+pub fn summaries(prog: &Prog) -> Summaries {
+    let mut funcs: Vec<&Func> = prog.funcs.iter().collect();
+    for decl in &prog.decls {
+        defined(&decl.body, &mut funcs)
+    }
+    for func in prog.funcs.iter() {
+        defined(&func.body, &mut funcs)
+    }
+    let declared: HashMap<&Var, &Var> = prog
+        .decls
+        .iter()
+        .filter_map(|decl| Some((&decl.id, declared(decl)?)))
+        .collect();
+    let calls: HashMap<&Var, HashSet<&Var>> = funcs
+        .iter()
+        .map(|func| {
+            let applied = applied(&func.body).into_iter();
+            let applied = applied.map(|fun| declared.get(fun).copied().unwrap_or(fun));
+            (&func.name, applied.collect())
+        })
+        .collect();
+    // The functions calling themselves, directly or not, may loop forever
+    let recursive = |name: &Var| {
+        let mut seen = HashSet::new();
+        let mut todo: Vec<&Var> = calls[name].iter().copied().collect();
+        while let Some(callee) = todo.pop() {
+            if callee == name {
+                return true;
+            }
+            if seen.insert(callee) {
+                todo.extend(calls.get(callee).into_iter().flatten().copied())
+            }
+        }
+        false
+    };
+    let recursive: HashSet<&Var> = funcs
+        .iter()
+        .map(|func| &func.name)
+        .filter(|name| recursive(name))
+        .collect();
+    let mut summaries = Summaries::new();
+    let names = funcs.iter().map(|func| &func.name);
+    for name in names.chain(declared.keys().copied()) {
+        summaries.insert(name.clone(), Effects::PURE);
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for func in &funcs {
+            let effects = Effects {
+                diverges: recursive.contains(&func.name),
+                ..Effects::PURE
+            };
+            let effects = effects.union(expr(&func.body, &summaries));
+            changed |= summaries.insert(func.name.clone(), effects) != Some(effects)
+        }
+        for (id, func) in &declared {
+            summaries.insert(id.to_string(), summaries[*func]);
+        }
+    }
+    summaries
+}
@@ -0,0 +1,159 @@
+/*! Scalar replacement of tuples of programs in static single assignment form. A tuple that never
+escapes, only ever projected or passed to parameters of blocks only passed such tuples, is
+flattened: projections are replaced by its components, and parameters by one per component, so
+that it's never allocated. Values of several components joined from branches, like those of
+functions returning tuples once inlined, are passed in registers then. Tuples passed to functions,
+returned, or stored in others still are allocated. */
+
+use crate::ir::anf::{Atom, Fresh, Var};
+use crate::ir::copy::resolve;
+use crate::ir::pass::{Pass, Run};
+use crate::ir::ssa::{Block, Func, Inst, Op, Prog, Term};
+
+use std::collections::{HashMap, HashSet};
+
+/// The pass flattening tuples
+pub const PASS: Pass = Pass {
+    name: "flatten",
+    run: Run::Ssa(flatten),
+    requires: &[],
+    once: false,
+};
+
+/** Flattens the tuples not escaping the functions and declarations of `prog` */
+pub fn flatten(prog: &Prog) -> Prog {
+    let mut fresh = prog.fresh();
+    let mut flatten_all = |funcs: &[Func]| -> Vec<Func> {
+        let flatten = |func: &Func| flatten_func(func, &mut fresh);
+        funcs.iter().map(flatten).collect()
+    };
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs: flatten_all(&prog.funcs),
+        decls: flatten_all(&prog.decls),
+    }
+}
+
+/** The registers of `func` holding tuples that don't escape it, by their arities */
+fn flattened(func: &Func) -> HashMap<Var, usize> {
+    let jumps = func.jumps();
+    let mut arities = HashMap::new();
+    for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+        if let Op::Tuple(elems) = &inst.op {
+            arities.insert(inst.var.clone(), elems.len());
+        }
+    }
+    // Parameters take the arity of the tuples passed
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (block, jumps) in func.blocks.iter().zip(&jumps) {
+            let Some(jumps) = jumps else { continue };
+            for (index, param) in block.params.iter().enumerate() {
+                let passed = jumps.iter().filter_map(|args| args[index].var());
+                let arity = passed.filter_map(|var| arities.get(var)).next();
+                if let (Some(&arity), false) = (arity, arities.contains_key(param)) {
+                    arities.insert(param.clone(), arity);
+                    changed = true
+                }
+            }
+        }
+    }
+    let same = |arities: &HashMap<Var, usize>, atom: &Atom, param: &Var| match atom.var() {
+        Some(var) => arities.contains_key(var) && arities.get(var) == arities.get(param),
+        None => false,
+    };
+    // Removing escaping tuples may make those passed with them escape
+    let mut changed = true;
+    while changed {
+        let mut escaping = HashSet::new();
+        for (block, jumped) in func.blocks.iter().zip(&jumps) {
+            for inst in &block.insts {
+                match &inst.op {
+                    Op::Nth(Atom::Var(var), index) if arities.get(var) > Some(index) => (),
+                    op => escaping.extend(op.atoms().into_iter().filter_map(Atom::var)),
+                }
+            }
+            match &block.term {
+                Term::Jump(next, args) if jumps[*next].is_some() => {
+                    let params = &func.blocks[*next].params;
+                    let passed = args.iter().zip(params);
+                    let escaping_args = passed.filter(|(arg, param)| !same(&arities, arg, param));
+                    escaping.extend(escaping_args.filter_map(|(arg, _)| arg.var()))
+                }
+                term => escaping.extend(term.atoms().into_iter().filter_map(Atom::var)),
+            }
+            for (index, param) in block.params.iter().enumerate() {
+                let passed = |args: &&[Atom]| same(&arities, &args[index], param);
+                if jumped
+                    .as_ref()
+                    .is_none_or(|jumped| !jumped.iter().all(passed))
+                {
+                    escaping.insert(param);
+                }
+            }
+        }
+        let before = arities.len();
+        arities.retain(|var, _| !escaping.contains(var));
+        changed = arities.len() < before
+    }
+    arities
+}
+
+fn flatten_func(func: &Func, fresh: &mut Fresh) -> Func {
+    let arities = flattened(func);
+    // The components of the tuples flattened, those of parameters as many new ones
+    let mut components: HashMap<&Var, Vec<Atom>> = HashMap::new();
+    for block in &func.blocks {
+        for param in block.params.iter().filter(|p| arities.contains_key(*p)) {
+            let params = (0..arities[param]).map(|_| Atom::Var(fresh.var(param)));
+            components.insert(param, params.collect());
+        }
+        for inst in &block.insts {
+            if let (Op::Tuple(elems), true) = (&inst.op, arities.contains_key(&inst.var)) {
+                components.insert(&inst.var, elems.clone());
+            }
+        }
+    }
+    let mut projected = HashMap::new();
+    for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+        if let Op::Nth(Atom::Var(var), index) = &inst.op {
+            if let Some(components) = components.get(var) {
+                projected.insert(inst.var.clone(), components[*index].clone());
+            }
+        }
+    }
+    let replace = |atom: &Atom| resolve(&projected, atom);
+    let expand = |atom: &Atom| match atom.var().and_then(|var| components.get(var)) {
+        Some(components) => components.iter().map(replace).collect(),
+        None => vec![replace(atom)],
+    };
+    let blocks = func.blocks.iter().map(|block| {
+        let params = block.params.iter().flat_map(|param| {
+            let param = Atom::Var(param.clone());
+            expand(&param).into_iter().filter_map(|p| p.var().cloned())
+        });
+        let insts = block
+            .insts
+            .iter()
+            .filter(|inst| !arities.contains_key(&inst.var) && !projected.contains_key(&inst.var));
+        let insts = insts.map(|inst| Inst {
+            var: inst.var.clone(),
+            op: inst.op.map_atoms(replace),
+        });
+        let term = match &block.term {
+            Term::Jump(next, args) => Term::Jump(*next, args.iter().flat_map(expand).collect()),
+            term => term.map_atoms(replace),
+        };
+        Block {
+            params: params.collect(),
+            insts: insts.collect(),
+            term,
+            handler: block.handler,
+        }
+    });
+    Func {
+        name: func.name.clone(),
+        blocks: blocks.collect(),
+    }
+}
@@ -0,0 +1,185 @@
+/*! Constant folding and propagation of programs in A-normal form. Variables bound to constants
+are replaced by them, operations on constants are evaluated like the interpreter would, and so are
+the identities of operations on one constant, like `x + 0` or `x & true`. An `if` on a constant
+takes its branch, and a join point jumped to right away is evaluated in place. Division and modulo by zero are left to raise `Div` when evaluated. */
+
+use crate::ast::ast::{grow, Binary, Constant};
+use crate::ir::anf::{Atom, Comp, Decl, Expr, Func, Prim, Prog, Var};
+use crate::ir::pass::{Pass, Run};
+
+use im::HashMap;
+
+/// The pass folding constants
+pub const PASS: Pass = Pass {
+    name: "fold",
+    run: Run::Anf(fold),
+    requires: &[],
+    once: false,
+};
+
+/// The constants variables are bound to
+type Consts = HashMap<Var, Constant>;
+
+/** Folds the constants of `prog` */
+pub fn fold(prog: &Prog) -> Prog {
+    let funcs = prog.funcs.iter().map(|func| Func {
+        body: fold_expr(&func.body, &Consts::new()),
+        ..func.clone()
+    });
+    let decls = prog.decls.iter().map(|decl| Decl {
+        body: fold_expr(&decl.body, &Consts::new()),
+        ..decl.clone()
+    });
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs: funcs.collect(),
+        decls: decls.collect(),
+    }
+}
+
+/** `atom`, the constant of the variable if it's bound to one */
+fn subst(atom: &Atom, consts: &Consts) -> Atom {
+    match atom.var().and_then(|var| consts.get(var)) {
+        Some(con) => Atom::Con(con.clone()),
+        None => atom.clone(),
+    }
+}
+
+fn fold_expr(expr: &Expr, consts: &Consts) -> Expr {
+    grow(|| {
+        let fold = |expr: &Expr| Box::new(fold_expr(expr, consts));
+        match expr {
+            Expr::Let { var, comp, body } => {
+                let comp = match comp {
+                    Comp::Handle { body, exn, handler } => Comp::Handle {
+                        body: fold(body),
+                        exn: exn.clone(),
+                        handler: fold(handler),
+                    },
+                    comp => fold_comp(comp.map_atoms(|a| subst(a, consts))),
+                };
+                match comp {
+                    Comp::Atom(Atom::Con(con)) => fold_expr(body, &consts.update(var.clone(), con)),
+                    comp => Expr::Let {
+                        var: var.clone(),
+                        comp,
+                        body: fold(body),
+                    },
+                }
+            }
+            Expr::Fix { funcs, body } => Expr::Fix {
+                funcs: funcs
+                    .iter()
+                    .map(|f| Func {
+                        body: *fold(&f.body),
+                        ..f.clone()
+                    })
+                    .collect(),
+                body: fold(body),
+            },
+            Expr::Join {
+                name,
+                params,
+                join,
+                body,
+            } => match *fold(body) {
+                // The branches of an `if` on a constant but the one taken are gone
+                Expr::Jump { name: k, args } if k == *name => {
+                    let binds = params.iter().zip(args).rev();
+                    let join = binds.fold(*join.clone(), |body, (param, arg)| Expr::Let {
+                        var: param.clone(),
+                        comp: Comp::Atom(arg),
+                        body: Box::new(body),
+                    });
+                    fold_expr(&join, consts)
+                }
+                body => Expr::Join {
+                    name: name.clone(),
+                    params: params.clone(),
+                    join: fold(join),
+                    body: Box::new(body),
+                },
+            },
+            Expr::Jump { name, args } => Expr::Jump {
+                name: name.clone(),
+                args: args.iter().map(|a| subst(a, consts)).collect(),
+            },
+            Expr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => match subst(cond, consts) {
+                Atom::Con(Constant::Boolean(true)) => fold_expr(branch_t, consts),
+                Atom::Con(Constant::Boolean(false)) => fold_expr(branch_f, consts),
+                cond => Expr::If {
+                    cond,
+                    branch_t: fold(branch_t),
+                    branch_f: fold(branch_f),
+                },
+            },
+            Expr::Case {
+                scrut,
+                arms,
+                default,
+            } => Expr::Case {
+                scrut: subst(scrut, consts),
+                arms: arms.iter().map(|(c, e)| (c.clone(), *fold(e))).collect(),
+                default: default.as_deref().map(fold),
+            },
+            Expr::Raise { exn } => Expr::Raise {
+                exn: subst(exn, consts),
+            },
+            Expr::Ret { val } => Expr::Ret {
+                val: subst(val, consts),
+            },
+        }
+    })
+}
+
+/** `comp`, its atoms substituted already, evaluated as far as it can be */
+fn fold_comp(comp: Comp) -> Comp {
+    let folded = match &comp {
+        Comp::Binop(lhs, op, rhs) => binop(lhs, op, rhs),
+        Comp::Prim(Prim::Arith(op, _), args) => binop(&args[0], op, &args[1]),
+        Comp::Prim(Prim::Convert(_, to), args) => match &args[..] {
+            [Atom::Con(con)] => con.as_int().map(|n| to.constant(to.wrap(n))).map(Atom::Con),
+            _ => None,
+        },
+        _ => None,
+    };
+    folded.map(Comp::Atom).unwrap_or(comp)
+}
+
+/** The atom `lhs op rhs` evaluates to, if it's known */
+fn binop(lhs: &Atom, op: &Binary, rhs: &Atom) -> Option<Atom> {
+    use Binary::*;
+    use Constant::*;
+    let int = |atom: &Atom, n: i64| matches!(atom, Atom::Con(c) if c.as_int() == Some(n));
+    let bool = |atom: &Atom, b: bool| *atom == Atom::Con(Boolean(b));
+    if let (Atom::Con(l), Atom::Con(r)) = (lhs, rhs) {
+        let folded = match (op, l, r) {
+            (Eq | Ne, _, _) => Some(Boolean((l == r) == (*op == Eq))),
+            (And, Boolean(l), Boolean(r)) => Some(Boolean(*l & *r)),
+            (Or, Boolean(l), Boolean(r)) => Some(Boolean(*l | *r)),
+            (_, Integer(l), Integer(r)) => op.apply_int(*l, *r),
+            (_, Sized(l, w), Sized(r, _)) => op.apply_sized(*l, *r, *w),
+            _ => None,
+        };
+        return folded.map(Atom::Con);
+    }
+    match op {
+        Add | Sub | Lor | Lxor | Shl | Shr if int(rhs, 0) => Some(lhs.clone()),
+        Add | Lor | Lxor if int(lhs, 0) => Some(rhs.clone()),
+        Mul | Div if int(rhs, 1) => Some(lhs.clone()),
+        Mul if int(lhs, 1) => Some(rhs.clone()),
+        Mul | Land if int(rhs, 0) => Some(rhs.clone()),
+        Mul | Land if int(lhs, 0) => Some(lhs.clone()),
+        And if bool(rhs, true) => Some(lhs.clone()),
+        And if bool(lhs, true) => Some(rhs.clone()),
+        Or if bool(rhs, false) => Some(lhs.clone()),
+        Or if bool(lhs, false) => Some(rhs.clone()),
+        And | Or if bool(rhs, *op == Or) => Some(rhs.clone()),
+        And | Or if bool(lhs, *op == Or) => Some(lhs.clone()),
+        _ => None,
+    }
+}
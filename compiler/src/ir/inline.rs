@@ -0,0 +1,479 @@
+/*! Inlining of functions in A-normal form. Applications of functions known at the call, bound by a
+`fix` of their own or declared, are replaced by the body of the function named apart, if it isn't
+recursive and is small, within [`BUDGET`], applied once in the program, or declared `@[inline]`.
+Curried functions make for many tiny functions, each applied once to get the next, which become
+straight line code. The body returns to the rest of the caller in place when it returns from one
+tail position, and through a join point otherwise. */
+
+use crate::ast::ast::grow;
+use crate::ir::anf::{Atom, Comp, Decl, Expr, Fresh, Func, Prog, Var};
+use crate::ir::pass::{Pass, Run};
+
+use im::HashMap;
+
+/// The pass inlining functions
+pub const PASS: Pass = Pass {
+    name: "inline",
+    run: Run::Anf(inline),
+    requires: &[],
+    once: false,
+};
+
+/// The size of the largest functions inlined however many times they're applied
+pub const BUDGET: usize = 16;
+
+/// The functions inlined where they're applied, by the variables bound to them
+type Known = HashMap<Var, Func>;
+
+/// Renaming of variables and join points
+type Renaming = HashMap<Var, Atom>;
+
+struct Inlining {
+    fresh: Fresh,
+    /// The number of times each variable is used in the program
+    uses: std::collections::HashMap<Var, usize>,
+}
+
+/** Inlines the functions of `prog` where they're applied */
+pub fn inline(prog: &Prog) -> Prog {
+    let mut inlining = Inlining {
+        fresh: Fresh::after(prog),
+        uses: std::collections::HashMap::new(),
+    };
+    let bodies = prog.funcs.iter().map(|f| &f.body);
+    for body in bodies.chain(prog.decls.iter().map(|d| &d.body)) {
+        count(body, &mut inlining.uses)
+    }
+    let mut known = Known::new();
+    let funcs = prog.funcs.iter().map(|func| Func {
+        body: inlining.expr(&func.body, &known),
+        ..func.clone()
+    });
+    let funcs = funcs.collect();
+    let mut decls = vec![];
+    for decl in &prog.decls {
+        let decl = Decl {
+            body: inlining.expr(&decl.body, &known),
+            ..decl.clone()
+        };
+        if let Expr::Fix { funcs, body } = &decl.body {
+            let inline = decl.attrs.iter().any(|attr| attr == "inline");
+            match &funcs[..] {
+                [func]
+                    if **body == ret(&func.name) && inlining.inlinable(&decl.id, func, inline) =>
+                {
+                    known.insert(decl.id.clone(), func.clone());
+                }
+                _ => (),
+            }
+        }
+        decls.push(decl)
+    }
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs,
+        decls,
+    }
+}
+
+impl Inlining {
+    /** Whether `func`, bound to `var`, is inlined where it's applied, `inline` if it's declared
+    so */
+    fn inlinable(&self, var: &Var, func: &Func, inline: bool) -> bool {
+        let once = self.uses.get(var) == Some(&1);
+        !func.body.free_vars().contains(&func.name)
+            && (inline || once || size(&func.body) <= BUDGET)
+    }
+
+    fn expr(&mut self, expr: &Expr, known: &Known) -> Expr {
+        grow(|| {
+            match expr {
+                Expr::Let { var, comp, body } => {
+                    if let Comp::App(Atom::Var(fun), args) = comp {
+                        match known.get(fun) {
+                            Some(func) if func.params.len() == args.len() => {
+                                let inlined = self.apply(func, args, var, body);
+                                return self.expr(&inlined, known);
+                            }
+                            _ => (),
+                        }
+                    }
+                    let known = match comp {
+                        // Copies of functions
+                        Comp::Atom(Atom::Var(fun)) if known.contains_key(fun) => {
+                            known.update(var.clone(), known[fun].clone())
+                        }
+                        _ => known.clone(),
+                    };
+                    let comp = match comp {
+                        Comp::Handle { body, exn, handler } => Comp::Handle {
+                            body: Box::new(self.expr(body, &known)),
+                            exn: exn.clone(),
+                            handler: Box::new(self.expr(handler, &known)),
+                        },
+                        comp => comp.clone(),
+                    };
+                    Expr::Let {
+                        var: var.clone(),
+                        comp,
+                        body: Box::new(self.expr(body, &known)),
+                    }
+                }
+                Expr::Fix { funcs, body } => {
+                    let funcs: Vec<Func> = funcs
+                        .iter()
+                        .map(|func| Func {
+                            body: self.expr(&func.body, known),
+                            ..func.clone()
+                        })
+                        .collect();
+                    let known = match &funcs[..] {
+                        [func] if self.inlinable(&func.name, func, false) => {
+                            known.update(func.name.clone(), func.clone())
+                        }
+                        _ => known.clone(),
+                    };
+                    Expr::Fix {
+                        body: Box::new(self.expr(body, &known)),
+                        funcs,
+                    }
+                }
+                Expr::Join {
+                    name,
+                    params,
+                    join,
+                    body,
+                } => Expr::Join {
+                    name: name.clone(),
+                    params: params.clone(),
+                    join: Box::new(self.expr(join, known)),
+                    body: Box::new(self.expr(body, known)),
+                },
+                Expr::If {
+                    cond,
+                    branch_t,
+                    branch_f,
+                } => Expr::If {
+                    cond: cond.clone(),
+                    branch_t: Box::new(self.expr(branch_t, known)),
+                    branch_f: Box::new(self.expr(branch_f, known)),
+                },
+                Expr::Case {
+                    scrut,
+                    arms,
+                    default,
+                } => Expr::Case {
+                    scrut: scrut.clone(),
+                    arms: arms
+                        .iter()
+                        .map(|(c, e)| (c.clone(), self.expr(e, known)))
+                        .collect(),
+                    default: default.as_deref().map(|e| Box::new(self.expr(e, known))),
+                },
+                Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => expr.clone(),
+            }
+        })
+    }
+
+    /** `let var = func(args) in body` with the body of `func` in place of the application */
+    fn apply(&mut self, func: &Func, args: &[Atom], var: &Var, body: &Expr) -> Expr {
+        let renaming = func.params.iter().cloned().zip(args.iter().cloned());
+        let inlined = self.rename(&func.body, &renaming.collect());
+        if *body == ret(var) {
+            return inlined;
+        }
+        let bind = |val: Atom| Expr::Let {
+            var: var.clone(),
+            comp: Comp::Atom(val),
+            body: Box::new(body.clone()),
+        };
+        if returns(&inlined) <= 1 {
+            return map_rets(&inlined, &mut |val| bind(val));
+        }
+        let name = self.fresh.var("ret");
+        let jump = |val: Atom| Expr::Jump {
+            name: name.clone(),
+            args: vec![val],
+        };
+        Expr::Join {
+            name: name.clone(),
+            params: vec![var.clone()],
+            join: Box::new(body.clone()),
+            body: Box::new(map_rets(&inlined, &mut |val| jump(val))),
+        }
+    }
+
+    /** `expr` with its variables renamed by `renaming`, and its binders named apart */
+    fn rename(&mut self, expr: &Expr, renaming: &Renaming) -> Expr {
+        grow(|| {
+            let atom = |atom: &Atom| match atom.var().and_then(|var| renaming.get(var)) {
+                Some(renamed) => renamed.clone(),
+                None => atom.clone(),
+            };
+            let var = |var: &Var| match renaming.get(var) {
+                Some(Atom::Var(renamed)) => renamed.clone(),
+                _ => var.clone(),
+            };
+            match expr {
+                Expr::Let {
+                    var: bound,
+                    comp,
+                    body,
+                } => {
+                    let comp = match comp {
+                        Comp::Handle { body, exn, handler } => {
+                            let fresh = self.fresh.var(exn);
+                            let inner = renaming.update(exn.clone(), Atom::Var(fresh.clone()));
+                            Comp::Handle {
+                                body: Box::new(self.rename(body, renaming)),
+                                exn: fresh,
+                                handler: Box::new(self.rename(handler, &inner)),
+                            }
+                        }
+                        comp => comp.map_atoms(atom),
+                    };
+                    let fresh = self.fresh.var(bound);
+                    let renaming = renaming.update(bound.clone(), Atom::Var(fresh.clone()));
+                    Expr::Let {
+                        var: fresh,
+                        comp,
+                        body: Box::new(self.rename(body, &renaming)),
+                    }
+                }
+                Expr::Fix { funcs, body } => {
+                    let mut renaming = renaming.clone();
+                    for func in funcs {
+                        let fresh = self.fresh.var(&func.name);
+                        renaming.insert(func.name.clone(), Atom::Var(fresh));
+                    }
+                    let funcs = funcs
+                        .iter()
+                        .map(|func| {
+                            let mut inner = renaming.clone();
+                            let params = func.params.iter().map(|param| {
+                                let fresh = self.fresh.var(param);
+                                inner.insert(param.clone(), Atom::Var(fresh.clone()));
+                                fresh
+                            });
+                            let params = params.collect();
+                            Func {
+                                name: var_of(&renaming[&func.name]),
+                                params,
+                                body: self.rename(&func.body, &inner),
+                            }
+                        })
+                        .collect();
+                    Expr::Fix {
+                        funcs,
+                        body: Box::new(self.rename(body, &renaming)),
+                    }
+                }
+                Expr::Join {
+                    name,
+                    params,
+                    join,
+                    body,
+                } => {
+                    let fresh = self.fresh.var(name);
+                    let renaming = renaming.update(name.clone(), Atom::Var(fresh.clone()));
+                    let mut inner = renaming.clone();
+                    let params = params.iter().map(|param| {
+                        let fresh = self.fresh.var(param);
+                        inner.insert(param.clone(), Atom::Var(fresh.clone()));
+                        fresh
+                    });
+                    Expr::Join {
+                        name: fresh,
+                        params: params.collect(),
+                        join: Box::new(self.rename(join, &inner)),
+                        body: Box::new(self.rename(body, &renaming)),
+                    }
+                }
+                Expr::Jump { name, args } => Expr::Jump {
+                    name: var(name),
+                    args: args.iter().map(atom).collect(),
+                },
+                Expr::If {
+                    cond,
+                    branch_t,
+                    branch_f,
+                } => Expr::If {
+                    cond: atom(cond),
+                    branch_t: Box::new(self.rename(branch_t, renaming)),
+                    branch_f: Box::new(self.rename(branch_f, renaming)),
+                },
+                Expr::Case {
+                    scrut,
+                    arms,
+                    default,
+                } => Expr::Case {
+                    scrut: atom(scrut),
+                    arms: arms
+                        .iter()
+                        .map(|(c, e)| (c.clone(), self.rename(e, renaming)))
+                        .collect(),
+                    default: default
+                        .as_deref()
+                        .map(|e| Box::new(self.rename(e, renaming))),
+                },
+                Expr::Raise { exn } => Expr::Raise { exn: atom(exn) },
+                Expr::Ret { val } => Expr::Ret { val: atom(val) },
+            }
+        })
+    }
+}
+
+fn ret(var: &Var) -> Expr {
+    Expr::Ret {
+        val: Atom::Var(var.clone()),
+    }
+}
+
+fn var_of(atom: &Atom) -> Var {
+    atom.var()
+        .expect("binders are renamed to variables")
+        .clone()
+}
+
+/** The size of `expr`, the number of its computations and terminators */
+pub fn size(expr: &Expr) -> usize {
+    grow(|| match expr {
+        Expr::Let { comp, body, .. } => {
+            let comp = match comp {
+                Comp::Handle { body, handler, .. } => size(body) + size(handler),
+                _ => 1,
+            };
+            comp + size(body)
+        }
+        Expr::Fix { funcs, body } => {
+            funcs.iter().map(|f| size(&f.body)).sum::<usize>() + size(body)
+        }
+        Expr::Join { join, body, .. } => size(join) + size(body),
+        Expr::If {
+            branch_t, branch_f, ..
+        } => 1 + size(branch_t) + size(branch_f),
+        Expr::Case { arms, default, .. } => {
+            let bodies = arms.iter().map(|(_, e)| e).chain(default.as_deref());
+            1 + bodies.map(size).sum::<usize>()
+        }
+        Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => 1,
+    })
+}
+
+/** Counts the uses of the variables of `expr` into `uses` */
+fn count(expr: &Expr, uses: &mut std::collections::HashMap<Var, usize>) {
+    grow(|| {
+        let mut atoms = |atoms: Vec<&Atom>| {
+            for var in atoms.into_iter().filter_map(Atom::var) {
+                *uses.entry(var.clone()).or_default() += 1
+            }
+        };
+        match expr {
+            Expr::Let { comp, body, .. } => {
+                atoms(comp.atoms());
+                if let Comp::Handle { body, handler, .. } = comp {
+                    count(body, uses);
+                    count(handler, uses)
+                }
+                count(body, uses)
+            }
+            Expr::Fix { funcs, body } => {
+                for func in funcs {
+                    count(&func.body, uses)
+                }
+                count(body, uses)
+            }
+            Expr::Join { join, body, .. } => {
+                count(join, uses);
+                count(body, uses)
+            }
+            Expr::Jump { args, .. } => atoms(args.iter().collect()),
+            Expr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => {
+                atoms(vec![cond]);
+                count(branch_t, uses);
+                count(branch_f, uses)
+            }
+            Expr::Case {
+                scrut,
+                arms,
+                default,
+            } => {
+                atoms(vec![scrut]);
+                for body in arms.iter().map(|(_, e)| e).chain(default.as_deref()) {
+                    count(body, uses)
+                }
+            }
+            Expr::Raise { exn: val } | Expr::Ret { val } => atoms(vec![val]),
+        }
+    })
+}
+
+/** The number of tail positions `expr` returns from */
+fn returns(expr: &Expr) -> usize {
+    grow(|| match expr {
+        Expr::Let { body, .. } | Expr::Fix { body, .. } => returns(body),
+        Expr::Join { join, body, .. } => returns(join) + returns(body),
+        Expr::If {
+            branch_t, branch_f, ..
+        } => returns(branch_t) + returns(branch_f),
+        Expr::Case { arms, default, .. } => {
+            let bodies = arms.iter().map(|(_, e)| e).chain(default.as_deref());
+            bodies.map(returns).sum()
+        }
+        Expr::Ret { .. } => 1,
+        Expr::Jump { .. } | Expr::Raise { .. } => 0,
+    })
+}
+
+/** `expr` with `f` of the atom returned in place of its tail positions returning */
+fn map_rets(expr: &Expr, f: &mut impl FnMut(Atom) -> Expr) -> Expr {
+    grow(|| match expr {
+        Expr::Let { var, comp, body } => Expr::Let {
+            var: var.clone(),
+            comp: comp.clone(),
+            body: Box::new(map_rets(body, f)),
+        },
+        Expr::Fix { funcs, body } => Expr::Fix {
+            funcs: funcs.clone(),
+            body: Box::new(map_rets(body, f)),
+        },
+        Expr::Join {
+            name,
+            params,
+            join,
+            body,
+        } => Expr::Join {
+            name: name.clone(),
+            params: params.clone(),
+            join: Box::new(map_rets(join, f)),
+            body: Box::new(map_rets(body, f)),
+        },
+        Expr::If {
+            cond,
+            branch_t,
+            branch_f,
+        } => Expr::If {
+            cond: cond.clone(),
+            branch_t: Box::new(map_rets(branch_t, f)),
+            branch_f: Box::new(map_rets(branch_f, f)),
+        },
+        Expr::Case {
+            scrut,
+            arms,
+            default,
+        } => Expr::Case {
+            scrut: scrut.clone(),
+            arms: arms
+                .iter()
+                .map(|(c, e)| (c.clone(), map_rets(e, f)))
+                .collect(),
+            default: default.as_deref().map(|e| Box::new(map_rets(e, f))),
+        },
+        Expr::Ret { val } => f(val.clone()),
+        Expr::Jump { .. } | Expr::Raise { .. } => expr.clone(),
+    })
+}
@@ -0,0 +1,984 @@
+/*! A reference interpreter of both intermediate representations, to check passes by running
+programs before and after them: a pass preserving the meaning of programs leaves what they print
+and the values of their declarations unchanged. Evaluation is by value like that of the
+[interpreter](crate::ast::interp) of the source, but tasks run to the end when they're spawned,
+like they do compiled, instead of taking turns on a pool. That's one of the orders the pool could
+run them in. Both machines keep their continuations on the heap, and calls in tail position don't
+grow them, so deep tail recursion runs like it does compiled. Values are abstract: tagging, untagging
+and unboxing are the identity, boxing wraps integers around to the width boxed, and functions are
+displayed alike, whether closures or not. */
+
+use crate::ast::ast::{grow, Binary, Constant, DIV, SUBSCRIPT};
+use crate::ast::semant::BUILTINS;
+use crate::ir::anf::{self, Atom, Comp, Expr, Prim, Var};
+use crate::ir::pass::Ir;
+use crate::ir::ssa::{self, Label, Op, Term};
+
+use std::fmt::{self, Display};
+use std::iter::zip;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use im::HashMap;
+
+/// Values of both intermediate representations. Copying them takes constant time
+#[derive(Clone)]
+pub enum Value<'a> {
+    Con(Constant),
+    Tuple(Rc<[Value<'a>]>),
+    Record(Rc<[(&'a str, Value<'a>)]>),
+    Data(&'a str, Fields<'a>),
+    /// A function of a `fix` in A-normal form, with the environment of the `fix`
+    Local(Rc<Local<'a>>),
+    /// A function at the top level, by name
+    Global(&'a str),
+    /// A function with the values it captures
+    Closure(Rc<Value<'a>>, Rc<[Value<'a>]>),
+    Builtin(&'a str),
+    Ref(usize),
+    /// Arrays, by the location of their first element and their length
+    Array(usize, usize),
+    /// A task started by `spawn`, run when spawned, with its value or the exception it raised
+    Task(Rc<Result<Value<'a>, Value<'a>>>),
+}
+
+/// The fields of a value of a constructor. Lists are chains of them as long as the lists, so they're
+/// dropped on a stack [grown](grow) as deep as the chain
+#[derive(Clone, Default)]
+pub struct Fields<'a>(Rc<[Value<'a>]>);
+
+impl<'a> Deref for Fields<'a> {
+    type Target = [Value<'a>];
+
+    fn deref(&self) -> &[Value<'a>] {
+        &self.0
+    }
+}
+
+impl<'a> From<Vec<Value<'a>>> for Fields<'a> {
+    fn from(fields: Vec<Value<'a>>) -> Self {
+        Fields(fields.into())
+    }
+}
+
+impl Drop for Fields<'_> {
+    fn drop(&mut self) {
+        let fields = std::mem::take(&mut self.0);
+        grow(|| drop(fields))
+    }
+}
+
+/// The function `index` of the functions `funcs` of a `fix`, defined in `env`
+pub struct Local<'a> {
+    funcs: &'a [anf::Func],
+    index: usize,
+    env: Env<'a>,
+}
+
+/// Why a program stopped before evaluating all of its declarations
+#[derive(Debug, PartialEq, Clone)]
+pub enum Stop {
+    /// An exception no handler caught, displayed
+    Uncaught(String),
+    /// An `assert` of `false` or an `assertEq` of different values
+    Assertion,
+    /// An operation on values of the wrong shape, or a variable without a value, with what went
+    /// wrong. Never for programs compiled right
+    Stuck(String),
+    /// Running out of fuel
+    Exhausted,
+}
+
+/// What running a program did
+#[derive(Debug, PartialEq, Clone)]
+pub struct Outcome {
+    /// What the builtins printed
+    pub output: String,
+    /// The values of the declarations in order, displayed, or why evaluation stopped
+    pub result: Result<Vec<(String, String)>, Stop>,
+}
+
+impl Outcome {
+    /** Whether `self` and `other` printed the same and stopped alike, the declarations both have
+    of the same values. Passes may add declarations, like the workers of uncurried functions, and
+    remove those unused */
+    pub fn agrees(&self, other: &Outcome) -> bool {
+        let same_values = |values: &[(String, String)], others: &[(String, String)]| {
+            values.iter().all(|(id, val)| {
+                let other = others.iter().find(|(other, _)| other == id);
+                other.is_none_or(|(_, other)| other == val)
+            })
+        };
+        self.output == other.output
+            && match (&self.result, &other.result) {
+                (Ok(values), Ok(others)) => same_values(values, others),
+                (stop, other) => stop == other,
+            }
+    }
+}
+
+impl Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Written as they're traversed, lists taking time linear in their length
+        let commas = |f: &mut fmt::Formatter, vals: &[Value]| {
+            for (index, val) in vals.iter().enumerate() {
+                let sep = if index == 0 { "" } else { ", " };
+                write!(f, "{sep}{val}")?
+            }
+            Ok(())
+        };
+        grow(|| match self {
+            Value::Con(con) => write!(f, "{con}"),
+            Value::Tuple(entries) => {
+                write!(f, "(")?;
+                commas(f, entries)?;
+                write!(f, ")")
+            }
+            Value::Record(fields) => {
+                write!(f, "{{")?;
+                for (index, (l, v)) in fields.iter().enumerate() {
+                    let sep = if index == 0 { "" } else { ", " };
+                    write!(f, "{sep}{l} = {v}")?
+                }
+                write!(f, "}}")
+            }
+            Value::Data(ctor, fields) if fields.is_empty() => write!(f, "{ctor}"),
+            Value::Data(ctor, fields) => {
+                write!(f, "{ctor}(")?;
+                commas(f, fields)?;
+                write!(f, ")")
+            }
+            Value::Local(_) | Value::Global(_) | Value::Closure(..) | Value::Builtin(_) => {
+                write!(f, "<fun>")
+            }
+            Value::Ref(_) => write!(f, "<ref>"),
+            Value::Array(..) => write!(f, "<array>"),
+            Value::Task(_) => write!(f, "<task>"),
+        })
+    }
+}
+
+impl Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Uncaught(exn) => write!(f, "Uncaught exception: {exn}"),
+            Stop::Assertion => write!(f, "Assertion failed"),
+            Stop::Stuck(why) => write!(f, "Stuck: {why}"),
+            Stop::Exhausted => write!(f, "Out of fuel"),
+        }
+    }
+}
+
+fn stuck<T>(why: impl Display) -> Result<T, Stop> {
+    Err(Stop::Stuck(why.to_string()))
+}
+
+/** The exception `ctor` without fields */
+fn exception<'a>(ctor: &'static str) -> Value<'a> {
+    Value::Data(ctor, Fields::default())
+}
+
+/// What an operation does, but return its value
+enum Done<'a> {
+    Value(Value<'a>),
+    Raise(Value<'a>),
+    /// Spawning a task, its function to apply to `()`
+    Spawn(Value<'a>),
+}
+
+/** Structural equality of values whose type admits equality. References and arrays are equal
+when they are the same */
+fn equal(v1: &Value, v2: &Value) -> Result<bool, Stop> {
+    let all_equal = |vs1: &[Value], vs2: &[Value]| {
+        zip(vs1, vs2).try_fold(true, |all, (v1, v2)| Ok(all && equal(v1, v2)?))
+    };
+    grow(|| match (v1, v2) {
+        (Value::Con(c1), Value::Con(c2)) => Ok(match (c1.as_int(), c2.as_int()) {
+            (Some(n1), Some(n2)) => n1 == n2,
+            _ => c1 == c2,
+        }),
+        (Value::Tuple(vs1), Value::Tuple(vs2)) => all_equal(vs1, vs2),
+        (Value::Data(c1, vs1), Value::Data(c2, vs2)) => Ok(c1 == c2 && all_equal(vs1, vs2)?),
+        (Value::Record(fs1), Value::Record(fs2)) => fs1.iter().try_fold(true, |all, (l, v1)| {
+            match fs2.iter().find(|(l2, _)| l2 == l) {
+                Some((_, v2)) => Ok(all && equal(v1, v2)?),
+                None => stuck(format!("comparing records without {l}")),
+            }
+        }),
+        (Value::Ref(l1), Value::Ref(l2)) | (Value::Array(l1, _), Value::Array(l2, _)) => {
+            Ok(l1 == l2)
+        }
+        _ => stuck(format!("comparing {v1} and {v2}")),
+    })
+}
+
+/** Applies `op` to `lhs` and `rhs`, raising `Div` for division by zero */
+fn binop<'a>(lhs: Value<'a>, op: &Binary, rhs: Value<'a>) -> Result<Done<'a>, Stop> {
+    use Binary::*;
+    use Constant::*;
+    let result = match (op, &lhs, &rhs) {
+        (Eq | Ne, _, _) => Boolean(equal(&lhs, &rhs)? == (*op == Eq)),
+        (And, Value::Con(Boolean(l)), Value::Con(Boolean(r))) => Boolean(l & r),
+        (Or, Value::Con(Boolean(l)), Value::Con(Boolean(r))) => Boolean(l | r),
+        (_, Value::Con(Integer(l)), Value::Con(Integer(r))) => match op.apply_int(*l, *r) {
+            Some(result) => result,
+            None => return Ok(Done::Raise(exception(DIV))),
+        },
+        (_, Value::Con(Sized(l, w)), Value::Con(Sized(r, _))) => match op.apply_sized(*l, *r, *w) {
+            Some(result) => result,
+            None => return Ok(Done::Raise(exception(DIV))),
+        },
+        _ => return stuck(format!("{lhs} {op} {rhs}")),
+    };
+    Ok(Done::Value(Value::Con(result)))
+}
+
+/** The entry `index` of the tuple `tuple` */
+fn nth<'a>(tuple: Value<'a>, index: usize) -> Result<Value<'a>, Stop> {
+    match &tuple {
+        Value::Tuple(entries) if index < entries.len() => Ok(entries[index].clone()),
+        _ => stuck(format!("{tuple}.{index}")),
+    }
+}
+
+/** The field `label` of the record `record` */
+fn proj<'a>(record: Value<'a>, label: &str) -> Result<Value<'a>, Stop> {
+    match &record {
+        Value::Record(fields) => match fields.iter().find(|(l, _)| *l == label) {
+            Some((_, val)) => Ok(val.clone()),
+            None => stuck(format!("{record}.{label}")),
+        },
+        _ => stuck(format!("{record}.{label}")),
+    }
+}
+
+/** The field `index` of the value of a constructor `data` */
+fn field<'a>(data: Value<'a>, index: usize) -> Result<Value<'a>, Stop> {
+    match &data {
+        Value::Data(_, fields) if index < fields.len() => Ok(fields[index].clone()),
+        _ => stuck(format!("{data}#{index}")),
+    }
+}
+
+/** The value `index` captured by the closure `clo` */
+fn env<'a>(clo: Value<'a>, index: usize) -> Result<Value<'a>, Stop> {
+    match &clo {
+        Value::Closure(_, env) if index < env.len() => Ok(env[index].clone()),
+        _ => stuck(format!("{clo}@{index}")),
+    }
+}
+
+/// The mutable state of a program running, shared by both machines
+struct Store<'a> {
+    cells: Vec<Value<'a>>,
+    /// The values of the declarations evaluated
+    globals: HashMap<&'a str, Value<'a>>,
+    /// The lines `readLine` reads, last first
+    input: Vec<String>,
+    output: String,
+    fuel: usize,
+}
+
+impl<'a> Store<'a> {
+    fn new(input: &str, fuel: usize) -> Self {
+        Store {
+            cells: vec![],
+            globals: HashMap::new(),
+            input: input.lines().rev().map(str::to_string).collect(),
+            output: String::new(),
+            fuel,
+        }
+    }
+
+    /** Takes a step, if there's fuel left */
+    fn tick(&mut self) -> Result<(), Stop> {
+        self.fuel = self.fuel.checked_sub(1).ok_or(Stop::Exhausted)?;
+        Ok(())
+    }
+
+    /** The value of the declaration, builtin or function at the top level `var`, those of
+    `funcs` */
+    fn global(&self, var: &'a str, funcs: impl Fn(&str) -> bool) -> Result<Value<'a>, Stop> {
+        if let Some(val) = self.globals.get(var) {
+            return Ok(val.clone());
+        }
+        if funcs(var) {
+            return Ok(Value::Global(var));
+        }
+        match BUILTINS.iter().find(|(id, _)| *id == var) {
+            Some((id, _)) => Ok(Value::Builtin(id)),
+            None => stuck(format!("{var} is unbound")),
+        }
+    }
+
+    /** Applies the builtin `id` to `args`, like the interpreter of the source does */
+    fn builtin(&mut self, id: &str, args: Vec<Value<'a>>) -> Result<Value<'a>, Stop> {
+        use Constant::*;
+        let val = match (id, &args[..]) {
+            ("print", [Value::Con(Str(s))]) => {
+                self.output.push_str(s);
+                Null
+            }
+            ("printInt", [Value::Con(Integer(n))]) => {
+                self.output.push_str(&n.to_string());
+                Null
+            }
+            ("readLine", [Value::Con(Null)]) => Str(self.input.pop().unwrap_or_default()),
+            ("showInt", [Value::Con(Integer(n))]) => Str(n.to_string()),
+            ("concat", [Value::Tuple(strs)]) => match &strs[..] {
+                [Value::Con(Str(s1)), Value::Con(Str(s2))] => Str(format!("{s1}{s2}")),
+                _ => return stuck(format!("concatenating {}", args[0])),
+            },
+            ("assert", [Value::Con(Boolean(true))]) => Null,
+            ("assert", [Value::Con(Boolean(false))]) => return Err(Stop::Assertion),
+            _ => return stuck(format!("applying {id} to {} arguments", args.len())),
+        };
+        Ok(Value::Con(val))
+    }
+
+    /** Applies `prim` to `args` */
+    fn prim(&mut self, prim: &Prim, args: Vec<Value<'a>>) -> Result<Done<'a>, Stop> {
+        use Constant::{Integer, Null};
+        let cell = |arr: &Value, idx: &Value| match (arr, idx) {
+            (Value::Array(start, len), Value::Con(Integer(i))) => {
+                Ok((0 <= *i && (*i as usize) < *len).then(|| start + *i as usize))
+            }
+            _ => stuck(format!("indexing {arr} by {idx}")),
+        };
+        let null = Value::Con(Null);
+        let val = match (prim, &args[..]) {
+            (Prim::Arith(op, _), [lhs, rhs]) => return binop(lhs.clone(), op, rhs.clone()),
+            (Prim::Convert(_, to), [Value::Con(con)]) => match con.as_int() {
+                Some(n) => Value::Con(to.constant(to.wrap(n))),
+                None => return stuck(format!("converting {con}")),
+            },
+            (Prim::Ref, [val]) => {
+                self.cells.push(val.clone());
+                Value::Ref(self.cells.len() - 1)
+            }
+            (Prim::Deref, [Value::Ref(loc)]) => self.cells[*loc].clone(),
+            (Prim::Assign, [Value::Ref(loc), val]) => {
+                self.cells[*loc] = val.clone();
+                null
+            }
+            (Prim::Array, [Value::Con(Integer(n)), _]) if *n < 0 => {
+                return Ok(Done::Raise(exception(SUBSCRIPT)))
+            }
+            (Prim::Array, [Value::Con(Integer(n)), init]) => {
+                let start = self.cells.len();
+                self.cells.resize(start + *n as usize, init.clone());
+                Value::Array(start, *n as usize)
+            }
+            (Prim::Sub, [arr, idx]) => match cell(arr, idx)? {
+                Some(loc) => self.cells[loc].clone(),
+                None => return Ok(Done::Raise(exception(SUBSCRIPT))),
+            },
+            (Prim::Update, [arr, idx, val]) => match cell(arr, idx)? {
+                Some(loc) => {
+                    self.cells[loc] = val.clone();
+                    null
+                }
+                None => return Ok(Done::Raise(exception(SUBSCRIPT))),
+            },
+            (Prim::AssertEq, [lhs, rhs]) => match equal(lhs, rhs)? {
+                true => null,
+                false => return Err(Stop::Assertion),
+            },
+            (Prim::Spawn, [fun]) => return Ok(Done::Spawn(fun.clone())),
+            (Prim::Join, [Value::Task(task)]) => match &**task {
+                Ok(val) => val.clone(),
+                Err(exn) => return Ok(Done::Raise(exn.clone())),
+            },
+            _ => return stuck(format!("{prim} of {} arguments", args.len())),
+        };
+        Ok(Done::Value(val))
+    }
+}
+
+/** Runs the declarations of `ir` in order for at most `fuel` steps, `readLine` reading the lines
+of `input` */
+pub fn run(ir: &Ir, input: &str, fuel: usize) -> Outcome {
+    match ir {
+        Ir::Anf(prog) => run_anf(prog, input, fuel),
+        Ir::Ssa(prog) => run_ssa(prog, input, fuel),
+    }
+}
+
+/** Runs the declarations of `prog` in A-normal form, like [`run`] */
+pub fn run_anf(prog: &anf::Prog, input: &str, fuel: usize) -> Outcome {
+    let mut machine = Anf {
+        funcs: prog.funcs.iter().map(|f| (f.name.as_str(), f)).collect(),
+        store: Store::new(input, fuel),
+    };
+    let mut values = vec![];
+    for decl in &prog.decls {
+        match machine.eval(&decl.body) {
+            Ok(val) => {
+                values.push((decl.id.clone(), val.to_string()));
+                machine.store.globals.insert(&decl.id, val);
+            }
+            Err(stop) => return machine.store.outcome(Err(stop)),
+        }
+    }
+    machine.store.outcome(Ok(values))
+}
+
+/** Runs the declarations of `prog` in static single assignment form, like [`run`] */
+pub fn run_ssa(prog: &ssa::Prog, input: &str, fuel: usize) -> Outcome {
+    let mut machine = Ssa {
+        funcs: prog.funcs.iter().map(|f| (f.name.as_str(), f)).collect(),
+        store: Store::new(input, fuel),
+    };
+    let mut values = vec![];
+    for decl in &prog.decls {
+        match machine.call(decl) {
+            Ok(val) => {
+                values.push((decl.name.clone(), val.to_string()));
+                machine.store.globals.insert(&decl.name, val);
+            }
+            Err(stop) => return machine.store.outcome(Err(stop)),
+        }
+    }
+    machine.store.outcome(Ok(values))
+}
+
+impl Store<'_> {
+    fn outcome(&mut self, result: Result<Vec<(String, String)>, Stop>) -> Outcome {
+        Outcome {
+            output: std::mem::take(&mut self.output),
+            result,
+        }
+    }
+}
+
+/// The variables and join points in scope in A-normal form
+#[derive(Clone, Default)]
+struct Env<'a> {
+    vars: HashMap<&'a str, Value<'a>>,
+    joins: HashMap<&'a str, Rc<JoinPoint<'a>>>,
+}
+
+/// A join point with the environment it's defined in
+struct JoinPoint<'a> {
+    params: &'a [Var],
+    join: &'a Expr,
+    env: Env<'a>,
+}
+
+impl<'a> Env<'a> {
+    fn bind(&self, var: &'a str, val: Value<'a>) -> Env<'a> {
+        Env {
+            vars: self.vars.update(var, val),
+            joins: self.joins.clone(),
+        }
+    }
+
+    fn bind_all(&self, vars: &'a [Var], vals: Vec<Value<'a>>) -> Result<Env<'a>, Stop> {
+        if vars.len() != vals.len() {
+            return stuck(format!(
+                "passing {} values to {}",
+                vals.len(),
+                vars.join(", ")
+            ));
+        }
+        let mut env = self.clone();
+        env.vars
+            .extend(zip(vars.iter().map(|var| var.as_str()), vals));
+        Ok(env)
+    }
+}
+
+/// The machine of A-normal form
+struct Anf<'a> {
+    funcs: std::collections::HashMap<&'a str, &'a anf::Func>,
+    store: Store<'a>,
+}
+
+/// What the machine of A-normal form does next
+enum State<'a> {
+    Eval(&'a Expr, Env<'a>),
+    Return(Value<'a>),
+    Raise(Value<'a>),
+}
+
+/// What's left to do once the expression evaluated returns
+enum Frame<'a> {
+    /// Evaluating the body with its value bound to the variable
+    Let(&'a str, &'a Expr, Env<'a>),
+    /// Evaluating the handler, with the exception bound to the variable, if it raises one
+    Handle(&'a str, &'a Expr, Env<'a>),
+    /// Making the task of its value, or of the exception it raises
+    Spawn,
+}
+
+impl<'a> Anf<'a> {
+    fn var(&self, var: &'a str, env: &Env<'a>) -> Result<Value<'a>, Stop> {
+        match env.vars.get(var) {
+            Some(val) => Ok(val.clone()),
+            None => self.store.global(var, |f| self.funcs.contains_key(f)),
+        }
+    }
+
+    fn atom(&self, atom: &'a Atom, env: &Env<'a>) -> Result<Value<'a>, Stop> {
+        match atom {
+            Atom::Con(con) => Ok(Value::Con(con.clone())),
+            Atom::Var(var) => self.var(var, env),
+        }
+    }
+
+    fn atoms(&self, atoms: &'a [Atom], env: &Env<'a>) -> Result<Vec<Value<'a>>, Stop> {
+        atoms.iter().map(|atom| self.atom(atom, env)).collect()
+    }
+
+    /** Evaluates `body` with an empty environment */
+    fn eval(&mut self, body: &'a Expr) -> Result<Value<'a>, Stop> {
+        let mut stack = vec![];
+        let mut state = State::Eval(body, Env::default());
+        loop {
+            self.store.tick()?;
+            state = match state {
+                State::Eval(expr, env) => self.step(expr, env, &mut stack)?,
+                State::Return(val) => match stack.pop() {
+                    None => return Ok(val),
+                    Some(Frame::Let(var, body, env)) => State::Eval(body, env.bind(var, val)),
+                    Some(Frame::Handle(..)) => State::Return(val),
+                    Some(Frame::Spawn) => State::Return(Value::Task(Rc::new(Ok(val)))),
+                },
+                State::Raise(exn) => loop {
+                    match stack.pop() {
+                        None => return Err(Stop::Uncaught(exn.to_string())),
+                        Some(Frame::Handle(var, handler, env)) => {
+                            break State::Eval(handler, env.bind(var, exn))
+                        }
+                        Some(Frame::Spawn) => break State::Return(Value::Task(Rc::new(Err(exn)))),
+                        Some(_) => (),
+                    }
+                },
+            }
+        }
+    }
+
+    /** Takes a step evaluating `expr` under `env`, pushing what's left to do after onto `stack` */
+    fn step(
+        &mut self,
+        expr: &'a Expr,
+        env: Env<'a>,
+        stack: &mut Vec<Frame<'a>>,
+    ) -> Result<State<'a>, Stop> {
+        match expr {
+            Expr::Let { var, comp, body } => {
+                // Nothing is left to do after a computation in tail position
+                let tail = matches!(&**body, Expr::Ret { val: Atom::Var(v) } if v == var);
+                let then = |stack: &mut Vec<Frame<'a>>| {
+                    if !tail {
+                        stack.push(Frame::Let(var, body, env.clone()))
+                    }
+                };
+                let done = match comp {
+                    Comp::App(fun, args) => {
+                        let fun = self.atom(fun, &env)?;
+                        let args = self.atoms(args, &env)?;
+                        then(stack);
+                        return self.apply(fun, args);
+                    }
+                    Comp::Handle {
+                        body: handled,
+                        exn,
+                        handler,
+                    } => {
+                        then(stack);
+                        stack.push(Frame::Handle(exn, handler, env.clone()));
+                        return Ok(State::Eval(handled, env));
+                    }
+                    Comp::Atom(atom) => Done::Value(self.atom(atom, &env)?),
+                    Comp::Binop(lhs, op, rhs) => {
+                        binop(self.atom(lhs, &env)?, op, self.atom(rhs, &env)?)?
+                    }
+                    Comp::Tuple(entries) => {
+                        Done::Value(Value::Tuple(self.atoms(entries, &env)?.into()))
+                    }
+                    Comp::Nth(tuple, index) => Done::Value(nth(self.atom(tuple, &env)?, *index)?),
+                    Comp::Record(fields) => {
+                        let fields = fields
+                            .iter()
+                            .map(|(l, a)| Ok((l.as_str(), self.atom(a, &env)?)));
+                        Done::Value(Value::Record(fields.collect::<Result<_, _>>()?))
+                    }
+                    Comp::Proj(record, label) => {
+                        Done::Value(proj(self.atom(record, &env)?, label)?)
+                    }
+                    Comp::Data(ctor, fields) => {
+                        Done::Value(Value::Data(ctor, self.atoms(fields, &env)?.into()))
+                    }
+                    Comp::Field(data, index) => Done::Value(field(self.atom(data, &env)?, *index)?),
+                    Comp::Closure(func, captured) => {
+                        let func = self.var(func, &env)?;
+                        let captured = self.atoms(captured, &env)?;
+                        Done::Value(Value::Closure(Rc::new(func), captured.into()))
+                    }
+                    Comp::Env(clo, index) => Done::Value(self::env(self.atom(clo, &env)?, *index)?),
+                    Comp::Prim(prim, args) => {
+                        let args = self.atoms(args, &env)?;
+                        self.store.prim(prim, args)?
+                    }
+                };
+                match done {
+                    Done::Value(val) => Ok(State::Eval(body, env.bind(var, val))),
+                    Done::Raise(exn) => Ok(State::Raise(exn)),
+                    Done::Spawn(fun) => {
+                        then(stack);
+                        stack.push(Frame::Spawn);
+                        self.apply(fun, vec![Value::Con(Constant::Null)])
+                    }
+                }
+            }
+            Expr::Fix { funcs, body } => {
+                let mut body_env = env.clone();
+                for (index, func) in funcs.iter().enumerate() {
+                    let local = Local {
+                        funcs,
+                        index,
+                        env: env.clone(),
+                    };
+                    body_env = body_env.bind(&func.name, Value::Local(Rc::new(local)))
+                }
+                Ok(State::Eval(body, body_env))
+            }
+            Expr::Join {
+                name,
+                params,
+                join,
+                body,
+            } => {
+                let point = JoinPoint {
+                    params,
+                    join,
+                    env: env.clone(),
+                };
+                let mut body_env = env;
+                body_env.joins.insert(name, Rc::new(point));
+                Ok(State::Eval(body, body_env))
+            }
+            Expr::Jump { name, args } => {
+                let Some(point) = env.joins.get(name.as_str()) else {
+                    return stuck(format!("jumping to {name} out of its scope"));
+                };
+                let args = self.atoms(args, &env)?;
+                Ok(State::Eval(
+                    point.join,
+                    point.env.bind_all(point.params, args)?,
+                ))
+            }
+            Expr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => match self.atom(cond, &env)? {
+                Value::Con(Constant::Boolean(true)) => Ok(State::Eval(branch_t, env)),
+                Value::Con(Constant::Boolean(false)) => Ok(State::Eval(branch_f, env)),
+                cond => stuck(format!("branching on {cond}")),
+            },
+            Expr::Case {
+                scrut,
+                arms,
+                default,
+            } => match self.atom(scrut, &env)? {
+                Value::Data(ctor, _) => {
+                    let arm = arms.iter().find(|(c, _)| c == ctor).map(|(_, arm)| arm);
+                    match arm.or(default.as_deref()) {
+                        Some(arm) => Ok(State::Eval(arm, env)),
+                        None => stuck(format!("no arm for {ctor}")),
+                    }
+                }
+                scrut => stuck(format!("case of {scrut}")),
+            },
+            Expr::Raise { exn } => Ok(State::Raise(self.atom(exn, &env)?)),
+            Expr::Ret { val } => Ok(State::Return(self.atom(val, &env)?)),
+        }
+    }
+
+    /** Applies `fun` to `args` */
+    fn apply(&mut self, fun: Value<'a>, args: Vec<Value<'a>>) -> Result<State<'a>, Stop> {
+        match &fun {
+            Value::Local(local) => {
+                let mut env = local.env.clone();
+                for (index, func) in local.funcs.iter().enumerate() {
+                    let local = Local {
+                        funcs: local.funcs,
+                        index,
+                        env: local.env.clone(),
+                    };
+                    env = env.bind(&func.name, Value::Local(Rc::new(local)))
+                }
+                let func = &local.funcs[local.index];
+                Ok(State::Eval(&func.body, env.bind_all(&func.params, args)?))
+            }
+            Value::Global(name) => {
+                let func = self.funcs[name];
+                Ok(State::Eval(
+                    &func.body,
+                    Env::default().bind_all(&func.params, args)?,
+                ))
+            }
+            Value::Closure(func, _) => {
+                let args = std::iter::once(fun.clone()).chain(args).collect();
+                self.apply((**func).clone(), args)
+            }
+            Value::Builtin(id) => Ok(State::Return(self.store.builtin(id, args)?)),
+            _ => stuck(format!("applying {fun}")),
+        }
+    }
+}
+
+/// The machine of static single assignment form
+struct Ssa<'a> {
+    funcs: std::collections::HashMap<&'a str, &'a ssa::Func>,
+    store: Store<'a>,
+}
+
+/// The call of a function running
+struct Activation<'a> {
+    func: &'a ssa::Func,
+    regs: std::collections::HashMap<&'a str, Value<'a>>,
+    /// The block running, and the position of its next instruction
+    label: Label,
+    index: usize,
+    /// What the caller does with the result, nothing for the outermost
+    resume: Option<Resume<'a>>,
+}
+
+/// What callers do with the results of calls
+enum Resume<'a> {
+    /// Jumping to the block with it
+    Jump(Label),
+    /// Assigning the task of it, or of the exception raised, to the register
+    Spawn(&'a str),
+}
+
+impl<'a> Activation<'a> {
+    fn new(func: &'a ssa::Func, args: Vec<Value<'a>>) -> Result<Self, Stop> {
+        let mut activation = Activation {
+            func,
+            regs: std::collections::HashMap::new(),
+            label: 0,
+            index: 0,
+            resume: None,
+        };
+        activation.jump(0, args)?;
+        Ok(activation)
+    }
+
+    fn jump(&mut self, label: Label, args: Vec<Value<'a>>) -> Result<(), Stop> {
+        let params = &self.func.blocks[label].params;
+        if params.len() != args.len() {
+            return stuck(format!("jumping to b{label} of {}", self.func.name));
+        }
+        self.regs
+            .extend(zip(params.iter().map(|p| p.as_str()), args));
+        self.label = label;
+        self.index = 0;
+        Ok(())
+    }
+
+    fn atom(
+        &self,
+        atom: &'a Atom,
+        store: &Store<'a>,
+        funcs: impl Fn(&str) -> bool,
+    ) -> Result<Value<'a>, Stop> {
+        match atom {
+            Atom::Con(con) => Ok(Value::Con(con.clone())),
+            Atom::Var(var) => match self.regs.get(var.as_str()) {
+                Some(val) => Ok(val.clone()),
+                None => store.global(var, funcs),
+            },
+        }
+    }
+}
+
+impl<'a> Ssa<'a> {
+    /** Calls `func` without arguments */
+    fn call(&mut self, func: &'a ssa::Func) -> Result<Value<'a>, Stop> {
+        let mut stack = vec![Activation::new(func, vec![])?];
+        loop {
+            self.store.tick()?;
+            let funcs = |f: &str| self.funcs.contains_key(f);
+            let act = stack.last_mut().unwrap();
+            let block = &act.func.blocks[act.label];
+            let atom = |atom| act.atom(atom, &self.store, funcs);
+            let atoms = |atoms: &'a [Atom]| atoms.iter().map(atom).collect::<Result<Vec<_>, _>>();
+            if let Some(inst) = block.insts.get(act.index) {
+                let done = match &inst.op {
+                    Op::Atom(a) | Op::Tag(a) | Op::Untag(a) | Op::Unbox(a) => Done::Value(atom(a)?),
+                    Op::Box(width, raw) => match atom(raw)? {
+                        Value::Con(con) if con.as_int().is_some() => {
+                            let n = con.as_int().unwrap();
+                            Done::Value(Value::Con(width.constant(width.wrap(n))))
+                        }
+                        val => return stuck(format!("boxing {val}")),
+                    },
+                    Op::Binop(lhs, op, rhs) => binop(atom(lhs)?, op, atom(rhs)?)?,
+                    Op::Tuple(entries) => Done::Value(Value::Tuple(atoms(entries)?.into())),
+                    Op::Nth(tuple, index) => Done::Value(nth(atom(tuple)?, *index)?),
+                    Op::Record(fields) => {
+                        let fields = fields.iter().map(|(l, a)| Ok((l.as_str(), atom(a)?)));
+                        Done::Value(Value::Record(fields.collect::<Result<_, _>>()?))
+                    }
+                    Op::Proj(record, label) => Done::Value(proj(atom(record)?, label)?),
+                    Op::Data(ctor, fields) => Done::Value(Value::Data(ctor, atoms(fields)?.into())),
+                    Op::Field(data, index) => Done::Value(field(atom(data)?, *index)?),
+                    Op::Closure(func, captured) => {
+                        let func = Rc::new(Value::Global(func));
+                        Done::Value(Value::Closure(func, atoms(captured)?.into()))
+                    }
+                    Op::Env(clo, index) => Done::Value(env(atom(clo)?, *index)?),
+                    Op::Prim(prim, args) => {
+                        let args = atoms(args)?;
+                        self.store.prim(prim, args)?
+                    }
+                };
+                act.index += 1;
+                match done {
+                    Done::Value(val) => {
+                        act.regs.insert(&inst.var, val);
+                    }
+                    Done::Raise(exn) => Self::raise(&mut stack, exn)?,
+                    Done::Spawn(fun) => {
+                        let resume = Resume::Spawn(&inst.var);
+                        let null = vec![Value::Con(Constant::Null)];
+                        self.enter(&mut stack, fun, null, resume)?
+                    }
+                }
+                continue;
+            }
+            match &block.term {
+                Term::Jump(label, args) => {
+                    let args = atoms(args)?;
+                    act.jump(*label, args)?
+                }
+                Term::Branch {
+                    cond,
+                    branch_t,
+                    branch_f,
+                } => match atom(cond)? {
+                    Value::Con(Constant::Boolean(b)) => {
+                        act.jump(if b { *branch_t } else { *branch_f }, vec![])?
+                    }
+                    cond => return stuck(format!("branching on {cond}")),
+                },
+                Term::Switch {
+                    scrut,
+                    arms,
+                    default,
+                } => match atom(scrut)? {
+                    Value::Data(ctor, _) => {
+                        let arm = arms.iter().find(|(c, _)| c == ctor).map(|(_, l)| *l);
+                        match arm.or(*default) {
+                            Some(label) => act.jump(label, vec![])?,
+                            None => return stuck(format!("no arm for {ctor}")),
+                        }
+                    }
+                    scrut => return stuck(format!("case of {scrut}")),
+                },
+                Term::Call { fun, args, ret } => {
+                    let (fun, args) = (atom(fun)?, atoms(args)?);
+                    self.enter(&mut stack, fun, args, Resume::Jump(*ret))?
+                }
+                Term::TailCall { fun, args } => {
+                    let (fun, args) = (atom(fun)?, atoms(args)?);
+                    let act = stack.pop().unwrap();
+                    match act.resume {
+                        Some(resume) => self.enter(&mut stack, fun, args, resume)?,
+                        // The outermost call
+                        None => match self.callee(fun, args)? {
+                            Ok(callee) => stack.push(callee),
+                            Err(val) => return Ok(val),
+                        },
+                    }
+                }
+                Term::Ret(val) => {
+                    let val = atom(val)?;
+                    let act = stack.pop().unwrap();
+                    match act.resume {
+                        Some(resume) => Self::resume(&mut stack, resume, val)?,
+                        None => return Ok(val),
+                    }
+                }
+                Term::Raise(exn) => {
+                    let exn = atom(exn)?;
+                    Self::raise(&mut stack, exn)?
+                }
+            }
+        }
+    }
+
+    /** The activation of `fun` applied to `args`, or its value if it's a builtin */
+    fn callee(
+        &mut self,
+        fun: Value<'a>,
+        args: Vec<Value<'a>>,
+    ) -> Result<Result<Activation<'a>, Value<'a>>, Stop> {
+        match &fun {
+            Value::Closure(func, _) => match &**func {
+                Value::Global(name) => {
+                    let args = std::iter::once(fun.clone()).chain(args).collect();
+                    Ok(Ok(Activation::new(self.funcs[name], args)?))
+                }
+                _ => stuck(format!("closure of {func}")),
+            },
+            Value::Global(name) => Ok(Ok(Activation::new(self.funcs[name], args)?)),
+            Value::Builtin(id) => Ok(Err(self.store.builtin(id, args)?)),
+            _ => stuck(format!("calling {fun}")),
+        }
+    }
+
+    /** Calls `fun` with `args`, the caller on top of `stack` doing `resume` with the result */
+    fn enter(
+        &mut self,
+        stack: &mut Vec<Activation<'a>>,
+        fun: Value<'a>,
+        args: Vec<Value<'a>>,
+        resume: Resume<'a>,
+    ) -> Result<(), Stop> {
+        match self.callee(fun, args)? {
+            Ok(mut callee) => {
+                callee.resume = Some(resume);
+                stack.push(callee);
+                Ok(())
+            }
+            Err(val) => Self::resume(stack, resume, val),
+        }
+    }
+
+    fn resume(
+        stack: &mut [Activation<'a>],
+        resume: Resume<'a>,
+        val: Value<'a>,
+    ) -> Result<(), Stop> {
+        let caller = stack.last_mut().unwrap();
+        match resume {
+            Resume::Jump(label) => caller.jump(label, vec![val]),
+            Resume::Spawn(var) => {
+                caller.regs.insert(var, Value::Task(Rc::new(Ok(val))));
+                Ok(())
+            }
+        }
+    }
+
+    /** Raises `exn` to the handler of the innermost block running that has one, or to the task
+    running it */
+    fn raise(stack: &mut Vec<Activation<'a>>, exn: Value<'a>) -> Result<(), Stop> {
+        while let Some(act) = stack.last_mut() {
+            if let Some(handler) = act.func.blocks[act.label].handler {
+                return act.jump(handler, vec![exn]);
+            }
+            // Tasks catch the exceptions they raise
+            if let Some(Resume::Spawn(var)) = stack.pop().unwrap().resume {
+                let caller = stack.last_mut().unwrap();
+                caller.regs.insert(var, Value::Task(Rc::new(Err(exn))));
+                return Ok(());
+            }
+        }
+        Err(Stop::Uncaught(exn.to_string()))
+    }
+}
@@ -0,0 +1,171 @@
+/*! Loop-invariant code motion of programs in static single assignment form. An instruction of a
+[loop](crate::ir::loops) is invariant when its operands are assigned outside of the loop, by
+invariant instructions, or are parameters of the header every back edge passes back unchanged,
+like the closure of a tail recursive function. Invariant instructions that can't raise nor read
+mutable memory are hoisted to a preheader, a block jumping to the header that the edges entering
+the loop go to instead, the entry of the function for loops of the entry. Inner loops go first, so
+that what they hoist may be hoisted from the loops they nest in. */
+
+use crate::ir::anf::{Atom, Fresh, Var};
+use crate::ir::effects;
+use crate::ir::loops::Loop;
+use crate::ir::pass::{Analyses, Pass, Run};
+use crate::ir::ssa::{Block, Func, Label, Op, Prog, Term};
+
+use std::collections::{HashMap, HashSet};
+
+/// The pass moving loop-invariant code
+pub const PASS: Pass = Pass {
+    name: "licm",
+    run: Run::Analyzed(licm),
+    requires: &[],
+    once: false,
+};
+
+/** Hoists the loop-invariant instructions of `prog` out of their loops */
+pub fn licm(prog: &Prog, analyses: &mut Analyses) -> Prog {
+    let mut fresh = prog.fresh();
+    let mut hoist_all = |funcs: &[Func]| -> Vec<Func> {
+        let hoist = |func: &Func| hoist(func, &analyses.loops(func), &mut fresh);
+        funcs.iter().map(hoist).collect()
+    };
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs: hoist_all(&prog.funcs),
+        decls: hoist_all(&prog.decls),
+    }
+}
+
+/** Whether evaluating `op` where it wouldn't be can't go wrong */
+fn speculatable(op: &Op) -> bool {
+    match op {
+        // Of a constructor tested first
+        Op::Field(..) => false,
+        op => effects::op(op).pure(),
+    }
+}
+
+/** `func` with the invariant instructions of `loops`, its loops, hoisted */
+fn hoist(func: &Func, loops: &[Loop], fresh: &mut Fresh) -> Func {
+    let mut func = func.clone();
+    let mut loops = loops.to_vec();
+    let handlers: HashSet<Label> = func.blocks.iter().filter_map(|b| b.handler).collect();
+    // Innermost first, the loop of the entry last as its preheader becomes the entry
+    let mut order: Vec<usize> = (0..loops.len()).rev().collect();
+    order.sort_by_key(|i| loops[*i].header == 0);
+    for i in order {
+        let header = loops[i].header;
+        let (hoisted, invariant) = invariants(&func, &loops[i]);
+        if hoisted.is_empty() || handlers.contains(&header) {
+            continue;
+        }
+        let params: Vec<Var> = func.blocks[header]
+            .params
+            .iter()
+            .map(|param| fresh.var(param))
+            .collect();
+        // The invariant parameters, by the arguments of the preheader they're passed
+        let renaming: HashMap<&Var, Atom> = func.blocks[header]
+            .params
+            .iter()
+            .zip(&params)
+            .filter(|(param, _)| invariant.contains(*param))
+            .map(|(param, arg)| (param, Atom::Var(arg.clone())))
+            .collect();
+        let rename = |atom: &Atom| match atom.var().and_then(|var| renaming.get(var)) {
+            Some(arg) => arg.clone(),
+            None => atom.clone(),
+        };
+        let mut insts = vec![];
+        for (label, index) in &hoisted {
+            let mut inst = func.blocks[*label].insts[*index].clone();
+            inst.op = inst.op.map_atoms(rename);
+            insts.push(inst)
+        }
+        for (label, block) in func.blocks.iter_mut().enumerate() {
+            let insts = block.insts.drain(..).enumerate();
+            let kept = insts.filter(|(index, _)| !hoisted.contains(&(label, *index)));
+            block.insts = kept.map(|(_, inst)| inst).collect()
+        }
+        let preheader = func.blocks.len();
+        let args = params.iter().cloned().map(Atom::Var).collect();
+        let mut pre = Block {
+            params,
+            insts,
+            term: Term::Jump(header, args),
+            handler: None,
+        };
+        if header == 0 {
+            // The header moves to the end, after the preheader takes its place
+            let relabel = |l: Label| if l == 0 { preheader } else { l };
+            for block in &mut func.blocks {
+                block.term = block.term.map_labels(relabel);
+                block.handler = block.handler.map(relabel)
+            }
+            pre.term = Term::Jump(preheader, pre.term.atoms().into_iter().cloned().collect());
+            let entry = std::mem::replace(&mut func.blocks[0], pre);
+            func.blocks.push(entry);
+            continue;
+        }
+        let entering = |l: Label| if l == header { preheader } else { l };
+        for (label, block) in func.blocks.iter_mut().enumerate() {
+            if !loops[i].contains(label) {
+                block.term = block.term.map_labels(entering)
+            }
+        }
+        func.blocks.push(pre);
+        // The preheader is in the loops this one nests in
+        let mut parent = loops[i].parent;
+        while let Some(outer) = parent {
+            loops[outer].blocks.push(preheader);
+            parent = loops[outer].parent
+        }
+    }
+    func
+}
+
+/** The invariant instructions of `l` hoisted, by block and index, in an order they can be
+evaluated in, and the invariant registers of the loop */
+fn invariants(func: &Func, l: &Loop) -> (Vec<(Label, usize)>, HashSet<Var>) {
+    let blocks = l.blocks.iter().map(|label| &func.blocks[*label]);
+    let assigned: HashSet<&Var> = blocks
+        .flat_map(|block| {
+            block
+                .params
+                .iter()
+                .chain(block.insts.iter().map(|i| &i.var))
+        })
+        .collect();
+    let header = &func.blocks[l.header].params;
+    let passed_back = |index: usize, param: &Var| {
+        l.latches
+            .iter()
+            .all(|latch| match &func.blocks[*latch].term {
+                Term::Jump(_, args) => args[index].var() == Some(param),
+                _ => false,
+            })
+    };
+    let mut invariant: HashSet<Var> = header
+        .iter()
+        .enumerate()
+        .filter(|(index, param)| passed_back(*index, param))
+        .map(|(_, param)| param.clone())
+        .collect();
+    let mut hoisted = vec![];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for label in &l.blocks {
+            for (index, inst) in func.blocks[*label].insts.iter().enumerate() {
+                let mut vars = inst.op.atoms().into_iter().filter_map(Atom::var);
+                let operands = vars.all(|var| !assigned.contains(var) || invariant.contains(var));
+                if operands && speculatable(&inst.op) && !invariant.contains(&inst.var) {
+                    hoisted.push((*label, index));
+                    invariant.insert(inst.var.clone());
+                    changed = true
+                }
+            }
+        }
+    }
+    (hoisted, invariant)
+}
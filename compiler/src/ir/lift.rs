@@ -0,0 +1,139 @@
+/*! Lambda lifting of programs in A-normal form. The functions of a `fix` not referring to the local
+variables around it are lifted to the top level of the program, inner ones first. After
+[closure conversion](crate::ir::closure) every function is closed, and the program becomes flat: its
+functions are first order, at the top level, and the bodies of the declarations and functions
+have no `fix` left. Variables are named apart in the whole program, so lifted functions keep their
+names. */
+
+use crate::ast::ast::grow;
+use crate::ir::anf::{Comp, Decl, Expr, Func, Prog, Var};
+use crate::ir::pass::{Pass, Run};
+
+use im::HashSet;
+
+/// The pass lifting functions, after closure conversion
+pub const PASS: Pass = Pass {
+    name: "lift",
+    run: Run::Anf(lift),
+    requires: &["closure"],
+    once: false,
+};
+
+/** Lifts the closed functions of `prog` to its top level */
+pub fn lift(prog: &Prog) -> Prog {
+    let mut lifted = vec![];
+    for func in &prog.funcs {
+        let params = func.params.iter().cloned().collect();
+        let body = lift_expr(&func.body, &params, &mut lifted);
+        lifted.push(Func {
+            body,
+            ..func.clone()
+        })
+    }
+    let decls = prog.decls.iter().map(|decl| Decl {
+        body: lift_expr(&decl.body, &HashSet::new(), &mut lifted),
+        ..decl.clone()
+    });
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        decls: decls.collect(),
+        funcs: lifted,
+    }
+}
+
+/** `expr` with its closed functions pushed to `lifted`, where `locals` are the variables bound
+outside of it in the function or declaration */
+fn lift_expr(expr: &Expr, locals: &HashSet<Var>, lifted: &mut Vec<Func>) -> Expr {
+    grow(|| {
+        let mut lift =
+            |expr: &Expr, locals: &HashSet<Var>| Box::new(lift_expr(expr, locals, lifted));
+        match expr {
+            Expr::Let { var, comp, body } => {
+                let comp = match comp {
+                    Comp::Handle { body, exn, handler } => Comp::Handle {
+                        body: lift(body, locals),
+                        exn: exn.clone(),
+                        handler: lift(handler, &locals.update(exn.clone())),
+                    },
+                    comp => comp.clone(),
+                };
+                Expr::Let {
+                    var: var.clone(),
+                    comp,
+                    body: lift(body, &locals.update(var.clone())),
+                }
+            }
+            Expr::Fix { funcs, body } => {
+                let names: HashSet<Var> = funcs.iter().map(|f| f.name.clone()).collect();
+                let closed = funcs.iter().all(|f| {
+                    let params = f.params.iter().cloned().collect();
+                    let free = f.body.free_vars().relative_complement(params);
+                    free.intersection(locals.clone()).is_empty()
+                });
+                let scope = match closed {
+                    true => HashSet::new(),
+                    false => locals.clone().union(names),
+                };
+                let funcs: Vec<Func> = funcs
+                    .iter()
+                    .map(|f| {
+                        let params = f.params.iter().cloned().collect();
+                        Func {
+                            body: lift_expr(&f.body, &scope.clone().union(params), lifted),
+                            ..f.clone()
+                        }
+                    })
+                    .collect();
+                if closed {
+                    lifted.extend(funcs);
+                    return lift_expr(body, locals, lifted);
+                }
+                let locals = funcs
+                    .iter()
+                    .fold(locals.clone(), |locals, f| locals.update(f.name.clone()));
+                Expr::Fix {
+                    funcs,
+                    body: Box::new(lift_expr(body, &locals, lifted)),
+                }
+            }
+            Expr::Join {
+                name,
+                params,
+                join,
+                body,
+            } => {
+                let join_locals = params
+                    .iter()
+                    .fold(locals.clone(), |locals, p| locals.update(p.clone()));
+                Expr::Join {
+                    name: name.clone(),
+                    params: params.clone(),
+                    join: lift(join, &join_locals),
+                    body: lift(body, locals),
+                }
+            }
+            Expr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => Expr::If {
+                cond: cond.clone(),
+                branch_t: lift(branch_t, locals),
+                branch_f: lift(branch_f, locals),
+            },
+            Expr::Case {
+                scrut,
+                arms,
+                default,
+            } => Expr::Case {
+                scrut: scrut.clone(),
+                arms: arms
+                    .iter()
+                    .map(|(c, e)| (c.clone(), *lift(e, locals)))
+                    .collect(),
+                default: default.as_ref().map(|e| lift(e, locals)),
+            },
+            Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => expr.clone(),
+        }
+    })
+}
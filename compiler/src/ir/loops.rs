@@ -0,0 +1,92 @@
+/*! Natural loops of functions in static single assignment form. An edge from a block to one
+dominating it is a back edge, to the header of a loop, from a latch. The loop is the header and the
+blocks reaching a latch without going through the header, the loops of one header merged. Loops
+nest in the loops with blocks including theirs. Tail recursive functions loop back to their entry
+once [tail calls](crate::ir::tail) are optimized. */
+
+use crate::ir::dominators::Dominators;
+use crate::ir::ssa::{Func, Label};
+
+/// Natural loops
+#[derive(Debug, PartialEq, Clone)]
+pub struct Loop {
+    pub header: Label,
+    /// The blocks with back edges to the header, in order
+    pub latches: Vec<Label>,
+    /// The blocks of the loop, the header included, in order
+    pub blocks: Vec<Label>,
+    /// The innermost loop this one nests in, by its index
+    pub parent: Option<usize>,
+    /// The number of loops this one nests in, plus one
+    pub depth: usize,
+}
+
+impl Loop {
+    /** Whether block `label` is in the loop */
+    pub fn contains(&self, label: Label) -> bool {
+        self.blocks.binary_search(&label).is_ok()
+    }
+}
+
+/** The loops of `func`, with `doms` its dominators, outer loops before those nested in them */
+pub fn loops(func: &Func, doms: &Dominators) -> Vec<Loop> {
+    let mut preds = vec![vec![]; func.blocks.len()];
+    let mut latches = vec![vec![]; func.blocks.len()];
+    for (label, block) in func.blocks.iter().enumerate() {
+        if !doms.reachable(label) {
+            continue;
+        }
+        for succ in block.successors() {
+            preds[succ].push(label);
+            if doms.dominates(succ, label) {
+                latches[succ].push(label)
+            }
+        }
+    }
+    let mut loops = vec![];
+    for (header, latches) in latches.into_iter().enumerate() {
+        if latches.is_empty() {
+            continue;
+        }
+        let mut blocks = vec![header];
+        let mut todo = latches.clone();
+        while let Some(label) = todo.pop() {
+            if !blocks.contains(&label) {
+                blocks.push(label);
+                todo.extend(preds[label].iter().copied())
+            }
+        }
+        blocks.sort();
+        loops.push(Loop {
+            header,
+            latches,
+            blocks,
+            parent: None,
+            depth: 1,
+        })
+    }
+    // Loops containing others are larger
+    loops.sort_by_key(|l| std::cmp::Reverse(l.blocks.len()));
+    for inner in 0..loops.len() {
+        let header = loops[inner].header;
+        let outer = (0..inner)
+            .rev()
+            .find(|outer| loops[*outer].contains(header));
+        if let Some(outer) = outer {
+            loops[inner].parent = Some(outer);
+            loops[inner].depth = loops[outer].depth + 1
+        }
+    }
+    loops
+}
+
+/** The number of loops of `loops` each block of `func` is in */
+pub fn depths(func: &Func, loops: &[Loop]) -> Vec<usize> {
+    let mut depths = vec![0; func.blocks.len()];
+    for l in loops {
+        for label in &l.blocks {
+            depths[*label] = depths[*label].max(l.depth)
+        }
+    }
+    depths
+}
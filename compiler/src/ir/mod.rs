@@ -0,0 +1,23 @@
+pub mod anf;
+pub mod closure;
+pub mod copy;
+pub mod cse;
+pub mod dataflow;
+pub mod dce;
+pub mod dominators;
+pub mod dot;
+pub mod effects;
+pub mod flatten;
+pub mod fold;
+pub mod inline;
+pub mod interp;
+pub mod licm;
+pub mod lift;
+pub mod loops;
+pub mod pass;
+pub mod repr;
+pub mod ssa;
+pub mod tail;
+pub mod text;
+pub mod uncurry;
+pub mod verify;
@@ -0,0 +1,343 @@
+/*! The pass manager, running the passes of a pipeline on programs lowered to
+[A-normal form](crate::ir::anf) until they're in [static single assignment form](crate::ir::ssa).
+Each pass declares itself as a [`Pass`] in its module, registered in [`PASSES`]. Pipelines are the
+presets of the optimization levels `-O0`, `-O1` and `-O2`, or lists of passes by name like
+`--passes=closure,lift,ssa`, checked to run each pass on the IR it takes, after the passes it
+requires. Hooks run after every pass, to time passes and dump the IR they produce, and the IR can be
+verified after each. Passes on static single assignment form may ask for [`Analyses`] of functions,
+kept until a pass runs. */
+
+use crate::ast::ast;
+use crate::ast::error::TypeError;
+use crate::ast::lower;
+use crate::ast::mono::monomorphize;
+use crate::ast::peval::{peval, FUEL};
+use crate::ir::anf::Var;
+use crate::ir::dataflow::{liveness, Solution};
+use crate::ir::dominators::{dominators, Dominators};
+use crate::ir::effects::{self, summaries, Summaries};
+use crate::ir::loops::{loops, Loop};
+use crate::ir::verify::{verify_anf, verify_ssa, VerifyError};
+use crate::ir::{
+    anf, closure, copy, cse, dce, flatten, fold, inline, licm, lift, repr, ssa, tail, uncurry,
+};
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Programs in either intermediate representation
+#[derive(Debug, PartialEq, Clone)]
+pub enum Ir {
+    Anf(anf::Prog),
+    Ssa(ssa::Prog),
+}
+
+/// What a pass does, by the IR it takes and produces
+#[derive(Clone, Copy)]
+pub enum Run {
+    Anf(fn(&anf::Prog) -> anf::Prog),
+    /// Passes on A-normal form using analyses
+    AnfAnalyzed(fn(&anf::Prog, &mut Analyses) -> anf::Prog),
+    /// Constructing static single assignment form
+    Construct(fn(&anf::Prog) -> ssa::Prog),
+    Ssa(fn(&ssa::Prog) -> ssa::Prog),
+    /// Passes on static single assignment form using analyses
+    Analyzed(fn(&ssa::Prog, &mut Analyses) -> ssa::Prog),
+}
+
+/// Analyses of the functions of a program in static single assignment form, by name, and of the
+/// program in A-normal form, computed when asked for and kept until the program changes
+#[derive(Default)]
+pub struct Analyses {
+    effects: Option<Rc<Summaries>>,
+    dominators: HashMap<Var, Rc<Dominators>>,
+    loops: HashMap<Var, Rc<Vec<Loop>>>,
+    liveness: HashMap<Var, Rc<Solution<im::HashSet<Var>>>>,
+}
+
+impl Analyses {
+    /** The effects of the functions of `prog` */
+    pub fn effects(&mut self, prog: &anf::Prog) -> Rc<Summaries> {
+        let effects = self.effects.get_or_insert_with(|| Rc::new(summaries(prog)));
+        effects.clone()
+    }
+
+    /** The dominators of the blocks of `func` */
+    pub fn dominators(&mut self, func: &ssa::Func) -> Rc<Dominators> {
+        let doms = self.dominators.entry(func.name.clone());
+        doms.or_insert_with(|| Rc::new(dominators(func))).clone()
+    }
+
+    /** The loops of `func` */
+    pub fn loops(&mut self, func: &ssa::Func) -> Rc<Vec<Loop>> {
+        if let Some(loops) = self.loops.get(&func.name) {
+            return loops.clone();
+        }
+        let found = Rc::new(loops(func, &self.dominators(func)));
+        self.loops.insert(func.name.clone(), found.clone());
+        found
+    }
+
+    /** The registers live at the entry and exit of the blocks of `func` */
+    pub fn liveness(&mut self, func: &ssa::Func) -> Rc<Solution<im::HashSet<Var>>> {
+        let live = self.liveness.entry(func.name.clone());
+        live.or_insert_with(|| Rc::new(liveness(func))).clone()
+    }
+
+    /** Forgets the analyses, of a program that changed */
+    pub fn invalidate(&mut self) {
+        *self = Analyses::default()
+    }
+}
+
+/// Passes, named for pipelines and hooks
+#[derive(Clone, Copy)]
+pub struct Pass {
+    pub name: &'static str,
+    pub run: Run,
+    /// The passes the IR it takes must have been through, like `closure` before `lift`
+    pub requires: &'static [&'static str],
+    /// Whether running it again breaks the IR, like deciding the representation of values twice
+    pub once: bool,
+}
+
+/// The passes pipelines are made of
+pub const PASSES: &[Pass] = &[
+    fold::PASS,
+    dce::PASS,
+    inline::PASS,
+    uncurry::PASS,
+    closure::PASS,
+    lift::PASS,
+    ssa::PASS,
+    copy::PASS,
+    flatten::PASS,
+    cse::PASS,
+    tail::PASS,
+    licm::PASS,
+    repr::PASS,
+];
+
+/// Optimization levels, the presets of pipelines
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Level {
+    /// Lowering only
+    O0,
+    #[default]
+    O1,
+    O2,
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Level::O0),
+            "1" => Ok(Level::O1),
+            "2" => Ok(Level::O2),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Level {
+    /** The names of the passes of the pipeline at this level */
+    pub fn passes(self) -> Vec<&'static str> {
+        match self {
+            // Tail calls are optimized at every level, and the representation of values decided
+            Level::O0 => vec!["closure", "lift", "ssa", "tail", "repr"],
+            Level::O1 => vec![
+                "fold", "dce", "uncurry", "closure", "lift", "ssa", "copy", "flatten", "tail",
+                "repr",
+            ],
+            Level::O2 => vec![
+                "fold", "dce", "inline", "fold", "dce", "uncurry", "closure", "lift", "ssa",
+                "copy", "flatten", "cse", "tail", "licm", "repr",
+            ],
+        }
+    }
+}
+
+/// Hooks run after every pass with its name, the IR it produced and the time it took
+pub type Hook<'a> = Box<dyn FnMut(&str, &Ir, Duration) + 'a>;
+
+/// Pipelines of passes, and what runs around them
+pub struct PassManager<'a> {
+    passes: Vec<Pass>,
+    /// Whether the IR is verified after every pass, and before the first
+    pub verify: bool,
+    analyses: Analyses,
+    /// The declaration the program is run for, the others it doesn't use pruned before the first
+    /// pass
+    pub entry: Option<String>,
+    /// The fuel declarations are [partially evaluated](crate::ast::peval) with when lowered, if
+    /// they are
+    pub fuel: Option<usize>,
+    /// Whether polymorphic declarations are [monomorphized](crate::ast::mono) when lowered, so no
+    /// type abstraction is left to represent
+    pub mono: bool,
+    hooks: Vec<Hook<'a>>,
+}
+
+/// The IR produced by a pass failing verification
+#[derive(Debug, PartialEq, Clone)]
+pub struct PassError {
+    /// The pass, `lower` if the IR was broken before any
+    pub pass: String,
+    pub err: VerifyError,
+}
+
+impl Display for PassError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "IR verification failed after {}. {}",
+            self.pass, self.err
+        )
+    }
+}
+
+impl Display for Ir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ir::Anf(prog) => write!(f, "{prog}"),
+            Ir::Ssa(prog) => write!(f, "{prog}"),
+        }
+    }
+}
+
+impl Ir {
+    fn verify(&self) -> Result<(), VerifyError> {
+        match self {
+            Ir::Anf(prog) => verify_anf(prog),
+            Ir::Ssa(prog) => verify_ssa(prog),
+        }
+    }
+}
+
+impl<'a> PassManager<'a> {
+    /** The pipeline of the preset of `level` */
+    pub fn preset(level: Level) -> Self {
+        let mut manager = Self::with_passes(&level.passes()).expect("presets are well formed");
+        // Declarations are evaluated at compile time when optimizing
+        manager.fuel = (level != Level::O0).then_some(FUEL);
+        manager
+    }
+
+    /** The pipeline of the passes `names`, in order. Returns: The pipeline, or what's wrong with
+    it if a name is no pass's, or a pass is run on the IR it doesn't take, before the passes it
+    requires, or again when it runs once */
+    pub fn with_passes(names: &[&str]) -> Result<Self, String> {
+        let mut passes: Vec<Pass> = vec![];
+        let mut in_ssa = false;
+        for name in names {
+            let pass = PASSES.iter().find(|pass| pass.name == *name);
+            let pass = pass.ok_or_else(|| format!("No pass is named {name}"))?;
+            let ran = |name: &str| passes.iter().any(|pass| pass.name == name);
+            if let Some(required) = pass.requires.iter().find(|required| !ran(required)) {
+                return Err(format!("{name} runs after {required}"));
+            }
+            if pass.once && ran(name) {
+                return Err(format!("{name} runs once"));
+            }
+            match (pass.run, in_ssa) {
+                (Run::Anf(_) | Run::AnfAnalyzed(_) | Run::Construct(_), true) => {
+                    return Err(format!("{name} runs on A-normal form, not after ssa"))
+                }
+                (Run::Ssa(_) | Run::Analyzed(_), false) => {
+                    return Err(format!(
+                        "{name} runs on static single assignment form, after ssa"
+                    ))
+                }
+                (Run::Construct(_), false) => in_ssa = true,
+                _ => (),
+            }
+            passes.push(*pass)
+        }
+        Ok(PassManager {
+            passes,
+            verify: false,
+            analyses: Analyses::default(),
+            entry: None,
+            fuel: None,
+            mono: false,
+            hooks: vec![],
+        })
+    }
+
+    /** The names of the passes, in order */
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name).collect()
+    }
+
+    /** Adds `hook`, run after every pass after those added before */
+    pub fn hook(&mut self, hook: impl FnMut(&str, &Ir, Duration) + 'a) {
+        self.hooks.push(Box::new(hook))
+    }
+
+    /** Lowers `prog` to A-normal form, monomorphized if the pipeline is, then its declarations
+    partially evaluated with the fuel of the pipeline. Returns: The program lowered, or the type
+    error found checking or monomorphizing it */
+    pub fn lower(&mut self, prog: &ast::Prog) -> Result<anf::Prog, TypeError> {
+        let mut prog = lower::lower_prog(prog)?;
+        if self.mono {
+            prog = monomorphize(&prog)?
+        }
+        let lowered = anf::lower_checked(&prog);
+        let Some(fuel) = self.fuel else {
+            return Ok(lowered);
+        };
+        // Evaluation is stuck on references and arrays anyway
+        let effects = self.analyses.effects(&lowered);
+        let evaluable = |id: &str| {
+            let decl = lowered.decl(id);
+            let effects = decl.map(|decl| effects::expr(&decl.body, &effects));
+            effects.is_some_and(|e| !e.reads && !e.writes && !e.allocates)
+        };
+        let evaluated = peval(&prog, fuel, evaluable);
+        self.analyses.invalidate();
+        Ok(anf::lower_checked(&evaluated))
+    }
+
+    /** Runs the passes on `prog`. Returns: The program they produce, or the first pass to break
+    an invariant of the IR when verifying */
+    pub fn run(&mut self, prog: anf::Prog) -> Result<Ir, PassError> {
+        let mut ir = match &self.entry {
+            Some(entry) => Ir::Anf(dce::prune(&prog, entry)),
+            None => Ir::Anf(prog),
+        };
+        let verify = |pass: &str, ir: &Ir| {
+            ir.verify().map_err(|err| PassError {
+                pass: pass.to_string(),
+                err,
+            })
+        };
+        if self.verify {
+            verify("lower", &ir)?
+        }
+        for pass in &self.passes {
+            let start = Instant::now();
+            ir = match (pass.run, &ir) {
+                (Run::Anf(run), Ir::Anf(prog)) => Ir::Anf(run(prog)),
+                (Run::AnfAnalyzed(run), Ir::Anf(prog)) => Ir::Anf(run(prog, &mut self.analyses)),
+                (Run::Construct(run), Ir::Anf(prog)) => Ir::Ssa(run(prog)),
+                (Run::Ssa(run), Ir::Ssa(prog)) => Ir::Ssa(run(prog)),
+                (Run::Analyzed(run), Ir::Ssa(prog)) => Ir::Ssa(run(prog, &mut self.analyses)),
+                _ => unreachable!("pipelines are checked to run passes on the IR they take"),
+            };
+            let elapsed = start.elapsed();
+            self.analyses.invalidate();
+            if self.verify {
+                verify(pass.name, &ir)?
+            }
+            for hook in &mut self.hooks {
+                hook(pass.name, &ir, elapsed)
+            }
+        }
+        Ok(ir)
+    }
+}
@@ -0,0 +1,213 @@
+/*! The uniform representation of values, decided on programs in static single assignment form.
+Every value is one machine word, so that polymorphic code, closures and the fields of tuples and
+constructors handle values of any type alike. Words with the lowest bit set are immediates: the
+integer or boolean `n` is `2n + 1`, `()`, `null` and `false` are `1`, `true` is `3`, and a
+constructor without fields is the tag of its index. Integers thus have one bit less than the word,
+wrapping around like those of the machine do. Words with the lowest bit clear are boxed values,
+pointers to blocks on the heap: a header word, the index of the constructor or the number of
+fields, then one word per field, those of tuples, records, constructors, closures with their
+function first, references and arrays. String literals are boxed too, in static data. Integers of
+`Int8` and `Int16` are immediates like those of `Int`, while those of `Int32` and `Int64` don't
+fit in one and are boxed, in blocks of their words, the lowest first.
+
+The representation is made explicit: arithmetic works on raw integers, so [`represent`] untags its
+operands and tags its results with [`Tag`](Op::Tag) and [`Untag`](Op::Untag). Comparisons work on
+tagged integers as well, their raw boolean tagged. Tagging a result then untagging it again is
+skipped. Arithmetic on `Int8` and `Int16` converts its raw result to the width, masking shift
+amounts to it first, and that on `Int32` unboxes its operands and boxes its result with
+[`Box`](Op::Box) and [`Unbox`](Op::Unbox). That on `Int64`, done by the runtime, takes and makes
+boxes itself. Constants are raw where operands are untagged, and tagged, or boxed if they're of
+`Int32` or `Int64`, everywhere else, by code generation. */
+
+use crate::ast::ast::{Binary, Constant, Width};
+use crate::ir::anf::{Atom, Fresh, Prim, Var};
+use crate::ir::pass::{Pass, Run};
+use crate::ir::ssa::{Block, Func, Inst, Op, Prog};
+
+use std::collections::{HashMap, HashSet};
+
+/// The pass deciding the representation of values
+pub const PASS: Pass = Pass {
+    name: "repr",
+    run: Run::Ssa(represent),
+    requires: &["ssa"],
+    once: true,
+};
+
+/// How the values of registers are represented
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Repr {
+    /// Machine integers, never pointers
+    Raw,
+    /// Tagged immediates
+    Immediate,
+    /// Pointers to blocks on the heap
+    Boxed,
+    /// Either an immediate or a boxed value
+    Uniform,
+}
+
+/** The representation of the value of `op`, once [`represent`]ed */
+pub fn repr(op: &Op) -> Repr {
+    match op {
+        Op::Binop(_, Binary::And | Binary::Or, _) | Op::Tag(_) => Repr::Immediate,
+        Op::Prim(Prim::Arith(Binary::Lt | Binary::Gt, _), _) => Repr::Raw,
+        Op::Prim(Prim::Arith(_, Width::W32 | Width::W64), _) => Repr::Boxed,
+        Op::Prim(Prim::Convert(_, Width::W32 | Width::W64), _) | Op::Box(..) => Repr::Boxed,
+        Op::Binop(..) | Op::Prim(Prim::Arith(..) | Prim::Convert(..), _) => Repr::Raw,
+        Op::Untag(_) | Op::Unbox(_) => Repr::Raw,
+        Op::Data(_, fields) if fields.is_empty() => Repr::Immediate,
+        Op::Tuple(_) | Op::Record(_) | Op::Data(..) | Op::Closure(..) => Repr::Boxed,
+        Op::Prim(Prim::Ref | Prim::Array, _) => Repr::Boxed,
+        _ => Repr::Uniform,
+    }
+}
+
+/** Whether `op` works on raw integers, its operands untagged */
+fn arithmetic(op: &Binary) -> bool {
+    use Binary::*;
+    matches!(
+        op,
+        Add | Sub | Mul | Div | Mod | Land | Lor | Lxor | Shl | Shr
+    )
+}
+
+/** Makes the representation of the values of `prog` explicit */
+pub fn represent(prog: &Prog) -> Prog {
+    let mut fresh = prog.fresh();
+    let mut represent_all = |funcs: &[Func]| -> Vec<Func> {
+        let represent = |func: &Func| represent_func(func, &mut fresh);
+        funcs.iter().map(represent).collect()
+    };
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs: represent_all(&prog.funcs),
+        decls: represent_all(&prog.decls),
+    }
+}
+
+fn represent_func(func: &Func, fresh: &mut Fresh) -> Func {
+    // The raw values of the registers tagging them, so untagged without instructions
+    let mut raws: HashMap<&Var, Var> = HashMap::new();
+    for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+        if repr(&inst.op) == Repr::Raw {
+            raws.insert(&inst.var, fresh.var(&inst.var));
+        }
+    }
+    let mut blocks: Vec<Block> = vec![];
+    for block in &func.blocks {
+        let mut insts = vec![];
+        let untag = |atom: &Atom, fresh: &mut Fresh, insts: &mut Vec<Inst>| match atom.var() {
+            Some(var) if raws.contains_key(var) => Atom::Var(raws[var].clone()),
+            Some(var) => name(var, Op::Untag(atom.clone()), fresh, insts),
+            None => atom.clone(),
+        };
+        // The raw word of a boxed integer, constants raw without instructions unless `all`
+        let unbox = |atom: &Atom, all: bool, fresh: &mut Fresh, insts: &mut Vec<Inst>| match atom {
+            Atom::Con(_) if !all => atom.clone(),
+            _ => name(
+                atom.var().map_or("n", |var| var),
+                Op::Unbox(atom.clone()),
+                fresh,
+                insts,
+            ),
+        };
+        for inst in &block.insts {
+            let var = &inst.var;
+            let op = match &inst.op {
+                Op::Binop(lhs, op, rhs) if arithmetic(op) => {
+                    let lhs = untag(lhs, fresh, &mut insts);
+                    Op::Binop(lhs, op.clone(), untag(rhs, fresh, &mut insts))
+                }
+                Op::Prim(
+                    Prim::Arith(op @ (Binary::Lt | Binary::Gt), Width::W8 | Width::W16),
+                    args,
+                ) => Op::Binop(args[0].clone(), op.clone(), args[1].clone()),
+                Op::Prim(Prim::Arith(op, width @ (Width::W8 | Width::W16)), args) => {
+                    let lhs = untag(&args[0], fresh, &mut insts);
+                    let mut rhs = untag(&args[1], fresh, &mut insts);
+                    if let Binary::Shl | Binary::Shr = op {
+                        let mask = Atom::Con(Constant::Sized(width.bits() as i64 - 1, *width));
+                        rhs = name(var, Op::Binop(rhs, Binary::Land, mask), fresh, &mut insts)
+                    }
+                    let raw = name(var, Op::Binop(lhs, op.clone(), rhs), fresh, &mut insts);
+                    Op::Prim(Prim::Convert(Width::Int, *width), vec![raw])
+                }
+                Op::Prim(Prim::Arith(op, Width::W32), args) => {
+                    let comparison = matches!(op, Binary::Lt | Binary::Gt);
+                    let lhs = unbox(&args[0], comparison, fresh, &mut insts);
+                    let rhs = unbox(&args[1], comparison, fresh, &mut insts);
+                    let raw = Op::Binop(lhs, op.clone(), rhs);
+                    match comparison {
+                        true => raw,
+                        false => Op::Box(Width::W32, name(var, raw, fresh, &mut insts)),
+                    }
+                }
+                Op::Prim(Prim::Convert(from, to), args) if from == to && to.bits() >= 32 => {
+                    Op::Atom(args[0].clone())
+                }
+                Op::Prim(Prim::Convert(from, to), args) => {
+                    let raw = match from {
+                        Width::W32 | Width::W64 => unbox(&args[0], true, fresh, &mut insts),
+                        _ => untag(&args[0], fresh, &mut insts),
+                    };
+                    match to {
+                        Width::W32 | Width::W64 => Op::Box(*to, raw),
+                        _ => Op::Prim(Prim::Convert(*from, *to), vec![raw]),
+                    }
+                }
+                op => op.clone(),
+            };
+            match raws.get(&inst.var) {
+                Some(raw) => {
+                    insts.push(Inst {
+                        var: raw.clone(),
+                        op,
+                    });
+                    insts.push(Inst {
+                        var: inst.var.clone(),
+                        op: Op::Tag(Atom::Var(raw.clone())),
+                    })
+                }
+                None => insts.push(Inst {
+                    var: inst.var.clone(),
+                    op,
+                }),
+            }
+        }
+        blocks.push(Block {
+            params: block.params.clone(),
+            insts,
+            term: block.term.clone(),
+            handler: block.handler,
+        })
+    }
+    // Tags of values only ever used untagged
+    let used: HashSet<Var> = blocks
+        .iter()
+        .flat_map(|block| {
+            let insts = block.insts.iter().flat_map(|inst| inst.op.atoms());
+            insts.chain(block.term.atoms())
+        })
+        .filter_map(Atom::var)
+        .cloned()
+        .collect();
+    for block in &mut blocks {
+        let unused = |inst: &Inst| matches!(inst.op, Op::Tag(_)) && !used.contains(&inst.var);
+        block.insts.retain(|inst| !unused(inst))
+    }
+    Func {
+        name: func.name.clone(),
+        blocks,
+    }
+}
+
+/** Names `op` by a register named after `base`, appending its instruction to `insts` */
+fn name(base: &str, op: Op, fresh: &mut Fresh, insts: &mut Vec<Inst>) -> Atom {
+    let var = fresh.var(base);
+    insts.push(Inst {
+        var: var.clone(),
+        op,
+    });
+    Atom::Var(var)
+}
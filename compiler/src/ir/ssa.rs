@@ -0,0 +1,658 @@
+/*! Static single assignment form, the intermediate representation of functions as control flow
+graphs that programs in [A-normal form](crate::ir::anf) are constructed into after
+[closure conversion](crate::ir::closure) and [lambda lifting](crate::ir::lift). A function is a
+list of basic blocks, the first its entry, each a list of instructions assigning operations on
+atoms to virtual registers and ending with a terminator: a jump, a branch, a call or a return.
+Registers are the variables of A-normal form, assigned once. Instead of φ-functions, blocks take
+parameters, the arguments of the jumps to them. The result of a call is the parameter of the block
+it continues to, and the exceptions raised in a block go to its handler, the parameter of which is
+the exception, or to the caller if it has none. */
+
+use crate::ast::ast::{grow, Binary, Width};
+use crate::ir::anf::{self, Atom, Comp, DataType, Expr, Fresh, Prim, Var};
+use crate::ir::pass::{Pass, Run};
+
+use std::fmt::{self, Display};
+
+use im::HashMap;
+
+/// The pass constructing static single assignment form, after lambda lifting
+pub const PASS: Pass = Pass {
+    name: "ssa",
+    run: Run::Construct(construct),
+    requires: &["closure", "lift"],
+    once: false,
+};
+
+/// Blocks, by their position in their function
+pub type Label = usize;
+
+/// Operations of instructions, those of [`Comp`] but applications and handlers
+#[derive(Debug, PartialEq, Clone)]
+pub enum Op {
+    Atom(Atom),
+    Binop(Atom, Binary, Atom),
+    Tuple(Vec<Atom>),
+    Nth(Atom, usize),
+    Record(Vec<(String, Atom)>),
+    Proj(Atom, String),
+    Data(String, Vec<Atom>),
+    Field(Atom, usize),
+    Closure(Var, Vec<Atom>),
+    Env(Atom, usize),
+    Prim(Prim, Vec<Atom>),
+    /// The uniform representation of a raw integer or boolean, like `tag(x)`. Only after
+    /// [representation](crate::ir::repr) is decided
+    Tag(Atom),
+    /// The raw integer or boolean of a tagged one, like `untag(x)`
+    Untag(Atom),
+    /// The boxed integer of the width holding a raw word, sign extended to that of `Int64`, like
+    /// `box64(x)`
+    Box(Width, Atom),
+    /// The raw lowest word of a boxed integer, or of an integer constant, like `unbox(x)`
+    Unbox(Atom),
+}
+
+/// Instructions like `x = op`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Inst {
+    pub var: Var,
+    pub op: Op,
+}
+
+/// Terminators, ending blocks
+#[derive(Debug, PartialEq, Clone)]
+pub enum Term {
+    /// Jumping to the block with the arguments, like `jump b1(x)`
+    Jump(Label, Vec<Atom>),
+    /// Branching on a boolean, like `if x then b1 else b2`
+    Branch {
+        cond: Atom,
+        branch_t: Label,
+        branch_f: Label,
+    },
+    /// Branching on the constructor of the value of `scrut`, going to `default` for the
+    /// constructors without an arm
+    Switch {
+        scrut: Atom,
+        arms: Vec<(String, Label)>,
+        default: Option<Label>,
+    },
+    /// Calling a function like `call f(x) to b1`, continuing with its result to `ret`
+    Call {
+        fun: Atom,
+        args: Vec<Atom>,
+        ret: Label,
+    },
+    /// Calling a function in tail position like `tailcall f(x)`, returning its result. The callee
+    /// takes the frame of the caller, so that tail recursion runs in constant space
+    TailCall {
+        fun: Atom,
+        args: Vec<Atom>,
+    },
+    Ret(Atom),
+    Raise(Atom),
+}
+
+/// Basic blocks
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub params: Vec<Var>,
+    pub insts: Vec<Inst>,
+    pub term: Term,
+    /// The block handling the exceptions raised in this one
+    pub handler: Option<Label>,
+}
+
+/// Functions, their blocks in order, the first their entry taking their parameters
+#[derive(Debug, PartialEq, Clone)]
+pub struct Func {
+    pub name: Var,
+    pub blocks: Vec<Block>,
+}
+
+/// The entire program
+#[derive(Debug, PartialEq, Clone)]
+pub struct Prog {
+    pub datatypes: Vec<DataType>,
+    pub funcs: Vec<Func>,
+    /// Declarations in order, functions without parameters named after them returning their value
+    pub decls: Vec<Func>,
+}
+
+impl Op {
+    /** The atoms operated on */
+    pub fn atoms(&self) -> Vec<&Atom> {
+        match self {
+            Op::Atom(a)
+            | Op::Nth(a, _)
+            | Op::Proj(a, _)
+            | Op::Field(a, _)
+            | Op::Env(a, _)
+            | Op::Tag(a)
+            | Op::Untag(a)
+            | Op::Box(_, a)
+            | Op::Unbox(a) => vec![a],
+            Op::Binop(lhs, _, rhs) => vec![lhs, rhs],
+            Op::Tuple(args) | Op::Data(_, args) | Op::Prim(_, args) | Op::Closure(_, args) => {
+                args.iter().collect()
+            }
+            Op::Record(fields) => fields.iter().map(|(_, a)| a).collect(),
+        }
+    }
+
+    /** The operation on the atoms `f` maps those of `self` to */
+    pub fn map_atoms(&self, f: impl Fn(&Atom) -> Atom) -> Op {
+        let map = |atoms: &[Atom]| atoms.iter().map(&f).collect();
+        match self {
+            Op::Atom(a) => Op::Atom(f(a)),
+            Op::Binop(lhs, op, rhs) => Op::Binop(f(lhs), op.clone(), f(rhs)),
+            Op::Tuple(entries) => Op::Tuple(map(entries)),
+            Op::Nth(tuple, i) => Op::Nth(f(tuple), *i),
+            Op::Record(fields) => {
+                Op::Record(fields.iter().map(|(l, a)| (l.clone(), f(a))).collect())
+            }
+            Op::Proj(record, label) => Op::Proj(f(record), label.clone()),
+            Op::Data(ctor, fields) => Op::Data(ctor.clone(), map(fields)),
+            Op::Field(data, i) => Op::Field(f(data), *i),
+            Op::Closure(func, env) => Op::Closure(func.clone(), map(env)),
+            Op::Env(clo, i) => Op::Env(f(clo), *i),
+            Op::Prim(prim, args) => Op::Prim(prim.clone(), map(args)),
+            Op::Tag(raw) => Op::Tag(f(raw)),
+            Op::Untag(tagged) => Op::Untag(f(tagged)),
+            Op::Box(width, raw) => Op::Box(*width, f(raw)),
+            Op::Unbox(boxed) => Op::Unbox(f(boxed)),
+        }
+    }
+}
+
+impl Term {
+    /** The atoms operated on */
+    pub fn atoms(&self) -> Vec<&Atom> {
+        match self {
+            Term::Jump(_, args) => args.iter().collect(),
+            Term::Branch { cond: a, .. } | Term::Switch { scrut: a, .. } => vec![a],
+            Term::Call { fun, args, .. } | Term::TailCall { fun, args } => {
+                std::iter::once(fun).chain(args).collect()
+            }
+            Term::Ret(a) | Term::Raise(a) => vec![a],
+        }
+    }
+
+    /** The terminator with the atoms `f` maps those of `self` to */
+    pub fn map_atoms(&self, f: impl Fn(&Atom) -> Atom) -> Term {
+        match self {
+            Term::Jump(label, args) => Term::Jump(*label, args.iter().map(f).collect()),
+            Term::Branch {
+                cond,
+                branch_t,
+                branch_f,
+            } => Term::Branch {
+                cond: f(cond),
+                branch_t: *branch_t,
+                branch_f: *branch_f,
+            },
+            Term::Switch {
+                scrut,
+                arms,
+                default,
+            } => Term::Switch {
+                scrut: f(scrut),
+                arms: arms.clone(),
+                default: *default,
+            },
+            Term::Call { fun, args, ret } => Term::Call {
+                fun: f(fun),
+                args: args.iter().map(f).collect(),
+                ret: *ret,
+            },
+            Term::TailCall { fun, args } => Term::TailCall {
+                fun: f(fun),
+                args: args.iter().map(f).collect(),
+            },
+            Term::Ret(val) => Term::Ret(f(val)),
+            Term::Raise(exn) => Term::Raise(f(exn)),
+        }
+    }
+
+    /** The terminator going to the blocks `f` maps those of `self` to */
+    pub fn map_labels(&self, f: impl Fn(Label) -> Label) -> Term {
+        match self {
+            Term::Jump(label, args) => Term::Jump(f(*label), args.clone()),
+            Term::Branch {
+                cond,
+                branch_t,
+                branch_f,
+            } => Term::Branch {
+                cond: cond.clone(),
+                branch_t: f(*branch_t),
+                branch_f: f(*branch_f),
+            },
+            Term::Switch {
+                scrut,
+                arms,
+                default,
+            } => Term::Switch {
+                scrut: scrut.clone(),
+                arms: arms.iter().map(|(c, l)| (c.clone(), f(*l))).collect(),
+                default: default.map(&f),
+            },
+            Term::Call { fun, args, ret } => Term::Call {
+                fun: fun.clone(),
+                args: args.clone(),
+                ret: f(*ret),
+            },
+            term => term.clone(),
+        }
+    }
+
+    /** The blocks control may go to next, in order */
+    pub fn successors(&self) -> Vec<Label> {
+        match self {
+            Term::Jump(label, _) => vec![*label],
+            Term::Branch {
+                branch_t, branch_f, ..
+            } => vec![*branch_t, *branch_f],
+            Term::Switch { arms, default, .. } => {
+                arms.iter().map(|(_, l)| *l).chain(*default).collect()
+            }
+            Term::Call { ret, .. } => vec![*ret],
+            Term::TailCall { .. } | Term::Ret(_) | Term::Raise(_) => vec![],
+        }
+    }
+}
+
+impl Block {
+    /** The blocks control may go to next, those of the terminator then the handler */
+    pub fn successors(&self) -> Vec<Label> {
+        let mut succs = self.term.successors();
+        succs.extend(self.handler);
+        succs
+    }
+}
+
+impl Func {
+    /** The parameters, those of the entry */
+    pub fn params(&self) -> &[Var] {
+        &self.blocks[0].params
+    }
+
+    /** The arguments of the jumps to each block, by label, or None for the blocks reached
+    otherwise too, the entry and handlers included */
+    pub fn jumps(&self) -> Vec<Option<Vec<&[Atom]>>> {
+        let mut jumps = vec![Some(vec![]); self.blocks.len()];
+        jumps[0] = None;
+        for block in &self.blocks {
+            if let Some(handler) = block.handler {
+                jumps[handler] = None
+            }
+            match &block.term {
+                Term::Jump(label, args) => {
+                    if let Some(passed) = &mut jumps[*label] {
+                        passed.push(&args[..])
+                    }
+                }
+                term => {
+                    for label in term.successors() {
+                        jumps[label] = None
+                    }
+                }
+            }
+        }
+        jumps
+    }
+
+    /** The function without the blocks unreachable from its entry, the others in order */
+    pub fn reachable(&self) -> Func {
+        let mut reached = vec![false; self.blocks.len()];
+        let mut todo = vec![0];
+        while let Some(label) = todo.pop() {
+            if !std::mem::replace(&mut reached[label], true) {
+                todo.extend(self.blocks[label].successors())
+            }
+        }
+        // The new labels of the blocks reached, by their old ones
+        let mut labels = vec![0; self.blocks.len()];
+        let mut next = 0;
+        for (label, reached) in reached.iter().enumerate() {
+            labels[label] = next;
+            next += *reached as usize
+        }
+        let blocks = self.blocks.iter().zip(&reached).filter(|(_, r)| **r);
+        let blocks = blocks.map(|(block, _)| Block {
+            params: block.params.clone(),
+            insts: block.insts.clone(),
+            term: block.term.map_labels(|l| labels[l]),
+            handler: block.handler.map(|l| labels[l]),
+        });
+        Func {
+            name: self.name.clone(),
+            blocks: blocks.collect(),
+        }
+    }
+}
+
+impl Prog {
+    /** The supply of registers not assigned in the program, nor naming its functions */
+    pub fn fresh(&self) -> Fresh {
+        let funcs = self.funcs.iter().chain(&self.decls);
+        let vars = funcs.flat_map(|func| {
+            let blocks = func.blocks.iter().flat_map(|block| {
+                let insts = block.insts.iter().map(|inst| inst.var.as_str());
+                block.params.iter().map(|p| p.as_str()).chain(insts)
+            });
+            std::iter::once(func.name.as_str()).chain(blocks)
+        });
+        Fresh::above(vars)
+    }
+
+    /** The function or declaration `name` */
+    pub fn func(&self, name: &str) -> Option<&Func> {
+        let mut funcs = self.funcs.iter().chain(&self.decls);
+        funcs.find(|func| func.name == name)
+    }
+}
+
+/** Constructs the control flow graphs of `prog`, closure converted and lifted */
+pub fn construct(prog: &anf::Prog) -> Prog {
+    let funcs = prog
+        .funcs
+        .iter()
+        .map(|func| construct_func(&func.name, &func.params, &func.body));
+    let decls = prog
+        .decls
+        .iter()
+        .map(|decl| construct_func(&decl.id, &[], &decl.body));
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs: funcs.collect(),
+        decls: decls.collect(),
+    }
+}
+
+/** The function `name` of `params` evaluating `body`, without `fix` */
+fn construct_func(name: &str, params: &[Var], body: &Expr) -> Func {
+    let mut construction = Construction::default();
+    let entry = construction.block();
+    let open = Open {
+        label: entry,
+        params: params.to_vec(),
+        handler: None,
+        insts: vec![],
+    };
+    construction.expr(body, open, &HashMap::new(), Exit::Ret);
+    Func {
+        name: name.to_string(),
+        blocks: construction.blocks.into_iter().flatten().collect(),
+    }
+}
+
+#[derive(Default)]
+struct Construction {
+    /// The blocks finished, by label
+    blocks: Vec<Option<Block>>,
+}
+
+/// The block being constructed
+struct Open {
+    label: Label,
+    params: Vec<Var>,
+    handler: Option<Label>,
+    insts: Vec<Inst>,
+}
+
+/// Where the value of an expression in tail position goes
+#[derive(Clone, Copy)]
+enum Exit {
+    /// Returned from the function
+    Ret,
+    /// Passed to the block after a handler
+    Jump(Label),
+}
+
+impl Construction {
+    /** The label of a block not constructed yet */
+    fn block(&mut self) -> Label {
+        self.blocks.push(None);
+        self.blocks.len() - 1
+    }
+
+    fn finish(&mut self, open: Open, term: Term) {
+        self.blocks[open.label] = Some(Block {
+            params: open.params,
+            insts: open.insts,
+            term,
+            handler: open.handler,
+        })
+    }
+
+    /** Constructs the blocks of `expr` from `open`, with `joins` the blocks of the join points in
+    scope */
+    fn expr(&mut self, expr: &Expr, mut open: Open, joins: &HashMap<Var, Label>, exit: Exit) {
+        grow(|| {
+            let open_with = |label, params, handler| Open {
+                label,
+                params,
+                handler,
+                insts: vec![],
+            };
+            match expr {
+                Expr::Let { var, comp, body } => {
+                    let op = match comp {
+                        Comp::Atom(a) => Op::Atom(a.clone()),
+                        Comp::Binop(lhs, op, rhs) => {
+                            Op::Binop(lhs.clone(), op.clone(), rhs.clone())
+                        }
+                        Comp::Tuple(entries) => Op::Tuple(entries.to_vec()),
+                        Comp::Nth(tuple, i) => Op::Nth(tuple.clone(), *i),
+                        Comp::Record(fields) => Op::Record(fields.clone()),
+                        Comp::Proj(record, label) => Op::Proj(record.clone(), label.clone()),
+                        Comp::Data(ctor, fields) => Op::Data(ctor.clone(), fields.to_vec()),
+                        Comp::Field(data, i) => Op::Field(data.clone(), *i),
+                        Comp::Closure(func, env) => Op::Closure(func.clone(), env.to_vec()),
+                        Comp::Env(clo, i) => Op::Env(clo.clone(), *i),
+                        Comp::Prim(prim, args) => Op::Prim(prim.clone(), args.to_vec()),
+                        Comp::App(fun, args) => {
+                            let ret = self.block();
+                            let handler = open.handler;
+                            let call = Term::Call {
+                                fun: fun.clone(),
+                                args: args.to_vec(),
+                                ret,
+                            };
+                            self.finish(open, call);
+                            let after = open_with(ret, vec![var.clone()], handler);
+                            return self.expr(body, after, joins, exit);
+                        }
+                        Comp::Handle {
+                            body: handled,
+                            exn,
+                            handler: handling,
+                        } => {
+                            let handler = open.handler;
+                            let after = self.block();
+                            let catch = self.block();
+                            let start = self.block();
+                            self.finish(open, Term::Jump(start, vec![]));
+                            let start = open_with(start, vec![], Some(catch));
+                            self.expr(handled, start, joins, Exit::Jump(after));
+                            let catch = open_with(catch, vec![exn.clone()], handler);
+                            self.expr(handling, catch, joins, Exit::Jump(after));
+                            let after = open_with(after, vec![var.clone()], handler);
+                            return self.expr(body, after, joins, exit);
+                        }
+                    };
+                    open.insts.push(Inst {
+                        var: var.clone(),
+                        op,
+                    });
+                    self.expr(body, open, joins, exit)
+                }
+                Expr::Fix { .. } => unreachable!("functions are lifted before construction"),
+                Expr::Join {
+                    name,
+                    params,
+                    join,
+                    body,
+                } => {
+                    let label = self.block();
+                    let handler = open.handler;
+                    self.expr(body, open, &joins.update(name.clone(), label), exit);
+                    let join_open = open_with(label, params.clone(), handler);
+                    self.expr(join, join_open, joins, exit)
+                }
+                Expr::Jump { name, args } => {
+                    self.finish(open, Term::Jump(joins[name], args.to_vec()))
+                }
+                Expr::If {
+                    cond,
+                    branch_t,
+                    branch_f,
+                } => {
+                    let (label_t, label_f) = (self.block(), self.block());
+                    let handler = open.handler;
+                    let branch = Term::Branch {
+                        cond: cond.clone(),
+                        branch_t: label_t,
+                        branch_f: label_f,
+                    };
+                    self.finish(open, branch);
+                    self.expr(branch_t, open_with(label_t, vec![], handler), joins, exit);
+                    self.expr(branch_f, open_with(label_f, vec![], handler), joins, exit)
+                }
+                Expr::Case {
+                    scrut,
+                    arms,
+                    default,
+                } => {
+                    let labels: Vec<Label> = arms.iter().map(|_| self.block()).collect();
+                    let default_label = default.as_ref().map(|_| self.block());
+                    let handler = open.handler;
+                    let switch = Term::Switch {
+                        scrut: scrut.clone(),
+                        arms: arms
+                            .iter()
+                            .zip(&labels)
+                            .map(|((ctor, _), label)| (ctor.clone(), *label))
+                            .collect(),
+                        default: default_label,
+                    };
+                    self.finish(open, switch);
+                    let bodies = arms.iter().map(|(_, e)| e).chain(default.as_deref());
+                    for (body, label) in bodies.zip(labels.into_iter().chain(default_label)) {
+                        self.expr(body, open_with(label, vec![], handler), joins, exit)
+                    }
+                }
+                Expr::Raise { exn } => self.finish(open, Term::Raise(exn.clone())),
+                Expr::Ret { val } => match exit {
+                    Exit::Ret => self.finish(open, Term::Ret(val.clone())),
+                    Exit::Jump(label) => self.finish(open, Term::Jump(label, vec![val.clone()])),
+                },
+            }
+        })
+    }
+}
+
+/** The items separated by commas */
+fn commas<T: Display>(items: &[T]) -> String {
+    let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+    items.join(", ")
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Atom(atom) => write!(f, "{atom}"),
+            Op::Binop(lhs, op, rhs) => write!(f, "{lhs} {op} {rhs}"),
+            Op::Tuple(entries) => write!(f, "({})", commas(entries)),
+            Op::Nth(tuple, index) => write!(f, "{tuple}.{index}"),
+            Op::Record(fields) => {
+                let fields: Vec<String> =
+                    fields.iter().map(|(l, a)| format!("{l} = {a}")).collect();
+                write!(f, "{{{}}}", fields.join(", "))
+            }
+            Op::Proj(record, label) => write!(f, "{record}.{label}"),
+            Op::Data(ctor, fields) if fields.is_empty() => write!(f, "{ctor}"),
+            Op::Data(ctor, fields) => write!(f, "{ctor}({})", commas(fields)),
+            Op::Field(data, index) => write!(f, "{data}#{index}"),
+            Op::Closure(func, env) => write!(f, "closure {func}({})", commas(env)),
+            Op::Env(clo, index) => write!(f, "{clo}@{index}"),
+            Op::Prim(prim, args) => write!(f, "{prim}({})", commas(args)),
+            Op::Tag(raw) => write!(f, "tag({raw})"),
+            Op::Untag(tagged) => write!(f, "untag({tagged})"),
+            Op::Box(width, raw) => write!(f, "box{width}({raw})"),
+            Op::Unbox(boxed) => write!(f, "unbox({boxed})"),
+        }
+    }
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Term::Jump(label, args) => write!(f, "jump b{label}({})", commas(args)),
+            Term::Branch {
+                cond,
+                branch_t,
+                branch_f,
+            } => write!(f, "if {cond} then b{branch_t} else b{branch_f}"),
+            Term::Switch {
+                scrut,
+                arms,
+                default,
+            } => {
+                let arms = arms.iter().map(|(c, l)| (c.as_str(), l));
+                let arms: Vec<String> = arms
+                    .chain(default.iter().map(|l| ("_", l)))
+                    .map(|(ctor, label)| format!("{ctor} => b{label}"))
+                    .collect();
+                write!(f, "case {scrut} of {}", arms.join(" | "))
+            }
+            Term::Call { fun, args, ret } => write!(f, "call {fun}({}) to b{ret}", commas(args)),
+            Term::TailCall { fun, args } => write!(f, "tailcall {fun}({})", commas(args)),
+            Term::Ret(val) => write!(f, "ret {val}"),
+            Term::Raise(exn) => write!(f, "raise {exn}"),
+        }
+    }
+}
+
+impl Display for Func {
+    /** The blocks, labeled `b0`, `b1`... after a header of the function's name */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        for (label, block) in self.blocks.iter().enumerate() {
+            write!(f, "b{label}")?;
+            if !block.params.is_empty() {
+                write!(f, "({})", block.params.join(", "))?;
+            }
+            if let Some(handler) = block.handler {
+                write!(f, " unwind b{handler}")?;
+            }
+            writeln!(f, ":")?;
+            for inst in &block.insts {
+                writeln!(f, "    {} = {}", inst.var, inst.op)?;
+            }
+            writeln!(f, "    {}", block.term)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Prog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for data in &self.datatypes {
+            let ctors: Vec<String> = data
+                .ctors
+                .iter()
+                .map(|(ctor, arity)| format!("{ctor}{}", " _".repeat(*arity)))
+                .collect();
+            writeln!(f, "data {} = {}", data.id, ctors.join(" | "))?;
+        }
+        for func in &self.funcs {
+            write!(f, "\nfun {func}")?;
+        }
+        for decl in &self.decls {
+            write!(f, "\nlet {decl}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+/*! Tail calls of programs in static single assignment form. A call is in tail position when the
+block it continues to returns its result right away, maybe after jumps passing it on, and no
+handler of the caller catches what the callee raises. Such calls become
+[`TailCall`](Term::TailCall)s, which code generation compiles to jumps reusing the frame of the
+caller, so that tail recursion runs in constant space whatever the optimization level. Calls of a
+function to itself, by its closure, become jumps back to its entry. */
+
+use crate::ir::anf::Atom;
+use crate::ir::pass::{Pass, Run};
+use crate::ir::ssa::{Func, Label, Op, Prog, Term};
+
+use std::collections::HashSet;
+
+/// The pass optimizing tail calls
+pub const PASS: Pass = Pass {
+    name: "tail",
+    run: Run::Ssa(tail),
+    requires: &[],
+    once: false,
+};
+
+/** The blocks of `func` ending with a call in tail position */
+pub fn tail_calls(func: &Func) -> Vec<Label> {
+    let blocks = func.blocks.iter().enumerate();
+    let calls = blocks.filter(|(_, block)| match block.term {
+        Term::Call { ret, .. } => block.handler.is_none() && returns(func, ret),
+        _ => false,
+    });
+    calls.map(|(label, _)| label).collect()
+}
+
+/** Whether block `label` of `func` returns its parameter right away */
+fn returns(func: &Func, label: Label) -> bool {
+    let mut seen = HashSet::new();
+    let mut label = label;
+    while seen.insert(label) {
+        let block = &func.blocks[label];
+        let [param] = &block.params[..] else {
+            return false;
+        };
+        let passed = |atom: &Atom| atom.var() == Some(param);
+        match &block.term {
+            _ if !block.insts.is_empty() => return false,
+            Term::Ret(val) => return passed(val),
+            Term::Jump(next, args) if args.len() == 1 && passed(&args[0]) => label = *next,
+            _ => return false,
+        }
+    }
+    // Looping forever
+    false
+}
+
+/** Turns the calls in tail position of `prog` into tail calls */
+pub fn tail(prog: &Prog) -> Prog {
+    // Functions of closures take their own closure first
+    let mut closures = HashSet::new();
+    for func in prog.funcs.iter().chain(&prog.decls) {
+        for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+            if let Op::Closure(name, _) = &inst.op {
+                closures.insert(name.as_str());
+            }
+        }
+    }
+    let tail_func = |func: &Func| {
+        let own = match func.params().first() {
+            Some(clo) if closures.contains(func.name.as_str()) => Some(Atom::Var(clo.clone())),
+            _ => None,
+        };
+        let mut blocks = func.blocks.clone();
+        for label in tail_calls(func) {
+            let Term::Call { fun, args, .. } = &blocks[label].term else {
+                unreachable!("tail calls are calls")
+            };
+            blocks[label].term = match own.as_ref() {
+                Some(own) if fun == own && args.len() + 1 == func.params().len() => {
+                    Term::Jump(0, std::iter::once(fun).chain(args).cloned().collect())
+                }
+                _ => Term::TailCall {
+                    fun: fun.clone(),
+                    args: args.clone(),
+                },
+            }
+        }
+        let func = Func {
+            name: func.name.clone(),
+            blocks,
+        };
+        func.reachable()
+    };
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs: prog.funcs.iter().map(tail_func).collect(),
+        decls: prog.decls.iter().map(tail_func).collect(),
+    }
+}
@@ -0,0 +1,794 @@
+/*! Textual syntax of the intermediate representations, that of their printers, parsed back into
+programs so that passes can be tested on IR written by hand and bugs reproduced from dumped IR.
+The core language is the source language, read by [`parse_prog`](crate::ast::parse::parse_prog).
+Programs of [A-normal form](crate::ir::anf) and [static single assignment form](crate::ir::ssa)
+are read by [`parse_anf`] and [`parse_ssa`]. Whitespace is insignificant, and words are keywords
+only where the syntax expects them. Constructors are told apart from variables by the `data`
+declarations, which come first. */
+
+use crate::ast::ast::{grow, Binary, Constant, Width};
+use crate::ir::anf::{self, Atom, Comp, DataType, Decl, Expr, Prim, Var};
+use crate::ir::ssa::{self, Block, Inst, Label, Op, Term};
+
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::ops::Range;
+
+use logos::Logos;
+
+/// Errors of parsing, at a byte offset of the source
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+#[derive(Logos, Debug, PartialEq, Clone, Copy)]
+#[logos(skip r"[ \t\n\r]+")]
+enum Tok<'a> {
+    #[regex(r"[A-Za-z][0-9A-Za-z_$']*", |lex| lex.slice())]
+    Word(&'a str),
+    /// Integers, with the suffix `i8`... of their width if they're sized
+    #[regex(r"-?[0-9]+(i(8|16|32|64))?", |lex| lex.slice())]
+    Int(&'a str),
+    #[regex(r#""([^"\\]|\\.)*""#, |lex| lex.slice())]
+    Str(&'a str),
+    #[regex(r"[(){}\[\],.#@!_|:&%/*+-]|=|=>|==|!=|:=|<|<<|>|>>", |lex| lex.slice())]
+    Sym(&'a str),
+}
+
+impl<'a> Display for Tok<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tok::Word(s) | Tok::Int(s) | Tok::Str(s) | Tok::Sym(s) => write!(f, "`{s}`"),
+        }
+    }
+}
+
+/** Parses `src` as a program in A-normal form, printed like [`anf::Prog`] */
+pub fn parse_anf(src: &str) -> Result<anf::Prog, ParseError> {
+    let mut parser = Parser::new(src)?;
+    let datatypes = parser.datatypes()?;
+    let (mut funcs, mut decls) = (vec![], vec![]);
+    while let Some(tok) = parser.peek() {
+        match tok {
+            Tok::Word("fun") => {
+                parser.next()?;
+                let name = parser.word()?;
+                let params = parser.params()?;
+                parser.sym("=")?;
+                let body = parser.expr()?;
+                funcs.push(anf::Func { name, params, body })
+            }
+            Tok::Word("let") | Tok::Sym("@") => {
+                let attrs = parser.attrs()?;
+                parser.keyword("let")?;
+                let id = parser.word()?;
+                parser.sym("=")?;
+                let body = parser.expr()?;
+                decls.push(Decl { id, body, attrs })
+            }
+            _ => return Err(parser.expected("`fun` or `let`")),
+        }
+    }
+    Ok(anf::Prog {
+        datatypes,
+        funcs,
+        decls,
+    })
+}
+
+/** Parses `src` as a program in static single assignment form, printed like [`ssa::Prog`]. The
+blocks of each function are labeled in order from `b0` */
+pub fn parse_ssa(src: &str) -> Result<ssa::Prog, ParseError> {
+    let mut parser = Parser::new(src)?;
+    let datatypes = parser.datatypes()?;
+    let (mut funcs, mut decls) = (vec![], vec![]);
+    while let Some(tok) = parser.peek() {
+        let global = match tok {
+            Tok::Word("fun") => &mut funcs,
+            Tok::Word("let") => &mut decls,
+            _ => return Err(parser.expected("`fun` or `let`")),
+        };
+        parser.next()?;
+        let name = parser.word()?;
+        let mut blocks = vec![];
+        while matches!(parser.peek(), Some(Tok::Word(w)) if label_of(w).is_some()) {
+            blocks.push(parser.block(blocks.len())?)
+        }
+        if blocks.is_empty() {
+            return Err(parser.expected("an entry block `b0`"));
+        }
+        global.push(ssa::Func { name, blocks })
+    }
+    Ok(ssa::Prog {
+        datatypes,
+        funcs,
+        decls,
+    })
+}
+
+/** The label of the block named `word`, like `b1` */
+fn label_of(word: &str) -> Option<Label> {
+    word.strip_prefix('b')?.parse().ok()
+}
+
+/** The primitive operation printed `name` in static single assignment form */
+fn prim_of(name: &str) -> Option<Prim> {
+    if let Some(widths) = name.strip_prefix("int") {
+        let (to, from) = widths.split_once("of").unwrap_or((widths, ""));
+        let from = width_of(from).filter(|from| *from != Width::Int || widths == to)?;
+        return Some(Prim::Convert(from, width_of(to)?));
+    }
+    if let Some(digit) = name.find(|c: char| c.is_ascii_digit()) {
+        let op = ARITHMETIC
+            .into_iter()
+            .find(|op| op.name() == &name[..digit])?;
+        return Some(Prim::Arith(op, width_of(&name[digit..])?));
+    }
+    let prim = match name {
+        "ref" => Prim::Ref,
+        "deref" => Prim::Deref,
+        "assign" => Prim::Assign,
+        "array" => Prim::Array,
+        "sub" => Prim::Sub,
+        "update" => Prim::Update,
+        "assertEq" => Prim::AssertEq,
+        "spawn" => Prim::Spawn,
+        "join" => Prim::Join,
+        _ => return None,
+    };
+    Some(prim)
+}
+
+/// The operators of [`Arith`](Prim::Arith)
+const ARITHMETIC: [Binary; 12] = {
+    use Binary::*;
+    [Add, Sub, Mul, Div, Mod, Land, Lor, Lxor, Shl, Shr, Lt, Gt]
+};
+
+/** The width of integers of `bits` bits, like `8`, that of `Int` for none */
+fn width_of(bits: &str) -> Option<Width> {
+    let width = match bits {
+        "" => Width::Int,
+        "8" => Width::W8,
+        "16" => Width::W16,
+        "32" => Width::W32,
+        "64" => Width::W64,
+        _ => return None,
+    };
+    Some(width)
+}
+
+/** The binary operator `tok`, if it's one */
+fn binary_of(tok: Tok) -> Option<Binary> {
+    match tok {
+        Tok::Sym(op @ ("+" | "-" | "*" | "/" | "%" | "<<" | ">>" | "==" | "!=" | "<" | ">"))
+        | Tok::Sym(op @ ("&" | "|"))
+        | Tok::Word(op @ ("land" | "lor" | "lxor")) => Some(Binary::of_str(op)),
+        _ => None,
+    }
+}
+
+/** The string literal `lit`, its escapes those of Rust's `{:?}` */
+fn unescape(lit: &str) -> Option<String> {
+    let mut s = String::new();
+    let mut chars = lit[1..lit.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => s.push('\n'),
+            't' => s.push('\t'),
+            'r' => s.push('\r'),
+            '0' => s.push('\0'),
+            c @ ('\\' | '"' | '\'') => s.push(c),
+            'u' => {
+                let code: String = chars.by_ref().skip(1).take_while(|&c| c != '}').collect();
+                s.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?)
+            }
+            _ => return None,
+        }
+    }
+    Some(s)
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Tok<'a>, Range<usize>)>,
+    pos: usize,
+    /// The length of the source, where errors at its end are
+    len: usize,
+    /// The constructors of the data types declared
+    ctors: HashSet<String>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Result<Self, ParseError> {
+        let mut tokens = vec![];
+        for (tok, span) in Tok::lexer(src).spanned() {
+            match tok {
+                Ok(tok) => tokens.push((tok, span)),
+                Err(()) => {
+                    return Err(ParseError {
+                        offset: span.start,
+                        message: "Unexpected character".to_string(),
+                    })
+                }
+            }
+        }
+        Ok(Parser {
+            tokens,
+            pos: 0,
+            len: src.len(),
+            ctors: HashSet::new(),
+        })
+    }
+
+    fn peek(&self) -> Option<Tok<'a>> {
+        self.peek_at(0)
+    }
+
+    /** The token `n` after the next one */
+    fn peek_at(&self, n: usize) -> Option<Tok<'a>> {
+        self.tokens.get(self.pos + n).map(|(tok, _)| *tok)
+    }
+
+    /** The error of the next token not being `what` */
+    fn expected(&self, what: &str) -> ParseError {
+        match self.tokens.get(self.pos) {
+            Some((tok, span)) => ParseError {
+                offset: span.start,
+                message: format!("Expected {what}, found {tok}"),
+            },
+            None => ParseError {
+                offset: self.len,
+                message: format!("Expected {what}, found the end"),
+            },
+        }
+    }
+
+    fn next(&mut self) -> Result<Tok<'a>, ParseError> {
+        let tok = self.peek().ok_or_else(|| self.expected("more"))?;
+        self.pos += 1;
+        Ok(tok)
+    }
+
+    /** Skips the next token if it's `tok` */
+    fn eat(&mut self, tok: Tok) -> bool {
+        let next = self.peek() == Some(tok);
+        self.pos += next as usize;
+        next
+    }
+
+    fn sym(&mut self, sym: &str) -> Result<(), ParseError> {
+        match self.eat(Tok::Sym(sym)) {
+            true => Ok(()),
+            false => Err(self.expected(&format!("`{sym}`"))),
+        }
+    }
+
+    fn keyword(&mut self, word: &str) -> Result<(), ParseError> {
+        match self.eat(Tok::Word(word)) {
+            true => Ok(()),
+            false => Err(self.expected(&format!("`{word}`"))),
+        }
+    }
+
+    fn word(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Tok::Word(word)) => {
+                self.pos += 1;
+                Ok(word.to_string())
+            }
+            _ => Err(self.expected("a name")),
+        }
+    }
+
+    /** Attributes like `@[inline, test]`, any number of them */
+    fn attrs(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut attrs = vec![];
+        while self.eat(Tok::Sym("@")) {
+            self.sym("[")?;
+            attrs.push(self.word()?);
+            while self.eat(Tok::Sym(",")) {
+                attrs.push(self.word()?)
+            }
+            self.sym("]")?
+        }
+        Ok(attrs)
+    }
+
+    fn index(&mut self) -> Result<usize, ParseError> {
+        match self.peek().and_then(|tok| match tok {
+            Tok::Int(n) => n.parse().ok(),
+            _ => None,
+        }) {
+            Some(n) => {
+                self.pos += 1;
+                Ok(n)
+            }
+            None => Err(self.expected("an index")),
+        }
+    }
+
+    fn label(&mut self) -> Result<Label, ParseError> {
+        match self.peek() {
+            Some(Tok::Word(word)) if label_of(word).is_some() => {
+                self.pos += 1;
+                Ok(label_of(word).unwrap())
+            }
+            _ => Err(self.expected("a label")),
+        }
+    }
+
+    /** The items parsed by `item` separated by commas in parentheses */
+    fn parens<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        self.sym("(")?;
+        let mut items = vec![];
+        if self.eat(Tok::Sym(")")) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.eat(Tok::Sym(")")) {
+                return Ok(items);
+            }
+            self.sym(",")?
+        }
+    }
+
+    fn params(&mut self) -> Result<Vec<Var>, ParseError> {
+        self.parens(Self::word)
+    }
+
+    fn atoms(&mut self) -> Result<Vec<Atom>, ParseError> {
+        self.parens(Self::atom)
+    }
+
+    /** The data types declared first, like `data Option = None | Some _` */
+    fn datatypes(&mut self) -> Result<Vec<DataType>, ParseError> {
+        let mut datatypes = vec![];
+        while self.eat(Tok::Word("data")) {
+            let id = self.word()?;
+            self.sym("=")?;
+            let mut ctors = vec![];
+            loop {
+                let ctor = self.word()?;
+                let mut arity = 0;
+                while self.eat(Tok::Sym("_")) {
+                    arity += 1
+                }
+                self.ctors.insert(ctor.clone());
+                ctors.push((ctor, arity));
+                if !self.eat(Tok::Sym("|")) {
+                    break;
+                }
+            }
+            datatypes.push(DataType { id, ctors })
+        }
+        Ok(datatypes)
+    }
+
+    fn atom(&mut self) -> Result<Atom, ParseError> {
+        let (tok, span) = match self.tokens.get(self.pos) {
+            Some((tok, span)) => (*tok, span.clone()),
+            None => return Err(self.expected("an atom")),
+        };
+        let con = match tok {
+            Tok::Word("true") => Constant::Boolean(true),
+            Tok::Word("false") => Constant::Boolean(false),
+            Tok::Word("null") => Constant::Null,
+            Tok::Word(word) if !self.ctors.contains(word) => {
+                self.pos += 1;
+                return Ok(Atom::Var(word.to_string()));
+            }
+            Tok::Int(n) => {
+                let (n, width) = match n.split_once('i') {
+                    Some((n, bits)) => (n, Some(Width::of_str(bits))),
+                    None => (n, None),
+                };
+                match (n.parse(), width) {
+                    (Ok(n), Some(width)) => Constant::Sized(n, width),
+                    (Ok(n), None) => Constant::Integer(n),
+                    (Err(_), _) => return Err(self.expected("an integer in range")),
+                }
+            }
+            Tok::Str(lit) => match unescape(lit) {
+                Some(s) => Constant::Str(s),
+                None => {
+                    return Err(ParseError {
+                        offset: span.start,
+                        message: "Invalid escape in string".to_string(),
+                    })
+                }
+            },
+            _ => return Err(self.expected("an atom")),
+        };
+        self.pos += 1;
+        Ok(Atom::Con(con))
+    }
+
+    /** Parses the operations common to A-normal form and static single assignment form, but for
+    those of primitives. `None` if the next tokens aren't one */
+    fn op(&mut self) -> Result<Option<Op>, ParseError> {
+        let op = match (self.peek(), self.peek_at(1)) {
+            (Some(Tok::Sym("(")), _) => Op::Tuple(self.atoms()?),
+            (Some(Tok::Sym("{")), _) => {
+                self.next()?;
+                let mut fields = vec![];
+                while !self.eat(Tok::Sym("}")) {
+                    if !fields.is_empty() {
+                        self.sym(",")?
+                    }
+                    let label = self.word()?;
+                    self.sym("=")?;
+                    fields.push((label, self.atom()?))
+                }
+                Op::Record(fields)
+            }
+            (Some(Tok::Word("closure")), Some(Tok::Word(_))) => {
+                self.next()?;
+                let func = self.word()?;
+                Op::Closure(func, self.atoms()?)
+            }
+            (Some(Tok::Word(ctor)), _) if self.ctors.contains(ctor) => {
+                self.next()?;
+                match self.peek() {
+                    Some(Tok::Sym("(")) => Op::Data(ctor.to_string(), self.atoms()?),
+                    _ => Op::Data(ctor.to_string(), vec![]),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(op))
+    }
+
+    /** Parses the operations on an atom first, like `x + y` or `t.0`, the atom parsed already */
+    fn op_on(&mut self, atom: Atom) -> Result<Op, ParseError> {
+        let tok = self.peek();
+        if let Some(op) = tok.and_then(binary_of) {
+            self.next()?;
+            return Ok(Op::Binop(atom, op, self.atom()?));
+        }
+        let op = match tok {
+            Some(Tok::Sym(".")) => {
+                self.next()?;
+                match self.peek() {
+                    Some(Tok::Int(_)) => Op::Nth(atom, self.index()?),
+                    _ => Op::Proj(atom, self.word()?),
+                }
+            }
+            Some(Tok::Sym("#")) => {
+                self.next()?;
+                Op::Field(atom, self.index()?)
+            }
+            Some(Tok::Sym("@")) => {
+                self.next()?;
+                Op::Env(atom, self.index()?)
+            }
+            _ => Op::Atom(atom),
+        };
+        Ok(op)
+    }
+
+    /** Parses computations, their primitives printed like `ref x` or `sub(a, i)` */
+    fn comp(&mut self) -> Result<Comp, ParseError> {
+        if let Some(op) = self.op()? {
+            return Ok(op.into());
+        }
+        let prefix = match self.peek() {
+            Some(Tok::Word("handle")) => {
+                self.next()?;
+                let body = self.expr()?;
+                self.keyword("with")?;
+                let exn = self.word()?;
+                self.sym("=>")?;
+                let handler = self.expr()?;
+                self.keyword("end")?;
+                return Ok(Comp::Handle {
+                    body: Box::new(body),
+                    exn,
+                    handler: Box::new(handler),
+                });
+            }
+            Some(Tok::Word(word))
+                if matches!(
+                    prim_of(word),
+                    Some(Prim::Convert(..) | Prim::Arith(..) | Prim::Array | Prim::Sub)
+                        | Some(Prim::Update | Prim::AssertEq)
+                ) =>
+            {
+                self.next()?;
+                return Ok(Comp::Prim(prim_of(word).unwrap(), self.atoms()?));
+            }
+            Some(Tok::Word("ref")) => Prim::Ref,
+            Some(Tok::Word("spawn")) => Prim::Spawn,
+            Some(Tok::Word("join")) => Prim::Join,
+            Some(Tok::Sym("!")) => Prim::Deref,
+            _ => {
+                let atom = self.atom()?;
+                return match self.peek() {
+                    Some(Tok::Sym("(")) => Ok(Comp::App(atom, self.atoms()?)),
+                    Some(Tok::Sym(":=")) => {
+                        self.next()?;
+                        Ok(Comp::Prim(Prim::Assign, vec![atom, self.atom()?]))
+                    }
+                    _ => Ok(self.op_on(atom)?.into()),
+                };
+            }
+        };
+        self.next()?;
+        Ok(Comp::Prim(prefix, vec![self.atom()?]))
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        grow(|| {
+            let expr = match self.peek() {
+                Some(Tok::Word("let")) => {
+                    self.next()?;
+                    let var = self.word()?;
+                    self.sym("=")?;
+                    let comp = self.comp()?;
+                    self.keyword("in")?;
+                    Expr::Let {
+                        var,
+                        comp,
+                        body: Box::new(self.expr()?),
+                    }
+                }
+                Some(Tok::Word("fix")) => {
+                    self.next()?;
+                    let mut funcs = vec![];
+                    loop {
+                        let name = self.word()?;
+                        let params = self.params()?;
+                        self.sym("=")?;
+                        let body = self.expr()?;
+                        funcs.push(anf::Func { name, params, body });
+                        if !self.eat(Tok::Word("and")) {
+                            break;
+                        }
+                    }
+                    self.keyword("in")?;
+                    Expr::Fix {
+                        funcs,
+                        body: Box::new(self.expr()?),
+                    }
+                }
+                Some(Tok::Word("join")) => {
+                    self.next()?;
+                    let name = self.word()?;
+                    let params = self.params()?;
+                    self.sym("=")?;
+                    let join = self.expr()?;
+                    self.keyword("in")?;
+                    Expr::Join {
+                        name,
+                        params,
+                        join: Box::new(join),
+                        body: Box::new(self.expr()?),
+                    }
+                }
+                Some(Tok::Word("jump")) => {
+                    self.next()?;
+                    let name = self.word()?;
+                    Expr::Jump {
+                        name,
+                        args: self.atoms()?,
+                    }
+                }
+                Some(Tok::Word("if")) => {
+                    self.next()?;
+                    let cond = self.atom()?;
+                    self.keyword("then")?;
+                    let branch_t = self.expr()?;
+                    self.keyword("else")?;
+                    Expr::If {
+                        cond,
+                        branch_t: Box::new(branch_t),
+                        branch_f: Box::new(self.expr()?),
+                    }
+                }
+                Some(Tok::Word("case")) => {
+                    self.next()?;
+                    let scrut = self.atom()?;
+                    self.keyword("of")?;
+                    let (mut arms, mut default) = (vec![], None);
+                    while self.eat(Tok::Sym("|")) {
+                        if default.is_some() {
+                            return Err(self.expected("`end` after the default arm"));
+                        }
+                        let ctor = match self.eat(Tok::Sym("_")) {
+                            true => None,
+                            false => Some(self.word()?),
+                        };
+                        self.sym("=>")?;
+                        let body = self.expr()?;
+                        match ctor {
+                            Some(ctor) => arms.push((ctor, body)),
+                            None => default = Some(Box::new(body)),
+                        }
+                    }
+                    self.keyword("end")?;
+                    Expr::Case {
+                        scrut,
+                        arms,
+                        default,
+                    }
+                }
+                Some(Tok::Word("raise")) => {
+                    self.next()?;
+                    Expr::Raise { exn: self.atom()? }
+                }
+                _ => Expr::Ret { val: self.atom()? },
+            };
+            Ok(expr)
+        })
+    }
+
+    /** Parses operations, their primitives printed like `sub(a, i)` */
+    fn ssa_op(&mut self) -> Result<Op, ParseError> {
+        if let Some(op) = self.op()? {
+            return Ok(op);
+        }
+        if let (Some(Tok::Word(name)), Some(Tok::Sym("("))) = (self.peek(), self.peek_at(1)) {
+            if let Some(prim) = prim_of(name) {
+                self.next()?;
+                return Ok(Op::Prim(prim, self.atoms()?));
+            }
+            let op: Option<fn(Atom) -> Op> = match name {
+                "tag" => Some(Op::Tag),
+                "untag" => Some(Op::Untag),
+                "unbox" => Some(Op::Unbox),
+                "box32" => Some(|atom| Op::Box(Width::W32, atom)),
+                "box64" => Some(|atom| Op::Box(Width::W64, atom)),
+                _ => None,
+            };
+            if let Some(op) = op {
+                self.next()?;
+                self.sym("(")?;
+                let atom = self.atom()?;
+                self.sym(")")?;
+                return Ok(op(atom));
+            }
+        }
+        let atom = self.atom()?;
+        self.op_on(atom)
+    }
+
+    fn term(&mut self) -> Result<Term, ParseError> {
+        let expected = "an instruction or a terminator";
+        let Some(Tok::Word(keyword)) = self.peek() else {
+            return Err(self.expected(expected));
+        };
+        self.pos += 1;
+        let term = match keyword {
+            "jump" => Term::Jump(self.label()?, self.atoms()?),
+            "if" => {
+                let cond = self.atom()?;
+                self.keyword("then")?;
+                let branch_t = self.label()?;
+                self.keyword("else")?;
+                Term::Branch {
+                    cond,
+                    branch_t,
+                    branch_f: self.label()?,
+                }
+            }
+            "case" => {
+                let scrut = self.atom()?;
+                self.keyword("of")?;
+                let (mut arms, mut default) = (vec![], None);
+                let arm = |p: &Self, n| p.peek_at(n) == Some(Tok::Sym("=>"));
+                let mut first = arm(self, 1);
+                while default.is_none()
+                    && (first || self.peek() == Some(Tok::Sym("|")) && arm(self, 2))
+                {
+                    if !std::mem::take(&mut first) {
+                        self.next()?;
+                    }
+                    let ctor = match self.eat(Tok::Sym("_")) {
+                        true => None,
+                        false => Some(self.word()?),
+                    };
+                    self.sym("=>")?;
+                    let label = self.label()?;
+                    match ctor {
+                        Some(ctor) => arms.push((ctor, label)),
+                        None => default = Some(label),
+                    }
+                }
+                Term::Switch {
+                    scrut,
+                    arms,
+                    default,
+                }
+            }
+            "call" => {
+                let fun = self.atom()?;
+                let args = self.atoms()?;
+                self.keyword("to")?;
+                Term::Call {
+                    fun,
+                    args,
+                    ret: self.label()?,
+                }
+            }
+            "tailcall" => Term::TailCall {
+                fun: self.atom()?,
+                args: self.atoms()?,
+            },
+            "ret" => Term::Ret(self.atom()?),
+            "raise" => Term::Raise(self.atom()?),
+            _ => {
+                self.pos -= 1;
+                return Err(self.expected(expected));
+            }
+        };
+        Ok(term)
+    }
+
+    /** Parses the block labeled `label`, like `b1(x) unwind b2: y = x + 1 ret y` */
+    fn block(&mut self, label: Label) -> Result<Block, ParseError> {
+        if self.label()? != label {
+            self.pos -= 1;
+            return Err(self.expected(&format!("the label `b{label}`")));
+        }
+        let params = match self.peek() {
+            Some(Tok::Sym("(")) => self.params()?,
+            _ => vec![],
+        };
+        let handler = match self.eat(Tok::Word("unwind")) {
+            true => Some(self.label()?),
+            false => None,
+        };
+        self.sym(":")?;
+        let mut insts = vec![];
+        while let (Some(Tok::Word(var)), Some(Tok::Sym("="))) = (self.peek(), self.peek_at(1)) {
+            self.pos += 2;
+            let op = self.ssa_op()?;
+            insts.push(Inst {
+                var: var.to_string(),
+                op,
+            })
+        }
+        Ok(Block {
+            params,
+            insts,
+            term: self.term()?,
+            handler,
+        })
+    }
+}
+
+impl From<Op> for Comp {
+    fn from(op: Op) -> Comp {
+        match op {
+            Op::Atom(a) => Comp::Atom(a),
+            Op::Binop(lhs, op, rhs) => Comp::Binop(lhs, op, rhs),
+            Op::Tuple(entries) => Comp::Tuple(entries),
+            Op::Nth(tuple, i) => Comp::Nth(tuple, i),
+            Op::Record(fields) => Comp::Record(fields),
+            Op::Proj(record, label) => Comp::Proj(record, label),
+            Op::Data(ctor, fields) => Comp::Data(ctor, fields),
+            Op::Field(data, i) => Comp::Field(data, i),
+            Op::Closure(func, env) => Comp::Closure(func, env),
+            Op::Env(clo, i) => Comp::Env(clo, i),
+            Op::Prim(prim, args) => Comp::Prim(prim, args),
+            Op::Tag(_) | Op::Untag(_) | Op::Box(..) | Op::Unbox(_) => {
+                unreachable!("A-normal form has no tags or boxes")
+            }
+        }
+    }
+}
@@ -0,0 +1,452 @@
+/*! Uncurrying of programs in A-normal form, run before [closure conversion](crate::ir::closure).
+A function returning a function right away, like `fix f(x) = fix g(y) = e in g in ...` for
+`λ x. λ y. e`, gets a worker taking all of the arguments at once, `fix f$1(x, y) = e`, and is left
+as a wrapper calling it, for its partial applications. Its applications to all of the arguments in
+turn become calls of the worker, when the partial applications in between are used for nothing
+else: applying the wrapper evaluates nothing but closures until the last argument, so these are
+left out. The workers of the functions of declarations are declared before them, so that any
+declaration can call them. */
+
+use crate::ast::ast::grow;
+use crate::ir::anf::{Atom, Comp, Decl, Expr, Fresh, Func, Prog, Var};
+use crate::ir::pass::{Pass, Run};
+
+use im::{HashMap, HashSet};
+
+/// The pass uncurrying, run before closure conversion
+pub const PASS: Pass = Pass {
+    name: "uncurry",
+    run: Run::Anf(uncurry),
+    requires: &[],
+    once: false,
+};
+
+/** Uncurries the functions of `prog` and their applications */
+pub fn uncurry(prog: &Prog) -> Prog {
+    let mut uncurrying = Uncurrying {
+        names: Fresh::after(prog),
+        known: HashMap::new(),
+        uses: HashMap::new(),
+        calls: HashMap::new(),
+        partial: HashSet::new(),
+    };
+    for func in &prog.funcs {
+        uncurrying.workers(&func.body);
+        count(&func.body, &mut uncurrying.uses)
+    }
+    let mut globals = vec![];
+    for decl in &prog.decls {
+        uncurrying.workers(&decl.body);
+        count(&decl.body, &mut uncurrying.uses);
+        if let Some(func) = function(&decl.body) {
+            if let Some(curried) = uncurrying.known.get(&func.name) {
+                let worker = uncurrying.names.var(&decl.id);
+                let levels = curried.levels.clone();
+                uncurrying
+                    .known
+                    .insert(decl.id.clone(), Curried { worker, levels });
+                globals.push(decl.id.clone())
+            }
+        }
+    }
+    for body in prog
+        .funcs
+        .iter()
+        .map(|f| &f.body)
+        .chain(prog.decls.iter().map(|d| &d.body))
+    {
+        uncurrying.applications(body, &HashMap::new())
+    }
+    let funcs = prog.funcs.iter().map(|func| Func {
+        body: uncurrying.expr(&func.body),
+        ..func.clone()
+    });
+    let funcs = funcs.collect();
+    let mut decls = vec![];
+    for decl in &prog.decls {
+        let body = uncurrying.expr(&decl.body);
+        if !globals.contains(&decl.id) {
+            decls.push(Decl {
+                body,
+                ..decl.clone()
+            });
+            continue;
+        }
+        // The wrapper and the worker of the function of the declaration, in that order
+        let Expr::Fix { mut funcs, .. } = body else {
+            unreachable!("declarations of functions are `fix`es")
+        };
+        let (wrapper, worker) = (funcs.remove(0), funcs.remove(0));
+        let global = uncurrying.known[&decl.id].worker.clone();
+        let mut funcs = vec![worker];
+        if funcs[0].body.free_vars().contains(&wrapper.name) {
+            funcs.push(wrapper.clone())
+        }
+        let name = funcs[0].name.clone();
+        decls.push(Decl {
+            id: global.clone(),
+            body: fix(funcs, name),
+            attrs: decl.attrs.clone(),
+        });
+        let name = uncurrying.names.var(&wrapper.name);
+        let levels = curried(function(&decl.body).unwrap());
+        let wrapper = uncurrying.wrapper(name.clone(), &levels, &global);
+        decls.push(Decl {
+            body: fix(vec![wrapper], name),
+            ..decl.clone()
+        })
+    }
+    Prog {
+        datatypes: prog.datatypes.clone(),
+        funcs,
+        decls,
+    }
+}
+
+/** The number of arguments `func` takes before evaluating anything but closures, counting those of
+the functions it returns right away */
+pub fn arity(func: &Func) -> usize {
+    curried(func).iter().map(|f| f.params.len()).sum()
+}
+
+/** The functions `func` returns right away in turn, `func` first */
+fn curried(func: &Func) -> Vec<&Func> {
+    let mut levels = vec![func];
+    while let Some(inner) = function(&levels[levels.len() - 1].body) {
+        // Recursive functions evaluate more than closures
+        if inner.body.free_vars().contains(&inner.name) {
+            break;
+        }
+        levels.push(inner)
+    }
+    levels
+}
+
+/** The function `expr` defines and returns right away */
+fn function(expr: &Expr) -> Option<&Func> {
+    match expr {
+        Expr::Fix { funcs, body } => match (&funcs[..], &**body) {
+            (
+                [func],
+                Expr::Ret {
+                    val: Atom::Var(var),
+                },
+            ) if var == &func.name => Some(func),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/** `fix funcs in name` */
+fn fix(funcs: Vec<Func>, name: Var) -> Expr {
+    Expr::Fix {
+        funcs,
+        body: Box::new(Expr::Ret {
+            val: Atom::Var(name),
+        }),
+    }
+}
+
+/** Counts the uses of the variables of `expr` in `uses` */
+fn count(expr: &Expr, uses: &mut HashMap<Var, usize>) {
+    grow(|| {
+        let used = |atom: &Atom, uses: &mut HashMap<Var, usize>| {
+            if let Atom::Var(var) = atom {
+                *uses.entry(var.clone()).or_default() += 1
+            }
+        };
+        match expr {
+            Expr::Let { comp, body, .. } => {
+                comp.atoms().into_iter().for_each(|a| used(a, uses));
+                if let Comp::Handle { body, handler, .. } = comp {
+                    count(body, uses);
+                    count(handler, uses)
+                }
+                count(body, uses)
+            }
+            Expr::Fix { funcs, body } => {
+                funcs.iter().for_each(|f| count(&f.body, uses));
+                count(body, uses)
+            }
+            Expr::Join { join, body, .. } => {
+                count(join, uses);
+                count(body, uses)
+            }
+            Expr::Jump { args, .. } => args.iter().for_each(|a| used(a, uses)),
+            Expr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => {
+                used(cond, uses);
+                count(branch_t, uses);
+                count(branch_f, uses)
+            }
+            Expr::Case {
+                scrut,
+                arms,
+                default,
+            } => {
+                used(scrut, uses);
+                for body in arms.iter().map(|(_, e)| e).chain(default.as_deref()) {
+                    count(body, uses)
+                }
+            }
+            Expr::Raise { exn: val } | Expr::Ret { val } => used(val, uses),
+        }
+    })
+}
+
+/// A function taking its arguments in turn, and its worker taking them at once
+#[derive(Clone)]
+struct Curried {
+    worker: Var,
+    /// The numbers of arguments taken in turn
+    levels: Vec<usize>,
+}
+
+struct Uncurrying {
+    names: Fresh,
+    /// Functions with workers, and declarations of them
+    known: HashMap<Var, Curried>,
+    /// Numbers of uses of the variables
+    uses: HashMap<Var, usize>,
+    /// Calls of workers replacing the last applications to all of the arguments
+    calls: HashMap<Var, Comp>,
+    /// Partial applications left out
+    partial: HashSet<Var>,
+}
+
+impl Uncurrying {
+    /** Names the workers of the functions of `expr` taking arguments in turn */
+    fn workers(&mut self, expr: &Expr) {
+        grow(|| match expr {
+            Expr::Let { comp, body, .. } => {
+                if let Comp::Handle { body, handler, .. } = comp {
+                    self.workers(body);
+                    self.workers(handler)
+                }
+                self.workers(body)
+            }
+            Expr::Fix { funcs, body } => {
+                for func in funcs {
+                    let levels = curried(func);
+                    if levels.len() > 1 {
+                        let curried = Curried {
+                            worker: self.names.var(&func.name),
+                            levels: levels.iter().map(|f| f.params.len()).collect(),
+                        };
+                        self.known.insert(func.name.clone(), curried);
+                    }
+                    self.workers(&levels[levels.len() - 1].body)
+                }
+                self.workers(body)
+            }
+            Expr::Join { join, body, .. } => {
+                self.workers(join);
+                self.workers(body)
+            }
+            Expr::If {
+                branch_t, branch_f, ..
+            } => {
+                self.workers(branch_t);
+                self.workers(branch_f)
+            }
+            Expr::Case { arms, default, .. } => {
+                for body in arms.iter().map(|(_, e)| e).chain(default.as_deref()) {
+                    self.workers(body)
+                }
+            }
+            Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => (),
+        })
+    }
+
+    /** Finds the applications in `expr` of functions with workers to all of their arguments,
+    where `apps` are the applications bound by `let` around it */
+    fn applications(&mut self, expr: &Expr, apps: &HashMap<Var, (Var, Vec<Atom>)>) {
+        grow(|| match expr {
+            Expr::Let { var, comp, body } => {
+                let mut apps = apps.clone();
+                match comp {
+                    Comp::App(Atom::Var(fun), args) => {
+                        if let Some((call, partial)) = self.call(fun, args, &apps) {
+                            self.calls.insert(var.clone(), call);
+                            self.partial.extend(partial)
+                        }
+                        apps.insert(var.clone(), (fun.clone(), args.clone()));
+                    }
+                    Comp::Handle { body, handler, .. } => {
+                        self.applications(body, &apps);
+                        self.applications(handler, &apps)
+                    }
+                    _ => (),
+                }
+                self.applications(body, &apps)
+            }
+            Expr::Fix { funcs, body } => {
+                funcs.iter().for_each(|f| self.applications(&f.body, apps));
+                self.applications(body, apps)
+            }
+            Expr::Join { join, body, .. } => {
+                self.applications(join, apps);
+                self.applications(body, apps)
+            }
+            Expr::If {
+                branch_t, branch_f, ..
+            } => {
+                self.applications(branch_t, apps);
+                self.applications(branch_f, apps)
+            }
+            Expr::Case { arms, default, .. } => {
+                for body in arms.iter().map(|(_, e)| e).chain(default.as_deref()) {
+                    self.applications(body, apps)
+                }
+            }
+            Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => (),
+        })
+    }
+
+    /** The call of a worker `fun(args)` stands for, if it's the last of the applications of a
+    function with a worker to all of its arguments, with the partial applications before it */
+    fn call(
+        &self,
+        fun: &Var,
+        args: &[Atom],
+        apps: &HashMap<Var, (Var, Vec<Atom>)>,
+    ) -> Option<(Comp, Vec<Var>)> {
+        let (mut fun, mut levels, mut partial) = (fun, vec![args], vec![]);
+        loop {
+            if let Some(curried) = self.known.get(fun) {
+                levels.reverse();
+                let taken: Vec<usize> = levels.iter().map(|args| args.len()).collect();
+                let args = levels.concat();
+                let call = Comp::App(Atom::Var(curried.worker.clone()), args);
+                return (taken == curried.levels).then_some((call, partial));
+            }
+            let (f, args) = apps.get(fun)?;
+            if self.uses[fun] != 1 {
+                return None;
+            }
+            partial.push(fun.clone());
+            levels.push(args);
+            fun = f
+        }
+    }
+
+    /** `expr` with workers for its functions, calling them in place of the applications found */
+    fn expr(&mut self, expr: &Expr) -> Expr {
+        grow(|| match expr {
+            Expr::Let { var, comp, body } => {
+                if self.partial.contains(var) {
+                    return self.expr(body);
+                }
+                let comp = match (self.calls.get(var), comp) {
+                    (Some(call), _) => call.clone(),
+                    (None, Comp::Handle { body, exn, handler }) => Comp::Handle {
+                        body: Box::new(self.expr(body)),
+                        exn: exn.clone(),
+                        handler: Box::new(self.expr(handler)),
+                    },
+                    (None, comp) => comp.clone(),
+                };
+                Expr::Let {
+                    var: var.clone(),
+                    comp,
+                    body: Box::new(self.expr(body)),
+                }
+            }
+            Expr::Fix { funcs, body } => {
+                let mut uncurried = vec![];
+                for func in funcs {
+                    let Some(known) = self.known.get(&func.name) else {
+                        uncurried.push(Func {
+                            body: self.expr(&func.body),
+                            ..func.clone()
+                        });
+                        continue;
+                    };
+                    let worker = known.worker.clone();
+                    let levels = curried(func);
+                    let wrapper = self.wrapper(func.name.clone(), &levels, &worker);
+                    uncurried.push(wrapper);
+                    uncurried.push(Func {
+                        name: worker,
+                        params: levels.iter().flat_map(|f| f.params.clone()).collect(),
+                        body: self.expr(&levels[levels.len() - 1].body),
+                    })
+                }
+                Expr::Fix {
+                    funcs: uncurried,
+                    body: Box::new(self.expr(body)),
+                }
+            }
+            Expr::Join {
+                name,
+                params,
+                join,
+                body,
+            } => Expr::Join {
+                name: name.clone(),
+                params: params.clone(),
+                join: Box::new(self.expr(join)),
+                body: Box::new(self.expr(body)),
+            },
+            Expr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => Expr::If {
+                cond: cond.clone(),
+                branch_t: Box::new(self.expr(branch_t)),
+                branch_f: Box::new(self.expr(branch_f)),
+            },
+            Expr::Case {
+                scrut,
+                arms,
+                default,
+            } => Expr::Case {
+                scrut: scrut.clone(),
+                arms: arms
+                    .iter()
+                    .map(|(c, e)| (c.clone(), self.expr(e)))
+                    .collect(),
+                default: default.as_ref().map(|e| Box::new(self.expr(e))),
+            },
+            Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => expr.clone(),
+        })
+    }
+
+    /** The function `name` taking the arguments of the `levels` in turn, calling `worker` with
+    all of them */
+    fn wrapper(&mut self, name: Var, levels: &[&Func], worker: &Var) -> Func {
+        let mut params: Vec<Vec<Var>> = levels
+            .iter()
+            .map(|f| f.params.iter().map(|p| self.names.var(p)).collect())
+            .collect();
+        let args = params.iter().flatten().cloned().map(Atom::Var).collect();
+        let result = self.names.var("t");
+        let mut body = Expr::Let {
+            var: result.clone(),
+            comp: Comp::App(Atom::Var(worker.clone()), args),
+            body: Box::new(Expr::Ret {
+                val: Atom::Var(result),
+            }),
+        };
+        let first = params.remove(0);
+        for (level, params) in levels[1..].iter().zip(params).rev() {
+            let inner = self.names.var(&level.name);
+            let func = Func {
+                name: inner.clone(),
+                params,
+                body,
+            };
+            body = fix(vec![func], inner)
+        }
+        Func {
+            name,
+            params: first,
+            body,
+        }
+    }
+}
@@ -0,0 +1,395 @@
+/*! Verification of the invariants of the intermediate representations, run after every pass under
+`--verify-ir` to catch miscompilations at the pass introducing them. Types are erased, so what lines
+up is the shape of values: constructors are declared and applied to as many fields as they have,
+the arms of a `case` are constructors of one data type, and primitives and join points get as many
+operands as they take. In [A-normal form](crate::ir::anf) variables are bound once in the program
+and used in their scope, and jumps go to join points in scope outside of functions and handlers.
+In [static single assignment form](crate::ir::ssa) registers are assigned once in their function
+and on every path to their uses, and the blocks jumped to exist and take as many parameters as
+they're passed. */
+
+use crate::ast::ast::grow;
+use crate::ast::semant::BUILTINS;
+use crate::ir::anf::{self, Atom, Comp, DataType, Expr, Prim, Var};
+use crate::ir::dataflow::{solve, Analysis, Direction};
+use crate::ir::ssa::{self, Label, Op, Term};
+
+use std::fmt::{self, Display};
+
+use im::{HashMap, HashSet};
+
+/// An invariant broken in the function or declaration `func`
+#[derive(Debug, PartialEq, Clone)]
+pub struct VerifyError {
+    pub func: String,
+    pub message: String,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "In {}: {}", self.func, self.message)
+    }
+}
+
+type Verified = Result<(), VerifyError>;
+
+/// The constructors of a program, with the data type they're of and their arity
+struct Ctors<'a>(HashMap<&'a str, (&'a str, usize)>);
+
+impl<'a> Ctors<'a> {
+    fn of(datatypes: &'a [DataType]) -> Self {
+        let ctors = datatypes.iter().flat_map(|data| {
+            let ctors = data.ctors.iter();
+            ctors.map(|(ctor, arity)| (ctor.as_str(), (data.id.as_str(), *arity)))
+        });
+        Ctors(ctors.collect())
+    }
+
+    /** Checks `ctor` is declared with `arity` fields */
+    fn data(&self, ctor: &str, arity: usize) -> Result<(), String> {
+        match self.0.get(ctor) {
+            Some((_, n)) if *n == arity => Ok(()),
+            Some((_, n)) => Err(format!("{ctor} has {n} fields, not {arity}")),
+            None => Err(format!("{ctor} isn't a constructor")),
+        }
+    }
+
+    /** Checks the constructors of the arms of a `case` are distinct and of one data type */
+    fn arms<'b>(&self, ctors: impl Iterator<Item = &'b String>) -> Result<(), String> {
+        let mut data = None;
+        let mut seen = HashSet::new();
+        for ctor in ctors {
+            let (id, _) = self
+                .0
+                .get(ctor.as_str())
+                .ok_or(format!("{ctor} isn't a constructor"))?;
+            if *data.get_or_insert(id) != id {
+                return Err(format!("{ctor} isn't a constructor of {}", data.unwrap()));
+            }
+            if seen.insert(ctor).is_some() {
+                return Err(format!("{ctor} has two arms"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/** Checks the shape of `op`, the operation of an instruction or computation */
+fn shape(op: &Op, ctors: &Ctors) -> Result<(), String> {
+    match op {
+        Op::Tuple(entries) if entries.len() < 2 => Err("Tuples have 2 entries or more".to_string()),
+        Op::Data(ctor, fields) => ctors.data(ctor, fields.len()),
+        Op::Prim(prim, args) => prim_shape(prim, args),
+        _ => Ok(()),
+    }
+}
+
+fn prim_shape(prim: &Prim, args: &[Atom]) -> Result<(), String> {
+    match prim.arity() == args.len() {
+        true => Ok(()),
+        false => Err(format!("{prim} takes {} operands", prim.arity())),
+    }
+}
+
+/** Verifies `prog` in A-normal form */
+pub fn verify_anf(prog: &anf::Prog) -> Verified {
+    let mut verifier = Anf {
+        ctors: Ctors::of(&prog.datatypes),
+        bound: HashSet::new(),
+    };
+    let mut globals: HashSet<Var> = BUILTINS.iter().map(|(id, _)| id.to_string()).collect();
+    globals.extend(prog.funcs.iter().map(|f| f.name.clone()));
+    // Functions at the top level may refer to any declaration
+    let decls = prog.decls.iter().map(|d| d.id.clone());
+    let func_scope: HashSet<Var> = globals.clone().into_iter().chain(decls).collect();
+    let in_func = |func: &str| {
+        let func = func.to_string();
+        move |message| VerifyError { func, message }
+    };
+    for func in &prog.funcs {
+        verifier.bind(&func.name).map_err(in_func(&func.name))?;
+    }
+    for func in &prog.funcs {
+        let mut scope = func_scope.clone();
+        for param in &func.params {
+            verifier.bind(param).map_err(in_func(&func.name))?;
+            scope.insert(param.clone());
+        }
+        let body = verifier.expr(&func.body, &scope, &HashMap::new());
+        body.map_err(in_func(&func.name))?
+    }
+    for decl in &prog.decls {
+        let body = verifier.expr(&decl.body, &globals, &HashMap::new());
+        body.map_err(in_func(&decl.id))?;
+        globals.insert(decl.id.clone());
+    }
+    Ok(())
+}
+
+struct Anf<'a> {
+    ctors: Ctors<'a>,
+    /// The variables bound so far in the program
+    bound: HashSet<Var>,
+}
+
+impl<'a> Anf<'a> {
+    fn bind(&mut self, var: &str) -> Result<(), String> {
+        match self.bound.insert(var.to_string()) {
+            None => Ok(()),
+            Some(_) => Err(format!("{var} is bound twice")),
+        }
+    }
+
+    fn atoms<'b>(
+        atoms: impl IntoIterator<Item = &'b Atom>,
+        scope: &HashSet<Var>,
+    ) -> Result<(), String> {
+        for var in atoms.into_iter().filter_map(Atom::var) {
+            if !scope.contains(var) {
+                return Err(format!("{var} is used out of scope"));
+            }
+        }
+        Ok(())
+    }
+
+    fn comp(&mut self, comp: &Comp, scope: &HashSet<Var>) -> Result<(), String> {
+        match comp {
+            Comp::Closure(func, _) if !scope.contains(func) => {
+                return Err(format!("{func} is used out of scope"))
+            }
+            Comp::Handle { body, exn, handler } => {
+                self.expr(body, scope, &HashMap::new())?;
+                self.bind(exn)?;
+                self.expr(handler, &scope.update(exn.clone()), &HashMap::new())?
+            }
+            Comp::Tuple(entries) if entries.len() < 2 => {
+                return Err("Tuples have 2 entries or more".to_string())
+            }
+            Comp::Data(ctor, fields) => self.ctors.data(ctor, fields.len())?,
+            Comp::Prim(prim, args) => prim_shape(prim, args)?,
+            _ => (),
+        }
+        Self::atoms(comp.atoms(), scope)
+    }
+
+    /** Verifies `expr` with `scope` the variables and `joins` the arities of the join points in
+    scope */
+    fn expr(
+        &mut self,
+        expr: &Expr,
+        scope: &HashSet<Var>,
+        joins: &HashMap<Var, usize>,
+    ) -> Result<(), String> {
+        grow(|| match expr {
+            Expr::Let { var, comp, body } => {
+                self.comp(comp, scope)?;
+                self.bind(var)?;
+                self.expr(body, &scope.update(var.clone()), joins)
+            }
+            Expr::Fix { funcs, body } => {
+                let mut scope = scope.clone();
+                for func in funcs {
+                    self.bind(&func.name)?;
+                    scope.insert(func.name.clone());
+                }
+                for func in funcs {
+                    let mut scope = scope.clone();
+                    for param in &func.params {
+                        self.bind(param)?;
+                        scope.insert(param.clone());
+                    }
+                    self.expr(&func.body, &scope, &HashMap::new())?
+                }
+                self.expr(body, &scope, joins)
+            }
+            Expr::Join {
+                name,
+                params,
+                join,
+                body,
+            } => {
+                self.bind(name)?;
+                let mut join_scope = scope.clone();
+                for param in params {
+                    self.bind(param)?;
+                    join_scope.insert(param.clone());
+                }
+                self.expr(join, &join_scope, joins)?;
+                self.expr(body, scope, &joins.update(name.clone(), params.len()))
+            }
+            Expr::Jump { name, args } => match joins.get(name) {
+                Some(arity) if *arity == args.len() => Self::atoms(args, scope),
+                Some(arity) => Err(format!("{name} takes {arity} arguments")),
+                None => Err(format!("{name} isn't a join point in scope")),
+            },
+            Expr::If {
+                cond,
+                branch_t,
+                branch_f,
+            } => {
+                Self::atoms([cond], scope)?;
+                self.expr(branch_t, scope, joins)?;
+                self.expr(branch_f, scope, joins)
+            }
+            Expr::Case {
+                scrut,
+                arms,
+                default,
+            } => {
+                Self::atoms([scrut], scope)?;
+                self.ctors.arms(arms.iter().map(|(ctor, _)| ctor))?;
+                for body in arms.iter().map(|(_, e)| e).chain(default.as_deref()) {
+                    self.expr(body, scope, joins)?
+                }
+                Ok(())
+            }
+            Expr::Raise { exn: val } | Expr::Ret { val } => Self::atoms([val], scope),
+        })
+    }
+}
+
+/** Verifies `prog` in static single assignment form */
+pub fn verify_ssa(prog: &ssa::Prog) -> Verified {
+    let ctors = Ctors::of(&prog.datatypes);
+    let funcs: HashSet<&str> = prog.funcs.iter().map(|f| f.name.as_str()).collect();
+    let mut globals: HashSet<&str> = BUILTINS.iter().map(|(id, _)| *id).collect();
+    globals.extend(funcs.clone());
+    let func_scope = globals
+        .clone()
+        .union(prog.decls.iter().map(|d| d.name.as_str()).collect());
+    for func in &prog.funcs {
+        verify_func(func, &func_scope, &funcs, &ctors).map_err(|message| VerifyError {
+            func: func.name.clone(),
+            message,
+        })?
+    }
+    for decl in &prog.decls {
+        let verified = match decl.blocks.first() {
+            Some(entry) if !entry.params.is_empty() => {
+                Err("Declarations take no parameters".to_string())
+            }
+            _ => verify_func(decl, &globals, &funcs, &ctors),
+        };
+        verified.map_err(|message| VerifyError {
+            func: decl.name.clone(),
+            message,
+        })?;
+        globals.insert(&decl.name);
+    }
+    Ok(())
+}
+
+/// The registers assigned on every path to a point, `None` until a path is found
+struct Assigned;
+
+impl Analysis for Assigned {
+    type Fact = Option<HashSet<Var>>;
+
+    fn direction(&self) -> Direction {
+        Direction::Forward
+    }
+
+    fn boundary(&self, _: &ssa::Func) -> Self::Fact {
+        Some(HashSet::new())
+    }
+
+    fn init(&self, _: &ssa::Func) -> Self::Fact {
+        None
+    }
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.clone().intersection(b.clone())),
+            (Some(set), None) | (None, Some(set)) => Some(set.clone()),
+            (None, None) => None,
+        }
+    }
+
+    fn transfer(&self, func: &ssa::Func, label: Label, fact: &Self::Fact) -> Self::Fact {
+        let block = &func.blocks[label];
+        let vars = block.insts.iter().map(|inst| inst.var.clone());
+        let assigns = block.params.iter().cloned().chain(vars);
+        fact.as_ref()
+            .map(|set| set.clone().union(assigns.collect()))
+    }
+}
+
+/** Verifies `func` with `globals` the global variables in scope and `funcs` the functions
+closures may be of */
+fn verify_func(
+    func: &ssa::Func,
+    globals: &HashSet<&str>,
+    funcs: &HashSet<&str>,
+    ctors: &Ctors,
+) -> Result<(), String> {
+    let blocks = &func.blocks;
+    if blocks.is_empty() {
+        return Err("Functions have an entry block".to_string());
+    }
+    let arity = |label: Label| match blocks.get(label) {
+        Some(block) => Ok(block.params.len()),
+        None => Err(format!("b{label} isn't a block")),
+    };
+    let mut assigned = HashSet::new();
+    for (label, block) in blocks.iter().enumerate() {
+        let vars = block.insts.iter().map(|inst| &inst.var);
+        for var in block.params.iter().chain(vars) {
+            if assigned.insert(var.as_str()).is_some() {
+                return Err(format!("{var} is assigned twice"));
+            }
+        }
+        for inst in &block.insts {
+            shape(&inst.op, ctors).map_err(|err| format!("{err}, in {}", inst.var))?;
+            if let Op::Closure(func, _) = &inst.op {
+                if !funcs.contains(func.as_str()) {
+                    return Err(format!("{func} isn't a function"));
+                }
+            }
+        }
+        let expected = |target: Label, n: usize| match arity(target)? {
+            params if params == n => Ok(()),
+            params => Err(format!(
+                "b{target} takes {params} parameters, b{label} passes {n}"
+            )),
+        };
+        match &block.term {
+            Term::Jump(target, args) => expected(*target, args.len())?,
+            Term::Call { ret, .. } => expected(*ret, 1)?,
+            Term::Switch { arms, .. } => {
+                ctors.arms(arms.iter().map(|(ctor, _)| ctor))?;
+                block
+                    .term
+                    .successors()
+                    .into_iter()
+                    .try_for_each(|l| expected(l, 0))?
+            }
+            term => term
+                .successors()
+                .into_iter()
+                .try_for_each(|l| expected(l, 0))?,
+        }
+        if let Some(handler) = block.handler {
+            expected(handler, 1)?
+        }
+    }
+    let entry = solve(&Assigned, func).entry;
+    for (label, block) in blocks.iter().enumerate() {
+        // Unreachable blocks use nothing
+        let Some(set) = &entry[label] else { continue };
+        let mut avail = set.clone().union(block.params.iter().cloned().collect());
+        let uses = |atoms: Vec<&Atom>, avail: &HashSet<Var>| {
+            for var in atoms.into_iter().filter_map(Atom::var) {
+                if !avail.contains(var) && !globals.contains(var.as_str()) {
+                    return Err(format!("{var} is used in b{label} before it's assigned"));
+                }
+            }
+            Ok(())
+        };
+        for inst in &block.insts {
+            uses(inst.op.atoms(), &avail)?;
+            avail.insert(inst.var.clone());
+        }
+        uses(block.term.atoms(), &avail)?
+    }
+    Ok(())
+}
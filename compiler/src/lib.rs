@@ -1,4 +1,6 @@
 #[macro_use]
 extern crate lalrpop_util;
 pub mod ast;
+pub mod codegen;
 pub mod cps;
+pub mod ir;
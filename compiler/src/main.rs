@@ -1,3 +1,9 @@
+use polylamb::ast::interp::Strategy;
+use polylamb::ast::pretty::Limits;
+use polylamb::ast::repl::Emit;
+use polylamb::codegen::riscv::Options;
+use polylamb::ir::pass::{Level, PassManager};
+
 // use annotate_snippets::display_list::{DisplayList, FormatOptions};
 // use annotate_snippets::snippet::{Annotation, Slice, Snippet};
 // use polylamb::ast::parse::parse_prog;
@@ -6,7 +12,183 @@
 // const SOURCE: &str = "tests/progs/simple.polylamb";
 
 fn main() {
-    let _ = polylamb::ast::repl::repl();
+    // `--trace` prints every reduction step of the expressions evaluated, `--trace=N` the N first.
+    // `--strategy=cbv|cbn|lazy` evaluates by value, by name, or lazily. `--depth=N` and `--width=N`
+    // elide values and expressions nested N deep, and entries past the N first. `--debug=FILE`
+    // debugs the program in FILE instead. `--coverage=FILE` evaluates it and prints its source
+    // annotated with coverage, `--lcov=FILE` an lcov tracefile of it. `--compile=FILE` prints the
+    // program in FILE compiled to SSA, `--verify-ir` verifying the IR after every pass.
+    // `-O0|-O1|-O2` picks the passes run, `--passes=a,b,c` lists them instead. `--time-passes`
+    // prints the time each pass takes, `--dump-after=a,b|all` the IR after them. `--entry=NAME`
+    // leaves out the declarations NAME doesn't use, `--mono` replaces polymorphic ones by their
+    // instances. `--emit=dot-ast`, `dot-cfg` or `dot-callgraph` prints its syntax tree, control
+    // flow graphs or call graph for Graphviz instead, and `--emit=asm` its RV32I assembly,
+    // `--march=rv32i|rv32im|rv32ic|rv32imc` selecting the instructions of the M extension and
+    // compressing those of the C extension too, `--regalloc=linear|spill` allocating registers by
+    // linear scan or spilling every one, and `--pic` addressing symbols relative to the code
+    let mut trace = None;
+    let mut strategy = Strategy::default();
+    let mut debug = None;
+    let mut coverage = None;
+    let mut compile = None;
+    let mut verify_ir = false;
+    let mut level = Level::default();
+    let mut passes = None;
+    let mut time_passes = false;
+    let mut dump_after = vec![];
+    let mut entry = None;
+    let mut mono = false;
+    let mut emit = None;
+    let mut options = Options::default();
+    let mut limits = Limits::default();
+    for arg in std::env::args().skip(1) {
+        if let Some((flag, n)) = arg.split_once('=') {
+            if flag == "--depth" || flag == "--width" {
+                match n.parse() {
+                    Ok(n) if flag == "--depth" => limits.depth = n,
+                    Ok(n) => limits.width = n,
+                    Err(_) => return eprintln!("Expected a number in {}", arg),
+                }
+                continue;
+            }
+        }
+        if let Some(file) = arg.strip_prefix("--compile=") {
+            compile = Some(file.to_string());
+            continue;
+        }
+        if arg == "--pic" {
+            options.pic = true;
+            continue;
+        }
+        if arg == "--mono" {
+            mono = true;
+            continue;
+        }
+        if arg == "--verify-ir" || arg == "--time-passes" {
+            verify_ir |= arg == "--verify-ir";
+            time_passes |= arg == "--time-passes";
+            continue;
+        }
+        if let Some(n) = arg.strip_prefix("-O") {
+            match n.parse() {
+                Ok(n) => level = n,
+                Err(()) => return eprintln!("Expected -O0, -O1 or -O2 in {}", arg),
+            }
+            continue;
+        }
+        if let Some(names) = arg.strip_prefix("--passes=") {
+            passes = Some(names.split(',').map(str::to_string).collect::<Vec<_>>());
+            continue;
+        }
+        if let Some(name) = arg.strip_prefix("--entry=") {
+            entry = Some(name.to_string());
+            continue;
+        }
+        if let Some(kind) = arg.strip_prefix("--emit=") {
+            match kind.parse() {
+                Ok(kind) => emit = Some(kind),
+                Err(()) => {
+                    return eprintln!("Expected asm, dot-ast, dot-cfg or dot-callgraph in {}", arg)
+                }
+            }
+            continue;
+        }
+        if let Some(march) = arg.strip_prefix("--march=") {
+            match march.parse() {
+                Ok(march) => options.march = march,
+                Err(()) => {
+                    return eprintln!("Expected rv32i, rv32im, rv32ic or rv32imc in {}", arg)
+                }
+            }
+            continue;
+        }
+        if let Some(kind) = arg.strip_prefix("--regalloc=") {
+            match kind.parse() {
+                Ok(kind) => options.allocator = kind,
+                Err(()) => return eprintln!("Expected linear or spill in {}", arg),
+            }
+            continue;
+        }
+        if let Some(names) = arg.strip_prefix("--dump-after=") {
+            dump_after = names.split(',').map(str::to_string).collect();
+            continue;
+        }
+        if let Some(file) = arg.strip_prefix("--debug=") {
+            debug = Some(file.to_string());
+            continue;
+        }
+        if let Some((flag, file)) = arg.split_once('=') {
+            if flag == "--coverage" || flag == "--lcov" {
+                coverage = Some((file.to_string(), flag == "--lcov"));
+                continue;
+            }
+        }
+        if let Some(s) = arg.strip_prefix("--strategy=") {
+            match s.parse() {
+                Ok(s) => strategy = s,
+                Err(()) => return eprintln!("Expected cbv, cbn or lazy in {}", arg),
+            }
+            continue;
+        }
+        match arg.strip_prefix("--trace") {
+            Some("") => trace = Some(usize::MAX),
+            Some(n) => match n.strip_prefix('=').and_then(|n| n.parse().ok()) {
+                Some(n) => trace = Some(n),
+                None => return eprintln!("Expected a number of steps in {}", arg),
+            },
+            None => return eprintln!("Unknown argument {}", arg),
+        }
+    }
+    if let Some((file, lcov)) = coverage {
+        match std::fs::read_to_string(&file) {
+            Ok(source) => polylamb::ast::repl::coverage(&source, &file, lcov),
+            Err(err) => eprintln!("Cannot read {}: {}", file, err),
+        }
+        return;
+    }
+    if let Some(Emit::Asm(kind)) = &mut emit {
+        *kind = options
+    }
+    if let Some(file) = compile {
+        let manager = match &passes {
+            Some(names) => {
+                PassManager::with_passes(&names.iter().map(|n| &n[..]).collect::<Vec<_>>())
+            }
+            None => Ok(PassManager::preset(level)),
+        };
+        let mut manager = match manager {
+            Ok(manager) => manager,
+            Err(err) => return eprintln!("{}", err),
+        };
+        manager.verify = verify_ir;
+        manager.entry = entry;
+        manager.mono = mono;
+        if time_passes {
+            manager.hook(|pass, _, time| eprintln!("{:<10} {:?}", pass, time))
+        }
+        if !dump_after.is_empty() {
+            manager.hook(|pass, ir, _| {
+                if dump_after.iter().any(|name| name == pass || name == "all") {
+                    eprintln!("; IR after {}\n{}", pass, ir)
+                }
+            })
+        }
+        match std::fs::read_to_string(&file) {
+            Ok(source) => polylamb::ast::repl::compile(&source, &mut manager, emit),
+            Err(err) => eprintln!("Cannot read {}: {}", file, err),
+        }
+        return;
+    }
+    if let Some(file) = debug {
+        match std::fs::read_to_string(&file) {
+            Ok(source) => {
+                let _ = polylamb::ast::repl::debug(&source, limits);
+            }
+            Err(err) => eprintln!("Cannot read {}: {}", file, err),
+        }
+        return;
+    }
+    let _ = polylamb::ast::repl::repl(trace, strategy, limits);
     // let program = std::fs::read_to_string(SOURCE).expect("Hmm");
     // let prog = parse_prog(&program).unwrap();
     // let result = check_prog(&prog);
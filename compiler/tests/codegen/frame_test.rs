@@ -0,0 +1,136 @@
+use crate::codegen::riscv_test::{compile, C_MAIN};
+use polylamb::codegen::frame::{self, Frame};
+use polylamb::codegen::legalize::legalize;
+use polylamb::codegen::regalloc::{allocate, Allocator};
+use polylamb::codegen::riscv::{
+    Alu, Block, Func, Inst, Reg, Target, A0, CALLEE_SAVED, FP, RUNTIME, T1,
+};
+use polylamb::codegen::sim::run;
+use polylamb::ir::pass::Level;
+
+const FUEL: usize = 100_000_000;
+
+/** The function `name` of the blocks `blocks` of instructions */
+fn func(name: &str, blocks: Vec<Vec<Inst>>, vregs: usize, slots: usize, outgoing: usize) -> Func {
+    let blocks = blocks
+        .into_iter()
+        .enumerate()
+        .map(|(index, insts)| Block {
+            label: match index {
+                0 => name.to_string(),
+                _ => format!(".L{name}.b{index}"),
+            },
+            insts,
+        })
+        .collect();
+    Func {
+        name: name.to_string(),
+        blocks,
+        vregs,
+        slots,
+        outgoing,
+    }
+}
+
+/** `f` of a tuple of `n` values all live at once, each `x * (i + 2)`, printing its first and
+last of `f 1` */
+fn pressure(n: usize) -> String {
+    let values: Vec<String> = (0..n).map(|i| format!("x * {}", i + 2)).collect();
+    format!(
+        "let f : Int -> Int = λ x: Int. let t = ({}) in t.0 + t.{}\nlet main : Unit = printInt (f 1)",
+        values.join(", "),
+        n - 1
+    )
+}
+
+#[test]
+fn test_layout() {
+    let frame = Frame::new(&func("f.code", vec![vec![Inst::Ret]], 0, 3, 2));
+    assert_eq!(frame.size, 32);
+    assert!(frame.small());
+    assert_eq!((frame::slot(0), frame::slot(2)), (-12, -20));
+    let frame = Frame::new(&func("f.code", vec![vec![Inst::Ret]], 0, 0, 0));
+    assert_eq!(frame.size, 16);
+    let frame = Frame::new(&func("f.code", vec![vec![Inst::Ret]], 0, 600, 0));
+    assert_eq!(frame.size, 2416);
+    assert!(!frame.small())
+}
+
+#[test]
+fn test_shared_slots() {
+    // Two groups of 30 values live at once, one after the other
+    let mut insts = vec![Inst::Li(Reg::V(60), 0)];
+    for group in [0, 30] {
+        for i in group..group + 30 {
+            insts.push(Inst::Li(Reg::V(i), i as i32))
+        }
+        for i in group..group + 30 {
+            insts.push(Inst::Op(Alu::Add, Reg::V(60), Reg::V(60), Reg::V(i)))
+        }
+    }
+    insts.extend([Inst::OpImm(Alu::Add, A0, Reg::V(60), 0), Inst::Ret]);
+    let mut spilled = func("f.code", vec![insts], 61, 0, 0);
+    let mut allocated = spilled.clone();
+    allocate(&mut spilled, Allocator::Spill);
+    allocate(&mut allocated, Allocator::LinearScan);
+    assert_eq!(spilled.slots, 61);
+    assert!(0 < allocated.slots && allocated.slots < 30, "{allocated}")
+}
+
+#[test]
+fn test_large_frames() {
+    let (_, asm) = compile(&pressure(200), Level::O0, Allocator::Spill);
+    assert!(asm.contains("    add sp, sp, t0\n"), "{asm}");
+    assert_eq!(run(&asm, "", FUEL).unwrap().output, "203");
+    // 600 values live at once, spilled to slots out of reach of the immediates
+    let mut insts: Vec<Inst> = (0..600).map(|i| Inst::Li(Reg::V(i), 0)).collect();
+    insts[0] = Inst::Li(Reg::V(0), 7);
+    insts[599] = Inst::Li(Reg::V(599), 5);
+    insts.push(Inst::Li(Reg::V(600), 0));
+    for i in 0..600 {
+        insts.push(Inst::Op(Alu::Add, Reg::V(600), Reg::V(600), Reg::V(i)))
+    }
+    insts.extend([Inst::OpImm(Alu::Add, A0, Reg::V(600), 0), Inst::Ret]);
+    let mut main = func("polylamb_main", vec![insts], 601, 0, 0);
+    allocate(&mut main, Allocator::LinearScan);
+    assert!(main.slots > 512);
+    frame::lower(&mut main);
+    legalize(&mut main, false);
+    let asm = format!("    .text\n{main}\n{RUNTIME}");
+    assert_eq!(run(&asm, "", FUEL).unwrap().code, 12)
+}
+
+#[test]
+fn test_saved_registers() {
+    let (s1, s3) = (CALLEE_SAVED[1], CALLEE_SAVED[3]);
+    let insts = vec![
+        Inst::Li(s3, 1),
+        Inst::Li(s1, 2),
+        Inst::Op(Alu::Add, A0, s1, s3),
+        Inst::Tail(Target::Reg(s1), 1),
+    ];
+    let mut f = func("f.code", vec![insts], 0, 1, 0);
+    let frame = Frame::new(&f);
+    assert_eq!(frame.saved, vec![s1, s3]);
+    assert_eq!(frame.size, 32);
+    frame::lower(&mut f);
+    let insts = &f.blocks[0].insts;
+    assert!(insts.contains(&Inst::Sw(s3, frame::slot(2), FP)));
+    assert!(insts.contains(&Inst::Lw(s1, frame::slot(1), FP)));
+    assert_eq!(insts.last(), Some(&Inst::Tail(Target::Reg(T1), 1)));
+    // Leaves changing none save none
+    let leaf = func("g.code", vec![vec![Inst::Li(A0, 1), Inst::Ret]], 0, 0, 0);
+    assert!(Frame::new(&leaf).saved.is_empty())
+}
+
+#[test]
+fn test_nested_calls() {
+    let src = "let fib : Int -> Int = fix fib = λ (n: Int) : Int. if n < 2 then n else fib (n - 1) + fib (n - 2) in fib\nlet main : Unit = let a = fib 10 in let b = fib 12 in printInt (a + b * fib 5)";
+    for level in [Level::O0, Level::O1, Level::O2] {
+        let (_, asm) = compile(src, level, Allocator::LinearScan);
+        assert!(asm.contains("    sw s1, "), "{asm}");
+        let asm = asm.replacen("    call polylamb_main\n", "    call c_main\n", 1) + C_MAIN;
+        let exit = run(&asm, "", FUEL).unwrap();
+        assert_eq!((&exit.output[..], exit.code), ("775", 0), "{level:?}")
+    }
+}
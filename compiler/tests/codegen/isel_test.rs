@@ -0,0 +1,60 @@
+use polylamb::ast::ast::{Binary, Constant};
+use polylamb::codegen::isel::{best, treeable, Binding, Tree, RULES};
+use polylamb::ir::anf::Atom;
+use polylamb::ir::ssa::Op;
+
+fn leaf(atom: &Atom, raw: bool) -> Box<Tree<'_>> {
+    Box::new(Tree::Leaf(atom, raw))
+}
+
+/** The name of the rule covering `tree` */
+fn rule(tree: &Tree) -> &'static str {
+    best(RULES, tree).unwrap().1.name
+}
+
+#[test]
+fn test_tagged_arithmetic() {
+    let (x, y) = (Atom::Var("x".into()), Atom::Var("y".into()));
+    let one = Atom::Con(Constant::Integer(1));
+    let increment = Tree::Tag(Box::new(Tree::Binop(
+        Box::new(Tree::Untag(leaf(&x, false))),
+        Binary::Add,
+        leaf(&one, true),
+    )));
+    let (cost, chosen, bindings) = best(RULES, &increment).unwrap();
+    assert_eq!((cost, chosen.name), (1, "tagged-addi"));
+    assert_eq!(bindings[1], Binding::Imm(1));
+    let sum = Tree::Tag(Box::new(Tree::Binop(
+        Box::new(Tree::Untag(leaf(&x, false))),
+        Binary::Add,
+        Box::new(Tree::Untag(leaf(&y, false))),
+    )));
+    assert_eq!(best(RULES, &sum).unwrap().0, 2);
+    // Too large for `addi` once doubled
+    let large = Atom::Con(Constant::Integer(1500));
+    let tree = Tree::Binop(leaf(&x, true), Binary::Add, leaf(&large, true));
+    assert_eq!(rule(&Tree::Tag(Box::new(tree.clone()))), "tag");
+    assert_eq!(rule(&tree), "addi");
+}
+
+#[test]
+fn test_branches() {
+    let (x, y) = (Atom::Var("x".into()), Atom::Var("y".into()));
+    let lt = Tree::Binop(leaf(&x, false), Binary::Lt, leaf(&y, false));
+    let branch = Tree::Branch(Box::new(Tree::Tag(Box::new(lt))));
+    assert_eq!(rule(&branch), "branch-lt");
+    assert_eq!(rule(&Tree::Branch(leaf(&x, false))), "branch");
+    let t = Atom::Con(Constant::Boolean(true));
+    assert_eq!(rule(&Tree::Branch(leaf(&t, false))), "branch-constant")
+}
+
+#[test]
+fn test_treeable() {
+    let x = Atom::Var("x".into());
+    let s = Atom::Con(Constant::Str("a".into()));
+    let zero = Atom::Con(Constant::Integer(0));
+    assert!(treeable(&Op::Binop(zero.clone(), Binary::Eq, x.clone())));
+    assert!(!treeable(&Op::Binop(s, Binary::Eq, x.clone())));
+    assert!(!treeable(&Op::Binop(x.clone(), Binary::Ne, x.clone())));
+    assert!(!treeable(&Op::Binop(x, Binary::Div, zero)))
+}
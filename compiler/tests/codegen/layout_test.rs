@@ -0,0 +1,100 @@
+use polylamb::codegen::layout::layout;
+use polylamb::codegen::riscv::{Alu, Block, Cond, Func, Inst, A0, A1, RUNTIME, ZERO};
+use polylamb::codegen::sim::run;
+
+const FUEL: usize = 100_000_000;
+
+/** The label of the block `index` of `polylamb_main` */
+fn label(index: usize) -> String {
+    match index {
+        0 => "polylamb_main".to_string(),
+        _ => format!(".Lpolylamb_main.b{index}"),
+    }
+}
+
+/** `polylamb_main` of the blocks `blocks`, in order, each labelled by its index */
+fn main(blocks: Vec<(usize, Vec<Inst>)>) -> Func {
+    let blocks = blocks
+        .into_iter()
+        .map(|(index, insts)| Block {
+            label: label(index),
+            insts,
+        })
+        .collect();
+    Func {
+        name: "polylamb_main".to_string(),
+        blocks,
+        vregs: 0,
+        slots: 0,
+        outgoing: 0,
+    }
+}
+
+/** The labels of the blocks of `func`, in order */
+fn order(func: &Func) -> Vec<String> {
+    func.blocks
+        .iter()
+        .map(|block| block.label.clone())
+        .collect()
+}
+
+/** The code `main` exits with and the number of instructions it runs */
+fn exit(main: &Func) -> (i32, usize) {
+    let exit = run(&format!("    .text\n{main}\n{RUNTIME}"), "", FUEL).unwrap();
+    (exit.code, exit.steps)
+}
+
+#[test]
+fn test_likely_successors() {
+    // An `if` whose first block falls through to its `then` and whose `else` falls through to
+    // the block after it
+    let mut func = main(vec![
+        (
+            0,
+            vec![
+                Inst::OpImm(Alu::Add, A0, ZERO, 0),
+                Inst::Branch(Cond::Eq, A0, ZERO, label(2)),
+            ],
+        ),
+        (1, vec![Inst::OpImm(Alu::Add, A0, A0, 1), Inst::J(label(3))]),
+        (2, vec![Inst::OpImm(Alu::Add, A0, A0, 2)]),
+        (3, vec![Inst::Ret]),
+    ]);
+    layout(&mut func);
+    assert_eq!(order(&func), [label(0), label(1), label(3), label(2)]);
+    assert_eq!(func.blocks[0].insts[2], Inst::J(label(1)));
+    assert_eq!(func.blocks[3].insts[1], Inst::J(label(3)));
+    assert_eq!(exit(&func).0, 2)
+}
+
+#[test]
+fn test_loops() {
+    // A loop counting to 10, its blocks scattered
+    let mut func = main(vec![
+        (
+            0,
+            vec![
+                Inst::OpImm(Alu::Add, A0, ZERO, 0),
+                Inst::OpImm(Alu::Add, A1, ZERO, 10),
+                Inst::J(label(1)),
+            ],
+        ),
+        (3, vec![Inst::Ret]),
+        (
+            2,
+            vec![Inst::Branch(Cond::Ge, A0, A1, label(3)), Inst::J(label(1))],
+        ),
+        (1, vec![Inst::OpImm(Alu::Add, A0, A0, 1), Inst::J(label(2))]),
+    ]);
+    let (code, steps) = exit(&func);
+    layout(&mut func);
+    assert_eq!(order(&func), [label(0), label(1), label(2), label(3)]);
+    // The loop branches back to its body, falling through to its exit
+    assert_eq!(
+        func.blocks[2].insts,
+        [Inst::Branch(Cond::Lt, A0, A1, label(1)), Inst::J(label(3))]
+    );
+    let (laid_out, fewer) = exit(&func);
+    assert_eq!((code, laid_out), (10, 10));
+    assert!(fewer < steps, "{fewer} < {steps}")
+}
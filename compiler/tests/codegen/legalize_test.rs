@@ -0,0 +1,103 @@
+use crate::codegen::riscv_test::compile_with;
+use polylamb::codegen::legalize::legalize;
+use polylamb::codegen::riscv::{Alu, Block, Func, Inst, Options, A0, FP, RUNTIME, T0, T2, ZERO};
+use polylamb::codegen::sim::run;
+use polylamb::ir::pass::Level;
+
+const FUEL: usize = 100_000_000;
+
+/** The instructions `inst` is legalized into */
+fn legalized(inst: Inst) -> Vec<Inst> {
+    let mut func = Func {
+        name: "f.code".to_string(),
+        blocks: vec![Block {
+            label: "f.code".to_string(),
+            insts: vec![inst],
+        }],
+        vregs: 0,
+        slots: 0,
+        outgoing: 0,
+    };
+    legalize(&mut func, false);
+    func.blocks.remove(0).insts
+}
+
+#[test]
+fn test_constants() {
+    assert_eq!(
+        legalized(Inst::Li(A0, -5)),
+        vec![Inst::OpImm(Alu::Add, A0, ZERO, -5)]
+    );
+    assert_eq!(legalized(Inst::Li(A0, 0x1000)), vec![Inst::Lui(A0, 1)]);
+    assert_eq!(
+        legalized(Inst::Li(A0, 0x12345678)),
+        vec![Inst::Lui(A0, 0x12345), Inst::OpImm(Alu::Add, A0, A0, 0x678)]
+    );
+    // The lower part is negative, rounding the upper part up
+    assert_eq!(
+        legalized(Inst::Li(A0, 0x12345fff)),
+        vec![Inst::Lui(A0, 0x12346), Inst::OpImm(Alu::Add, A0, A0, -1)]
+    );
+    assert_eq!(
+        legalized(Inst::OpImm(Alu::Add, A0, A0, 3000)),
+        vec![
+            Inst::Lui(T2, 1),
+            Inst::OpImm(Alu::Add, T2, T2, -1096),
+            Inst::Op(Alu::Add, A0, A0, T2)
+        ]
+    );
+}
+
+#[test]
+fn test_far_offsets() {
+    assert_eq!(
+        legalized(Inst::Lw(A0, 5000, A0)),
+        vec![
+            Inst::Lui(T2, 1),
+            Inst::Op(Alu::Add, T2, T2, A0),
+            Inst::Lw(A0, 904, T2)
+        ]
+    );
+    assert_eq!(
+        legalized(Inst::Sw(T2, -3000, FP)),
+        vec![
+            Inst::Lui(T0, 0xfffff),
+            Inst::Op(Alu::Add, T0, T0, FP),
+            Inst::Sw(T2, 1096, T0)
+        ]
+    );
+}
+
+#[test]
+fn test_pic() {
+    let src = "let s : String = \"ab\"\nlet main : Unit = let u = print s in printInt 123456789";
+    let mut outputs = vec![];
+    for pic in [false, true] {
+        let options = Options {
+            pic,
+            ..Options::default()
+        };
+        let (_, asm) = compile_with(src, Level::O1, options);
+        let code = asm.strip_suffix(RUNTIME).unwrap();
+        assert!(
+            !code.contains("\n    la ") && !code.contains("\n    li "),
+            "{code}"
+        );
+        assert_eq!(code.contains("auipc"), pic, "{code}");
+        assert_eq!(code.contains("%hi("), !pic, "{code}");
+        outputs.push(run(&asm, "", FUEL).unwrap().output)
+    }
+    assert_eq!(outputs, ["ab123456789", "ab123456789"])
+}
+
+#[test]
+fn test_large_blocks() {
+    // Tuples of 600 fields, their size and the offsets of their last fields out of range
+    let values: Vec<String> = (0..600).map(|i| format!("x * {}", i + 2)).collect();
+    let src = format!(
+        "let f : Int -> Int = λ x: Int. let t = ({}) in t.0 + t.599\nlet main : Unit = printInt (f 1)",
+        values.join(", ")
+    );
+    let (_, asm) = compile_with(&src, Level::O0, Options::default());
+    assert_eq!(run(&asm, "", FUEL).unwrap().output, "603")
+}
@@ -0,0 +1,8 @@
+mod frame_test;
+mod isel_test;
+mod layout_test;
+mod legalize_test;
+mod regalloc_test;
+mod relax_test;
+mod riscv_test;
+mod sim_test;
@@ -0,0 +1,62 @@
+use crate::codegen::riscv_test::compile;
+use polylamb::codegen::regalloc::Allocator;
+use polylamb::codegen::sim::run;
+use polylamb::ir::pass::Level;
+
+const FUEL: usize = 100_000_000;
+
+/** The loads and stores of slots in `asm` */
+fn accesses(asm: &str) -> usize {
+    asm.lines().filter(|line| line.ends_with("(s0)")).count()
+}
+
+#[test]
+fn test_allocator_parse() {
+    assert_eq!("linear".parse(), Ok(Allocator::LinearScan));
+    assert_eq!("spill".parse(), Ok(Allocator::Spill));
+    assert_eq!("coloring".parse::<Allocator>(), Err(()));
+    assert_eq!(Allocator::default(), Allocator::LinearScan)
+}
+
+#[test]
+fn test_fewer_accesses() {
+    let src = "let sum : Int -> Int = fix sum = λ (n: Int) : Int. if n == 0 then 0 else n + sum (n - 1) in sum\nlet main : Unit = printInt (sum 100)";
+    for level in [Level::O0, Level::O2] {
+        let (_, spilled) = compile(src, level, Allocator::Spill);
+        let (_, allocated) = compile(src, level, Allocator::LinearScan);
+        assert!(accesses(&allocated) < accesses(&spilled), "{allocated}");
+        let spilled = run(&spilled, "", FUEL).unwrap();
+        let allocated = run(&allocated, "", FUEL).unwrap();
+        assert_eq!(
+            (&spilled.output[..], &allocated.output[..]),
+            ("5050", "5050")
+        );
+        assert!(allocated.steps < spilled.steps)
+    }
+}
+
+#[test]
+fn test_spills_under_pressure() {
+    // Sixteen values live at once, more than there are registers to allocate
+    let values: Vec<String> = (0..16)
+        .map(|i| format!("let x{i} = n * {} in ", i + 2))
+        .collect();
+    let sum: Vec<String> = (0..16).map(|i| format!("x{i}")).collect();
+    let src = format!(
+        "let f : Int -> Int = λ n: Int. {}{}\nlet main : Unit = printInt (f 1)",
+        values.concat(),
+        sum.join(" + ")
+    );
+    let (_, asm) = compile(&src, Level::O0, Allocator::LinearScan);
+    assert!(asm.contains("sw t2, "), "{asm}");
+    assert_eq!(run(&asm, "", FUEL).unwrap().output, "152")
+}
+
+#[test]
+fn test_values_across_calls() {
+    let src = "let g : Int -> Int = λ n: Int. n * 2\nlet main : Unit = let a = g 3 in let b = g 4 in let c = g 5 in printInt (a - b + c * 10)";
+    for level in [Level::O0, Level::O1] {
+        let (_, asm) = compile(src, level, Allocator::LinearScan);
+        assert_eq!(run(&asm, "", FUEL).unwrap().output, "98")
+    }
+}
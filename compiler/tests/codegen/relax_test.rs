@@ -0,0 +1,110 @@
+use crate::codegen::riscv_test::compile_with;
+use polylamb::codegen::relax::relax;
+use polylamb::codegen::riscv::{Alu, Block, Cond, Func, Inst, Options, Target, A0, RUNTIME, ZERO};
+use polylamb::codegen::sim::{run, Fault};
+use polylamb::ir::pass::Level;
+
+const FUEL: usize = 100_000_000;
+
+/** `polylamb_main`, exiting with 12 unless `padding` instructions between the branch and jump
+of its first block and their targets are run */
+fn padded(padding: usize) -> Func {
+    let label = |index: usize| format!(".Lpolylamb_main.b{index}");
+    let mut padded = vec![Inst::OpImm(Alu::Add, A0, A0, 1); padding];
+    padded.push(Inst::Ret);
+    let blocks = vec![
+        vec![
+            Inst::OpImm(Alu::Add, A0, ZERO, 7),
+            Inst::Branch(Cond::Ne, A0, ZERO, label(2)),
+            Inst::J(label(1)),
+        ],
+        padded,
+        vec![
+            Inst::Branch(Cond::Eq, A0, ZERO, label(1)),
+            Inst::J(label(3)),
+        ],
+        vec![Inst::OpImm(Alu::Add, A0, A0, 5), Inst::Ret],
+    ];
+    let blocks = blocks
+        .into_iter()
+        .enumerate()
+        .map(|(index, insts)| Block {
+            label: match index {
+                0 => "polylamb_main".to_string(),
+                _ => label(index),
+            },
+            insts,
+        })
+        .collect();
+    Func {
+        name: "polylamb_main".to_string(),
+        blocks,
+        vregs: 0,
+        slots: 0,
+        outgoing: 0,
+    }
+}
+
+/** The program of the function `main` and the runtime */
+fn program(main: &Func) -> String {
+    format!("    .text\n{main}\n{RUNTIME}")
+}
+
+#[test]
+fn test_near_branches() {
+    let mut main = padded(100);
+    let before = main.clone();
+    relax(&mut main);
+    assert_eq!(main, before);
+    assert_eq!(run(&program(&main), "", FUEL).unwrap().code, 12)
+}
+
+#[test]
+fn test_far_branches() {
+    // 4400 bytes, past the 4 KiB branches reach, forward and backward
+    let mut main = padded(1100);
+    assert!(matches!(
+        run(&program(&main), "", FUEL),
+        Err(Fault::Assembly(..))
+    ));
+    relax(&mut main);
+    assert_eq!(
+        main.blocks[0].insts[1..],
+        [
+            Inst::Branch(Cond::Eq, A0, ZERO, ".Lpolylamb_main.far0".to_string()),
+            Inst::J(".Lpolylamb_main.b2".to_string()),
+            Inst::Label(".Lpolylamb_main.far0".to_string()),
+            Inst::J(".Lpolylamb_main.b1".to_string()),
+        ]
+    );
+    assert_eq!(
+        main.blocks[2].insts[0],
+        Inst::Branch(Cond::Ne, A0, ZERO, ".Lpolylamb_main.far1".to_string())
+    );
+    assert_eq!(run(&program(&main), "", FUEL).unwrap().code, 12)
+}
+
+#[test]
+fn test_far_jumps() {
+    // 1.2 MiB, past the 1 MiB jumps reach
+    let mut main = padded(300_000);
+    relax(&mut main);
+    assert_eq!(
+        main.blocks[0].insts[2],
+        Inst::Tail(Target::Symbol(".Lpolylamb_main.b2".to_string()), 0)
+    );
+    assert_eq!(run(&program(&main), "", FUEL).unwrap().code, 12)
+}
+
+#[test]
+fn test_large_functions() {
+    // Dividing by zero branches to the handler past the code of a tuple of 600 fields
+    let values: Vec<String> = (0..600).map(|i| format!("x * {}", i + 2)).collect();
+    let src = format!(
+        "let f : Int -> Int = λ x: Int. (let t = (10 / x, {}) in t.0 + t.600) handle Div => 3\nlet main : Unit = let u = printInt (f 1) in printInt (f 0)",
+        values.join(", ")
+    );
+    let (_, asm) = compile_with(&src, Level::O0, Options::default());
+    assert!(asm.contains(".far0:"), "{asm}");
+    assert_eq!(run(&asm, "", FUEL).unwrap().output, "6113")
+}
@@ -0,0 +1,452 @@
+use crate::ir::anf_test::PROGRAMS;
+use polylamb::ast::interp::{eval_prog_in, Store};
+use polylamb::ast::parse::parse_prog;
+use polylamb::ast::semant::check_prog;
+use polylamb::codegen::regalloc::Allocator;
+use polylamb::codegen::riscv::{emit, March, Options, RUNTIME};
+use polylamb::codegen::sim::{run, text_size, Exit};
+use polylamb::ir::interp::{self, Stop};
+use polylamb::ir::pass::{Ir, Level, PassManager};
+
+const FUEL: usize = 100_000_000;
+
+/// Programs, what they print, and the code they exit with
+const RUN: &[(&str, &str, i32)] = &[
+    (
+        "let main : Unit = let u = print \"a\\n\" in printInt (3 * (0 - 4) + 100 / 7 - 9 % 4)",
+        "a\n1",
+        0,
+    ),
+    (
+        "let main : Unit = printInt (if 1 < 2 & (3 > 4 | 2 != 2) == false then 1 else 0)",
+        "1",
+        0,
+    ),
+    (
+        "let t : Int * Int * Int = (1, 2, 3)\nlet main : Unit = printInt (t.0 + t.2)",
+        "4",
+        0,
+    ),
+    (
+        "let r : {a: Int, b: Int} = {b = 2, a = 1}\nlet main : Unit = printInt (r.b - r.a)",
+        "1",
+        0,
+    ),
+    (
+        "let f : Int -> Int -> Int = λ x: Int. λ y: Int. x - y\nlet main : Unit = printInt (f 10 3)",
+        "7",
+        0,
+    ),
+    (
+        "let main : Unit = printInt ((1 / 0) handle Div => 0 - 5)",
+        "-5",
+        0,
+    ),
+    (
+        "let main : Unit = let a = array(3, 2) in let u = update(a, 2, 7) in printInt (sub(a, 0) + sub(a, 2))",
+        "9",
+        0,
+    ),
+    (
+        "let main : Int = let a = array(3, 2) in sub(a, 3)",
+        "",
+        2,
+    ),
+    ("let main : Unit = assert (\"ab\" == \"ba\")", "", 1),
+    (
+        "let main : Unit = printInt (if (1, \"ab\") == (1, \"ab\") then 1 else 0)",
+        "1",
+        0,
+    ),
+    (
+        "let main : Unit = print (concat (showInt (0 - 120), concat (\" \", showInt 7)))",
+        "-120 7",
+        0,
+    ),
+    (
+        "data Option A = None | Some A deriving (Show)\ndata Box = Box (Option (Option Int)) String deriving (Show)\nlet main : Unit = print (show_Box (Box (Some (Some 12)) \"a\"))",
+        "Box (Some (Some 12)) \"a\"",
+        0,
+    ),
+    // Constrained functions take dictionaries
+    (
+        "class Size A { size: A -> Int }\ninstance Size Int { size = λ n. n }\ninstance Size Bool { size = λ b. 1 }\nlet total : ∀ (A: Size). A -> A -> Int = Λ (A: Size). λ x y. size x + size y\nlet main : Unit = printInt (total 20 21 + total true false)",
+        "43",
+        0,
+    ),
+    // Tasks run when spawned, their exceptions raised when joined
+    (
+        "let main : Unit = let t = spawn (print \"task \") in print \"main\"",
+        "task main",
+        0,
+    ),
+    (
+        "let main : Unit = let t = spawn (let u = print \"a\" in 1 / 0) in let v = print \"b\" in printInt (join t handle Div => 7)",
+        "ab7",
+        0,
+    ),
+];
+
+/** The assembly of `src` compiled at `level`, its registers allocated by `allocator` */
+pub(crate) fn compile(src: &str, level: Level, allocator: Allocator) -> (Ir, String) {
+    let options = Options {
+        allocator,
+        ..Options::default()
+    };
+    compile_with(src, level, options)
+}
+
+/** The assembly of `src` compiled at `level` with `options` */
+pub(crate) fn compile_with(src: &str, level: Level, options: Options) -> (Ir, String) {
+    let mut manager = PassManager::preset(level);
+    let prog = manager.lower(&parse_prog(src).expect(src)).expect(src);
+    let ir = manager.run(prog).expect(src);
+    let Ir::Ssa(prog) = &ir else {
+        panic!("{src} isn't in SSA")
+    };
+    let asm = emit(prog, options);
+    (ir, asm)
+}
+
+#[test]
+fn test_run() {
+    for (src, output, code) in RUN {
+        for level in [Level::O0, Level::O2] {
+            let (_, asm) = compile(src, level, Allocator::LinearScan);
+            let exit = run(&asm, "", FUEL).expect(src);
+            assert_eq!((&exit.output[..], exit.code), (*output, *code), "{src}")
+        }
+    }
+}
+
+#[test]
+fn test_agrees_with_interpreter() {
+    for (level, allocator) in [
+        (Level::O0, Allocator::Spill),
+        (Level::O0, Allocator::LinearScan),
+        (Level::O1, Allocator::LinearScan),
+        (Level::O2, Allocator::Spill),
+        (Level::O2, Allocator::LinearScan),
+    ] {
+        for src in PROGRAMS {
+            let (ir, asm) = compile(src, level, allocator);
+            let outcome = interp::run(&ir, "", FUEL);
+            let code = match outcome.result {
+                Ok(_) => 0,
+                Err(Stop::Assertion) => 1,
+                Err(Stop::Uncaught(_)) => 2,
+                Err(stop) => panic!("{stop:?}: {src}"),
+            };
+            let Exit {
+                output, code: exit, ..
+            } = run(&asm, "", FUEL).expect(src);
+            assert_eq!(
+                (output, exit),
+                (outcome.output, code),
+                "{level:?} {allocator:?}: {src}"
+            )
+        }
+    }
+}
+
+/// Programs whose integers wrap around past the 31 bits of `Int`
+const BOUNDARY: &[&str] = &[
+    "let f : Int -> Int = λ x: Int. x * 3\nlet main : Unit = printInt (f 1000000000)",
+    "let sum : Int -> Int -> Int = fix sum = λ (n: Int) : Int -> Int. λ acc: Int. if n == 0 then acc else sum (n - 1) (acc + n) in sum\nlet main : Unit = printInt (sum 100000 0)",
+    "let f : Int -> Int = λ x: Int. x + 1\nlet main : Unit = let u = printInt (f 1073741823) in printInt (if f 1073741823 < 0 then 1 else 0)",
+    "let f : Int -> Int -> Int = λ x: Int. λ y: Int. x / y\nlet main : Unit = let u = printInt (f (0 - 1073741823 - 1) (0 - 1)) in printInt (f 7 (0 - 2))",
+    "let shl : Int -> Int -> Int = λ x: Int. λ n: Int. x << n\nlet main : Unit = let u = printInt (shl 1 30) in let v = printInt (shl 1 31) in printInt (shl 3 (0 - 2))",
+    "let f : Int8 -> Int = λ x: Int8. int(x) * 1000000\nlet main : Unit = printInt (f 127 + f (-128))",
+];
+
+#[test]
+fn test_agrees_at_boundary() {
+    for src in BOUNDARY {
+        let mut expected = vec![];
+        let mut store = Store::new("".as_bytes(), &mut expected);
+        eval_prog_in(&parse_prog(src).unwrap(), &mut store).expect(src);
+        drop(store);
+        let expected = String::from_utf8(expected).unwrap();
+        for level in [Level::O0, Level::O2] {
+            let (_, asm) = compile(src, level, Allocator::LinearScan);
+            let exit = run(&asm, "", FUEL).expect(src);
+            assert_eq!(exit.output, expected, "{level:?}: {src}")
+        }
+    }
+}
+
+#[test]
+fn test_long_lists() {
+    // Checking and lowering recurse on the tail of each `Cons`, 10000 deep
+    let entries: Vec<String> = (1..=10000).map(|i| i.to_string()).collect();
+    let src = format!(
+        "let xs : List Int = [{}]\nlet sum : List Int -> Int = fix sum = λ (xs: List Int) : Int. case xs of Nil => 0; Cons x rest => x + sum rest end in sum\nlet main : Unit = printInt (sum xs)",
+        entries.join(", ")
+    );
+    check_prog(&parse_prog(&src).unwrap()).unwrap();
+    for level in [Level::O0, Level::O2] {
+        let (_, asm) = compile(&src, level, Allocator::LinearScan);
+        assert_eq!(run(&asm, "", FUEL).unwrap().output, "50005000")
+    }
+}
+
+/// Polymorphic programs, monomorphized before they're compiled, and instances they have
+const POLYMORPHIC: &[(&str, &str)] = &[
+    (
+        "let id : ∀ A. A -> A = λ x: A. x\nlet main : Unit = if id [Bool] true then printInt (id [Int] 42) else null",
+        "id<Int>",
+    ),
+    (
+        "let pair : ∀ A. ∀ B. A -> B -> A * B = Λ A. Λ B. λ x: A. λ y: B. (x, y)\nlet main : Unit = printInt ((pair [Bool] [Int] true 40).1 + (pair [Int] [Int] 1 1).0 * 2)",
+        "pair<Int, Int>",
+    ),
+    (
+        "let length : ∀ A. List A -> Int = Λ A. fix len = λ (l: List A) : Int. case l of [] => 0; _ :: t => 1 + len t end in len\nlet main : Unit = printInt (length [Int] ([1, 2]) + length [List Bool] ([[true]]))",
+        "length<List Bool>",
+    ),
+];
+
+#[test]
+fn test_monomorphized() {
+    for (src, instance) in POLYMORPHIC {
+        let mut expected = vec![];
+        let mut store = Store::new("".as_bytes(), &mut expected);
+        eval_prog_in(&parse_prog(src).unwrap(), &mut store).expect(src);
+        drop(store);
+        let expected = String::from_utf8(expected).unwrap();
+        for level in [Level::O0, Level::O2] {
+            let mut manager = PassManager::preset(level);
+            manager.mono = true;
+            let prog = manager.lower(&parse_prog(src).unwrap()).expect(src);
+            assert!(prog.decl(instance).is_some(), "{instance} in {src}");
+            let Ok(Ir::Ssa(prog)) = manager.run(prog) else {
+                panic!("{src} isn't in SSA")
+            };
+            let asm = emit(&prog, Options::default());
+            let exit = run(&asm, "", FUEL).expect(src);
+            assert_eq!(exit.output, expected, "{level:?}: {src}")
+        }
+    }
+    // Polymorphism only known when running the program can't be compiled monomorphized
+    let src = "let apply : (∀ A. A -> A) -> Int = λ f: ∀ A. A -> A. f [Int] 1";
+    let mut manager = PassManager::preset(Level::O0);
+    manager.mono = true;
+    let err = manager.lower(&parse_prog(src).unwrap()).unwrap_err();
+    assert_eq!(err.title, "Cannot monomorphize")
+}
+
+/// Programs on integers of every width, `Int32` and `Int64` boxed, printing the low bits of each
+const WIDE: &[&str] = &[
+    "let f : Int64 -> Int64 = λ x: Int64. x * 3\nlet main : Unit = printInt (int(f 1000000000 / 1000))",
+    "let x : Int32 = 2147483647\nlet main : Unit = let u = printInt (int((x + 1) >> 16)) in printInt (int(x * x))",
+    "let p : Int64 -> Unit = λ x: Int64. let u = printInt (int(x >> 40)) in let v = printInt (int(x >> 20 land 1048575)) in printInt (int(x land 1048575))\nlet x : Int64 = 0 - 81985529216486895\nlet y : Int64 = 1311768467463790320\nlet main : Unit = let a = p (x + y) in let b = p (x - y) in let c = p (x * y) in let d = p (y / 12345) in let e = p ((x / 0 - 7) handle Div => y) in let f = p (x % 1000003) in p (y % (0 - 77))",
+    "let p : Int64 -> Unit = λ x: Int64. printInt (int(x land 1073741823) + int(x >> 32))\nlet x : Int64 = 0 - 6148914691236517206\nlet main : Unit = let a = p (x << 0) in let b = p (x << 5) in let c = p (x << 32) in let d = p (x << 40) in let e = p (x >> 63) in let f = p (x >> 33) in let g = p (x << 64) in let h = p (x lxor 255 lor 4096) in p (x >> (0 - 1))",
+    "let x : Int64 = 4294967296\nlet main : Unit = let a = printInt (if x > 4294967295 & x + 1 != x & (x, 1) == (4294967296, 1) then 1 else 0) in printInt (if x < 0 - x | [x] == [x + 1] then 1 else 0)",
+    "let main : Unit = let a = printInt (int(int64(int8(200)) * 3)) in let b = printInt (int(int32((5000000000 : Int64)))) in let c = printInt (int(int8(int64(1000)))) in printInt (int(int32(int64(int32(0 - 3)))))",
+    "let x : Int8 = 127\nlet y : Int16 = 200\nlet main : Unit = let a = printInt (int(x + 1)) in let b = printInt (int(x << 9)) in let c = printInt (int(y * y)) in printInt (if int8(0 - 128) / int8(0 - 1) < x then 1 else 0)",
+    "let id : ∀ A. A -> A = Λ A. λ x: A. x\nlet main : Unit = let r = ref (id [Int64] 123456789012) in let u = r := !r + 1 in let v = printInt (int(!r / 1000)) in printInt ((int(id [Int32] 70000 * 70000) handle Div => 0) + (int((!r / (!r - !r))) handle Div => 9))",
+];
+
+#[test]
+fn test_wide_integers() {
+    for src in WIDE {
+        let mut expected = vec![];
+        let mut store = Store::new("".as_bytes(), &mut expected);
+        eval_prog_in(&parse_prog(src).expect(src), &mut store).expect(src);
+        drop(store);
+        let expected = String::from_utf8(expected).unwrap();
+        for level in [Level::O0, Level::O2] {
+            let (ir, asm) = compile(src, level, Allocator::LinearScan);
+            assert_eq!(
+                interp::run(&ir, "", FUEL).output,
+                expected,
+                "{level:?}: {src}"
+            );
+            let exit = run(&asm, "", FUEL).expect(src);
+            assert_eq!(
+                (&exit.output[..], exit.code),
+                (&expected[..], 0),
+                "{level:?}: {src}"
+            )
+        }
+    }
+}
+
+#[test]
+fn test_m_extension() {
+    assert_eq!("rv32im".parse(), Ok(March { m: true, c: false }));
+    assert_eq!("rv32i".parse(), Ok(March::default()));
+    assert!("rv32imm".parse::<March>().is_err() && "rv64i".parse::<March>().is_err());
+    let options = Options {
+        march: March { m: true, c: false },
+        ..Options::default()
+    };
+    for (src, output, code) in RUN {
+        for level in [Level::O0, Level::O2] {
+            let (_, asm) = compile_with(src, level, options);
+            let code_only = asm.strip_suffix(RUNTIME).unwrap();
+            assert!(!code_only.contains("call __"), "{code_only}");
+            let exit = run(&asm, "", FUEL).expect(src);
+            assert_eq!((&exit.output[..], exit.code), (*output, *code), "{src}")
+        }
+    }
+    let (_, asm) = compile_with(RUN[0].0, Level::O0, options);
+    for mnemonic in ["mul", "div", "rem"] {
+        assert!(asm.contains(&format!("\n    {mnemonic} ")), "{asm}")
+    }
+}
+
+#[test]
+fn test_read_line() {
+    let src = "let main : Unit = let s = readLine null in let u = print s in print s";
+    let (_, asm) = compile(src, Level::O1, Allocator::LinearScan);
+    assert_eq!(run(&asm, "ab\ncd", FUEL).unwrap().output, "abab")
+}
+
+#[test]
+fn test_tail_calls() {
+    let src = "let loop : Int -> Int = fix loop = λ (n: Int) : Int. if n == 0 then 0 else loop (n - 1) in loop\nlet main : Unit = printInt (loop 100000)";
+    for level in [Level::O0, Level::O2] {
+        let (_, asm) = compile(src, level, Allocator::LinearScan);
+        assert_eq!(run(&asm, "", FUEL).unwrap().output, "0")
+    }
+}
+
+#[test]
+fn test_fused_branches() {
+    let src = "let f : Int -> Int = λ n: Int. if n < 10 then n + 1 else n\nlet main : Unit = printInt (f 3)";
+    let (_, asm) = compile(src, Level::O1, Allocator::LinearScan);
+    assert!(asm.contains("\n    blt "), "{asm}");
+    assert!(!asm.contains("andi t2, t0, 2\n"), "{asm}");
+    assert_eq!(run(&asm, "", FUEL).unwrap().output, "4")
+}
+
+/// `c_main`, calling `polylamb_main` as C would, and ending the program with 3 unless the
+/// registers the callee saves and the stack pointer are kept
+pub(crate) const C_MAIN: &str = "
+    .text
+c_main:
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    li s1, 1
+    li s2, 2
+    li s3, 3
+    li s4, 4
+    li s5, 5
+    li s6, 6
+    li s7, 7
+    li s8, 8
+    li s9, 9
+    li s10, 10
+    li s11, 11
+    mv s0, sp
+    call polylamb_main
+    bne s0, sp, .Lbroken
+    li t0, 1
+    bne s1, t0, .Lbroken
+    li t0, 2
+    bne s2, t0, .Lbroken
+    li t0, 3
+    bne s3, t0, .Lbroken
+    li t0, 4
+    bne s4, t0, .Lbroken
+    li t0, 5
+    bne s5, t0, .Lbroken
+    li t0, 6
+    bne s6, t0, .Lbroken
+    li t0, 7
+    bne s7, t0, .Lbroken
+    li t0, 8
+    bne s8, t0, .Lbroken
+    li t0, 9
+    bne s9, t0, .Lbroken
+    li t0, 10
+    bne s10, t0, .Lbroken
+    li t0, 11
+    bne s11, t0, .Lbroken
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+.Lbroken:
+    li a0, 3
+    li a7, 93
+    ecall
+";
+
+/// `polylamb_print_int` as C may compile it, checking the stack is aligned, and changing every
+/// register the caller saves but `a0`
+const C_PRINT_INT: &str = "
+    .text
+polylamb_print_int:
+    andi t0, sp, 15
+    bnez t0, .Lmisaligned
+    addi sp, sp, -16
+    sw ra, 12(sp)
+    call polylamb_print_int_asm
+    li a1, 0x5a5
+    li a2, 0x5a5
+    li a3, 0x5a5
+    li a4, 0x5a5
+    li a5, 0x5a5
+    li a6, 0x5a5
+    li a7, 0x5a5
+    li t0, 0x5a5
+    li t1, 0x5a5
+    li t2, 0x5a5
+    li t3, 0x5a5
+    li t4, 0x5a5
+    li t5, 0x5a5
+    li t6, 0x5a5
+    lw ra, 12(sp)
+    addi sp, sp, 16
+    ret
+.Lmisaligned:
+    li a0, 4
+    li a7, 93
+    ecall
+";
+
+#[test]
+fn test_called_from_c() {
+    let src = "let f : Int -> Int -> Int = λ x: Int. λ y: Int. x * y\nlet main : Unit = let n = f 6 7 in let u = printInt n in assert (n == 42)";
+    for level in [Level::O0, Level::O2] {
+        let (_, asm) = compile(src, level, Allocator::LinearScan);
+        let asm = asm.replacen("    call polylamb_main\n", "    call c_main\n", 1) + C_MAIN;
+        let exit = run(&asm, "", FUEL).unwrap();
+        assert_eq!((&exit.output[..], exit.code), ("42", 0))
+    }
+}
+
+#[test]
+fn test_calls_into_c() {
+    let src = "let p : Int -> Unit = λ n: Int. printInt n\nlet main : Unit = let u = (printInt 1) handle Div => null in p (10 / 5)";
+    for level in [Level::O0, Level::O2] {
+        let (_, asm) = compile(src, level, Allocator::LinearScan);
+        let asm = asm.replacen("polylamb_print_int:\n", "polylamb_print_int_asm:\n", 1);
+        let exit = run(&(asm + C_PRINT_INT), "", FUEL).unwrap();
+        assert_eq!((&exit.output[..], exit.code), ("12", 0))
+    }
+}
+
+#[test]
+fn test_c_extension() {
+    assert_eq!("rv32imc".parse(), Ok(March { m: true, c: true }));
+    assert_eq!("rv32ic".parse(), Ok(March { m: false, c: true }));
+    assert!("rv32icm".parse::<March>().is_err());
+    let compressed = Options {
+        march: March { m: true, c: true },
+        ..Options::default()
+    };
+    for (src, output, code) in RUN {
+        for level in [Level::O0, Level::O2] {
+            let (_, asm) = compile_with(src, level, compressed);
+            let exit = run(&asm, "", FUEL).expect(src);
+            assert_eq!((&exit.output[..], exit.code), (*output, *code), "{src}")
+        }
+        // A quarter smaller at least, the runtime included
+        let (_, plain) = compile_with(src, Level::O1, Options::default());
+        let (_, asm) = compile_with(src, Level::O1, compressed);
+        let (plain, compressed) = (text_size(&plain).unwrap(), text_size(&asm).unwrap());
+        assert!(4 * compressed < 3 * plain, "{compressed} of {plain}: {src}")
+    }
+}
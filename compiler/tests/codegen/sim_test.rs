@@ -0,0 +1,68 @@
+use polylamb::codegen::riscv::RUNTIME;
+use polylamb::codegen::sim::{run, text_size, Fault};
+
+/** `main` followed by the runtime */
+fn program(main: &str) -> String {
+    format!("    .text\npolylamb_main:\n{main}\n{RUNTIME}")
+}
+
+#[test]
+fn test_exit() {
+    let asm = program("    li a0, 7\n    li a7, 93\n    ecall");
+    assert_eq!(run(&asm, "", 1000).unwrap().code, 7);
+    let asm = program("    li a0, 0x12345678\n    li a1, 0x12345678\n    sub a0, a0, a1\n    li a7, 93\n    ecall");
+    assert_eq!(run(&asm, "", 1000).unwrap().code, 0)
+}
+
+#[test]
+fn test_faults() {
+    let asm = program("    lw a0, 0(zero)");
+    assert_eq!(run(&asm, "", 1000), Err(Fault::Memory(0)));
+    let asm = program("    addi a0, a0, 2048");
+    assert!(matches!(run(&asm, "", 1000), Err(Fault::Assembly(3, _))));
+    let asm = program("    beqz a0, far\n    .align 13\nfar:\n    ret");
+    assert!(matches!(run(&asm, "", 1000), Err(Fault::Assembly(3, _))));
+    let asm = program(".Lloop:\n    j .Lloop");
+    assert_eq!(run(&asm, "", 1000), Err(Fault::Exhausted))
+}
+
+#[test]
+fn test_m_extension() {
+    let exit = |op: &str, a: i32, b: i32| {
+        let asm = program(&format!(
+            "    li a0, {a}\n    li a1, {b}\n    {op} a0, a0, a1\n    li a7, 93\n    ecall"
+        ));
+        run(&asm, "", 1000).unwrap().code
+    };
+    assert_eq!(exit("mul", -7, 6), -42);
+    assert_eq!((exit("div", -7, 2), exit("rem", -7, 2)), (-3, -1));
+    assert_eq!((exit("div", 7, 0), exit("rem", 7, 0)), (-1, 7));
+    assert_eq!(
+        (exit("div", i32::MIN, -1), exit("rem", i32::MIN, -1)),
+        (i32::MIN, 0)
+    );
+    let asm = program("    muli a0, a0, 2");
+    assert!(matches!(run(&asm, "", 1000), Err(Fault::Assembly(3, _))))
+}
+
+#[test]
+fn test_compressed() {
+    let main = "    li a0, 5\n    addi a0, a0, 2\n    .align 2\n    li a7, 93\n    ecall";
+    let plain = program(main);
+    let compressed = program(&format!("    .option rvc\n{main}"));
+    assert_eq!(run(&compressed, "", 1000).unwrap().code, 7);
+    // `li` and `addi` take 2 bytes each, but the runtime, in the same mode
+    assert!(text_size(&compressed).unwrap() < text_size(&plain).unwrap() - 4);
+    let pushed = program(&format!(
+        "    .option rvc\n    .option push\n    .option norvc\n{main}\n    .option pop"
+    ));
+    // Only `li a0, 5` and `addi` are compressed, `li a7, 93` out of the range of `c.li`
+    assert_eq!(
+        text_size(&pushed).unwrap() - text_size(&compressed).unwrap(),
+        4
+    );
+    // Jumping into the middle of an instruction
+    let asm =
+        program("    .option rvc\n    li a0, 1\n    la t0, polylamb_main\n    jalr ra, 4(t0)");
+    assert_eq!(run(&asm, "", 1000), Err(Fault::Memory(0x10000 + 4)))
+}
@@ -0,0 +1,270 @@
+use polylamb::ast::erase::erase_prog;
+use polylamb::ast::lower;
+use polylamb::ast::parse::{parse_expr, parse_prog};
+use polylamb::ast::semant::{check_closed_expr, BUILTINS};
+use polylamb::ir::anf::{lower_checked, lower_expr, lower_prog, Atom, Comp, Expr, Prog};
+use std::collections::HashSet;
+
+/// Closed expressions and their A-normal forms
+const LOWERED: &[(&str, &str)] = &[
+    ("1 + 2 * 3", "let t$0 = 2 * 3 in\nlet t$1 = 1 + t$0 in\nt$1"),
+    (
+        "let f = λ x: Int. x + 1 in f 2",
+        "fix f$0(x$1) =\n    let t$2 = x$1 + 1 in\n    t$2\nin\nlet t$3 = f$0(2) in\nt$3",
+    ),
+    (
+        "(if true then 1 else 2) + 3",
+        "join k$0(v$1) =\n    let t$2 = v$1 + 3 in\n    t$2\nin\nif true then\n    jump k$0(1)\nelse\n    jump k$0(2)",
+    ),
+    (
+        "let (x, _, y) = (1, 2, 3) in x + y",
+        "let t$0 = (1, 2, 3) in\nlet x$1 = t$0.0 in\nlet y$2 = t$0.2 in\nlet t$3 = x$1 + y$2 in\nt$3",
+    ),
+    (
+        "(Λ A. λ x: A. x) [Int] 1",
+        "fix f$0(u$1) =\n    fix f$2(x$3) =\n        x$3\n    in\n    f$2\nin\nlet t$4 = f$0(null) in\nlet t$5 = t$4(1) in\nt$5",
+    ),
+    (
+        "case 3 of 0 => 1; n => n * 2 end",
+        "join next$2() =\n    let t$1 = 3 * 2 in\n    t$1\nin\nlet t$0 = 3 == 0 in\nif t$0 then\n    1\nelse\n    jump next$2()",
+    ),
+];
+
+/// Programs using every kind of expression
+pub const PROGRAMS: &[&str] = &[
+    r"data Option A = None | Some A
+      let get : Option Int -> Int = λ o: Option Int. case o of None => 0; Some x => x end
+      let main : Int = get (Some [Int] 1) + get (None [Int])",
+    r"let firsts : List (Int * Int) -> Int = λ l: List (Int * Int).
+          case l of [] => 0; (1, y) :: _ => y; _ :: (x, 3) :: _ => x; _ => 5 end
+      let main : Int = firsts ([(2, 0), (4, 3)])",
+    r"exception Fail Int
+      let main : Int = (raise [Int] (Fail 3)) handle Fail n => n
+      let div : Int = (1 / 0 handle Div => 2) + (if true then 1 else raise [Int] (Fail 0))",
+    r"let cons : Int -> List Int -> List Int = Cons [Int]
+      let nil : ∀ A. List A = Nil
+      let id : ∀ A. A -> A = Λ A. λ x: A. x
+      let main : List Int = cons (id [Int] 1) (nil [Int])",
+    r"let even_odd : Int -> Bool =
+          fix even = λ (n: Int) : Bool. if n == 0 then true else odd (n - 1)
+          and odd = λ (n: Int) : Bool. if n == 0 then false else even (n - 1)
+          in even
+      let main : Bool = even_odd 10",
+    r"let point : {x: Int, y: Int} = {x = 1, y = 2}
+      let main : Int = case point of {x = 1, y = y} => y; {x = x} => x end + point.x",
+    r"let main : Int =
+          let r = ref 0 in
+          let a = array(3, 1) in
+          let u = update(a, 0, !r) in
+          let v = r := sub(a, 1) + int(int8(300)) in
+          let t = spawn (!r + 1) in
+          let w = assertEq(join t, 2) in
+          !r",
+    r"signature COUNTER = sig type T val zero: T val get: T -> Int end
+      structure Counter : COUNTER = struct type T = Int let zero = 0 let get = λ n: T. n + 1 end
+      let main : Int = open Counter in get zero",
+    r"let main : Int =
+          let p = pack [Int] (1, λ n: Int. n) as ∃ S. S * (S -> Int) in
+          unpack [S] q = p in q.1 q.0",
+    r#"data Option A = None | Some A deriving (Show)
+      let main : Unit = print (concat (show_Option [Int] showInt (Some (0 - 4)), "
+"))"#,
+];
+
+/** Checks the variables of `prog` are bound once, and used where they're in scope. Jumps are
+checked to be to join points in scope outside of functions and handlers, with as many arguments
+as the join point has parameters */
+pub fn check_scopes(prog: &Prog) {
+    let mut bound = HashSet::new();
+    let mut globals: HashSet<String> = BUILTINS.iter().map(|(id, _)| id.to_string()).collect();
+    globals.extend(prog.funcs.iter().map(|f| f.name.clone()));
+    for func in &prog.funcs {
+        bind(&func.name, &mut bound);
+    }
+    // Functions at the top level may refer to any declaration
+    let decls = prog.decls.iter().map(|d| d.id.clone());
+    let func_scope: HashSet<String> = globals.iter().cloned().chain(decls).collect();
+    for func in &prog.funcs {
+        let mut scope = func_scope.clone();
+        for param in &func.params {
+            bind(param, &mut bound);
+            scope.insert(param.clone());
+        }
+        check_expr(&func.body, &scope, &[], &mut bound)
+    }
+    for decl in &prog.decls {
+        check_expr(&decl.body, &globals, &[], &mut bound);
+        globals.insert(decl.id.clone());
+    }
+}
+
+fn bind(var: &str, bound: &mut HashSet<String>) {
+    assert!(bound.insert(var.to_string()), "{var} is bound twice");
+}
+
+fn check_atom(atom: &Atom, scope: &HashSet<String>) {
+    if let Atom::Var(var) = atom {
+        assert!(scope.contains(var), "{var} is out of scope");
+    }
+}
+
+fn check_comp(comp: &Comp, scope: &HashSet<String>, bound: &mut HashSet<String>) {
+    match comp {
+        Comp::Closure(func, _) => assert!(scope.contains(func), "{func} is out of scope"),
+        Comp::Handle { body, exn, handler } => {
+            check_expr(body, scope, &[], bound);
+            bind(exn, bound);
+            let mut scope = scope.clone();
+            scope.insert(exn.clone());
+            check_expr(handler, &scope, &[], bound);
+        }
+        _ => (),
+    }
+    comp.atoms().into_iter().for_each(|a| check_atom(a, scope))
+}
+
+fn check_expr(
+    expr: &Expr,
+    scope: &HashSet<String>,
+    joins: &[(&str, usize)],
+    bound: &mut HashSet<String>,
+) {
+    let mut scope = scope.clone();
+    match expr {
+        Expr::Let { var, comp, body } => {
+            check_comp(comp, &scope, bound);
+            bind(var, bound);
+            scope.insert(var.clone());
+            check_expr(body, &scope, joins, bound)
+        }
+        Expr::Fix { funcs, body } => {
+            for func in funcs {
+                bind(&func.name, bound);
+                scope.insert(func.name.clone());
+            }
+            for func in funcs {
+                let mut scope = scope.clone();
+                for param in &func.params {
+                    bind(param, bound);
+                    scope.insert(param.clone());
+                }
+                check_expr(&func.body, &scope, &[], bound)
+            }
+            check_expr(body, &scope, joins, bound)
+        }
+        Expr::Join {
+            name,
+            params,
+            join,
+            body,
+        } => {
+            bind(name, bound);
+            let mut join_scope = scope.clone();
+            for param in params {
+                bind(param, bound);
+                join_scope.insert(param.clone());
+            }
+            check_expr(join, &join_scope, joins, bound);
+            let mut joins = joins.to_vec();
+            joins.push((name, params.len()));
+            check_expr(body, &scope, &joins, bound)
+        }
+        Expr::Jump { name, args } => {
+            match joins.iter().find(|(k, _)| k == name) {
+                Some((_, arity)) => assert_eq!(*arity, args.len(), "jump {name}"),
+                None => panic!("{name} is out of scope"),
+            }
+            args.iter().for_each(|a| check_atom(a, &scope))
+        }
+        Expr::If {
+            cond,
+            branch_t,
+            branch_f,
+        } => {
+            check_atom(cond, &scope);
+            check_expr(branch_t, &scope, joins, bound);
+            check_expr(branch_f, &scope, joins, bound)
+        }
+        Expr::Case {
+            scrut,
+            arms,
+            default,
+        } => {
+            check_atom(scrut, &scope);
+            for body in arms.iter().map(|(_, e)| e).chain(default.as_deref()) {
+                check_expr(body, &scope, joins, bound)
+            }
+        }
+        Expr::Raise { exn: val } | Expr::Ret { val } => check_atom(val, &scope),
+    }
+}
+
+/** The number of `Case`s in `expr` */
+fn cases(expr: &Expr) -> usize {
+    match expr {
+        Expr::Let {
+            comp: Comp::Handle { body, handler, .. },
+            body: rest,
+            ..
+        } => cases(body) + cases(handler) + cases(rest),
+        Expr::Let { body, .. } => cases(body),
+        Expr::Fix { funcs, body } => {
+            funcs.iter().map(|f| cases(&f.body)).sum::<usize>() + cases(body)
+        }
+        Expr::Join { join, body, .. } => cases(join) + cases(body),
+        Expr::If {
+            branch_t, branch_f, ..
+        } => cases(branch_t) + cases(branch_f),
+        Expr::Case { arms, default, .. } => {
+            let arms = arms.iter().map(|(_, e)| e).chain(default.as_deref());
+            1 + arms.map(cases).sum::<usize>()
+        }
+        Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => 0,
+    }
+}
+
+#[test]
+fn test_lowering() {
+    for (src, expected) in LOWERED {
+        let expr = parse_expr(src).unwrap();
+        check_closed_expr(&expr).unwrap();
+        assert_eq!(lower_expr(&expr).to_string(), *expected, "{src}");
+    }
+}
+
+#[test]
+fn test_scopes() {
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        check_scopes(&prog);
+        assert!(prog.decl("main").is_some(), "{src}");
+    }
+}
+
+#[test]
+fn test_erased() {
+    for src in PROGRAMS {
+        let prog = lower::lower_prog(&parse_prog(src).unwrap()).unwrap();
+        check_scopes(&lower_checked(&erase_prog(&prog)));
+    }
+    // Type abstractions aren't functions once erased
+    let prog = lower::lower_prog(&parse_prog(PROGRAMS[3]).unwrap()).unwrap();
+    let id = lower_checked(&erase_prog(&prog))
+        .decl("id")
+        .unwrap()
+        .body
+        .to_string();
+    assert_eq!(id, "fix id$7(x$8) =\n    x$8\nin\nid$7");
+}
+
+#[test]
+fn test_patterns() {
+    // Arms on distinct constructors binding their fields are a single `Case`
+    let prog = lower_prog(&parse_prog(PROGRAMS[0]).unwrap()).unwrap();
+    assert_eq!(cases(&prog.decl("get").unwrap().body), 1);
+    // Nested patterns test the constructors one after the other
+    let prog = lower_prog(&parse_prog(PROGRAMS[1]).unwrap()).unwrap();
+    assert_eq!(cases(&prog.decl("firsts").unwrap().body), 4);
+    assert_eq!(prog.ctor("Cons"), Some((1, 2)));
+    assert_eq!(prog.ctor("Div"), Some((1, 0)));
+}
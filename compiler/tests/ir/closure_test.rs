@@ -0,0 +1,97 @@
+use super::anf_test::{check_scopes, PROGRAMS};
+use polylamb::ast::parse::parse_prog;
+use polylamb::ast::semant::BUILTINS;
+use polylamb::ir::anf::{lower_prog, Comp, Expr, Func, Prog};
+use polylamb::ir::closure::convert;
+use std::collections::HashSet;
+
+/// Programs with functions capturing variables
+const CAPTURING: &[&str] = &[
+    "let add : Int -> Int -> Int = λ x: Int. λ y: Int. x + y",
+    r"let parity : Int -> Int -> Bool = λ k: Int. λ m: Int.
+          fix even = λ (n: Int) : Bool. if n == 0 then true else odd (n - k)
+          and odd = λ (n: Int) : Bool. if n == 0 then false else even (n - 1)
+          in even m",
+    r"let compose : ∀ A. ∀ B. ∀ C. (B -> C) -> (A -> B) -> A -> C =
+          Λ A. Λ B. Λ C. λ f: B -> C. λ g: A -> B. λ x: A. f (g x)
+      let main : Int = compose [Int] [Int] [Int] (λ x: Int. x * 2) (λ x: Int. x + 1) 3",
+];
+
+const CONVERTED: &str = "let add =
+    fix add$5(env$6, x$1) =
+        fix f$7(env$8, y$3) =
+            let x$9 = env$8@0 in
+            let t$4 = x$9 + y$3 in
+            t$4
+        in
+        let f$2 = closure f$7(x$1) in
+        f$2
+    in
+    let add$0 = closure add$5() in
+    add$0
+";
+
+/** The functions of the `fix`es of `expr` */
+pub fn funcs(expr: &Expr) -> Vec<&Func> {
+    match expr {
+        Expr::Let {
+            comp: Comp::Handle { body, handler, .. },
+            body: rest,
+            ..
+        } => [funcs(body), funcs(handler), funcs(rest)].concat(),
+        Expr::Let { body, .. } => funcs(body),
+        Expr::Fix { funcs: fs, body } => {
+            let nested = fs.iter().flat_map(|f| funcs(&f.body));
+            fs.iter().chain(nested).chain(funcs(body)).collect()
+        }
+        Expr::Join { join, body, .. } => [funcs(join), funcs(body)].concat(),
+        Expr::If {
+            branch_t, branch_f, ..
+        } => [funcs(branch_t), funcs(branch_f)].concat(),
+        Expr::Case { arms, default, .. } => {
+            let arms = arms.iter().map(|(_, e)| e).chain(default.as_deref());
+            arms.flat_map(funcs).collect()
+        }
+        Expr::Jump { .. } | Expr::Raise { .. } | Expr::Ret { .. } => vec![],
+    }
+}
+
+/** Checks the functions of `prog` only refer to their parameters, to globals, and to functions */
+pub fn check_closed(prog: &Prog) {
+    let nested = prog.funcs.iter().flat_map(|f| funcs(&f.body));
+    let all: Vec<&Func> = prog.funcs.iter().chain(nested).collect();
+    let all = all
+        .into_iter()
+        .chain(prog.decls.iter().flat_map(|d| funcs(&d.body)))
+        .collect::<Vec<_>>();
+    let mut globals: HashSet<&str> = BUILTINS.iter().map(|(id, _)| *id).collect();
+    globals.extend(prog.decls.iter().map(|d| d.id.as_str()));
+    globals.extend(all.iter().map(|f| f.name.as_str()));
+    for func in all {
+        for var in func.body.free_vars() {
+            let param = func.params.contains(&var);
+            assert!(
+                param || globals.contains(var.as_str()),
+                "{} captures {var}",
+                func.name
+            );
+        }
+    }
+}
+
+#[test]
+fn test_closure_conversion() {
+    for src in PROGRAMS.iter().chain(CAPTURING) {
+        let prog = convert(&lower_prog(&parse_prog(src).unwrap()).unwrap());
+        check_scopes(&prog);
+        check_closed(&prog);
+    }
+    let prog = convert(&lower_prog(&parse_prog(CAPTURING[0]).unwrap()).unwrap());
+    let decl = prog.decl("add").unwrap();
+    let printed = Prog {
+        datatypes: vec![],
+        funcs: vec![],
+        decls: vec![decl.clone()],
+    };
+    assert_eq!(printed.to_string().trim_start(), CONVERTED);
+}
@@ -0,0 +1,47 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::closure::convert;
+use polylamb::ir::copy::copy;
+use polylamb::ir::lift::lift;
+use polylamb::ir::ssa::{construct, Op, Prog};
+use polylamb::ir::text::parse_ssa;
+use polylamb::ir::verify::verify_ssa;
+
+/// Functions in static single assignment form, and what's left of them
+const PROPAGATED: &[(&str, &str)] = &[
+    (
+        "fun f\nb0(a):\n    b = a\n    c = b\n    d = c + 1\n    ret c",
+        "f\nb0(a):\n    d = a + 1\n    ret a\n",
+    ),
+    (
+        "fun f\nb0(a, b):\n    if b then b1 else b2\nb1:\n    c = a\n    jump b3(c, 1)\nb2:\n    jump b3(a, 2)\nb3(d, e):\n    g = d + e\n    ret g",
+        "f\nb0(a, b):\n    if b then b1 else b2\nb1:\n    jump b3(1)\nb2:\n    jump b3(2)\nb3(e):\n    g = a + e\n    ret g\n",
+    ),
+    (
+        "fun f\nb0(a, n):\n    jump b1(a, 0)\nb1(x, i):\n    c = i < n\n    if c then b2 else b3\nb2:\n    j = i + 1\n    jump b1(x, j)\nb3:\n    y = x + i\n    ret y",
+        "f\nb0(a, n):\n    jump b1(0)\nb1(i):\n    c = i < n\n    if c then b2 else b3\nb2:\n    j = i + 1\n    jump b1(j)\nb3:\n    y = a + i\n    ret y\n",
+    ),
+];
+
+/** The number of copies in the functions of `prog` */
+fn copies(prog: &Prog) -> usize {
+    let blocks = prog.funcs.iter().chain(&prog.decls).flat_map(|f| &f.blocks);
+    let insts = blocks.flat_map(|block| &block.insts);
+    insts.filter(|inst| matches!(inst.op, Op::Atom(_))).count()
+}
+
+#[test]
+fn test_propagation() {
+    for (src, func) in PROPAGATED {
+        let prog = copy(&parse_ssa(src).expect(src));
+        assert_eq!(prog.funcs[0].to_string(), *func, "{src}");
+        assert_eq!(verify_ssa(&prog), Ok(()), "{src}")
+    }
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        let prog = copy(&construct(&lift(&convert(&prog))));
+        assert_eq!(verify_ssa(&prog), Ok(()), "{src}");
+        assert_eq!(copies(&prog), 0, "{src}")
+    }
+}
@@ -0,0 +1,39 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::closure::convert;
+use polylamb::ir::cse::cse;
+use polylamb::ir::lift::lift;
+use polylamb::ir::ssa::construct;
+use polylamb::ir::text::parse_ssa;
+use polylamb::ir::verify::verify_ssa;
+
+/// Functions in static single assignment form, and what's left of them
+const ELIMINATED: &[(&str, &str)] = &[
+    (
+        "fun f\nb0(a, b):\n    c = a + b\n    d = b + a\n    e = a < b\n    g = b > a\n    if e then b1 else b2\nb1:\n    h = a + b\n    ret h\nb2:\n    i = c * d\n    ret i",
+        "f\nb0(a, b):\n    c = a + b\n    e = a < b\n    if e then b1 else b2\nb1:\n    ret c\nb2:\n    i = c * c\n    ret i\n",
+    ),
+    (
+        "fun f\nb0:\n    r = ref(1)\n    s = ref(1)\n    u = deref(r)\n    v = deref(r)\n    w = assign(r, 2)\n    y = deref(r)\n    z = (u, v, y)\n    ret z",
+        "f\nb0:\n    r = ref(1)\n    s = ref(1)\n    u = deref(r)\n    w = assign(r, 2)\n    y = deref(r)\n    z = (u, u, y)\n    ret z\n",
+    ),
+    (
+        "fun f\nb0(a):\n    jump b2()\nb1(e):\n    d = a + 1\n    ret d\nb2 unwind b1:\n    c = a + 1\n    g = a / 0\n    ret c",
+        "f\nb0(a):\n    jump b2()\nb1(e):\n    d = a + 1\n    ret d\nb2 unwind b1:\n    c = a + 1\n    g = a / 0\n    ret c\n",
+    ),
+];
+
+#[test]
+fn test_elimination() {
+    for (src, func) in ELIMINATED {
+        let prog = cse(&parse_ssa(src).expect(src));
+        assert_eq!(prog.funcs[0].to_string(), *func, "{src}");
+        assert_eq!(verify_ssa(&prog), Ok(()), "{src}")
+    }
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        let prog = construct(&lift(&convert(&prog)));
+        assert_eq!(verify_ssa(&cse(&prog)), Ok(()), "{src}")
+    }
+}
@@ -0,0 +1,40 @@
+use polylamb::ir::dataflow::{live_after, liveness, reverse_postorder};
+use polylamb::ir::ssa::Func;
+use polylamb::ir::text::parse_ssa;
+
+/** The sorted registers of `sets` */
+fn sorted(sets: &[im::HashSet<String>]) -> Vec<Vec<&str>> {
+    let sorted = sets.iter().map(|set| {
+        let mut vars: Vec<&str> = set.iter().map(|v| v.as_str()).collect();
+        vars.sort();
+        vars
+    });
+    sorted.collect()
+}
+
+fn func(src: &str) -> Func {
+    parse_ssa(src).expect(src).funcs.remove(0)
+}
+
+#[test]
+fn test_liveness() {
+    let src = "fun f\nb0(n, acc):\n    c = n == 0\n    if c then b1 else b2\nb1:\n    ret acc\nb2:\n    m = n - 1\n    a = acc + n\n    jump b0(m, a)";
+    let f = func(src);
+    let live = liveness(&f);
+    assert_eq!(sorted(&live.entry), [vec![], vec!["acc"], vec!["acc", "n"]]);
+    assert_eq!(sorted(&live.exit), [vec!["acc", "n"], vec![], vec![]]);
+    assert_eq!(
+        sorted(&live_after(&f, &live, 2)),
+        [vec!["acc", "m", "n"], vec!["a", "m"]]
+    );
+    let src = "fun f\nb0(x):\n    y = x + 1\n    jump b2()\nb1(e):\n    z = y + x\n    ret z\nb2 unwind b1:\n    w = x / 0\n    call print(w) to b3\nb3(v):\n    ret v";
+    let f = func(src);
+    let live = liveness(&f);
+    // What the handler uses is live all through the blocks it handles
+    assert_eq!(
+        sorted(&live.entry[1..]),
+        [vec!["x", "y"], vec!["x", "y"], vec![]]
+    );
+    assert_eq!(sorted(&live_after(&f, &live, 2)), [vec!["w", "x", "y"]]);
+    assert_eq!(reverse_postorder(&f), [0, 2, 1, 3]);
+}
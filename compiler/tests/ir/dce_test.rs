@@ -0,0 +1,57 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::dce::{dce, prune};
+use polylamb::ir::text::parse_anf;
+use polylamb::ir::verify::verify_anf;
+
+/// Programs in A-normal form and the bodies of their last declaration without dead code
+const ELIMINATED: &[(&str, &str)] = &[
+    (
+        "let x =\n    let a = 1 + 2 in\n    let b = a * 2 in\n    let c = print(\"c\") in\n    1",
+        "let c = print(\"c\") in\n1",
+    ),
+    ("let x =\n    let a = 1 / 0 in\n    let b = 1 / 2 in\n    1", "let a = 1 / 0 in\n1"),
+    (
+        "let x =\n    join k(v) =\n        v\n    in\n    fix f(u) =\n        let r = g(u) in\n        r\n    and g(w) =\n        let s = f(w) in\n        s\n    in\n    fix h(z) =\n        z\n    in\n    h",
+        "fix h(z) =\n    z\nin\nh",
+    ),
+    (
+        "let x =\n    fix f(u) =\n        let r = g(u) in\n        r\n    and g(w) =\n        let s = g(w) in\n        s\n    in\n    f",
+        "fix f(u) =\n    let r = g(u) in\n    r\nand g(w) =\n    let s = g(w) in\n    s\nin\nf",
+    ),
+    (
+        "data Option = None | Some _\nlet x =\n    let o = Some(3) in\n    case o of\n    | None =>\n        0\n    | Some =>\n        let n = o#0 in\n        n\n    end",
+        "let o = Some(3) in\nlet n = o#0 in\nn",
+    ),
+    (
+        "let x =\n    let y = ref 1 in\n    if false then\n        let u = y := 2 in\n        u\n    else\n        3",
+        "3",
+    ),
+];
+
+#[test]
+fn test_elimination() {
+    for (src, body) in ELIMINATED {
+        let prog = dce(&parse_anf(src).expect(src));
+        assert_eq!(prog.decls.last().unwrap().body.to_string(), *body, "{src}");
+    }
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        assert_eq!(verify_anf(&dce(&prog)), Ok(()), "{src}")
+    }
+}
+
+#[test]
+fn test_pruning() {
+    let src = "let a =\n    1\nlet b =\n    let c = print(\"b\") in\n    a\nlet c =\n    2\nlet main =\n    let r = b + 1 in\n    r";
+    let prog = parse_anf(src).unwrap();
+    let ids: Vec<_> = prune(&prog, "main")
+        .decls
+        .into_iter()
+        .map(|d| d.id)
+        .collect();
+    assert_eq!(ids, ["a", "b", "main"]);
+    let ids: Vec<_> = prune(&prog, "c").decls.into_iter().map(|d| d.id).collect();
+    assert_eq!(ids, ["c"]);
+}
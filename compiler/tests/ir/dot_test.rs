@@ -0,0 +1,50 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::dot::{ast, callgraph, cfg, Graph};
+use polylamb::ir::pass::{Ir, Level, PassManager};
+use polylamb::ir::text::parse_ssa;
+
+#[test]
+fn test_ast() {
+    let prog = parse_prog("let x : Int = if true then 1 else 2").unwrap();
+    let dot = "digraph ast {\n    node [shape=box];\n    n0 [label=\"let x : Int\", style=bold];\n    n1 [label=\"if\"];\n    n2 [label=\"true\"];\n    n1 -> n2 [label=\"cond\"];\n    n3 [label=\"1\"];\n    n1 -> n3 [label=\"then\"];\n    n4 [label=\"2\"];\n    n1 -> n4 [label=\"else\"];\n    n0 -> n1;\n}\n";
+    assert_eq!(ast(&prog), dot);
+    assert_eq!("dot-callgraph".parse(), Ok(Graph::CallGraph));
+    assert_eq!("dot".parse::<Graph>(), Err(()))
+}
+
+#[test]
+fn test_cfg() {
+    let src = "let main\nb0:\n    x = \"a\\\"b\"\n    if true then b1 else b2\nb1:\n    ret x\nb2:\n    ret 1";
+    let dot = cfg(&parse_ssa(src).unwrap());
+    assert!(
+        dot.contains("f0b0 [label=\"b0:\\l    x = \\\"a\\\\\\\"b\\\"\\l"),
+        "{dot}"
+    );
+    assert!(dot.contains("f0b0 -> f0b1 [label=\"true\"];"), "{dot}");
+    assert!(dot.contains("f0b0 -> f0b2 [label=\"false\"];"), "{dot}");
+}
+
+#[test]
+fn test_callgraph() {
+    let src = "fun f\nb0(env, x):\n    call env(x) to b1\nb1(y):\n    ret y\n\nfun g\nb0(env, h):\n    tailcall h(1)\n\nlet f\nb0:\n    c = closure f()\n    ret c\n\nlet main\nb0:\n    d = closure g()\n    call f(1) to b1\nb1(y):\n    call d(y) to b2\nb2(z):\n    ret z";
+    let dot = callgraph(&parse_ssa(src).unwrap());
+    let edges = [
+        "\"f\" -> \"f\";",
+        "\"g\" -> unknown [style=dashed];",
+        "\"main\" -> \"f\";",
+        "\"main\" -> \"g\";",
+    ];
+    for edge in edges {
+        assert!(dot.contains(edge), "{dot}")
+    }
+    for src in PROGRAMS {
+        let mut manager = PassManager::preset(Level::O1);
+        let prog = manager.lower(&parse_prog(src).unwrap()).unwrap();
+        let Ok(Ir::Ssa(prog)) = manager.run(prog) else {
+            panic!("{src}")
+        };
+        assert!(cfg(&prog).starts_with("digraph cfg {"));
+        assert!(callgraph(&prog).ends_with("}\n"))
+    }
+}
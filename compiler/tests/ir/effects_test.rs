@@ -0,0 +1,39 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::dce::dce;
+use polylamb::ir::effects::{summaries, Effects};
+use polylamb::ir::text::parse_anf;
+use polylamb::ir::verify::verify_anf;
+
+#[test]
+fn test_summaries() {
+    let src = "let inc =\n    fix inc(x) =\n        let y = x + 1 in\n        y\n    in\n    inc\nlet loop =\n    fix loop(x) =\n        let y = loop(x) in\n        y\n    in\n    loop\nlet say =\n    fix say(x) =\n        let u = print(x) in\n        u\n    in\n    say\nlet get =\n    fix get(r) =\n        let v = !r in\n        let w = 1 / v in\n        w\n    in\n    get";
+    let summaries = summaries(&parse_anf(src).unwrap());
+    assert_eq!(summaries["inc"], Effects::PURE);
+    let diverges = Effects {
+        diverges: true,
+        ..Effects::PURE
+    };
+    assert_eq!(summaries["loop"], diverges);
+    assert_eq!(summaries["say"], Effects::ANY);
+    let get = Effects {
+        reads: true,
+        raises: true,
+        ..Effects::PURE
+    };
+    assert_eq!(summaries["get"], get);
+    assert!(!get.pure() && !get.removable())
+}
+
+#[test]
+fn test_elimination() {
+    let src = "let inc =\n    fix inc(x) =\n        let y = x + 1 in\n        y\n    in\n    inc\nlet main =\n    let a = inc(1) in\n    let b = print(\"b\") in\n    2";
+    let prog = dce(&parse_anf(src).unwrap());
+    let body = "let b = print(\"b\") in\n2";
+    assert_eq!(prog.decls.last().unwrap().body.to_string(), body);
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        assert_eq!(verify_anf(&dce(&prog)), Ok(()), "{src}")
+    }
+}
@@ -0,0 +1,61 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::flatten::flatten;
+use polylamb::ir::pass::{Ir, Level, PassManager};
+use polylamb::ir::ssa::Op;
+use polylamb::ir::text::parse_ssa;
+use polylamb::ir::verify::verify_ssa;
+
+/// Functions in static single assignment form, and what's left of them
+const FLATTENED: &[(&str, &str)] = &[
+    (
+        "fun f\nb0(a, b):\n    t = (a, b)\n    x = t.0\n    y = t.1\n    z = x + y\n    ret z",
+        "f\nb0(a, b):\n    z = a + b\n    ret z\n",
+    ),
+    (
+        "fun f\nb0(a, b):\n    if b then b1 else b2\nb1:\n    t = (a, 1)\n    jump b3(t)\nb2:\n    u = (2, a)\n    jump b3(u)\nb3(v):\n    x = v.0\n    y = v.1\n    z = x * y\n    ret z",
+        "f\nb0(a, b):\n    if b then b1 else b2\nb1:\n    jump b3(a, 1)\nb2:\n    jump b3(2, a)\nb3(v$0, v$1):\n    z = v$0 * v$1\n    ret z\n",
+    ),
+    (
+        "fun f\nb0(a, n):\n    t = (a, 0)\n    jump b1(t)\nb1(v):\n    i = v.1\n    c = i < n\n    if c then b2 else b3\nb2:\n    x = v.0\n    j = i + 1\n    w = (x, j)\n    jump b1(w)\nb3:\n    ret v",
+        "f\nb0(a, n):\n    t = (a, 0)\n    jump b1(t)\nb1(v):\n    i = v.1\n    c = i < n\n    if c then b2 else b3\nb2:\n    x = v.0\n    j = i + 1\n    w = (x, j)\n    jump b1(w)\nb3:\n    ret v\n",
+    ),
+    (
+        "fun f\nb0(a, n):\n    t = (a, 0)\n    jump b1(t)\nb1(v):\n    i = v.1\n    c = i < n\n    if c then b2 else b3\nb2:\n    x = v.0\n    j = i + 1\n    w = (x, j)\n    jump b1(w)\nb3:\n    ret i",
+        "f\nb0(a, n):\n    jump b1(a, 0)\nb1(v$0, v$1):\n    c = v$1 < n\n    if c then b2 else b3\nb2:\n    j = v$1 + 1\n    jump b1(v$0, j)\nb3:\n    ret v$1\n",
+    ),
+];
+
+#[test]
+fn test_flattening() {
+    for (src, func) in FLATTENED {
+        let prog = flatten(&parse_ssa(src).expect(src));
+        assert_eq!(prog.funcs[0].to_string(), *func, "{src}");
+        assert_eq!(verify_ssa(&prog), Ok(()), "{src}")
+    }
+}
+
+#[test]
+fn test_returned_tuples() {
+    // Returned by a function inlined, the tuple is never allocated
+    let src = "let f : Int -> Int * Int = λ x : Int. (x + 1, x * 2)\nlet g : Int -> Int = λ y : Int. let (a, b) = f(y) in a + b";
+    let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+    let Ok(Ir::Ssa(prog)) = PassManager::preset(Level::O2).run(prog) else {
+        panic!("{src}")
+    };
+    let g = prog.funcs.iter().find(|f| f.name.starts_with("g")).unwrap();
+    let insts = g.blocks.iter().flat_map(|block| &block.insts);
+    assert!(
+        !insts
+            .into_iter()
+            .any(|inst| matches!(inst.op, Op::Tuple(_))),
+        "{g}"
+    );
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        let mut manager = PassManager::preset(Level::O2);
+        manager.verify = true;
+        assert!(manager.run(prog).is_ok(), "{src}")
+    }
+}
@@ -0,0 +1,45 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::{lower_prog, Prog};
+use polylamb::ir::fold::fold;
+use polylamb::ir::verify::verify_anf;
+
+/// Programs and the bodies of their last declaration, folded
+const FOLDED: &[(&str, &str)] = &[
+    (
+        "let x : Int = let element = 3 in if element - 1 < 5 then 1 else 2",
+        "1",
+    ),
+    (
+        "let below : Int -> Bool = let max = 10 in λ element: Int. element - 1 < max",
+        "fix f$0(element$1) =\n    let t$2 = element$1 - 1 in\n    let t$3 = t$2 < 10 in\n    t$3\nin\nf$0",
+    ),
+    (
+        "let x : Int = 1 / 0 + 2 * 3",
+        "let t$0 = 1 / 0 in\nlet x$3 = t$0 + 6 in\nx$3",
+    ),
+    (
+        "let f : Int -> Bool -> Bool = λ x: Int. λ b: Bool. x * 1 == 0 + x & b & true",
+        "fix f$0(x$1) =\n    fix f$2(b$3) =\n        let t$4 = x$1 in\n        let t$5 = x$1 in\n        let t$6 = t$4 == t$5 in\n        let t$7 = t$6 & b$3 in\n        let t$8 = t$7 in\n        t$8\n    in\n    f$2\nin\nf$0",
+    ),
+    ("let x : Int8 = int8(300) + 1", "45i8"),
+    (
+        "let s : Bool = let t = \"a\" in if t == \"a\" | false then true else false",
+        "true",
+    ),
+];
+
+fn folded(src: &str) -> Prog {
+    fold(&lower_prog(&parse_prog(src).expect(src)).expect(src))
+}
+
+#[test]
+fn test_folding() {
+    for (src, body) in FOLDED {
+        let prog = folded(src);
+        assert_eq!(prog.decls.last().unwrap().body.to_string(), *body, "{src}");
+    }
+    for src in PROGRAMS {
+        assert_eq!(verify_anf(&folded(src)), Ok(()), "{src}")
+    }
+}
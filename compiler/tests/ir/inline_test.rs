@@ -0,0 +1,48 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::inline::inline;
+use polylamb::ir::text::parse_anf;
+use polylamb::ir::verify::verify_anf;
+
+/// Programs in A-normal form and the bodies of their last declaration, inlined
+const INLINED: &[(&str, &str)] = &[
+    (
+        "let x =\n    fix f(u) =\n        let v = u + 1 in\n        v\n    in\n    let a = f(1) in\n    let b = f(a) in\n    b",
+        "fix f(u) =\n    let v = u + 1 in\n    v\nin\nlet v$0 = 1 + 1 in\nlet a = v$0 in\nlet v$1 = a + 1 in\nv$1",
+    ),
+    (
+        "let x =\n    fix f(u) =\n        let v = f(u) in\n        v\n    in\n    let a = f(1) in\n    a",
+        "fix f(u) =\n    let v = f(u) in\n    v\nin\nlet a = f(1) in\na",
+    ),
+    (
+        "let abs =\n    fix g(u) =\n        let c = u < 0 in\n        if c then\n            let n = 0 - u in\n            n\n        else\n            u\n    in\n    g\nlet x =\n    let a = abs(3) in\n    let b = a * 2 in\n    b",
+        "join ret$2(a) =\n    let b = a * 2 in\n    b\nin\nlet c$0 = 3 < 0 in\nif c$0 then\n    let n$1 = 0 - 3 in\n    jump ret$2(n$1)\nelse\n    jump ret$2(3)",
+    ),
+];
+
+#[test]
+fn test_inlining() {
+    for (src, body) in INLINED {
+        let prog = inline(&parse_anf(src).expect(src));
+        assert_eq!(prog.decls.last().unwrap().body.to_string(), *body, "{src}");
+        assert_eq!(verify_anf(&prog), Ok(()), "{src}")
+    }
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        assert_eq!(verify_anf(&inline(&prog)), Ok(()), "{src}")
+    }
+}
+
+#[test]
+fn test_budget() {
+    let body: String = (0..20)
+        .map(|i| format!("        let v{i} = u + {i} in\n"))
+        .collect();
+    let src = format!("let f =\n    fix g(u) =\n{body}        v0\n    in\n    g\nlet x =\n    let a = f(1) in\n    let b = f(a) in\n    b");
+    let prog = inline(&parse_anf(&src).unwrap());
+    assert_eq!(prog.decls[1], parse_anf(&src).unwrap().decls[1]);
+    let prog = inline(&parse_anf(&format!("@[inline] {src}")).unwrap());
+    assert_eq!(prog.decls[1].body.to_string().matches("+ 19").count(), 2);
+    assert_eq!(prog.decls[0].attrs, ["inline"]);
+}
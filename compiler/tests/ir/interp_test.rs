@@ -0,0 +1,112 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::interp::{run, run_anf, Outcome, Stop};
+use polylamb::ir::pass::{Ir, Level, PassManager};
+
+const FUEL: usize = 10_000_000;
+
+/// Programs, what they print and the value of their last declaration, or why they stop
+const RUN: &[(&str, &str, Result<&str, Stop>)] = &[
+    (
+        "let main : Int = let u = print \"a\" in let v = printInt 2 in 3",
+        "a2",
+        Ok("3"),
+    ),
+    (
+        "exception Fail Int\nlet main : Int = raise [Int] (Fail 3)",
+        "",
+        Err(Stop::Uncaught(String::new())),
+    ),
+    ("let main : Unit = assert (1 == 2)", "", Err(Stop::Assertion)),
+    (
+        "let main : Int * Bool = let a = array(2, 0) in let u = update(a, 1, 5) in (sub(a, 1), 1 / 1 == 1)",
+        "",
+        Ok("(5, true)"),
+    ),
+    (
+        "let loop : Int -> Int = fix loop = λ (n: Int) : Int. if n == 0 then 0 else loop (n - 1) in loop\nlet main : Int = loop 10000",
+        "",
+        Ok("0"),
+    ),
+];
+
+/** The outcome of `prog` and those after each pass of `level`, with the names of the passes */
+fn outcomes(src: &str, level: Level) -> Vec<(String, Outcome)> {
+    let mut irs = vec![];
+    let mut manager = PassManager::preset(level);
+    manager.hook(|pass, ir, _| irs.push((pass.to_string(), ir.clone())));
+    let prog = manager.lower(&parse_prog(src).expect(src)).expect(src);
+    let lowered = run_anf(&prog, "", FUEL);
+    manager.run(prog).expect(src);
+    drop(manager);
+    let after = irs
+        .iter()
+        .map(|(pass, ir)| (pass.clone(), run(ir, "", FUEL)));
+    std::iter::once(("lower".to_string(), lowered))
+        .chain(after)
+        .collect()
+}
+
+#[test]
+fn test_outcomes() {
+    for (src, output, value) in RUN {
+        for (pass, outcome) in outcomes(src, Level::O2) {
+            assert_eq!(outcome.output, *output, "{pass}: {src}");
+            let last = outcome
+                .result
+                .map(|values| values.last().unwrap().1.clone());
+            match (last, value) {
+                (Err(Stop::Uncaught(_)), Err(Stop::Uncaught(_))) => (),
+                (last, value) => assert_eq!(
+                    last.as_deref().map_err(Clone::clone),
+                    value.clone(),
+                    "{pass}: {src}"
+                ),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_passes_preserve_outcomes() {
+    for level in [Level::O0, Level::O1, Level::O2] {
+        for src in PROGRAMS {
+            let outcomes = outcomes(src, level);
+            let (_, lowered) = &outcomes[0];
+            for (pass, outcome) in &outcomes[1..] {
+                assert!(
+                    outcome.agrees(lowered),
+                    "{pass}: {outcome:?} {lowered:?} {src}"
+                )
+            }
+        }
+    }
+}
+
+#[test]
+fn test_tail_calls() {
+    let src = "let loop : Int -> Int = fix loop = λ (n: Int) : Int. if n == 0 then 0 else loop (n - 1) in loop\nlet main : Int = loop 100000";
+    let mut manager = PassManager::preset(Level::O0);
+    let prog = manager.lower(&parse_prog(src).unwrap()).unwrap();
+    let Ok(ir @ Ir::Ssa(_)) = manager.run(prog) else {
+        panic!()
+    };
+    let outcome = run(&ir, "", FUEL);
+    assert_eq!(outcome.result.unwrap().last().unwrap().1, "0");
+    assert_eq!(run(&ir, "", 1000).result, Err(Stop::Exhausted))
+}
+
+#[test]
+fn test_long_lists() {
+    // Displaying, comparing and dropping lists go as deep as they're long
+    let src = "let build : Int -> List Int -> List Int = fix build = λ (n: Int) : List Int -> List Int. λ acc: List Int. if n == 0 then acc else build (n - 1) (n :: acc) in build\nlet xs : List Int = build 100000 []\nlet main : Bool = xs == build 100000 []";
+    let mut manager = PassManager::preset(Level::O0);
+    let prog = manager.lower(&parse_prog(src).unwrap()).unwrap();
+    let lowered = run_anf(&prog, "", FUEL);
+    let ir = manager.run(prog).unwrap();
+    let outcome = run(&ir, "", FUEL);
+    assert!(outcome.agrees(&lowered));
+    let values = outcome.result.unwrap();
+    assert_eq!(values[2], ("main".to_string(), "true".to_string()));
+    assert!(values[1].1.starts_with("Cons(1, Cons(2, "))
+}
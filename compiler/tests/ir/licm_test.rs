@@ -0,0 +1,38 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::licm::licm;
+use polylamb::ir::pass::{Analyses, Level, PassManager};
+use polylamb::ir::text::parse_ssa;
+use polylamb::ir::verify::verify_ssa;
+
+/// Functions with loops, and what's left in them
+const HOISTED: &[(&str, &str)] = &[
+    (
+        "fun f\nb0(clo, n):\n    k = clo@0\n    m = k * 2\n    c = n < m\n    if c then b1 else b2\nb1:\n    d = n + 1\n    jump b0(clo, d)\nb2:\n    ret n",
+        "f\nb0(clo$0, n$1):\n    k = clo$0@0\n    m = k * 2\n    jump b3(clo$0, n$1)\nb1:\n    d = n + 1\n    jump b3(clo, d)\nb2:\n    ret n\nb3(clo, n):\n    c = n < m\n    if c then b1 else b2\n",
+    ),
+    (
+        "fun f\nb0(n):\n    jump b1(0)\nb1(i):\n    c = i < n\n    if c then b2 else b5\nb2:\n    jump b3(0)\nb3(j):\n    t = n * 2\n    u = i * 3\n    v = t + u\n    d = j < v\n    if d then b4 else b6\nb4:\n    k = j + 1\n    jump b3(k)\nb5:\n    ret i\nb6:\n    l = i + 1\n    jump b1(l)",
+        "f\nb0(n):\n    jump b8(0)\nb1(i):\n    c = i < n\n    if c then b2 else b5\nb2:\n    jump b7(0)\nb3(j):\n    d = j < v\n    if d then b4 else b6\nb4:\n    k = j + 1\n    jump b3(k)\nb5:\n    ret i\nb6:\n    l = i + 1\n    jump b1(l)\nb7(j$0):\n    u = i * 3\n    v = t + u\n    jump b3(j$0)\nb8(i$1):\n    t = n * 2\n    jump b1(i$1)\n",
+    ),
+    (
+        "fun f\nb0(r, n, m):\n    jump b1(0)\nb1(i):\n    x = deref(r)\n    y = n / m\n    z = n / 2\n    c = i < x\n    if c then b2 else b3\nb2:\n    d = i + 1\n    jump b1(d)\nb3:\n    ret i",
+        "f\nb0(r, n, m):\n    jump b4(0)\nb1(i):\n    x = deref(r)\n    y = n / m\n    c = i < x\n    if c then b2 else b3\nb2:\n    d = i + 1\n    jump b1(d)\nb3:\n    ret i\nb4(i$0):\n    z = n / 2\n    jump b1(i$0)\n",
+    ),
+];
+
+#[test]
+fn test_hoisting() {
+    for (src, func) in HOISTED {
+        let prog = licm(&parse_ssa(src).expect(src), &mut Analyses::default());
+        assert_eq!(prog.funcs[0].to_string(), *func, "{src}");
+        assert_eq!(verify_ssa(&prog), Ok(()), "{src}")
+    }
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        let mut manager = PassManager::preset(Level::O2);
+        manager.verify = true;
+        assert!(manager.run(prog).is_ok(), "{src}")
+    }
+}
@@ -0,0 +1,45 @@
+use super::anf_test::{check_scopes, PROGRAMS};
+use super::closure_test::{check_closed, funcs};
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::{lower_prog, Prog};
+use polylamb::ir::closure::convert;
+use polylamb::ir::lift::lift;
+
+const LIFTED: &str = "
+fun f$7(env$8, y$3) =
+    let x$9 = env$8@0 in
+    let t$4 = x$9 + y$3 in
+    t$4
+
+fun add$5(env$6, x$1) =
+    let f$2 = closure f$7(x$1) in
+    f$2
+
+let add =
+    let add$0 = closure add$5() in
+    add$0
+";
+
+fn lifted(src: &str) -> Prog {
+    lift(&convert(
+        &lower_prog(&parse_prog(src).expect(src)).expect(src),
+    ))
+}
+
+#[test]
+fn test_lifting() {
+    for src in PROGRAMS {
+        let prog = lifted(src);
+        check_scopes(&prog);
+        check_closed(&prog);
+        let nested = prog.funcs.iter().flat_map(|f| funcs(&f.body));
+        let nested = nested.chain(prog.decls.iter().flat_map(|d| funcs(&d.body)));
+        assert_eq!(nested.count(), 0, "{src}");
+    }
+    let prog = lifted("let add : Int -> Int -> Int = λ x: Int. λ y: Int. x + y");
+    let printed = Prog {
+        datatypes: vec![],
+        ..prog
+    };
+    assert_eq!(printed.to_string(), LIFTED);
+}
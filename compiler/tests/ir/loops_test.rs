@@ -0,0 +1,92 @@
+use polylamb::ir::dominators::dominators;
+use polylamb::ir::loops::{depths, loops};
+use polylamb::ir::pass::Analyses;
+use polylamb::ir::text::parse_ssa;
+
+use std::rc::Rc;
+
+/// Two nested loops, counting `i` up to `n` and `j` up to `i`, with an unreachable block
+const NESTED: &str = "fun f
+b0(n):
+    jump b1(0)
+b1(i):
+    c = i < n
+    if c then b2 else b5
+b2:
+    jump b3(0)
+b3(j):
+    d = j < i
+    if d then b4 else b6
+b4:
+    k = j + 1
+    jump b3(k)
+b5:
+    ret i
+b6:
+    l = i + 1
+    jump b1(l)
+b7:
+    jump b1(0)";
+
+#[test]
+fn test_dominators() {
+    let func = parse_ssa(NESTED).unwrap().funcs.remove(0);
+    let doms = dominators(&func);
+    let idoms: Vec<_> = (0..8).map(|l| doms.idom(l)).collect();
+    let expected = [
+        None,
+        Some(0),
+        Some(1),
+        Some(2),
+        Some(3),
+        Some(1),
+        Some(3),
+        None,
+    ];
+    assert_eq!(idoms, expected);
+    assert!(doms.dominates(1, 4) && doms.dominates(4, 4) && !doms.dominates(4, 6));
+    assert!(!doms.reachable(7));
+    assert_eq!(doms.children(3), [4, 6]);
+}
+
+#[test]
+fn test_loops() {
+    let func = parse_ssa(NESTED).unwrap().funcs.remove(0);
+    let found = loops(&func, &dominators(&func));
+    let summary: Vec<_> = found
+        .iter()
+        .map(|l| {
+            (
+                l.header,
+                l.latches.clone(),
+                l.blocks.clone(),
+                l.parent,
+                l.depth,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (1, vec![6], vec![1, 2, 3, 4, 6], None, 1),
+            (3, vec![4], vec![3, 4], Some(0), 2),
+        ]
+    );
+    assert_eq!(depths(&func, &found), [0, 1, 1, 2, 2, 0, 1, 0]);
+}
+
+#[test]
+fn test_analyses() {
+    let func = parse_ssa(NESTED).unwrap().funcs.remove(0);
+    let mut analyses = Analyses::default();
+    let loops = analyses.loops(&func);
+    assert_eq!(loops.len(), 2);
+    // Kept until invalidated
+    assert!(Rc::ptr_eq(&loops, &analyses.loops(&func)));
+    assert!(Rc::ptr_eq(
+        &analyses.liveness(&func),
+        &analyses.liveness(&func)
+    ));
+    analyses.invalidate();
+    assert!(!Rc::ptr_eq(&loops, &analyses.loops(&func)));
+}
@@ -0,0 +1,22 @@
+pub(crate) mod anf_test;
+mod closure_test;
+mod copy_test;
+mod cse_test;
+mod dataflow_test;
+mod dce_test;
+mod dot_test;
+mod effects_test;
+mod flatten_test;
+mod fold_test;
+mod inline_test;
+mod interp_test;
+mod licm_test;
+mod lift_test;
+mod loops_test;
+mod pass_test;
+mod repr_test;
+mod ssa_test;
+mod tail_test;
+mod text_test;
+mod uncurry_test;
+mod verify_test;
@@ -0,0 +1,91 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::closure::convert;
+use polylamb::ir::copy::copy;
+use polylamb::ir::dce::dce;
+use polylamb::ir::flatten::flatten;
+use polylamb::ir::fold::fold;
+use polylamb::ir::lift::lift;
+use polylamb::ir::pass::{Ir, Level, PassManager};
+use polylamb::ir::repr::represent;
+use polylamb::ir::ssa::construct;
+use polylamb::ir::tail::tail;
+use polylamb::ir::text::parse_anf;
+use polylamb::ir::uncurry::uncurry;
+
+#[test]
+fn test_presets() {
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        let mut manager = PassManager::preset(Level::O0);
+        manager.verify = true;
+        let lowered = represent(&tail(&construct(&lift(&convert(&prog)))));
+        assert_eq!(manager.run(prog.clone()), Ok(Ir::Ssa(lowered)));
+        let mut manager = PassManager::preset(Level::O1);
+        manager.verify = true;
+        let optimized = convert(&uncurry(&dce(&fold(&prog))));
+        let optimized = represent(&tail(&flatten(&copy(&construct(&lift(&optimized))))));
+        assert_eq!(manager.run(prog.clone()), Ok(Ir::Ssa(optimized)));
+        let mut manager = PassManager::preset(Level::O2);
+        manager.verify = true;
+        assert!(manager.run(prog).is_ok());
+    }
+}
+
+#[test]
+fn test_pipelines() {
+    let manager = PassManager::with_passes(&["uncurry", "closure"]).unwrap();
+    assert_eq!(manager.names(), ["uncurry", "closure"]);
+    let err = |names: &[&str]| PassManager::with_passes(names).err().unwrap();
+    assert_eq!(err(&["inlining"]), "No pass is named inlining");
+    assert_eq!(
+        err(&["closure", "lift", "ssa", "lift"]),
+        "lift runs on A-normal form, not after ssa"
+    );
+    // Lowering passes run in order, and the representation of values is decided once
+    assert_eq!(err(&["ssa"]), "ssa runs after closure");
+    assert_eq!(err(&["closure", "ssa"]), "ssa runs after lift");
+    assert_eq!(err(&["lift", "ssa", "repr"]), "lift runs after closure");
+    assert_eq!(err(&["closure", "closure"]), "closure runs once");
+    assert_eq!(
+        err(&["closure", "lift", "ssa", "repr", "repr"]),
+        "repr runs once"
+    );
+    for level in [Level::O0, Level::O1, Level::O2] {
+        assert!(PassManager::with_passes(&level.passes()).is_ok())
+    }
+    let prog = lower_prog(&parse_prog(PROGRAMS[0]).unwrap()).unwrap();
+    let mut manager = PassManager::with_passes(&["closure"]).unwrap();
+    assert_eq!(manager.run(prog.clone()), Ok(Ir::Anf(convert(&prog))));
+}
+
+#[test]
+fn test_hooks() {
+    let prog = lower_prog(&parse_prog(PROGRAMS[4]).unwrap()).unwrap();
+    let mut ran = vec![];
+    let mut dumped = None;
+    let mut manager = PassManager::preset(Level::O0);
+    manager.hook(|pass, _, _| ran.push(pass.to_string()));
+    manager.hook(|pass, ir, _| {
+        if pass == "closure" {
+            dumped = Some(ir.to_string())
+        }
+    });
+    manager.run(prog.clone()).unwrap();
+    drop(manager);
+    assert_eq!(ran, ["closure", "lift", "ssa", "tail", "repr"]);
+    assert_eq!(dumped, Some(convert(&prog).to_string()));
+}
+
+#[test]
+fn test_verification() {
+    let prog = parse_anf("let x =\n    y").unwrap();
+    let mut manager = PassManager::preset(Level::O1);
+    manager.verify = true;
+    let err = manager.run(prog).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "IR verification failed after lower. In x: y is used out of scope"
+    );
+}
@@ -0,0 +1,57 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::ast::Binary;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::{lower_prog, Atom};
+use polylamb::ir::pass::{Ir, Level, PassManager};
+use polylamb::ir::repr::{repr, represent, Repr};
+use polylamb::ir::ssa::Op;
+use polylamb::ir::text::parse_ssa;
+use polylamb::ir::verify::verify_ssa;
+
+/// Functions in static single assignment form, and their values represented
+const REPRESENTED: &[(&str, &str)] = &[
+    (
+        "fun f\nb0(a, b):\n    c = a + b\n    d = c * 2\n    e = d < a\n    ret e",
+        "f\nb0(a, b):\n    a$3 = untag(a)\n    b$4 = untag(b)\n    c$0 = a$3 + b$4\n    d$1 = c$0 * 2\n    d = tag(d$1)\n    e$2 = d < a\n    e = tag(e$2)\n    ret e\n",
+    ),
+    (
+        "fun f\nb0(a, b):\n    c = a - 1\n    t = (c, b)\n    d = int8(c)\n    g = a < b\n    h = g & true\n    ret d",
+        "f\nb0(a, b):\n    a$3 = untag(a)\n    c$0 = a$3 - 1\n    c = tag(c$0)\n    t = (c, b)\n    d$1 = int8(c$0)\n    d = tag(d$1)\n    g$2 = a < b\n    g = tag(g$2)\n    h = g & true\n    ret d\n",
+    ),
+];
+
+#[test]
+fn test_representation() {
+    for (src, func) in REPRESENTED {
+        let prog = represent(&parse_ssa(src).expect(src));
+        assert_eq!(prog.funcs[0].to_string(), *func, "{src}");
+        assert_eq!(parse_ssa(&prog.to_string()), Ok(prog.clone()), "{src}");
+        assert_eq!(verify_ssa(&prog), Ok(()), "{src}")
+    }
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        let mut manager = PassManager::preset(Level::O1);
+        manager.verify = true;
+        let Ok(Ir::Ssa(prog)) = manager.run(prog) else {
+            panic!("{src}")
+        };
+        // Arithmetic and tags are on raw integers only
+        for func in prog.funcs.iter().chain(&prog.decls) {
+            let insts: Vec<_> = func.blocks.iter().flat_map(|b| &b.insts).collect();
+            let raw = |atom: &Atom| match atom.var() {
+                Some(var) => insts
+                    .iter()
+                    .any(|i| i.var == *var && repr(&i.op) == Repr::Raw),
+                None => true,
+            };
+            for inst in &insts {
+                let operands = match &inst.op {
+                    Op::Binop(lhs, Binary::Add | Binary::Sub | Binary::Mul, rhs) => vec![lhs, rhs],
+                    Op::Tag(raw) => vec![raw],
+                    _ => vec![],
+                };
+                assert!(operands.into_iter().all(raw), "{func}")
+            }
+        }
+    }
+}
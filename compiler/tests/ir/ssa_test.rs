@@ -0,0 +1,119 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ast::semant::BUILTINS;
+use polylamb::ir::anf::{lower_prog, Atom};
+use polylamb::ir::closure::convert;
+use polylamb::ir::lift::lift;
+use polylamb::ir::ssa::{construct, Prog, Term};
+use std::collections::HashSet;
+
+const ADD: &str = "
+fun f$7
+b0(env$8, y$3):
+    x$9 = env$8@0
+    t$4 = x$9 + y$3
+    ret t$4
+
+fun add$5
+b0(env$6, x$1):
+    f$2 = closure f$7(x$1)
+    ret f$2
+
+let add
+b0:
+    add$0 = closure add$5()
+    ret add$0
+";
+
+const HANDLE: &str = "main
+b0:
+    jump b3()
+b1(main$5):
+    ret main$5
+b2(exn$1):
+    case exn$1 of Fail => b5 | _ => b6
+b3 unwind b2:
+    t$0 = Fail(3)
+    raise t$0
+b4:
+    raise exn$1
+b5:
+    n$2 = exn$1#0
+    jump b1(n$2)
+b6:
+    jump b4()
+";
+
+fn constructed(src: &str) -> Prog {
+    let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+    construct(&lift(&convert(&prog)))
+}
+
+/** Asserts that the registers of the functions of `prog` are assigned once, that the registers
+they use are assigned in them or global, and that their blocks are jumped to with as many
+arguments as they take */
+pub fn check_ssa(prog: &Prog) {
+    let mut globals: HashSet<&str> = BUILTINS.iter().map(|(id, _)| *id).collect();
+    globals.extend(
+        prog.funcs
+            .iter()
+            .chain(&prog.decls)
+            .map(|f| f.name.as_str()),
+    );
+    for func in prog.funcs.iter().chain(&prog.decls) {
+        let mut assigned = HashSet::new();
+        for block in &func.blocks {
+            let vars = block.insts.iter().map(|inst| &inst.var);
+            for var in block.params.iter().chain(vars) {
+                assert!(assigned.insert(var.as_str()), "{var} is assigned twice");
+            }
+        }
+        let arity = |label: usize| match func.blocks.get(label) {
+            Some(block) => block.params.len(),
+            None => panic!("b{label} isn't a block of {}", func.name),
+        };
+        for block in &func.blocks {
+            let atoms = block.insts.iter().flat_map(|inst| inst.op.atoms());
+            for var in atoms.chain(block.term.atoms()).filter_map(Atom::var) {
+                let var = var.as_str();
+                assert!(
+                    assigned.contains(var) || globals.contains(var),
+                    "{var} is unbound"
+                );
+            }
+            match &block.term {
+                Term::Jump(label, args) => assert_eq!(arity(*label), args.len()),
+                Term::Call { ret, .. } => assert_eq!(arity(*ret), 1),
+                term => term
+                    .successors()
+                    .into_iter()
+                    .for_each(|l| assert_eq!(arity(l), 0)),
+            }
+            block
+                .handler
+                .into_iter()
+                .for_each(|l| assert_eq!(arity(l), 1))
+        }
+    }
+    for decl in &prog.decls {
+        assert!(decl.params().is_empty())
+    }
+}
+
+#[test]
+fn test_construction() {
+    for src in PROGRAMS {
+        check_ssa(&constructed(src))
+    }
+    let prog = constructed("let add : Int -> Int -> Int = λ x: Int. λ y: Int. x + y");
+    let printed = Prog {
+        datatypes: vec![],
+        ..prog
+    };
+    assert_eq!(printed.to_string(), ADD);
+    let prog = constructed(
+        "exception Fail Int
+         let main : Int = (raise [Int] (Fail 3)) handle Fail n => n",
+    );
+    assert_eq!(prog.decls[0].to_string(), HANDLE);
+}
@@ -0,0 +1,78 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::pass::{Ir, Level, PassManager};
+use polylamb::ir::ssa::{Prog, Term};
+use polylamb::ir::tail::{tail, tail_calls};
+use polylamb::ir::text::parse_ssa;
+use polylamb::ir::verify::verify_ssa;
+
+/// Functions in static single assignment form, and the blocks ending with calls in tail position
+const TAIL_CALLS: &[(&str, &[usize])] = &[
+    ("fun f\nb0(x):\n    call x(x) to b1\nb1(y):\n    ret y", &[0]),
+    (
+        "fun f\nb0(x):\n    call x(x) to b1\nb1(y):\n    jump b2(y)\nb2(z):\n    ret z",
+        &[0],
+    ),
+    ("fun f\nb0(x):\n    call x(x) to b1\nb1(y):\n    ret x", &[]),
+    (
+        "fun f\nb0(x):\n    call x(x) to b1\nb1(y):\n    z = y + 1\n    ret z",
+        &[],
+    ),
+    (
+        "fun f\nb0(x):\n    jump b2()\nb1(e):\n    ret 0\nb2 unwind b1:\n    call x(x) to b3\nb3(y):\n    ret y",
+        &[],
+    ),
+];
+
+/// Deep tail recursion, direct and mutual
+const RECURSIVE: &str = r"
+let count : Int -> Int -> Int =
+    fix count = λ (n: Int) : Int -> Int. λ acc: Int.
+        if n == 0 then acc else count (n - 1) (acc + 1)
+    in count
+let even_odd : Int -> Bool =
+    fix even = λ (n: Int) : Bool. if n == 0 then true else odd (n - 1)
+    and odd = λ (n: Int) : Bool. if n == 0 then false else even (n - 1)
+    in even
+let main : Bool = even_odd (count 1000000 0)";
+
+fn compiled(src: &str, level: Level) -> Prog {
+    let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+    let mut manager = PassManager::preset(level);
+    manager.verify = true;
+    match manager.run(prog) {
+        Ok(Ir::Ssa(prog)) => prog,
+        ir => panic!("{ir:?}"),
+    }
+}
+
+#[test]
+fn test_tail_calls() {
+    for (src, labels) in TAIL_CALLS {
+        let prog = parse_ssa(src).expect(src);
+        assert_eq!(tail_calls(&prog.funcs[0]), *labels, "{src}");
+        assert_eq!(verify_ssa(&tail(&prog)), Ok(()), "{src}")
+    }
+}
+
+#[test]
+fn test_recursion() {
+    for level in [Level::O0, Level::O1, Level::O2] {
+        let prog = compiled(RECURSIVE, level);
+        for func in &prog.funcs {
+            assert_eq!(tail_calls(func), [], "{func}");
+            // Uncurried, the recursive calls jump back or reuse the frame
+            let mut terms = func.blocks.iter().map(|block| &block.term);
+            if level != Level::O0 {
+                assert!(
+                    !terms.any(|term| matches!(term, Term::Call { .. })),
+                    "{func}"
+                )
+            }
+        }
+    }
+    for src in PROGRAMS {
+        compiled(src, Level::O1);
+    }
+}
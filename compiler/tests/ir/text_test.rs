@@ -0,0 +1,52 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::closure::convert;
+use polylamb::ir::lift::lift;
+use polylamb::ir::ssa::construct;
+use polylamb::ir::text::{parse_anf, parse_ssa};
+use std::fs;
+
+/// Programs of A-normal form, and the static single assignment form they're constructed into
+const GOLDEN: &[&str] = &["tests/ir/golden/handle", "tests/ir/golden/loop"];
+
+#[test]
+fn test_round_trip() {
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        assert_eq!(parse_anf(&prog.to_string()), Ok(prog.clone()));
+        let lifted = lift(&convert(&prog));
+        assert_eq!(parse_anf(&lifted.to_string()), Ok(lifted.clone()));
+        let constructed = construct(&lifted);
+        assert_eq!(parse_ssa(&constructed.to_string()), Ok(constructed));
+    }
+    let prog = lower_prog(&parse_prog("let s : Int8 * String = (3, \"a\\\"\\n\")").unwrap());
+    let prog = prog.unwrap();
+    assert!(prog.to_string().contains("(3i8, \"a\\\"\\n\")"));
+    assert_eq!(parse_anf(&prog.to_string()), Ok(prog))
+}
+
+#[test]
+fn test_golden() {
+    for path in GOLDEN {
+        let anf = fs::read_to_string(format!("{path}.anf.ir")).unwrap();
+        let ssa = fs::read_to_string(format!("{path}.ssa.ir")).unwrap();
+        let prog = parse_anf(&anf).unwrap_or_else(|err| panic!("{path}: {err}"));
+        assert_eq!(prog.to_string(), anf);
+        assert_eq!(construct(&lift(&convert(&prog))).to_string(), ssa);
+        assert_eq!(parse_ssa(&ssa).unwrap().to_string(), ssa);
+    }
+}
+
+#[test]
+fn test_errors() {
+    let err = |src: &str| parse_anf(src).map(|_| ()).unwrap_err();
+    assert_eq!(err("let x =\n    let y = 1 +").offset, 23);
+    assert_eq!(err("let x = 1 ?").offset, 10);
+    assert!(err("let x = let y = 1 in")
+        .message
+        .contains("found the end"));
+    let err = |src: &str| parse_ssa(src).map(|_| ()).unwrap_err();
+    assert!(err("let x\nb1:\n    ret 1").message.contains("`b0`"));
+    assert!(err("let x\nb0:\n    y = 1").message.contains("terminator"));
+}
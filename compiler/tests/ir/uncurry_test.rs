@@ -0,0 +1,79 @@
+use super::anf_test::{check_scopes, PROGRAMS};
+use super::closure_test::check_closed;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::{lower_prog, Expr, Prog};
+use polylamb::ir::closure::convert;
+use polylamb::ir::lift::lift;
+use polylamb::ir::uncurry::{arity, uncurry};
+
+const CURRIED: &str = r"let add : Int -> Int -> Int = λ x: Int. λ y: Int. x + y
+let inc : Int -> Int = add 1
+let main : Int = add (inc 2) 3";
+
+const UNCURRIED: &str = "
+let add$12 =
+    fix add$11(x$1, y$3) =
+        let t$4 = x$1 + y$3 in
+        t$4
+    in
+    add$11
+
+let add =
+    fix add$17(x$18) =
+        fix f$21(y$19) =
+            let t$20 = add$12(x$18, y$19) in
+            t$20
+        in
+        f$21
+    in
+    add$17
+
+let inc =
+    let inc$6 = add(1) in
+    inc$6
+
+let main =
+    let t$7 = inc(2) in
+    let main$10 = add$12(t$7, 3) in
+    main$10
+";
+
+fn uncurried(src: &str) -> Prog {
+    uncurry(&lower_prog(&parse_prog(src).expect(src)).expect(src))
+}
+
+#[test]
+fn test_uncurrying() {
+    let prog = uncurried(CURRIED);
+    let printed = Prog {
+        datatypes: vec![],
+        ..prog
+    };
+    assert_eq!(printed.to_string(), UNCURRIED);
+    for src in PROGRAMS.iter().chain([&CURRIED]) {
+        let prog = uncurried(src);
+        check_scopes(&prog);
+        let prog = lift(&convert(&prog));
+        check_scopes(&prog);
+        check_closed(&prog)
+    }
+}
+
+#[test]
+fn test_arity() {
+    let prog = lower_prog(&parse_prog(CURRIED).unwrap()).unwrap();
+    let Expr::Fix { funcs, .. } = &prog.decl("add").unwrap().body else {
+        panic!("add is a function")
+    };
+    assert_eq!(arity(&funcs[0]), 2);
+    // Recursive functions are applied to their argument before returning
+    let prog = uncurried(
+        r"let main : Int =
+              let mul = λ x: Int. λ y: Int. x * y in
+              fix loop = λ (n: Int) : Int -> Int. λ acc: Int. if n == 0 then acc else loop (n - 1) (mul n acc) in
+              loop 5 1",
+    );
+    let body = prog.decl("main").unwrap().body.to_string();
+    assert!(body.contains("loop$19(t$10, t$13)"), "{body}");
+    assert!(body.contains("mul$18(n$6, acc$8)"), "{body}");
+}
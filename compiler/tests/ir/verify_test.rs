@@ -0,0 +1,94 @@
+use super::anf_test::PROGRAMS;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ir::anf::lower_prog;
+use polylamb::ir::closure::convert;
+use polylamb::ir::lift::lift;
+use polylamb::ir::ssa::construct;
+use polylamb::ir::text::{parse_anf, parse_ssa};
+use polylamb::ir::uncurry::uncurry;
+use polylamb::ir::verify::{verify_anf, verify_ssa};
+
+/// Programs in A-normal form breaking an invariant, and what the verifier reports
+const BROKEN_ANF: &[(&str, &str)] = &[
+    ("let x =\n    y", "In x: y is used out of scope"),
+    (
+        "let x =\n    let y = 1 in\n    let y = 2 in\n    y",
+        "In x: y is bound twice",
+    ),
+    (
+        "data Option = None | Some _\nlet x =\n    let y = Some(1, 2) in\n    y",
+        "In x: Some has 1 fields, not 2",
+    ),
+    (
+        "let x =\n    join k(v) =\n        v\n    in\n    jump k(1, 2)",
+        "In x: k takes 1 arguments",
+    ),
+    (
+        "let x =\n    join k() =\n        1\n    in\n    fix f(u) =\n        jump k()\n    in\n    f",
+        "In x: k isn't a join point in scope",
+    ),
+    (
+        "data A = B | C\ndata D = E\nlet y =\n    1\nlet x =\n    case y of\n    | B =>\n        1\n    | E =>\n        2\n    end",
+        "In x: E isn't a constructor of A",
+    ),
+    ("let x =\n    let a = sub(1) in\n    a", "In x: sub takes 2 operands"),
+];
+
+/// Programs in static single assignment form breaking an invariant, and what the verifier reports
+const BROKEN_SSA: &[(&str, &str)] = &[
+    (
+        "let x\nb0:\n    if true then b1 else b2\nb1:\n    y = 1\n    jump b2()\nb2:\n    ret y",
+        "In x: y is used in b2 before it's assigned",
+    ),
+    (
+        "let x\nb0:\n    y = 1\n    jump b1()\nb1:\n    y = 2\n    ret y",
+        "In x: y is assigned twice",
+    ),
+    (
+        "let x\nb0:\n    jump b1(1)\nb1:\n    ret 1",
+        "In x: b1 takes 0 parameters, b0 passes 1",
+    ),
+    (
+        "let x\nb0:\n    call print(\"a\") to b2\nb1(y):\n    ret y",
+        "In x: b2 isn't a block",
+    ),
+    (
+        "fun f\nb0(y):\n    ret y\n\nlet x\nb0(z):\n    ret z",
+        "In x: Declarations take no parameters",
+    ),
+    (
+        "let x\nb0:\n    c = closure g()\n    ret c",
+        "In x: g isn't a function",
+    ),
+    (
+        "let x\nb0:\n    jump b2()\nb1(e):\n    ret y\nb2 unwind b1:\n    y = 1\n    raise y",
+        "In x: y is used in b1 before it's assigned",
+    ),
+];
+
+#[test]
+fn test_verified() {
+    for src in PROGRAMS {
+        let prog = lower_prog(&parse_prog(src).expect(src)).expect(src);
+        assert_eq!(verify_anf(&prog), Ok(()), "{src}");
+        let prog = uncurry(&prog);
+        assert_eq!(verify_anf(&prog), Ok(()), "{src}");
+        let prog = convert(&prog);
+        assert_eq!(verify_anf(&prog), Ok(()), "{src}");
+        let prog = lift(&prog);
+        assert_eq!(verify_anf(&prog), Ok(()), "{src}");
+        assert_eq!(verify_ssa(&construct(&prog)), Ok(()), "{src}")
+    }
+}
+
+#[test]
+fn test_broken() {
+    for (src, err) in BROKEN_ANF {
+        let prog = parse_anf(src).expect(src);
+        assert_eq!(verify_anf(&prog).unwrap_err().to_string(), *err)
+    }
+    for (src, err) in BROKEN_SSA {
+        let prog = parse_ssa(src).expect(src);
+        assert_eq!(verify_ssa(&prog).unwrap_err().to_string(), *err)
+    }
+}
@@ -1 +1,3 @@
+pub mod codegen;
+pub mod ir;
 pub mod system_f;
@@ -0,0 +1,67 @@
+use polylamb::ast::ast::{Expr, RawExpr, RawType};
+use polylamb::ast::erase::{erase, erase_prog};
+use polylamb::ast::interp::eval_closed_expr;
+use polylamb::ast::lower::lower_prog;
+use polylamb::ast::parse::{parse_expr, parse_prog};
+use polylamb::ast::semant::check_closed_expr;
+
+/// Closed expressions using types at runtime
+const TYPED: &[&str] = &[
+    "(Λ A. λ x: A. x) [Int] 1",
+    "let twice = Λ A. λ f: A -> A. λ x: A. f (f x) in twice [Int] (λ n: Int. n * 3) 2",
+    "(unfold (fold [μ L. Int -> Int] (λ n: Int. n + 1))) 2",
+    r"let p = pack [Int] (1, λ n: Int. n + 1) as ∃ S. S * (S -> Int) in
+      unpack [S] q = p in q.1 q.0",
+    "fix f = λ (n: Int) : Int. if n == 0 then 1 else n * f (n - 1) in f 5",
+    "let r = ref (Λ A. λ x: A. x) in let u = r := !r in !r [Int] 3",
+];
+
+/** Whether `expr` has types left, besides holes */
+fn typed(expr: &Expr) -> bool {
+    use RawExpr::*;
+    let hole = |t: &RawType| matches!(t, RawType::Hole);
+    match &expr.expr {
+        Any { .. } | TApp { .. } | Fold { .. } | Unfold { .. } | Pack { .. } | Unpack { .. } => {
+            true
+        }
+        Lambda { arg: (_, t), .. } | Raise { typ: t, .. } if !hole(t) => true,
+        Fix { funcs, .. } if funcs.iter().any(|(_, _, t, r, _)| !hole(t) || !hole(r)) => true,
+        e => e.subexprs().into_iter().any(typed),
+    }
+}
+
+#[test]
+fn test_erasure() {
+    for src in TYPED {
+        let mut expr = parse_expr(src).unwrap();
+        check_closed_expr(&expr).expect(src);
+        let expected = eval_closed_expr(&expr).expect(src).to_string();
+        erase(&mut expr);
+        assert!(!typed(&expr), "{src}");
+        assert_eq!(eval_closed_expr(&expr).expect(src).to_string(), expected);
+    }
+}
+
+#[test]
+fn test_erase_prog() {
+    let prog = parse_prog(
+        r"data Pair A B = Pair A B
+          type Id = ∀ A. A -> A
+          let id : Id = Λ A. λ x: A. x
+          let main : Pair Int Bool = Pair [Int] [Bool] (id [Int] 1) (id [Bool] true)",
+    )
+    .unwrap();
+    let prog = erase_prog(&lower_prog(&prog).unwrap());
+    assert!(prog.aliases.is_empty());
+    for decl in prog.declarations.values() {
+        assert_eq!(decl.sig.typ, RawType::Hole);
+        assert!(!typed(&decl.body), "{}", decl.id);
+    }
+    let pair = prog.datatypes.iter().find(|d| d.id == "Pair").unwrap();
+    assert!(pair.params.is_empty());
+    assert_eq!(pair.ctors[0].1.len(), 2);
+    assert_eq!(
+        prog.declarations["main"].body.to_string(),
+        "Pair (id 1) (id true)"
+    );
+}
@@ -0,0 +1,82 @@
+use polylamb::ast::intern::{Interner, Name, Node};
+use polylamb::ast::parse::parse_type;
+
+/// Types interned alike, and types interned apart
+const INTERNED: &[(&str, &str, bool)] = &[
+    ("∀ A. A -> A", "∀ B. B -> B", true),
+    ("∀ A. ∀ B. A", "∀ A. ∀ B. B", false),
+    ("{x: Int, y: Bool}", "{y: Bool, x: Int}", true),
+    ("∀ (F: * -> *). F Int", "∀ (G: * -> *). G Int", true),
+    ("(λ A. A * A) Int", "Int * Int", true),
+    ("∀ A. A", "A", false),
+    ("_", "_", false),
+];
+
+/// Types and those their interned ids name, with bound type variables renamed so as not to
+/// capture others
+const RAW: &[(&str, &str)] = &[
+    ("∀ A. A -> A", "∀ A. A -> A"),
+    ("∀ B. ∀ A. A -> B", "∀ B. ∀ A. A -> B"),
+    ("∀ A. (λ B. ∀ A. A -> B) A", "∀ A. ∀ A1. A1 -> A"),
+    ("{y: Bool, x: Int}", "{x: Int, y: Bool}"),
+];
+
+#[test]
+fn test_interning() {
+    let mut types = Interner::default();
+    for (t1, t2, alike) in INTERNED {
+        let id1 = types.intern(&parse_type(t1).unwrap().typ);
+        let id2 = types.intern(&parse_type(t2).unwrap().typ);
+        assert_eq!(id1 == id2, *alike, "{t1} and {t2}");
+    }
+    let id = types.intern(&parse_type("∀ A. A").unwrap().typ);
+    let Node::Forall(_, body) = types.get(id) else {
+        panic!()
+    };
+    assert_eq!(*types.get(*body), Node::TVar(Name::Bound(0)));
+}
+
+#[test]
+fn test_sharing() {
+    let mut types = Interner::default();
+    let typ = parse_type("(Int -> Int) * (Int -> Int) * (Int -> Int)").unwrap();
+    types.intern(&typ.typ);
+    // `Int`, `Int -> Int` and the product
+    assert_eq!(types.len(), 3);
+    types.intern(&parse_type("Int -> Int").unwrap().typ);
+    assert_eq!(types.len(), 3)
+}
+
+#[test]
+fn test_raw() {
+    for (typ, raw) in RAW {
+        let mut types = Interner::default();
+        let id = types.intern(&parse_type(typ).unwrap().typ);
+        let expected = parse_type(raw).unwrap().typ;
+        assert_eq!(types.raw(id).to_string(), expected.to_string(), "{typ}")
+    }
+    // Binders are named as in the first type interned with them
+    let mut types = Interner::default();
+    let id = types.intern(&parse_type("∀ A. A").unwrap().typ);
+    types.intern(&parse_type("∀ B. B").unwrap().typ);
+    assert_eq!(types.raw(id).to_string(), "∀ A. A")
+}
+
+#[test]
+fn test_open_close() {
+    let mut types = Interner::default();
+    let id = types.intern(&parse_type("∀ A. ∀ B. A -> B").unwrap().typ);
+    let Node::Forall(_, body) = *types.get(id) else {
+        panic!()
+    };
+    let int = types.intern(&parse_type("Int").unwrap().typ);
+    let opened = types.open(body, int);
+    let expected = types.intern(&parse_type("∀ B. Int -> B").unwrap().typ);
+    assert_eq!(opened, expected);
+    // Closing over a free variable binds it again
+    let free = types.intern(&parse_type("∀ B. C -> B").unwrap().typ);
+    assert!(types.free_in("C", free));
+    let body = types.close("C", free);
+    assert!(!types.free_in("C", body));
+    assert_eq!(types.open(body, int), expected)
+}
@@ -1,6 +1,15 @@
-use polylamb::ast::interp::eval_closed_expr;
-use polylamb::ast::parse::parse_expr;
-use polylamb::ast::semant::check_closed_expr;
+use polylamb::ast::coverage::cover;
+use polylamb::ast::debug::{Breakpoint, Debugger, Pause};
+use polylamb::ast::interp::{
+    builtin_environment, eval_closed_expr, eval_datatype, eval_exception, eval_expr, eval_prog,
+    trace_expr, Environment, EvalError, Store, Strategy,
+};
+use polylamb::ast::lower::Newtypes;
+use polylamb::ast::nbe::{beta_eta_equal, normalize};
+use polylamb::ast::parse::{parse_data, parse_expr, parse_prog};
+use polylamb::ast::pretty::{elide, Limits, Pretty};
+use polylamb::ast::semant::{builtin_context, check_closed_expr, prelude, Aliases, Context};
+use std::iter::zip;
 
 const ARITHMETIC: &[&str] = &[
     "1 + 3",
@@ -9,8 +18,8 @@ const ARITHMETIC: &[&str] = &[
     "(-1 * -3) * (2 + 5)",
     "-1048576 * -1048576",
     "0 * -0",
-    "9223372036854775807 * -1",
-    "9223372036854775807 + 1", // Min int
+    "1073741823 * -1",
+    "1073741823 + 1", // Min int
 ];
 
 const BOOLEAN: &[&str] = &[
@@ -23,7 +32,7 @@ const BOOLEAN: &[&str] = &[
     "false & false",
     "false | false",
     "1 < 3",
-    "-9223372036854775808 < 0",
+    "-1073741824 < 0",
     "0 < 0",
     "0 > 6",
     "100 > 99",
@@ -41,8 +50,7 @@ const LAMBDA: &[&str] = &[
           if y & (z x) then x else 0 - x",
 ];
 
-const APP: &[&str] =
-    &["(lambda pair: Int * Int. let (p, q) = pair in (p * 2, q * 2)) (2, 3)"];
+const APP: &[&str] = &["(lambda pair: Int * Int. let (p, q) = pair in (p * 2, q * 2)) (2, 3)"];
 
 const ANY: &[&str] = &[
     "any T. λ g: T -> T. λ h: T. g (g h)",
@@ -52,18 +60,652 @@ const ANY: &[&str] = &[
     "any A. λ x: A. λ y: A. y", // false
 ];
 
-const LET: &[&str] = &[
-    r"let twice = any T. \ f: T -> T. \ x: T. f (f x) in
+const LET: &[&str] = &[r"let twice = any T. \ f: T -> T. \ x: T. f (f x) in
       let quad = any T. lambda f: T -> T. \ x: T. twice[T] (twice[T] f) x in
       let plus_one = lambda x: Int. x + 1 in
-      quad[Int] plus_one 3",
-];
+      quad[Int] plus_one 3"];
 
 const FIX: &[&str] = &[
     "fix fact = lambda (x:Int) : Int. if x > 0 then x * fact (x - 1) else 1 in fact 10",
     "fix fib = lambda (x: Int) : Int. if x == 0 then 0 else if x == 1 then 1 else fib (x - 1) + (fib (x - 2)) in fib 10"
 ];
 
+const CASE: &[&str] = &[
+    "case 3 of 0 => 1; n => n * 2 end",
+    "fix fact = lambda (x: Int) : Int. case x of 0 => 1; n => n * fact (n - 1) end in fact 5",
+    "case (false, 7) of (true, x) => x; (false, 7) => 0; _ => 1 end",
+];
+
+const PACK: &[&str] = &[
+    "unpack [C] c = pack [Int] (41, λ x: Int. x + 1) as ∃ S. S * (S -> Int) in
+     let (s, f) = c in f s",
+];
+
+const RECORD: &[&str] = &[
+    "{x = 1 + 2, y = true}",
+    "let r = {x = 1, y = {z = 2}} in r.x + r.y.z",
+    "let {b = b, a = a} = {a = 1, b = 2} in a - b",
+    "let p = (1, (true, 3)) in if p.1.0 then p.0 + p.1.1 else 0",
+];
+
+/// Programs using references and the values they evaluate to
+const REF: &[(&str, &str)] = &[
+    ("let r = ref 1 in let _ = r := !r + 41 in !r", "42"),
+    ("let r = ref 1 in let s = r in let _ = s := 5 in !r", "5"),
+    (
+        "let c = ref 0 in
+         let next = λ u: Unit. let _ = c := !c + 1 in !c in
+         let _ = next null in let _ = next null in next null",
+        "3",
+    ),
+    (
+        "let r = ref (ref true) in let _ = !r := false in !(!r)",
+        "false",
+    ),
+];
+
+/// Programs spawning and joining tasks and the values they evaluate to
+const FUTURE: &[(&str, &str)] = &[
+    ("join (spawn (6 * 7))", "42"),
+    (
+        "let t = spawn (1, true) in (join t, join t)",
+        "((1, true), (1, true))",
+    ),
+    // Tasks run once, however many times they're joined
+    (
+        "let r = ref 0 in
+         let t = spawn (let _ = r := !r + 1 in !r) in
+         let _ = join t in let _ = join t in !r",
+        "1",
+    ),
+    // Tasks take turns, so one can wait for another to update a reference
+    (
+        "let r = ref false in
+         let t = spawn (fix wait = λ (u: Unit) : Int. if !r then 1 else wait null in wait null) in
+         let _ = r := true in join t",
+        "1",
+    ),
+    (
+        "let fs = (spawn (λ x: Int. x + 1), spawn (λ x: Int. x * 2)) in
+         join fs.1 (join fs.0 20)",
+        "42",
+    ),
+    ("join (join (spawn (spawn 7)))", "7"),
+    // Exceptions raised by a task are raised by joining it
+    ("let t = spawn (1 / 0) in join t handle Div => 7", "7"),
+];
+
+/// Functions see the bindings where they are defined, not where they are called
+const CLOSURES: &[(&str, &str)] = &[
+    (
+        "let k = λ x: Int. λ y: Int. x in let f = k 1 in let g = k 2 in (f 0, g 0)",
+        "(1, 2)",
+    ),
+    (
+        "let n = 10 in let add = λ m: Int. m + n in (λ n2: Int. add n2) 5",
+        "15",
+    ),
+    (
+        "let mk = any A. λ a: A. any B. λ b: B. (a, b) in let p = mk [Int] 1 in (p [Bool] true, p [Int] 2)",
+        "((1, true), (1, 2))",
+    ),
+    (
+        "fix even = λ (n: Int) : Bool. if n == 0 then true else odd (n - 1)
+         and odd = λ (n: Int) : Bool. if n == 0 then false else even (n - 1) in
+         let check = λ f: Int -> Bool. f 7 in (check even, check odd)",
+        "(false, true)",
+    ),
+];
+
+#[test]
+fn test_closures() {
+    for (s, expected) in CLOSURES {
+        let exp = parse_expr(s).unwrap();
+        check_closed_expr(&exp).unwrap();
+        assert_eq!(
+            eval_closed_expr(&exp).unwrap().to_string(),
+            *expected,
+            "{s}"
+        )
+    }
+}
+
+/// Recursion deeper than the Rust stack of the test thread allows
+#[test]
+fn test_deep_recursion() {
+    let exps = [
+        (
+            "fix count = λ (n: Int) : Int. if n == 0 then 0 else 1 + count (n - 1) in count 100000",
+            "100000",
+        ),
+        (
+            "fix down = λ (n: Int) : Int. if n == 0 then 1 / n else 1 + down (n - 1) in
+             down 100000 handle Div => 7",
+            "7",
+        ),
+    ];
+    let (ctxt, env) = prelude_env();
+    for (s, expected) in exps {
+        let exp = parse_expr(s).unwrap();
+        let (val, _) = eval_expr(
+            &exp,
+            &ctxt,
+            &env,
+            &Newtypes::default(),
+            &mut Store::default(),
+            Strategy::default(),
+        )
+        .unwrap();
+        assert_eq!(val.to_string(), expected)
+    }
+}
+
+/// Values by value, by name, and lazily, told apart by the effects of evaluating arguments.
+/// Counters are bound by `case`, which evaluates them once by any strategy
+const STRATEGIES: &[(&str, [&str; 3])] = &[
+    ("(λ x: Int. 1) (1 / 0)", ["Div", "1", "1"]),
+    ("let x = 1 / 0 in 2", ["Div", "2", "2"]),
+    (
+        "case ref 0 of c => let n = (λ x: Int. x + x) (let _ = c := !c + 1 in 1) in (n, !c) end",
+        ["(2, 1)", "(2, 2)", "(2, 1)"],
+    ),
+    ("case ref 0 of c => let u = c := 1 in !c end", ["1", "0", "0"]),
+    (
+        "case ref 0 of c => let xs = [let _ = c := !c + 1 in 1] in case (xs, xs) of _ => !c end end",
+        ["1", "2", "1"],
+    ),
+    (
+        "fix f = λ (n: Int) : Int. if n == 0 then 0 else f (n - 1) in f 1000",
+        ["0", "0", "0"],
+    ),
+];
+
+#[test]
+fn test_strategies() {
+    let (ctxt, env) = prelude_env();
+    let strategies = [Strategy::CallByValue, Strategy::CallByName, Strategy::Lazy];
+    for (s, expected) in STRATEGIES {
+        let exp = parse_expr(s).unwrap();
+        for (strategy, expected) in zip(strategies, expected) {
+            let result = eval_expr(
+                &exp,
+                &ctxt,
+                &env,
+                &Newtypes::default(),
+                &mut Store::default(),
+                strategy,
+            );
+            let val = match result {
+                Ok((val, _)) => val.to_string(),
+                Err(EvalError::Uncaught(exn)) => exn.to_string(),
+                Err(err) => panic!("{:?}", err),
+            };
+            assert_eq!(val, *expected, "{s} {strategy:?}")
+        }
+    }
+}
+
+/// Expressions using the builtins with their input, and their output and value
+const BUILTINS: &[(&str, &str, &str, &str)] = &[
+    ("", r#"print "hello\n""#, "hello\n", "null"),
+    ("", "printInt (6 * 7)", "42", "null"),
+    (
+        "Alice\nBob\n",
+        "(readLine null, readLine null)",
+        "",
+        r#"("Alice", "Bob")"#,
+    ),
+    ("", "readLine null", "", r#""""#),
+    (
+        "ping\n",
+        r#"case readLine null of "ping" => print "pong"; _ => print "?" end"#,
+        "pong",
+        "null",
+    ),
+    (
+        "",
+        r#"fix f = λ (n: Int) : Unit. if n == 0 then null else let _ = printInt n in let _ = print " " in f (n - 1) in f 3"#,
+        "3 2 1 ",
+        "null",
+    ),
+    (
+        "",
+        r#"concat (showInt (0 - 12), concat (" ", showInt 0))"#,
+        "",
+        r#""-12 0""#,
+    ),
+    // Tasks finish before evaluation does, joined or not
+    (
+        "",
+        r#"let t = spawn (print " task") in let u = print "main" in null"#,
+        "main task",
+        "null",
+    ),
+    (
+        "",
+        r#"let t = spawn (let u = print "raised" in 1 / 0) in 2"#,
+        "raised",
+        "2",
+    ),
+];
+
+#[test]
+fn test_builtins() {
+    let ctxt = builtin_context();
+    let env = builtin_environment();
+    for (input, s, output, expected) in BUILTINS {
+        let mut out = vec![];
+        let mut store = Store::new(input.as_bytes(), &mut out);
+        let exp = parse_expr(s).unwrap();
+        let (val, _) = eval_expr(
+            &exp,
+            &ctxt,
+            &env,
+            &Newtypes::default(),
+            &mut store,
+            Strategy::default(),
+        )
+        .unwrap();
+        drop(store);
+        assert_eq!(val.to_string(), *expected, "{s}");
+        assert_eq!(String::from_utf8(out).unwrap(), *output, "{s}")
+    }
+}
+
+/// Ill-typed expressions evaluated without type checking, and the part they go wrong at
+const RUNTIME_ERRORS: &[(&str, &str, &str)] = &[
+    ("y + 1", "Unbound variable at runtime", "y"),
+    ("1 + (2 3)", "Type mismatch at runtime", "2 3"),
+    (
+        "if 1 then 2 else 3",
+        "Type mismatch at runtime",
+        "if 1 then 2 else 3",
+    ),
+    ("{x = 1}.y", "Type mismatch at runtime", "{x = 1}.y"),
+    (
+        "(λ x: Int. x) == (λ x: Int. x)",
+        "Type mismatch at runtime",
+        "(λ x: Int. x) == (λ x: Int. x)",
+    ),
+    (
+        "case 3 of 0 => 1 end",
+        "Match failure",
+        "case 3 of 0 => 1 end",
+    ),
+    (
+        "let (a, b) = 1 in a",
+        "Type mismatch at runtime",
+        "let (a, b) = 1 in a",
+    ),
+];
+
+#[test]
+fn test_runtime_errors() {
+    for (s, title, at) in RUNTIME_ERRORS {
+        let err = eval_closed_expr(&parse_expr(s).unwrap()).unwrap_err();
+        match err {
+            EvalError::Runtime(err) => {
+                assert_eq!(err.title(), *title, "{s}");
+                let (start, end) = err.span().unwrap();
+                assert_eq!(&s[start..end], *at, "{s}")
+            }
+            _ => panic!("{s}"),
+        }
+    }
+}
+
+/// Assertions that fail, the source they fail at, and the reason
+const FAILED_ASSERTIONS: &[(&str, &str, &str)] = &[
+    (
+        "let _ = assert (1 < 2) in assert (2 < 1)",
+        "assert (2 < 1)",
+        "this assertion doesn't hold",
+    ),
+    (
+        "let f = λ x: Int. x * 2 in let _ = assertEq(f 2, 4) in assertEq((f 1, true), (3, true))",
+        "assertEq((f 1, true), (3, true))",
+        "(2, true) isn't equal to (3, true)",
+    ),
+];
+
+#[test]
+fn test_assertions() {
+    for s in [
+        "assert true",
+        "assertEq({x = 1, y = \"a\"}, {y = \"a\", x = 1})",
+    ] {
+        let exp = parse_expr(s).unwrap();
+        check_closed_expr(&exp).unwrap();
+        assert_eq!(eval_closed_expr(&exp).unwrap().to_string(), "null")
+    }
+    for (s, at, reason) in FAILED_ASSERTIONS {
+        let exp = parse_expr(s).unwrap();
+        check_closed_expr(&exp).unwrap();
+        match eval_closed_expr(&exp).unwrap_err() {
+            EvalError::Runtime(err) => {
+                assert_eq!(err.title(), "Assertion failed");
+                assert_eq!(err.to_string(), *reason);
+                let (start, end) = err.span().unwrap();
+                assert_eq!(&s[start..end], *at)
+            }
+            _ => panic!("{s}"),
+        }
+    }
+}
+
+const EQUALITY: &[(&str, &str)] = &[
+    ("(1, (true, null)) == (1, (true, null))", "true"),
+    ("(1, 2) != (1, 3)", "true"),
+    ("{x = 1, y = false} == {y = false, x = 1}", "true"),
+    ("let r = ref 1 in r == ref 1", "false"),
+    ("let r = ref 1 in let s = r in r == s", "true"),
+];
+
+#[test]
+fn test_equality() {
+    for (s, expected) in EQUALITY {
+        let exp = parse_expr(s).unwrap();
+        check_closed_expr(&exp).unwrap();
+        assert_eq!(eval_closed_expr(&exp).unwrap().to_string(), *expected)
+    }
+    let prog = "data List A = Nil | Cons A (List A)
+        exception Wrong
+        let xs: List Int = Cons 1 (Cons 2 Nil)
+        let x: Unit = if xs == Cons 1 (Cons 2 Nil) & xs != Cons 1 Nil then null else raise Wrong";
+    eval_prog(&parse_prog(prog).unwrap()).unwrap();
+}
+
+const LISTS: &[(&str, &str)] = &[
+    ("[1, 2, 3]", "[1, 2, 3]"),
+    ("0 :: []", "[0]"),
+    ("[[true], []]", "[[true], []]"),
+    (
+        "fix len = λ l. case l of [] => 0; _ :: t => 1 + len t end in len ([4, 5, 6])",
+        "3",
+    ),
+    (
+        "fix rev = λ l acc. case l of [] => acc; x :: t => rev t (x :: acc) end in rev ([1, 2]) []",
+        "[2, 1]",
+    ),
+    ("case [1, 2] of [x, y] => x + y; _ => 0 end", "3"),
+];
+
+/** Context and environment with the data types and exceptions of the prelude */
+fn prelude_env() -> (Context, Environment) {
+    let (mut ctxt, mut aliases, mut env) = (Context::new(), Aliases::new(), Environment::new());
+    let mut newtypes = Newtypes::new();
+    let prelude = prelude();
+    for data in &prelude.datatypes {
+        eval_datatype(data, &mut ctxt, &mut aliases, &mut env, &mut newtypes).unwrap()
+    }
+    for exn in &prelude.exceptions {
+        eval_exception(exn, &mut ctxt, &aliases, &mut env).unwrap()
+    }
+    (ctxt, env)
+}
+
+#[test]
+fn test_lists() {
+    let (ctxt, env) = prelude_env();
+    for (s, expected) in LISTS {
+        let exp = parse_expr(s).unwrap();
+        let (val, _) = eval_expr(
+            &exp,
+            &ctxt,
+            &env,
+            &Newtypes::default(),
+            &mut Store::default(),
+            Strategy::default(),
+        )
+        .unwrap();
+        assert_eq!(val.to_string(), *expected)
+    }
+}
+
+/// Division rounds towards zero, like in RISC-V
+const DIVISION: &[(&str, &str)] = &[
+    ("7 / 2", "3"),
+    ("-7 / 2", "-3"),
+    ("7 % 2", "1"),
+    ("-7 % 2", "-1"),
+    ("7 % -2", "1"),
+    ("(1073741823 + 1) / -1", "-1073741824"),
+    ("(1073741823 + 1) % -1", "0"),
+    ("1 / 0 handle Div => 42", "42"),
+    ("let zero = 0 in (5 % zero) handle Div => 0 - 1", "-1"),
+];
+
+#[test]
+fn test_division() {
+    let (ctxt, env) = prelude_env();
+    for (s, expected) in DIVISION {
+        let exp = parse_expr(s).unwrap();
+        let (val, _) = eval_expr(
+            &exp,
+            &ctxt,
+            &env,
+            &Newtypes::default(),
+            &mut Store::default(),
+            Strategy::default(),
+        )
+        .unwrap();
+        assert_eq!(val.to_string(), *expected)
+    }
+    let exp = parse_expr("1 % 0").unwrap();
+    let err = eval_expr(
+        &exp,
+        &ctxt,
+        &env,
+        &Newtypes::default(),
+        &mut Store::default(),
+        Strategy::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, EvalError::Uncaught(exn) if exn.to_string() == "Div"))
+}
+
+/// Pairs of bitwise expressions and their values
+const BITWISE: &[(&str, &str)] = &[
+    ("12 land 10", "8"),
+    ("12 lor 10", "14"),
+    ("12 lxor 10", "6"),
+    ("1 << 10", "1024"),
+    ("-16 >> 2", "-4"),
+    // Only the lowest 5 bits of the amount count, those of the 31 bits of `Int`
+    ("1 << 32", "1"),
+    ("1 << -2", "-1073741824"),
+    ("1 << 31", "0"),
+    ("(255 land 60) >> 2 lor 1", "15"),
+];
+
+#[test]
+fn test_bitwise() {
+    for (s, expected) in BITWISE {
+        let exp = parse_expr(s).unwrap();
+        check_closed_expr(&exp).unwrap();
+        assert_eq!(eval_closed_expr(&exp).unwrap().to_string(), *expected)
+    }
+}
+
+/// Sized integers wrap around, and shift amounts are masked to their width
+const SIZED_INTS: &[(&str, &str)] = &[
+    ("(127 : Int8) + 1", "-128"),
+    ("(-128 : Int8) - 1", "127"),
+    ("(300 * 300 : Int16)", "24464"),
+    ("(2147483647 : Int32) * 2", "-2"),
+    ("(1 : Int8) << 9", "2"),
+    ("(-128 : Int8) >> 1", "-64"),
+    ("(-128 : Int8) / -1", "-128"),
+    ("int8(300)", "44"),
+    ("int8(128)", "-128"),
+    ("int16(int8(-1))", "-1"),
+    ("int64((100 : Int8) + 100) + 100", "44"),
+    ("int((100 : Int8)) * 100", "10000"),
+    ("(4611686018427387904 : Int64) * 2", "-9223372036854775808"),
+    ("int((1073741824 : Int64))", "-1073741824"),
+    ("(int8(256) : Int8) == 0", "true"),
+    ("case int8(255) of -1 => true; _ => false end", "true"),
+    ("(1 : Int8) / 0 handle Div => 7", "7"),
+];
+
+#[test]
+fn test_sized_ints() {
+    let (ctxt, env) = prelude_env();
+    for (s, expected) in SIZED_INTS {
+        let exp = parse_expr(s).unwrap();
+        let (val, _) = eval_expr(
+            &exp,
+            &ctxt,
+            &env,
+            &Newtypes::default(),
+            &mut Store::default(),
+            Strategy::default(),
+        )
+        .unwrap();
+        assert_eq!(val.to_string(), *expected)
+    }
+}
+
+/// Ascriptions are erased before evaluation
+const ASCRIPTIONS: &[(&str, &str)] = &[
+    ("(1 + 2 : Int)", "3"),
+    ("(λ x. x : Int -> Int) 4", "4"),
+    ("let f = (λ x. (x : Bool)) in f true", "true"),
+    ("([] : List Int)", "[]"),
+];
+
+#[test]
+fn test_ascriptions() {
+    let (ctxt, env) = prelude_env();
+    for (s, expected) in ASCRIPTIONS {
+        let exp = parse_expr(s).unwrap();
+        let (val, _) = eval_expr(
+            &exp,
+            &ctxt,
+            &env,
+            &Newtypes::default(),
+            &mut Store::default(),
+            Strategy::default(),
+        )
+        .unwrap();
+        assert_eq!(val.to_string(), *expected)
+    }
+    assert_eq!(
+        eval_closed_expr(&parse_expr("((1, 2) : Int * Int)").unwrap())
+            .unwrap()
+            .to_string(),
+        "(1, 2)"
+    )
+}
+
+const ARRAYS: &[(&str, &str)] = &[
+    ("let a = array(3, 7) in sub(a, 2)", "7"),
+    (
+        "let a = array(3, 0) in let _ = update(a, 1, 5) in sub(a, 1) + sub(a, 0)",
+        "5",
+    ),
+    // Arrays alias like references
+    (
+        "let a = array(1, 0) in let b = a in let _ = update(b, 0, 1) in sub(a, 0)",
+        "1",
+    ),
+    (
+        "let a = array(2, ref 0) in let _ = sub(a, 0) := 3 in !(sub(a, 1))",
+        "3",
+    ),
+    ("sub(array(2, 0), 2) handle Subscript => 0 - 1", "-1"),
+    ("sub(array(2, 0), 0 - 1) handle Subscript => 0 - 1", "-1"),
+    ("update(array(0, 0), 0, 1) handle Subscript => null", "null"),
+    ("sub(array(0 - 1, 0), 0) handle Subscript => 0", "0"),
+    (
+        "let a = array(1, 0) in (a == a, a == array(1, 0))",
+        "(true, false)",
+    ),
+];
+
+#[test]
+fn test_arrays() {
+    let (ctxt, env) = prelude_env();
+    for (s, expected) in ARRAYS {
+        let exp = parse_expr(s).unwrap();
+        let (val, _) = eval_expr(
+            &exp,
+            &ctxt,
+            &env,
+            &Newtypes::default(),
+            &mut Store::default(),
+            Strategy::default(),
+        )
+        .unwrap();
+        assert_eq!(val.to_string(), *expected)
+    }
+    let exp = parse_expr("sub(array(1, 0), 1)").unwrap();
+    let err = eval_expr(
+        &exp,
+        &ctxt,
+        &env,
+        &Newtypes::default(),
+        &mut Store::default(),
+        Strategy::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, EvalError::Uncaught(exn) if exn.to_string() == "Subscript"))
+}
+
+#[test]
+fn test_references() {
+    for (s, expected) in REF {
+        let exp = parse_expr(s).unwrap();
+        check_closed_expr(&exp).unwrap();
+        assert_eq!(eval_closed_expr(&exp).unwrap().to_string(), *expected)
+    }
+}
+
+#[test]
+fn test_futures() {
+    let (ctxt, env) = prelude_env();
+    for (s, expected) in FUTURE {
+        let exp = parse_expr(s).unwrap();
+        let (val, _) = eval_expr(
+            &exp,
+            &ctxt,
+            &env,
+            &Newtypes::default(),
+            &mut Store::default(),
+            Strategy::default(),
+        )
+        .unwrap();
+        assert_eq!(val.to_string(), *expected)
+    }
+    // Tasks printing take turns printing
+    let s = r#"let loop = λ s: String. fix loop = λ (n: Int) : Unit. if n == 0 then null else let _ = print s in loop (n - 1) in loop 100 in
+        let t = spawn (loop "a") in let u = spawn (loop "b") in let _ = join t in join u"#;
+    let mut out = vec![];
+    let mut store = Store::new("".as_bytes(), &mut out);
+    let exp = parse_expr(s).unwrap();
+    eval_expr(
+        &exp,
+        &builtin_context(),
+        &builtin_environment(),
+        &Newtypes::default(),
+        &mut store,
+        Strategy::default(),
+    )
+    .unwrap();
+    drop(store);
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.matches('a').count(), 100);
+    assert_eq!(out.matches('b').count(), 100);
+    assert!(out.contains("ab") && out.contains("ba"), "{out}");
+    // A task joining itself waits forever
+    let s = "let r = ref (spawn 0) in let _ = r := spawn (join (!r)) in 1";
+    let err = eval_closed_expr(&parse_expr(s).unwrap()).unwrap_err();
+    match err {
+        EvalError::Runtime(err) => assert_eq!(err.title(), "Deadlock"),
+        _ => panic!("{s} doesn't deadlock"),
+    }
+}
+
 #[test]
 fn test_snippets() {
     let everything = ARITHMETIC
@@ -73,12 +715,665 @@ fn test_snippets() {
         .chain(APP)
         .chain(ANY)
         .chain(LET)
-        .chain(FIX);
+        .chain(FIX)
+        .chain(CASE)
+        .chain(RECORD)
+        .chain(PACK);
     for expr_string in everything {
         println!("{}", expr_string);
         let exp = parse_expr(expr_string).unwrap();
         check_closed_expr(&exp).unwrap();
-        println!("{}", eval_closed_expr(&exp));
+        println!("{}", eval_closed_expr(&exp).unwrap());
         println!("---------------------------------------------")
     }
 }
+
+/// Reduction steps of expressions, one per line of the trace
+const TRACES: &[(&str, &[&str])] = &[
+    ("(λ x: Int. x + 1) 2", &["2 + 1", "3"]),
+    (
+        "let (a, b) = (1 + 1, true) in if b then a else 0",
+        &[
+            "let (a, b) = (2, true) in (if b then a else 0)",
+            "(if true then 2 else 0)",
+            "2",
+        ],
+    ),
+    ("{x = 1, y = 2}.y", &["2"]),
+    (
+        "(1 / 0) + 1 handle Div => 7",
+        &[
+            "((raise [_] Div) + 1) handle Div => 7",
+            "(raise [_] Div) handle Div => 7",
+            "7",
+        ],
+    ),
+];
+
+/// Expressions reading and writing the heap, what they print, and the expressions they reduce to
+const EFFECTFUL_TRACES: &[(&str, &str, &[&str])] = &[
+    (
+        "let u = print \"a\" in 1 + 2",
+        "a",
+        &["let u = null in 1 + 2", "1 + 2", "3"],
+    ),
+    (
+        "let r = ref 1 in !r + 2",
+        "",
+        &["let r = ℓ0 in (!r) + 2", "(!ℓ0) + 2", "1 + 2", "3"],
+    ),
+    (
+        "let r = ref 1 in let u = r := !r + 1 in !r",
+        "",
+        &[
+            "let r = ℓ0 in let u = r := (!r) + 1 in !r",
+            "let u = ℓ0 := (!ℓ0) + 1 in !ℓ0",
+            "let u = ℓ0 := 1 + 1 in !ℓ0",
+            "let u = ℓ0 := 2 in !ℓ0",
+            "let u = null in !ℓ0",
+            "!ℓ0",
+            "2",
+        ],
+    ),
+    (
+        "let a = array(2, 0) in let u = update(a, 1, 5) in sub(a, 1)",
+        "",
+        &[
+            "let a = ℓ0 in let u = update(a, 1, 5) in sub(a, 1)",
+            "let u = update(ℓ0, 1, 5) in sub(ℓ0, 1)",
+            "let u = null in sub(ℓ0, 1)",
+            "sub(ℓ0, 1)",
+            "5",
+        ],
+    ),
+    (
+        "sub(array(1, 0), 3) handle Subscript => 7",
+        "",
+        &[
+            "sub(ℓ0, 3) handle Subscript => 7",
+            "(raise [_] Subscript) handle Subscript => 7",
+            "7",
+        ],
+    ),
+    // Failed assertions are stuck
+    (
+        "let u = printInt (1 + 2) in assert false",
+        "3",
+        &[
+            "let u = printInt 3 in assert false",
+            "let u = null in assert false",
+            "assert false",
+        ],
+    ),
+];
+
+#[test]
+fn test_traces() {
+    let (ctxt, env) = prelude_env();
+    let newtypes = Newtypes::default();
+    for (s, expected) in TRACES {
+        let store = Store::new("".as_bytes(), vec![]);
+        let steps = trace_expr(&parse_expr(s).unwrap(), &ctxt, &newtypes, usize::MAX, store);
+        let steps: Vec<_> = steps.unwrap().iter().map(|e| e.to_string()).collect();
+        assert_eq!(steps, *expected, "{s}")
+    }
+    let builtins = ctxt.clone().union(builtin_context());
+    for (s, output, expected) in EFFECTFUL_TRACES {
+        let mut out = vec![];
+        let store = Store::new("".as_bytes(), &mut out);
+        let steps = trace_expr(
+            &parse_expr(s).unwrap(),
+            &builtins,
+            &newtypes,
+            usize::MAX,
+            store,
+        );
+        let steps: Vec<_> = steps.unwrap().iter().map(|e| e.to_string()).collect();
+        assert_eq!(steps, *expected, "{s}");
+        assert_eq!(String::from_utf8(out).unwrap(), *output, "{s}")
+    }
+    // Small steps end in the value of evaluation
+    let everything = ARITHMETIC
+        .iter()
+        .chain(BOOLEAN)
+        .chain(APP)
+        .chain(LET)
+        .chain(FIX)
+        .chain(CASE)
+        .chain(RECORD)
+        .chain(PACK)
+        .chain(DIVISION.iter().map(|(s, _)| s));
+    for s in everything {
+        let exp = parse_expr(s).unwrap();
+        let (val, _) = eval_expr(
+            &exp,
+            &ctxt,
+            &env,
+            &newtypes,
+            &mut Store::default(),
+            Strategy::default(),
+        )
+        .unwrap();
+        let steps = trace_expr(&exp, &ctxt, &newtypes, 10000, Store::default()).unwrap();
+        let last = steps.last().map_or(s.to_string(), |e| e.to_string());
+        assert_eq!(last, val.to_string(), "{s}")
+    }
+    let steps = trace_expr(
+        &parse_expr(FIX[1]).unwrap(),
+        &ctxt,
+        &newtypes,
+        3,
+        Store::default(),
+    );
+    let steps = steps.unwrap();
+    assert_eq!(steps.len(), 3)
+}
+
+/// Values and expressions printed with depth limit 2 and width limit 3
+const PRETTY: &[(&str, &str, &str)] = &[
+    ("(1, (2, (3, 4)))", "(1, (2, …))", "(1, (2, …))"),
+    ("(1, 2, 3, 4, 5)", "(1, 2, 3, …)", "(1, 2, 3, …)"),
+    (
+        "{a = 1, b = 2, c = 3, d = {e = 4}}",
+        "{a = 1, b = 2, c = 3, …}",
+        "{a = 1, b = 2, c = 3, d = {e = 4}}",
+    ),
+    ("[1, 2, 3, 4]", "[1, 2, 3, …]", "Cons 1 (Cons 2 …)"),
+    (
+        "[[1], [2, 3]]",
+        "[[1], [2, 3]]",
+        "Cons (Cons 1 Nil) (Cons … Nil)",
+    ),
+    ("1 + (2 * (3 - 4))", "-1", "1 + (2 * …)"),
+];
+
+#[test]
+fn test_pretty() {
+    colored::control::set_override(false);
+    let (ctxt, env) = prelude_env();
+    let newtypes = Newtypes::default();
+    let limits = Limits { depth: 2, width: 3 };
+    for (s, val, expr) in PRETTY {
+        let exp = parse_expr(s).unwrap();
+        let mut store = Store::default();
+        let strategy = Strategy::default();
+        let (value, _) = eval_expr(&exp, &ctxt, &env, &newtypes, &mut store, strategy).unwrap();
+        assert_eq!(Pretty::new(&value, limits).to_string(), *val, "{s}");
+        assert_eq!(elide(&exp, limits).to_string(), *expr, "{s}");
+        assert_eq!(
+            Pretty::new(&value, Limits::NONE).to_string(),
+            value.to_string()
+        )
+    }
+}
+
+/// Open expressions and their normal forms
+const NORMAL_FORMS: &[(&str, &str)] = &[
+    ("λ x: Int. (λ y: Int. y + 1) x", "λ v0: Int. v0 + 1"),
+    ("λ x: Int. x + 2 * 3", "λ v0: Int. v0 + 6"),
+    ("λ f: Int -> Int. λ x: Int. f x", "λ v0: Int -> Int. v0"),
+    ("(Λ A. λ x: A. x) [Int]", "λ v0: Int. v0"),
+    (
+        "λ m: {get: Int}. let c = 3 in let b = 2 in let a = 1 in open m in a + b + c",
+        "λ v0: {get: Int}. let a = 1 in let b = 2 in let c = 3 in open v0 in (a + b) + c",
+    ),
+    (
+        "λ x: Int. let (a, b) = (x, 1) in a + b",
+        "λ v0: Int. v0 + 1",
+    ),
+    ("λ x: Int. {a = x, b = 2}.b", "λ v0: Int. 2"),
+    (
+        "λ b: Bool. (λ x: Int. if b then x else 0) 3",
+        "λ v0: Bool. (if v0 then 3 else 0)",
+    ),
+    (
+        "λ xs: List Int. case xs of Nil => 0; Cons y ys => y end",
+        "λ v0: List Int. case v0 of Nil => 0; Cons v1 v2 => v1 end",
+    ),
+    (
+        "fix fact = λ (n: Int) : Int. if n == 0 then 1 else n * fact (n - 1) in fact 5",
+        "120",
+    ),
+    // Effects are neither dropped nor copied
+    (
+        "λ x: Int. let y = print \"a\" in (x, x)",
+        "λ v0: Int. let v1 = print \"a\" in (v0, v0)",
+    ),
+    (
+        "λ x: Int. let y = x / 0 in 1",
+        "λ v0: Int. let v1 = v0 / 0 in 1",
+    ),
+    (
+        "λ r: Ref Int. let _ = r := 1 in !r",
+        "λ v0: Ref Int. let v1 = v0 := 1 in !v0",
+    ),
+    ("λ x: Int. x handle Div => 0", "λ v0: Int. v0"),
+];
+
+/// Pairs of expressions, and whether they're equal up to β and η
+const BETA_ETA: &[(&str, &str, bool)] = &[
+    ("λ x: Int. x", "λ y: Int. y", true),
+    ("λ f: Int -> Int. f", "λ g: Int -> Int. λ x: Int. g x", true),
+    (
+        "λ x: Int. (λ y: Int. y * 2) (x + 1)",
+        "λ z: Int. (z + 1) * 2",
+        true,
+    ),
+    (
+        "λ p: Int * Int. let (a, b) = p in a",
+        "λ p: Int * Int. let (b, a) = p in b",
+        true,
+    ),
+    ("λ x: Int. x + 1", "λ x: Int. 1 + x", false),
+    ("λ x: Int. let _ = printInt x in x", "λ x: Int. x", false),
+];
+
+#[test]
+fn test_normalize() {
+    colored::control::set_override(false);
+    for (s, expected) in NORMAL_FORMS {
+        let normal = normalize(&parse_expr(s).unwrap());
+        assert_eq!(normal.to_string(), *expected, "{s}")
+    }
+    for (s1, s2, expected) in BETA_ETA {
+        let (e1, e2) = (parse_expr(s1).unwrap(), parse_expr(s2).unwrap());
+        assert_eq!(beta_eta_equal(&e1, &e2), *expected, "{s1} {s2}")
+    }
+    // Closed expressions normalize to their values
+    let everything = ARITHMETIC
+        .iter()
+        .chain(BOOLEAN)
+        .chain(FIX)
+        .chain(CASE)
+        .chain(RECORD);
+    for s in everything {
+        let exp = parse_expr(s).unwrap();
+        let val = eval_closed_expr(&exp).unwrap();
+        assert_eq!(normalize(&exp).to_string(), val.to_string(), "{s}")
+    }
+}
+
+const DATATYPES: &[&str] = &[
+    "data Option A = None | Some A
+     let x: Int = case Some [Int] 3 of None => 0; Some x => x end",
+    "data List A = Nil | Cons A (List A)
+     let sum: List Int -> Int = fix sum = λ (l: List Int) : Int.
+       case l of Nil => 0; Cons x tl => x + sum tl end in sum
+     let six: Int = sum (Cons [Int] 1 (Cons [Int] 2 (Cons [Int] 3 (Nil [Int]))))",
+    "data Option A = None | Some A
+     type IntList = rec L. Option (Int * L)
+     let nil: IntList = fold [IntList] (None [Int * IntList])
+     let cons: Int -> IntList -> IntList = λ (x: Int) (l: IntList). fold [IntList] (Some [Int * IntList] (x, l))
+     let sum: IntList -> Int = fix sum = λ (l: IntList) : Int.
+       case unfold l of None => 0; Some (x, tl) => x + sum tl end in sum
+     let six: Int = sum (cons 1 (cons 2 (cons 3 nil)))",
+    "data List A = Nil | Cons A (List A)
+     let map = fix map = λ f l. case l of Nil => Nil; Cons x tl => Cons (f x) (map f tl) end in map
+     let sum = fix sum = λ l. case l of Nil => 0; Cons x tl => x + sum tl end in sum
+     let twelve = sum (map (λ x. x * 2) (Cons 1 (Cons 2 (Cons 3 Nil))))",
+    "data Option A = None | Some A
+     let get: ∀ A. A -> Option A -> A = λ (d: A) (o: Option A). case o of None => d; Some x => x end
+     let three: Int = get [Int] 0 (Some 3)",
+];
+
+#[test]
+fn test_datatypes() {
+    for s in DATATYPES {
+        eval_prog(&parse_prog(s).unwrap()).unwrap();
+    }
+}
+
+#[test]
+fn test_outcome() {
+    colored::control::set_override(false);
+    let prog = "data Color = Red | Green deriving (Eq)
+                let pair: Int * Color = (1 + 2, Green)
+                let same: Bool = eq_Color (pair.1) Red";
+    let outcome = eval_prog(&parse_prog(prog).unwrap()).unwrap();
+    // Derived declarations aren't part of the outcome
+    assert_eq!(outcome.order, ["pair", "same"]);
+    assert_eq!(outcome.value("pair").unwrap().to_string(), "(3, Green)");
+    assert_eq!(outcome.typ("pair").unwrap().to_string(), "(Int * Color)");
+    assert_eq!(outcome.value("same").unwrap().to_string(), "false");
+    assert!(outcome.value("eq_Color").is_none())
+}
+
+#[test]
+fn test_coverage() {
+    let source = "let sign: Int -> Int =
+  λ x: Int.
+    case x < 0 of
+      true => 0 - 1;
+      false => let _ = print \"positive\" in 1
+    end
+
+let unused: Int -> Int = λ x: Int. x * 2
+
+let main: Int = sign 5 + sign 7";
+    let mut out = vec![];
+    let mut store = Store::new("".as_bytes(), &mut out);
+    let (coverage, outcome) = cover(&parse_prog(source).unwrap(), source, &mut store);
+    drop(store);
+    assert_eq!(outcome.unwrap().value("main").unwrap().to_string(), "2");
+    assert_eq!(String::from_utf8(out).unwrap(), "positivepositive");
+    let lines = [
+        None,
+        Some(1),
+        Some(2),
+        Some(0),
+        Some(2),
+        None,
+        None,
+        Some(0),
+        None,
+        Some(1),
+    ];
+    assert_eq!(coverage.line_hits(), lines);
+    let (covered, total) = coverage.covered();
+    assert!(covered < total);
+    let lcov = coverage.lcov("sign.polylamb");
+    assert!(lcov.starts_with("TN:\nSF:sign.polylamb\n"));
+    for record in [
+        "BRDA:3,0,0,0",
+        "BRDA:3,0,1,2",
+        "DA:4,0",
+        "DA:8,0",
+        "LF:6",
+        "LH:4",
+    ] {
+        assert!(
+            lcov.lines().any(|line| line == record),
+            "{record} in {lcov}"
+        )
+    }
+    assert!(coverage
+        .annotated(source)
+        .contains("#####:    8:let unused"))
+}
+
+const EXCEPTIONS: &[&str] = &[
+    "exception Empty
+     let x: Int = raise Empty handle Empty => 1",
+    "exception Fail Int
+     let check = λ n. if n < 0 then raise (Fail n) else n
+     let x: Int = check (-5) handle Fail n => 0 - n",
+    "exception Fail Int
+     exception Other
+     let x: Int = ((raise (Fail 2) handle Other => 0) + 1) handle Fail n => n",
+    "exception Skip
+     let r = ref 0
+     let x: Unit = (let _ = r := 1 in raise Skip) handle Skip => null
+     let y: Bool = !r == 1",
+];
+
+#[test]
+fn test_exceptions() {
+    for s in EXCEPTIONS {
+        eval_prog(&parse_prog(s).unwrap()).unwrap();
+    }
+    let uncaught = "exception Fail Int let x: Int = raise (Fail 3) handle Fail 0 => 1";
+    let err = eval_prog(&parse_prog(uncaught).unwrap()).unwrap_err();
+    assert!(matches!(err, EvalError::Uncaught(exn) if exn.to_string() == "Fail 3"))
+}
+
+const CLASSES: &[&str] = &[
+    "class Eq A { eq: A -> A -> Bool }
+     instance Eq Int { eq = λ x y. x == y }
+     instance Eq Bool { eq = λ x y. if x then y else if y then false else true }
+     let x: Bool = eq 1 1 & eq false false",
+    // Instances for compound types use the instances of their parts
+    "class Eq A { eq: A -> A -> Bool }
+     instance Eq Int { eq = λ x y. x == y }
+     instance Eq (Int * Int) { eq = λ p q. let (a, b) = p in let (c, d) = q in eq a c & eq b d }
+     let x: Bool = eq (1, 2) (1, 2)",
+    // Dictionaries can be passed explicitly to polymorphic functions
+    "class Eq A { eq: A -> A -> Bool }
+     let neq: ∀ A. Eq A -> A -> A -> Bool = Λ A. λ d x y. if eq x y then false else true
+     let int_eq: Eq Int = Eq [Int] {eq = λ x y. x == y}
+     let x: Bool = neq [Int] int_eq 1 2",
+    "class Monoid A { empty: A, combine: A -> A -> A }
+     instance Monoid Int { empty = 0, combine = λ x y. x + y }
+     let x: Int = combine 1 (combine 2 empty)",
+    // Constrained functions get the dictionary of the type they're used at
+    "exception Wrong
+     class Monoid A { empty: A, combine: A -> A -> A }
+     instance Monoid Int { empty = 0, combine = λ x y. x + y }
+     instance Monoid Bool { empty = false, combine = λ x y. x | y }
+     let twice: ∀ (A: Monoid). A -> A = Λ (A: Monoid). λ x. combine x (combine x empty)
+     let x: Unit = if twice 3 == 6 & twice true then null else raise Wrong",
+];
+
+/// Newtypes are represented by their fields
+const NEWTYPES: &[(&str, &str)] = &[
+    ("UserId 5", "5"),
+    ("case UserId 5 of UserId n => n + 1 end", "6"),
+    ("let f = UserId in f 3", "3"),
+    ("Wrap (UserId 1, 2)", "(1, 2)"),
+    ("let (UserId a, b) = (UserId 1, 2) in a + b", "3"),
+    ("Some (UserId 7)", "Some 7"),
+];
+
+#[test]
+fn test_newtypes() {
+    let (mut ctxt, mut env) = prelude_env();
+    let (mut aliases, mut newtypes) = (Aliases::new(), Newtypes::new());
+    for s in [
+        "data Option A = None | Some A",
+        "newtype UserId = UserId Int",
+        "newtype Wrap A = Wrap (A * Int)",
+    ] {
+        let data = parse_data(s).unwrap();
+        eval_datatype(&data, &mut ctxt, &mut aliases, &mut env, &mut newtypes).unwrap()
+    }
+    for (s, expected) in NEWTYPES {
+        let exp = parse_expr(s).unwrap();
+        let (val, _) = eval_expr(
+            &exp,
+            &ctxt,
+            &env,
+            &newtypes,
+            &mut Store::default(),
+            Strategy::default(),
+        )
+        .unwrap();
+        assert_eq!(val.to_string(), *expected)
+    }
+    let prog = "newtype UserId = UserId Int deriving (Eq)
+                let u: UserId = UserId 1
+                let check: Int = if eq_UserId u (UserId 1) then 0 else 1 / 0";
+    eval_prog(&parse_prog(prog).unwrap()).unwrap();
+}
+
+/// Each program raises `Div` unless the derived equalities give the right answers
+const DERIVING: &[&str] = &[
+    "data Option A = None | Some A deriving (Eq)
+     let int_eq: Int -> Int -> Bool = λ x y. x == y
+     let t: Bool = eq_Option [Int] int_eq (Some 1) (Some 1)
+     let f: Bool = eq_Option [Int] int_eq (Some 1) (Some 2) | eq_Option [Int] int_eq None (Some 1)
+     let check: Int = if t & f == false then 0 else 1 / 0",
+    // Equalities of parameters decide the comparison of their fields
+    "data Pair A = Pair A (A * Bool) deriving (Eq)
+     let parity: Int -> Int -> Bool = λ x y. x % 2 == y % 2
+     let t: Bool = eq_Pair [Int] parity (Pair 1 (3, true)) (Pair 5 (7, true))
+     let f: Bool = eq_Pair [Int] parity (Pair 1 (3, true)) (Pair 1 (3, false))
+     let check: Int = if t & f == false then 0 else 1 / 0",
+    "data Option A = None | Some A deriving (Eq)
+     data Tree A = Leaf | Node (Tree A) (Option A) {right: Tree A} deriving (Eq)
+     let int_eq: Int -> Int -> Bool = λ x y. x == y
+     let leaf: Tree Int = Leaf
+     let t1: Tree Int = Node leaf (Some 1) {right = Node leaf None {right = leaf}}
+     let t2: Tree Int = Node leaf (Some 1) {right = Node leaf (Some 2) {right = leaf}}
+     let check: Int = if eq_Tree [Int] int_eq t1 t1 & eq_Tree [Int] int_eq t1 t2 == false then 0 else 1 / 0",
+];
+
+#[test]
+fn test_deriving() {
+    for s in DERIVING {
+        eval_prog(&parse_prog(s).unwrap()).unwrap();
+    }
+}
+
+/// Programs showing `value` by its derived `Show`, and the string they show it as
+const SHOWN: &[(&str, &str)] = &[
+    (
+        "data Color = Red | Green deriving (Show)
+         let value: Color = Green",
+        "show_Color value",
+    ),
+    (
+        "data Option A = None | Some A deriving (Show, Eq)
+         let value: Option Int = Some (0 - 3)",
+        "show_Option [Int] showInt value",
+    ),
+    // Fields of parameters are in parentheses when their data type derives `Show` too
+    (
+        "data Option A = None | Some A deriving (Show)
+         data Box = Box (Option (Option Int)) (Option Bool) deriving (Show)
+         let value: Box = Box (Some (Some 1)) None",
+        "show_Box value",
+    ),
+    (
+        "data Option A = None | Some A deriving (Show)
+         data Tree A = Leaf | Node (Tree A) (Option A) {right: Tree A, tag: String * Bool} deriving (Show)
+         let leaf: Tree Int = Leaf
+         let value: Tree Int = Node leaf (Some 1) {right = Node leaf None {right = leaf, tag = (\"b\", false)}, tag = (\"a\", true)}",
+        "show_Tree [Int] showInt value",
+    ),
+];
+
+#[test]
+fn test_deriving_show() {
+    colored::control::set_override(false);
+    for (s, show) in SHOWN {
+        let prog = format!("{s}\nlet shown: String = {show}");
+        let outcome = eval_prog(&parse_prog(&prog).unwrap()).unwrap();
+        // Values show like the interpreter prints them
+        let value = outcome.value("value").unwrap().to_string();
+        let shown = outcome.value("shown").unwrap().to_string();
+        assert_eq!(shown, format!("{value:?}"), "{s}")
+    }
+    // Newtypes show their constructor, though it's erased before evaluation
+    let prog = "newtype UserId = UserId Int deriving (Show)
+                let shown: String = show_UserId (UserId 7)";
+    let outcome = eval_prog(&parse_prog(prog).unwrap()).unwrap();
+    assert_eq!(outcome.value("shown").unwrap().to_string(), r#""UserId 7""#)
+}
+
+#[test]
+fn test_classes() {
+    for s in CLASSES {
+        eval_prog(&parse_prog(s).unwrap()).unwrap();
+    }
+}
+
+/// Structures are records of their members, checked by raising when a result is off
+const MODULES: &[&str] = &[
+    "exception Wrong
+     signature COUNTER = sig type T val zero: T val incr: T -> T val get: T -> Int end
+     structure Counter : COUNTER = struct
+       type T = Int
+       let zero = 10
+       let incr = λ n: T. n + 1
+       let get = λ n: T. n
+     end
+     let x: Unit = if (open Counter in get (incr (incr zero))) == 12 then null else raise Wrong",
+    "exception Wrong
+     structure Util = struct let double = λ n: Int. n * 2 let quad = λ n: Int. double (double n) end
+     let x: Unit = if Util.quad 3 == 12 then null else raise Wrong",
+];
+
+#[test]
+fn test_modules() {
+    for s in MODULES {
+        eval_prog(&parse_prog(s).unwrap()).unwrap();
+    }
+}
+
+const DEBUGGED: &str = "let double: Int -> Int = λ x: Int. x + x
+let main: Int =
+  let y = double 3 in
+  y + 1";
+
+#[test]
+fn test_debugger() {
+    colored::control::set_override(false);
+    let prog = parse_prog(DEBUGGED).unwrap();
+    let new = || {
+        Debugger::new(
+            &prog,
+            DEBUGGED,
+            "main",
+            Store::new("".as_bytes(), std::io::sink()),
+        )
+        .unwrap()
+    };
+    let mut debugger = new();
+    let env: Vec<_> = debugger.env().iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(env, ["double", "main"]);
+    assert_eq!(debugger.resume(), Pause::Done);
+    assert_eq!(debugger.expr().to_string(), "7");
+    assert_eq!(debugger.step(), Pause::Done);
+    // Pauses before unfolding declarations
+    let mut debugger = new();
+    debugger.breakpoints.push("double".parse().unwrap());
+    let paused = Pause::Breakpoint(Breakpoint::Decl("double".to_string()));
+    assert_eq!(debugger.resume(), paused);
+    assert_eq!(debugger.line(), Some(3));
+    assert_eq!(debugger.step(), Pause::Step);
+    assert_eq!(debugger.resume(), Pause::Done);
+    // Pauses when reduction enters a line
+    let mut debugger = new();
+    debugger.breakpoints.push("4".parse().unwrap());
+    assert_eq!(debugger.resume(), Pause::Breakpoint(Breakpoint::Line(4)));
+    assert_eq!(debugger.expr().to_string(), "6 + 1");
+    assert_eq!(debugger.resume(), Pause::Done);
+    assert_eq!(debugger.expr().to_string(), "7");
+}
+
+const DEBUGGED_EFFECTS: &str = "let main: Int =
+  let u = print \"a\" in
+  let r = ref 1 in
+  let v = r := !r + 2 in
+  !r";
+
+#[test]
+fn test_debugger_effects() {
+    colored::control::set_override(false);
+    let prog = parse_prog(DEBUGGED_EFFECTS).unwrap();
+    let mut out = vec![];
+    let store = Store::new("".as_bytes(), &mut out);
+    let mut debugger = Debugger::new(&prog, DEBUGGED_EFFECTS, "main", store).unwrap();
+    debugger.breakpoints.push("4".parse().unwrap());
+    // Printed, and the reference allocated
+    assert_eq!(debugger.resume(), Pause::Breakpoint(Breakpoint::Line(4)));
+    assert_eq!(
+        debugger.expr().to_string(),
+        "let v = ℓ0 := (!ℓ0) + 2 in !ℓ0"
+    );
+    assert_eq!(debugger.resume(), Pause::Done);
+    assert_eq!(
+        (debugger.steps(), debugger.expr().to_string()),
+        (9, "3".to_string())
+    );
+    drop(debugger);
+    assert_eq!(out, b"a")
+}
+
+/// Looking up a list many times, which takes time quadratic in its length unless it's shared
+#[test]
+fn test_sharing() {
+    let prog = "data List A = Nil | Cons A (List A)
+     let build: Int -> List Int = fix build = λ (n: Int) : List Int. if n == 0 then Nil else Cons n (build (n - 1)) in build
+     let xs: List Int = build 3000
+     let count: Int = fix count = λ (n: Int) : Int. if n == 0 then 0 else (case xs of Nil => 0; Cons x _ => x end) + count (n - 1) in count 3000";
+    let outcome = eval_prog(&parse_prog(prog).unwrap()).unwrap();
+    assert_eq!(outcome.value("count").unwrap().to_string(), "9000000");
+    // Calling a function with a large body many times, which copies the body on every call
+    // unless it's shared
+    let big = vec!["n"; 5000].join(" + ");
+    let prog = format!(
+        "let count: Int = fix count = λ (n: Int) : Int. if n < 0 then {big} else if n == 0 then 0 else 1 + count (n - 1) in count 20000"
+    );
+    let outcome = eval_prog(&parse_prog(&prog).unwrap()).unwrap();
+    assert_eq!(outcome.value("count").unwrap().to_string(), "20000")
+}
@@ -65,6 +65,13 @@ const COMMENTS: [&str; 4] = [
     "\n/*\t\t\n boom shakalaka. */\n\t",
 ];
 
+const DOC_COMMENTS: [(&str, &str); 4] = [
+    ("(** Adds one *)", "Adds one"),
+    ("(***)", ""),
+    ("(** x * y *)", "x * y"),
+    ("(**\n  Multi-line\n  (docs) **)", "Multi-line\n  (docs)"),
+];
+
 #[test]
 fn int_lits() {
     for (input, expect) in INT_PAIRS {
@@ -77,6 +84,14 @@ fn unit_lit() {
     check_one("null", Token::UnitLit);
 }
 
+#[test]
+fn str_lits() {
+    check_one(r#""""#, Token::StrLit(String::new()));
+    check_one(r#""hi there""#, Token::StrLit("hi there".to_string()));
+    check_one(r#""a\n\t\\\"""#, Token::StrLit("a\n\t\\\"".to_string()));
+    check_one("String", Token::TString);
+}
+
 #[test]
 fn bool_lits() {
     check_one("true", Token::BoolLit(true));
@@ -101,13 +116,134 @@ fn comments() {
     }
 }
 
+#[test]
+fn doc_comments() {
+    for (input, expect) in DOC_COMMENTS {
+        check_one(input, Token::DocComment(expect));
+    }
+}
+
+#[test]
+fn attribute_tokens() {
+    check_one("@", Token::At);
+    check_one("[", Token::LBrack);
+    check_one("]", Token::RBrack);
+}
+
+#[test]
+fn case_tokens() {
+    check_one("case", Token::Case);
+    check_one("of", Token::Of);
+    check_one("end", Token::End);
+    check_one("=>", Token::FatArrow);
+    check_one(";", Token::Semicolon);
+}
+
+#[test]
+fn record_tokens() {
+    check_one("{", Token::LBrace);
+    check_one("}", Token::RBrace);
+}
+
+#[test]
+fn recursive_type_tokens() {
+    check_one("rec", Token::Rec);
+    check_one("μ", Token::Rec);
+    check_one("fold", Token::Fold);
+    check_one("unfold", Token::Unfold);
+}
+
+#[test]
+fn existential_tokens() {
+    check_one("exists", Token::Exists);
+    check_one("∃", Token::Exists);
+    check_one("pack", Token::Pack);
+    check_one("as", Token::As);
+    check_one("unpack", Token::Unpack);
+}
+
+#[test]
+fn reference_tokens() {
+    check_one("ref", Token::Ref);
+    check_one("Ref", Token::TRef);
+    check_one("!", Token::Bang);
+    check_one(":=", Token::Assign);
+}
+
+#[test]
+fn array_tokens() {
+    check_one("array", Token::Array);
+    check_one("sub", Token::Sub);
+    check_one("update", Token::Update);
+    check_one("Array", Token::TArray);
+    check_one("subarray", Token::ExpId("subarray"));
+}
+
+#[test]
+fn list_tokens() {
+    check_one("::", Token::ColonColon);
+    check_one("[", Token::LBrack);
+    check_one("]", Token::RBrack);
+}
+
+#[test]
+fn class_tokens() {
+    check_one("class", Token::Class);
+    check_one("instance", Token::Instance);
+    check_one("deriving", Token::Deriving);
+}
+
+#[test]
+fn exception_tokens() {
+    check_one("exception", Token::Exception);
+    check_one("raise", Token::Raise);
+    check_one("handle", Token::Handle);
+    check_one("Exn", Token::TExn);
+}
+
+#[test]
+fn sized_int_tokens() {
+    for bits in ["8", "16", "32", "64"] {
+        check_one(&format!("Int{bits}"), Token::TSized(bits));
+        check_one(&format!("int{bits}"), Token::Convert(bits));
+    }
+    check_one("Int128", Token::TypId("Int128"));
+    check_one("int7", Token::ExpId("int7"));
+    check_one("int8s", Token::ExpId("int8s"));
+    check_one("int", Token::Convert(""));
+}
+
+#[test]
+fn data_tokens() {
+    check_one("data", Token::Data);
+    check_one("newtype", Token::Newtype);
+    check_one("type", Token::Type);
+}
+
+#[test]
+fn module_tokens() {
+    check_one("signature", Token::Signature);
+    check_one("sig", Token::Sig);
+    check_one("val", Token::Val);
+    check_one("structure", Token::Structure);
+    check_one("struct", Token::Struct);
+    check_one("open", Token::Open);
+    check_one("opens", Token::ExpId("opens"));
+}
+
 #[test]
 fn infixes() {
+    let ops7 = ["/", "%", "<<", ">>", "land", "lor", "lxor"];
     let ops6 = ["+", "-"];
     let ops4 = ["<", ">", "==", "!="];
     let ops3 = ["&", "|"];
     check_one("*", Token::Mul);
     check_one("=", Token::Equal);
+    check_one("|>", Token::Pipe);
+    check_one("landing", Token::ExpId("landing"));
+    for input in ops7 {
+        check_one(input, Token::Infix7(input));
+    }
     for input in ops6 {
         check_one(input, Token::Infix6(input));
     }
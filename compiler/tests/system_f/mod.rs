@@ -1,4 +1,8 @@
+mod erase_test;
+mod intern_test;
 mod interp_test;
 mod lex_test;
+mod mono_test;
 mod parse_test;
+mod peval_test;
 mod semant_test;
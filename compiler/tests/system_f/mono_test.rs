@@ -0,0 +1,129 @@
+use polylamb::ast::ast::{Expr, Prog, RawExpr};
+use polylamb::ast::interp::{
+    builtin_environment, eval_datatype, eval_decl, eval_exception, eval_prog, Store, Strategy,
+};
+use polylamb::ast::lower::{lower_prog, Newtypes};
+use polylamb::ast::mono::monomorphize;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ast::semant::{builtin_context, Aliases};
+use polylamb::ir::anf::lower_checked;
+
+/// Polymorphic programs, and instances their monomorphizations have
+const POLYMORPHIC: &[(&str, &[&str])] = &[
+    (
+        r"let id : ∀ A. A -> A = λ x: A. x
+          let main : Int = if id [Bool] true then id [Int] 1 else 0",
+        &["id<Bool>", "id<Int>"],
+    ),
+    (
+        r"let pair : ∀ A. ∀ B. A -> B -> A * B = Λ A. Λ B. λ x: A. λ y: B. (x, y)
+          let swap : ∀ A. A * A -> A * A = Λ A. λ p: A * A. pair [A] [A] p.1 p.0
+          let main : Int = (swap [Int] (1, 2)).0 + (pair [Bool] [Int] true 3).1",
+        &["pair<Int, Int>", "pair<Bool, Int>", "swap<Int>"],
+    ),
+    (
+        r"let length : ∀ A. List A -> Int = Λ A.
+              fix len = λ (l: List A) : Int. case l of [] => 0; _ :: t => 1 + len t end in len
+          let nil : ∀ A. List A = Nil
+          let main : Int = length [Int] ([1, 2]) + length [Bool] (nil [Bool])",
+        &["length<Int>", "length<Bool>", "nil<Bool>"],
+    ),
+    (
+        r"let main : Int =
+              let f = λ x. x in
+              let g = λ y. (f y, f 1) in
+              (g true).1 + (Λ A. λ x: A. x) [Int] 2",
+        &[],
+    ),
+    (
+        r"class Size A { size: A -> Int }
+          instance Size Int { size = λ n. n }
+          let main : Int = size 4 * 2",
+        &["size<Int>"],
+    ),
+];
+
+/// Programs whose polymorphism is only known when running them
+const DYNAMIC: &[&str] = &[
+    "let apply : (∀ A. A -> A) -> Int = λ f: ∀ A. A -> A. f [Int] 1",
+    r"let id : ∀ A. A -> A = λ x: A. x
+      let ids : (∀ A. A -> A) * Int = (id, 1)",
+    r"let id : ∀ A. A -> A = λ x: A. x
+      let main : Int = let p = pack [Int] 1 as ∃ S. S in unpack [S] q = p in case id [S] q of _ => 0 end",
+];
+
+/** Evaluates the declarations of `prog`, type checked and lowered, and returns the value of `main` */
+fn eval_main(prog: &Prog) -> String {
+    let (mut ctxt, mut aliases, mut env) =
+        (builtin_context(), Aliases::new(), builtin_environment());
+    let mut newtypes = Newtypes::new();
+    for data in &prog.datatypes {
+        eval_datatype(data, &mut ctxt, &mut aliases, &mut env, &mut newtypes).unwrap()
+    }
+    for exn in &prog.exceptions {
+        eval_exception(exn, &mut ctxt, &aliases, &mut env).unwrap()
+    }
+    let mut store = Store::default();
+    for id in &prog.order {
+        let decl = &prog.declarations[id];
+        eval_decl(
+            decl,
+            &mut ctxt,
+            &mut env,
+            &newtypes,
+            &mut store,
+            Strategy::default(),
+        )
+        .unwrap();
+    }
+    env["main"].to_string()
+}
+
+/** Whether `expr` abstracts over types, or applies anything but constructors to types */
+fn polymorphic(expr: &Expr) -> bool {
+    match &expr.expr {
+        RawExpr::Any { .. } => true,
+        RawExpr::TApp { exp, .. } => match &exp.expr {
+            RawExpr::Var { id } => id.starts_with(char::is_lowercase),
+            _ => polymorphic(exp),
+        },
+        e => e.subexprs().into_iter().any(polymorphic),
+    }
+}
+
+#[test]
+fn test_monomorphization() {
+    for (src, instances) in POLYMORPHIC {
+        let prog = parse_prog(src).unwrap();
+        let expected = eval_prog(&prog).unwrap().value("main").unwrap().to_string();
+        let mono = monomorphize(&lower_prog(&prog).unwrap()).unwrap();
+        for id in &mono.order {
+            assert!(!polymorphic(&mono.declarations[id].body), "{id} in {src}");
+        }
+        let declared: Vec<&str> = mono.order.iter().map(String::as_str).collect();
+        for instance in *instances {
+            assert!(declared.contains(instance), "{instance} in {src}");
+        }
+        assert_eq!(eval_main(&mono), expected, "{src}");
+        assert!(lower_checked(&mono).decl("main").is_some(), "{src}");
+    }
+}
+
+#[test]
+fn test_dynamic_polymorphism() {
+    for src in DYNAMIC {
+        let prog = lower_prog(&parse_prog(src).unwrap()).expect(src);
+        let err = monomorphize(&prog).unwrap_err();
+        assert_eq!(err.title, "Cannot monomorphize", "{src}");
+    }
+    // Never type checked, since declarations can't refer to themselves
+    let prog = parse_prog(
+        r"let f : ∀ A. A -> Int = Λ A. λ x: A. f [A * A] (x, x)
+          let main : Int = f [Int] 1",
+    )
+    .unwrap();
+    assert_eq!(
+        monomorphize(&prog).unwrap_err().title,
+        "Polymorphic recursion"
+    );
+}
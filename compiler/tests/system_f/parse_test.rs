@@ -1,5 +1,11 @@
-use polylamb::ast::ast::{RawExpr, RawType};
-use polylamb::ast::parse::{parse_decl, parse_expr, parse_type};
+use polylamb::ast::ast::{
+    Binary, Expr, Kind, Pattern, RawExpr, RawPattern, RawType, Span, Type, Width,
+};
+use polylamb::ast::class::dictionary_param;
+use polylamb::ast::parse::{
+    parse_class, parse_data, parse_decl, parse_expr, parse_instance, parse_prog, parse_signature,
+    parse_structure, parse_type,
+};
 
 const LITERALS: &[&str] = &["1", "-123", "true", "false", "null", "1048576", "0", "-0"];
 
@@ -15,6 +21,10 @@ const BINOPS: &[&str] = &[
     "null & (if true then false else null)",
     "1 + (lambda (x: Int). x) 2",
     "true * (lambda (x:A) (y:B) (z: C). y)",
+    "a / b % c",
+    "n - n / 2 * 2 == n % 2",
+    "x land 255 lor y << 8",
+    "(a >> 1) lxor a",
 ];
 
 const IFS: &[&str] = &[
@@ -31,6 +41,80 @@ const FIX: &[&str] = &[
     "fix fib = λ (x : Int) : Int. if x > 0 | x < 0 then 1 else (fib (x - 1)) + (fib (x - 2)) in fib"
 ];
 
+const CASES: &[&str] = &[
+    "case n of 0 => 1; _ => n end",
+    "case (b, 2) of (true, x) => x; (false, 0) => 1; _ => 2 end",
+    "case null of null => case x of y => y end end",
+];
+
+const RECORDS: &[&str] = &[
+    "{x = 1, y = true}",
+    "{p = (1, 2), f = λ x: Int. x}",
+    "{inner = {a = null}}",
+];
+
+const PROJS: &[&str] = &["r.x", "{x = 1}.x", "(f r).inner.a"];
+
+const NTHS: &[&str] = &["p.0", "(1, (2, 3)).1.0", "(f p).1"];
+
+const FOLDS: &[&str] = &[
+    "fold [rec S. Int * S] (1, s)",
+    "fold [μ L. Option (Int * L)] (None [Int * (μ L. Option (Int * L))])",
+    "unfold s",
+    "unfold (unfold s)",
+];
+
+const REFS: &[&str] = &["ref 0", "!r", "r := !r + 1", "!(f r)", "ref (λ x: Int. x)"];
+
+const FUTURES: &[&str] = &["spawn (fib 30)", "join t", "join (spawn 1)"];
+
+const ARRAYS: &[&str] = &[
+    "array(3, 0)",
+    "sub(a, i + 1)",
+    "update(a, 0, sub(a, 1))",
+    "array(n, array(n, false))",
+];
+
+const LISTS: &[&str] = &[
+    "[]",
+    "[1, 2, 3]",
+    "1 :: 2 :: []",
+    "x + 1 :: xs",
+    "[[true], []]",
+    "case l of [] => 0; [x] => x; x :: y :: _ => x + y end",
+    "f ([1]) [Int]",
+    "f []",
+    "build 10 [] [Int]",
+];
+
+const ASCRIPTIONS: &[&str] = &[
+    "(1 : Int)",
+    "(f x : Int -> Int) 1",
+    "((x, y) : Int * Bool)",
+    "λ x: Bool. (x : Bool)",
+];
+
+const CONVERSIONS: &[&str] = &[
+    "int8(x)",
+    "int64(x + 1) * 2",
+    "f int16(y)",
+    "int32((1 : Int8))",
+    "int(int8(x))",
+];
+
+const EXNS: &[&str] = &[
+    "raise Empty",
+    "raise [Int] (Fail 1)",
+    "f x handle Fail n => n + 1",
+    "(raise Empty handle Empty => 0) handle _ => 1",
+];
+
+const PACKS: &[&str] = &[
+    "pack [Int] (1, λ x: Int. x > 0) as ∃ S. S * (S -> Bool)",
+    "unpack [S] p = c in let (s, f) = p in f s",
+    "unpack [S] p = (pack [Bool] true as exists T. T) in 1",
+];
+
 const DECLS: &[&str] = &[
     "let x: Int = 1",
     "let xx: Hehe_2_w = (true & false + -1048)",
@@ -38,6 +122,24 @@ const DECLS: &[&str] = &[
     "let fib: Int -> Int = (fix fib = λ (x : Int) : Int . if x == 0 | x == 1 then 1 else (fib (x - 1)) + (fib (x - 2)) in fib)"
 ];
 
+const ALIASES: &[&str] = &[
+    "type Predicate = Int -> Bool
+     let pos: Predicate = λ x: Int. x > 0",
+    "type Pair = Int * Int
+     type Swap = Pair -> Pair
+     let swap: Swap = λ p: Pair. let (a, b) = p in (b, a)",
+];
+
+const DATATYPES: &[&str] = &[
+    "data Bit = Zero | One
+     let b: Bit = One",
+    "data Option A = None | Some A
+     let x: Option Int = Some [Int] 1",
+    "data List A = Nil | Cons A (List A)
+     let len: List Int -> Int = fix len = λ (l: List Int) : Int.
+       case l of Nil => 0; Cons _ tl => 1 + len tl end in len",
+];
+
 const TYPE_VARS: &[&str] = &[
     "A",
     "B",
@@ -68,6 +170,15 @@ macro_rules! assert_matches {
     };
 }
 
+/// Types with type operators and kinded binders
+const OPERATORS: &[&str] = &[
+    "λ A. A * A",
+    "(λ A. A * A) Int",
+    "∀ (F: * -> *). F Int -> F Int",
+    "λ (F: (* -> *) -> *) (G: * -> *). F G",
+    "∀ (P: * -> * -> *). P Int Bool",
+];
+
 #[test]
 fn check_exprs() {
     for s in LITERALS {
@@ -90,6 +201,188 @@ fn check_exprs() {
         println!("{}", exp);
         assert_matches!(exp, RawExpr::Fix { .. })
     }
+    for s in CASES {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(exp, RawExpr::Case { .. })
+    }
+    for s in RECORDS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(exp, RawExpr::Record { .. })
+    }
+    for s in PROJS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(exp, RawExpr::Proj { .. })
+    }
+    for s in NTHS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(exp, RawExpr::Nth { .. })
+    }
+    assert!(parse_expr("p.-1").is_err());
+    for s in FOLDS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(exp, RawExpr::Fold { .. } | RawExpr::Unfold { .. })
+    }
+    for s in PACKS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(exp, RawExpr::Pack { .. } | RawExpr::Unpack { .. })
+    }
+    for s in REFS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(
+            exp,
+            RawExpr::Ref { .. } | RawExpr::Deref { .. } | RawExpr::Assign { .. }
+        )
+    }
+    assert_matches!(
+        raw_expr_of("assertEq(f 1, (2, 3))"),
+        RawExpr::AssertEq { .. }
+    );
+    for s in FUTURES {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(exp, RawExpr::Spawn { .. } | RawExpr::Join { .. })
+    }
+    for s in ARRAYS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(
+            exp,
+            RawExpr::Array { .. } | RawExpr::Sub { .. } | RawExpr::Update { .. }
+        )
+    }
+    // Array primitives are atoms
+    assert_matches!(raw_expr_of("f sub(a, 0)"), RawExpr::EApp { .. });
+    for s in EXNS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(exp, RawExpr::Raise { .. } | RawExpr::Handle { .. })
+    }
+    for s in ASCRIPTIONS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(
+            exp,
+            RawExpr::Ascribe { .. } | RawExpr::EApp { .. } | RawExpr::Lambda { .. }
+        )
+    }
+    for s in CONVERSIONS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(
+            exp,
+            RawExpr::Convert { .. } | RawExpr::Binop { .. } | RawExpr::EApp { .. }
+        )
+    }
+    for s in LISTS {
+        let exp = raw_expr_of(s);
+        println!("{}", exp);
+        assert_matches!(
+            exp,
+            RawExpr::EApp { .. }
+                | RawExpr::Var { .. }
+                | RawExpr::Case { .. }
+                | RawExpr::TApp { .. }
+        )
+    }
+    // `::` is right associative, binding looser than arithmetic and tighter than comparisons
+    let cons = |s| match raw_expr_of(s) {
+        RawExpr::EApp { exp, arg } => match exp.expr {
+            RawExpr::EApp { exp, arg: head } => {
+                assert_eq!(
+                    exp.expr,
+                    RawExpr::Var {
+                        id: "Cons".to_string()
+                    }
+                );
+                (head.expr, arg.expr)
+            }
+            e => panic!("{e}"),
+        },
+        e => panic!("{e}"),
+    };
+    assert_matches!(
+        cons("1 + 2 :: xs"),
+        (RawExpr::Binop { .. }, RawExpr::Var { .. })
+    );
+    assert_matches!(
+        cons("1 :: 2 :: xs"),
+        (RawExpr::Con { .. }, RawExpr::EApp { .. })
+    );
+    assert_matches!(raw_expr_of("xs == 1 :: ys"), RawExpr::Binop { .. });
+    assert!(parse_expr("f [1]").is_err());
+    // The empty list is an argument like any atom
+    let arg = |exp| match exp {
+        RawExpr::EApp { arg, .. } => arg.expr,
+        e => panic!("{e}"),
+    };
+    let nil = RawExpr::Var {
+        id: "Nil".to_string(),
+    };
+    assert_eq!(arg(raw_expr_of("f []")), nil);
+    assert_matches!(arg(raw_expr_of("f [] 1")), RawExpr::Con { .. });
+    assert!(parse_expr("f [[]]").is_err());
+    // Dereferencing binds tighter than arithmetic
+    assert_matches!(raw_expr_of("!r + 1"), RawExpr::Binop { .. });
+    // Division and modulo bind like multiplication, to the left
+    assert_matches!(
+        raw_expr_of("a + b / c"),
+        RawExpr::Binop {
+            op: Binary::Add,
+            ..
+        }
+    );
+    assert_matches!(
+        raw_expr_of("a / b * c"),
+        RawExpr::Binop {
+            op: Binary::Mul,
+            ..
+        }
+    );
+    // So do bitwise operators, unlike in C
+    assert_matches!(
+        raw_expr_of("1 << n - 1"),
+        RawExpr::Binop {
+            op: Binary::Sub,
+            ..
+        }
+    );
+    assert_matches!(
+        raw_expr_of("x land 1 == 0"),
+        RawExpr::Binop { op: Binary::Eq, .. }
+    );
+    // Bare arguments are left for inference
+    assert_matches!(
+        raw_expr_of("λ x y. x"),
+        RawExpr::Lambda {
+            arg: (
+                _,
+                Type {
+                    typ: RawType::Hole,
+                    span: None
+                }
+            ),
+            ..
+        }
+    );
+    // Projection binds tighter than application
+    assert_matches!(raw_expr_of("f r.x"), RawExpr::EApp { .. });
+    assert_matches!(
+        raw_expr_of("let {x = a, y = _} = r in a"),
+        RawExpr::Let {
+            pat: Pattern {
+                pat: RawPattern::Record(_),
+                ..
+            },
+            ..
+        }
+    )
 }
 
 #[test]
@@ -99,6 +392,36 @@ fn check_types() {
         println!("{}", typ);
         assert_matches!(typ, RawType::TVar(..))
     }
+    assert_matches!(raw_type_of("{x: Int, f: Int -> Bool}"), RawType::Record(..));
+    assert_matches!(raw_type_of("rec L. Unit * L"), RawType::Rec(..));
+    assert_matches!(raw_type_of("∃ S. S -> Int"), RawType::Exists(..));
+    assert_matches!(raw_type_of("Ref (Int * Int)"), RawType::Ref(..));
+    assert_matches!(raw_type_of("Array (Array Int)"), RawType::Array(..));
+    assert_matches!(raw_type_of("Future (Int -> Int)"), RawType::Future(..));
+    assert_matches!(raw_type_of("Int8"), RawType::Sized(Width::W8));
+    assert_matches!(raw_type_of("Int32 -> Int16"), RawType::Arrow(..));
+    // 64 bit integers are the default ones
+    assert_matches!(raw_type_of("Int64"), RawType::Sized(Width::W64));
+    assert_matches!(raw_type_of("λ A. A * A"), RawType::Lam(_, Kind::Star, _));
+    assert_matches!(raw_type_of("(λ A. A) Int"), RawType::App(..));
+    assert_matches!(
+        raw_type_of("∀ (F: * -> *). F Int"),
+        RawType::Forall(_, Kind::Arrow(..), _)
+    );
+    assert_matches!(
+        raw_expr_of("any (F: * -> *) A. λ x: F A. x"),
+        RawExpr::Any {
+            kind: Kind::Arrow(..),
+            ..
+        }
+    );
+    // Printing keeps the kinds of binders that aren't `*`
+    colored::control::set_override(false);
+    for t in OPERATORS {
+        let first_print = raw_type_of(t).to_string();
+        println!("{}", first_print);
+        assert_eq!(raw_type_of(&first_print).to_string(), first_print)
+    }
 }
 
 #[test]
@@ -106,6 +429,138 @@ fn check_decls() {
     for s in DECLS {
         assert_matches!(parse_decl(s), Result::Ok(..))
     }
+    let decl = parse_decl("let id = λ x. x").unwrap();
+    assert_matches!(decl.sig.typ, RawType::Hole);
+}
+
+#[test]
+fn check_doc_comments() {
+    let decl = parse_decl("(** Adds one *) let succ: Int -> Int = λ x: Int. x + 1").unwrap();
+    assert_eq!(decl.doc.as_deref(), Some("Adds one"));
+    let decl = parse_decl("(** First *) (** Second *) let x: Int = 1").unwrap();
+    assert_eq!(decl.doc.as_deref(), Some("First\nSecond"));
+    let decl = parse_decl("let x: Int = 1").unwrap();
+    assert_eq!(decl.doc, None);
+    let prog =
+        parse_prog("(** Unary predicates *) type Pred = Int -> Bool let x: Int = 1").unwrap();
+    assert_eq!(prog.aliases[0].doc.as_deref(), Some("Unary predicates"));
+}
+
+/// Pairs of pipelines and the applications they desugar to
+const PIPES: &[(&str, &str)] = &[
+    ("x |> f", "f x"),
+    ("x |> f |> g", "g (f x)"),
+    ("1 + 2 |> f", "f (1 + 2)"),
+    ("x < y |> not", "not (x < y)"),
+    ("a |> f & b", "(f a) & b"),
+    ("x |> f y", "f y x"),
+];
+
+#[test]
+fn check_pipes() {
+    for (pipe, app) in PIPES {
+        assert_eq!(raw_expr_of(pipe).to_string(), raw_expr_of(app).to_string())
+    }
+}
+
+#[test]
+fn check_attributes() {
+    let decl = parse_decl("@[inline] let succ: Int -> Int = λ x: Int. x + 1").unwrap();
+    assert!(decl.has_attr("inline"));
+    assert!(!decl.has_attr("no_check"));
+    let decl = parse_decl("(** Docs *) @[inline, no_check] @[test] let x: Int = 1").unwrap();
+    let names: Vec<&str> = decl.attrs.iter().map(|a| a.name.as_str()).collect();
+    assert_eq!(names, ["inline", "no_check", "test"]);
+    assert_eq!(decl.doc.as_deref(), Some("Docs"));
+    assert!(parse_decl("@[] let x: Int = 1").is_err());
+}
+
+#[test]
+fn check_datatypes() {
+    for s in DATATYPES {
+        let prog = parse_prog(s).unwrap();
+        assert_eq!(prog.datatypes.len(), 1);
+        assert_eq!(prog.order.len(), 1)
+    }
+    let data = parse_data("data Either A B = Left A | Right B").unwrap();
+    assert_eq!(data.params.len(), 2);
+    assert_eq!(data.ctors.len(), 2);
+    let data = parse_data("data Option A = None | Some A deriving (Eq, Show)").unwrap();
+    assert_eq!(data.ctors[1].1.len(), 1);
+    let classes: Vec<_> = data.deriving.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(classes, ["Eq", "Show"]);
+    let data = parse_data("newtype Wrap A = Wrap (A * Int)").unwrap();
+    assert!(data.newtype);
+    assert_eq!(data.ctors[0].1.len(), 1);
+    parse_data("newtype Pair = Pair Int Int").unwrap_err();
+    assert_eq!(
+        parse_type("List (Option A) -> Int").unwrap().to_string(),
+        "(List (Option A)) -> Int"
+    );
+}
+
+#[test]
+fn check_classes() {
+    let class = parse_class("class Monoid A { empty: A, combine: A -> A -> A }").unwrap();
+    assert_eq!(class.param.name, "A");
+    assert_eq!(class.methods.len(), 2);
+    let inst = parse_instance("instance Monoid (Int * Int) { empty = (0, 0) }").unwrap();
+    assert_eq!(inst.class.name, "Monoid");
+    assert_matches!(inst.typ.typ, RawType::Prod(_));
+    let prog = parse_prog(
+        "class Eq A { eq: A -> A -> Bool } instance Eq Int { eq = λ x y. x == y } let x: Bool = eq 1 1",
+    )
+    .unwrap();
+    assert_eq!((prog.classes.len(), prog.instances.len()), (1, 1));
+    assert_eq!(prog.order.len(), 1);
+    assert!(parse_class("class Eq { eq: Int }").is_err());
+    // Constraints are dictionary parameters
+    assert_eq!(
+        parse_type("∀ (A: Eq, Show). A").unwrap().to_string(),
+        parse_type("∀ A. Eq A -> Show A -> A").unwrap().to_string()
+    );
+    let RawExpr::Any { body, .. } = parse_expr("Λ (A: Eq). λ x: A. x").unwrap().expr else {
+        panic!("expected a type abstraction")
+    };
+    let RawExpr::Lambda { arg: (d, _), .. } = body.expr else {
+        panic!("expected the dictionary parameter")
+    };
+    assert_eq!(d.name, dictionary_param("Eq", "A"));
+}
+
+#[test]
+fn check_aliases() {
+    for s in ALIASES {
+        let prog = parse_prog(s).unwrap();
+        assert!(!prog.aliases.is_empty());
+        assert_eq!(prog.order.len(), 1)
+    }
+}
+
+#[test]
+fn check_modules() {
+    let sig =
+        parse_signature("signature SET = sig type S val empty: S val mem: Int -> S -> Bool end")
+            .unwrap();
+    assert_eq!(sig.id.name, "SET");
+    assert_eq!(sig.types.len(), 1);
+    assert_eq!(sig.vals.len(), 2);
+    let module = parse_structure(
+        "structure Set : SET = struct type S = List Int let empty = Nil [Int] let mem: Int -> S -> Bool = λ n s. false end",
+    )
+    .unwrap();
+    assert_eq!(module.sig.unwrap().name, "SET");
+    assert_eq!((module.types.len(), module.decls.len()), (1, 2));
+    let module = parse_structure("structure M = struct end").unwrap();
+    assert!(module.sig.is_none() && module.decls.is_empty());
+    let prog = parse_prog(
+        "signature S = sig val x: Int end structure M : S = struct let x = 1 end let y: Int = open M in x",
+    )
+    .unwrap();
+    assert_eq!((prog.signatures.len(), prog.structures.len()), (1, 1));
+    assert_eq!(prog.order, ["M", "y"]);
+    assert_matches!(raw_expr_of("open m in x + 1"), RawExpr::Open { .. });
+    assert!(parse_signature("signature S = sig val x: Int type T end").is_err());
 }
 
 // Check pretty-printing emits the same AST when parsed back
@@ -113,7 +568,21 @@ fn check_decls() {
 fn check_pretty_print() {
     // Disable color printing, otherwise can't test for string equality
     colored::control::set_override(false);
-    for s in LITERALS.iter().chain(BINOPS).chain(IFS).chain(FIX) {
+    for s in LITERALS
+        .iter()
+        .chain(BINOPS)
+        .chain(IFS)
+        .chain(FIX)
+        .chain(CASES)
+        .chain(RECORDS)
+        .chain(PROJS)
+        .chain(NTHS)
+        .chain(FOLDS)
+        .chain(PACKS)
+        .chain(LISTS)
+        .chain(ASCRIPTIONS)
+        .chain(CONVERSIONS)
+    {
         println!("{}", s);
         let first_parse = raw_expr_of(s);
         let first_print = first_parse.to_string();
@@ -123,3 +592,236 @@ fn check_pretty_print() {
         assert_eq!(first_print, second_print)
     }
 }
+
+/// Asserts that `span` exists and lies within `outer`
+fn assert_within(span: Option<Span>, outer: Span) -> Span {
+    let (l, r) = span.expect("missing span");
+    assert!(outer.0 <= l && l <= r && r <= outer.1);
+    (l, r)
+}
+
+fn check_type_spans(typ: &Type, outer: Span) {
+    let span = assert_within(typ.span, outer);
+    match &typ.typ {
+        RawType::Prod(ts) => ts.iter().for_each(|t| check_type_spans(t, span)),
+        RawType::Arrow(t1, t2) => {
+            check_type_spans(t1, span);
+            check_type_spans(t2, span)
+        }
+        RawType::Forall(v, _, t)
+        | RawType::Lam(v, _, t)
+        | RawType::Rec(v, t)
+        | RawType::Exists(v, t) => {
+            assert_within(v.span, span);
+            check_type_spans(t, span)
+        }
+        RawType::App(t1, t2) => {
+            check_type_spans(t1, span);
+            check_type_spans(t2, span)
+        }
+        RawType::Ref(t) | RawType::Array(t) | RawType::Future(t) => check_type_spans(t, span),
+        RawType::Record(fields) => {
+            for (l, t) in fields {
+                assert_within(l.span, span);
+                check_type_spans(t, span)
+            }
+        }
+        _ => (),
+    }
+}
+
+fn check_pattern_spans(pat: &Pattern, outer: Span) {
+    let span = assert_within(pat.span, outer);
+    match &pat.pat {
+        RawPattern::Tuple(ps) => ps.iter().for_each(|p| check_pattern_spans(p, span)),
+        RawPattern::Binding(v) => {
+            assert_within(v.span, span);
+        }
+        RawPattern::Record(fields) => {
+            for (l, p) in fields {
+                assert_within(l.span, span);
+                check_pattern_spans(p, span)
+            }
+        }
+        _ => (),
+    }
+}
+
+fn check_expr_spans(expr: &Expr, outer: Span) {
+    use RawExpr::*;
+    let span = assert_within(expr.span, outer);
+    match &expr.expr {
+        Let { pat, exp, body } => {
+            check_pattern_spans(pat, span);
+            check_expr_spans(exp, span);
+            check_expr_spans(body, span)
+        }
+        Fix { funcs, body } => {
+            for (f, v, t1, t2, e) in funcs {
+                assert_within(f.span, span);
+                assert_within(v.span, span);
+                check_type_spans(t1, span);
+                check_type_spans(t2, span);
+                check_expr_spans(e, span)
+            }
+            check_expr_spans(body, span)
+        }
+        EApp { exp, arg } => {
+            check_expr_spans(exp, span);
+            check_expr_spans(arg, span)
+        }
+        TApp { exp, arg } => {
+            check_expr_spans(exp, span);
+            check_type_spans(arg, span)
+        }
+        Tuple { entries } => entries.iter().for_each(|e| check_expr_spans(e, span)),
+        Binop { lhs, rhs, .. } => {
+            check_expr_spans(lhs, span);
+            check_expr_spans(rhs, span)
+        }
+        Lambda { arg: (v, t), body } => {
+            assert_within(v.span, span);
+            check_type_spans(t, span);
+            check_expr_spans(body, span)
+        }
+        Any { arg, body, .. } => {
+            assert_within(arg.span, span);
+            check_expr_spans(body, span)
+        }
+        If {
+            cond,
+            branch_t,
+            branch_f,
+        } => {
+            check_expr_spans(cond, span);
+            check_expr_spans(branch_t, span);
+            check_expr_spans(branch_f, span)
+        }
+        Case { exp, arms } => {
+            check_expr_spans(exp, span);
+            for (p, e) in arms {
+                check_pattern_spans(p, span);
+                check_expr_spans(e, span)
+            }
+        }
+        Record { fields } => {
+            for (l, e) in fields {
+                assert_within(l.span, span);
+                check_expr_spans(e, span)
+            }
+        }
+        Proj { exp, field } => {
+            check_expr_spans(exp, span);
+            assert_within(field.span, span);
+        }
+        Fold { typ, exp } | Ascribe { exp, typ } => {
+            check_type_spans(typ, span);
+            check_expr_spans(exp, span)
+        }
+        Unfold { exp }
+        | Nth { exp, .. }
+        | Ref { exp }
+        | Deref { exp }
+        | Spawn { exp }
+        | Join { exp }
+        | Convert { exp, .. } => check_expr_spans(exp, span),
+        Raise { exp, typ } => {
+            check_expr_spans(exp, span);
+            // An omitted type is a hole with nothing in the source to point at
+            if typ.span.is_some() {
+                check_type_spans(typ, span)
+            }
+        }
+        Handle { exp, pat, handler } => {
+            check_expr_spans(exp, span);
+            check_pattern_spans(pat, span);
+            check_expr_spans(handler, span)
+        }
+        Assign { lhs, rhs } | AssertEq { lhs, rhs } => {
+            check_expr_spans(lhs, span);
+            check_expr_spans(rhs, span)
+        }
+        Array { len, init } => {
+            check_expr_spans(len, span);
+            check_expr_spans(init, span)
+        }
+        Sub { arr, idx } => {
+            check_expr_spans(arr, span);
+            check_expr_spans(idx, span)
+        }
+        Update { arr, idx, val } => {
+            check_expr_spans(arr, span);
+            check_expr_spans(idx, span);
+            check_expr_spans(val, span)
+        }
+        Pack { witness, exp, typ } => {
+            check_type_spans(witness, span);
+            check_expr_spans(exp, span);
+            check_type_spans(typ, span)
+        }
+        Unpack {
+            tvar,
+            var,
+            exp,
+            body,
+        } => {
+            assert_within(tvar.span, span);
+            assert_within(var.span, span);
+            check_expr_spans(exp, span);
+            check_expr_spans(body, span)
+        }
+        Open { exp, body } => {
+            check_expr_spans(exp, span);
+            check_expr_spans(body, span)
+        }
+        Con { .. } | Var { .. } | Instance { .. } => (),
+    }
+}
+
+#[test]
+fn check_spans() {
+    let nested = [
+        "λ (x: Int) (y: Bool * Int). (x, y)",
+        "any A B. λ (a: A) (b: B). (b, a)",
+        "let (x, _) = (1, true) in case x of 0 => x; n => n |> f end",
+        "λ r: {x: Int, y: Bool}. let {y = b} = r in {z = r.x, w = b}",
+        "λ s: rec S. Int * S. fold [rec S. Int * S] (1, unfold s)",
+        "unpack [S] p = pack [Int] (1, 2) as ∃ S. S * Int in let (_, n) = p in n",
+        "open m in get zero",
+    ];
+    for s in LITERALS
+        .iter()
+        .chain(BINOPS)
+        .chain(IFS)
+        .chain(FIX)
+        .chain(CASES)
+        .chain(REFS)
+        .chain(EXNS)
+        .chain(LISTS)
+        .chain(ASCRIPTIONS)
+        .chain(CONVERSIONS)
+        .chain(NTHS)
+        .chain(nested.iter())
+    {
+        check_expr_spans(&parse_expr(s).unwrap(), (0, s.len()))
+    }
+    for s in DECLS {
+        let decl = parse_decl(s).unwrap();
+        assert_eq!(decl.span, Some((0, s.len())));
+        check_type_spans(&decl.sig, (0, s.len()));
+        check_expr_spans(&decl.body, (0, s.len()))
+    }
+    // Curried binders start at their own argument
+    let s = "λ (x: Int) (y: Int). x";
+    let exp = parse_expr(s).unwrap();
+    assert_eq!(exp.span, Some((0, s.len())));
+    if let RawExpr::Lambda { body, .. } = &exp.expr {
+        assert_eq!(body.span, Some((s.find("(y").unwrap(), s.len())))
+    }
+}
+
+#[test]
+fn check_any_binder_order() {
+    let exp = raw_expr_of("any A B. λ (a: A). a");
+    assert!(matches!(exp, RawExpr::Any { arg, .. } if arg.name == "A"))
+}
@@ -0,0 +1,36 @@
+use polylamb::ast::lower::lower_prog;
+use polylamb::ast::parse::parse_prog;
+use polylamb::ast::peval::{peval, FUEL};
+
+/// Declarations, and what they're evaluated to at compile time
+const EVALUATED: &[(&str, &str)] = &[
+    ("let n : Int = 6 * 7", "42"),
+    ("let p : Int * Int = (n + 1, n - 1)", "(43, 41)"),
+    (
+        "let xs : List Int = Cons [Int] p.0 (Nil [Int])",
+        "(Cons[Int]) 43 (Nil[Int])",
+    ),
+    ("let f : Int -> Int = λ x: Int. x * n", ""),
+    ("let y : Int = f 2", ""),
+    ("let r : Ref Int = ref 1", ""),
+    ("let z : Int = 1 / 0", ""),
+    (
+        "let w : Int = fix loop = λ (k: Int) : Int. loop k in loop 0",
+        "",
+    ),
+];
+
+#[test]
+fn test_partial_evaluation() {
+    let srcs: Vec<_> = EVALUATED.iter().map(|(src, _)| *src).collect();
+    let prog = lower_prog(&parse_prog(&srcs.join("\n\n")).unwrap()).unwrap();
+    let evaluated = peval(&prog, FUEL, |_| true);
+    for (src, val) in EVALUATED {
+        let id = src.split_whitespace().nth(1).unwrap();
+        let (before, after) = (&prog.declarations[id], &evaluated.declarations[id]);
+        match *val {
+            "" => assert_eq!(after, before, "{src}"),
+            val => assert_eq!(after.body.to_string(), val, "{src}"),
+        }
+    }
+}
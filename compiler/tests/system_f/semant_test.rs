@@ -1,6 +1,10 @@
 use polylamb::ast::ast::Expr;
-use polylamb::ast::parse::{parse_expr, parse_type};
-use polylamb::ast::semant::{check_closed_expr, equivalent};
+use polylamb::ast::intern::Interner;
+use polylamb::ast::parse::{parse_alias, parse_decl, parse_expr, parse_prog, parse_type};
+use polylamb::ast::semant::{
+    alpha_equivalent, check_against, check_closed_expr, check_decl, check_prog, define_alias,
+    define_datatype, equivalent, fold, prog_aliases, with_prelude,
+};
 
 const EQUIVALENT_NEGATIVE: &[(&str, &str)] = &[
     ("X", "Y"),
@@ -9,6 +13,34 @@ const EQUIVALENT_NEGATIVE: &[(&str, &str)] = &[
     ("∀ X. ∀ Y. X", "∀ T1. ∀ T2. T2"),
     ("∀ X. X -> X", "∀ Y. Y -> Unit"),
     ("∀ Z. Z", "∀ TypVar. Typ"),
+    ("∀ A. ∀ B. A -> B", "∀ B. ∀ A. A -> B"),
+    ("∀ A. A -> B", "∀ B. B -> B"),
+    ("Int * Int", "Int * Int * Int"),
+];
+
+/// Types equal up to the names of their bound type variables
+const EQUIVALENT_ALPHA: &[(&str, &str)] = &[
+    ("∀ A. A -> A", "∀ B. B -> B"),
+    ("∀ X. ∀ Y. X", "∀ T1. ∀ T2. T1"),
+    ("∀ A. ∀ A. A", "∀ B. ∀ C. C"),
+    ("∃ S. S * (S -> Int)", "∃ T. T * (T -> Int)"),
+    ("rec L. Unit * L", "μ T. Unit * T"),
+    (
+        "∀ (F: * -> *). F Int -> F Int",
+        "∀ (G: * -> *). G Int -> G Int",
+    ),
+    ("λ A. A -> B", "λ C. C -> B"),
+];
+
+/// Types equal after applying their type operators
+const EQUIVALENT_BETA: &[(&str, &str)] = &[
+    ("(λ A. A * A) Int", "Int * Int"),
+    ("(λ (F: * -> *). F Int) (λ A. A -> A)", "Int -> Int"),
+    ("∀ B. (λ A. A -> B) Bool", "∀ B. Bool -> B"),
+    (
+        "∀ B. (λ (F: * -> *). ∀ C. F C) (λ A. A -> B)",
+        "∀ B. ∀ C. C -> B",
+    ),
 ];
 
 /// Pairs of (binop, type) strings
@@ -17,6 +49,11 @@ const BINOPS: &[(&str, &str)] = &[
     ("2 < (3 * 4 + 5 * 7)", "Bool"),
     ("true & false", "Bool"),
     ("true & (if false then 1 < 2 else 10 == 10)", "Bool"),
+    ("7 / 2 % 3 < 1", "Bool"),
+    (
+        "λ x: Int. x land 1 lor (x >> 2) lxor (x << 3)",
+        "Int -> Int",
+    ),
 ];
 
 /// Pairs of (any, type) strings
@@ -33,6 +70,34 @@ const ANYS: &[(&str, &str)] = &[
     ("(any X. Λ Y. λ (y: Y). y) [Int] [Bool]", "Bool -> Bool"),
 ];
 
+/// Pairs of (expression, type) strings, with Λ binding type variables bound outside again
+const SHADOWING: &[(&str, &str)] = &[
+    ("(Λ A. Λ A. λ x: A. x) [Int] [Bool]", "Bool -> Bool"),
+    ("(Λ A. λ x: A. Λ A. λ y: A. x) [Int] 1 [Bool] true", "Int"),
+    (
+        "(Λ A. λ x: A. Λ A. λ y: A. x) [Int]",
+        "Int -> (∀ B. B -> Int)",
+    ),
+    (
+        "Λ A. λ x: A. Λ A. λ y: A. (x, y)",
+        "∀ A. A -> (∀ B. B -> (A * B))",
+    ),
+    (
+        "Λ A. (Λ B. Λ A. λ (x: B) (y: A). x) [A]",
+        "∀ A. ∀ B. A -> B -> A",
+    ),
+    (
+        "Λ A. λ x: A. (λ g: ∀ B. B -> A. g [Bool] true) (Λ A. λ y: A. x)",
+        "∀ A. A -> A",
+    ),
+];
+
+/// Expressions that only check if Λ captured type variables bound outside
+const SHADOWING_NEG: &[&str] = &[
+    "Λ A. λ x: A. (λ g: ∀ B. B -> B. g) (Λ A. λ y: A. x)",
+    "(Λ A. λ x: A. Λ A. λ y: A. x) [Int] 1 [Bool] true & true",
+];
+
 /// Pairs of (λ, type) strings
 const LAMBDAS: &[(&str, &str)] = &[
     ("λ (a: Int). 0", "Int -> Int"),
@@ -49,25 +114,657 @@ const TUPLES: &[(&str, &str)] = &[
         "(Int -> Int) * (Bool -> Int)",
     ),
     ("(69, Λ A. λ (a: A). a)", "Int * (forall A. A -> A)"),
+    ("(1, (true, null)).1.0", "Bool"),
+    (
+        "λ p: Int * Bool. if p.1 then p.0 else 0",
+        "Int * Bool -> Int",
+    ),
     (
         "(λ (x: Int). x + 1, λ (x: Bool). if x then 1 else 0)",
         "(Int -> Int) * (Bool -> Int)",
     ),
 ];
 
+/// Pairs of (case, type) strings
+const CASES: &[(&str, &str)] = &[
+    ("case 3 of 0 => true; n => n > 1 end", "Bool"),
+    (
+        "case (1, true) of (0, b) => b; (_, true) => false; (_, false) => true end",
+        "Bool",
+    ),
+    ("case null of null => 1 end", "Int"),
+    (
+        "λ (p: Bool * Bool). case p of (true, true) => 0; (false, _) => 1; (_, false) => 2 end",
+        "Bool * Bool -> Int",
+    ),
+];
+
+/// Pairs of (record, type) strings
+const RECORDS: &[(&str, &str)] = &[
+    ("{x = 1, y = true}", "{x: Int, y: Bool}"),
+    ("{x = 1, y = true}", "{y: Bool, x: Int}"),
+    ("{x = 1, y = true}.y", "Bool"),
+    (
+        "λ r: {a: Int, b: Int}. let {a = x} = r in x + r.b",
+        "{b: Int, a: Int} -> Int",
+    ),
+    (
+        "case {p = (1, false), q = null} of {p = (0, _)} => 0; {p = (_, b)} => 1 end",
+        "Int",
+    ),
+];
+
+/// Pairs of (fold or unfold, type) strings
+const FOLDS: &[(&str, &str)] = &[
+    (
+        "λ s: rec S. Int * S. unfold s",
+        "(rec S. Int * S) -> Int * (rec S. Int * S)",
+    ),
+    (
+        "λ s: rec S. Int * S. fold [rec S. Int * S] (0, s)",
+        "(rec S. Int * S) -> (rec S. Int * S)",
+    ),
+    ("λ f: μ F. F -> Int. (unfold f) f", "(μ F. F -> Int) -> Int"),
+];
+
+/// Pairs of (pack or unpack, type) strings
+const PACKS: &[(&str, &str)] = &[
+    (
+        "pack [Int] (1, λ x: Int. x + 1) as ∃ S. S * (S -> S)",
+        "∃ S. S * (S -> S)",
+    ),
+    (
+        "unpack [C] c = pack [Int] (0, λ x: Int. x > 0) as ∃ S. S * (S -> Bool) in
+         let (s, f) = c in f s",
+        "Bool",
+    ),
+    (
+        "λ c: ∃ S. S * (S -> Int). unpack [T] p = c in let (s, f) = p in f s",
+        "(∃ S. S * (S -> Int)) -> Int",
+    ),
+];
+
 /// Negative tests form binary expressions
 const BINOP_NEG: &[&str] = &[
     "2 + (if 3 then 4 else 5)",
     "2 + false",
     "true & (if false then 1048576 else -42069)",
     "true - 3 == 1",
+    "1 / true",
+    "false % 2",
+    "true land false",
+    "1 << true",
 ];
 
 const LAMBDA_NEG: &[&str] = &["λ (x: Int) (x: Int). y", "λ (x: Int) (y: Bool) (y: Int). y"];
 
-const LET_NEG: &[&str] = &[
-    "let x = 1 in x & true",
-    "let (x, y) = (1, 2) in x | y",
+const RECORD_NEG: &[&str] = &[
+    "{x = 1, x = 2}",
+    "{x = 1}.y",
+    "(1, 2).x",
+    "(1, 2).2",
+    "{x = 1}.0",
+    "(λ p. p.0) (1, 2)",
+    "let {z = a} = {x = 1} in a",
+    "let {x = a, x = b} = {x = 1} in a",
+    "case {b = true} of {b = true} => 0 end",
+    "(λ r: {x: Int}. r.x) {x = 1, y = 2}",
+];
+
+const FOLD_NEG: &[&str] = &[
+    "fold [Int] 1",
+    "unfold 1",
+    "fold [rec S. Int * S] (1, 2)",
+    "λ s: rec S. Int * S. (unfold s) + 1",
+];
+
+/// Pairs of expressions using references and their types
+const REFS: &[(&str, &str)] = &[
+    ("ref 0", "Ref Int"),
+    ("let r = ref true in !r", "Bool"),
+    ("let r = ref 0 in r := !r + 1", "Unit"),
+    ("λ r: Ref (Int -> Int). !r 1", "Ref (Int -> Int) -> Int"),
+    ("ref (ref null)", "Ref (Ref Unit)"),
+];
+
+/// Pairs of assertions and their types
+const ASSERTIONS: &[(&str, &str)] = &[
+    ("assert (1 < 2)", "Unit"),
+    ("assert", "Bool -> Unit"),
+    ("assertEq((1, true), (1, false))", "Unit"),
+    ("λ x: Int8. assertEq(1, x)", "Int8 -> Unit"),
+    ("λ r: Ref Int. assertEq(r, r)", "Ref Int -> Unit"),
+];
+
+const ASSERTION_NEG: &[&str] = &[
+    "assert 1",
+    "assertEq(1, true)",
+    "assertEq(λ x: Int. x, λ x: Int. x)",
+    "assertEq(1, 1) + 1",
+];
+
+/// Pairs of expressions spawning and joining tasks, and their types
+const FUTURES: &[(&str, &str)] = &[
+    ("spawn (1 + 1)", "Future Int"),
+    ("join (spawn true)", "Bool"),
+    (
+        "λ t: Future (Int -> Int). join t 1",
+        "Future (Int -> Int) -> Int",
+    ),
+    ("let r = ref 0 in spawn (r := 1)", "Future Unit"),
+    ("spawn (spawn \"task\")", "Future (Future String)"),
+];
+
+/// Pairs of expressions using strings and the builtins, and their types
+const STRINGS: &[(&str, &str)] = &[
+    (r#""hi""#, "String"),
+    (r#"print "hi""#, "Unit"),
+    (
+        "λ n: Int. let _ = printInt n in readLine null",
+        "Int -> String",
+    ),
+    (
+        r#"case readLine null of "yes" => true; _ => false end"#,
+        "Bool",
+    ),
+    (r#""a" == "b""#, "Bool"),
+];
+
+/// Pairs of expressions using arrays and their types
+const ARRAYS: &[(&str, &str)] = &[
+    ("array(3, true)", "Array Bool"),
+    ("let a = array(2, 0) in sub(a, 1) + 1", "Int"),
+    ("let a = array(2, 0) in update(a, 0, 1)", "Unit"),
+    (
+        "λ a: Array (Array Int). sub(sub(a, 0), 1)",
+        "Array (Array Int) -> Int",
+    ),
+    ("λ a: Array Int. a == a", "Array Int -> Bool"),
+];
+
+const ASCRIPTIONS: &[(&str, &str)] = &[
+    ("(1 : Int)", "Int"),
+    ("λ x: Int. ((x, x) : Int * Int)", "Int -> Int * Int"),
+    ("(λ x. x : Int -> Int)", "Int -> Int"),
+    ("(Λ A. λ x: A. x : ∀ B. B -> B)", "∀ A. A -> A"),
+    ("(pack [Int] 1 as ∃ S. S : ∃ T. T)", "∃ S. S"),
+    ("let f = (λ x. x : Int -> Int) in f 1", "Int"),
+];
+
+const ASCRIPTION_NEG: &[&str] = &["(1 : Bool)", "λ x: Int. (x : Bool)", "(λ x. x : Int)"];
+
+/// Integer literals default to `Int`, of 31 bits, unless a sized integer type is expected
+const SIZED_INTS: &[(&str, &str)] = &[
+    ("(127 : Int8)", "Int8"),
+    ("(-32768 : Int16)", "Int16"),
+    ("λ x: Int8. x + 1", "Int8 -> Int8"),
+    ("λ x: Int32. 1 << x", "Int32 -> Int32"),
+    ("λ x: Int16. (x, 1)", "Int16 -> Int16 * Int"),
+    ("λ x: Int8. x < 0 & 0 == x", "Int8 -> Bool"),
+    ("(1 + 2 * 3 : Int16)", "Int16"),
+    ("λ x: Int8. int(x) + 1000", "Int8 -> Int"),
+    ("λ x: Int8. int64(x) + 4294967296", "Int8 -> Int64"),
+    ("int32(int8(300))", "Int32"),
+    ("(int8(1) : Int8)", "Int8"),
+    (
+        "λ x: Int8. case x of 0 => true; -128 => true; _ => false end",
+        "Int8 -> Bool",
+    ),
+];
+
+const SIZED_INT_NEG: &[&str] = &[
+    "(128 : Int8)",
+    "(-32769 : Int16)",
+    "λ x: Int8. λ y: Int16. x + y",
+    "λ x: Int8. (x : Int)",
+    "λ x: Int32. x + (1 : Int)",
+    "(λ x: Int. x) 1073741824",
+    "λ x: Int. case x of -1073741825 => true; _ => false end",
+    "λ x: Int64. x + (1 : Int)",
+    "int8(true)",
+    "λ x: Int8. case x of 255 => true; _ => false end",
+];
+
+const EQUALITIES: &[(&str, &str)] = &[
+    ("(1, true) == (1, false)", "Bool"),
+    ("{x = 1, y = null} != {y = null, x = 2}", "Bool"),
+    ("λ r: Ref (Int -> Int). r == r", "Ref (Int -> Int) -> Bool"),
+    ("λ p: rec L. Unit * L. p == p", "(μ L. Unit * L) -> Bool"),
+];
+
+const EQUALITY_NEG: &[&str] = &[
+    "(λ x: Int. x) == (λ x: Int. x)",
+    "(1, λ x: Int. x) == (1, λ x: Int. x)",
+    "any A. λ x: A. x == x",
+    "λ p: ∃ S. S. p == p",
+    "spawn 1 == spawn 1",
+];
+
+const EQUALITY_PROGS: &[&str] = &[
+    "data List A = Nil | Cons A (List A)
+     let same: Bool = Cons (1, true) Nil == Nil",
+    "data Tree A = Leaf A | Node (Tree (A * A))
+     let same: Bool = Node (Leaf (1, 2)) == Leaf 1",
+];
+
+const EQUALITY_PROG_NEG: &[&str] = &[
+    "data List A = Nil | Cons A (List A)
+     let same: Bool = Cons (λ x: Int. x) Nil == Nil",
+    "data Box = Box (Int -> Int) let f: Box -> Bool = λ b. b == b",
+    "data Tree A = Leaf A | Node (Tree (A -> A))
+     let same: Bool = Leaf 1 == Leaf 1",
+    "exception Fail let same: Bool = Fail == Fail",
+];
+
+const REF_NEG: &[&str] = &[
+    "!1",
+    "1 := 2",
+    "let r = ref 0 in r := true",
+    "let r = ref 0 in !r & true",
+    "(λ r: Ref Int. !r) 1",
+];
+
+const FUTURE_NEG: &[&str] = &[
+    "join 1",
+    "join (ref 1)",
+    "spawn 1 + 1",
+    "join (spawn 1) & true",
+];
+
+const STRING_NEG: &[&str] = &[
+    "print 1",
+    r#"printInt "1""#,
+    "readLine 1",
+    r#""a" + 1"#,
+    r#"case "a" of "a" => 1 end"#,
+];
+
+const ARRAY_NEG: &[&str] = &[
+    "array(true, 0)",
+    "sub(ref 0, 0)",
+    "sub(array(1, 0), true)",
+    "update(array(1, 0), 0, true)",
+    "let a = array(1, 0) in sub(a, 0) & true",
+];
+
+const PACK_NEG: &[&str] = &[
+    "pack [Int] true as ∃ S. S",
+    "pack [Int] 1 as Int",
+    "unpack [S] x = 1 in x",
+    "unpack [S] p = pack [Int] (1, 2) as ∃ S. S * Int in let (s, _) = p in s",
+    "unpack [S] p = pack [Int] (1, 2) as ∃ S. S * Int in let (s, _) = p in s + 1",
+];
+
+const LET_NEG: &[&str] = &["let x = 1 in x & true", "let (x, y) = (1, 2) in x | y"];
+
+const CASE_NEG: &[&str] = &[
+    "case 3 of 0 => 1; 1 => 2 end",
+    "case true of true => 1 end",
+    "case (true, false) of (true, _) => 0; (_, true) => 1 end",
+    "case 3 of true => 1; _ => 2 end",
+    "case 3 of 0 => 1; _ => false end",
+    "let 0 = 1 in 2",
+    "let (x, true) = (1, false) in x",
+];
+
+/// Programs and the number of `case` arms in them that can never be reached
+const UNREACHABLE: &[(&str, usize)] = &[
+    ("let f: Int -> Int = λ x. case x of n => 1; 0 => 2 end", 1),
+    ("let f: Bool -> Int = λ b. case b of true => 1; false => 2; _ => 3 end", 1),
+    (
+        "let f: Bool * Bool -> Int = λ p. case p of (true, _) => 1; (false, _) => 2; (_, true) => 3 end",
+        1,
+    ),
+    ("let f = λ x. case x + 1 of 0 => 1; 0 => 2; n => n; _ => 4 end", 2),
+    ("let f: Int -> Int = λ x. case x of 0 => 1; _ => 2 end", 0),
+    (
+        "data Option A = None | Some A
+         let get = λ o. case o of Some x => x; None => 0; Some 1 => 2 end",
+        1,
+    ),
+];
+
+/// Pairs of expressions abstracting over type operators and their types
+const OPERATORS: &[(&str, &str)] = &[
+    (
+        "any (F: * -> *). λ x: F Int. x",
+        "∀ (F: * -> *). F Int -> F Int",
+    ),
+    (
+        "(any (F: * -> *). λ x: F Int. x) [λ A. A * A] (1, 2)",
+        "Int * Int",
+    ),
+    (
+        "any (P: * -> * -> *). λ p: P Int Bool. p",
+        "∀ (P: * -> * -> *). P Int Bool -> P Int Bool",
+    ),
+];
+
+/// Ill-kinded expressions
+const KIND_NEG: &[&str] = &[
+    "λ x: (λ A. A). x",
+    "any (F: * -> *). λ x: F. x",
+    "any A. λ x: A Int. x",
+    "(any (F: * -> *). 1) [Int]",
+    "(any A. 1) [λ B. B]",
+];
+
+/// Programs using type operators as aliases and data types as type operators
+const OPERATOR_PROGS: &[&str] = &[
+    "type Pair = λ A. λ B. A * B
+     let p: Pair Int Bool = (1, true)",
+    "type Twice = λ (F: * -> *). λ A. F (F A)
+     data Option A = None | Some A
+     let x: Twice Option Int = Some (Some 1)",
+    "data List A = Nil | Cons A (List A)
+     let wrap: ∀ (F: * -> *). (∀ A. A -> F A) -> F Int =
+       any (F: * -> *). λ (w: ∀ A. A -> F A). w [Int] 1
+     let l: List Int = wrap [List] (any A. λ x: A. Cons [A] x (Nil [A]))
+     let m: List Int = wrap (any A. λ x: A. Cons x Nil)",
+];
+
+const OPERATOR_PROG_NEG: &[&str] = &[
+    "type Bad = (λ A. A) Int Int
+     let x: Int = 1",
+    "type Pair = λ A. λ B. A * B
+     let p: Pair Int = (1, true)",
+];
+
+/// Declarations with holes, paired with the types filled in for each hole
+const HOLES: &[(&str, &[&str])] = &[
+    ("let f: Int -> _ = λ x: Int. x > 0", &["Bool"]),
+    (
+        "let g: Int -> Int -> Int = λ (x: _) (y: _). x * y",
+        &["Int", "Int"],
+    ),
+    ("let h: _ = (1, true)", &["(Int * Bool)"]),
+    ("let k: forall A. A -> A = any A. λ a: _. a", &["A"]),
+    ("let f: _ = λ x: _. x", &["∀ A. A -> A", "A"]),
+    ("let g: Int = (any A. λ a: A. 1) [_] null", &["Unit"]),
+];
+
+const HOLE_NEG: &[&str] = &[
+    "let f = let g = (λ x. x) (λ y. y) in (g 1, g true)",
+    "let f = λ x. let y = x in (y + 1, y & true)",
+    "let f = let r = ref (λ x. x) in let _ = r := (λ n. n + 1) in !r true",
+    "let f: _ = λ x. x x",
+    "let g: Int = (λ f: _. 1) (λ x. x)",
+    "let h: _ = λ g. (g 1, g true)",
+    "let k: Int -> Bool = λ x. x + 1",
+];
+
+/// Programs, the name of a declaration in them, and the type inferred for it
+const INFERENCE: &[(&str, &str, &str)] = &[
+    ("let id = λ x. x", "id", "∀ A. A -> A"),
+    (
+        "let k = Λ A. λ x: A. Λ A. λ y. x",
+        "k",
+        "∀ A. ∀ C. A -> (∀ B. C -> A)",
+    ),
+    (
+        "let compose = λ f g x. f (g x)",
+        "compose",
+        "∀ A. ∀ B. ∀ C. (A -> B) -> (C -> A) -> C -> B",
+    ),
+    (
+        "let id = λ x. x
+         let pair = (id 1, id true)",
+        "pair",
+        "Int * Bool",
+    ),
+    (
+        "data Option A = None | Some A
+         let x = Some 1",
+        "x",
+        "Option Int",
+    ),
+    (
+        "let fact = fix f = λ n. if n < 1 then 1 else n * f (n - 1) in f",
+        "fact",
+        "Int -> Int",
+    ),
+    (
+        "data List A = Nil | Cons A (List A)
+         let len = fix len = λ l. case l of Nil => 0; Cons _ t => 1 + len t end in len",
+        "len",
+        "∀ A. List A -> Int",
+    ),
+    (
+        "let both = λ (g: ∀ A. A -> A). (g 1, g true)",
+        "both",
+        "(∀ A. A -> A) -> Int * Bool",
+    ),
+    (
+        "let apply: ((forall A. A -> A) -> Int) -> Int = λ h. h (any A. λ x. x)",
+        "apply",
+        "((∀ A. A -> A) -> Int) -> Int",
+    ),
+    (
+        "let pair = let id = λ x. x in (id 1, id true)",
+        "pair",
+        "Int * Bool",
+    ),
+    (
+        "let k = λ x. let const = λ y. x in (const 1, const true)",
+        "k",
+        "∀ B. B -> B * B",
+    ),
+    ("let incr = λ r. r := !r + 1", "incr", "Ref Int -> Unit"),
+    ("let first = λ a. sub(a, 0)", "first", "∀ A. Array A -> A"),
+    (
+        "let zero = λ a. update(a, 0, 0)",
+        "zero",
+        "Array Int -> Unit",
+    ),
+    // Unknown types compared for equality default to `Int`
+    ("let eq = λ x y. x == y", "eq", "Int -> Int -> Bool"),
+    ("let xs = [[1], []]", "xs", "List (List Int)"),
+    (
+        "let head = λ l d. case l of [] => d; x :: _ => x end",
+        "head",
+        "∀ A. List A -> A -> A",
+    ),
+    (
+        "let same = λ p. p == (1, true)",
+        "same",
+        "Int * Bool -> Bool",
+    ),
+    (
+        "let f = λ x. (x == x, x & true)",
+        "f",
+        "Bool -> Bool * Bool",
+    ),
+    // Ascriptions guide inference
+    ("let f = λ p. (p : Int * _)", "f", "∀ A. Int * A -> Int * A"),
+    ("let nil = ([] : List Bool)", "nil", "List Bool"),
+    // Literals take the width of the other operand, and unknown operands are `Int`
+    ("let inc = λ x: Int8. 1 + x", "inc", "Int8 -> Int8"),
+    ("let inc = λ x. x + 1", "inc", "Int -> Int"),
+    ("let narrow = λ x. int16(x)", "narrow", "Int -> Int16"),
+    // Only values are generalized
+    ("let r = ref (1 :: Nil)", "r", "Ref (List Int)"),
+    ("let xs = (λ x. x) (true :: Nil)", "xs", "List Bool"),
+    ("let get = λ x. let r = ref x in !r", "get", "∀ A. A -> A"),
+];
+
+/// Polymorphic references and other non-values, which can't be generalized
+const VALUE_RESTRICTION: &[&str] = &[
+    "let r = ref (λ x. x)",
+    "let xs = (λ x. x) Nil",
+    "let id = (λ f. f) (λ x. x)",
+    "let a = array(3, λ x. x)",
+    "let r = ref (λ x. x)
+     let u: Unit = r := (λ n. n + 1)
+     let b: Bool = (!r) true",
+];
+
+/// Non-values bound by `let` in expressions stay monomorphic
+const MONOMORPHIC_LETS: &[(&str, bool)] = &[
+    (
+        "let n: Int = let r = ref (λ x. x) in let _ = r := (λ n. n + 1) in (!r) 1",
+        true,
+    ),
+    (
+        "let b: Bool = let r = ref (λ x. x) in let _ = r := (λ n. n + 1) in (!r) true",
+        false,
+    ),
+];
+
+/// Ill-typed declarations and the subterm the error should point at
+const MISMATCH_SPANS: &[(&str, &str)] = &[
+    ("let f: Int -> Int = λ x. if x > 0 then 1 else true", "true"),
+    ("let p: Int * Bool = (1, 2)", "2"),
+    ("let g: Int -> Int = λ x: Bool. 1", "Bool"),
+    ("let h: Int = (λ x: Int. x) (1, false)", "(1, false)"),
+];
+
+/// Expressions whose lambda parameters are only known from the type they are checked against
+const CHECKED: &[(&str, &str)] = &[
+    ("λ x. x + 1", "Int -> Int"),
+    ("any A. λ x. x", "∀ B. B -> B"),
+    ("(λ x. x, λ b. b & true)", "(Int -> Int) * (Bool -> Bool)"),
+    ("if true then λ x. x else λ y. 0", "Int -> Int"),
+];
+
+const ALIASES: &[&str] = &[
+    "type Predicate = Int -> Bool
+     let pos: Predicate = λ x: Int. x > 0",
+    "type Pair = Int * Int
+     type Swap = Pair -> Pair
+     let swap: Swap = λ p: Pair. let (a, b) = p in (b, a)",
+    "type Id = forall A. A -> A
+     let id: Id = any A. λ a: A. a",
+    "type A = Int
+     let poly: forall A. A -> A = any A. λ a: A. a",
+];
+
+const ALIAS_NEG: &[&str] = &[
+    "type Predicate = Int -> Bool
+     let bad: Predicate = λ x: Int. x + 1",
+    "type T = Int
+     type T = Bool
+     let x: T = 1",
+];
+
+const DATATYPES: &[&str] = &[
+    "data Option A = None | Some A
+     let get: Option Int -> Int = λ o: Option Int. case o of None => 0; Some x => x end",
+    "data List A = Nil | Cons A (List A)
+     let sum: List Int -> Int = fix sum = λ (l: List Int) : Int.
+       case l of Nil => 0; Cons x tl => x + sum tl end in sum",
+    "data Pair A B = Pair A B
+     let swap: forall X. forall Y. Pair X Y -> Pair Y X =
+       any X. any Y. λ p: Pair X Y. let Pair x y = p in Pair [Y] [X] y x",
+    "type Opt = Option Bool
+     data Option A = None | Some A
+     let x: Opt = Some [Bool] true",
+    "data Option A = None | Some A
+     type IntList = rec L. Option (Int * L)
+     let nil: IntList = fold [IntList] (None [Int * IntList])
+     let len: IntList -> Int = fix len = λ (l: IntList) : Int.
+       case unfold l of None => 0; Some (_, tl) => 1 + len tl end in len",
+];
+
+const DATATYPE_NEG: &[&str] = &[
+    "data Option A = None | Some A
+     let get: Option Int -> Int = λ o: Option Int. case o of Some x => x end",
+    "data Option A = None | Some A
+     let get: Option Int -> Int = λ o: Option Int. case o of None => 0; Some x y => x end",
+    "data Option A = None | Some A
+     data Bit = Zero | One
+     let get: Option Int -> Int = λ o: Option Int. case o of Zero => 0; _ => 1 end",
+    "data Option A = None | Some A
+     let x: Option Int = Some [Bool] true",
+    "data T = A | B
+     data U = A",
+];
+
+const EXCEPTIONS: &[&str] = &[
+    "exception Empty let x: Int = raise Empty",
+    "exception Fail Int
+     let f: Int -> Int = λ x. if x < 0 then raise (Fail x) else x
+     let g: Int = f 1 handle Fail n => n",
+    "exception Pair Int Bool let e: Exn = Pair 1 true",
+    "type Msg = Int exception Fail Msg let x: Bool = raise [Bool] (Fail 1) handle _ => false",
+];
+
+const EXCEPTION_NEG: &[&str] = &[
+    // Only exceptions can be raised
+    "let x: Int = raise 1",
+    "exception Fail Int let x: Int = 1 handle Fail b => b & true",
+    "exception Fail Int let x: Int = 1 handle Fail => 0",
+    "exception Fail exception Fail Int let x: Int = 1",
+    "data Option A = None | Some A exception None let x: Int = 1",
+];
+
+/// Programs using the lists of the prelude
+const LISTS: &[&str] = &[
+    "let xs: List Int = 1 :: [2, 3]",
+    "let sum: List Int -> Int = fix sum = λ l. case l of [] => 0; x :: t => x + sum t end in sum",
+    "let pairs: List (Int * Bool) -> Int =
+       λ l. case l of [(n, true)] => n; [_, _] => 2; _ => 0 end",
+    // Programs may define their own lists
+    "data List = Nil | Cons Int List let xs: List = Cons 1 Nil",
+];
+
+const LIST_NEG: &[&str] = &[
+    // Exceptions of the prelude can't be redefined
+    "exception Subscript",
+    "let xs: List Int = [1, true]",
+    "let xs: List Int = 1 :: 2",
+    "let f: List Int -> Int = λ l. case l of [] => 0; [x] => x end",
+    // The prelude's lists are replaced by a program's own
+    "data List = Empty | Node Int List let xs: List = [1]",
+];
+
+const CLASSES: &[&str] = &[
+    "class Eq A { eq: A -> A -> Bool }
+     instance Eq Int { eq = λ x y. x == y }
+     let x: Bool = eq 1 2",
+    // Instances for aliases and data types
+    "class Size A { size: A -> Int }
+     data Option A = None | Some A
+     type Pair = Int * Int
+     instance Size Pair { size = λ p. 2 }
+     instance Size (Option Int) { size = λ o. case o of None => 0; Some _ => 1 end }
+     let x: Int = size (1, 2) + size (Some 3)",
+    // Constrained type abstractions take dictionaries, passed where they're used
+    "class Eq A { eq: A -> A -> Bool }
+     instance Eq Int { eq = λ x y. x == y }
+     let same: ∀ (A: Eq). A -> Bool = Λ (A: Eq). λ x. eq x x
+     let x: Bool = same 1 & same [Int] 2",
+    "class Eq A { eq: A -> A -> Bool }
+     class Size A { size: A -> Int }
+     instance Eq Int { eq = λ x y. x == y }
+     instance Size Int { size = λ n. n }
+     let small: ∀ (A: Eq, Size). A -> Bool = Λ (A: Eq, Size). λ x. eq x x & size x < 10
+     let both: ∀ (A: Eq, Size). ∀ (B: Eq). A -> B -> Bool =
+       Λ (A: Eq, Size) (B: Eq). λ x y. small x & eq y y
+     let x: Bool = both 1 2 & both [Int] [Int] 3 4",
+];
+
+const CLASS_NEG: &[&str] = &[
+    // No instance for the type
+    "class Eq A { eq: A -> A -> Bool } let x: Bool = eq 1 2",
+    // Dictionaries aren't abstracted over implicitly
+    "class Eq A { eq: A -> A -> Bool } let f: ∀ A. A -> Bool = Λ A. λ x. eq x x",
+    "instance Eq Int { eq = λ x y. x == y }",
+    "class Eq A { eq: A -> A -> Bool }
+     instance Eq Int { eq = λ x y. x == y }
+     instance Eq Int { eq = λ x y. true }",
+    "class Eq A { eq: A -> A -> Bool } let eq: Int = 1",
+    "class Eq A { eq: A -> A -> Bool } class Same A { eq: A -> A -> Bool }",
+    "class Eq A { eq: A -> A -> Bool } instance Eq Int { eq = λ x. x }",
+    "class Monoid A { empty: A, combine: A -> A -> A } instance Monoid Int { empty = 0 }",
+    // Constraints are checked where the dictionaries are passed
+    "class Eq A { eq: A -> A -> Bool }
+     instance Eq Int { eq = λ x y. x == y }
+     let same: ∀ (A: Eq). A -> Bool = Λ (A: Eq). λ x. eq x x
+     let x: Bool = same true",
+    "class Eq A { eq: A -> A -> Bool }
+     let same: ∀ (A: Eq). A -> Bool = Λ (A: Eq). λ x. eq x x
+     let f: ∀ A. A -> Bool = Λ A. λ x. same x",
+    "data Option A = None | Some A let f: ∀ (A: Option). A -> A = Λ (A: Option). λ x. x",
 ];
 
 #[test]
@@ -77,13 +774,82 @@ fn test_equivalent_neg() {
         let typ2 = parse_type(s2).unwrap().typ;
         println!("{}", typ1);
         println!("{}", typ2);
-        assert!(!equivalent(&typ1, &typ2))
+        assert!(!equivalent(&typ1, &typ2));
+        let mut types = Interner::default();
+        assert_ne!(types.intern(&typ1), types.intern(&typ2))
+    }
+}
+
+#[test]
+fn test_equivalent_alpha() {
+    for (s1, s2) in EQUIVALENT_ALPHA {
+        let typ1 = parse_type(s1).unwrap().typ;
+        let typ2 = parse_type(s2).unwrap().typ;
+        assert!(alpha_equivalent(&typ1, &typ2), "{s1} {s2}");
+        assert!(equivalent(&typ1, &typ2));
+        let mut types = Interner::default();
+        assert_eq!(types.intern(&typ1), types.intern(&typ2))
+    }
+    // Renamed binders also match during inference and checking
+    let prog = "let id: ∀ B. B -> B = any A. λ x: A. x
+                let f: (∀ C. C -> C) -> Int = λ g. g [Int] 1
+                let n: Int = f id";
+    check_prog(&parse_prog(prog).unwrap()).unwrap();
+}
+
+#[test]
+fn test_equivalent_beta() {
+    for (s1, s2) in EQUIVALENT_BETA {
+        let typ1 = parse_type(s1).unwrap().typ;
+        let typ2 = parse_type(s2).unwrap().typ;
+        assert!(equivalent(&typ1, &typ2));
+        let mut types = Interner::default();
+        assert_eq!(types.intern(&typ1), types.intern(&typ2), "{s1} {s2}")
+    }
+}
+
+#[test]
+fn test_type_operators() {
+    for (s1, s2) in OPERATORS {
+        let exp = parse_expr(s1).unwrap();
+        let typ = parse_type(s2).unwrap().typ;
+        let checked = check_closed_expr(&exp).unwrap();
+        println!("{}", checked);
+        assert!(equivalent(&typ, &checked))
+    }
+    for s in KIND_NEG {
+        let exp = parse_expr(s).unwrap();
+        check_closed_expr(&exp).unwrap_err();
+    }
+    for s in OPERATOR_PROGS {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for s in OPERATOR_PROG_NEG {
+        check_prog(&parse_prog(s).unwrap()).unwrap_err();
     }
 }
 
 #[test]
 fn test_type_checking() {
-    let everything = [BINOPS, ANYS, LAMBDAS, TUPLES];
+    let everything = [
+        BINOPS,
+        ANYS,
+        SHADOWING,
+        LAMBDAS,
+        TUPLES,
+        CASES,
+        RECORDS,
+        FOLDS,
+        PACKS,
+        REFS,
+        ASSERTIONS,
+        FUTURES,
+        STRINGS,
+        ARRAYS,
+        EQUALITIES,
+        ASCRIPTIONS,
+        SIZED_INTS,
+    ];
     for suite in everything {
         for (s1, s2) in suite {
             let exp = parse_expr(s1).unwrap().expr;
@@ -98,7 +864,24 @@ fn test_type_checking() {
 
 #[test]
 fn test_type_checking_negative() {
-    let everything = [BINOP_NEG, LAMBDA_NEG, LET_NEG];
+    let everything = [
+        BINOP_NEG,
+        SHADOWING_NEG,
+        LAMBDA_NEG,
+        LET_NEG,
+        CASE_NEG,
+        RECORD_NEG,
+        FOLD_NEG,
+        PACK_NEG,
+        REF_NEG,
+        ASSERTION_NEG,
+        FUTURE_NEG,
+        STRING_NEG,
+        ARRAY_NEG,
+        EQUALITY_NEG,
+        ASCRIPTION_NEG,
+        SIZED_INT_NEG,
+    ];
     for suite in everything {
         for s in suite {
             let exp = parse_expr(s).unwrap().expr;
@@ -106,3 +889,374 @@ fn test_type_checking_negative() {
         }
     }
 }
+
+#[test]
+fn test_type_holes() {
+    colored::control::set_override(false);
+    for (s, filled) in HOLES {
+        let decl = parse_decl(s).unwrap();
+        let notes = check_decl(&decl, &mut Default::default()).unwrap();
+        assert_eq!(notes.len(), filled.len());
+        for (note, typ) in notes.iter().zip(filled.iter()) {
+            assert_eq!(note.label, format!("`_` has type `{typ}`"))
+        }
+    }
+    for s in HOLE_NEG {
+        let decl = parse_decl(s).unwrap();
+        check_decl(&decl, &mut Default::default()).unwrap_err();
+    }
+}
+
+#[test]
+fn test_inference() {
+    for (s, id, expected) in INFERENCE {
+        let prog = with_prelude(&parse_prog(s).unwrap());
+        let mut aliases = prog_aliases(&prog).unwrap();
+        let mut ctxt = Default::default();
+        for data in &prog.datatypes {
+            define_datatype(data, &mut ctxt, &mut aliases).unwrap();
+        }
+        for decl in &prog.order {
+            check_decl(&prog.declarations[decl], &mut ctxt).unwrap();
+        }
+        let typ = parse_type(expected).unwrap().typ;
+        println!("{}", ctxt[*id]);
+        assert!(equivalent(&typ, &ctxt[*id]))
+    }
+}
+
+#[test]
+fn test_value_restriction() {
+    for s in VALUE_RESTRICTION {
+        let err = check_prog(&parse_prog(s).unwrap()).unwrap_err();
+        assert_eq!(err.title, "Cannot generalize type")
+    }
+    for (s, ok) in MONOMORPHIC_LETS {
+        assert_eq!(check_prog(&parse_prog(s).unwrap()).is_ok(), *ok, "{s}")
+    }
+}
+
+#[test]
+fn test_unreachable() {
+    for (s, count) in UNREACHABLE {
+        let prog = parse_prog(s).unwrap();
+        let mut aliases = prog_aliases(&prog).unwrap();
+        let mut ctxt = Default::default();
+        for data in &prog.datatypes {
+            define_datatype(data, &mut ctxt, &mut aliases).unwrap();
+        }
+        let mut warnings = 0;
+        for decl in &prog.order {
+            let notes = check_decl(&prog.declarations[decl], &mut ctxt).unwrap();
+            warnings += notes
+                .iter()
+                .filter(|n| n.title == "Unreachable pattern")
+                .count();
+        }
+        assert_eq!(warnings, *count, "{s}")
+    }
+}
+
+#[test]
+fn test_mismatch_spans() {
+    for (s, culprit) in MISMATCH_SPANS {
+        let decl = parse_decl(s).unwrap();
+        let err = check_decl(&decl, &mut Default::default()).unwrap_err();
+        let start = s.find(culprit).unwrap();
+        assert_eq!(err.annotations[0].range, (start, start + culprit.len()))
+    }
+}
+
+/// Programs whose annotations only mention types in scope
+const WELL_FORMED: &[&str] = &[
+    "data Tree = Leaf | Node Tree Tree let t: Tree = Node Leaf Leaf",
+    "type Id = λ A. A -> A let f: ∀ B. Id B = any B. λ x: B. x",
+    "let f: Exn -> Exn = λ e. e",
+    "let n: Int = unpack [S] p = pack [Int] 1 as ∃ T. T in (λ s: S. 1) p",
+    // Type variables of the signature scope over the body
+    "let f: ∀ A. A -> A = λ x: A. x",
+    "let k: ∀ A. ∀ B. A -> B -> A = λ x y. (λ z: A. z) ((λ (w: B). x) y)",
+    "data List A = Nil | Cons A (List A)
+     let rev: ∀ A. List A -> List A = λ xs.
+       fix go = λ (acc: List A) : List A -> List A. λ l: List A.
+         case l of Nil => acc; Cons x tl => go (Cons x acc) tl end
+       in go (Nil [A]) xs",
+    "let f: ∀ (F: * -> *). F Int -> F Int = λ x: F Int. x",
+];
+
+/// Pairs of programs mentioning unknown types, and the annotation the error points at
+const UNKNOWN_TYPES: &[(&str, &str)] = &[
+    ("let f: A -> A = λ x. x", "A"),
+    ("let f: Int -> Int = λ x: Foo. 1", "Foo"),
+    ("let f: Int -> Int = any A. λ x: B. x", "B"),
+    ("let n: Int = (any A. λ y: A. 1) [Foo] 2", "Foo"),
+    ("data T = C (Option Int)", "Option Int"),
+    ("exception E Tree", "Tree"),
+    ("type P = Pair * Int", "Pair"),
+];
+
+/// Type variables of signatures that are out of scope
+const UNSCOPED: &[&str] = &[
+    // An explicit abstraction of the body hides the variables of the signature
+    "let f: ∀ A. A -> A = Λ B. λ x: A. x",
+    "let f: ∀ A. A -> A = λ x: A. x let g: Int -> Int = λ y: A. y",
+];
+
+#[test]
+fn test_well_formedness() {
+    for s in WELL_FORMED {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for (s, culprit) in UNKNOWN_TYPES {
+        let err = check_prog(&parse_prog(s).unwrap()).unwrap_err();
+        let start = s.find(culprit).unwrap();
+        assert_eq!(err.title, "Unknown type");
+        assert_eq!(err.annotations[0].range, (start, start + culprit.len()))
+    }
+    for s in UNSCOPED {
+        let err = check_prog(&parse_prog(s).unwrap()).unwrap_err();
+        assert_eq!(err.title, "Unknown type")
+    }
+    check_closed_expr(&parse_expr("λ x: A. x").unwrap()).unwrap_err();
+}
+
+#[test]
+fn test_checking_mode() {
+    for (s, t) in CHECKED {
+        let exp = parse_expr(s).unwrap();
+        let typ = parse_type(t).unwrap().typ;
+        check_against(&exp, &typ, &Default::default(), &Default::default()).unwrap();
+        check_closed_expr(&exp).unwrap_err();
+    }
+}
+
+#[test]
+fn test_aliases() {
+    for s in ALIASES {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for s in ALIAS_NEG {
+        check_prog(&parse_prog(s).unwrap()).unwrap_err();
+    }
+}
+
+#[test]
+fn test_datatypes() {
+    for s in DATATYPES {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for s in DATATYPE_NEG {
+        check_prog(&parse_prog(s).unwrap()).unwrap_err();
+    }
+}
+
+const NEWTYPES: &[&str] = &[
+    "newtype UserId = UserId Int
+     let next: UserId -> UserId = λ u. case u of UserId n => UserId (n + 1) end",
+    "newtype Wrap A = Wrap (A * Int) deriving (Eq)
+     let w: Wrap Bool = Wrap (true, 1)
+     let b: Bool = eq_Wrap [Bool] (λ x y. x == y) w w",
+];
+
+/// Newtypes are distinct from their fields
+const NEWTYPE_NEG: &[&str] = &[
+    "newtype UserId = UserId Int let u: UserId = 1",
+    "newtype UserId = UserId Int let n: Int = UserId 1 + 1",
+    "newtype UserId = UserId Int newtype OrderId = OrderId Int let o: OrderId = UserId 1",
+];
+
+#[test]
+fn test_newtypes() {
+    for s in NEWTYPES {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for s in NEWTYPE_NEG {
+        check_prog(&parse_prog(s).unwrap()).unwrap_err();
+    }
+}
+
+const MODULES: &[&str] = &[
+    "signature COUNTER = sig type T val zero: T val incr: T -> T val get: T -> Int end
+     structure Counter : COUNTER = struct
+       type T = Int
+       let zero = 0
+       let incr = λ n: T. n + 1
+       let get = λ n: T. n
+     end
+     let one: Int = open Counter in get (incr zero)",
+    "structure Util = struct let double = λ n: Int. n * 2 let quad = λ n: Int. double (double n) end
+     let n: Int = open Util in quad 2",
+    "structure Poly = struct let id: ∀ A. A -> A = λ x. x end
+     let p: Int * Bool = open Poly in (id [Int] 1, id [Bool] true)",
+    "signature STACK = sig type S val empty: S val push: Int -> S -> S end
+     structure Stack : STACK = struct
+       type S = List Int
+       let empty: S = Nil
+       let push = λ (n: Int) (s: S). n :: s
+       let secret = 1
+     end
+     let s: STACK = Stack",
+    // Signatures alike but for the names of their types, opened in the same declaration
+    "signature S = sig type T val zero: T val get: T -> Int end
+     signature U = sig type V val zero: V val get: V -> Int end
+     structure A : S = struct type T = Int let zero = 0 let get = λ n: T. n end
+     structure B : U = struct type V = Int let zero = 1 let get = λ n: V. n end
+     let one: Int = (open A in get zero) + (open B in (λ z. get z) zero)",
+];
+
+/// Abstract types and hidden members of sealed structures, and the errors of elaboration
+const MODULE_NEG: &[(&str, &str)] = &[
+    (
+        "signature C = sig type T val zero: T end
+         structure M : C = struct type T = Int let zero = 0 end
+         let n: Int = open M in zero + 1",
+        "Mismatched Types",
+    ),
+    (
+        "signature C = sig type T val zero: T end
+         structure M : C = struct type T = Int let zero = 0 end
+         let z = open M in zero",
+        "Escaping type variable",
+    ),
+    (
+        "signature C = sig val x: Int end
+         structure M : C = struct let x = 1 let y = 2 end
+         let n: Int = open M in y",
+        "Unbound variable",
+    ),
+    (
+        "signature C = sig type T val x: Int end structure M : C = struct let x = 1 end",
+        "Missing structure type",
+    ),
+    (
+        "signature C = sig val x: Int val y: Int end structure M : C = struct let x = 1 end",
+        "Missing structure member",
+    ),
+    (
+        "signature C = sig val x: Bool end structure M : C = struct let x = 1 end",
+        "Mismatched Types",
+    ),
+    (
+        "structure M : C = struct let x = 1 end",
+        "Unknown signature",
+    ),
+    ("let n: Int = open 1 in n", "Illegal open"),
+];
+
+#[test]
+fn test_modules() {
+    for s in MODULES {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for (s, title) in MODULE_NEG {
+        let err = check_prog(&parse_prog(s).unwrap()).unwrap_err();
+        assert_eq!(err.title, *title, "{s}")
+    }
+}
+
+#[test]
+fn test_exceptions() {
+    for s in EXCEPTIONS {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for s in EXCEPTION_NEG {
+        check_prog(&parse_prog(s).unwrap()).unwrap_err();
+    }
+}
+
+#[test]
+fn test_equality_types() {
+    for s in EQUALITY_PROGS {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for s in EQUALITY_PROG_NEG {
+        check_prog(&parse_prog(s).unwrap()).unwrap_err();
+    }
+}
+
+#[test]
+fn test_lists() {
+    for s in LISTS {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for s in LIST_NEG {
+        check_prog(&parse_prog(s).unwrap()).unwrap_err();
+    }
+}
+
+const DERIVING: &[&str] = &[
+    "data Color = Red | Green deriving (Eq)
+     let b: Bool = eq_Color Red Green",
+    "data Option A = None | Some A deriving (Eq)
+     let b: Bool = eq_Option [Int] (λ x y. x == y) (Some 1) None",
+    // Fields of other data types use their derived equality, even holding functions
+    "data Option A = None | Some A deriving (Eq)
+     data Tree A = Leaf | Node (Tree A) (A * Int) (Option A) {left: Tree A} deriving (Eq)
+     let b: Bool = eq_Tree [Int -> Int] (λ f g. f 0 == g 0) Leaf Leaf",
+    "data Nat = Z | S Nat deriving (Eq) let b: Bool = eq_Nat (S Z) Z",
+    "data Color = Red | Green deriving (Show, Eq)
+     let s: String = concat (show_Color Red, showInt 1)",
+    "data Option A = None | Some A deriving (Show)
+     data Tree A = Leaf | Node (Tree A) (A * String) (Option A) {left: Tree A, b: Bool} deriving (Show)
+     let s: String = show_Tree [Unit] (λ u. \"()\") Leaf",
+];
+
+const DERIVING_NEG: &[&str] = &[
+    "data Color = Red | Green deriving (Ord)",
+    // Only integers, booleans, strings and units show without a derived `Show`
+    "data Op = Op (Int -> Int) deriving (Show)",
+    "data Small = Small Int8 deriving (Show)",
+    "data Option A = None | Some A deriving (Eq)
+     data Box A = Box (Option A) deriving (Show)",
+    // Only fields mentioning no parameter are compared with `==`
+    "data Fun A = Fun (A -> Int) deriving (Eq)",
+    "data Op = Op (Int -> Int) deriving (Eq)",
+    // Option doesn't derive its equality
+    "data Option A = None | Some A
+     data Box A = Box (Option A) deriving (Eq)",
+    "data Color = Red | Green deriving (Eq) let eq_Color: Int = 1",
+    // Derived functions are monomorphic in the data type
+    "data Option A = None | Some A deriving (Eq) let b: Bool = eq_Option [Int] (λ x y. x == y) 1 2",
+];
+
+#[test]
+fn test_deriving() {
+    for s in DERIVING {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for s in DERIVING_NEG {
+        check_prog(&parse_prog(s).unwrap()).unwrap_err();
+    }
+}
+
+#[test]
+fn test_classes() {
+    for s in CLASSES {
+        check_prog(&parse_prog(s).unwrap()).unwrap()
+    }
+    for s in CLASS_NEG {
+        check_prog(&parse_prog(s).unwrap()).unwrap_err();
+    }
+}
+
+#[test]
+fn test_fold_aliases() {
+    colored::control::set_override(false);
+    let prog = parse_prog("type Predicate = Int -> Bool let x: Int = 1").unwrap();
+    let aliases = prog_aliases(&prog).unwrap();
+    let typ = parse_type("(Int -> Bool) -> Int -> Bool").unwrap().typ;
+    assert_eq!(fold(&typ, &aliases).to_string(), "Predicate -> Predicate");
+    // Of equivalent aliases, the first defined is printed
+    let prog = "type Test = Int -> Bool type Predicate = Int -> Bool type Check = Int -> Bool
+                let x: Int = 1";
+    let aliases = prog_aliases(&parse_prog(prog).unwrap()).unwrap();
+    assert_eq!(fold(&typ, &aliases).to_string(), "Test -> Test")
+}
+
+#[test]
+fn test_redefine_alias() {
+    let mut aliases = prog_aliases(&parse_prog("type T = Int let x: Int = 1").unwrap()).unwrap();
+    define_alias(&parse_alias("type T = Bool").unwrap(), &mut aliases).unwrap_err();
+    // The existing alias is kept
+    assert_eq!(aliases["T"], parse_type("Int").unwrap().typ)
+}